@@ -1,5 +1,5 @@
-// Shader for drawing a collider mesh with a single transparent colour
-// Ideally i will do a wireframe pass as well
+// Draws collider meshes for the collider debug view, either as a transparent
+// solid or as black outlines
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
@@ -21,7 +21,7 @@ struct VertexOutput {
     @builtin(position) position: vec4<f32>,
 };
 
-@group(0) @binding(0) 
+@group(0) @binding(0)
 var<uniform> camera: Camera;
 
 @vertex
@@ -34,11 +34,15 @@ fn vs_main(in: VertexInput, instance: InstanceInput) -> VertexOutput {
         instance.m3
     );
     out.position = camera.matrix * instance_matrix * vec4<f32>(in.position, 1.0);
-    //out.position = camera.matrix * vec4<f32>(in.position, 1.0);
     return out;
 }
 
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4(0.26, 0.65, 0.96, 0.6);
-}
\ No newline at end of file
+fn fs_solid(in: VertexOutput) -> @location(0) vec4<f32> {
+    return output_colour(vec4<f32>(0.26, 0.65, 0.96, 0.6));
+}
+
+@fragment
+fn fs_outline(in: VertexOutput) -> @location(0) vec4<f32> {
+    return output_colour(vec4<f32>(0.0, 0.0, 0.0, 1.0));
+}
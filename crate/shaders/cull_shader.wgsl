@@ -0,0 +1,106 @@
+// Culls the reis against the camera on the gpu. The ones that could be on
+// screen get copied into a packed buffer, a cluster at a time, and counted
+// straight into the arguments of the indirect draws that draw them, so the
+// cpu never has to know how many there are.
+
+struct Culling {
+    // Facing inwards, normalised
+    planes: array<vec4<f32>, 6>,
+    // The model's bounding sphere, with the radius in w
+    sphere: vec4<f32>,
+    num_instances: u32,
+    num_clusters: u32,
+    // Each cluster gets one draw per mesh, one after the other
+    num_meshes: u32,
+    _padding: u32,
+};
+
+// The same as wgpu's DrawIndexedIndirect
+struct DrawArgs {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+// How many floats there are in an InstanceRaw. gpu_culling.rs checks this
+// still matches when it's built.
+const INSTANCE_FLOATS: u32 = 27u;
+
+@group(0) @binding(0)
+var<uniform> culling: Culling;
+@group(0) @binding(1)
+var<storage, read> instances: array<f32>;
+@group(0) @binding(2)
+var<storage, read_write> visible: array<f32>;
+// Where each cluster's instances start, in both buffers
+@group(0) @binding(3)
+var<storage, read> cluster_starts: array<u32>;
+@group(0) @binding(4)
+var<storage, read_write> draws: array<DrawArgs>;
+
+fn column(base: u32) -> vec4<f32> {
+    return vec4<f32>(
+        instances[base],
+        instances[base + 1u],
+        instances[base + 2u],
+        instances[base + 3u],
+    );
+}
+
+@compute @workgroup_size(64)
+fn cull(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= culling.num_instances {
+        return;
+    }
+
+    // The model matrix is the first thing in an instance
+    let base = index * INSTANCE_FLOATS;
+    let model = mat4x4<f32>(
+        column(base),
+        column(base + 4u),
+        column(base + 8u),
+        column(base + 12u),
+    );
+
+    let centre = vec4<f32>((model * vec4<f32>(culling.sphere.xyz, 1.0)).xyz, 1.0);
+    let scale = max(length(model[0].xyz), max(length(model[1].xyz), length(model[2].xyz)));
+    let radius = culling.sphere.w * scale;
+
+    for (var i = 0u; i < 6u; i += 1u) {
+        if dot(culling.planes[i], centre) < -radius {
+            return;
+        }
+    }
+
+    // There's only ever a few clusters, so it's quicker to look through them
+    // than to work out which one it is any other way
+    var cluster = 0u;
+    while cluster + 1u < culling.num_clusters && index >= cluster_starts[cluster + 1u] {
+        cluster += 1u;
+    }
+
+    let slot = atomicAdd(&draws[cluster * culling.num_meshes].instance_count, 1u);
+    let out = (cluster_starts[cluster] + slot) * INSTANCE_FLOATS;
+    for (var i = 0u; i < INSTANCE_FLOATS; i += 1u) {
+        visible[out + i] = instances[base + i];
+    }
+}
+
+// Only each cluster's first draw gets counted into, so this copies the count
+// to the rest of its meshes' draws once the culling's done
+@compute @workgroup_size(64)
+fn copy_counts(@builtin(global_invocation_id) id: vec3<u32>) {
+    let cluster = id.x;
+    if cluster >= culling.num_clusters {
+        return;
+    }
+
+    let first = cluster * culling.num_meshes;
+    let count = atomicLoad(&draws[first].instance_count);
+    for (var mesh = 1u; mesh < culling.num_meshes; mesh += 1u) {
+        atomicStore(&draws[first + mesh].instance_count, count);
+    }
+}
@@ -0,0 +1,34 @@
+// Copies the depth buffer into a normal texture, so it can be read while the
+// depth buffer itself is still being used for depth testing. When it's
+// multisampled, each pixel gets the closest of its samples.
+
+@group(0) @binding(0)
+var depth: texture_depth_multisampled_2d;
+
+// Used instead when multisampling is off
+@group(0) @binding(1)
+var single_depth: texture_depth_2d;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    // One big triangle that covers the whole screen
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) f32 {
+    let coords = vec2<i32>(position.xy);
+    var closest = 1.0;
+
+    for (var i = 0; i < i32(textureNumSamples(depth)); i++) {
+        closest = min(closest, textureLoad(depth, coords, i));
+    }
+
+    return closest;
+}
+
+@fragment
+fn fs_single(@builtin(position) position: vec4<f32>) -> @location(0) f32 {
+    return textureLoad(single_depth, vec2<i32>(position.xy), 0);
+}
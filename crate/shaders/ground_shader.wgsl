@@ -0,0 +1,88 @@
+// The ground, pushed down wherever the heightfield says things have landed.
+// Lit the same way as the models (see include/lighting.wgsl).
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_normal: vec3<f32>,
+    @location(1) world_position: vec3<f32>,
+    // How dented this bit is, from 0 (flat) to 1 (as deep as it goes)
+    @location(2) dent: f32,
+};
+
+#include "lighting.wgsl"
+#include "contact.wgsl"
+#include "ground.wgsl"
+
+@group(1) @binding(0)
+var<uniform> ground: Ground;
+@group(1) @binding(1)
+var heightfield: texture_2d<f32>;
+@group(1) @binding(2)
+var heightfield_sampler: sampler;
+
+const GROUND_COLOUR: vec3<f32> = vec3<f32>(0.42, 0.38, 0.3);
+// The bottoms of the dents are a bit darker, so they show up from above too
+const DENT_COLOUR: vec3<f32> = vec3<f32>(0.26, 0.22, 0.17);
+// How much lighter and darker the checkerboard's squares are
+const CHECKER_CONTRAST: f32 = 0.12;
+
+// How dented the ground is at a point, from 0 to 1. The splats just add up,
+// so this flattens them out to get deeper more and more slowly.
+fn dent(position: vec2<f32>) -> f32 {
+    let uv = position / ground.extent + 0.5;
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) {
+        return 0.0;
+    }
+
+    let amount = textureSampleLevel(heightfield, heightfield_sampler, uv, 0.0).r;
+    return 1.0 - exp(-amount);
+}
+
+fn height(position: vec2<f32>) -> f32 {
+    return ground.level - dent(position) * ground.max_depth;
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    // The normal comes from the slope between the neighbouring texels
+    let step = ground.extent / f32(textureDimensions(heightfield).x);
+    let dx = height(in.position + vec2<f32>(step, 0.0)) - height(in.position - vec2<f32>(step, 0.0));
+    let dz = height(in.position + vec2<f32>(0.0, step)) - height(in.position - vec2<f32>(0.0, step));
+
+    let amount = dent(in.position);
+    let position = vec3<f32>(in.position.x, ground.level - amount * ground.max_depth, in.position.y);
+
+    var out: VertexOutput;
+    out.world_position = position;
+    out.world_normal = normalize(vec3<f32>(-dx, 2.0 * step, -dz));
+    out.clip_position = camera.matrix * vec4<f32>(position, 1.0);
+    out.dent = amount;
+    return out;
+}
+
+// Which square of the checkerboard a point is on, 0 or 1, blurred by however
+// much of it one pixel covers. Far away the squares all blur into 0.5, so it
+// fades out instead of shimmering.
+fn checker(position: vec2<f32>) -> f32 {
+    let p = position / max(ground.tile_size, 0.001);
+    let width = max(fwidth(p), vec2<f32>(0.0001));
+    let i = 2.0 * (abs(fract((p - 0.5 * width) * 0.5) - 0.5) - abs(fract((p + 0.5 * width) * 0.5) - 0.5)) / width;
+    return select(0.5, 0.5 - 0.5 * i.x * i.y, ground.tile_size > 0.0);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = normalize(in.world_normal);
+    let tile = 1.0 + CHECKER_CONTRAST * (checker(in.world_position.xz) * 2.0 - 1.0);
+    let object_colour = mix(GROUND_COLOUR, DENT_COLOUR, in.dent) * tile;
+
+    // Dirt isn't very shiny
+    let result = lighting(in.world_position, normal, tweaks.specular_power, 0.2)
+        * object_colour * contact_factor(in.world_position);
+
+    return output_colour(vec4<f32>(fogged(result, in.world_position), 1.0));
+}
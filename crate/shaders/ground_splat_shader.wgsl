@@ -0,0 +1,50 @@
+// Draws soft blobs into the ground's heightfield where things have landed.
+// The heightfield is looked at from above, with x going across and z going
+// down, and covers a square `ground.extent` wide around the origin.
+
+#include "ground.wgsl"
+
+@group(0) @binding(0)
+var<uniform> ground: Ground;
+
+struct SplatInput {
+    @location(0) centre: vec2<f32>,
+    @location(1) radius: f32,
+    @location(2) strength: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Where on the quad this is, from -1 to 1 on each axis
+    @location(0) corner: vec2<f32>,
+    @location(1) strength: f32,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, splat: SplatInput) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[index];
+
+    let world = splat.centre + corner * splat.radius;
+    let uv = world / ground.extent + 0.5;
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.corner = corner;
+    out.strength = splat.strength;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Deepest in the middle, smoothly down to nothing at the edge
+    let falloff = 1.0 - smoothstep(0.0, 1.0, length(in.corner));
+    return vec4<f32>(in.strength * falloff, 0.0, 0.0, 0.0);
+}
@@ -0,0 +1,45 @@
+// Renders the model from one angle into a cell of the impostor atlas
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) normal: vec3<f32>,
+};
+
+struct Bake {
+    view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> bake: Bake;
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = bake.view_proj * vec4<f32>(in.position, 1.0);
+    out.tex_coords = in.tex_coords;
+    out.normal = in.normal;
+    return out;
+}
+
+@group(1) @binding(0)
+var diffuse_texture: texture_2d<f32>;
+@group(1) @binding(1)
+var diffuse_sampler: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let colour = textureSample(diffuse_texture, diffuse_sampler, in.tex_coords);
+
+    // The sprites aren't lit, so bake in some simple lighting from above
+    let light_dir = normalize(vec3<f32>(0.3, 1.0, 0.5));
+    let diffuse = max(dot(normalize(in.normal), light_dir), 0.0);
+
+    return output_colour(vec4<f32>(colour.rgb * (0.5 + 0.5 * diffuse), 1.0));
+}
@@ -0,0 +1,8 @@
+// Where the camera is and how to get from the world to clip space, see camera.rs
+struct Camera {
+    position: vec4<f32>,
+    matrix: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
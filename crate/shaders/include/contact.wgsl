@@ -0,0 +1,28 @@
+// See contact.rs
+struct Contact {
+    extent: f32,
+    strength: f32,
+    reach: f32,
+    ground_level: f32,
+}
+
+@group(3) @binding(3)
+var contact_density: texture_2d<f32>;
+@group(3) @binding(4)
+var contact_sampler: sampler;
+@group(3) @binding(5)
+var<uniform> contact: Contact;
+
+// How much light gets to a point past the pile around it, from 1 down to
+// 1 - the strength. Only anything close to the ground gets darker.
+fn contact_factor(world_position: vec3<f32>) -> f32 {
+    let uv = world_position.xz / contact.extent + 0.5;
+    if contact.strength <= 0.0 || any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) {
+        return 1.0;
+    }
+
+    let density = textureSampleLevel(contact_density, contact_sampler, uv, 0.0).r;
+    let height = world_position.y - contact.ground_level;
+    let closeness = 1.0 - smoothstep(0.0, contact.reach, height);
+    return 1.0 - contact.strength * density * closeness;
+}
@@ -0,0 +1,12 @@
+// Fading instances get holes dithered into them instead of being blended, so
+// they don't need sorting. True if this pixel should be left out.
+fn dithered_out(position: vec2<f32>, alpha: f32) -> bool {
+    var bayer = array<f32, 16>(
+        0.0, 8.0, 2.0, 10.0,
+        12.0, 4.0, 14.0, 6.0,
+        3.0, 11.0, 1.0, 9.0,
+        15.0, 7.0, 13.0, 5.0,
+    );
+    let cell = vec2<u32>(position) % 4u;
+    return alpha < (bayer[cell.y * 4u + cell.x] + 0.5) / 16.0;
+}
@@ -0,0 +1,7 @@
+// How the reis from the emitter being drawn look, see clusters.rs
+struct Emitter {
+    tint: vec3<f32>,
+}
+
+@group(2) @binding(1)
+var<uniform> emitter: Emitter;
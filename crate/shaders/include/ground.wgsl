@@ -0,0 +1,10 @@
+// See ground.rs
+struct Ground {
+    // The height of the flat ground
+    level: f32,
+    // How wide the heightfield is, in world units
+    extent: f32,
+    max_depth: f32,
+    // How wide the checkerboard's squares are, 0 if there isn't one
+    tile_size: f32,
+};
@@ -0,0 +1,16 @@
+// See light.rs
+struct Light {
+    position: vec3<f32>,
+    scale: f32,
+    colour: vec3<f32>,
+    brightness: f32,
+    // Which way the sun is, and its colour (black if there isn't one)
+    sun_direction: vec3<f32>,
+    sun_colour: vec3<f32>,
+    // The sky's colour at the horizon, for the time of day. It lights
+    // everything a bit and is what the fog fades to.
+    sky_colour: vec3<f32>,
+}
+
+@group(2) @binding(0)
+var<uniform> light: Light;
@@ -0,0 +1,54 @@
+// The lighting everything in the scene shares: the light (which falls off
+// and casts shadows), the sun (which doesn't), the sky, and the fog.
+
+#include "camera.wgsl"
+#include "light.wgsl"
+#include "tweaks.wgsl"
+#include "shadow.wgsl"
+
+// All the light that reaches a point, to be multiplied by its colour. The
+// highlights are `specular_scale` times as bright as the tweak says.
+fn lighting(world_position: vec3<f32>, normal: vec3<f32>, specular_power: f32, specular_scale: f32) -> vec3<f32> {
+    // Ambient light
+    let ambient_colour = light.colour * tweaks.ambient_strength + light.sky_colour * tweaks.sky_ambient_strength;
+
+    // Diffuse light
+    let light_dir = normalize(light.position - world_position);
+    let diffuse_strength = max(dot(light_dir, normal), 0.0);
+    let diffuse_colour = diffuse_strength * light.colour;
+
+    // Specular light
+    let view_dir = normalize(camera.position.xyz - world_position);
+    let half_dir = normalize(view_dir + light_dir);
+    let specular_strength = pow(max(dot(normal, half_dir), 0.0), specular_power)
+        * tweaks.specular_strength * specular_scale;
+    let specular_colour = light.colour * specular_strength;
+
+    var distance_scale: f32;
+    let distance = distance(world_position, light.position);
+    let cutoff = 0.1;
+
+    if distance <= cutoff {
+        distance_scale = light.brightness;
+    } else {
+        let dist_from_cutoff = (distance - cutoff + light.scale) / light.scale;
+        distance_scale = light.brightness / (dist_from_cutoff * dist_from_cutoff);
+    }
+
+    let lit = shadow_factor(world_position);
+
+    // The sun's too far away to fall off, and doesn't cast shadows
+    let sun_half_dir = normalize(view_dir + light.sun_direction);
+    let sun_diffuse = max(dot(light.sun_direction, normal), 0.0);
+    let sun_specular = pow(max(dot(normal, sun_half_dir), 0.0), specular_power)
+        * tweaks.specular_strength * specular_scale;
+    let sun_colour = (sun_diffuse + sun_specular) * light.sun_colour;
+
+    return ambient_colour + sun_colour + (diffuse_colour + specular_colour) * distance_scale * lit;
+}
+
+// A lit colour with the fog over it, fading to the sky further away
+fn fogged(colour: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let fog = fog_factor(length(camera.position.xyz - world_position));
+    return mix(light.sky_colour, colour, fog);
+}
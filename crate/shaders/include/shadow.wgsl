@@ -0,0 +1,40 @@
+// See shadow.rs
+struct Shadow {
+    view_proj: mat4x4<f32>,
+    enabled: u32,
+    bias: f32,
+};
+
+@group(3) @binding(0)
+var<uniform> shadow: Shadow;
+@group(3) @binding(1)
+var shadow_map: texture_depth_2d;
+@group(3) @binding(2)
+var shadow_sampler: sampler_comparison;
+
+// How lit a point is by the light, from 0 (fully in shadow) to 1
+fn shadow_factor(world_position: vec3<f32>) -> f32 {
+    let light_space = shadow.view_proj * vec4<f32>(world_position, 1.0);
+    let ndc = light_space.xyz / light_space.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, ndc.y * -0.5 + 0.5);
+    let depth = ndc.z - shadow.bias;
+
+    // Average a 3x3 area to soften the edges a bit
+    let texel_size = 1.0 / f32(textureDimensions(shadow_map).x);
+    var lit = 0.0;
+    for (var x = -1; x <= 1; x = x + 1) {
+        for (var y = -1; y <= 1; y = y + 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel_size;
+            lit = lit + textureSampleCompare(shadow_map, shadow_sampler, uv + offset, depth);
+        }
+    }
+    lit = lit / 9.0;
+
+    // Anything the shadow map doesn't cover is lit
+    let outside = light_space.w <= 0.0
+        || any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0))
+        || depth > 1.0
+        || shadow.enabled == 0u;
+
+    return select(lit, 1.0, outside);
+}
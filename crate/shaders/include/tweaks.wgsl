@@ -0,0 +1,17 @@
+// Lighting numbers that can be changed from the ui, see tweaks.rs
+struct Tweaks {
+    ambient_strength: f32,
+    sky_ambient_strength: f32,
+    specular_strength: f32,
+    specular_power: f32,
+    fog_density: f32,
+}
+
+@group(2) @binding(2)
+var<uniform> tweaks: Tweaks;
+
+// How much of a point's own colour is left after the fog, from 1 (none of it
+// is fog) to 0 (it's all fog)
+fn fog_factor(distance: f32) -> f32 {
+    return exp(-tweaks.fog_density * distance);
+}
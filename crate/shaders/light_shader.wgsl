@@ -4,6 +4,14 @@ struct VertexInput {
     @location(2) normal: vec3<f32>,
 };
 
+// Only where it's drawn, it's not lit so it doesn't need the rest
+struct InstanceInput {
+    @location(5) m0: vec4<f32>,
+    @location(6) m1: vec4<f32>,
+    @location(7) m2: vec4<f32>,
+    @location(8) m3: vec4<f32>,
+};
+
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
 };
@@ -27,15 +35,21 @@ var<uniform> camera: Camera;
 var<uniform> light: Light;
 
 @vertex
-fn vs_main(in: VertexInput) -> VertexOutput {
+fn vs_main(in: VertexInput, instance: InstanceInput) -> VertexOutput {
     var out: VertexOutput;
+    let instance_matrix = mat4x4<f32>(
+        instance.m0,
+        instance.m1,
+        instance.m2,
+        instance.m3
+    );
+
     // Perspective projection using the camera uniform binding
-    let scale = 0.25;
-    out.clip_position = camera.matrix * vec4<f32>(in.position * scale + light.position, 1.0);
+    out.clip_position = camera.matrix * instance_matrix * vec4<f32>(in.position, 1.0);
     return out;
 }
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(light.colour, 1.0);
+    return output_colour(vec4<f32>(light.colour, 1.0));
 }
\ No newline at end of file
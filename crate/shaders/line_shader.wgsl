@@ -0,0 +1,32 @@
+// Flat coloured lines, for debug views
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) colour: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) colour: vec3<f32>,
+};
+
+struct Camera {
+    position: vec4<f32>,
+    matrix: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.matrix * vec4<f32>(in.position, 1.0);
+    out.colour = in.colour;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return output_colour(vec4<f32>(in.colour, 1.0));
+}
@@ -9,6 +9,8 @@ struct VertexOutput {
     @location(0) tex_coords: vec2<f32>,
     @location(1) world_normal: vec3<f32>,
     @location(2) world_position: vec3<f32>,
+    @location(3) speed: f32,
+    @location(4) alpha: f32,
 };
 
 struct InstanceInput {
@@ -17,28 +19,18 @@ struct InstanceInput {
     @location(7) m2: vec4<f32>,
     @location(8) m3: vec4<f32>,
 
-    @location(9) r0: vec3<f32>,
-    @location(10) r1: vec3<f32>,
-    @location(11) r2: vec3<f32>,
-};
+    @location(9) n0: vec3<f32>,
+    @location(10) n1: vec3<f32>,
+    @location(11) n2: vec3<f32>,
 
-struct Camera {
-    position: vec4<f32>,
-    matrix: mat4x4<f32>,
+    @location(12) speed: f32,
+    @location(13) alpha: f32,
 };
 
-struct Light {
-    position: vec3<f32>,
-    scale: f32,
-    colour: vec3<f32>,
-    brightness: f32,
-}
-
-@group(0) @binding(0)
-var<uniform> camera: Camera;
-
-@group(2) @binding(0)
-var<uniform> light: Light;
+#include "lighting.wgsl"
+#include "contact.wgsl"
+#include "dither.wgsl"
+#include "emitter.wgsl"
 
 @vertex
 fn vs_main(in: VertexInput, instance: InstanceInput) -> VertexOutput {
@@ -50,19 +42,21 @@ fn vs_main(in: VertexInput, instance: InstanceInput) -> VertexOutput {
         instance.m3
     );
 
-    let rotation_matrix = mat3x3<f32>(
-        instance.r0,
-        instance.r1,
-        instance.r2
+    let normal_matrix = mat3x3<f32>(
+        instance.n0,
+        instance.n1,
+        instance.n2
     );
 
     // Perspective projection using the camera uniform binding
 
     let position = instance_matrix * vec4<f32>(in.position, 1.0);
     out.world_position = position.xyz;
-    out.world_normal = rotation_matrix * in.normal;
+    out.world_normal = normalize(normal_matrix * in.normal);
     out.clip_position = camera.matrix * position;
     out.tex_coords = in.tex_coords;
+    out.speed = instance.speed;
+    out.alpha = instance.alpha;
     return out;
 }
 
@@ -71,40 +65,52 @@ var diffuse_texture: texture_2d<f32>;
 @group(1) @binding(1)
 var diffuse_sampler: sampler;
 
+struct Material {
+    uv_offset: vec2<f32>,
+    uv_scale: vec2<f32>,
+    // How bright the highlights are, on top of the tweak
+    specular: f32,
+    // The specular exponent, or 0 to use the tweak's
+    shininess: f32,
+}
+
+@group(1) @binding(2)
+var<uniform> material: Material;
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    // Ambient light
-    let object_colour = textureSample(diffuse_texture, diffuse_sampler, in.tex_coords);
-    let world_colour = vec3<f32>(0.5, 0.82, 0.98);
-    let ambient_strength = 0.1;
-    let world_ambient_strength = 0.5;
+    let tex_coords = in.tex_coords * material.uv_scale + material.uv_offset;
+    let object_colour = textureSample(diffuse_texture, diffuse_sampler, tex_coords);
 
-    let ambient_colour = light.colour * ambient_strength + world_colour * world_ambient_strength;
+    let specular_power = select(tweaks.specular_power, material.shininess, material.shininess > 0.0);
+    let result = lighting(in.world_position, normalize(in.world_normal), specular_power, material.specular)
+        * object_colour.xyz * emitter.tint * contact_factor(in.world_position);
 
-    // Diffuse light
-    let light_dir = normalize(light.position - in.world_position);
-    let diffuse_strength = max(dot(light_dir, in.world_normal), 0.0);
-    let diffuse_colour = diffuse_strength * light.colour;
-
-    // Specular light
-    let view_dir = normalize(camera.position.xyz - in.world_position);
-    let half_dir = normalize(view_dir + light_dir);
+    // This has to come after all the texture sampling, which isn't allowed
+    // once some pixels might have been discarded
+    if dithered_out(in.clip_position.xy, in.alpha) {
+        discard;
+    }
 
-    let specular_strength = pow(max(dot(view_dir, half_dir), 0.0), 10.0) * 0.4;
-    let specular_colour = light.colour * specular_strength;
+    return output_colour(vec4<f32>(fogged(result, in.world_position), object_colour.a));
+}
 
-    var distance_scale: f32;
-    let distance= distance(in.world_position, light.position);
-    let cutoff = 0.1;
+// Anything going this fast (in units per second) or faster is fully red
+const MAX_SPEED: f32 = 20.0;
 
-    if distance <= cutoff {
-        distance_scale = light.brightness;
-    } else {
-        let dist_from_cutoff = (distance - cutoff + light.scale) / light.scale;
-        distance_scale = light.brightness / (dist_from_cutoff*dist_from_cutoff);
+// Debug view: colours each instance by its speed, from blue (still) to red (fast)
+@fragment
+fn fs_speed(in: VertexOutput) -> @location(0) vec4<f32> {
+    if dithered_out(in.clip_position.xy, in.alpha) {
+        discard;
     }
 
-    let result = (ambient_colour + (diffuse_colour + specular_colour) * distance_scale) * object_colour.xyz;
+    let t = clamp(in.speed / MAX_SPEED, 0.0, 1.0);
+    let colour = mix(vec3<f32>(0.1, 0.2, 1.0), vec3<f32>(1.0, 0.1, 0.05), t);
 
-    return vec4<f32>(result, object_colour.a);
-}
\ No newline at end of file
+    // A bit of basic lighting so the shapes are still readable
+    let light_dir = normalize(light.position - in.world_position);
+    let diffuse_strength = max(dot(light_dir, in.world_normal), 0.0);
+
+    return output_colour(vec4<f32>(colour * (0.4 + 0.6 * diffuse_strength), 1.0));
+}
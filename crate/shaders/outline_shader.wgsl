@@ -0,0 +1,75 @@
+// Draws an outline around the picked body. It's drawn twice: first into only
+// the stencil buffer, marking where the body is on screen, then again pushed
+// out along its normals, coloured in wherever it wasn't marked. All that's
+// left of the second one is a rim around the edge.
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+};
+
+struct InstanceInput {
+    @location(5) m0: vec4<f32>,
+    @location(6) m1: vec4<f32>,
+    @location(7) m2: vec4<f32>,
+    @location(8) m3: vec4<f32>,
+
+    @location(9) n0: vec3<f32>,
+    @location(10) n1: vec3<f32>,
+    @location(11) n2: vec3<f32>,
+};
+
+struct Camera {
+    position: vec4<f32>,
+    matrix: mat4x4<f32>,
+};
+
+struct Outline {
+    colour: vec4<f32>,
+    // The size of the scene in pixels
+    viewport: vec2<f32>,
+    // How far the outline sticks out, in pixels
+    width: f32,
+    _padding: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+@group(1) @binding(0)
+var<uniform> outline: Outline;
+
+// Where a vertex ends up on screen, pushed `width` pixels out from the middle
+// of the body, going by which way its normal faces on screen
+fn clip_position(in: VertexInput, instance: InstanceInput, width: f32) -> vec4<f32> {
+    let model = mat4x4<f32>(instance.m0, instance.m1, instance.m2, instance.m3);
+    let normal_matrix = mat3x3<f32>(instance.n0, instance.n1, instance.n2);
+
+    let clip = camera.matrix * model * vec4<f32>(in.position, 1.0);
+    let normal = normalize(normal_matrix * in.normal);
+
+    // Normals pointing straight at the camera don't go anywhere on screen
+    let on_screen = (camera.matrix * vec4<f32>(normal, 0.0)).xy * outline.viewport;
+    if length(on_screen) < 0.0001 {
+        return clip;
+    }
+
+    // Pixels to clip space, where the screen's 2 across
+    let offset = normalize(on_screen) * width * 2.0 / outline.viewport;
+    return vec4<f32>(clip.xy + offset * clip.w, clip.zw);
+}
+
+@vertex
+fn vs_mask(in: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    return clip_position(in, instance, 0.0);
+}
+
+@vertex
+fn vs_outline(in: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    return clip_position(in, instance, outline.width);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return output_colour(outline.colour);
+}
@@ -0,0 +1,92 @@
+// Dust puffs, drawn as camera-facing quads. With soft particles on, they fade
+// out where they get close to whatever's behind them, instead of being cut
+// off with a hard line where they go into it.
+
+struct InstanceInput {
+    @location(0) position: vec3<f32>,
+    @location(1) size: f32,
+    @location(2) alpha: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Where on the quad this is, from -1 to 1 on each axis
+    @location(0) corner: vec2<f32>,
+    @location(1) alpha: f32,
+};
+
+struct Camera {
+    position: vec4<f32>,
+    matrix: mat4x4<f32>,
+};
+
+struct Particles {
+    // The camera's right and up directions, in world space
+    right: vec4<f32>,
+    up: vec4<f32>,
+    znear: f32,
+    zfar: f32,
+    // How close (in world units) a particle has to get to the scene behind it
+    // before it starts fading out
+    softness: f32,
+    // 0 or 1, bools can't go in uniforms
+    soft: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(1) @binding(0)
+var<uniform> particles: Particles;
+// The scene's depth, resolved from the multisampled depth buffer
+@group(1) @binding(1)
+var scene_depth: texture_2d<f32>;
+
+const DUST_COLOUR: vec3<f32> = vec3<f32>(0.55, 0.5, 0.45);
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, instance: InstanceInput) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[index];
+
+    let offset = particles.right.xyz * corner.x + particles.up.xyz * corner.y;
+    let world_position = instance.position + offset * instance.size;
+
+    var out: VertexOutput;
+    out.clip_position = camera.matrix * vec4<f32>(world_position, 1.0);
+    out.corner = corner;
+    out.alpha = instance.alpha;
+    return out;
+}
+
+// Turns a value from the depth buffer back into a distance from the camera
+fn linear_depth(depth: f32) -> f32 {
+    let n = particles.znear;
+    let f = particles.zfar;
+    return n * f / (f - depth * (f - n));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let distance_squared = dot(in.corner, in.corner);
+    if distance_squared > 1.0 {
+        discard;
+    }
+
+    var alpha = in.alpha * (1.0 - distance_squared);
+
+    if particles.soft != 0u {
+        let scene = textureLoad(scene_depth, vec2<i32>(in.clip_position.xy), 0).r;
+        let gap = linear_depth(scene) - linear_depth(in.clip_position.z);
+        alpha *= clamp(gap / particles.softness, 0.0, 1.0);
+    }
+
+    return output_colour(vec4<f32>(DUST_COLOUR, alpha));
+}
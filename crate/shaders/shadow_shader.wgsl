@@ -0,0 +1,35 @@
+// Renders the depth of everything from the light's point of view
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+};
+
+struct InstanceInput {
+    @location(5) m0: vec4<f32>,
+    @location(6) m1: vec4<f32>,
+    @location(7) m2: vec4<f32>,
+    @location(8) m3: vec4<f32>,
+};
+
+struct Shadow {
+    view_proj: mat4x4<f32>,
+    enabled: u32,
+    bias: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> shadow: Shadow;
+
+@vertex
+fn vs_main(in: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    let instance_matrix = mat4x4<f32>(
+        instance.m0,
+        instance.m1,
+        instance.m2,
+        instance.m3
+    );
+
+    return shadow.view_proj * instance_matrix * vec4<f32>(in.position, 1.0);
+}
@@ -0,0 +1,42 @@
+// The sky, looked up in a cubemap in whichever direction each pixel faces
+
+struct Skybox {
+    // Clip space to world space directions, without the camera's position
+    inverse_view_proj: mat4x4<f32>,
+    // For the time of day
+    tint: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) direction: vec3<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> skybox: Skybox;
+@group(0) @binding(1)
+var sky: texture_cube<f32>;
+@group(0) @binding(2)
+var sky_sampler: sampler;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // One big triangle that covers the whole screen
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let position = vec4<f32>(uv * 2.0 - 1.0, 1.0, 1.0);
+
+    // Where the pixel is on the far plane, which (with the camera at the
+    // origin) is also the direction it's looking
+    let far = skybox.inverse_view_proj * position;
+
+    var out: VertexOutput;
+    out.clip_position = position;
+    out.direction = far.xyz / far.w;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let colour = textureSample(sky, sky_sampler, normalize(in.direction));
+    return output_colour(vec4<f32>(colour.rgb * skybox.tint, 1.0));
+}
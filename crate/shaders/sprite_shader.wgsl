@@ -0,0 +1,106 @@
+// Draws each instance as a camera-facing sprite, using whichever of the
+// impostor atlas's angles is closest to the direction it's being seen from
+
+struct InstanceInput {
+    @location(5) m0: vec4<f32>,
+    @location(6) m1: vec4<f32>,
+    @location(7) m2: vec4<f32>,
+    @location(8) m3: vec4<f32>,
+    @location(13) alpha: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) alpha: f32,
+    @location(2) world_position: vec3<f32>,
+};
+
+struct Sprite {
+    // The centre of the model's bounds, in model space
+    centre: vec3<f32>,
+    // Half the width of the area the model was rendered into
+    radius: f32,
+    angles: u32,
+};
+
+#include "camera.wgsl"
+#include "dither.wgsl"
+#include "emitter.wgsl"
+// Only the sky's colour is used from this, the sprites are already lit
+#include "light.wgsl"
+// Only the fog's used from this
+#include "tweaks.wgsl"
+
+@group(1) @binding(2)
+var<uniform> sprite: Sprite;
+
+const TAU: f32 = 6.283185307;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, instance: InstanceInput) -> VertexOutput {
+    // Two triangles making up a square, from (0, 0) to (1, 1)
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+    );
+    let corner = corners[index];
+
+    let instance_matrix = mat4x4<f32>(
+        instance.m0,
+        instance.m1,
+        instance.m2,
+        instance.m3
+    );
+
+    let centre = (instance_matrix * vec4<f32>(sprite.centre, 1.0)).xyz;
+    let scale = length(instance.m0.xyz);
+    let to_camera = normalize(camera.position.xyz - centre);
+
+    // Face the camera, keeping the sprite upright where possible
+    var right = cross(vec3<f32>(0.0, 1.0, 0.0), to_camera);
+    if length(right) < 0.001 {
+        right = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    right = normalize(right);
+    let up = cross(to_camera, right);
+
+    let offset = (corner * 2.0 - 1.0) * sprite.radius * scale;
+    let position = centre + right * offset.x + up * offset.y;
+
+    // Work out which angle the instance is being seen from, around its own y axis
+    let local_x = normalize(instance.m0.xyz);
+    let local_z = normalize(instance.m2.xyz);
+    let angle = atan2(dot(to_camera, local_x), dot(to_camera, local_z));
+    let step = TAU / f32(sprite.angles);
+    let frame = u32(round((angle + TAU) / step)) % sprite.angles;
+
+    var out: VertexOutput;
+    out.clip_position = camera.matrix * vec4<f32>(position, 1.0);
+    out.tex_coords = vec2<f32>((f32(frame) + corner.x) / f32(sprite.angles), 1.0 - corner.y);
+    out.alpha = instance.alpha;
+    out.world_position = position;
+    return out;
+}
+
+@group(1) @binding(0)
+var atlas_texture: texture_2d<f32>;
+@group(1) @binding(1)
+var atlas_sampler: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let colour = textureSample(atlas_texture, atlas_sampler, in.tex_coords);
+
+    // Cutting out the transparent parts means the sprites don't need sorting
+    if colour.a < 0.5 || dithered_out(in.clip_position.xy, in.alpha) {
+        discard;
+    }
+
+    let fog = fog_factor(distance(camera.position.xyz, in.world_position));
+    return output_colour(vec4<f32>(mix(light.sky_colour, colour.rgb * emitter.tint, fog), 1.0));
+}
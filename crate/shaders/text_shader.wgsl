@@ -0,0 +1,77 @@
+// Text, one quad per glyph cut out of the glyph atlas. Glyphs either sit at a
+// fixed place on the screen, or hang off a point in the world at a fixed size
+// in pixels (so they always face the camera) and get hidden behind things.
+
+struct GlyphInput {
+    @location(0) anchor: vec3<f32>,
+    @location(1) in_world: f32,
+    @location(2) offset: vec2<f32>,
+    @location(3) size: vec2<f32>,
+    @location(4) uv_min: vec2<f32>,
+    @location(5) uv_max: vec2<f32>,
+    @location(6) colour: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) colour: vec4<f32>,
+};
+
+struct Camera {
+    position: vec4<f32>,
+    matrix: mat4x4<f32>,
+};
+
+struct Text {
+    // The size of the scene in pixels
+    screen_size: vec2<f32>,
+    _padding: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(1) @binding(0)
+var atlas: texture_2d<f32>;
+@group(1) @binding(1)
+var atlas_sampler: sampler;
+@group(1) @binding(2)
+var<uniform> text: Text;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32, in: GlyphInput) -> VertexOutput {
+    var out: VertexOutput;
+    let corner = vec2<f32>(f32(vertex_index & 1u), f32(vertex_index >> 1u));
+
+    var origin = vec2<f32>(0.0);
+    var depth = 0.0;
+    if in.in_world > 0.5 {
+        let clip = camera.matrix * vec4<f32>(in.anchor, 1.0);
+        if clip.w <= 0.0 {
+            // Behind the camera, so it goes somewhere it won't be drawn
+            out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+            return out;
+        }
+
+        let ndc = clip.xyz / clip.w;
+        // Snapped to a whole pixel so the glyphs stay as sharp as they were
+        // rasterised
+        origin = floor((ndc.xy * vec2<f32>(0.5, -0.5) + 0.5) * text.screen_size + 0.5);
+        depth = ndc.z;
+    }
+
+    let pixel = origin + in.offset + corner * in.size;
+    let position = pixel / text.screen_size * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+
+    out.clip_position = vec4<f32>(position, depth, 1.0);
+    out.uv = mix(in.uv_min, in.uv_max, corner);
+    out.colour = in.colour;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = textureSample(atlas, atlas_sampler, in.uv).r;
+    return output_colour(vec4<f32>(in.colour.rgb, in.colour.a * coverage));
+}
@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{assets::Assets, audio::Sound, physics::PhysicsSimulation};
+
+/// A looping sound placed in the world, e.g. a humming light or some wind.
+/// It's loudest right next to it and fades out completely at its radius.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AmbientSound {
+    pub name: String,
+    /// The sound file to loop
+    pub sound: String,
+    /// Where the sound is, or its offset from whatever it's attached to
+    pub position: [f32; 3],
+    /// Something in the scene for the sound to follow around
+    pub attach_to: Option<Attachment>,
+    /// How far away the sound can be heard from
+    pub radius: f32,
+    pub volume: f64,
+}
+
+impl Default for AmbientSound {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            sound: String::new(),
+            position: [0.0; 3],
+            attach_to: None,
+            radius: 20.0,
+            volume: 1.0,
+        }
+    }
+}
+
+impl AmbientSound {
+    /// Where the sound currently is, following whatever it's attached to.
+    /// If that doesn't exist, its position is used as is.
+    pub fn world_position(
+        &self,
+        physics: &PhysicsSimulation,
+        light_position: [f32; 3],
+    ) -> [f32; 3] {
+        let origin = match &self.attach_to {
+            None => None,
+            Some(Attachment::Statue) => Some(physics.statue_position()),
+            Some(Attachment::Light) => Some(light_position),
+            Some(Attachment::Emitter(name)) => physics
+                .emitters()
+                .iter()
+                .find(|emitter| &emitter.name == name)
+                .map(|emitter| emitter.position),
+        };
+
+        let [x, y, z] = origin.unwrap_or([0.0; 3]);
+        let [dx, dy, dz] = self.position;
+        [x + dx, y + dy, z + dz]
+    }
+}
+
+/// Things in the scene an ambient sound can be attached to.
+/// In the scene file this is `"statue"`, `"light"` or `{ emitter = "name" }`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Attachment {
+    Statue,
+    Light,
+    Emitter(String),
+}
+
+/// Loads the sound files for all the ambient sounds. Any that can't be loaded
+/// are left out (with an error in the log) rather than stopping the whole scene
+/// from loading.
+#[cfg(feature = "audio")]
+pub async fn load_sounds(
+    assets: &Assets,
+    sounds: Vec<AmbientSound>,
+) -> Vec<(AmbientSound, Sound)> {
+    // They all load at once, and come out in the same order
+    let data = futures::future::join_all(sounds.iter().map(|sound| async {
+        assets
+            .load_sound(&sound.sound)
+            .await
+            .map(|handle| assets.get(handle))
+    }))
+    .await;
+
+    let mut loaded = Vec::with_capacity(sounds.len());
+
+    for (sound, data) in sounds.into_iter().zip(data) {
+        match data {
+            // Static sounds share their samples, so this clone is cheap
+            Ok(Some(data)) => loaded.push((sound, (*data).clone())),
+            Ok(None) => {}
+            Err(error) => log::error!("{}", error.report()),
+        }
+    }
+
+    loaded
+}
+
+/// There's nothing to play them with, so they aren't loaded at all
+#[cfg(not(feature = "audio"))]
+pub async fn load_sounds(_assets: &Assets, _sounds: Vec<AmbientSound>) -> Vec<(AmbientSound, Sound)> {
+    Vec::new()
+}
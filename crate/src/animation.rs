@@ -0,0 +1,266 @@
+use std::f32::consts::TAU;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{light::LightUniform, locale::Strings};
+
+/// The per-material uniform, used to transform texture coordinates and for
+/// how shiny the material is (see [Surface](crate::model::Surface))
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct MaterialUniform {
+    pub uv_offset: [f32; 2],
+    pub uv_scale: [f32; 2],
+    pub specular: f32,
+    // 0 means the material doesn't say, and the shader tweak gets used
+    pub shininess: f32,
+    pub _padding: [f32; 2],
+}
+
+impl Default for MaterialUniform {
+    fn default() -> Self {
+        Self {
+            uv_offset: [0.0, 0.0],
+            uv_scale: [1.0, 1.0],
+            specular: 1.0,
+            shininess: 0.0,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// Animates a material's texture coordinates over time. This can scroll the
+/// texture, flip through the frames of a sprite sheet, or both.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UvAnimation {
+    /// How far the texture scrolls per second, in uv units
+    pub scroll: [f32; 2],
+    /// The number of columns and rows in the sprite sheet. [1, 1] means the
+    /// texture isn't a sprite sheet.
+    pub frames: [u32; 2],
+    /// Sprite sheet frames per second
+    pub fps: f32,
+}
+
+impl Default for UvAnimation {
+    fn default() -> Self {
+        Self {
+            scroll: [0.0, 0.0],
+            frames: [1, 1],
+            fps: 0.0,
+        }
+    }
+}
+
+impl UvAnimation {
+    pub fn uniform_at(&self, time: f32) -> MaterialUniform {
+        let columns = self.frames[0].max(1);
+        let rows = self.frames[1].max(1);
+
+        let frame = (time * self.fps).max(0.0) as u32 % (columns * rows);
+        let column = frame % columns;
+        let row = frame / columns;
+
+        let uv_scale = [1.0 / columns as f32, 1.0 / rows as f32];
+
+        // Keep the scroll small so precision doesn't fall apart after running for a while
+        let scroll = self.scroll.map(|speed| (speed * time).fract());
+
+        MaterialUniform {
+            uv_offset: [
+                column as f32 * uv_scale[0] + scroll[0],
+                row as f32 * uv_scale[1] + scroll[1],
+            ],
+            uv_scale,
+            ..Default::default()
+        }
+    }
+}
+
+/// Changes a light's brightness and colour over time, on top of whatever
+/// they're set to. Everything's off by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LightAnimation {
+    pub flicker: Flicker,
+    pub pulse: Pulse,
+    pub colour_cycle: ColourCycle,
+}
+
+/// The brightness jittering about at random, like a candle or a dodgy bulb
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Flicker {
+    /// How much of the brightness comes and goes, from 0 to 1
+    pub amount: f32,
+    /// Roughly how many times a second it changes
+    pub speed: f32,
+}
+
+impl Default for Flicker {
+    fn default() -> Self {
+        Self {
+            amount: 0.0,
+            speed: 8.0,
+        }
+    }
+}
+
+/// The brightness going smoothly up and down
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Pulse {
+    /// How much of the brightness comes and goes, from 0 to 1
+    pub amount: f32,
+    /// Pulses per second
+    pub speed: f32,
+}
+
+impl Default for Pulse {
+    fn default() -> Self {
+        Self {
+            amount: 0.0,
+            speed: 0.5,
+        }
+    }
+}
+
+/// The colour going round the colour wheel, keeping its brightness
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColourCycle {
+    /// Times round the wheel per second, where 0 doesn't cycle at all
+    pub speed: f32,
+}
+
+impl LightAnimation {
+    /// The light as it should be `time` seconds in
+    pub fn apply(&self, mut light: LightUniform, time: f32) -> LightUniform {
+        let pulse = self.pulse.amount * (TAU * (self.pulse.speed * time).fract()).sin();
+        let flicker = self.flicker.amount * perlin(self.flicker.speed * time);
+        light.brightness = (light.brightness * (1.0 + pulse) * (1.0 + flicker)).max(0.0);
+
+        if self.colour_cycle.speed != 0.0 {
+            let turns = (self.colour_cycle.speed * time).fract();
+            light.colour = rotate_hue(light.colour, turns);
+        }
+
+        light
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, strings: &Strings) {
+        use egui::DragValue;
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("light.flicker"));
+            ui.add(
+                DragValue::new(&mut self.flicker.amount)
+                    .clamp_range(0.0..=1.0)
+                    .speed(0.01),
+            );
+            ui.label(strings.get("light.speed"));
+            ui.add(
+                DragValue::new(&mut self.flicker.speed)
+                    .clamp_range(0.0..=60.0)
+                    .speed(0.1),
+            );
+        });
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("light.pulse"));
+            ui.add(
+                DragValue::new(&mut self.pulse.amount)
+                    .clamp_range(0.0..=1.0)
+                    .speed(0.01),
+            );
+            ui.label(strings.get("light.speed"));
+            ui.add(
+                DragValue::new(&mut self.pulse.speed)
+                    .clamp_range(0.0..=10.0)
+                    .speed(0.01),
+            );
+        });
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("light.colour_cycle"));
+            ui.add(
+                DragValue::new(&mut self.colour_cycle.speed)
+                    .clamp_range(-2.0..=2.0)
+                    .speed(0.01),
+            );
+        });
+    }
+}
+
+// One dimensional perlin noise, going smoothly between -1 and 1 and passing
+// through 0 at every whole number
+fn perlin(x: f32) -> f32 {
+    // A gradient between -1 and 1 for each whole number, from a hash of it
+    let gradient = |i: i32| {
+        let mut hash = (i as u32).wrapping_mul(0x27d4_eb2d);
+        hash ^= hash >> 15;
+        hash = hash.wrapping_mul(0x85eb_ca6b);
+        hash ^= hash >> 13;
+        hash as f32 / u32::MAX as f32 * 2.0 - 1.0
+    };
+
+    let cell = x.floor();
+    let t = x - cell;
+    let fade = t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+
+    let a = gradient(cell as i32) * t;
+    let b = gradient(cell as i32 + 1) * (t - 1.0);
+
+    // On its own it only gets to half way
+    2.0 * (a + (b - a) * fade)
+}
+
+// Turns a colour `turns` of the way round the colour wheel, by rotating it
+// around the grey diagonal
+fn rotate_hue(colour: [f32; 3], turns: f32) -> [f32; 3] {
+    let (sin, cos) = (turns * TAU).sin_cos();
+    let k = (1.0 - cos) / 3.0;
+    let s = sin / 3f32.sqrt();
+    let [r, g, b] = colour;
+
+    [
+        r * (cos + k) + g * (k - s) + b * (k + s),
+        r * (k + s) + g * (cos + k) + b * (k - s),
+        r * (k - s) + g * (k + s) + b * (cos + k),
+    ]
+    .map(|c| c.max(0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: [f32; 3], b: [f32; 3]) -> bool {
+        a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-4)
+    }
+
+    #[test]
+    fn light_animation() {
+        let light = LightUniform::new([0.0; 3], [1.0, 0.0, 0.0], 10.0, 2.0);
+
+        // Nothing changes unless it's turned on
+        let still = LightAnimation::default().apply(light, 12.3);
+        assert_eq!(still.brightness, 2.0);
+        assert_eq!(still.colour, [1.0, 0.0, 0.0]);
+
+        // A third of the way round, red's green
+        let mut animation = LightAnimation::default();
+        animation.colour_cycle.speed = 1.0 / 3.0;
+        assert!(close(animation.apply(light, 1.0).colour, [0.0, 1.0, 0.0]));
+        assert!(close(animation.apply(light, 3.0).colour, [1.0, 0.0, 0.0]));
+
+        // The flicker stays within its amount, and never goes negative
+        animation.flicker.amount = 1.0;
+        for i in 0..1000 {
+            let brightness = animation.apply(light, i as f32 * 0.013).brightness;
+            assert!((0.0..=4.0).contains(&brightness));
+        }
+        assert!((0..100).all(|i| perlin(i as f32 - 50.0) == 0.0));
+    }
+}
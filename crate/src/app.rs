@@ -1,339 +1,395 @@
-use std::{sync::Arc, f32::INFINITY};
+use std::{future::Future, path::PathBuf, sync::Arc};
 
+use cfg_if::cfg_if;
+use cgmath::{EuclideanSpace, MetricSpace, Point3};
 use egui::DragValue;
+use rapier3d::na;
+use rapier3d::prelude::RigidBodyHandle;
 use instant::Instant;
 
-use anyhow::anyhow;
-use egui_wgpu::renderer::ScreenDescriptor;
 use egui_winit_platform::{Platform, PlatformDescriptor};
-use kira::{
-    manager::{AudioManager, AudioManagerSettings},
-    sound::static_sound::{StaticSoundData, StaticSoundHandle},
-};
-use wgpu::{
-    util::{BufferInitDescriptor, DeviceExt},
-    TextureViewDescriptor,
-};
 use winit::{
     dpi::PhysicalSize,
-    event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent},
-    window::Window,
+    event::{
+        ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+    },
+    window::{Fullscreen, Window},
 };
 
-use crate::{camera::Camera, physics};
-use crate::light;
-use crate::{input, model::InstanceRaw, physics::PhysicsSimulation};
 use crate::{
-    model::{self, ModelVertex, Vertex},
-    resources, texture,
+    ambience::{AmbientSound, Attachment},
+    assets::Assets,
+    audio::{self, AudioSystem, Music, Sound, SoundEvent},
+    bench::{self, Bench},
+    camera::Camera,
+    camera_controller::{CameraController, CameraMode, CameraSmoothing, Fly},
+    camera_effects::CameraEffects,
+    camera_path::{self, CameraPath},
+    collider_gen::{self, ColliderReport, ColliderSettings, ColliderShape},
+    clipboard,
+    config::{Config, FrameLimit, PresentMode},
+    diagnostics::Diagnostics,
+    editor::{self, Editor},
+    choreography::{Action, Sequencer},
+    emitter::Emitter,
+    entities::{Bodies, Entities, Entity},
+    error::{self, Error},
+    fonts::FontSettings,
+    gpu_timings::GpuPass,
+    impostor::RenderMode,
+    integrity,
+    kiosk::Kiosk,
+    labels,
+    locale::{Locale, Strings},
+    physics,
+    scene::{self, Prop, SceneDescription},
+    selection::{Group, MarqueeShape, Selection},
+    stats::StatsRecorder,
+    sun::DayCycle,
+    text::TextAnchor,
+    throttle::SpawnThrottle,
+    touch::{TouchAction, TouchLayout},
+    upload::{self, SharedUploads, UploadScheduler},
 };
-
-const CLEAR_COLOUR: wgpu::Color = wgpu::Color {
-    r: 0.5,
-    g: 0.82,
-    b: 0.98,
-    a: 1.0,
+#[cfg(feature = "debug-render")]
+use crate::debug_collider::{ColliderView, DebugCollider};
+use crate::animation::LightAnimation;
+use crate::light::{self, LightMode};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::storage::{self, Location};
+use crate::tasks::{self, Task};
+use crate::{
+    input::{self, Command, InputMap},
+    physics::{PhysicsSimulation, ProjectileSettings},
+    prompts::{self, Interactable, LookedAt},
+};
+use crate::{
+    model,
+    notifications::Notifications,
+    onboarding::{Onboarding, Step},
+    pacing::{self, FramePacer},
+    quality::{QualityPreset, QualitySettings},
+    render_inputs::{Culling, RenderInputs, SceneBuffers},
+    renderer::{Frame, Renderer},
+    tweaks::ShaderTweaks,
 };
 
+// Size of the crosses drawn on contact points, and length of their normals
+#[cfg(feature = "debug-render")]
+const CONTACT_CROSS_SIZE: f32 = 0.15;
+#[cfg(feature = "debug-render")]
+const CONTACT_NORMAL_LENGTH: f32 = 0.4;
+// Contacts go from yellow to purple as their impulse gets up to this
+#[cfg(feature = "debug-render")]
+const CONTACT_STRONG_IMPULSE: f32 = 5.0;
+
+// Seconds the clean mode hint shows up for
+const CLEAN_MODE_HINT_DURATION: f32 = 2.0;
+
+// Running out of gpu memory this many times in a row (each time after
+// freeing some up) means there's no point carrying on
+const MAX_OUT_OF_MEMORY_RECOVERIES: u32 = 2;
+// Same for the surface being lost even after setting it up again, which means
+// the gpu's been lost along with it
+const MAX_LOST_SURFACE_RECOVERIES: u32 = 3;
+
+// How far reis get blown away from an emitter by exploding it
+const EXPLODE_RADIUS: f32 = 8.0;
+// Speed given to the reis closest to the explosion
+const EXPLODE_STRENGTH: f32 = 12.0;
+// Where the hud goes and how big it is, in points
+const HUD_POSITION: [f32; 2] = [12.0, 10.0];
+const HUD_SIZE: f32 = 20.0;
+// How far below and to the right of the mouse the editor's readout of where
+// things are goes, and how big it is, in points
+const READOUT_OFFSET: f32 = 18.0;
+const READOUT_SIZE: f32 = 14.0;
+// How fast the editor's "throw up" button throws the group
+const LIFT_SPEED: f32 = 10.0;
+// How big the crosses over each rei in the editor's group are
+const GROUP_MARKER_SIZE: f32 = 0.3;
+const GROUP_MARKER_COLOUR: [f32; 3] = [1.0, 0.9, 0.2];
+
+// How many reis each emitter lets out when the spawn button's pressed
+const TOUCH_BURST: usize = 5;
+// How hard clicking on something shoves it, as a change in speed
+const PUSH_SPEED: f32 = 8.0;
+
+// Thuds are at full volume once something hits the ground this hard (in
+// newtons), and quieter below that
+const THUD_FULL_FORCE: f32 = 20000.0;
+
+// How many pixels of scrolling on a touchpad count as one line of a mouse
+// wheel
+const SCROLL_LINE_HEIGHT: f32 = 40.0;
+
+// Where the camera starts off, and goes back to on a full reset
+const CAMERA_START: [f32; 3] = [0.25, 3.8, 9.65];
+
 #[derive(PartialEq)]
 pub enum State {
     Loading,
     Playing,
+    // Something went wrong while loading, the error gets shown on screen
+    Failed,
+}
+
+/// What gets left alone when the simulation's reset. The reis are always
+/// cleared away, and anything not kept goes back to how the scene had it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResetOptions {
+    pub keep_camera: bool,
+    /// The light, and the sun
+    pub keep_light: bool,
+    /// The emitters' settings, and where they and the props have been moved to
+    pub keep_emitters: bool,
+}
+
+impl ResetOptions {
+    /// Just starts the action over, for when someone's presenting it
+    pub const BODIES: Self = Self {
+        keep_camera: true,
+        keep_light: true,
+        keep_emitters: true,
+    };
+
+    /// The scene as it was loaded, but seen from wherever the camera is
+    pub const SCENE: Self = Self {
+        keep_camera: true,
+        keep_light: false,
+        keep_emitters: false,
+    };
+
+    /// Back to how it was when the scene was first loaded
+    pub const FULL: Self = Self {
+        keep_camera: false,
+        keep_light: false,
+        keep_emitters: false,
+    };
 }
 
-pub const SAMPLE_COUNT: u32 = 4;
+// Which way a camera path's going while its file dialog is open
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Copy)]
+enum CameraPathPick {
+    Export,
+    Import,
+}
 
 pub struct App {
     // WGPU stuff
-    surface: wgpu::Surface,
-    config: wgpu::SurfaceConfiguration,
-    pub device: Arc<wgpu::Device>,
-    pub queue: Arc<wgpu::Queue>,
-    size: PhysicalSize<u32>,
-    window: Window,
-    pipeline: wgpu::RenderPipeline,
-    depth_texture: texture::Texture,
-    msaa_texture: wgpu::Texture,
-    msaa_view: wgpu::TextureView,
+    pub renderer: Renderer,
+    pub uploads: SharedUploads,
+    pub assets: Assets,
+    window: Arc<Window>,
+    // Settings that are kept between runs, and what they were when they were
+    // last saved
+    config: Config,
+    saved_config: Config,
+    // Debug view that shades reis by speed instead of normally
+    colour_by_speed: bool,
+    quality: QualitySettings,
+    // None if the settings have been changed from the preset's
+    quality_preset: Option<QualityPreset>,
+    // How many times in a row the gpu's run out of memory without a frame
+    // getting drawn in between
+    out_of_memory_failures: u32,
+    // The same for the surface being lost
+    lost_surfaces: u32,
+    // Decides when frames get drawn, see pacing.rs
+    pub pacer: FramePacer,
     // The rest of the app
     // Since this is so simple there's not really much
     //
     // ...
     // This was a comment from a simpler time
     keyboard: input::KeyboardWatcher,
+    input_map: InputMap,
+    // Whatever can be interacted with in the middle of the screen
+    looked_at: Option<LookedAt>,
+    // Where the mouse is in the window, in physical pixels
+    cursor: Option<[f32; 2]>,
+    // The body that was last clicked on, which gets outlined
+    picked: Option<RigidBodyHandle>,
+    // Which model the outline's drawn with this frame, if anything's outlined
+    outlined: Option<Bodies>,
     pub state: State,
+    error: Option<Error>,
 
-    pub rei_model: Option<model::Model>,
-    pub light_model: Option<model::Model>,
+    // Every model in the scene, and what it's drawn at
+    pub entities: Entities,
     camera: Camera,
+    camera_effects: CameraEffects,
+    // What's moving the camera around, and which one it is
+    camera_mode: CameraMode,
+    camera_controller: Box<dyn CameraController>,
+    // When the keys were last looked at to move the camera, and how far
+    // (in seconds) it's been moved on past the last update by latching it
+    // just before a frame was sent off (see latch_camera)
+    camera_sampled: Instant,
+    camera_latched: f32,
+    // Whether the camera's moved since it was last sent to the gpu
+    camera_moved: bool,
+    // The camera, light, tweaks and instances, as the gpu sees them
+    buffers: SceneBuffers,
 
     light_uniform: light::LightUniform,
-    light_buffer: wgpu::Buffer,
-    light_bind_group: wgpu::BindGroup,
-    light_pipeline: wgpu::RenderPipeline,
+    // Lighting numbers for the model shader, editable from the ui
+    tweaks: ShaderTweaks,
+    show_tweaks: bool,
+    // Whether any of the ui windows are open, which turns the sound down
+    menus_open: bool,
+    // The simulation (and the sound) stops, but the camera can still move
+    paused: bool,
+    // How fast the simulation goes, where 1 is normal and less is slow motion
+    time_scale: f32,
+    // Nothing but the scene gets drawn, for recording it
+    clean_mode: bool,
+    hide_cursor_in_clean_mode: bool,
+    // Names floating over the emitters (never in clean mode)
+    show_emitter_labels: bool,
+    // The time and how many reis there are, in the top left corner
+    show_hud: bool,
+    // Seconds left of the hint saying how to get out of clean mode
+    clean_mode_hint: f32,
+    // Set when it's running unattended at an installation
+    kiosk: Option<Kiosk>,
+    // Set while it's running the benchmark (see bench.rs)
+    bench: Option<Bench>,
+    // Set once the kiosk's passphrase has been typed, or the benchmark's done
+    quit_requested: bool,
+    light_mode: LightMode,
+    light_shadow: light::LightShadow,
+    // Applied on top of the light's colour and brightness as they're sent to
+    // the gpu, so what's in light_uniform (and gets saved) stays put
+    light_animation: LightAnimation,
+    // The sun, and the time of day
+    day: DayCycle,
+    shadow_budget: light::ShadowBudget,
+    // Where the light is relative to the selection, when following it
+    light_follow_offset: [f32; 3],
+    selection: Option<Selection>,
+    editor: Editor,
+    // The reis picked out with the editor's marquee, and what they get
+    // tinted with
+    group: Group,
+    group_tint: [f32; 3],
+    // The big buttons for phones and tablets, used instead of the windows
+    touch: TouchLayout,
+    // Holds the emitters back while frames are taking too long
+    spawn_throttle: SpawnThrottle,
+    // How long the last frame's update and drawing took, not counting
+    // waiting for the screen to be ready for it or presenting it
+    update_work: std::time::Duration,
+    render_work: std::time::Duration,
+    // The introduction that's shown the first time it's opened
+    onboarding: Onboarding,
+    // Reis that stay put, as they're saved in the scene. The simulation gets
+    // a copy whenever they change.
+    props: Vec<Prop>,
+    // Debug view of the contact points between colliders
+    #[cfg(feature = "debug-render")]
+    show_contacts: bool,
+    // Contacts pushing less than this are left out of the debug view
+    #[cfg(feature = "debug-render")]
+    contact_min_impulse: f32,
+    #[cfg(feature = "debug-render")]
+    collider_view: ColliderView,
+    // One for each shape in the simulation, made when the collider view is
+    // first turned on. Emptied whenever the simulation's replaced, since its
+    // reis might have different colliders.
+    #[cfg(feature = "debug-render")]
+    debug_colliders: Vec<DebugCollider>,
+    render_mode: RenderMode,
+    // Set once auto mode has switched over to sprites
+    sprites_active: bool,
+    // In auto mode, reis get drawn as sprites if the fps drops below this
+    sprite_fps_threshold: f32,
 
     // Audio
-    pub song: Option<StaticSoundData>,
-    song_handle: Option<StaticSoundHandle>,
-    audio_manager: Option<AudioManager>,
+    // Taken once it starts playing, since streamed songs can only be played once
+    pub song: Option<Music>,
+    // Set if the song couldn't be played, so it isn't tried again every frame
+    music_failed: bool,
+    audio: AudioSystem,
+    // Play a click whenever something hits something else
+    collision_sounds: bool,
+    click_sound: Sound,
+    // Play a thud whenever something lands on the ground hard
+    thud_sounds: bool,
+    thud_sound: Sound,
 
     // Egui stuff
     pub egui_platform: Platform,
-    egui_renderer: egui_wgpu::Renderer,
     start_time: Instant,
 
     physics: PhysicsSimulation,
-    rei_instance_buffer: wgpu::Buffer,
-
-    frames_counted: u32,
-    frame_counter: Instant,
-    fps: f32,
-}
-
-fn create_render_pipeline(
-    device: &wgpu::Device,
-    label: &str,
-    layout: &wgpu::PipelineLayout,
-    colour_format: wgpu::TextureFormat,
-    depth_format: Option<wgpu::TextureFormat>,
-    vertex_layouts: &[wgpu::VertexBufferLayout],
-    shader: &wgpu::ShaderModule,
-    samples: u32,
-) -> wgpu::RenderPipeline {
-    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some(label),
-        layout: Some(&layout),
-        vertex: wgpu::VertexState {
-            module: shader,
-            entry_point: "vs_main",
-            buffers: vertex_layouts,
-        },
-        fragment: Some(wgpu::FragmentState {
-            module: shader,
-            entry_point: "fs_main",
-            targets: &[Some(wgpu::ColorTargetState {
-                format: colour_format,
-                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                write_mask: wgpu::ColorWrites::ALL,
-            })],
-        }),
-        primitive: wgpu::PrimitiveState {
-            topology: wgpu::PrimitiveTopology::TriangleList,
-            strip_index_format: None,
-            front_face: wgpu::FrontFace::Ccw,
-            cull_mode: Some(wgpu::Face::Back),
-            // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-            polygon_mode: wgpu::PolygonMode::Fill,
-            // Requires Features::DEPTH_CLIP_CONTROL
-            unclipped_depth: false,
-            // Requires Features::CONSERVATIVE_RASTERIZATION
-            conservative: false,
-        },
-        depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
-            format,
-            depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::Less,
-            stencil: Default::default(),
-            bias: Default::default(),
-        }),
-        multisample: wgpu::MultisampleState {
-            count: samples,
-            ..Default::default()
-        },
-        multiview: None,
-    })
+    // Keep using the same seed when the simulation's reset, so the same run
+    // plays out again
+    fixed_seed: bool,
+    // Seconds spent in the playing state, used for animations
+    scene_time: f32,
+    sequencer: Sequencer,
+    // The scene as it was loaded, for the parts that can't be edited when
+    // it's saved again
+    scene: SceneDescription,
+    // The rei's collider while it's being made from the model and measured.
+    // The simulation waits for a generated one, so a seeded run always
+    // plays out the same.
+    rei_collider_task: Option<Task<collider_gen::Generated>>,
+    // How well the rei's collider fits, once it's been measured
+    collider_report: Option<ColliderReport>,
+    // How the balls thrown with the bowl command are thrown
+    projectile_settings: ProjectileSettings,
+
+    stats: StatsRecorder,
+    diagnostics: Diagnostics,
+    fonts: FontSettings,
+    // The file dialog for a camera path that's being exported or imported.
+    // It's None if it was cancelled.
+    #[cfg(not(target_arch = "wasm32"))]
+    camera_path_dialog: Option<(CameraPathPick, Task<Option<PathBuf>>)>,
+    // A camera path that's being picked and read on the web
+    #[cfg(target_arch = "wasm32")]
+    camera_path_upload: Option<Task<error::Result<(String, String)>>>,
+    // Whether saving's been asked for, and is waiting on saving over the
+    // scene file being confirmed
+    #[cfg(not(target_arch = "wasm32"))]
+    confirming_save: bool,
+    locale: Locale,
+    notifications: Notifications,
 }
 
 impl App {
-    pub async fn new(window: Window) -> anyhow::Result<Self> {
-        // --- RENDERER CODE ---
-        // A lot of this instantiation boilerplate (as well as a lot of the
-        // code, to be fair) was taken from the wgpu tutorial at
-        // https://sotrh.github.io/learn-wgpu/
+    pub async fn new(
+        window: Arc<Window>,
+        mut config: Config,
+        kiosk: Option<Kiosk>,
+        bench: Option<Bench>,
+    ) -> error::Result<Self> {
         let size = window.inner_size();
-
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            dx12_shader_compiler: Default::default(),
-        });
-
-        // SAFETY: surface should live as long as the window as they are both
-        // owned by the same struct. I'm pretty sure. That's what they said
-        // on the tutorial. But aren't self referential structs generally
-        // unsafe?
-        let surface = unsafe { instance.create_surface(&window) }?;
-
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: Default::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .ok_or(anyhow!("Error requesting wgpu adapter."))?;
-
-        log::info!("Backend: {:?}", adapter.get_info().backend);
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    features: wgpu::Features::empty(),
-                    limits: if cfg!(target_arch = "wasm32") {
-                        wgpu::Limits::downlevel_webgl2_defaults()
-                            .using_resolution(wgpu::Limits::default())
-                    } else {
-                        wgpu::Limits::default()
-                    },
-                },
-                None, /*trace_path*/
-            )
-            .await?;
-
-        let surface_capabilities = surface.get_capabilities(&adapter);
-
-        let format = surface_capabilities
-            .formats
-            .iter()
-            .copied()
-            .find(|f| f.is_srgb())
-            .unwrap_or(surface_capabilities.formats[0]);
-
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format,
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
-            alpha_mode: surface_capabilities.alpha_modes[0],
-            view_formats: vec![],
-        };
-
-        surface.configure(&device, &config);
-
-        let camera = Camera::new(
-            &device,
-            &queue,
-            (0.25, 3.8, 9.65).into(),
-            config.width as f32 / config.height as f32,
-        );
-
-        let light_uniform = light::LightUniform::new([2.0, 3.0, 2.0], [0.96, 0.68, 1.0], 15.0, 1.5);
-
-        let light_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Light buffer"),
-            contents: bytemuck::cast_slice(&[light_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let light_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("light bind group layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-            });
-
-        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("light bind group"),
-            layout: &light_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding(),
-            }],
-        });
-
-        let camera_bind_group_layout = Camera::bind_group_layout(&device);
-
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("pipeline layout descriptor"),
-            bind_group_layouts: &[
-                camera_bind_group_layout,
-                texture::Texture::texture_bind_group_layout(&device),
-                &light_bind_group_layout,
-            ],
-            push_constant_ranges: &[],
-        });
-
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("model shader"),
-            source: wgpu::ShaderSource::Wgsl(
-                #[cfg(debug_assertions)]
-                resources::load_string("shaders/model_shader.wgsl")
-                    .await?
-                    .into(),
-                #[cfg(not(debug_assertions))]
-                include_str!("../shaders/model_shader.wgsl").into(),
-            ),
-        });
-
-        let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "depth texture");
-
-        let pipeline = create_render_pipeline(
-            &device,
-            "render pipeline",
-            &pipeline_layout,
-            config.format,
-            Some(texture::Texture::DEPTH_FORMAT),
-            &[ModelVertex::desc(), InstanceRaw::desc()],
-            &shader,
-            SAMPLE_COUNT,
+        let renderer = Renderer::new(&window, &config).await?;
+        let device = renderer.device.as_ref();
+        // Which is the one from the settings, if the surface can do it
+        config.present_mode = PresentMode::from_wgpu(renderer.present_mode());
+
+        let quality_preset = renderer.suggested_preset();
+        let mut quality = quality_preset.settings();
+        // Which is the one from the settings, if the gpu can do it
+        quality.sample_count = renderer.sample_count();
+
+        let mut pacer = FramePacer::new(
+            config.frame_limit,
+            config.present_mode == PresentMode::Vsync,
         );
+        pacer.set_refresh_rate(pacing::monitor_refresh_rate(&window));
 
-        let light_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Light shader"),
-            source: wgpu::ShaderSource::Wgsl(
-                #[cfg(debug_assertions)]
-                resources::load_string("shaders/light_shader.wgsl")
-                    .await?
-                    .into(),
-                #[cfg(not(debug_assertions))]
-                include_str!("../shaders/light_shader.wgsl").into(),
-            ),
-        });
-
-        let light_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Light pipeline layout"),
-                bind_group_layouts: &[camera_bind_group_layout, &light_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-
-        let light_pipeline = create_render_pipeline(
-            &device,
-            "light pipeline",
-            &light_pipeline_layout,
-            config.format,
-            Some(texture::Texture::DEPTH_FORMAT),
-            &[ModelVertex::desc()],
-            &light_shader,
-            SAMPLE_COUNT,
+        let camera = Camera::new(
+            CAMERA_START.into(),
+            size.width as f32 / size.height as f32,
         );
-
-        let msaa_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("msaa texture"),
-            size: wgpu::Extent3d {
-                width: size.width,
-                height: size.height,
-                depth_or_array_layers: 1,
-            },
-            sample_count: SAMPLE_COUNT,
-            dimension: wgpu::TextureDimension::D2,
-            format: config.format,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            mip_level_count: 1,
-            view_formats: &[],
-        });
-
-        let msaa_view = msaa_texture.create_view(&TextureViewDescriptor::default());
+        let light_uniform = light::LightSettings::default().uniform();
+        let tweaks = ShaderTweaks::default();
 
         let egui_platform = Platform::new(PlatformDescriptor {
             physical_width: size.width,
@@ -342,354 +398,3030 @@ impl App {
             ..Default::default()
         });
 
-        let egui_renderer = egui_wgpu::Renderer::new(
-            &device,
-            config.format,
-            Some(texture::Texture::DEPTH_FORMAT),
-            SAMPLE_COUNT,
-        );
+        let mut fonts = FontSettings::new();
+        fonts.apply_default(&egui_platform.context());
 
-        let physics = PhysicsSimulation::new();
+        let uploads = UploadScheduler::shared(upload::DEFAULT_BUDGET);
+        let assets = Assets::new(renderer.device.clone(), uploads.clone());
 
-        let rei_instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Rei instance buffer"),
-            size: (std::mem::size_of::<InstanceRaw>() * (physics::NUM_REIS + 1)) as _,
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+        let locale = Locale::new(&assets, config.language).await;
+
+        // Until the scene file is loaded, just use the default emitter
+        let mut physics = PhysicsSimulation::with_seed(physics::random_seed());
+        physics.set_max_reis(quality.max_reis);
+
+        let mut notifications = Notifications::new();
+        if quality_preset != QualityPreset::High {
+            let strings = locale.strings();
+            notifications.push(strings.format(
+                "notifications.slow_device",
+                &[("preset", &quality_preset.name(&strings))],
+            ));
+        }
+
+        let mut buffers = SceneBuffers::new(
+            device,
+            &renderer.queue,
+            &camera,
+            light_uniform,
+            tweaks.uniform(),
+            quality.max_reis,
+            renderer.culling.is_some(),
+        );
+        let inputs = RenderInputs::extract(&physics, None, None, light_uniform, tweaks.uniform());
+        buffers.upload(device, &renderer.queue, &inputs);
 
-        queue.write_buffer(&rei_instance_buffer, 0, bytemuck::cast_slice(&physics.instances()));
+        let mut audio = AudioSystem::new();
+        audio.set_master_volume(config.master_volume);
+        audio.set_music_volume(config.music_volume);
+        audio.set_sfx_volume(config.sfx_volume);
+        audio.set_music_loudness(config.music_loudness);
+        audio.set_muted(config.muted);
+        audio.set_preserve_music_pitch(config.preserve_music_pitch);
 
         Ok(Self {
-            surface,
-            config,
-            device: Arc::new(device),
-            queue: Arc::new(queue),
-            size,
+            renderer,
+            uploads,
+            assets,
             window,
-            pipeline,
-            depth_texture,
-            rei_model: None,
-            light_model: None,
+            config,
+            saved_config: config,
+            colour_by_speed: false,
+            quality,
+            quality_preset: (quality == quality_preset.settings()).then_some(quality_preset),
+            out_of_memory_failures: 0,
+            lost_surfaces: 0,
+            pacer,
+            entities: Entities::default(),
             camera,
-            msaa_texture,
-            msaa_view,
+            camera_effects: CameraEffects::default(),
+            camera_mode: CameraMode::Fly,
+            camera_controller: Box::new(Fly::new(config.camera_smoothing)),
+            camera_sampled: Instant::now(),
+            camera_latched: 0.0,
+            camera_moved: true,
+            buffers,
 
             keyboard: input::KeyboardWatcher::new(),
+            input_map: InputMap::default(),
+            looked_at: None,
+            cursor: None,
+            picked: None,
+            outlined: None,
             song: None,
-            song_handle: None,
-            audio_manager: None,
+            music_failed: false,
+            audio,
+            collision_sounds: false,
+            click_sound: audio::click_sound(),
+            thud_sounds: true,
+            thud_sound: audio::thud_sound(),
             light_uniform,
-            light_buffer,
-            light_bind_group,
-            light_pipeline,
+            tweaks,
+            show_tweaks: false,
+            menus_open: false,
+            paused: false,
+            time_scale: 1.0,
+            clean_mode: kiosk.is_some() || bench.is_some(),
+            hide_cursor_in_clean_mode: true,
+            show_emitter_labels: true,
+            show_hud: false,
+            clean_mode_hint: 0.0,
+            kiosk,
+            bench,
+            quit_requested: false,
+            light_mode: LightMode::Orbit,
+            day: DayCycle::default(),
+            light_shadow: Default::default(),
+            light_animation: Default::default(),
+            shadow_budget: Default::default(),
+            light_follow_offset: light::FOLLOW_OFFSET,
+            selection: None,
+            editor: Editor::default(),
+            group: Group::default(),
+            group_tint: [1.0, 0.4, 0.4],
+            touch: TouchLayout::new(),
+            spawn_throttle: SpawnThrottle::new(config.adaptive_spawning),
+            update_work: std::time::Duration::ZERO,
+            render_work: std::time::Duration::ZERO,
+            onboarding: Onboarding::new(config.seen_onboarding),
+            props: Vec::new(),
+            #[cfg(feature = "debug-render")]
+            show_contacts: false,
+            #[cfg(feature = "debug-render")]
+            contact_min_impulse: 0.0,
+            #[cfg(feature = "debug-render")]
+            collider_view: ColliderView::Hidden,
+            #[cfg(feature = "debug-render")]
+            debug_colliders: Vec::new(),
+            render_mode: RenderMode::Auto,
+            sprites_active: false,
+            sprite_fps_threshold: 20.0,
 
             state: State::Loading,
+            error: None,
             egui_platform,
-            egui_renderer,
             start_time: Instant::now(),
             physics,
-            rei_instance_buffer,
-            frames_counted: 0,
-            frame_counter: Instant::now(),
-            fps: 0.0,
-
-
+            fixed_seed: false,
+            scene_time: 0.0,
+            sequencer: Sequencer::new(Default::default()),
+            scene: Default::default(),
+            rei_collider_task: None,
+            collider_report: None,
+            projectile_settings: ProjectileSettings::default(),
+            stats: StatsRecorder::new(),
+            diagnostics: Diagnostics::default(),
+            fonts,
+            #[cfg(not(target_arch = "wasm32"))]
+            camera_path_dialog: None,
+            #[cfg(target_arch = "wasm32")]
+            camera_path_upload: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            confirming_save: false,
+            locale,
+            notifications,
         })
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        match self.state {
+        let result = match self.state {
             State::Loading => self.render_loading(),
             State::Playing => self.render_loaded(),
+            State::Failed => self.render_error(),
+        };
+
+        if result.is_ok() {
+            self.out_of_memory_failures = 0;
+            self.lost_surfaces = 0;
         }
+
+        result
     }
 
-    pub fn render_loading(&mut self) -> Result<(), wgpu::SurfaceError> {
-        // TODO: Loading screen
-        let output = self.surface.get_current_texture()?;
-        let view = output.texture.create_view(&Default::default());
-
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
-
-        let render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("Render pass"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &self.msaa_view,
-                resolve_target: Some(&view),
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color::BLUE),
-                    store: true,
-                },
-            })],
-            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &self.depth_texture.view,
-                depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(1.0),
-                    store: true,
-                }),
-                stencil_ops: None,
-            }),
-        });
+    /// Sets the surface up again after it's been lost. Returns false if
+    /// that's already been tried too many times in a row without it helping,
+    /// in which case the gpu's gone and the app has to be restarted.
+    pub fn recover_from_lost_surface(&mut self) -> bool {
+        if self.lost_surfaces >= MAX_LOST_SURFACE_RECOVERIES {
+            log::error!("The surface is still lost after setting it up again, the gpu's gone");
+            return false;
+        }
 
-        drop(render_pass);
+        self.lost_surfaces += 1;
+        self.resize(self.size());
+        true
+    }
 
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+    /// Starts a new app in the same window with the same settings, for when
+    /// the gpu's been lost and everything that was on it has to be made
+    /// again. It comes with the scene as it is now (if it's finished loading),
+    /// to be put back once the new app's loaded.
+    pub fn restart(
+        &self,
+    ) -> (
+        impl Future<Output = error::Result<App>> + 'static,
+        Option<SceneDescription>,
+    ) {
+        // Benchmarks can't be compared with one that started over partway
+        if self.bench.is_some() {
+            log::warn!("Restarting stopped the benchmark");
+        }
 
-        Ok(())
+        let app = App::new(self.window.clone(), self.config, self.kiosk.clone(), None);
+        let scene = (self.state == State::Playing).then(|| self.current_scene());
+        (app, scene)
     }
 
-    pub fn render_loaded(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
-        let view = output.texture.create_view(&Default::default());
+    /// Tries to free up enough gpu memory to keep going after running out:
+    /// half as many reis, no antialiasing and none of the assets that aren't
+    /// being used, then the surface is set up again for the next frame.
+    /// Returns false if that's already been tried too many times in a row
+    /// without it helping, in which case there's nothing left but to quit.
+    pub fn recover_from_out_of_memory(&mut self) -> bool {
+        if self.out_of_memory_failures >= MAX_OUT_OF_MEMORY_RECOVERIES {
+            log::error!("Still out of gpu memory after trying to free some up, giving up");
+            return false;
+        }
 
-        let screen_descriptor = ScreenDescriptor {
-            size_in_pixels: [self.config.width, self.config.height],
-            pixels_per_point: self.window.scale_factor() as f32,
+        self.out_of_memory_failures += 1;
+
+        // This isn't a choice anyone made, so it shouldn't be remembered as
+        // their antialiasing setting
+        let sample_count = self.config.sample_count;
+        let quality = QualitySettings {
+            max_reis: (self.quality.max_reis / 2).max(1),
+            sample_count: 1,
+            ..self.quality
         };
+        self.quality_preset = None;
+        self.apply_quality(quality);
+        self.config.sample_count = sample_count;
 
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
+        let unloaded = self.assets.unload_unused();
+        self.resize(self.size());
+
+        log::warn!(
+            "Out of gpu memory, dropped to {} reis without antialiasing and unloaded {unloaded} unused assets",
+            quality.max_reis
+        );
+        self.notifications.push(self.locale.strings().format(
+            "notifications.out_of_memory",
+            &[("max_reis", &quality.max_reis)],
+        ));
 
-        // Egui setup
+        true
+    }
+
+    // Runs a frame of egui, and gets the renderer ready to draw it over the frame
+    fn run_ui(&mut self, frame: &mut Frame, build_ui: impl FnOnce(&mut Self, &egui::Context)) {
         self.egui_platform
             .update_time(self.start_time.elapsed().as_secs_f64());
         self.egui_platform.begin_frame();
 
-        self.ui(&self.egui_platform.context());
+        build_ui(self, &self.egui_platform.context());
 
         let full_output = self.egui_platform.end_frame(Some(&self.window));
-        let paint_jobs = self.egui_platform.context().tessellate(full_output.shapes);
-        let textures_delta = full_output.textures_delta;
+        self.renderer.prepare_ui(
+            frame,
+            &self.egui_platform.context(),
+            full_output,
+            self.window.scale_factor() as f32,
+        );
+    }
+
+    pub fn render_error(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let mut frame = self.renderer.begin_frame()?;
+
+        self.run_ui(&mut frame, |app, ctx| {
+            let Some(error) = app.error.as_ref() else {
+                return;
+            };
+
+            let strings = app.locale.strings();
+            egui::CentralPanel::default().show(ctx, |ui| {
+                ui.heading(strings.get("error.heading"));
+                ui.add_space(10.0);
+                ui.label(error.report());
+                ui.add_space(10.0);
+                ui.label(error.hint());
+
+                #[cfg(target_arch = "wasm32")]
+                if matches!(error, Error::AssetCorrupt { .. }) && ui.button(strings.get("error.download_again")).clicked() {
+                    integrity::redownload();
+                }
+            });
+        });
+
+        drop(self.renderer.scene_pass(&mut frame, wgpu::Color::BLACK));
+
+        self.renderer.end_frame(frame);
+
+        Ok(())
+    }
+
+    pub fn render_loading(&mut self) -> Result<(), wgpu::SurfaceError> {
+        // TODO: Loading screen
+        let mut frame = self.renderer.begin_frame()?;
+
+        // This goes into the scene target like the scene does, so there's
+        // something to fade from once loading's done
+        drop(self.renderer.scene_pass(&mut frame, wgpu::Color::BLUE));
 
-        for texture in textures_delta.free.iter() {
-            self.egui_renderer.free_texture(texture);
+        self.renderer.end_frame(frame);
+
+        Ok(())
+    }
+
+    pub fn render_loaded(&mut self) -> Result<(), wgpu::SurfaceError> {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.renderer.reload_changed_shaders();
+
+        let mut frame = self.renderer.begin_frame()?;
+        // Getting the frame can mean waiting for the screen, which isn't work
+        let recording = Instant::now();
+        let camera_moved = self.latch_camera();
+        self.prepare_frame(camera_moved);
+
+        self.run_ui(&mut frame, |app, ctx| {
+            if app.clean_mode {
+                app.clean_mode_ui(ctx);
+            } else if app.touch.active {
+                app.touch_ui(ctx);
+            } else {
+                app.ui(ctx);
+            }
+        });
+
+        let use_sprites = self.use_sprites();
+
+        if let Some(rei_model) = self.entities.rei_model() {
+            if use_sprites && !self.renderer.sprites.is_baked() {
+                log::info!("Baking rei sprites");
+                let renderer = &mut self.renderer;
+                renderer
+                    .sprites
+                    .bake(&renderer.device, &renderer.queue, rei_model);
+            }
         }
 
-        for (id, image_delta) in textures_delta.set {
-            self.egui_renderer
-                .update_texture(&self.device, &self.queue, id, &image_delta);
+        // Sprites are for when things are already too slow, so they don't get shadows
+        let shadow_casters = if use_sprites {
+            0
+        } else {
+            self.buffers.shadow_casters
+        };
+
+        if let Some(culling) = &self.renderer.culling {
+            culling.dispatch(frame.encoder());
         }
 
-        self.egui_renderer.update_buffers(
-            &self.device,
-            &self.queue,
-            &mut encoder,
-            &paint_jobs,
-            &screen_descriptor,
+        self.renderer.start_timing(&mut frame);
+
+        if let Some(rei_model) = self.entities.rei_model() {
+            self.renderer.shadow_map.render(
+                frame.encoder(),
+                rei_model,
+                self.buffers.rei_instances.buffer(),
+                shadow_casters,
+            );
+        }
+        self.renderer
+            .finish_timing(frame.encoder(), GpuPass::Shadows);
+        self.renderer.ground.render_splats(frame.encoder());
+
+        // The same colour as the sky at the horizon, for if it's turned off
+        let [r, g, b] = self.light_uniform.sky_colour.map(f64::from);
+        let clear_colour = wgpu::Color { r, g, b, a: 1.0 };
+        let mut render_pass = self.renderer.scene_pass(&mut frame, clear_colour);
+
+        self.renderer.skybox.draw(&mut render_pass);
+
+        self.renderer.ground.draw(
+            &mut render_pass,
+            &self.buffers.camera,
+            &self.buffers.clusters,
+            &self.renderer.shadow_map,
         );
 
-        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("Render pass"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &self.msaa_view,
-                resolve_target: Some(&view),
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(CLEAR_COLOUR),
-                    store: true,
-                },
-            })],
-            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &self.depth_texture.view,
-                depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(1.0),
-                    store: true,
-                }),
-                stencil_ops: None,
-            }),
-        });
+        for entity in self.entities.iter() {
+            // The reis are split up by emitter, and might be culled on the
+            // gpu or drawn as sprites instead
+            if entity.bodies == Some(Bodies::Reis) {
+                if use_sprites {
+                    self.renderer.sprites.draw(
+                        &mut render_pass,
+                        &self.buffers.camera,
+                        self.buffers.rei_instances.buffer(),
+                        &self.buffers.clusters,
+                    );
+                } else {
+                    self.render_reis(&mut render_pass, &entity.model);
+                }
+            } else {
+                self.render_entity(&mut render_pass, entity);
+            }
+        }
+
+        drop(render_pass);
+
+        self.renderer.particles.resolve_depth(frame.encoder());
+        self.renderer.finish_timing(frame.encoder(), GpuPass::Scene);
+
+        // Everything that's drawn over the top of the scene
+        let mut render_pass = self.renderer.overlay_pass(&mut frame);
+
+        self.renderer
+            .particles
+            .draw(&mut render_pass, &self.buffers.camera);
+        // The debug views would spoil the recording in clean mode
+        if !self.clean_mode {
+            #[cfg(feature = "debug-render")]
+            self.renderer.colliders.draw(
+                &mut render_pass,
+                &self.buffers.camera,
+                &self.debug_colliders,
+                self.collider_view,
+            );
+            if let Some(model) = self.outlined.and_then(|bodies| self.entities.model_for(bodies)) {
+                self.renderer
+                    .outline
+                    .draw(&mut render_pass, &self.buffers.camera, model);
+            }
+            self.renderer
+                .lines
+                .draw(&mut render_pass, &self.buffers.camera);
+            self.renderer
+                .text
+                .draw(&mut render_pass, &self.buffers.camera);
+        }
+
+        drop(render_pass);
+        self.renderer
+            .finish_timing(frame.encoder(), GpuPass::Overlay);
+
+        let recorded = recording.elapsed();
+        let submit_time = self.renderer.end_frame(frame);
+        self.render_work = recorded + submit_time;
+        self.diagnostics.record_submit(submit_time);
+        if let Some(bench) = &mut self.bench {
+            bench.record_submit(submit_time);
+        }
+
+        Ok(())
+    }
+
+    // Moves the camera on by whatever keys have been held since it was last
+    // moved, right after the frame's been acquired. Acquiring the frame, the
+    // physics and the ui can all take a while, and otherwise the camera would
+    // be drawn where it was before them. Returns whether it's moved since the
+    // camera was last sent to the gpu.
+    fn latch_camera(&mut self) -> bool {
+        let moved = std::mem::take(&mut self.camera_moved);
+        if !self.config.late_camera || self.bench.is_some() {
+            return moved;
+        }
+
+        let now = Instant::now();
+        let delta_time = now.saturating_duration_since(self.camera_sampled).as_secs_f32();
+        let input = self.keyboard.snapshot_at(now);
+        self.camera_sampled = now;
+        self.camera_latched += delta_time;
+
+        self.camera_controller
+            .update(&mut self.camera, &input, &self.physics, delta_time);
+        self.camera.refresh() || moved
+    }
 
-        // Light Model
-        let light_model = self.light_model.as_ref().unwrap();
-        render_pass.set_pipeline(&self.light_pipeline);
-        render_pass.set_bind_group(0, &self.camera.bind_group, &[]);
-        render_pass.set_bind_group(1, &self.light_bind_group, &[]);
-        render_pass.set_vertex_buffer(0, light_model.meshes[0].vertex_buffer.slice(..));
-        render_pass.set_index_buffer(
-            light_model.meshes[0].index_buffer.slice(..),
-            wgpu::IndexFormat::Uint32,
+    // Gets everything that depends on where the camera is up to the gpu,
+    // once it's been latched for the frame and before any of the frame's
+    // passes are recorded, so nothing's written to behind the gpu's back
+    fn prepare_frame(&mut self, camera_moved: bool) {
+        self.renderer
+            .particles
+            .prepare(&self.renderer.queue, &self.camera);
+        self.renderer
+            .ground
+            .prepare(&self.renderer.device, &self.renderer.queue);
+        self.renderer
+            .skybox
+            .prepare(&self.renderer.queue, &self.camera, self.day.sky_tint());
+        self.update_lines();
+        self.update_text();
+        #[cfg(feature = "debug-render")]
+        self.update_debug_colliders();
+
+        // Everything the gpu needs from this frame goes up in one go
+        let inputs = RenderInputs::extract(
+            &self.physics,
+            camera_moved.then_some(&self.camera),
+            self.culling().as_ref(),
+            self.light_animation.apply(self.light_uniform, self.scene_time),
+            self.tweaks.uniform(),
         );
-        render_pass.draw_indexed(0..light_model.meshes[0].num_indices as _, 0, 0..1);
+        self.buffers
+            .upload(&self.renderer.device, &self.renderer.queue, &inputs);
+        self.entities.update(
+            &self.renderer.device,
+            &self.renderer.queue,
+            &self.physics,
+            &inputs.light,
+        );
+        self.renderer
+            .contact
+            .update(&self.renderer.queue, &inputs.positions);
+        self.update_outline();
+        if let (Some(culling), Some(rei_model)) =
+            (&mut self.renderer.culling, self.entities.rei_model())
+        {
+            culling.update(
+                &self.renderer.device,
+                &self.renderer.queue,
+                &self.buffers.rei_instances,
+                &inputs.clusters,
+                &self.camera.frustum(),
+                rei_model,
+            );
+        }
+        self.diagnostics
+            .set_buffers(self.buffers.instance_buffer_sizes());
+        self.diagnostics
+            .set_cull_counts(inputs.cull_counts, self.renderer.culling.is_some());
+    }
 
-        // Rei
-        render_pass.set_pipeline(&self.pipeline);
-        //render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-        render_pass.set_bind_group(2, &self.light_bind_group, &[]);
-        render_pass.set_vertex_buffer(1, self.rei_instance_buffer.slice(..));
+    fn render_reis<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        rei_model: &'a model::Model,
+    ) {
+        if self.colour_by_speed {
+            render_pass.set_pipeline(&self.renderer.pipelines().speed);
+        } else {
+            render_pass.set_pipeline(&self.renderer.pipelines().model);
+        }
+        render_pass.set_bind_group(0, &self.buffers.camera.bind_group, &[]);
+        render_pass.set_bind_group(3, &self.renderer.shadow_map.bind_group, &[]);
+        render_pass.set_vertex_buffer(1, self.buffers.rei_instances.buffer().slice(..));
+
+        for (index, mesh) in rei_model.meshes.iter().enumerate() {
+            let Some(bind_group) = mesh
+                .material
+                .and_then(|material| rei_model.materials.get(material))
+                .and_then(|material| material.diffuse_bind_group.as_ref())
+            else {
+                continue;
+            };
+
+            render_pass.set_bind_group(1, bind_group, &[]);
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
 
-        let rei_model = self.rei_model.as_ref().unwrap();
+            match &self.renderer.culling {
+                Some(culling) => culling.draw(render_pass, &self.buffers.clusters, 2, index),
+                None => self.buffers.clusters.draw(render_pass, 2, |render_pass, instances| {
+                    render_pass.draw_indexed(0..mesh.num_indices, 0, instances);
+                }),
+            }
+        }
+    }
 
-        for mesh in rei_model.meshes.iter() {
-            let material = &rei_model.materials[mesh.material.unwrap()];
+    // Draws an entity at its own instance, or at every one of its bodies.
+    // Lights are lit by nothing but themselves.
+    fn render_entity<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, entity: &'a Entity) {
+        let instances = match entity.bodies {
+            Some(bodies) => self.buffers.instances(bodies),
+            None => entity.instance(),
+        };
+        if instances.is_empty() {
+            return;
+        }
 
-            render_pass.set_bind_group(1, material.diffuse_bind_group.as_ref().unwrap(), &[]);
+        if entity.light {
+            render_pass.set_pipeline(&self.renderer.pipelines().light);
+            render_pass.set_bind_group(1, &self.buffers.light_bind_group, &[]);
+        } else {
+            render_pass.set_pipeline(&self.renderer.pipelines().model);
+            render_pass.set_bind_group(3, &self.renderer.shadow_map.bind_group, &[]);
+            self.buffers.clusters.bind_plain(render_pass, 2);
+        }
+        render_pass.set_bind_group(0, &self.buffers.camera.bind_group, &[]);
+        render_pass.set_vertex_buffer(1, instances.buffer().slice(..));
+
+        let model = &entity.model;
+        for mesh in model.meshes.iter() {
+            if !entity.light {
+                // A mesh without a material (like one from an obj with no
+                // mtl file) is skipped rather than drawn with the last one's
+                let Some(bind_group) = mesh
+                    .material
+                    .and_then(|material| model.materials.get(material))
+                    .and_then(|material| material.diffuse_bind_group.as_ref())
+                else {
+                    continue;
+                };
+                render_pass.set_bind_group(1, bind_group, &[]);
+            }
             render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
             render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-            render_pass.draw_indexed(0..mesh.num_indices, 0, 0..self.physics.num_instances() as _);
+            render_pass.draw_indexed(0..mesh.num_indices, 0, 0..instances.len());
         }
+    }
 
-        // Egui draw
-        self.egui_renderer
-            .render(&mut render_pass, &paint_jobs, &screen_descriptor);
+    // Culls against the camera, once the models are there to cull with
+    fn culling(&self) -> Option<Culling> {
+        let sphere = |bodies| {
+            self.entities
+                .model_for(bodies)
+                .map(|model| model.bounds.bounding_sphere())
+        };
 
-        drop(render_pass);
+        // The gpu culls the reis itself if it can
+        let reis = match self.renderer.culling {
+            Some(_) => None,
+            None => Some(sphere(Bodies::Reis)?),
+        };
 
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+        Some(Culling {
+            frustum: self.camera.frustum(),
+            reis,
+            shadow: self.renderer.shadow_map.frustum(),
+            projectiles: sphere(Bodies::Projectiles)?,
+        })
+    }
 
-        Ok(())
+    fn use_sprites(&self) -> bool {
+        match self.render_mode {
+            RenderMode::Auto => self.sprites_active,
+            RenderMode::Meshes => false,
+            RenderMode::Sprites => true,
+        }
     }
 
     fn ui(&mut self, ctx: &egui::Context) {
-        egui::Window::new("evan the gelion").show(ctx, |ui| {
-            ui.label("wasd to move around\nspace and shift to go up and down\narrow keys to look around.");
+        // Held onto separately so the rest of self can still be changed
+        let strings = self.locale.strings();
+
+        let main_window = egui::Window::new("evan the gelion").show(ctx, |ui| {
+            ui.label(self.camera_mode.controls(&strings));
+
+            ui.horizontal(|ui| {
+                for mode in CameraMode::ALL {
+                    let hint = self
+                        .input_map
+                        .hint(mode.command(), mode.command().description(&strings));
+                    if ui
+                        .radio(self.camera_mode == mode, mode.name(&strings))
+                        .on_hover_text(hint)
+                        .clicked()
+                    {
+                        self.set_camera_mode(mode);
+                    }
+                }
+            });
+
+            let mut smooth = self.config.camera_smoothing != CameraSmoothing::OFF;
+            if ui
+                .checkbox(&mut smooth, strings.get("camera.smooth"))
+                .on_hover_text(strings.get("camera.smooth_hint"))
+                .changed()
+            {
+                self.config.camera_smoothing = if smooth {
+                    CameraSmoothing::default()
+                } else {
+                    CameraSmoothing::OFF
+                };
+
+                if self.camera_mode == CameraMode::Fly {
+                    self.set_camera_mode(CameraMode::Fly);
+                }
+            }
+
+            ui.checkbox(&mut self.config.late_camera, strings.get("camera.late"))
+                .on_hover_text(strings.get("camera.late_hint"));
+
+            ui.checkbox(&mut self.editor.enabled, strings.get("editor.enabled"))
+                .on_hover_text(self.input_map.hint(Command::Editor, strings.get("commands.editor")));
+
+            self.locale.ui(ui);
 
             ui.add_space(30.0);
 
             ui.horizontal(|ui| {
-                ui.label("Light colour: ");
+                ui.label(strings.get("light.colour"));
                 let mut hsva = egui::epaint::Hsva::from_rgb(self.light_uniform.colour);
 
-                ui.color_edit_button_hsva(&mut hsva);
+                let picker = ui.color_edit_button_hsva(&mut hsva);
+                self.onboarding.target(Step::LightColour, picker.rect);
 
                 self.light_uniform.colour = hsva.to_rgb();
             });
 
             ui.horizontal(|ui| {
-                ui.label("Light scale: ");
+                ui.label(strings.get("light.position"));
+
+                // Typing in a position stops the light moving on its own,
+                // otherwise it'd just move straight away again
+                let mut moved = false;
+                for value in self.light_uniform.position.iter_mut() {
+                    moved |= ui.add(DragValue::new(value).speed(0.1)).changed();
+                }
+
+                if moved {
+                    self.light_mode = LightMode::Still;
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label(strings.get("light.scale"));
+
+                ui.add(DragValue::new(&mut self.light_uniform.scale).clamp_range(0.1..=f32::INFINITY).speed(0.25));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label(strings.get("light.brightness"));
+
+                ui.add(DragValue::new(&mut self.light_uniform.brightness).clamp_range(0.0..=f32::INFINITY).speed(0.1));
+            });
 
-                ui.add(DragValue::new(&mut self.light_uniform.scale).clamp_range(0.1..=INFINITY).speed(0.25));
+            ui.horizontal(|ui| {
+                ui.label(strings.get("light.mode"));
+                ui.radio_value(&mut self.light_mode, LightMode::Orbit, strings.get("light.orbit"));
+                ui.radio_value(&mut self.light_mode, LightMode::FollowSelection, strings.get("light.follow_selection"));
+                ui.radio_value(&mut self.light_mode, LightMode::Held, strings.get("light.held"));
+                ui.radio_value(&mut self.light_mode, LightMode::Still, strings.get("light.still"));
             });
 
             ui.horizontal(|ui| {
-                ui.label("Light brightness: ");
+                ui.checkbox(&mut self.light_shadow.enabled, strings.get("light.casts_shadows"));
+                ui.label(strings.get("light.priority"));
+                ui.add(DragValue::new(&mut self.light_shadow.priority))
+                    .on_hover_text(strings.get("light.priority_hint"));
+            });
+
+            ui.collapsing(strings.get("shadows.heading"), |ui| {
+                self.renderer.shadow_map.ui(ui, &strings);
 
-                ui.add(DragValue::new(&mut self.light_uniform.brightness).clamp_range(0.0..=INFINITY).speed(0.1));
+                ui.horizontal(|ui| {
+                    ui.label(strings.get("shadows.per_frame"));
+                    ui.add(DragValue::new(&mut self.shadow_budget.max_lights).clamp_range(0..=light::ShadowBudget::LIMIT));
+                });
+
+                ui.label(strings.format("shadows.count", &[("count", &self.pick_shadowed_lights().len())]));
+            });
+
+            ui.collapsing(strings.get("contact.heading"), |ui| {
+                self.renderer.contact.ui(ui, &strings);
             });
 
-            if ui.button("reset simulation").clicked() {
-                self.physics = PhysicsSimulation::new();
+            ui.collapsing(strings.get("colliders.heading"), |ui| self.colliders_ui(ui, &strings));
+
+            ui.collapsing(strings.get("sun.heading"), |ui| self.day.ui(ui, &strings));
+
+            cfg_if! {
+                if #[cfg(target_arch = "wasm32")] {
+                    let save = ui.button(strings.get("scene.copy")).on_hover_text(strings.get("scene.copy_hint"));
+                } else {
+                    let save = ui.button(strings.get("scene.save")).on_hover_text(scene::SCENE_PATH);
+                }
             }
 
-            ui.add_space(10.0);
+            if save.clicked() {
+                self.save_scene(ui.ctx());
+            }
+
+            ui.horizontal(|ui| {
+                ui.label(strings.get("selection.selected"));
 
-            ui.label(format!("Fps: {}", self.fps));
-            ui.label(format!("Reis: {}", self.physics.num_instances()));
+                if ui.selectable_label(self.selection == Some(Selection::Statue), strings.get("selection.statue")).clicked() {
+                    self.selection = Some(Selection::Statue);
+                }
 
-            ui.collapsing("Camera info", |ui| {
-                ui.label(format!("{:#?}", self.camera))
+                if ui.button(strings.get("selection.clear")).clicked() {
+                    self.selection = None;
+                }
             });
-        });
-    }
 
-    pub fn process_input(&mut self, event: &WindowEvent) -> bool {
-        self.keyboard.process_input(event);
-        match event {
-            WindowEvent::KeyboardInput {
-                input:
-                    KeyboardInput {
-                        state: ElementState::Pressed,
-                        virtual_keycode: Some(VirtualKeyCode::H),
-                        ..
-                    },
-                ..
-            } => {
-                log::info!("hiii!!!! :3");
-                true
+            ui.horizontal(|ui| {
+                let reset = ui.button(strings.get("simulation.reset")).on_hover_text(self.input_map.hint(Command::Reset, strings.get("simulation.reset_hint")));
+                self.onboarding.target(Step::Reset, reset.rect);
+                if reset.clicked() {
+                    self.reset(ResetOptions::BODIES);
+                }
+
+                if ui.button(strings.get("simulation.reset_scene")).on_hover_text(strings.get("simulation.reset_scene_hint")).clicked() {
+                    self.reset(ResetOptions::SCENE);
+                }
+
+                if ui.button(strings.get("simulation.full_reset")).on_hover_text(self.input_map.hint(Command::FullReset, strings.get("simulation.full_reset_hint"))).clicked() {
+                    self.reset(ResetOptions::FULL);
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label(strings.get("simulation.seed"));
+
+                // Typing in someone else's seed replays their run
+                let mut seed = self.physics.seed();
+                if ui.add(DragValue::new(&mut seed).speed(0.0).clamp_range(0..=physics::MAX_RANDOM_SEED)).on_hover_text(strings.get("simulation.seed_hint")).changed() {
+                    self.fixed_seed = true;
+                    self.reset_simulation(seed);
+                }
+
+                if ui.button(strings.get("simulation.copy_seed")).clicked() {
+                    clipboard::copy(ui.ctx(), seed.to_string());
+                    self.notifications.push(strings.format("notifications.seed_copied", &[("seed", &seed)]));
+                }
+
+                if ui.button(strings.get("simulation.randomize")).clicked() {
+                    self.reset_simulation(physics::random_seed());
+                }
+            });
+
+            ui.checkbox(&mut self.fixed_seed, strings.get("simulation.keep_seed"))
+                .on_hover_text(strings.get("simulation.keep_seed_hint"));
+
+            ui.checkbox(&mut self.paused, strings.get("simulation.paused"))
+                .on_hover_text(self.input_map.hint(Command::Pause, strings.get("commands.pause")));
+
+            ui.horizontal(|ui| {
+                ui.label(strings.get("simulation.speed"));
+                ui.add(egui::Slider::new(&mut self.time_scale, 0.1..=2.0))
+                    .on_hover_text(strings.get("simulation.speed_hint"));
+                if ui.button(strings.get("simulation.normal_speed")).clicked() {
+                    self.time_scale = 1.0;
+                }
+            });
+
+            if ui.add_enabled(!self.physics.is_clearing(), egui::Button::new(strings.get("simulation.clear_pile"))).clicked() {
+                self.physics.clear_pile();
             }
 
-            _ => false,
-        }
-    }
+            ui.horizontal(|ui| {
+                let mut max_age = self.physics.max_age();
+                let mut limited = max_age.is_some();
+
+                ui.checkbox(&mut limited, strings.get("simulation.despawn"));
+                let mut seconds = max_age.unwrap_or(60.0);
+                ui.add_enabled(
+                    limited,
+                    DragValue::new(&mut seconds).clamp_range(1.0..=3600.0),
+                );
+
+                max_age = limited.then_some(seconds);
+                if max_age != self.physics.max_age() {
+                    self.physics.set_max_age(max_age);
+                }
+            });
 
-    pub fn update(&mut self, delta_time: f32) {
-        self.frames_counted += 1;
-        let elapsed = self.frame_counter.elapsed().as_secs_f32();
+            if ui.checkbox(&mut self.config.adaptive_spawning, strings.get("simulation.adaptive_spawning"))
+                .on_hover_text(strings.get("simulation.adaptive_spawning_hint"))
+                .changed()
+            {
+                self.spawn_throttle.enabled = self.config.adaptive_spawning;
+            }
 
-        if elapsed >= 1.0 {
-            self.fps = self.frames_counted as f32 / elapsed;
-            self.frame_counter = Instant::now();
-            self.frames_counted = 0;
-        }
+            ui.collapsing(strings.get("emitters.heading"), |ui| {
+                ui.checkbox(&mut self.show_emitter_labels, strings.get("emitters.show_labels"))
+                    .on_hover_text(strings.get("emitters.show_labels_hint"));
+
+                let mut place = None;
+                let mut select = None;
+
+                for (i, emitter) in self.physics.emitters_mut().iter_mut().enumerate() {
+                    ui.push_id(i, |ui| {
+                        ui.collapsing(emitter.name.clone(), |ui| {
+                            emitter.ui(ui, &strings);
+
+                            let selected = self.selection == Some(Selection::Emitter(i));
+                            if ui.selectable_label(selected, strings.get("emitters.select")).clicked() {
+                                select = Some(i);
+                            }
+
+                            if ui.button(strings.get("emitters.place_at_crosshair")).clicked() {
+                                place = Some(i);
+                            }
+                        });
+                    });
+                }
+
+                if let Some(i) = place {
+                    self.place_emitter_at_crosshair(i);
+                }
+
+                if let Some(i) = select {
+                    self.selection = Some(Selection::Emitter(i));
+                }
+            });
 
-        if self.state == State::Playing {
-            self.light_uniform.update();
-            self.queue.write_buffer(
-                &self.light_buffer,
-                0,
-                bytemuck::cast_slice(&[self.light_uniform]),
-            );
+            ui.add_space(10.0);
 
-            self.camera.update(&self.queue, &self.keyboard);
+            ui.label(strings.format("stats.fps", &[("fps", &self.stats.fps())]));
+            ui.label(strings.format("stats.reis", &[("reis", &self.stats.bodies())]));
 
-            self.physics.update(delta_time);
-            self.queue.write_buffer(
-                &self.rei_instance_buffer,
-                0,
-                bytemuck::cast_slice(&self.physics.instances()),
-            );
-        }
-    }
+            ui.collapsing(strings.get("stats.heading"), |ui| {
+                self.stats.plot_ui(ui, &strings);
+            });
 
-    pub fn resize(&mut self, size: PhysicalSize<u32>) {
-        if size.width > 0 && size.height > 0 {
-            self.size = size;
-            self.config.width = size.width;
-            self.config.height = size.height;
-            self.surface.configure(&self.device, &self.config);
-            self.depth_texture =
-                texture::Texture::create_depth_texture(&self.device, &self.config, "depth texture");
+            ui.collapsing(strings.get("performance.heading"), |ui| {
+                let mut uploads = self.uploads.lock().unwrap();
+                let mut budget_kib = uploads.budget / 1024;
 
-            self.msaa_texture = self.device.create_texture(&wgpu::TextureDescriptor {
-                label: Some("msaa texture"),
-                size: wgpu::Extent3d {
-                    width: self.config.width,
-                    height: self.config.height,
-                    depth_or_array_layers: 1,
-                },
-                sample_count: SAMPLE_COUNT,
-                dimension: wgpu::TextureDimension::D2,
-                format: self.config.format,
-                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-                mip_level_count: 1,
-                view_formats: &[],
+                ui.horizontal(|ui| {
+                    ui.label(strings.get("performance.upload_budget"));
+                    ui.add(DragValue::new(&mut budget_kib).clamp_range(1..=65536));
+                });
+
+                uploads.budget = budget_kib * 1024;
+                ui.label(strings.format("performance.pending_uploads", &[("kib", &(uploads.pending_bytes() / 1024))]));
             });
 
-            self.msaa_view = self
-                .msaa_texture
-                .create_view(&TextureViewDescriptor::default());
-        }
-    }
+            ui.collapsing(strings.get("debug.heading"), |ui| {
+                ui.checkbox(&mut self.colour_by_speed, strings.get("debug.colour_by_speed"))
+                    .on_hover_text(strings.get("debug.colour_by_speed_hint"));
+                ui.checkbox(&mut self.collision_sounds, strings.get("debug.collision_sounds"));
+
+                #[cfg(feature = "debug-render")]
+                self.debug_render_ui(ui, &strings);
+
+                let queue = &self.renderer.queue;
+                let mut auditing = queue.auditing();
+                if ui
+                    .checkbox(&mut auditing, strings.get("debug.audit"))
+                    .on_hover_text(strings.get("debug.audit_hint"))
+                    .changed()
+                {
+                    queue.set_auditing(auditing);
+                }
+                if auditing {
+                    ui.label(strings.format("debug.problems", &[("count", &queue.hazards())]));
+                }
+            });
 
-    pub fn size(&self) -> &PhysicalSize<u32> {
-        &self.size
-    }
+            ui.collapsing(strings.get("bowling.heading"), |ui| {
+                ui.label(self.input_map.hint(Command::Bowl, strings.get("commands.throw_ball")));
+                let settings = &mut self.projectile_settings;
+
+                ui.horizontal(|ui| {
+                    ui.label(strings.get("bowling.mass"));
+                    ui.add(DragValue::new(&mut settings.mass).clamp_range(1.0..=5000.0));
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label(strings.get("bowling.speed"));
+                    ui.add(DragValue::new(&mut settings.speed).clamp_range(1.0..=200.0));
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label(strings.get("bowling.radius"));
+                    ui.add(DragValue::new(&mut settings.radius).clamp_range(0.1..=5.0).speed(0.05));
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label(strings.get("bowling.lifetime"));
+                    ui.add(DragValue::new(&mut settings.lifetime).clamp_range(1.0..=120.0));
+                });
+            });
 
-    pub fn window(&self) -> &Window {
-        &self.window
-    }
+            ui.collapsing(strings.get("particles.heading"), |ui| {
+                self.renderer.particles.ui(ui, &strings);
+            });
 
-    pub fn play_music(&mut self) {
-        if self.audio_manager.is_none() {
-            self.audio_manager = AudioManager::new(AudioManagerSettings::default()).ok();
-        }
-        self.song_handle = self
-            .audio_manager
-            .as_mut()
-            .unwrap()
-            .play(self.song.as_ref().unwrap().clone())
-            .ok();
-    }
+            ui.collapsing(strings.get("ground.heading"), |ui| {
+                self.renderer.ground.ui(ui, &strings);
+            });
+
+            ui.collapsing(strings.get("choreography.heading"), |ui| {
+                let emitters: Vec<String> =
+                    self.physics.emitters().iter().map(|e| e.name.clone()).collect();
+
+                if self.sequencer.ui(ui, &emitters, &strings) {
+                    self.restart_choreography();
+                }
+
+                self.camera_path_ui(ui, &strings);
+            });
+
+            ui.collapsing(strings.get("audio.heading"), |ui| {
+                ui.add_enabled_ui(!self.config.muted, |ui| {
+                    for (label, volume) in [
+                        ("audio.volume", &mut self.config.master_volume),
+                        ("audio.music", &mut self.config.music_volume),
+                        ("audio.sound_effects", &mut self.config.sfx_volume),
+                    ] {
+                        ui.horizontal(|ui| {
+                            ui.label(strings.get(label));
+                            ui.add(egui::Slider::new(volume, 0.0..=1.0));
+                        });
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label(strings.get("audio.loudness"));
+                    let loudness = &mut self.config.music_loudness;
+                    ui.add(egui::Slider::new(loudness, -30.0..=-8.0).suffix(" LUFS"))
+                        .on_hover_text(strings.get("audio.loudness_hint"));
+                });
+                ui.checkbox(&mut self.config.muted, strings.get("audio.mute"))
+                    .on_hover_text(self.input_map.hint(Command::Mute, strings.get("commands.mute")));
+                ui.checkbox(
+                    &mut self.config.preserve_music_pitch,
+                    strings.get("audio.preserve_pitch"),
+                )
+                .on_hover_text(strings.get("audio.preserve_pitch_hint"));
+
+                self.audio.set_master_volume(self.config.master_volume);
+                self.audio.set_music_volume(self.config.music_volume);
+                self.audio.set_sfx_volume(self.config.sfx_volume);
+                self.audio.set_music_loudness(self.config.music_loudness);
+                self.audio.set_muted(self.config.muted);
+                self.audio
+                    .set_preserve_music_pitch(self.config.preserve_music_pitch);
+
+                ui.checkbox(&mut self.thud_sounds, strings.get("audio.thuds"))
+                    .on_hover_text(strings.get("audio.thuds_hint"));
+
+                self.audio.ui(ui, &strings);
+            });
+
+            ui.collapsing(strings.get("graphics.heading"), |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(strings.get("graphics.draw_reis_as"));
+                    ui.radio_value(&mut self.render_mode, RenderMode::Auto, strings.get("graphics.auto"));
+                    ui.radio_value(&mut self.render_mode, RenderMode::Meshes, strings.get("graphics.meshes"));
+                    ui.radio_value(&mut self.render_mode, RenderMode::Sprites, strings.get("graphics.sprites"));
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label(strings.get("graphics.sprite_threshold"));
+                    ui.add(DragValue::new(&mut self.sprite_fps_threshold).clamp_range(1.0..=240.0));
+                });
+
+                ui.checkbox(&mut self.show_tweaks, strings.get("graphics.tweaks"))
+                    .on_hover_text(strings.get("graphics.tweaks_hint"));
+                ui.checkbox(&mut self.renderer.skybox.enabled, strings.get("graphics.sky"))
+                    .on_hover_text(strings.get("graphics.sky_hint"));
+
+                ui.horizontal(|ui| {
+                    ui.label(strings.get("graphics.present_mode"));
+
+                    let mut present_mode = self.config.present_mode;
+                    egui::ComboBox::from_id_source("present mode")
+                        .selected_text(present_mode.name(&strings))
+                        .show_ui(ui, |ui| {
+                            // Only the ones this surface can actually do
+                            for mode in PresentMode::ALL {
+                                if self.renderer.supports_present_mode(mode.to_wgpu()) {
+                                    ui.selectable_value(&mut present_mode, mode, mode.name(&strings));
+                                }
+                            }
+                        });
+
+                    if present_mode != self.config.present_mode {
+                        // The surface might not take it after all, and fall back on vsync
+                        let used = self.renderer.set_present_mode(present_mode.to_wgpu());
+                        let present_mode = PresentMode::from_wgpu(used);
+                        self.config.present_mode = present_mode;
+                        self.pacer.vsync = present_mode == PresentMode::Vsync;
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label(strings.get("graphics.frame_limit"));
+                    for limit in FrameLimit::ALL {
+                        if ui
+                            .selectable_value(&mut self.config.frame_limit, limit, limit.name(&strings))
+                            .clicked()
+                        {
+                            self.pacer.limit = limit;
+                        }
+                    }
+                });
+                if self.config.frame_limit == FrameLimit::MatchRefresh {
+                    match self.pacer.refresh_rate() {
+                        Some(hz) => ui.label(strings.format("graphics.refresh_rate", &[("hz", &format!("{hz:.0}"))])),
+                        None => ui.label(strings.get("graphics.refresh_rate_unknown")),
+                    };
+                }
+
+                ui.checkbox(&mut self.show_hud, strings.get("graphics.show_hud"));
+
+                ui.checkbox(&mut self.hide_cursor_in_clean_mode, strings.get("graphics.hide_cursor"))
+                    .on_hover_text(self.input_map.hint(Command::CleanMode, strings.get("commands.clean_mode")));
+
+                let mut fullscreen = self.config.fullscreen;
+                if ui
+                    .checkbox(&mut fullscreen, strings.get("graphics.fullscreen"))
+                    .on_hover_text(self.input_map.hint(Command::Fullscreen, strings.get("commands.fullscreen")))
+                    .changed()
+                {
+                    self.toggle_fullscreen();
+                }
+
+                if self.render_mode == RenderMode::Auto {
+                    let current = if self.sprites_active { "graphics.using_sprites" } else { "graphics.using_meshes" };
+                    ui.label(strings.get(current));
+
+                    if self.sprites_active && ui.button(strings.get("graphics.back_to_meshes")).clicked() {
+                        self.sprites_active = false;
+                    }
+                }
+            });
+
+            ui.collapsing(strings.get("quality.heading"), |ui| {
+                self.quality_ui(ui, &strings);
+            });
+
+            ui.collapsing(strings.get("controls.heading"), |ui| {
+                self.input_map.ui(ui, &strings);
+
+                if ui.button(strings.get("onboarding.show_again")).clicked() {
+                    self.onboarding.restart();
+                }
+            });
+
+            ui.collapsing(strings.get("font.heading"), |ui| {
+                self.fonts.ui(ui, ctx, &strings);
+            });
+
+            ui.collapsing(strings.get("camera.effects"), |ui| {
+                self.camera_effects.ui(ui, &strings);
+            });
+
+            ui.collapsing(strings.get("camera.info"), |ui| {
+                ui.label(format!("{:#?}", self.camera))
+            });
+        });
+
+        if self.editor.enabled {
+            egui::Window::new(strings.get("editor.heading"))
+                .id(egui::Id::new("editor"))
+                .show(ctx, |ui| self.editor_ui(ui, &strings));
+        }
+
+        egui::Window::new(strings.get("tweaks.heading"))
+            .id(egui::Id::new("shader tweaks"))
+            .open(&mut self.show_tweaks)
+            .show(ctx, |ui| self.tweaks.ui(ui, &strings));
+
+        // The main window only counts if it isn't collapsed
+        self.menus_open =
+            main_window.is_some_and(|window| window.inner.is_some()) || self.show_tweaks;
+
+        self.diagnostics.show(ctx, &strings);
+        self.spawn_throttle.show(ctx, &strings);
+        #[cfg(not(target_arch = "wasm32"))]
+        self.confirm_save_ui(ctx);
+
+        if let Some(looked_at) = self.looked_at {
+            prompts::show(ctx, &self.camera, &looked_at, &self.prompt_text(looked_at.target, &strings));
+        }
+
+        if self.onboarding.show(ctx, &strings) {
+            self.config.seen_onboarding = true;
+        }
+
+        self.notifications.show(ctx);
+        self.save_config();
+    }
+
+    // The touch layout's buttons and settings sheet, instead of the windows
+    fn touch_ui(&mut self, ctx: &egui::Context) {
+        let strings = self.locale.strings();
+
+        match self.touch.show(ctx, &strings, self.paused) {
+            Some(TouchAction::Spawn) => {
+                for i in 0..self.physics.emitters().len() {
+                    self.physics.burst(i, TOUCH_BURST);
+                }
+            }
+
+            // There's no pointing at an emitter with a finger, so it's
+            // whichever one's being looked at, or the statue if none are
+            Some(TouchAction::Explode) => {
+                let position = match self.looked_at {
+                    Some(LookedAt { target: Interactable::Emitter(_), position, .. }) => position,
+                    _ => self.physics.statue_position(),
+                };
+                self.physics.explode(position, EXPLODE_RADIUS, EXPLODE_STRENGTH);
+            }
+
+            Some(TouchAction::Reset) => self.reset(ResetOptions::BODIES),
+            Some(TouchAction::Pause) => self.run_command(Command::Pause),
+            None => {}
+        }
+
+        // Copied out so the settings can change the rest of self
+        let mut touch = self.touch;
+        touch.settings_sheet(ctx, &strings, |ui| {
+            self.quality_preset_ui(ui, &strings);
+
+            if ui.checkbox(&mut self.config.muted, strings.get("audio.mute")).changed() {
+                self.audio.set_muted(self.config.muted);
+            }
+
+            self.locale.ui(ui);
+        });
+        self.touch = touch;
+
+        self.menus_open = self.touch.settings_open();
+
+        if let Some(looked_at) = self.looked_at {
+            prompts::show(ctx, &self.camera, &looked_at, &self.prompt_text(looked_at.target, &strings));
+        }
+
+        self.notifications.show(ctx);
+        self.save_config();
+    }
+
+    // Lets the user know about any assets that didn't match the manifest. When
+    // that stops the scene from loading the error screen says so instead.
+    fn notify_corrupted_assets(&mut self) {
+        let mut corrupted = integrity::take_corrupted();
+        if corrupted.is_empty() || self.state == State::Failed {
+            return;
+        }
+
+        corrupted.sort();
+        corrupted.dedup();
+        let strings = self.locale.strings();
+        let text = strings.format(
+            "notifications.corrupted_assets",
+            &[("files", &corrupted.join(", "))],
+        );
+
+        cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                self.notifications.push_with_action(
+                    text,
+                    crate::notifications::Action {
+                        label: strings.get("error.download_again").to_string(),
+                        run: integrity::redownload,
+                    },
+                );
+            } else {
+                self.notifications.push(text);
+            }
+        }
+    }
+
+    // Saves the settings if anything's changed them since they were last saved.
+    // Benchmarks never save them, since they run with the frame rate
+    // unlocked and that shouldn't stick once they're done.
+    fn save_config(&mut self) {
+        if self.bench.is_some() || self.config == self.saved_config {
+            return;
+        }
+
+        if let Err(error) = self.config.save() {
+            log::warn!("{}", error.report());
+        }
+
+        // Even if saving failed, otherwise it'd be tried (and fail) every frame
+        self.saved_config = self.config;
+    }
+
+    // All there is in clean mode is a hint about how to get out of it, and
+    // that only shows up for a moment when a key that does something is pressed
+    fn clean_mode_ui(&mut self, ctx: &egui::Context) {
+        self.menus_open = false;
+        self.clean_mode_hint -= ctx.input(|input| input.unstable_dt);
+
+        if self.clean_mode_hint <= 0.0 {
+            return;
+        }
+
+        let strings = self.locale.strings();
+        let text = self
+            .input_map
+            .hint(Command::CleanMode, strings.get("commands.leave_clean_mode"));
+
+        egui::Area::new("clean mode hint")
+            .anchor(egui::Align2::CENTER_TOP, [0.0, 20.0])
+            .interactable(false)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.label(strings.format("controls.clean_mode_on", &[("hint", &text)]));
+                });
+            });
+    }
+
+    // Switches to another way of moving the camera, carrying on from wherever
+    // it is now
+    fn set_camera_mode(&mut self, mode: CameraMode) {
+        self.camera_mode = mode;
+        self.camera_controller =
+            mode.controller(&mut self.camera, &self.physics, self.config.camera_smoothing);
+    }
+
+    fn set_clean_mode(&mut self, clean_mode: bool) {
+        self.clean_mode = clean_mode;
+        self.clean_mode_hint = 0.0;
+        self.window
+            .set_cursor_visible(!(clean_mode && self.hide_cursor_in_clean_mode));
+    }
+
+    fn prompt_text(&self, target: Interactable, strings: &Strings) -> String {
+        match target {
+            Interactable::Emitter(_) => self
+                .input_map
+                .hint(Command::Explode, strings.get("commands.explode")),
+            Interactable::Light if self.light_mode == LightMode::Held => self
+                .input_map
+                .hint(Command::GrabLight, strings.get("commands.drop_light")),
+            Interactable::Light => self
+                .input_map
+                .hint(Command::GrabLight, strings.get("commands.grab_light")),
+        }
+    }
+
+    fn run_command(&mut self, command: Command) {
+        match command {
+            Command::Explode => {
+                if let Some(LookedAt { target: Interactable::Emitter(_), position, .. }) = self.looked_at {
+                    self.physics.explode(position, EXPLODE_RADIUS, EXPLODE_STRENGTH);
+                }
+            }
+
+            Command::GrabLight => {
+                if self.light_mode == LightMode::Held {
+                    self.light_mode = LightMode::Still;
+                } else if matches!(self.looked_at, Some(LookedAt { target: Interactable::Light, .. })) {
+                    self.light_mode = LightMode::Held;
+                }
+            }
+
+            Command::Bowl => {
+                let (origin, direction) = self.camera.ray_through([0.0, 0.0]);
+                self.physics.launch_projectile(origin.into(), direction.into(), &self.projectile_settings);
+            }
+
+            Command::Pause => self.paused = !self.paused,
+            Command::CleanMode => self.set_clean_mode(!self.clean_mode),
+            Command::Walk if self.camera_mode == CameraMode::Walk => {
+                self.set_camera_mode(CameraMode::Fly)
+            }
+            Command::Walk => self.set_camera_mode(CameraMode::Walk),
+            Command::FlyCamera => self.set_camera_mode(CameraMode::Fly),
+            Command::WalkCamera => self.set_camera_mode(CameraMode::Walk),
+            Command::OrbitCamera => self.set_camera_mode(CameraMode::Orbit),
+            Command::FollowCamera => self.set_camera_mode(CameraMode::Follow),
+            Command::Diagnostics => self.diagnostics.visible = !self.diagnostics.visible,
+            Command::Reset => self.reset(ResetOptions::BODIES),
+            Command::FullReset => self.reset(ResetOptions::FULL),
+            Command::Editor => self.editor.enabled = !self.editor.enabled,
+            Command::Fullscreen => self.toggle_fullscreen(),
+            Command::Mute => {
+                self.config.muted = !self.config.muted;
+                self.audio.set_muted(self.config.muted);
+            }
+        }
+    }
+
+    // Works out which interactive thing (if any) the camera is looking at
+    fn update_looked_at(&mut self) {
+        let emitters = (0..self.physics.emitters().len()).filter_map(|i| {
+            let position = Selection::Emitter(i).position(&self.physics)?;
+            Some((Interactable::Emitter(i), position))
+        });
+        let light = (Interactable::Light, self.light_uniform.position);
+
+        self.looked_at = prompts::looked_at(&self.camera, emitters.chain([light]));
+    }
+
+    // The contact point and collider debug views' settings
+    #[cfg(feature = "debug-render")]
+    fn debug_render_ui(&mut self, ui: &mut egui::Ui, strings: &Strings) {
+        ui.checkbox(&mut self.show_contacts, strings.get("debug.show_contacts"))
+            .on_hover_text(strings.get("debug.show_contacts_hint"));
+        ui.add_enabled_ui(self.show_contacts, |ui| {
+            ui.horizontal(|ui| {
+                ui.label(strings.get("debug.min_impulse"));
+                ui.add(
+                    DragValue::new(&mut self.contact_min_impulse)
+                        .clamp_range(0.0..=100.0)
+                        .speed(0.01),
+                );
+            });
+            ui.label(strings.format("debug.contacts_shown", &[("count", &(self.renderer.lines.num_lines() / 4))]));
+        });
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("debug.colliders"));
+            ui.radio_value(&mut self.collider_view, ColliderView::Hidden, strings.get("debug.hidden"));
+            ui.radio_value(&mut self.collider_view, ColliderView::Solid, strings.get("debug.solid"));
+            ui.radio_value(&mut self.collider_view, ColliderView::Wireframe, strings.get("debug.wireframe"));
+        })
+        .response
+        .on_hover_text(strings.get("debug.colliders_hint"));
+    }
+
+    fn quality_preset_ui(&mut self, ui: &mut egui::Ui, strings: &Strings) {
+        ui.horizontal(|ui| {
+            ui.label(strings.get("quality.preset"));
+
+            for preset in QualityPreset::ALL {
+                if ui.selectable_label(self.quality_preset == Some(preset), preset.name(strings)).clicked() {
+                    self.quality_preset = Some(preset);
+                    self.apply_quality(preset.settings());
+                }
+            }
+
+            if self.quality_preset.is_none() {
+                ui.label(strings.get("quality.custom"));
+            }
+        });
+    }
+
+    fn quality_ui(&mut self, ui: &mut egui::Ui, strings: &Strings) {
+        self.quality_preset_ui(ui, strings);
+
+        let mut quality = self.quality;
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("quality.max_reis"));
+            ui.add(DragValue::new(&mut quality.max_reis).clamp_range(1..=physics::NUM_REIS));
+        });
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("quality.antialiasing"));
+            for &count in self.renderer.sample_counts() {
+                if count == 1 {
+                    ui.radio_value(&mut quality.sample_count, 1, strings.get("quality.off"));
+                } else {
+                    ui.radio_value(&mut quality.sample_count, count, format!("{count}x"));
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("quality.render_scale"));
+            ui.add(egui::Slider::new(&mut quality.render_scale, 0.25..=1.0));
+        });
+
+        if quality != self.quality {
+            self.quality_preset = None;
+            self.apply_quality(quality);
+        }
+    }
+
+    // Scrolling zooms the orbiting cameras in and out. Scrolling over the ui
+    // is left for the ui.
+    fn scroll(&mut self, delta: MouseScrollDelta) -> bool {
+        let ctx = self.egui_platform.context();
+        if ctx.is_pointer_over_area() || ctx.wants_pointer_input() {
+            return false;
+        }
+
+        let lines = match delta {
+            MouseScrollDelta::LineDelta(_, lines) => lines,
+            MouseScrollDelta::PixelDelta(pixels) => pixels.y as f32 / SCROLL_LINE_HEIGHT,
+        };
+        self.keyboard.scroll(lines);
+        true
+    }
+
+    // Left clicking shoves whatever's under the mouse, and right clicking drops
+    // a new rei onto it. Clicks on the ui are left for the ui.
+    fn click(&mut self, button: MouseButton) -> bool {
+        let ctx = self.egui_platform.context();
+        if ctx.is_pointer_over_area() || ctx.wants_pointer_input() {
+            return false;
+        }
+
+        let Some(cursor) = self.cursor else {
+            return false;
+        };
+
+        let size = self.size();
+        let (origin, direction) = self
+            .camera
+            .ray_through_pixel(cursor, [size.width as f32, size.height as f32]);
+        let direction = na::Vector3::new(direction.x, direction.y, direction.z);
+
+        let Some(hit) = self.physics.pick(
+            na::Point3::new(origin.x, origin.y, origin.z),
+            direction,
+            self.camera.zfar,
+        ) else {
+            return false;
+        };
+
+        match button {
+            MouseButton::Left => {
+                self.picked = hit.body;
+                if let Some(body) = hit.body {
+                    self.physics.push(body, hit.point, direction * PUSH_SPEED);
+                }
+            }
+            MouseButton::Right => self.physics.spawn_rei_at(hit.point, hit.normal),
+            _ => return false,
+        }
+
+        true
+    }
+
+    // Whatever's in the middle of the screen
+    fn crosshair_hit(&self) -> Option<na::Point3<f32>> {
+        let (origin, direction) = self.camera.ray_through([0.0, 0.0]);
+
+        self.physics.cast_ray(
+            na::Point3::new(origin.x, origin.y, origin.z),
+            na::Vector3::new(direction.x, direction.y, direction.z),
+            self.camera.zfar,
+        )
+    }
+
+    // Moves an emitter to whatever's in the middle of the screen
+    fn place_emitter_at_crosshair(&mut self, index: usize) {
+        if let Some(hit) = self.crosshair_hit() {
+            let emitter = &mut self.physics.emitters_mut()[index];
+            emitter.position = [hit.x, hit.y + emitter.surface_offset, hit.z];
+            emitter.reset_velocity();
+        }
+    }
+
+    fn editor_ui(&mut self, ui: &mut egui::Ui, strings: &Strings) {
+        ui.label(strings.get("editor.help"));
+
+        let selected = match self.selection {
+            Some(Selection::Statue) => Some(strings.get("selection.statue").to_string()),
+            Some(Selection::Emitter(i)) => self
+                .physics
+                .emitters()
+                .get(i)
+                .map(|emitter| emitter.name.clone()),
+            Some(Selection::Prop(i)) => {
+                Some(strings.format("editor.prop", &[("number", &(i + 1))]))
+            }
+            Some(Selection::Light) => Some(strings.get("editor.light").to_string()),
+            None => None,
+        };
+        let selected = selected.unwrap_or_else(|| strings.get("editor.nothing").to_string());
+        ui.label(strings.format("editor.selected", &[("name", &selected)]));
+
+        let snapping = &mut self.editor.snapping;
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut snapping.enabled, strings.get("editor.snap"))
+                .on_hover_text(strings.get("editor.snap_hint"));
+            ui.label(strings.get("editor.grid"));
+            ui.add(
+                DragValue::new(&mut snapping.grid)
+                    .clamp_range(0.05..=10.0)
+                    .speed(0.05),
+            );
+            ui.label(strings.get("editor.angle"));
+            ui.add(
+                DragValue::new(&mut snapping.angle)
+                    .clamp_range(1.0..=90.0)
+                    .speed(1.0)
+                    .suffix("°"),
+            );
+        });
+
+        let snap = self.snapping();
+        let snapping = self.editor.snapping;
+        if let Some(prop) = self.selection.and_then(|selection| match selection {
+            Selection::Prop(i) => self.props.get_mut(i),
+            _ => None,
+        }) {
+            let mut changed = false;
+            ui.horizontal(|ui| {
+                ui.label(strings.get("editor.rotation"));
+                // The drag value keeps track of where it'd be without snapping,
+                // so dragging slowly still gets to the next step
+                let rotation = DragValue::from_get_set(|value| {
+                    if let Some(value) = value {
+                        prop.rotation = if snap {
+                            snapping.angle(value as f32)
+                        } else {
+                            value as f32
+                        };
+                    }
+                    prop.rotation as f64
+                });
+                changed |= ui.add(rotation.speed(1.0).suffix("°")).changed();
+                ui.label(strings.get("editor.scale"));
+                changed |= ui
+                    .add(
+                        DragValue::new(&mut prop.scale)
+                            .clamp_range(0.1..=10.0)
+                            .speed(0.05),
+                    )
+                    .changed();
+            });
+
+            if changed {
+                self.physics.set_props(&self.props);
+            }
+        }
+
+        if self.selection == Some(Selection::Light) {
+            self.light_animation.ui(ui, strings);
+        }
+
+        self.group_ui(ui, strings);
+
+        ui.horizontal(|ui| {
+            if ui.button(strings.get("editor.add_emitter")).clicked() {
+                self.add_emitter(strings);
+            }
+
+            if ui.button(strings.get("editor.add_prop")).clicked() {
+                self.add_prop();
+            }
+
+            if ui.button(strings.get("editor.select_light")).clicked() {
+                self.selection = Some(Selection::Light);
+            }
+
+            let deletable = matches!(
+                self.selection,
+                Some(Selection::Emitter(_) | Selection::Prop(_))
+            );
+            if ui
+                .add_enabled(deletable, egui::Button::new(strings.get("editor.delete")))
+                .clicked()
+            {
+                self.delete_selection();
+            }
+        });
+
+        // There's nowhere to save it on the web, so it gets downloaded
+        cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                if ui.button(strings.get("editor.download")).clicked() {
+                    self.download_scene();
+                }
+            } else {
+                if ui.button(strings.get("scene.save")).on_hover_text(scene::SCENE_PATH).clicked() {
+                    self.save_scene(ui.ctx());
+                }
+            }
+        }
+    }
+
+    // What can be done to the reis picked out with the marquee, and the
+    // marquee itself while it's being dragged out
+    fn group_ui(&mut self, ui: &mut egui::Ui, strings: &Strings) {
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label(strings.format("editor.group", &[("count", &self.group.len())]))
+                .on_hover_text(strings.get("editor.group_hint"));
+            ui.radio_value(
+                &mut self.editor.marquee_shape,
+                MarqueeShape::Rectangle,
+                strings.get("editor.rectangle"),
+            );
+            ui.radio_value(
+                &mut self.editor.marquee_shape,
+                MarqueeShape::Lasso,
+                strings.get("editor.lasso"),
+            );
+        });
+
+        let mut deselect = false;
+        ui.add_enabled_ui(!self.group.is_empty(), |ui| {
+            let group = self.group.bodies();
+            ui.horizontal(|ui| {
+                if ui.button(strings.get("editor.lift")).clicked() {
+                    self.physics.lift_reis(group, LIFT_SPEED);
+                }
+                if ui.button(strings.get("editor.freeze")).clicked() {
+                    self.physics.freeze_reis(group, true);
+                }
+                if ui.button(strings.get("editor.unfreeze")).clicked() {
+                    self.physics.freeze_reis(group, false);
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.color_edit_button_rgb(&mut self.group_tint);
+                if ui.button(strings.get("editor.tint")).clicked() {
+                    self.physics.tint_reis(group, Some(self.group_tint));
+                }
+                if ui.button(strings.get("editor.untint")).clicked() {
+                    self.physics.tint_reis(group, None);
+                }
+            });
+
+            ui.horizontal(|ui| {
+                if ui.button(strings.get("editor.clear_reis")).clicked() {
+                    self.physics.clear_reis(group);
+                }
+                deselect = ui.button(strings.get("editor.deselect")).clicked();
+            });
+        });
+
+        if deselect {
+            self.group.clear();
+        }
+
+        if let Some(marquee) = self.editor.marquee() {
+            let painter = ui.ctx().layer_painter(egui::LayerId::new(
+                egui::Order::Foreground,
+                egui::Id::new("editor marquee"),
+            ));
+            let scale = ui.ctx().pixels_per_point();
+            let outline = marquee
+                .outline()
+                .into_iter()
+                .map(|[x, y]| egui::pos2(x / scale, y / scale))
+                .collect();
+            let colour = ui.visuals().selection.bg_fill;
+            painter.add(egui::Shape::closed_line(
+                outline,
+                egui::Stroke::new(1.5, colour),
+            ));
+        }
+    }
+
+    // Adds a small emitter at the crosshair, and selects it
+    fn add_emitter(&mut self, strings: &Strings) {
+        let emitters = self.physics.emitters_mut();
+        emitters.push(Emitter {
+            name: strings.format("editor.new_emitter", &[("number", &(emitters.len() + 1))]),
+            extents: [2.0, 0.0, 2.0],
+            ..Default::default()
+        });
+
+        let index = emitters.len() - 1;
+        self.place_emitter_at_crosshair(index);
+        self.selection = Some(Selection::Emitter(index));
+    }
+
+    // Adds a prop standing on whatever's at the crosshair (or just in front of
+    // the camera if there's nothing there), and selects it
+    fn add_prop(&mut self) {
+        let position = match self.crosshair_hit() {
+            Some(hit) => [hit.x, hit.y, hit.z],
+            None => (self.camera.eye + self.camera.forward() * 10.0).into(),
+        };
+
+        self.props.push(Prop {
+            position,
+            ..Default::default()
+        });
+        self.physics.set_props(&self.props);
+        self.selection = Some(Selection::Prop(self.props.len() - 1));
+    }
+
+    fn delete_selection(&mut self) {
+        match self.selection.take() {
+            Some(Selection::Emitter(i)) if i < self.physics.emitters().len() => {
+                self.physics.remove_emitter(i);
+            }
+
+            Some(Selection::Prop(i)) if i < self.props.len() => {
+                self.props.remove(i);
+                self.physics.set_props(&self.props);
+            }
+
+            selection => self.selection = selection,
+        }
+    }
+
+    // The editor's only active while its window's up
+    fn editing(&self) -> bool {
+        self.editor.enabled && !self.clean_mode && self.state == State::Playing
+    }
+
+    // Whether the editor's snapping right now. Holding ctrl does the opposite
+    // of what the checkbox says.
+    fn snapping(&self) -> bool {
+        let ctrl = self.keyboard.is_down(VirtualKeyCode::LControl)
+            || self.keyboard.is_down(VirtualKeyCode::RControl);
+        self.editor.snapping.enabled != ctrl
+    }
+
+    // Where the gizmo goes for something selected in the editor, which is
+    // wherever its position in the scene file puts it. The statue can't be
+    // moved, so it doesn't get one.
+    fn editor_position(&self, selection: Selection) -> Option<[f32; 3]> {
+        match selection {
+            Selection::Statue => None,
+            Selection::Emitter(i) => self
+                .physics
+                .emitters()
+                .get(i)
+                .map(|emitter| emitter.position),
+            Selection::Prop(i) => self.props.get(i).map(|prop| prop.position),
+            Selection::Light => Some(self.light_uniform.position),
+        }
+    }
+
+    fn move_selection(&mut self, selection: Selection, position: [f32; 3]) {
+        match selection {
+            Selection::Statue => {}
+
+            Selection::Emitter(i) => {
+                if let Some(emitter) = self.physics.emitters_mut().get_mut(i) {
+                    emitter.position = position;
+                }
+            }
+
+            Selection::Prop(i) => {
+                if let Some(prop) = self.props.get_mut(i) {
+                    prop.position = position;
+                    self.physics.set_props(&self.props);
+                }
+            }
+
+            // Otherwise it'd just get moved back
+            Selection::Light => {
+                self.light_uniform.position = position;
+                self.light_mode = LightMode::Still;
+            }
+        }
+    }
+
+    // Drags the selection along with the gizmo, or otherwise keeps track of
+    // which of its arrows the mouse is over. Returns true while dragging.
+    fn editor_cursor_moved(&mut self, to: [f32; 2]) -> bool {
+        if self.editor.extend_marquee(to) {
+            return true;
+        }
+
+        let size = self.size();
+        let size = [size.width as f32, size.height as f32];
+        let gizmo = self
+            .selection
+            .and_then(|selection| Some((selection, self.editor_position(selection)?)));
+
+        let snap = self.snapping();
+        let moved = gizmo
+            .zip(self.cursor)
+            .and_then(|((selection, position), from)| {
+                Some((
+                    selection,
+                    self.editor
+                        .drag(&self.camera, position, from, to, size, snap)?,
+                ))
+            });
+        if let Some((selection, moved)) = moved {
+            self.move_selection(selection, moved);
+            return true;
+        }
+
+        let position = gizmo.map(|(_, position)| position);
+        self.editor.hover(&self.camera, position, to, size);
+        false
+    }
+
+    // In the editor, clicking grabs the gizmo if the mouse is on it, and
+    // otherwise selects whatever it's on. If there's nothing there, it starts
+    // dragging out a marquee to pick out a group of reis instead.
+    fn editor_click(&mut self) -> bool {
+        let ctx = self.egui_platform.context();
+        if ctx.is_pointer_over_area() || ctx.wants_pointer_input() {
+            return false;
+        }
+
+        if self.editor.start_drag() {
+            return true;
+        }
+
+        let Some(cursor) = self.cursor else {
+            return false;
+        };
+
+        let size = self.size();
+        let candidates = self
+            .physics
+            .emitters()
+            .iter()
+            .enumerate()
+            .filter(|(_, emitter)| emitter.visible)
+            .map(|(i, emitter)| (Selection::Emitter(i), emitter.position))
+            .chain(
+                self.props
+                    .iter()
+                    .enumerate()
+                    .map(|(i, prop)| (Selection::Prop(i), prop.position)),
+            )
+            .chain(std::iter::once((
+                Selection::Light,
+                self.light_uniform.position,
+            )));
+
+        let size = [size.width as f32, size.height as f32];
+        self.selection = editor::pick(&self.camera, candidates, cursor, size);
+        if self.selection.is_none() {
+            self.editor.start_marquee(cursor);
+        }
+
+        // The new selection's gizmo might already be under the mouse
+        let gizmo = self
+            .selection
+            .and_then(|selection| self.editor_position(selection));
+        self.editor.hover(&self.camera, gizmo, cursor, size);
+        true
+    }
+
+    // Selects whatever's inside the marquee once it's let go of, if one was
+    // being dragged
+    fn finish_marquee(&mut self) {
+        let Some(marquee) = self.editor.finish_marquee() else {
+            return;
+        };
+
+        let size = self.size();
+        let adding = self.keyboard.is_down(VirtualKeyCode::LShift)
+            || self.keyboard.is_down(VirtualKeyCode::RShift);
+        self.group.select(
+            &marquee,
+            &self.physics,
+            &self.camera,
+            [size.width as f32, size.height as f32],
+            adding,
+        );
+    }
+
+    pub fn process_input(&mut self, event: &WindowEvent) -> bool {
+        if let WindowEvent::Touch(_) = event {
+            self.touch.touched();
+        }
+
+        if let Some(kiosk) = &mut self.kiosk {
+            kiosk.process_event(event);
+        }
+
+        if let Some(key) = self.keyboard.process_input(event) {
+            if let Some(kiosk) = &mut self.kiosk {
+                self.quit_requested |= kiosk.type_key(key);
+            }
+
+            if self.input_map.rebind(key) {
+                return true;
+            }
+
+            // Alt+enter as well as the key it's bound to, since that's what
+            // it is in most games
+            let alt = self.keyboard.is_down(VirtualKeyCode::LAlt)
+                || self.keyboard.is_down(VirtualKeyCode::RAlt);
+            let command = if alt && key == VirtualKeyCode::Return {
+                Some(Command::Fullscreen)
+            } else {
+                self.input_map.command_for(key)
+            };
+
+            if let Some(command) = command {
+                if self.kiosk.is_some() && !Kiosk::allows(command) {
+                    return true;
+                }
+
+                // Going fullscreen works on the loading screen too
+                if self.state == State::Playing || command == Command::Fullscreen {
+                    self.run_command(command);
+                }
+
+                // Commands still work in clean mode, but with nothing on
+                // screen it's easy to forget it's on. There's no getting out
+                // of it in kiosk mode though.
+                if self.clean_mode && self.kiosk.is_none() && command != Command::CleanMode {
+                    self.clean_mode_hint = CLEAN_MODE_HINT_DURATION;
+                }
+                return true;
+            }
+        }
+
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::H),
+                        ..
+                    },
+                ..
+            } => {
+                log::info!("hiii!!!! :3");
+                true
+            }
+
+            WindowEvent::CursorMoved { position, .. } => {
+                let cursor = [position.x as f32, position.y as f32];
+                let dragged = self.editing() && self.editor_cursor_moved(cursor);
+                self.cursor = Some(cursor);
+                dragged
+            }
+
+            WindowEvent::MouseWheel { delta, .. } => self.scroll(*delta),
+
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor = None;
+                false
+            }
+
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if self.editing() => self.editor_click(),
+
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.editor.stop_drag();
+                self.finish_marquee();
+                false
+            }
+
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button,
+                ..
+            } if self.state == State::Playing && self.kiosk.is_none() && !self.editing() => {
+                self.click(*button)
+            }
+
+            // Only the passphrase gets out of kiosk mode
+            WindowEvent::CloseRequested
+            | WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::Escape),
+                        ..
+                    },
+                ..
+            } if self.kiosk.is_some() => true,
+
+            _ => false,
+        }
+    }
+
+    /// Whether the app's asked to be closed, rather than the window
+    pub fn quit_requested(&self) -> bool {
+        self.quit_requested
+    }
+
+    pub fn is_benchmarking(&self) -> bool {
+        self.bench.is_some()
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        let started = Instant::now();
+
+        // Benchmarks always move on the same amount, however long frames take
+        let delta_time = match &mut self.bench {
+            Some(bench) if self.state == State::Playing => bench.start_frame(),
+            _ => delta_time,
+        };
+
+        self.flush_uploads();
+        self.fonts.poll(&self.egui_platform.context());
+        self.poll_rei_collider();
+        #[cfg(not(target_arch = "wasm32"))]
+        self.poll_camera_path_dialog();
+        #[cfg(target_arch = "wasm32")]
+        self.poll_camera_path_upload();
+        self.locale.poll();
+        self.config.language = self.locale.language();
+        self.notify_corrupted_assets();
+
+        // Taken even while loading so keys held during the loading screen
+        // don't all get applied at once on the first playing frame
+        let input = self.keyboard.snapshot();
+        self.camera_sampled = Instant::now();
+
+        if self.stats.record_frame(self.physics.body_counts()) {
+            self.update_title();
+            self.stats.publish(&self.physics);
+
+            // Give it a few seconds after loading for the frame rate to settle down.
+            // Once it's switched it doesn't switch back, since the sprites being
+            // faster would just make it flip back and forth.
+            // Benchmarks stay on whatever they started with, so runs can be
+            // compared
+            if self.render_mode == RenderMode::Auto
+                && self.bench.is_none()
+                && !self.sprites_active
+                && self.state == State::Playing
+                && self.scene_time > 5.0
+                && self.stats.fps() < self.sprite_fps_threshold
+            {
+                log::info!(
+                    "Fps dropped to {:.0}, drawing reis as sprites",
+                    self.stats.fps()
+                );
+                self.sprites_active = true;
+            }
+        }
+
+        if self.state == State::Playing {
+            let shadow_target = self.update_light(delta_time);
+            let casting = self.pick_shadowed_lights().contains(&0);
+            self.renderer.shadow_map.update(
+                &self.renderer.queue,
+                self.light_uniform.position,
+                shadow_target,
+                casting,
+            );
+
+            if let Some(bench) = &self.bench {
+                let (eye, look_at) = bench::camera(bench.time());
+                self.camera.eye = eye.into();
+                self.camera.look_at(look_at.into());
+            } else {
+                // Whatever it was moved on by when the last frame was latched
+                // has already been done
+                let delta_time = (delta_time - self.camera_latched).max(0.0);
+                self.camera_controller
+                    .update(&mut self.camera, &input, &self.physics, delta_time);
+            }
+            self.camera_latched = 0.0;
+            self.camera_effects.update(delta_time);
+            self.camera
+                .set_view_effect(self.camera_effects.view_transform());
+            self.camera_moved |= self.camera.refresh();
+            self.update_looked_at();
+
+            // Benchmarks need every run to spawn the same, however slow it is
+            if self.bench.is_none() && !self.paused {
+                let work_time = (self.update_work + self.render_work).as_secs_f32();
+                let spawn_rate = self
+                    .spawn_throttle
+                    .update(delta_time, work_time, self.pacer.frame_budget());
+                self.physics.set_spawn_rate(spawn_rate);
+            }
+
+            // Everything after this stands still while paused
+            self.audio.set_paused(self.paused);
+            self.audio.set_ducked(self.menus_open);
+            self.audio.set_time_scale(self.time_scale as f64);
+            let delta_time = if self.paused {
+                0.0
+            } else {
+                delta_time * self.time_scale
+            };
+
+            self.scene_time += delta_time;
+            self.day.update(delta_time);
+            self.light_uniform.set_sun(&self.day);
+            self.renderer.update(delta_time);
+            for entity in self.entities.iter() {
+                entity.model.update_animations(&self.renderer.queue, self.scene_time);
+            }
+
+            if self.kiosk.as_mut().is_some_and(|kiosk| kiosk.restart_due(delta_time)) {
+                self.reset(ResetOptions::BODIES);
+            }
+
+            self.run_choreography(delta_time);
+            let physics_started = Instant::now();
+            if !self.waiting_for_collider() {
+                self.physics.update(delta_time);
+            }
+            let physics_took = physics_started.elapsed();
+            self.group.prune(&self.physics);
+            self.diagnostics.record_physics(physics_took);
+            if let Some(bench) = &mut self.bench {
+                bench.record_physics(physics_took);
+            }
+
+            // Impacts are always drained so they don't pile up while the sounds are off
+            let mut rng = rand::thread_rng();
+            for impact in self.physics.drain_impacts() {
+                self.renderer.particles.spawn_puff(impact.position, &mut rng);
+                self.renderer.ground.impact(impact.position);
+
+                if self.collision_sounds {
+                    self.audio.queue(SoundEvent::centred(
+                        impact.time,
+                        self.click_sound.clone(),
+                        0.3,
+                    ));
+                }
+            }
+
+            for hit in self.physics.drain_ground_hits() {
+                let strength = hit.force / THUD_FULL_FORCE;
+                let distance = Point3::from(hit.position).distance(self.camera.eye);
+                self.camera_effects.impact(strength, distance);
+
+                if self.thud_sounds {
+                    let volume = strength.min(1.0).sqrt();
+                    self.audio.queue(SoundEvent::at(
+                        hit.time,
+                        self.thud_sound.clone(),
+                        volume as f64,
+                        hit.position.into(),
+                        self.camera.eye.to_vec(),
+                        self.camera.orientation(),
+                    ));
+                }
+            }
+
+            self.audio.update(self.physics.time());
+            self.renderer.particles.update(delta_time);
+            self.update_ambience();
+
+            // The gpu's only timed while someone's looking at the timings
+            if let Some(timer) = &mut self.renderer.timer {
+                timer.enabled = self.diagnostics.visible;
+                timer.poll(&self.renderer.device);
+                self.diagnostics.set_gpu_timings(timer.timings());
+            }
+        }
+
+        let counts = self.physics.body_counts();
+        self.update_work = started.elapsed();
+        self.diagnostics
+            .record_update(delta_time, self.update_work, counts);
+
+        if let Some(bench) = self.bench.as_mut().filter(|_| self.state == State::Playing) {
+            bench.record_update(started.elapsed(), counts.total);
+
+            if bench.is_finished() {
+                let size = self.renderer.size();
+                bench.write_report([size.width, size.height]);
+                self.bench = None;
+                self.quit_requested = true;
+            }
+        }
+    }
+
+    // Redraws the contact points debug view for the latest simulation step,
+    // and the editor's gizmo
+    fn update_lines(&mut self) {
+        self.renderer.lines.clear();
+
+        let gizmo = self
+            .selection
+            .filter(|_| self.editing())
+            .and_then(|selection| self.editor_position(selection));
+        if let Some(position) = gizmo {
+            self.editor
+                .draw(&mut self.renderer.lines, &self.camera, position);
+        }
+
+        if self.editing() {
+            let group = self.group.bodies();
+            for (_, position) in self
+                .physics
+                .visible_reis()
+                .filter(|(handle, _)| group.contains(handle))
+            {
+                self.renderer
+                    .lines
+                    .cross(position, GROUP_MARKER_SIZE, GROUP_MARKER_COLOUR);
+            }
+        }
+
+        #[cfg(feature = "debug-render")]
+        if self.show_contacts {
+            for contact in self.physics.contacts(self.contact_min_impulse) {
+                let [x, y, z] = contact.position;
+                let [nx, ny, nz] = contact.normal;
+                let end = [
+                    x + nx * CONTACT_NORMAL_LENGTH,
+                    y + ny * CONTACT_NORMAL_LENGTH,
+                    z + nz * CONTACT_NORMAL_LENGTH,
+                ];
+
+                let strength = (contact.impulse / CONTACT_STRONG_IMPULSE).min(1.0);
+                let colour = [1.0, 0.9 * (1.0 - strength), 0.1 + 0.9 * strength];
+
+                self.renderer.lines.cross(contact.position, CONTACT_CROSS_SIZE, colour);
+                self.renderer.lines.line(contact.position, end, [1.0, 0.2, 0.2]);
+            }
+        }
+
+        let renderer = &mut self.renderer;
+        renderer.lines.prepare(&renderer.device, &renderer.queue);
+    }
+
+    // Queues up the hud and the labels over the emitters. There's none of
+    // either in clean mode.
+    fn update_text(&mut self) {
+        self.renderer.text.clear();
+
+        if !self.clean_mode {
+            let strings = self.locale.strings();
+
+            if self.show_emitter_labels {
+                labels::queue(&mut self.renderer.text, &self.camera, &self.physics, &strings);
+            }
+
+            if self.show_hud {
+                let seconds = self.scene_time as u32;
+                let time = format!("{}:{:02}", seconds / 60, seconds % 60);
+                let reis = self.physics.num_reis();
+                self.renderer.text.queue(
+                    strings.format("hud.text", &[("time", &time), ("reis", &reis)]),
+                    TextAnchor::Screen(HUD_POSITION),
+                    HUD_SIZE,
+                    [1.0; 4],
+                );
+            }
+        }
+
+        // Where whatever's being dragged in the editor is, next to the mouse
+        let dragged = self
+            .selection
+            .filter(|_| self.editing() && self.editor.dragging())
+            .and_then(|selection| self.editor_position(selection))
+            .zip(self.cursor);
+        if let Some(([x, y, z], cursor)) = dragged {
+            let strings = self.locale.strings();
+            let [x, y, z] = [x, y, z].map(|value| format!("{value:.2}"));
+            let mut text = strings.format("editor.readout", &[("x", &x), ("y", &y), ("z", &z)]);
+            if self.snapping() {
+                let grid = format!("{}", self.editor.snapping.grid);
+                text.push('\n');
+                text.push_str(&strings.format("editor.snapping_to", &[("grid", &grid)]));
+            }
+
+            let scale = self.window.scale_factor() as f32;
+            let at = [
+                cursor[0] / scale + READOUT_OFFSET,
+                cursor[1] / scale + READOUT_OFFSET,
+            ];
+            self.renderer
+                .text
+                .queue(text, TextAnchor::Screen(at), READOUT_SIZE, [1.0; 4]);
+        }
+
+        self.renderer.prepare_text(self.window.scale_factor() as f32);
+    }
+
+    // Starts making the rei's collider the way the scene says, and
+    // measuring how well it fits. Both happen in the background, since
+    // splitting the model up can take a while.
+    fn generate_rei_collider(&mut self) {
+        self.collider_report = None;
+        self.rei_collider_task = None;
+
+        let Some(rei) = self.entities.get("rei") else {
+            return;
+        };
+        let mesh = rei.model.collision_mesh.clone();
+        let settings = self.rei_collider_settings();
+        let hand_made = physics::hand_made_rei_parts(self.physics.rei_transform());
+        if settings.shape == ColliderShape::HandMade {
+            self.replace_rei_parts(hand_made.clone());
+        }
+
+        self.rei_collider_task = Some(tasks::spawn(async move {
+            collider_gen::generate_and_measure(&mesh, &settings, &hand_made)
+        }));
+    }
+
+    fn rei_collider_settings(&self) -> ColliderSettings {
+        ColliderSettings::for_model(&self.scene.colliders, "rei")
+    }
+
+    // Whether the simulation's waiting for the rei's collider to be made
+    fn waiting_for_collider(&self) -> bool {
+        self.rei_collider_task.is_some()
+            && self.rei_collider_settings().shape != ColliderShape::HandMade
+    }
+
+    fn poll_rei_collider(&mut self) {
+        let Some(generated) = self.rei_collider_task.as_mut().and_then(Task::poll) else {
+            return;
+        };
+        self.rei_collider_task = None;
+        self.collider_report = Some(generated.report);
+
+        // A hand-made one was put in when it was asked for
+        match generated.parts {
+            Some(parts) => self.replace_rei_parts(parts),
+            None if self.rei_collider_settings().shape != ColliderShape::HandMade => {
+                log::warn!("Couldn't make a collider from the rei's model, keeping the hand-made one");
+                let hand_made = physics::hand_made_rei_parts(self.physics.rei_transform());
+                self.replace_rei_parts(hand_made);
+            }
+            None => {}
+        }
+    }
+
+    fn replace_rei_parts(&mut self, parts: collider_gen::Parts) {
+        self.physics.set_rei_parts(parts);
+        #[cfg(feature = "debug-render")]
+        self.debug_colliders.clear();
+    }
+
+    // The rei's collider, how well it fits, and what it's made of
+    fn colliders_ui(&mut self, ui: &mut egui::Ui, strings: &Strings) {
+        let mut settings = self.rei_collider_settings();
+        if settings.ui(ui, strings) {
+            self.scene.colliders.insert("rei".to_string(), settings);
+            self.generate_rei_collider();
+        }
+
+        match &self.collider_report {
+            Some(report) if self.rei_collider_task.is_none() => report.ui(ui, strings),
+            _ => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label(strings.get("colliders.generating"));
+                });
+            }
+        }
+    }
+
+    // Moves the collider debug view's colliders to where they are now
+    #[cfg(feature = "debug-render")]
+    fn update_debug_colliders(&mut self) {
+        if self.collider_view == ColliderView::Hidden {
+            return;
+        }
+
+        let device = &self.renderer.device;
+        if self.debug_colliders.is_empty() {
+            self.debug_colliders = self
+                .physics
+                .collider_shapes()
+                .iter()
+                .map(|shape| DebugCollider::new(device, shape.as_ref()))
+                .collect::<Option<_>>()
+                .unwrap_or_else(|| {
+                    log::warn!("Some colliders have shapes that can't be drawn");
+                    Vec::new()
+                });
+        }
+
+        let instances = self.physics.collider_instances();
+        for (collider, instances) in self.debug_colliders.iter_mut().zip(instances.iter()) {
+            collider.set_instances(device, &self.renderer.queue, instances);
+        }
+    }
+
+    // Does whatever the choreography says should happen this frame
+    fn run_choreography(&mut self, delta_time: f32) {
+        let actions = self
+            .sequencer
+            .update(delta_time, self.audio.song_position());
+
+        for action in actions {
+            match action {
+                Action::Burst { emitter, count } => {
+                    match self.physics.emitters().iter().position(|e| e.name == emitter) {
+                        Some(i) => self.physics.burst(i, count),
+                        None => log::warn!("Can't burst from emitter \"{emitter}\", it doesn't exist"),
+                    }
+                }
+
+                Action::Gravity { gravity } => self.physics.set_gravity(gravity),
+                Action::LightColour { colour } => self.light_uniform.colour = colour,
+
+                Action::Camera {
+                    position,
+                    look_at,
+                    roll,
+                } => {
+                    self.camera.eye = position.into();
+                    self.camera.look_at(look_at.into());
+                    self.camera_effects.roll = roll.to_radians();
+                }
+            }
+        }
+    }
+
+    fn restart_choreography(&mut self) {
+        if self.sequencer.choreography.follow_music {
+            if let Some(song) = self.audio.song_handle_mut() {
+                if let Err(e) = song.seek_to(0.0) {
+                    log::warn!("Couldn't rewind the music: {e}");
+                }
+            }
+        }
+
+        self.physics.set_gravity(physics::GRAVITY.into());
+        self.sequencer.restart();
+    }
+
+    fn update_ambience(&mut self) {
+        let physics = &self.physics;
+        let light_position = self.light_uniform.position;
+
+        self.audio.update_spatial(
+            self.camera.eye.to_vec(),
+            self.camera.orientation(),
+            |sound| sound.world_position(physics, light_position),
+        );
+    }
+
+    // Moves the light according to its mode, returning the point it should
+    // be casting shadows towards
+    fn update_light(&mut self, delta_time: f32) -> [f32; 3] {
+        match self.light_mode {
+            LightMode::Orbit => {
+                self.light_uniform.update(delta_time);
+                [0.0, 0.0, 0.0]
+            }
+
+            LightMode::FollowSelection => {
+                let target = self
+                    .selection
+                    .or(Some(Selection::Emitter(0)))
+                    .and_then(|selection| selection.position(&self.physics))
+                    .unwrap_or([0.0, 0.0, 0.0]);
+
+                self.light_follow_offset = light::orbit(self.light_follow_offset, delta_time);
+
+                let [x, y, z] = target;
+                let [dx, dy, dz] = self.light_follow_offset;
+                self.light_uniform.position = [x + dx, y + dy, z + dz];
+
+                target
+            }
+
+            LightMode::Held => {
+                let forward = self.camera.forward();
+                let position = self.camera.eye + forward * light::HOLD_DISTANCE;
+                self.light_uniform.position = position.into();
+
+                (position + forward * 10.0).into()
+            }
+
+            LightMode::Still => [0.0, 0.0, 0.0],
+        }
+    }
+
+    // Outlines whatever's selected in the editor if it has a body, otherwise
+    // whatever was last clicked on
+    fn update_outline(&mut self) {
+        let body = self
+            .selection
+            .and_then(|selection| selection.body(&self.physics))
+            .or(self.picked);
+        let outlined = body.and_then(|body| self.physics.body_instance(body));
+
+        self.outlined = outlined.map(|(bodies, _)| bodies);
+        self.renderer
+            .prepare_outline(outlined.map(|(_, instance)| instance));
+    }
+
+    // Which lights get shadows this frame. There's only the one so far.
+    fn pick_shadowed_lights(&self) -> Vec<usize> {
+        let distance = Point3::from(self.light_uniform.position).distance(self.camera.eye);
+        self.shadow_budget.pick(&[(self.light_shadow, distance)])
+    }
+
+    // Does this frame's share of the pending asset uploads
+    fn flush_uploads(&mut self) {
+        let mut uploads = self.uploads.lock().unwrap();
+
+        if uploads.is_idle() {
+            return;
+        }
+
+        let mut encoder =
+            self.renderer
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Upload Encoder"),
+                });
+
+        uploads.flush(&self.renderer.device, &self.renderer.queue, &mut encoder);
+        self.renderer.queue.submit(std::iter::once(encoder.finish()));
+        uploads.recall();
+    }
+
+    /// Switches to the error screen
+    pub fn fail(&mut self, error: Error) {
+        log::error!("{}", error.report());
+        self.error = Some(error);
+        self.state = State::Failed;
+    }
+
+    // The loaded scene, with everything that's been changed since
+    fn current_scene(&self) -> SceneDescription {
+        SceneDescription {
+            emitters: self.physics.emitters().to_vec(),
+            props: self.props.clone(),
+            max_age: self.physics.max_age(),
+            light: light::LightSettings {
+                position: self.light_uniform.position,
+                colour: self.light_uniform.colour,
+                scale: self.light_uniform.scale,
+                brightness: self.light_uniform.brightness,
+                mode: self.light_mode,
+                shadow: self.light_shadow,
+                animation: self.light_animation,
+            },
+            choreography: self.sequencer.choreography.clone(),
+            sun: self.day,
+            tweaks: self.tweaks,
+            seed: self.fixed_seed.then(|| self.physics.seed()),
+            ..self.scene.clone()
+        }
+    }
+
+    // Saves the scene file, or on the web (where there's nowhere to save it)
+    // copies it so it can be pasted into one. Saving over the file asks first
+    // (see confirm_save_ui), since the comments in it don't survive.
+    fn save_scene(&mut self, ctx: &egui::Context) {
+        cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                let result = self
+                    .current_scene()
+                    .to_toml()
+                    .map(|text| clipboard::copy(ctx, text))
+                    .map_err(|e| Error::save(scene::SCENE_PATH, e));
+                let done = self.locale.strings().get("notifications.scene_copied").to_string();
+                self.notify_saved(result, done);
+            } else {
+                let _ = ctx;
+                self.confirming_save = true;
+            }
+        }
+    }
+
+    // Asks whether to save over the scene file, once saving's been asked for
+    #[cfg(not(target_arch = "wasm32"))]
+    fn confirm_save_ui(&mut self, ctx: &egui::Context) {
+        if !self.confirming_save {
+            return;
+        }
+
+        let strings = self.locale.strings();
+        let mut save = false;
+        let mut cancel = false;
+        egui::Window::new(strings.get("scene.overwrite_heading"))
+            .id(egui::Id::new("confirm save"))
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(strings.format("scene.overwrite", &[("path", &scene::SCENE_PATH)]));
+                ui.horizontal(|ui| {
+                    save = ui.button(strings.get("scene.save")).clicked();
+                    cancel = ui.button(strings.get("scene.cancel")).clicked();
+                });
+            });
+
+        if save {
+            let result = self.current_scene().save(scene::SCENE_PATH);
+            let done = strings.format("notifications.scene_saved", &[("path", &scene::SCENE_PATH)]);
+            self.notify_saved(result, done);
+        }
+        if save || cancel {
+            self.confirming_save = false;
+        }
+    }
+
+    fn notify_saved(&mut self, result: error::Result<()>, done: String) {
+        match result {
+            Ok(()) => self.notifications.push(done),
+            Err(error) => {
+                log::error!("{}", error.report());
+                self.notifications.push(error.report());
+            }
+        }
+    }
+
+    // Exporting the choreography's camera cuts, and swapping them for ones
+    // that were exported somewhere else
+    fn camera_path_ui(&mut self, ui: &mut egui::Ui, strings: &Strings) {
+        ui.separator();
+        ui.label(strings.get("camera_path.heading"))
+            .on_hover_text(strings.get("camera_path.hint"));
+
+        ui.horizontal(|ui| {
+            if ui.button(strings.get("camera_path.export")).clicked() {
+                self.export_camera_path();
+            }
+
+            if ui.button(strings.get("camera_path.import")).clicked() {
+                self.import_camera_path();
+            }
+        });
+    }
+
+    // Natively where it goes is picked first, on the web it's downloaded
+    fn export_camera_path(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.pick_camera_path_file(CameraPathPick::Export);
+        #[cfg(target_arch = "wasm32")]
+        self.write_camera_path(|path| path.download(camera_path::DEFAULT_FILENAME));
+    }
+
+    fn write_camera_path(&mut self, write: impl FnOnce(&CameraPath) -> error::Result<()>) {
+        let path = CameraPath::from_choreography(&self.sequencer.choreography);
+
+        match write(&path) {
+            Ok(()) => {
+                let done = self.locale.strings().format(
+                    "notifications.camera_path_exported",
+                    &[("count", &path.keyframes.len())],
+                );
+                self.notifications.push(done);
+            }
+            Err(error) => {
+                log::error!("{}", error.report());
+                self.notifications.push(error.report());
+            }
+        }
+    }
+
+    // Either way it's picked first and then read once it has been
+    fn import_camera_path(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.pick_camera_path_file(CameraPathPick::Import);
+
+        #[cfg(target_arch = "wasm32")]
+        match crate::files::upload(".json") {
+            Ok(task) => self.camera_path_upload = Some(task),
+            Err(error) => {
+                log::error!("{}", error.report());
+                self.notifications.push(error.report());
+            }
+        }
+    }
+
+    // Opens a file dialog for exporting or importing a camera path, starting
+    // in the data directory. The rest happens once it's closed (see
+    // poll_camera_path_dialog), so the app keeps going while it's open.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn pick_camera_path_file(&mut self, pick: CameraPathPick) {
+        if self.camera_path_dialog.is_some() {
+            return;
+        }
+
+        let strings = self.locale.strings();
+        let dialog = rfd::AsyncFileDialog::new()
+            .add_filter(strings.get("camera_path.heading"), &["json"])
+            .set_directory(storage::dir(Location::Data));
+
+        // The dialog has to be opened from the main thread on some platforms,
+        // it's only waited on in the background
+        let task = match pick {
+            CameraPathPick::Export => {
+                let picked = dialog
+                    .set_file_name(camera_path::DEFAULT_FILENAME)
+                    .save_file();
+                tasks::spawn(async move { picked.await.map(|file| file.path().to_path_buf()) })
+            }
+            CameraPathPick::Import => {
+                let picked = dialog.pick_file();
+                tasks::spawn(async move { picked.await.map(|file| file.path().to_path_buf()) })
+            }
+        };
+
+        self.camera_path_dialog = Some((pick, task));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn poll_camera_path_dialog(&mut self) {
+        let Some((pick, task)) = &mut self.camera_path_dialog else {
+            return;
+        };
+        let Some(picked) = task.poll() else {
+            return;
+        };
+        let pick = *pick;
+        self.camera_path_dialog = None;
+
+        // Nothing to do if it was cancelled
+        let Some(file) = picked else {
+            return;
+        };
+        let filename = file.display().to_string();
+
+        match pick {
+            CameraPathPick::Export => self.write_camera_path(|path| path.save(&filename)),
+            CameraPathPick::Import => match CameraPath::load(&filename) {
+                Ok(path) => self.use_camera_path(path),
+                Err(error) => {
+                    log::error!("{}", error.report());
+                    self.notifications.push(error.report());
+                }
+            },
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn poll_camera_path_upload(&mut self) {
+        let Some(result) = self.camera_path_upload.as_mut().and_then(Task::poll) else {
+            return;
+        };
+        self.camera_path_upload = None;
+
+        match result.and_then(|(name, json)| CameraPath::from_json(&name, &json)) {
+            Ok(path) => self.use_camera_path(path),
+            Err(error) => {
+                log::error!("{}", error.report());
+                self.notifications.push(error.report());
+            }
+        }
+    }
+
+    fn use_camera_path(&mut self, path: CameraPath) {
+        path.apply(&mut self.sequencer.choreography);
+
+        let done = self.locale.strings().format(
+            "notifications.camera_path_imported",
+            &[("count", &path.keyframes.len())],
+        );
+        self.notifications.push(done);
+    }
+
+    // Downloads the scene file, since there's nowhere to save it on the web
+    #[cfg(target_arch = "wasm32")]
+    fn download_scene(&mut self) {
+        if let Err(error) = self.current_scene().download(scene::SCENE_PATH) {
+            log::error!("{}", error.report());
+            self.notifications.push(error.report());
+        }
+    }
+
+    /// Starts the simulation over, putting back whatever `options` doesn't
+    /// keep. It's the same seed again if that's been fixed.
+    pub fn reset(&mut self, options: ResetOptions) {
+        if !options.keep_emitters {
+            *self.physics.emitters_mut() = self.scene.emitters.clone();
+            self.props = self.scene.props.clone();
+            self.physics.set_max_age(self.scene.max_age);
+            self.sequencer.restart();
+        }
+
+        let seed = if self.fixed_seed {
+            self.physics.seed()
+        } else {
+            physics::random_seed()
+        };
+        self.reset_simulation(seed);
+
+        if !options.keep_light {
+            self.light_uniform = self.scene.light.uniform();
+            self.light_mode = self.scene.light.mode;
+            self.light_shadow = self.scene.light.shadow;
+            self.light_animation = self.scene.light.animation;
+            self.light_follow_offset = light::FOLLOW_OFFSET;
+            self.day = self.scene.sun;
+            self.light_uniform.set_sun(&self.day);
+        }
+
+        if !options.keep_camera {
+            self.camera = Camera::new(CAMERA_START.into(), self.camera.aspect);
+            self.set_camera_mode(CameraMode::Fly);
+        }
+    }
+
+    // Starts the simulation over with the same emitters
+    fn reset_simulation(&mut self, seed: u64) {
+        let max_age = self.physics.max_age();
+        let rei_parts = self.physics.rei_parts().clone();
+        self.physics = PhysicsSimulation::new(
+            self.physics.emitters().to_vec(),
+            *self.physics.rei_transform(),
+            seed,
+        );
+        self.physics.set_rei_parts(rei_parts);
+        self.physics.set_max_reis(self.quality.max_reis);
+        self.physics.set_max_age(max_age);
+        self.physics.set_props(&self.props);
+        #[cfg(feature = "debug-render")]
+        self.debug_colliders.clear();
+        self.renderer.ground.reset();
+        self.picked = None;
+        self.camera_effects.settle();
+        self.spawn_throttle.reset();
+    }
+
+    pub fn load_scene(&mut self, scene: SceneDescription) {
+        self.scene = scene.clone();
+        self.light_uniform = scene.light.uniform();
+        self.light_mode = scene.light.mode;
+        self.light_shadow = scene.light.shadow;
+        self.light_animation = scene.light.animation;
+        self.day = scene.sun;
+        self.light_uniform.set_sun(&self.day);
+        self.tweaks = scene.tweaks;
+
+        // Whatever was on screen before fades into the new scene, once every
+        // pipeline's been warmed up under it
+        self.renderer.start_transition();
+        if let Some(rei_model) = self.entities.rei_model() {
+            let bake_sprites = self.render_mode != RenderMode::Meshes;
+            self.renderer.warm_up(&self.buffers, rei_model, bake_sprites);
+        }
+
+        let rei_transform = scene.models.get("rei").copied().unwrap_or_default();
+        self.fixed_seed = scene.seed.is_some();
+        let seed = scene.seed.unwrap_or_else(physics::random_seed);
+        self.physics = PhysicsSimulation::new(scene.emitters, rei_transform, seed);
+        self.physics.set_max_reis(self.quality.max_reis);
+        self.physics.set_max_age(scene.max_age);
+        self.props = scene.props;
+        self.physics.set_props(&self.props);
+        self.generate_rei_collider();
+        #[cfg(feature = "debug-render")]
+        self.debug_colliders.clear();
+        self.renderer.ground.reset();
+        self.picked = None;
+        self.camera_effects.settle();
+        self.spawn_throttle.reset();
+        self.sequencer = Sequencer::new(scene.choreography);
+
+        if let Some(font) = scene.font {
+            self.fonts.request(font);
+        }
+
+        for anim in scene.animations {
+            let found = self
+                .entities
+                .get(&anim.model)
+                .map(|entity| entity.model.set_animation(&anim.material, anim.animation))
+                .unwrap_or(false);
+
+            if !found {
+                log::warn!(
+                    "Scene animates material \"{}\" of model \"{}\" but it doesn't exist",
+                    anim.material,
+                    anim.model
+                );
+            }
+        }
+    }
+
+    pub fn set_ambience(&mut self, sounds: Vec<(AmbientSound, Sound)>) {
+        for (sound, _) in sounds.iter() {
+            if let Some(Attachment::Emitter(name)) = &sound.attach_to {
+                if !self.physics.emitters().iter().any(|e| &e.name == name) {
+                    log::warn!(
+                        "Ambient sound \"{}\" is attached to emitter \"{name}\" but it doesn't exist",
+                        sound.name
+                    );
+                }
+            }
+        }
+
+        self.audio.set_ambience(sounds);
+    }
+
+    // Switches to new quality settings, rebuilding whatever they affect
+    fn apply_quality(&mut self, quality: QualitySettings) {
+        let old = std::mem::replace(&mut self.quality, quality);
+
+        if quality.max_reis != old.max_reis {
+            self.physics.set_max_reis(quality.max_reis);
+        }
+
+        // The rest of the quality settings depend on the device, but the
+        // antialiasing is down to taste so it's remembered
+        if quality.sample_count != old.sample_count {
+            self.config.sample_count = Some(quality.sample_count);
+        }
+
+        self.renderer
+            .set_quality(quality.sample_count, quality.render_scale);
+    }
+
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        self.renderer.resize(size);
+
+        if size.width > 0 && size.height > 0 {
+            self.camera.aspect = size.width as f32 / size.height as f32;
+        }
+    }
+
+    // Switches between a window and borderless fullscreen. The window gets
+    // resized along with it, which rebuilds everything that depends on its
+    // size. It's remembered for next time.
+    fn toggle_fullscreen(&mut self) {
+        self.config.fullscreen = !self.config.fullscreen;
+        self.window.set_fullscreen(
+            self.config
+                .fullscreen
+                .then_some(Fullscreen::Borderless(None)),
+        );
+    }
+
+    // Shows the latest stats in the window title (or the page title on web)
+    // so they're visible even with the egui window collapsed
+    fn update_title(&self) {
+        let title = self.stats.title();
+
+        cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                if let Some(document) = web_sys::window().and_then(|win| win.document()) {
+                    document.set_title(&title);
+                }
+            } else {
+                self.window.set_title(&title);
+            }
+        }
+    }
+
+    pub fn size(&self) -> PhysicalSize<u32> {
+        self.renderer.size()
+    }
+
+    pub fn window(&self) -> &Window {
+        &self.window
+    }
+
+    /// Checks how fast the window's monitor refreshes again, e.g. after it's
+    /// been moved to another one
+    pub fn update_refresh_rate(&mut self) {
+        self.pacer.set_refresh_rate(pacing::monitor_refresh_rate(&self.window));
+    }
+
+    /// Starts the music once everything's loaded, and keeps it going
+    pub fn update_music(&mut self) {
+        if self.state != State::Playing || self.music_failed {
+            return;
+        }
+
+        if self.audio.music_started() {
+            self.audio.keep_music_playing();
+            return;
+        }
+
+        log::info!("Playing music");
+        // No sound isn't worth stopping everything for
+        if let Err(error) = self.play_music() {
+            log::error!("{}", error.report());
+            self.music_failed = true;
+        }
+    }
+
+    /// Fades out the sound, returning how long to wait before actually quitting
+    pub fn quit(&mut self) -> std::time::Duration {
+        // The window's size is only remembered on the way out, rather than
+        // saving it over and over while it's being dragged around. On the
+        // web it's the page's size, so there's no point.
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.window.fullscreen().is_none() {
+            let size = self.window.inner_size();
+            self.config.width = size.width;
+            self.config.height = size.height;
+        }
+        self.save_config();
+
+        self.audio.fade_out()
+    }
+
+    fn play_music(&mut self) -> error::Result<()> {
+        let song = self.song.take().ok_or(Error::Audio {
+            context: "the song hasn't been loaded".to_string(),
+            source: None,
+        })?;
+
+        self.audio.play_music(song)
+    }
 
-    pub fn song_handle_mut(&mut self) -> Option<&mut StaticSoundHandle> {
-        self.song_handle.as_mut()
-    }
 }
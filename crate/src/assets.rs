@@ -0,0 +1,455 @@
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+};
+
+use cfg_if::cfg_if;
+use futures::{future::Shared, FutureExt};
+#[cfg(feature = "audio")]
+use kira::sound::static_sound::{StaticSoundData, StaticSoundSettings};
+
+use crate::{
+    colour::ColourSpace,
+    error::{Error, Result},
+    locale::Translation,
+    model::{ImportTransform, Model},
+    resources::{self, Bytes},
+    texture::Texture,
+    upload::SharedUploads,
+};
+
+cfg_if! {
+    if #[cfg(target_arch = "wasm32")] {
+        // Requests on the web aren't Send
+        type Fetch = futures::future::LocalBoxFuture<'static, FetchResult>;
+    } else {
+        type Fetch = futures::future::BoxFuture<'static, FetchResult>;
+    }
+}
+
+// So sounds can be decoded straight from a fetched file
+#[cfg(feature = "audio")]
+struct SharedBytes(Arc<Bytes>);
+
+#[cfg(feature = "audio")]
+impl AsRef<[u8]> for SharedBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+// Errors can't be cloned, so everyone waiting on a fetch shares the same one
+type FetchResult = std::result::Result<Arc<Bytes>, Arc<Error>>;
+
+/// A cheap reference to something loaded by [Assets]. It stays valid until
+/// the asset's unloaded, after which looking it up gives nothing (even if the
+/// same file gets loaded again).
+pub struct Handle<T> {
+    index: usize,
+    generation: u32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+// Derived impls would need T to implement these too
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> std::hash::Hash for Handle<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Handle({}v{})", self.index, self.generation)
+    }
+}
+
+struct Slot<T> {
+    path: String,
+    generation: u32,
+    value: Option<Arc<T>>,
+}
+
+// Loaded assets of one type, by path
+pub struct Store<T> {
+    paths: HashMap<String, usize>,
+    slots: Vec<Slot<T>>,
+    // Slots that have been unloaded and can be reused
+    free: Vec<usize>,
+}
+
+impl<T> Default for Store<T> {
+    fn default() -> Self {
+        Self {
+            paths: HashMap::new(),
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+}
+
+impl<T> Store<T> {
+    fn find(&self, path: &str) -> Option<Handle<T>> {
+        let index = *self.paths.get(path)?;
+        Some(self.handle(index))
+    }
+
+    fn handle(&self, index: usize) -> Handle<T> {
+        Handle {
+            index,
+            generation: self.slots[index].generation,
+            _marker: PhantomData,
+        }
+    }
+
+    // If something else loaded the same path in the meantime, that one's kept
+    fn insert(&mut self, path: &str, value: T) -> Handle<T> {
+        if let Some(handle) = self.find(path) {
+            return handle;
+        }
+
+        let value = Some(Arc::new(value));
+        let index = match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index];
+                slot.path = path.to_string();
+                slot.value = value;
+                index
+            }
+            None => {
+                self.slots.push(Slot {
+                    path: path.to_string(),
+                    generation: 0,
+                    value,
+                });
+                self.slots.len() - 1
+            }
+        };
+
+        self.paths.insert(path.to_string(), index);
+        self.handle(index)
+    }
+
+    fn get(&self, handle: Handle<T>) -> Option<Arc<T>> {
+        let slot = self.slots.get(handle.index)?;
+        (slot.generation == handle.generation)
+            .then(|| slot.value.clone())
+            .flatten()
+    }
+
+    fn remove(&mut self, handle: Handle<T>) -> bool {
+        let Some(slot) = self.slots.get_mut(handle.index) else {
+            return false;
+        };
+
+        if slot.generation != handle.generation || slot.value.is_none() {
+            return false;
+        }
+
+        // Old handles to this slot stop working once it's reused
+        slot.value = None;
+        slot.generation += 1;
+        self.paths.remove(&slot.path);
+        self.free.push(handle.index);
+        true
+    }
+
+    // Removes everything that only the store's holding on to, returning how
+    // many there were
+    fn remove_unused(&mut self) -> usize {
+        let unused = (0..self.slots.len())
+            .filter(|&index| {
+                self.slots[index]
+                    .value
+                    .as_ref()
+                    .is_some_and(|value| Arc::strong_count(value) == 1)
+            })
+            .map(|index| self.handle(index))
+            .collect::<Vec<_>>();
+
+        unused.into_iter().filter(|&handle| self.remove(handle)).count()
+    }
+}
+
+// Files that are being fetched right now. Asking for one of these again
+// waits on the same request instead of starting another.
+#[derive(Default)]
+struct Fetches {
+    in_flight: HashMap<String, Shared<Fetch>>,
+}
+
+impl Fetches {
+    fn start(&mut self, path: &str) -> Shared<Fetch> {
+        self.in_flight
+            .entry(path.to_string())
+            .or_insert_with(|| {
+                let path = path.to_string();
+                let fetch = async move {
+                    resources::map_bytes(&path)
+                        .await
+                        .map(Arc::new)
+                        .map_err(Arc::new)
+                };
+
+                cfg_if! {
+                    if #[cfg(target_arch = "wasm32")] {
+                        fetch.boxed_local().shared()
+                    } else {
+                        fetch.boxed().shared()
+                    }
+                }
+            })
+            .clone()
+    }
+
+    fn finish(&mut self, path: &str) {
+        self.in_flight.remove(path);
+    }
+}
+
+#[derive(Default)]
+pub struct Stores {
+    models: Store<Model>,
+    textures: Store<Texture>,
+    #[cfg(feature = "audio")]
+    sounds: Store<StaticSoundData>,
+    translations: Store<Translation>,
+    fetches: Fetches,
+}
+
+/// Something [Assets] can keep track of
+pub trait Asset: Sized {
+    #[doc(hidden)]
+    fn store(stores: &mut Stores) -> &mut Store<Self>;
+}
+
+impl Asset for Model {
+    fn store(stores: &mut Stores) -> &mut Store<Self> {
+        &mut stores.models
+    }
+}
+
+impl Asset for Texture {
+    fn store(stores: &mut Stores) -> &mut Store<Self> {
+        &mut stores.textures
+    }
+}
+
+#[cfg(feature = "audio")]
+impl Asset for StaticSoundData {
+    fn store(stores: &mut Stores) -> &mut Store<Self> {
+        &mut stores.sounds
+    }
+}
+
+impl Asset for Translation {
+    fn store(stores: &mut Stores) -> &mut Store<Self> {
+        &mut stores.translations
+    }
+}
+
+/// Loads models, textures, sounds and translations, and keeps them around so anything else
+/// that wants the same file gets the one that's already loaded. On the web
+/// every file is a round trip to the server, so files that are already on
+/// their way are only requested once too.
+///
+/// Everything's cached by path, so loading the same file twice with different
+/// settings (like a model's import transform) gives back the first one.
+/// Cloning this is cheap, and the clone shares the same cache.
+#[derive(Clone)]
+pub struct Assets {
+    device: Arc<wgpu::Device>,
+    uploads: SharedUploads,
+    stores: Arc<Mutex<Stores>>,
+}
+
+impl Assets {
+    pub fn new(device: Arc<wgpu::Device>, uploads: SharedUploads) -> Self {
+        Self {
+            device,
+            uploads,
+            stores: Default::default(),
+        }
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn uploads(&self) -> &SharedUploads {
+        &self.uploads
+    }
+
+    /// The contents of a file. If it's already being fetched for something
+    /// else, this waits for that instead of fetching it again.
+    pub async fn fetch(&self, path: &str) -> Result<Arc<Bytes>> {
+        let fetch = self.stores.lock().unwrap().fetches.start(path);
+        let result = fetch.await;
+        self.stores.lock().unwrap().fetches.finish(path);
+
+        result.map_err(|error| error.duplicate())
+    }
+
+    pub async fn load_texture(
+        &self,
+        path: &str,
+        colour_space: ColourSpace,
+    ) -> Result<Handle<Texture>> {
+        if let Some(handle) = self.find(path) {
+            return Ok(handle);
+        }
+
+        let bytes = self.fetch(path).await?;
+        let texture = Texture::from_bytes(
+            &self.device,
+            &self.uploads,
+            &bytes,
+            Some(path),
+            colour_space,
+        )?;
+
+        Ok(self.insert(path, texture))
+    }
+
+    /// Loads an obj or gltf model, see [Model::load]
+    pub async fn load_model(
+        &self,
+        path: &str,
+        texture_layout: Option<&wgpu::BindGroupLayout>,
+        transform: &ImportTransform,
+    ) -> Result<Handle<Model>> {
+        if let Some(handle) = self.find(path) {
+            return Ok(handle);
+        }
+
+        let model = Model::load(self, path, texture_layout, transform).await?;
+        Ok(self.insert(path, model))
+    }
+
+    #[cfg(feature = "audio")]
+    pub async fn load_sound(&self, path: &str) -> Result<Handle<StaticSoundData>> {
+        if let Some(handle) = self.find(path) {
+            return Ok(handle);
+        }
+
+        let bytes = self.fetch(path).await?;
+        let sound = StaticSoundData::from_cursor(
+            std::io::Cursor::new(SharedBytes(bytes)),
+            StaticSoundSettings::default(),
+        )
+        .map_err(|e| Error::decode(path, e))?;
+
+        Ok(self.insert(path, sound))
+    }
+
+    /// Loads a language's ui strings, see [Translation]
+    pub async fn load_translation(&self, path: &str) -> Result<Handle<Translation>> {
+        if let Some(handle) = self.find(path) {
+            return Ok(handle);
+        }
+
+        let bytes = self.fetch(path).await?;
+        let translation = Translation::from_bytes(path, &bytes)?;
+
+        Ok(self.insert(path, translation))
+    }
+
+    /// The handle of an asset that's already been loaded from this path
+    pub fn find<T: Asset>(&self, path: &str) -> Option<Handle<T>> {
+        T::store(&mut self.stores.lock().unwrap()).find(path)
+    }
+
+    /// The asset, or None if it's been unloaded
+    pub fn get<T: Asset>(&self, handle: Handle<T>) -> Option<Arc<T>> {
+        T::store(&mut self.stores.lock().unwrap()).get(handle)
+    }
+
+    /// Unloads everything that isn't being used by anything, to free up some
+    /// memory. Returns how many assets that was.
+    pub fn unload_unused(&self) -> usize {
+        let mut stores = self.stores.lock().unwrap();
+        let unloaded = stores.models.remove_unused()
+            + stores.textures.remove_unused()
+            + stores.translations.remove_unused();
+
+        #[cfg(feature = "audio")]
+        let unloaded = unloaded + stores.sounds.remove_unused();
+
+        unloaded
+    }
+
+    fn insert<T: Asset>(&self, path: &str, value: T) -> Handle<T> {
+        T::store(&mut self.stores.lock().unwrap()).insert(path, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_path_gives_same_handle() {
+        let mut store = Store::default();
+        let first = store.insert("a", 1);
+        let second = store.insert("a", 2);
+
+        assert_eq!(first, second);
+        assert_eq!(*store.get(first).unwrap(), 1);
+        assert_eq!(store.find("a"), Some(first));
+    }
+
+    #[test]
+    fn unloaded_handles_stay_dead_when_the_slot_is_reused() {
+        let mut store = Store::default();
+        let old = store.insert("a", 1);
+
+        assert!(store.remove(old));
+        assert!(!store.remove(old));
+        assert_eq!(store.get(old), None);
+        assert_eq!(store.find("a"), None);
+
+        let new = store.insert("b", 2);
+        assert_eq!(new.index, old.index);
+        assert_eq!(store.get(old), None);
+        assert_eq!(*store.get(new).unwrap(), 2);
+    }
+
+    #[test]
+    fn only_unused_assets_are_unloaded() {
+        let mut store = Store::default();
+        let used = store.insert("a", 1);
+        let unused = store.insert("b", 2);
+        let kept = store.get(used).unwrap();
+
+        assert_eq!(store.remove_unused(), 1);
+        assert_eq!(store.get(unused), None);
+        assert_eq!(store.get(used), Some(kept));
+    }
+
+    #[test]
+    fn fetches_in_flight_are_shared() {
+        let mut fetches = Fetches::default();
+        let first = fetches.start("Cargo.toml");
+        let second = fetches.start("Cargo.toml");
+
+        let (first, second) = futures::executor::block_on(futures::future::join(first, second));
+        assert!(Arc::ptr_eq(&first.unwrap(), &second.unwrap()));
+    }
+}
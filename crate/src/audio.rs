@@ -0,0 +1,778 @@
+use std::{sync::Arc, time::Duration};
+
+use cgmath::{InnerSpace, One, Quaternion, Rotation, Vector3};
+use kira::{
+    clock::{ClockHandle, ClockSpeed},
+    dsp::Frame,
+    manager::{error::PlaySoundError, AudioManager, AudioManagerSettings},
+    sound::{
+        static_sound::{StaticSoundData, StaticSoundHandle, StaticSoundSettings},
+        PlaybackState,
+    },
+    spatial::{
+        emitter::{EmitterDistances, EmitterHandle, EmitterSettings},
+        listener::{ListenerHandle, ListenerSettings},
+        scene::{SpatialSceneHandle, SpatialSceneSettings},
+    },
+    track::{TrackBuilder, TrackHandle},
+    tween::Tween,
+    StartTime, Volume,
+};
+
+use crate::{
+    ambience::AmbientSound,
+    error::{Error, Result},
+    locale::Strings,
+};
+
+pub use crate::music::{Music, MusicData, MusicHandle};
+
+/// A sound that's been loaded (or made up, like the click)
+pub type Sound = StaticSoundData;
+
+// Resolution of the clock that sound effects are scheduled on, per second of
+// simulation time (so it ticks slower when the simulation's slowed down)
+const CLOCK_TICKS_PER_SECOND: f64 = 1000.0;
+// Sounds are played this long (in real seconds) after the simulation step
+// that caused them. A frame would have to take longer than this before
+// things go out of sync.
+const SCHEDULE_LATENCY: f64 = 0.1;
+// If the simulation and audio clocks have drifted apart by more than this
+// many real seconds (e.g. after a long frame, or the window being dragged
+// around), they get lined up again
+const MAX_DRIFT: f64 = 0.25;
+// Ambient sounds are at full volume when the listener is this close (or
+// closer, for sounds with a tiny radius)
+const AMBIENT_MIN_DISTANCE: f32 = 1.0;
+// How long pausing, ducking and quitting take to fade
+const PAUSE_FADE: Duration = Duration::from_millis(200);
+const DUCK_FADE: Duration = Duration::from_millis(300);
+const QUIT_FADE: Duration = Duration::from_millis(500);
+const MUTE_FADE: Duration = Duration::from_millis(100);
+const TIME_SCALE_FADE: Duration = Duration::from_millis(100);
+// The song fades in when it starts, instead of starting at full volume
+const MUSIC_FADE_IN: Duration = Duration::from_secs(2);
+/// How loud the song's made to be by default, in LUFS
+pub const DEFAULT_MUSIC_LOUDNESS: f64 = -16.0;
+// The most the song can be turned up to make it as loud as it should be, in
+// decibels. Any more and a quiet song with loud bits would clip.
+const MAX_MUSIC_BOOST: f64 = 6.0;
+// How much quieter everything gets while the menus are open, in decibels
+const DEFAULT_DUCK_AMOUNT: f64 = 8.0;
+/// The most sound effects that can be playing at once. Past that, new ones
+/// are dropped (the quietest first) so a big pile landing doesn't turn into
+/// a wall of noise.
+pub const MAX_SFX_VOICES: usize = 24;
+// Sound effects are at full volume this close to the listener, and get
+// quieter further away
+const SFX_FULL_VOLUME_DISTANCE: f32 = 10.0;
+// How far to the left or right sound effects can be panned, from 0 (not at
+// all) to 1 (completely into one ear)
+const SFX_PAN_WIDTH: f32 = 0.7;
+
+/// A sound that should play at a specific point in simulation time
+pub struct SoundEvent {
+    /// Simulation time, in seconds
+    pub time: f64,
+    pub sound: StaticSoundData,
+    pub volume: f64,
+    /// 0 is hard left, 1 is hard right
+    pub panning: f64,
+}
+
+impl SoundEvent {
+    /// A sound that isn't coming from anywhere in particular
+    pub fn centred(time: f64, sound: StaticSoundData, volume: f64) -> Self {
+        Self {
+            time,
+            sound,
+            volume,
+            panning: 0.5,
+        }
+    }
+
+    /// A sound coming from somewhere in the world, which is quieter the
+    /// further it is from the listener and panned towards whichever side
+    /// it's on. It's not as good as going through the spatial scene, but
+    /// sound effects are too short-lived to be worth an emitter each.
+    pub fn at(
+        time: f64,
+        sound: StaticSoundData,
+        volume: f64,
+        position: Vector3<f32>,
+        listener_position: Vector3<f32>,
+        listener_orientation: Quaternion<f32>,
+    ) -> Self {
+        let offset = position - listener_position;
+        let distance = offset.magnitude();
+        let falloff = (SFX_FULL_VOLUME_DISTANCE / distance.max(SFX_FULL_VOLUME_DISTANCE)) as f64;
+
+        let right = listener_orientation.rotate_vector(Vector3::unit_x());
+        let side = if distance > f32::EPSILON {
+            offset.dot(right) / distance
+        } else {
+            0.0
+        };
+
+        Self {
+            time,
+            sound,
+            volume: volume * falloff,
+            panning: (0.5 + 0.5 * side * SFX_PAN_WIDTH) as f64,
+        }
+    }
+}
+
+// One of the scene's ambient sounds, playing (or waiting to play) in the spatial scene
+struct AmbientVoice {
+    sound: AmbientSound,
+    data: StaticSoundData,
+    // Both of these are None until the audio manager has started
+    emitter: Option<EmitterHandle>,
+    handle: Option<StaticSoundHandle>,
+}
+
+/// Owns the audio manager and everything played through it.
+///
+/// Sound effects are queued with the simulation time they happened at, and
+/// then played on kira's clock at the same spacing they had in the
+/// simulation, so they don't bunch up or jitter along with the frame rate.
+///
+/// Ambient sounds are played through a spatial scene, with the listener
+/// following the camera around.
+///
+/// Whatever the app is doing decides how everything sounds: it's all paused
+/// along with the simulation, turned down (ducked) while the menus are open
+/// and faded out when quitting.
+///
+/// When the simulation's slowed down (or sped up), the clock and the sound
+/// effects go along with it, and so does the music unless it's set to keep
+/// its pitch. The ambience always plays at normal speed.
+///
+/// The music and the sound effects (ambience included) go through their own
+/// tracks, so they can be turned up and down separately. Both of those go
+/// through the main track, which the master volume and muting apply to.
+pub struct AudioSystem {
+    // Not created until the music starts, since browsers won't allow
+    // audio before the user interacts with the page
+    manager: Option<AudioManager>,
+    clock: Option<ClockHandle>,
+    spatial_scene: Option<SpatialSceneHandle>,
+    listener: Option<ListenerHandle>,
+    music_track: Option<TrackHandle>,
+    sfx_track: Option<TrackHandle>,
+    // The simulation time and clock tick that line up with each other
+    anchor: Option<(f64, u64)>,
+    queue: Vec<SoundEvent>,
+    // Sound effects that are playing, or scheduled to
+    sfx_voices: Vec<StaticSoundHandle>,
+    ambience: Vec<AmbientVoice>,
+    song_handle: Option<MusicHandle>,
+    // How loud the song is, and how loud it should be, in LUFS
+    song_loudness: Option<f64>,
+    music_loudness: f64,
+    paused: bool,
+    ducked: bool,
+    // In decibels
+    duck_amount: f64,
+    // From 0 to 1, everything's multiplied by this
+    master_volume: f64,
+    // Also from 0 to 1, on top of the master volume
+    music_volume: f64,
+    sfx_volume: f64,
+    muted: bool,
+    // How fast the simulation's going, where 1 is normal
+    time_scale: f64,
+    // If set, the music plays at normal speed whatever the time scale is
+    preserve_music_pitch: bool,
+}
+
+impl AudioSystem {
+    pub fn new() -> Self {
+        Self {
+            manager: None,
+            clock: None,
+            spatial_scene: None,
+            listener: None,
+            music_track: None,
+            sfx_track: None,
+            anchor: None,
+            queue: Vec::new(),
+            sfx_voices: Vec::new(),
+            ambience: Vec::new(),
+            song_handle: None,
+            song_loudness: None,
+            music_loudness: DEFAULT_MUSIC_LOUDNESS,
+            paused: false,
+            ducked: false,
+            duck_amount: DEFAULT_DUCK_AMOUNT,
+            master_volume: 1.0,
+            music_volume: 1.0,
+            sfx_volume: 1.0,
+            muted: false,
+            time_scale: 1.0,
+            preserve_music_pitch: false,
+        }
+    }
+
+    fn start_manager(&mut self) -> Result<&mut AudioManager> {
+        if self.manager.is_none() {
+            let mut manager = AudioManager::new(AudioManagerSettings::default())
+                .map_err(|e| Error::audio("couldn't start the audio manager", e))?;
+
+            let clock = manager
+                .add_clock(ClockSpeed::TicksPerSecond(CLOCK_TICKS_PER_SECOND))
+                .map_err(|e| Error::audio("couldn't create the audio clock", e))?;
+            clock
+                .start()
+                .map_err(|e| Error::audio("couldn't start the audio clock", e))?;
+
+            let music_track = manager
+                .add_sub_track(TrackBuilder::new())
+                .map_err(|e| Error::audio("couldn't create the music track", e))?;
+            let sfx_track = manager
+                .add_sub_track(TrackBuilder::new())
+                .map_err(|e| Error::audio("couldn't create the sound effects track", e))?;
+
+            let mut spatial_scene = manager
+                .add_spatial_scene(SpatialSceneSettings::default())
+                .map_err(|e| Error::audio("couldn't create the spatial scene", e))?;
+            let listener = spatial_scene
+                .add_listener(
+                    Vector3::new(0.0, 0.0, 0.0),
+                    Quaternion::one(),
+                    // The ambience counts as sound effects
+                    ListenerSettings::default().track(&sfx_track),
+                )
+                .map_err(|e| Error::audio("couldn't create the audio listener", e))?;
+
+            self.manager = Some(manager);
+            self.clock = Some(clock);
+            self.spatial_scene = Some(spatial_scene);
+            self.listener = Some(listener);
+            self.music_track = Some(music_track);
+            self.sfx_track = Some(sfx_track);
+
+            // Anything that happened before the audio started still counts
+            self.apply_volume(Duration::ZERO);
+            self.apply_time_scale(Duration::ZERO);
+            if self.paused {
+                self.apply_pause(Duration::ZERO);
+            }
+        }
+
+        Ok(self.manager.as_mut().unwrap())
+    }
+
+    pub fn play_music(&mut self, song: Music) -> Result<()> {
+        self.start_manager()?;
+
+        self.song_loudness = song.loudness;
+        let volume = self.music_gain();
+        let fade_in = tween(MUSIC_FADE_IN);
+        if let Some(loudness) = song.loudness {
+            log::info!("The song is {loudness:.1} LUFS, so it's turned {volume:+.1} dB");
+        }
+
+        // Both of these exist once the manager does
+        let manager = self.manager.as_mut().unwrap();
+        let track = self.music_track.as_ref().unwrap();
+
+        let handle = match song.data {
+            MusicData::Static(data) => {
+                let data = data.with_modified_settings(|s| {
+                    s.output_destination(track)
+                        .volume(Volume::Decibels(volume))
+                        .fade_in_tween(fade_in)
+                });
+                MusicHandle::Static(manager.play(data).map_err(play_error)?)
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            MusicData::Streaming(mut data) => {
+                data.settings = data
+                    .settings
+                    .output_destination(track)
+                    .volume(Volume::Decibels(volume))
+                    .fade_in_tween(fade_in);
+                MusicHandle::Streaming(manager.play(data).map_err(play_error)?)
+            }
+        };
+
+        self.song_handle = Some(handle);
+        self.apply_time_scale(Duration::ZERO);
+        Ok(())
+    }
+
+    /// Sets how loud the song's made to be, in LUFS. Every song's turned up
+    /// or down to this, so they all sound about as loud as each other.
+    pub fn set_music_loudness(&mut self, loudness: f64) {
+        if loudness == self.music_loudness {
+            return;
+        }
+
+        self.music_loudness = loudness;
+        let volume = Volume::Decibels(self.music_gain());
+        if let Some(handle) = self.song_handle.as_mut() {
+            if let Err(e) = handle.set_volume(volume, Tween::default()) {
+                log::warn!("Couldn't change the music's loudness: {e}");
+            }
+        }
+    }
+
+    // How much the song's turned up or down by, in decibels, to make it as
+    // loud as it should be. Songs with no loudness are left alone.
+    fn music_gain(&self) -> f64 {
+        self.song_loudness.map_or(0.0, |loudness| {
+            (self.music_loudness - loudness).min(MAX_MUSIC_BOOST)
+        })
+    }
+
+    pub fn song_handle_mut(&mut self) -> Option<&mut MusicHandle> {
+        self.song_handle.as_mut()
+    }
+
+    /// Whether the song's been started (even if it's paused now)
+    pub fn music_started(&self) -> bool {
+        self.song_handle.is_some()
+    }
+
+    /// Picks the song back up if something other than us paused it
+    pub fn keep_music_playing(&mut self) {
+        if self.paused {
+            return;
+        }
+
+        if let Some(handle) = self.song_handle.as_mut() {
+            if handle.state() == PlaybackState::Paused {
+                log::info!("Resuming music");
+                if let Err(e) = handle.resume(Tween::default()) {
+                    log::warn!("Couldn't resume the music: {e}");
+                }
+            }
+        }
+    }
+
+    /// Pauses (or resumes) everything: the music, ambience and sound effects
+    pub fn set_paused(&mut self, paused: bool) {
+        if paused != self.paused {
+            self.paused = paused;
+            self.apply_pause(PAUSE_FADE);
+        }
+    }
+
+    /// Turns everything down (or back up) by the duck amount
+    pub fn set_ducked(&mut self, ducked: bool) {
+        if ducked != self.ducked {
+            self.ducked = ducked;
+            self.apply_volume(DUCK_FADE);
+        }
+    }
+
+    /// How loud everything is, from 0 (silent) to 1
+    pub fn set_master_volume(&mut self, volume: f64) {
+        if volume != self.master_volume {
+            self.master_volume = volume;
+            self.apply_volume(Duration::ZERO);
+        }
+    }
+
+    /// How loud the music is compared to everything else, from 0 to 1
+    pub fn set_music_volume(&mut self, volume: f64) {
+        if volume != self.music_volume {
+            self.music_volume = volume;
+            self.apply_volume(Duration::ZERO);
+        }
+    }
+
+    /// How loud the sound effects and ambience are, from 0 to 1
+    pub fn set_sfx_volume(&mut self, volume: f64) {
+        if volume != self.sfx_volume {
+            self.sfx_volume = volume;
+            self.apply_volume(Duration::ZERO);
+        }
+    }
+
+    /// Silences everything (or brings it back), without forgetting the volumes
+    pub fn set_muted(&mut self, muted: bool) {
+        if muted != self.muted {
+            self.muted = muted;
+            self.apply_volume(MUTE_FADE);
+        }
+    }
+
+    /// How fast the simulation's going (1 is normal), which the sound
+    /// effects follow so they stay lined up with it
+    pub fn set_time_scale(&mut self, scale: f64) {
+        if scale != self.time_scale {
+            self.time_scale = scale;
+            self.apply_time_scale(TIME_SCALE_FADE);
+        }
+    }
+
+    /// Keeps the music at its normal speed and pitch when the time scale
+    /// changes, instead of slowing down along with everything else
+    pub fn set_preserve_music_pitch(&mut self, preserve: bool) {
+        if preserve != self.preserve_music_pitch {
+            self.preserve_music_pitch = preserve;
+            self.apply_time_scale(TIME_SCALE_FADE);
+        }
+    }
+
+    /// Starts fading everything out, returning how long that'll take
+    pub fn fade_out(&mut self) -> Duration {
+        let Some(manager) = self.manager.as_ref() else {
+            return Duration::ZERO;
+        };
+
+        if let Err(e) = manager.pause(tween(QUIT_FADE)) {
+            log::warn!("Couldn't fade out the audio: {e}");
+            return Duration::ZERO;
+        }
+
+        QUIT_FADE
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, strings: &Strings) {
+        ui.horizontal(|ui| {
+            ui.label(strings.get("audio.ducking"));
+            let changed = ui
+                .add(
+                    egui::DragValue::new(&mut self.duck_amount)
+                        .clamp_range(0.0..=60.0)
+                        .speed(0.1),
+                )
+                .changed();
+
+            if changed {
+                self.apply_volume(Duration::ZERO);
+            }
+        });
+    }
+
+    fn apply_pause(&mut self, fade: Duration) {
+        let Some(manager) = self.manager.as_ref() else {
+            return;
+        };
+
+        // The clock stops along with everything else, so sound effects stay
+        // lined up with the simulation
+        let result = if self.paused {
+            manager.pause(tween(fade))
+        } else {
+            manager.resume(tween(fade))
+        };
+
+        if let Err(e) = result {
+            log::warn!("Couldn't pause or resume the audio: {e}");
+        }
+    }
+
+    fn apply_volume(&mut self, fade: Duration) {
+        let Some(manager) = self.manager.as_ref() else {
+            return;
+        };
+
+        let duck = if self.ducked { -self.duck_amount } else { 0.0 };
+        let volume = if self.muted {
+            0.0
+        } else {
+            self.master_volume * Volume::Decibels(duck).as_amplitude()
+        };
+
+        let mut result = manager
+            .main_track()
+            .set_volume(Volume::Amplitude(volume), tween(fade));
+
+        for (track, volume) in [
+            (self.music_track.as_ref(), self.music_volume),
+            (self.sfx_track.as_ref(), self.sfx_volume),
+        ] {
+            if let Some(track) = track {
+                result = result.and(track.set_volume(Volume::Amplitude(volume), tween(fade)));
+            }
+        }
+
+        if let Err(e) = result {
+            log::warn!("Couldn't change the volume: {e}");
+        }
+    }
+
+    fn apply_time_scale(&mut self, fade: Duration) {
+        // The clock counts simulation time, so it speeds up and slows down
+        // with it. Sounds that have already started keep their own speed.
+        if let Some(clock) = self.clock.as_ref() {
+            let speed = ClockSpeed::TicksPerSecond(CLOCK_TICKS_PER_SECOND * self.time_scale);
+            if let Err(e) = clock.set_speed(speed, tween(fade)) {
+                log::warn!("Couldn't change the clock's speed: {e}");
+            }
+        }
+
+        let music_rate = if self.preserve_music_pitch {
+            1.0
+        } else {
+            self.time_scale
+        };
+
+        if let Some(handle) = self.song_handle.as_mut() {
+            if let Err(e) = handle.set_playback_rate(music_rate, tween(fade)) {
+                log::warn!("Couldn't change the music's speed: {e}");
+            }
+        }
+    }
+
+    /// How far into the song the music is, in seconds, if it's started
+    pub fn song_position(&self) -> Option<f64> {
+        self.song_handle.as_ref().map(|handle| handle.position())
+    }
+
+    /// Replaces the ambient sounds with new ones. They start playing on the
+    /// next call to `update_spatial` (once the audio has started).
+    pub fn set_ambience(&mut self, sounds: Vec<(AmbientSound, StaticSoundData)>) {
+        for voice in self.ambience.iter_mut() {
+            if let Some(handle) = voice.handle.as_mut() {
+                if let Err(e) = handle.stop(Tween::default()) {
+                    log::warn!("Couldn't stop ambient sound \"{}\": {e}", voice.sound.name);
+                }
+            }
+        }
+
+        self.ambience = sounds
+            .into_iter()
+            .map(|(sound, data)| AmbientVoice {
+                sound,
+                data,
+                emitter: None,
+                handle: None,
+            })
+            .collect();
+    }
+
+    /// Moves the listener to the camera and the ambient sounds to wherever
+    /// `position_of` says they are, starting any that aren't playing yet.
+    /// This should be called once a frame.
+    pub fn update_spatial(
+        &mut self,
+        listener_position: Vector3<f32>,
+        listener_orientation: Quaternion<f32>,
+        position_of: impl Fn(&AmbientSound) -> [f32; 3],
+    ) {
+        let (Some(spatial_scene), Some(listener)) =
+            (self.spatial_scene.as_mut(), self.listener.as_mut())
+        else {
+            return;
+        };
+
+        let result = listener
+            .set_position(listener_position, Tween::default())
+            .and_then(|_| listener.set_orientation(listener_orientation, Tween::default()));
+
+        if let Err(e) = result {
+            log::warn!("Couldn't move the audio listener: {e}");
+        }
+
+        for voice in self.ambience.iter_mut() {
+            let position = Vector3::from(position_of(&voice.sound));
+
+            if let Some(emitter) = voice.emitter.as_mut() {
+                if let Err(e) = emitter.set_position(position, Tween::default()) {
+                    log::warn!("Couldn't move ambient sound \"{}\": {e}", voice.sound.name);
+                }
+
+                continue;
+            }
+
+            // Not playing yet, so start it up
+            let radius = voice.sound.radius.max(0.1);
+            let settings = EmitterSettings::new().distances(EmitterDistances {
+                min_distance: AMBIENT_MIN_DISTANCE.min(radius / 2.0),
+                max_distance: radius,
+            });
+
+            let emitter = match spatial_scene.add_emitter(position, settings) {
+                Ok(emitter) => emitter,
+                Err(e) => {
+                    log::warn!("Couldn't add ambient sound \"{}\": {e}", voice.sound.name);
+                    continue;
+                }
+            };
+
+            let settings = StaticSoundSettings::new()
+                .loop_region(..)
+                .volume(voice.sound.volume)
+                .output_destination(&emitter);
+
+            // The manager must exist if the spatial scene does
+            let manager = self.manager.as_mut().unwrap();
+            match manager.play(voice.data.with_settings(settings)) {
+                Ok(handle) => voice.handle = Some(handle),
+                Err(e) => log::warn!("Couldn't play ambient sound \"{}\": {e}", voice.sound.name),
+            }
+
+            // Kept even if the sound failed so it isn't retried every frame
+            voice.emitter = Some(emitter);
+        }
+    }
+
+    /// Queues a sound to be played at the given simulation time
+    pub fn queue(&mut self, event: SoundEvent) {
+        self.queue.push(event);
+    }
+
+    /// Schedules all the queued sounds on the audio clock. This should be
+    /// called once a frame, after the simulation has been updated.
+    pub fn update(&mut self, sim_time: f64) {
+        let (Some(manager), Some(clock), Some(track)) = (
+            self.manager.as_mut(),
+            self.clock.as_ref(),
+            self.sfx_track.as_ref(),
+        ) else {
+            // No audio yet, so nothing can be played
+            self.queue.clear();
+            return;
+        };
+
+        let now = clock.time();
+        let ticks_to_secs = |ticks: u64| ticks as f64 / CLOCK_TICKS_PER_SECOND;
+
+        let drifted = self.anchor.is_none_or(|(anchor_time, anchor_ticks)| {
+            let expected = sim_time - anchor_time;
+            let actual = ticks_to_secs(now.ticks.saturating_sub(anchor_ticks));
+            (expected - actual).abs() > MAX_DRIFT * self.time_scale
+        });
+
+        if drifted {
+            self.anchor = Some((sim_time, now.ticks));
+        }
+
+        let (anchor_time, anchor_ticks) = self.anchor.unwrap();
+
+        self.sfx_voices
+            .retain(|handle| handle.state() != PlaybackState::Stopped);
+
+        let free_voices = MAX_SFX_VOICES.saturating_sub(self.sfx_voices.len());
+        keep_loudest(&mut self.queue, free_voices);
+
+        for event in self.queue.drain(..) {
+            let offset = event.time - anchor_time + SCHEDULE_LATENCY * self.time_scale;
+            let target = anchor_ticks as f64 + offset * CLOCK_TICKS_PER_SECOND;
+
+            // Anything that's already late just plays as soon as possible
+            let start_time = if target > now.ticks as f64 {
+                StartTime::ClockTime(now + (target as u64 - now.ticks))
+            } else {
+                StartTime::Immediate
+            };
+
+            let settings = StaticSoundSettings::new()
+                .start_time(start_time)
+                .volume(event.volume)
+                .panning(event.panning)
+                .playback_rate(self.time_scale)
+                .output_destination(track);
+
+            match manager.play(event.sound.with_settings(settings)) {
+                Ok(handle) => self.sfx_voices.push(handle),
+                Err(e) => log::warn!("Couldn't play sound effect: {e}"),
+            }
+        }
+    }
+}
+
+fn tween(duration: Duration) -> Tween {
+    Tween {
+        duration,
+        ..Default::default()
+    }
+}
+
+// The two kinds of songs have different error types
+fn play_error<E>(error: PlaySoundError<E>) -> Error
+where
+    PlaySoundError<E>: std::error::Error + Send + Sync + 'static,
+{
+    Error::audio("couldn't play the song", error)
+}
+
+/// A short, quiet knock, generated so it doesn't need an asset
+pub fn click_sound() -> StaticSoundData {
+    const SAMPLE_RATE: u32 = 44100;
+    const DURATION: f32 = 0.06;
+    const FREQUENCY: f32 = 220.0;
+
+    let frames = (0..(SAMPLE_RATE as f32 * DURATION) as u32)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let envelope = (-t * 80.0).exp();
+            Frame::from_mono((t * FREQUENCY * std::f32::consts::TAU).sin() * envelope * 0.5)
+        })
+        .collect::<Vec<_>>();
+
+    StaticSoundData {
+        sample_rate: SAMPLE_RATE,
+        frames: Arc::from(frames),
+        settings: StaticSoundSettings::default(),
+    }
+}
+
+// If they can't all play, the loudest ones get to
+fn keep_loudest(queue: &mut Vec<SoundEvent>, free_voices: usize) {
+    if queue.len() > free_voices {
+        queue.sort_by(|a, b| b.volume.total_cmp(&a.volume));
+        queue.truncate(free_voices);
+    }
+}
+
+/// A low, soft thump for things landing on the ground. Also generated.
+pub fn thud_sound() -> StaticSoundData {
+    const SAMPLE_RATE: u32 = 44100;
+    const DURATION: f32 = 0.25;
+    // The pitch drops from the first to the second, like a drum
+    const START_FREQUENCY: f32 = 110.0;
+    const END_FREQUENCY: f32 = 45.0;
+
+    let mut phase = 0.0;
+    let frames = (0..(SAMPLE_RATE as f32 * DURATION) as u32)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let frequency = END_FREQUENCY + (START_FREQUENCY - END_FREQUENCY) * (-t * 30.0).exp();
+            phase += frequency / SAMPLE_RATE as f32;
+
+            let envelope = (-t * 18.0).exp() * (t * 400.0).min(1.0);
+            Frame::from_mono((phase * std::f32::consts::TAU).sin() * envelope * 0.8)
+        })
+        .collect::<Vec<_>>();
+
+    StaticSoundData {
+        sample_rate: SAMPLE_RATE,
+        frames: Arc::from(frames),
+        settings: StaticSoundSettings::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_quietest_sounds_are_dropped_past_the_cap() {
+        let mut queue: Vec<_> = (0..MAX_SFX_VOICES + 4)
+            .map(|i| SoundEvent::centred(0.0, click_sound(), i as f64))
+            .collect();
+
+        // A few voices are already playing, so even fewer new ones fit
+        keep_loudest(&mut queue, MAX_SFX_VOICES - 2);
+
+        assert_eq!(queue.len(), MAX_SFX_VOICES - 2);
+        assert!(queue.iter().all(|event| event.volume >= 6.0));
+    }
+
+    #[test]
+    fn nothing_is_dropped_under_the_cap() {
+        let mut queue = vec![SoundEvent::centred(0.0, click_sound(), 1.0)];
+        keep_loudest(&mut queue, MAX_SFX_VOICES);
+        assert_eq!(queue.len(), 1);
+
+        keep_loudest(&mut queue, 0);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,230 @@
+//! Benchmark mode, for telling whether a change made things faster or slower.
+//! `--bench` plays the same thirty seconds every time: the same emitters,
+//! seed and camera path, with the simulation moved on a fixed sixtieth of a
+//! second each frame however long the frame really took. Then it writes how
+//! long the frames took to a json report and quits.
+//!
+//! ```text
+//! tumblin-down --bench --bench-output before.json
+//! ```
+
+use std::time::Duration;
+
+use instant::Instant;
+use serde::Serialize;
+
+use crate::{emitter::Emitter, scene::SceneDescription, storage};
+
+/// How long the scenario runs for, in simulated seconds
+pub const DURATION: f32 = 30.0;
+/// How far the simulation moves on each frame
+pub const STEP: f32 = 1.0 / 60.0;
+pub const DEFAULT_OUTPUT: &str = "bench.json";
+
+const SEED: u64 = 0x7ab1e;
+// The camera goes round the statue once over the whole run
+const ORBIT_RADIUS: f32 = 14.0;
+const ORBIT_HEIGHT: f32 = 6.0;
+
+/// The scene it always runs, which is whatever's been loaded (for the models,
+/// light and sky) with the emitters, props and seed swapped out so it doesn't
+/// matter what's been saved
+pub fn scene(scene: SceneDescription) -> SceneDescription {
+    let emitters = vec![
+        Emitter {
+            name: "Bench rain".to_string(),
+            ..Default::default()
+        },
+        Emitter {
+            name: "Bench pour".to_string(),
+            position: [0.0, 12.0, 0.0],
+            extents: [1.0, 0.0, 1.0],
+            spawn_interval: 1.0 / 30.0,
+            ..Default::default()
+        },
+    ];
+
+    SceneDescription {
+        emitters,
+        props: Vec::new(),
+        max_age: None,
+        choreography: Default::default(),
+        seed: Some(SEED),
+        ..scene
+    }
+}
+
+/// Where the camera is and what it's looking at, this far into the run
+pub fn camera(time: f32) -> ([f32; 3], [f32; 3]) {
+    let angle = time / DURATION * std::f32::consts::TAU;
+    let eye = [
+        angle.sin() * ORBIT_RADIUS,
+        ORBIT_HEIGHT,
+        angle.cos() * ORBIT_RADIUS,
+    ];
+    (eye, [0.0, 1.0, 0.0])
+}
+
+/// The timings for a benchmark that's running, in milliseconds
+#[derive(Debug)]
+pub struct Bench {
+    output: String,
+    time: f32,
+    last_frame: Option<Instant>,
+    frames: Vec<f32>,
+    updates: Vec<f32>,
+    physics: Vec<f32>,
+    submits: Vec<f32>,
+    peak_bodies: usize,
+}
+
+impl Bench {
+    pub fn new(output: String) -> Self {
+        Self {
+            output,
+            time: 0.0,
+            last_frame: None,
+            frames: Vec::new(),
+            updates: Vec::new(),
+            physics: Vec::new(),
+            submits: Vec::new(),
+            peak_bodies: 0,
+        }
+    }
+
+    /// Starts a frame, recording how long the last one really took. Returns
+    /// how far the simulation should move, which is always the same.
+    pub fn start_frame(&mut self) -> f32 {
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame {
+            self.frames.push(milliseconds(now - last_frame));
+        }
+
+        self.last_frame = Some(now);
+        self.time += STEP;
+        STEP
+    }
+
+    /// How far into the run it is, in simulated seconds
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.time >= DURATION
+    }
+
+    pub fn record_update(&mut self, took: Duration, bodies: usize) {
+        self.updates.push(milliseconds(took));
+        self.peak_bodies = self.peak_bodies.max(bodies);
+    }
+
+    pub fn record_physics(&mut self, took: Duration) {
+        self.physics.push(milliseconds(took));
+    }
+
+    pub fn record_submit(&mut self, took: Duration) {
+        self.submits.push(milliseconds(took));
+    }
+
+    pub fn report(&self, size: [u32; 2]) -> Report {
+        Report {
+            seconds: DURATION,
+            frames: self.frames.len(),
+            size,
+            peak_bodies: self.peak_bodies,
+            frame: Timings::of(&self.frames),
+            update: Timings::of(&self.updates),
+            physics: Timings::of(&self.physics),
+            submit: Timings::of(&self.submits),
+        }
+    }
+
+    /// Writes the report where the command line said. If that doesn't work
+    /// it's logged instead, so the run isn't wasted.
+    pub fn write_report(&self, size: [u32; 2]) {
+        let report = serde_json::to_string_pretty(&self.report(size))
+            .expect("the report should always serialise");
+
+        match storage::write_file(self.output.as_ref(), &report) {
+            Ok(()) => log::info!("Wrote the benchmark report to {}", self.output),
+            Err(error) => {
+                log::error!("{}", error.report());
+                log::info!("{report}");
+            }
+        }
+    }
+}
+
+/// What a benchmark run found
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub seconds: f32,
+    pub frames: usize,
+    /// The size of the window, in pixels
+    pub size: [u32; 2],
+    /// The most bodies in the simulation at once
+    pub peak_bodies: usize,
+    pub frame: Timings,
+    pub update: Timings,
+    pub physics: Timings,
+    pub submit: Timings,
+}
+
+/// How long something took over the run, in milliseconds
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Timings {
+    pub mean: f32,
+    pub p50: f32,
+    pub p95: f32,
+    pub p99: f32,
+    pub max: f32,
+}
+
+impl Timings {
+    fn of(samples: &[f32]) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f32::total_cmp);
+
+        // Nearest rank, so each one is a time that actually happened
+        let percentile = |p: f32| {
+            let rank = (p / 100.0 * sorted.len() as f32).ceil() as usize;
+            sorted[rank.clamp(1, sorted.len()) - 1]
+        };
+
+        Self {
+            mean: sorted.iter().sum::<f32>() / sorted.len() as f32,
+            p50: percentile(50.0),
+            p95: percentile(95.0),
+            p99: percentile(99.0),
+            max: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+fn milliseconds(duration: Duration) -> f32 {
+    duration.as_secs_f32() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_are_times_that_happened() {
+        let samples: Vec<f32> = (1..=100).rev().map(|ms| ms as f32).collect();
+        let timings = Timings::of(&samples);
+
+        assert_eq!(timings.p50, 50.0);
+        assert_eq!(timings.p95, 95.0);
+        assert_eq!(timings.p99, 99.0);
+        assert_eq!(timings.max, 100.0);
+        assert!((timings.mean - 50.5).abs() < 1e-4);
+
+        assert_eq!(Timings::of(&[]), Timings::default());
+    }
+}
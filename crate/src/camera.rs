@@ -1,15 +1,22 @@
-use std::{f32::consts::PI, sync::OnceLock};
+use std::f32::consts::PI;
 
-use cgmath::{perspective, vec3, Deg, InnerSpace, Matrix3, Matrix4, Point3, Rad, Vector3};
+use cgmath::{
+    perspective, vec3, vec4, Deg, InnerSpace, Matrix3, Matrix4, Point3, Quaternion,
+    Rad, Rotation3, SquareMatrix, Vector3, Zero,
+};
 use winit::event::VirtualKeyCode;
 
-use crate::input::KeyboardWatcher;
+use crate::{
+    frustum::Frustum, input::InputSnapshot, layouts::LayoutCache, sync_audit::AuditedQueue,
+};
 
-const ROTATION_SPEED: f32 = 0.03;
-const MOVE_SPEED: f32 = 0.1;
+// Radians per second
+const ROTATION_SPEED: f32 = 1.8;
+// Units per second
+const MOVE_SPEED: f32 = 6.0;
 const HALFPI: f32 = PI / 2.0;
 
-static CAMERA_BIND_GROUP_LAYOUT: OnceLock<wgpu::BindGroupLayout> = OnceLock::new();
+static CAMERA_BIND_GROUP_LAYOUT: LayoutCache = LayoutCache::new();
 
 #[derive(Debug)]
 pub struct Camera {
@@ -22,7 +29,27 @@ pub struct Camera {
     pub znear: f32,
     pub zfar: f32,
 
-    pub buffer: wgpu::Buffer,
+    // Applied after the view, for effects like shaking that shouldn't change
+    // where the camera actually is or which way it's facing
+    view_effect: Matrix4<f32>,
+
+    // Recalculated by refresh, so everything that needs them this frame
+    // gets the same ones without redoing the work
+    matrices: CameraMatrices,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CameraMatrices {
+    view: Matrix4<f32>,
+    projection: Matrix4<f32>,
+    view_proj: Matrix4<f32>,
+    inverse_view_proj: Matrix4<f32>,
+}
+
+/// The camera's uniform buffer, kept separate from the camera itself so the
+/// camera's maths can be used (and tested) without a gpu
+pub struct CameraBinding {
+    buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
 }
 
@@ -34,16 +61,16 @@ pub struct CameraUniform {
 }
 
 #[rustfmt::skip]
-const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
     0.0, 1.0, 0.0, 0.0,
     0.0, 0.0, 0.5, 0.0,
     0.0, 0.0, 0.5, 1.0,
 );
 
-impl Camera {
+impl CameraBinding {
     pub fn bind_group_layout(device: &wgpu::Device) -> &wgpu::BindGroupLayout {
-        CAMERA_BIND_GROUP_LAYOUT.get_or_init(|| {
+        CAMERA_BIND_GROUP_LAYOUT.get(device, |device| {
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("Camera bind group layout"),
                 entries: &[wgpu::BindGroupLayoutEntry {
@@ -62,12 +89,7 @@ impl Camera {
         })
     }
 
-    pub fn new(
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        position: Point3<f32>,
-        aspect: f32,
-    ) -> Self {
+    pub fn new(device: &wgpu::Device, queue: &AuditedQueue, camera: &Camera) -> Self {
         let buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Camera uniform buffer"),
             size: std::mem::size_of::<CameraUniform>() as _,
@@ -77,14 +99,27 @@ impl Camera {
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Camera bind group"),
-            layout: &Self::bind_group_layout(device),
+            layout: Self::bind_group_layout(device),
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
                 resource: buffer.as_entire_binding(),
             }],
         });
 
-        let camera = Self {
+        let binding = Self { buffer, bind_group };
+        binding.write(queue, &camera.to_uniform());
+        binding
+    }
+
+    /// Sends the camera's matrices (from [Camera::to_uniform]) to the gpu
+    pub fn write(&self, queue: &AuditedQueue, uniform: &CameraUniform) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(uniform));
+    }
+}
+
+impl Camera {
+    pub fn new(position: Point3<f32>, aspect: f32) -> Self {
+        let mut camera = Self {
             eye: position,
             h_angle: 0.0,
             v_angle: 0.0,
@@ -93,26 +128,147 @@ impl Camera {
             fovy: 45.0,
             znear: 0.1,
             zfar: 200.0,
-            buffer,
-            bind_group,
+            view_effect: Matrix4::identity(),
+            matrices: CameraMatrices {
+                view: Matrix4::identity(),
+                projection: Matrix4::identity(),
+                view_proj: Matrix4::identity(),
+                inverse_view_proj: Matrix4::identity(),
+            },
         };
 
-        queue.write_buffer(
-            &camera.buffer,
-            0,
-            bytemuck::cast_slice(&[camera.to_uniform()]),
-        );
-
+        camera.matrices = camera.calculate_matrices();
         camera
     }
 
-    pub fn build_camera_matrix(&self) -> Matrix4<f32> {
+    fn calculate_matrices(&self) -> CameraMatrices {
         let direction = self.direction_matrix() * (-1f32 * Vector3::unit_z());
         let target = self.eye + direction;
-        let view = Matrix4::look_at_rh(self.eye, target, self.up);
-        let projection = perspective(Deg(self.fovy), self.aspect, self.znear, self.zfar);
+        let view = self.view_effect * Matrix4::look_at_rh(self.eye, target, self.up);
+        let projection =
+            OPENGL_TO_WGPU_MATRIX * perspective(Deg(self.fovy), self.aspect, self.znear, self.zfar);
+        let view_proj = projection * view;
+
+        CameraMatrices {
+            view,
+            projection,
+            view_proj,
+            inverse_view_proj: view_proj
+                .invert()
+                .expect("camera matrix should be invertible"),
+        }
+    }
+
+    /// Sets a transform to apply on top of the view, in view space. It's
+    /// included in the matrices from the next refresh on, but nothing else
+    /// about the camera (like which way it's facing) takes it into account.
+    pub fn set_view_effect(&mut self, effect: Matrix4<f32>) {
+        self.view_effect = effect;
+    }
+
+    /// Recalculates the camera's matrices after it's been moved or changed,
+    /// returning true if they're different (so the binding needs updating).
+    /// This should be called once a frame, before anything uses the matrices.
+    pub fn refresh(&mut self) -> bool {
+        let matrices = self.calculate_matrices();
+        let changed = matrices != self.matrices;
+        self.matrices = matrices;
+        changed
+    }
+
+    /// The world to view space transform, as of the last refresh
+    pub fn view(&self) -> Matrix4<f32> {
+        self.matrices.view
+    }
+
+    /// The view to clip space transform (already corrected for wgpu's depth
+    /// range), as of the last refresh
+    pub fn projection(&self) -> Matrix4<f32> {
+        self.matrices.projection
+    }
+
+    /// The world to clip space transform, as of the last refresh
+    pub fn view_proj(&self) -> Matrix4<f32> {
+        self.matrices.view_proj
+    }
+
+    /// The planes around what the camera can see, as of the last refresh
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(self.matrices.view_proj)
+    }
+
+    /// The clip to world space transform, as of the last refresh
+    pub fn inverse_view_proj(&self) -> Matrix4<f32> {
+        self.matrices.inverse_view_proj
+    }
+
+    /// Returns a ray (origin and direction) starting at the near plane and going
+    /// through the given point on the screen, in normalised device coordinates
+    /// (so (0, 0) is the centre of the screen).
+    pub fn ray_through(&self, ndc: [f32; 2]) -> (Point3<f32>, Vector3<f32>) {
+        let inverse = self.inverse_view_proj();
+
+        let near = inverse * vec4(ndc[0], ndc[1], 0.0, 1.0);
+        let far = inverse * vec4(ndc[0], ndc[1], 1.0, 1.0);
+        let near = Point3::from_homogeneous(near);
+        let far = Point3::from_homogeneous(far);
+
+        (near, (far - near).normalize())
+    }
+
+    /// Like [Camera::ray_through], but through a pixel (like where the mouse
+    /// is) on a screen of the given size
+    pub fn ray_through_pixel(&self, pixel: [f32; 2], size: [f32; 2]) -> (Point3<f32>, Vector3<f32>) {
+        let x = 2.0 * pixel[0] / size[0] - 1.0;
+        let y = 1.0 - 2.0 * pixel[1] / size[1];
+        self.ray_through([x, y])
+    }
+
+    /// The other way round from [Camera::ray_through_pixel], which pixel a
+    /// point in the world is drawn at. None if it's behind the camera.
+    pub fn to_pixel(&self, position: Point3<f32>, size: [f32; 2]) -> Option<[f32; 2]> {
+        let clip = self.view_proj() * position.to_homogeneous();
+        if clip.w <= 0.0 {
+            return None;
+        }
+
+        Some([
+            (clip.x / clip.w * 0.5 + 0.5) * size[0],
+            (0.5 - clip.y / clip.w * 0.5) * size[1],
+        ])
+    }
+
+    /// Which way the camera is facing, as a rotation from looking down -z
+    pub fn orientation(&self) -> Quaternion<f32> {
+        Quaternion::from_angle_y(Rad(self.h_angle)) * Quaternion::from_angle_x(Rad(self.v_angle))
+    }
+
+    /// The direction the camera is facing
+    pub fn forward(&self) -> Vector3<f32> {
+        self.direction_matrix() * -Vector3::unit_z()
+    }
 
-        OPENGL_TO_WGPU_MATRIX * projection * view
+    /// Turns the camera to face a point
+    pub fn look_at(&mut self, target: Point3<f32>) {
+        self.look_in(target - self.eye);
+    }
+
+    /// Turns the camera to face the same way as a rotation from looking down
+    /// -z (like [Camera::orientation]). The camera's always upright, so any
+    /// roll in it is lost.
+    pub fn set_orientation(&mut self, orientation: Quaternion<f32>) {
+        self.look_in(orientation * -Vector3::unit_z());
+    }
+
+    fn look_in(&mut self, direction: Vector3<f32>) {
+        if direction.magnitude2() < f32::EPSILON {
+            return;
+        }
+
+        // The inverse of direction_matrix applied to -z
+        let direction = direction.normalize();
+        self.v_angle = direction.y.asin().clamp(-HALFPI + 0.05, HALFPI - 0.05);
+        self.h_angle = (-direction.x).atan2(-direction.z).rem_euclid(2.0 * PI);
     }
 
     fn direction_matrix(&self) -> Matrix3<f32> {
@@ -122,69 +278,241 @@ impl Camera {
     pub fn to_uniform(&self) -> CameraUniform {
         CameraUniform {
             position: self.eye.to_homogeneous().into(),
-            matrix: self.build_camera_matrix().into(),
+            matrix: self.view_proj().into(),
         }
     }
 
     // Updates the direction of the camera in response to input.
-    // returns true if the camera changed.
-    pub fn update(&mut self, queue: &wgpu::Queue, keyboard: &KeyboardWatcher) {
-        let mut vdir = 0.0;
-        let mut hdir = 0.0;
-        let mut fdir = 0.0;
-        let mut vrot = 0.0;
-        let mut hrot = 0.0;
-
-        // There has to be a better way to do this
-        if keyboard.pressed(VirtualKeyCode::A) {
-            hdir -= 1.0;
-        }
-        if keyboard.pressed(VirtualKeyCode::D) {
-            hdir += 1.0;
-        }
-        if keyboard.pressed(VirtualKeyCode::W) {
-            fdir -= 1.0;
-        }
-        if keyboard.pressed(VirtualKeyCode::S) {
-            fdir += 1.0;
-        }
-        if keyboard.pressed(VirtualKeyCode::Space) {
-            vdir += 1.0;
-        }
-        if keyboard.pressed(VirtualKeyCode::LShift) {
-            vdir -= 1.0;
-        }
+    // Movement is scaled by how long each key was held since the last update,
+    // so the camera moves at the same speed regardless of frame rate.
+    // The matrices aren't updated until the next refresh.
+    pub fn update(&mut self, input: &InputSnapshot) {
+        self.turn(input);
+        self.eye += self.movement(input);
+    }
 
-        if keyboard.pressed(VirtualKeyCode::Left) {
-            hrot += 1.0;
-        }
-        if keyboard.pressed(VirtualKeyCode::Right) {
-            hrot -= 1.0;
-        }
-        if keyboard.pressed(VirtualKeyCode::Up) {
-            vrot += 1.0;
-        }
-        if keyboard.pressed(VirtualKeyCode::Down) {
-            vrot -= 1.0;
-        }
+    /// Turns the camera with the arrow keys, without moving it
+    pub fn turn(&mut self, input: &InputSnapshot) {
+        let hrot = input.axis(VirtualKeyCode::Left, VirtualKeyCode::Right);
+        let vrot = input.axis(VirtualKeyCode::Up, VirtualKeyCode::Down);
+        self.rotate(hrot, vrot);
+    }
 
+    /// Turns the camera as if the keys for turning it left and up had been
+    /// held for this many seconds (negative for right and down)
+    pub fn rotate(&mut self, hrot: f32, vrot: f32) {
         self.v_angle = (self.v_angle + vrot * ROTATION_SPEED).clamp(-HALFPI + 0.05, HALFPI - 0.05);
         self.h_angle = (self.h_angle + hrot * ROTATION_SPEED) % (2.0 * PI);
+    }
 
-        if hdir != 0.0 || fdir != 0.0 {
-            let xz_dir = self.direction_matrix() * vec3(hdir, 0.0, fdir);
-            let xz_move = vec3(xz_dir.x, 0.0, xz_dir.z).normalize() * MOVE_SPEED;
-            self.eye += xz_move;
-        }
+    /// How far wasd, space and shift would move the camera when flying
+    pub fn movement(&self, input: &InputSnapshot) -> Vector3<f32> {
+        let vdir = input.axis(VirtualKeyCode::Space, VirtualKeyCode::LShift);
+        self.ground_movement(input) + vec3(0.0, vdir * MOVE_SPEED, 0.0)
+    }
 
-        if vdir != 0.0 {
-            self.eye.y += vdir * MOVE_SPEED;
+    /// How far wasd would move the camera, flat along the ground. Walking
+    /// uses this too, it just doesn't always get to go all the way.
+    pub fn ground_movement(&self, input: &InputSnapshot) -> Vector3<f32> {
+        let hdir = input.axis(VirtualKeyCode::D, VirtualKeyCode::A);
+        let fdir = input.axis(VirtualKeyCode::S, VirtualKeyCode::W);
+        if hdir == 0.0 && fdir == 0.0 {
+            return Vector3::zero();
         }
 
-        let did_update = vrot != 0.0 || hrot != 0.0 || hdir != 0.0 || vdir != 0.0 || fdir != 0.0;
+        // Moving diagonally shouldn't be any faster than moving straight
+        let held = hdir.abs().max(fdir.abs());
+        let xz_dir = self.direction_matrix() * vec3(hdir, 0.0, fdir);
+        vec3(xz_dir.x, 0.0, xz_dir.z).normalize() * MOVE_SPEED * held
+    }
+}
 
-        if did_update {
-            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.to_uniform()]));
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use cgmath::{assert_abs_diff_eq, EuclideanSpace, Matrix4, Vector4};
+    use instant::Instant;
+
+    use super::*;
+    use crate::input::KeyboardWatcher;
+
+    // A snapshot where each key was held for the given number of seconds
+    fn held_for(keys: &[(VirtualKeyCode, f32)]) -> InputSnapshot {
+        let start = Instant::now();
+        let mut keyboard = KeyboardWatcher::new();
+
+        for &(keycode, seconds) in keys {
+            keyboard.press(keycode, start);
+            keyboard.release(keycode, start + Duration::from_secs_f32(seconds));
         }
+
+        keyboard.snapshot_at(start + Duration::from_secs(10))
+    }
+
+    fn camera_at_origin() -> Camera {
+        Camera::new(Point3::new(0.0, 0.0, 0.0), 1.0)
+    }
+
+    #[test]
+    fn looking_up_and_down_is_clamped() {
+        let mut camera = camera_at_origin();
+
+        camera.update(&held_for(&[(VirtualKeyCode::Up, 5.0)]));
+        assert_abs_diff_eq!(camera.v_angle, HALFPI - 0.05);
+
+        camera.update(&held_for(&[(VirtualKeyCode::Down, 5.0)]));
+        assert_abs_diff_eq!(camera.v_angle, -HALFPI + 0.05);
+    }
+
+    #[test]
+    fn turning_wraps_around() {
+        let mut camera = camera_at_origin();
+
+        camera.update(&held_for(&[(
+            VirtualKeyCode::Left,
+            2.0 * PI / ROTATION_SPEED + 0.5,
+        )]));
+        assert!((0.0..2.0 * PI).contains(&camera.h_angle));
+        assert_abs_diff_eq!(camera.h_angle, 0.5 * ROTATION_SPEED, epsilon = 1.0e-3);
+    }
+
+    #[test]
+    fn moving_diagonally_isnt_faster() {
+        let mut straight = camera_at_origin();
+        straight.update(&held_for(&[(VirtualKeyCode::W, 1.0)]));
+
+        let mut diagonal = camera_at_origin();
+        diagonal.update(&held_for(&[
+            (VirtualKeyCode::W, 1.0),
+            (VirtualKeyCode::D, 1.0),
+        ]));
+
+        assert_abs_diff_eq!(
+            straight.eye,
+            Point3::new(0.0, 0.0, -MOVE_SPEED),
+            epsilon = 1.0e-4
+        );
+        assert_abs_diff_eq!(
+            diagonal.eye.to_vec().magnitude(),
+            MOVE_SPEED,
+            epsilon = 1.0e-4
+        );
+        assert!(diagonal.eye.x > 0.0 && diagonal.eye.z < 0.0);
+    }
+
+    #[test]
+    fn looking_down_doesnt_slow_movement() {
+        let mut camera = camera_at_origin();
+        camera.v_angle = -1.0;
+        camera.update(&held_for(&[(VirtualKeyCode::W, 1.0)]));
+
+        assert_abs_diff_eq!(
+            camera.eye,
+            Point3::new(0.0, 0.0, -MOVE_SPEED),
+            epsilon = 1.0e-4
+        );
+    }
+
+    #[test]
+    fn matrices_match_known_values() {
+        let mut camera = camera_at_origin();
+        camera.refresh();
+
+        // Looking down -z from the origin, so the view doesn't change anything
+        assert_abs_diff_eq!(camera.view(), Matrix4::identity(), epsilon = 1.0e-6);
+
+        let f = 1.0 / (22.5f32).to_radians().tan();
+        assert_abs_diff_eq!(camera.projection().x.x, f, epsilon = 1.0e-5);
+        assert_abs_diff_eq!(camera.projection().y.y, f, epsilon = 1.0e-5);
+
+        let to_ndc = |point: Vector4<f32>| {
+            let clip = camera.view_proj() * point;
+            clip.truncate() / clip.w
+        };
+
+        // The near and far planes end up at wgpu's 0 to 1 depth range
+        assert_abs_diff_eq!(
+            to_ndc(Vector4::new(0.0, 0.0, -camera.znear, 1.0)).z,
+            0.0,
+            epsilon = 1.0e-5
+        );
+        assert_abs_diff_eq!(
+            to_ndc(Vector4::new(0.0, 0.0, -camera.zfar, 1.0)).z,
+            1.0,
+            epsilon = 1.0e-4
+        );
+
+        // And the top right edge of the view is the top right of the screen
+        let edge = 1.0 / f;
+        let corner = to_ndc(Vector4::new(edge, edge, -1.0, 1.0));
+        assert_abs_diff_eq!(corner.x, 1.0, epsilon = 1.0e-5);
+        assert_abs_diff_eq!(corner.y, 1.0, epsilon = 1.0e-5);
+
+        assert_abs_diff_eq!(
+            camera.inverse_view_proj() * camera.view_proj(),
+            Matrix4::identity(),
+            epsilon = 1.0e-4
+        );
+    }
+
+    #[test]
+    fn pixels_are_in_screen_space() {
+        let mut camera = camera_at_origin();
+        camera.refresh();
+        let size = [200.0, 100.0];
+
+        let (_, centre) = camera.ray_through_pixel([100.0, 50.0], size);
+        assert_abs_diff_eq!(centre, camera.forward(), epsilon = 1.0e-5);
+
+        // Down the screen is down in the world
+        let (_, bottom_left) = camera.ray_through_pixel([0.0, 100.0], size);
+        assert!(bottom_left.x < 0.0 && bottom_left.y < 0.0);
+
+        // And back again
+        let pixel = camera
+            .to_pixel(Point3::from_vec(bottom_left * 10.0), size)
+            .unwrap();
+        assert_abs_diff_eq!(pixel[0], 0.0, epsilon = 1.0e-3);
+        assert_abs_diff_eq!(pixel[1], 100.0, epsilon = 1.0e-3);
+        assert!(camera
+            .to_pixel(Point3::from_vec(-camera.forward()), size)
+            .is_none());
+    }
+
+    #[test]
+    fn refresh_only_reports_changes() {
+        let mut camera = camera_at_origin();
+        assert!(!camera.refresh());
+
+        camera.eye.x += 1.0;
+        assert!(camera.refresh());
+        assert!(!camera.refresh());
+    }
+
+    #[test]
+    fn look_at_faces_the_target() {
+        let mut camera = Camera::new(Point3::new(1.0, 2.0, 3.0), 1.0);
+        let target = Point3::new(-4.0, 0.0, 7.0);
+        camera.look_at(target);
+
+        assert_abs_diff_eq!(
+            camera.forward(),
+            (target - camera.eye).normalize(),
+            epsilon = 1.0e-5
+        );
+    }
+
+    #[test]
+    fn orientation_round_trips() {
+        let mut camera = camera_at_origin();
+        camera.h_angle = 2.0;
+        camera.v_angle = -0.4;
+        let orientation = camera.orientation();
+
+        let mut other = camera_at_origin();
+        other.set_orientation(orientation);
+        assert_abs_diff_eq!(other.h_angle, 2.0, epsilon = 1.0e-5);
+        assert_abs_diff_eq!(other.v_angle, -0.4, epsilon = 1.0e-5);
     }
 }
@@ -0,0 +1,466 @@
+//! The different ways the camera can be moved around. Flying is the default,
+//! but it can also walk on the ground, circle around the statue, or follow
+//! the newest rei down as it falls. Each of these is a [CameraController],
+//! and they're switched between with the number keys or from the ui.
+//!
+//! Adding another one means giving it a [CameraMode], and making one of it
+//! in [CameraMode::controller].
+
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3, VectorSpace, Zero};
+use serde::{Deserialize, Serialize};
+use winit::event::VirtualKeyCode;
+
+use crate::{
+    camera::Camera,
+    input::{Command, InputSnapshot},
+    locale::Strings,
+    physics::PhysicsSimulation,
+    walker::Walker,
+};
+
+// How close and how far the orbiting cameras can get from what they're
+// circling around
+const MIN_DISTANCE: f32 = 2.0;
+const MAX_DISTANCE: f32 = 60.0;
+// How much closer each line the mouse wheel's scrolled gets. Holding w does
+// the same as scrolling this many lines a second.
+const ZOOM_PER_LINE: f32 = 0.9;
+const ZOOM_LINES_PER_SECOND: f32 = 8.0;
+// How quickly the following camera catches up with its rei, in how much of
+// the way it gets there each second. It doesn't snap straight to it, since
+// the newest rei keeps changing as more are spawned.
+const FOLLOW_CATCH_UP: f32 = 0.95;
+
+// The longest any of the smoothing can take, in seconds. Any more and it
+// feels like the camera's stuck in treacle.
+const MAX_SMOOTHING_TIME: f32 = 2.0;
+
+/// How the flying camera eases in and out of moving and turning, instead of
+/// starting and stopping dead. They're all in seconds, roughly how long it
+/// takes to get most of the way there, and 0 turns that bit of it off.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraSmoothing {
+    /// How long it takes to get up to speed once a key's pressed
+    pub acceleration_time: f32,
+    /// How long it takes to come to a stop once they're all let go
+    pub damping_time: f32,
+    /// How long it takes to finish turning after the arrow keys say to
+    pub turn_time: f32,
+}
+
+impl Default for CameraSmoothing {
+    fn default() -> Self {
+        Self {
+            acceleration_time: 0.12,
+            damping_time: 0.2,
+            turn_time: 0.06,
+        }
+    }
+}
+
+impl CameraSmoothing {
+    /// None of it, so the camera moves exactly as the keys say
+    pub const OFF: Self = Self {
+        acceleration_time: 0.0,
+        damping_time: 0.0,
+        turn_time: 0.0,
+    };
+
+    // Settings files get edited by hand, so these could be anything
+    pub fn sanitised(self) -> Self {
+        let fix = |time: f32| {
+            if time.is_finite() {
+                time.clamp(0.0, MAX_SMOOTHING_TIME)
+            } else {
+                0.0
+            }
+        };
+
+        Self {
+            acceleration_time: fix(self.acceleration_time),
+            damping_time: fix(self.damping_time),
+            turn_time: fix(self.turn_time),
+        }
+    }
+}
+
+// How much of the way something smoothed over `time` seconds gets in
+// `delta_time` seconds. It's exponential, so it's the same whatever the frame
+// rate.
+fn catch_up(time: f32, delta_time: f32) -> f32 {
+    if time <= 0.0 {
+        1.0
+    } else {
+        1.0 - (-delta_time / time).exp()
+    }
+}
+
+/// Something that moves the camera around each frame
+pub trait CameraController {
+    /// Moves the camera in response to input. This gets real time rather
+    /// than simulation time, so it still works while it's paused.
+    fn update(
+        &mut self,
+        camera: &mut Camera,
+        input: &InputSnapshot,
+        physics: &PhysicsSimulation,
+        delta_time: f32,
+    );
+}
+
+/// Which controller's moving the camera
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CameraMode {
+    #[default]
+    Fly,
+    Walk,
+    Orbit,
+    Follow,
+}
+
+impl CameraMode {
+    pub const ALL: [CameraMode; 4] = [
+        CameraMode::Fly,
+        CameraMode::Walk,
+        CameraMode::Orbit,
+        CameraMode::Follow,
+    ];
+
+    /// A controller for this mode, carrying on from wherever the camera is
+    pub fn controller(
+        self,
+        camera: &mut Camera,
+        physics: &PhysicsSimulation,
+        smoothing: CameraSmoothing,
+    ) -> Box<dyn CameraController> {
+        match self {
+            CameraMode::Fly => Box::new(Fly::new(smoothing)),
+            CameraMode::Walk => Box::new(Walker::default()),
+            CameraMode::Orbit => Box::new(Orbit::new(camera, physics.statue_position().into())),
+            CameraMode::Follow => {
+                let target = physics
+                    .newest_rei_position()
+                    .unwrap_or(physics.statue_position());
+                Box::new(Follow {
+                    orbit: Orbit::new(camera, target.into()),
+                })
+            }
+        }
+    }
+
+    /// The command that switches to it
+    pub fn command(self) -> Command {
+        match self {
+            CameraMode::Fly => Command::FlyCamera,
+            CameraMode::Walk => Command::WalkCamera,
+            CameraMode::Orbit => Command::OrbitCamera,
+            CameraMode::Follow => Command::FollowCamera,
+        }
+    }
+
+    pub fn name(self, strings: &Strings) -> &str {
+        strings.get(match self {
+            CameraMode::Fly => "camera.fly",
+            CameraMode::Walk => "camera.walk",
+            CameraMode::Orbit => "camera.orbit",
+            CameraMode::Follow => "camera.follow",
+        })
+    }
+
+    /// How to move the camera around in this mode
+    pub fn controls(self, strings: &Strings) -> &str {
+        strings.get(match self {
+            CameraMode::Fly => "controls.flying",
+            CameraMode::Walk => "controls.walking",
+            CameraMode::Orbit | CameraMode::Follow => "controls.orbiting",
+        })
+    }
+}
+
+/// Flying around freely, with nothing in the way. It speeds up, slows down
+/// and turns smoothly, unless the smoothing's turned off.
+pub struct Fly {
+    smoothing: CameraSmoothing,
+    // Units per second
+    velocity: Vector3<f32>,
+    // How much turning the arrow keys have asked for that the camera hasn't
+    // done yet, in seconds of holding them (like Camera::rotate takes)
+    turn_left: f32,
+    turn_up: f32,
+}
+
+impl Fly {
+    /// Starts off still, with nothing left to turn
+    pub fn new(smoothing: CameraSmoothing) -> Self {
+        Self {
+            smoothing,
+            velocity: Vector3::zero(),
+            turn_left: 0.0,
+            turn_up: 0.0,
+        }
+    }
+}
+
+impl CameraController for Fly {
+    fn update(
+        &mut self,
+        camera: &mut Camera,
+        input: &InputSnapshot,
+        _: &PhysicsSimulation,
+        delta_time: f32,
+    ) {
+        // Nothing's passed, so there's nothing to smooth over
+        if delta_time <= 0.0 {
+            camera.update(input);
+            return;
+        }
+
+        // Turning goes part of the way towards wherever the keys have turned
+        // to, and the rest is left for later frames
+        self.turn_left += input.axis(VirtualKeyCode::Left, VirtualKeyCode::Right);
+        self.turn_up += input.axis(VirtualKeyCode::Up, VirtualKeyCode::Down);
+
+        let from = camera.orientation();
+        camera.rotate(self.turn_left, self.turn_up);
+        let to = camera.orientation();
+
+        let amount = catch_up(self.smoothing.turn_time, delta_time);
+        camera.set_orientation(from.slerp(to, amount));
+        self.turn_left *= 1.0 - amount;
+        self.turn_up *= 1.0 - amount;
+
+        // Moving heads towards the speed the keys want, speeding up or
+        // slowing down depending on whether any are held
+        let wanted = camera.movement(input) / delta_time;
+        let time = if wanted == Vector3::zero() {
+            self.smoothing.damping_time
+        } else {
+            self.smoothing.acceleration_time
+        };
+
+        self.velocity = self.velocity.lerp(wanted, catch_up(time, delta_time));
+        camera.eye += self.velocity * delta_time;
+    }
+}
+
+impl CameraController for Walker {
+    fn update(
+        &mut self,
+        camera: &mut Camera,
+        input: &InputSnapshot,
+        physics: &PhysicsSimulation,
+        delta_time: f32,
+    ) {
+        Walker::update(self, camera, input, physics, delta_time);
+    }
+}
+
+/// Circling around a point, always looking at it. The arrow keys (and a and
+/// d) go around it, and w and s or the mouse wheel get closer and further.
+pub struct Orbit {
+    pub target: Point3<f32>,
+    distance: f32,
+}
+
+impl Orbit {
+    /// Starts circling around `target` from wherever the camera is, turning
+    /// it to face the target
+    pub fn new(camera: &mut Camera, target: Point3<f32>) -> Self {
+        camera.look_at(target);
+        Self {
+            target,
+            distance: (camera.eye - target)
+                .magnitude()
+                .clamp(MIN_DISTANCE, MAX_DISTANCE),
+        }
+    }
+}
+
+impl CameraController for Orbit {
+    fn update(
+        &mut self,
+        camera: &mut Camera,
+        input: &InputSnapshot,
+        _: &PhysicsSimulation,
+        _: f32,
+    ) {
+        camera.turn(input);
+        camera.rotate(input.axis(VirtualKeyCode::A, VirtualKeyCode::D), 0.0);
+
+        let lines = input.scrolled()
+            + input.axis(VirtualKeyCode::W, VirtualKeyCode::S) * ZOOM_LINES_PER_SECOND;
+        self.distance =
+            (self.distance * ZOOM_PER_LINE.powf(lines)).clamp(MIN_DISTANCE, MAX_DISTANCE);
+
+        camera.eye = self.target - camera.forward() * self.distance;
+    }
+}
+
+/// Circling around the newest rei, following it as it falls. Once it's gone
+/// it stays where it last was, until there's another.
+pub struct Follow {
+    orbit: Orbit,
+}
+
+impl CameraController for Follow {
+    fn update(
+        &mut self,
+        camera: &mut Camera,
+        input: &InputSnapshot,
+        physics: &PhysicsSimulation,
+        delta_time: f32,
+    ) {
+        if let Some(rei) = physics.newest_rei_position() {
+            let amount = 1.0 - (1.0 - FOLLOW_CATCH_UP).powf(delta_time);
+            let target = self.orbit.target.to_vec().lerp(rei.into(), amount);
+            self.orbit.target = Point3::from_vec(target);
+        }
+
+        self.orbit.update(camera, input, physics, delta_time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{input::KeyboardWatcher, model::ImportTransform};
+
+    // Holds a key for the first `seconds` of a `delta_time` long frame
+    fn held_for(keycode: VirtualKeyCode, seconds: f32, delta_time: f32) -> InputSnapshot {
+        let mut keyboard = KeyboardWatcher::new();
+        let start = instant::Instant::now();
+        keyboard.press(keycode, start);
+        keyboard.release(keycode, start + std::time::Duration::from_secs_f32(seconds));
+        keyboard.snapshot_at(start + std::time::Duration::from_secs_f32(delta_time))
+    }
+
+    #[test]
+    fn flying_glides_to_a_stop() {
+        let physics = PhysicsSimulation::new(Vec::new(), ImportTransform::default(), 0);
+        let mut camera = Camera::new((0.0, 0.0, 0.0).into(), 1.0);
+        let mut fly = Fly::new(CameraSmoothing::default());
+
+        let frame = 1.0 / 60.0;
+        fly.update(
+            &mut camera,
+            &held_for(VirtualKeyCode::W, frame, frame),
+            &physics,
+            frame,
+        );
+        let pressed = camera.eye;
+        assert!(pressed.z < 0.0);
+
+        // It keeps going after the key's let go, but gradually less each frame
+        let mut step = f32::INFINITY;
+        for _ in 0..10 {
+            let before = camera.eye;
+            fly.update(&mut camera, &InputSnapshot::default(), &physics, frame);
+            let moved = (camera.eye - before).magnitude();
+            assert!(moved > 0.0 && moved < step);
+            step = moved;
+        }
+
+        for _ in 0..600 {
+            fly.update(&mut camera, &InputSnapshot::default(), &physics, frame);
+        }
+        assert!(fly.velocity.magnitude() < 1e-4);
+    }
+
+    #[test]
+    fn flying_without_smoothing_follows_the_keys() {
+        let physics = PhysicsSimulation::new(Vec::new(), ImportTransform::default(), 0);
+        let mut smoothed = Camera::new((0.0, 0.0, 0.0).into(), 1.0);
+        let mut unsmoothed = Camera::new((0.0, 0.0, 0.0).into(), 1.0);
+        let mut fly = Fly::new(CameraSmoothing::OFF);
+
+        let input = held_for(VirtualKeyCode::Left, 0.25, 0.5);
+        fly.update(&mut smoothed, &input, &physics, 0.5);
+        unsmoothed.update(&input);
+        assert!((smoothed.forward() - unsmoothed.forward()).magnitude() < 1e-4);
+
+        let input = held_for(VirtualKeyCode::W, 0.25, 0.5);
+        fly.update(&mut smoothed, &input, &physics, 0.5);
+        unsmoothed.update(&input);
+        assert!((smoothed.eye - unsmoothed.eye).magnitude() < 1e-4);
+
+        fly.update(&mut smoothed, &InputSnapshot::default(), &physics, 0.5);
+        assert!((smoothed.eye - unsmoothed.eye).magnitude() < 1e-4);
+    }
+
+    #[test]
+    fn smoothed_turning_catches_up() {
+        let physics = PhysicsSimulation::new(Vec::new(), ImportTransform::default(), 0);
+        let mut camera = Camera::new((0.0, 0.0, 0.0).into(), 1.0);
+        let mut target = Camera::new((0.0, 0.0, 0.0).into(), 1.0);
+        let mut fly = Fly::new(CameraSmoothing::default());
+
+        let input = held_for(VirtualKeyCode::Left, 0.2, 0.2);
+        target.update(&input);
+        fly.update(&mut camera, &input, &physics, 0.2);
+        let partway = (camera.forward() - target.forward()).magnitude();
+        assert!(partway > 1e-3);
+
+        for _ in 0..60 {
+            fly.update(&mut camera, &InputSnapshot::default(), &physics, 1.0 / 60.0);
+        }
+        assert!((camera.forward() - target.forward()).magnitude() < partway * 1e-3);
+    }
+
+    #[test]
+    fn latching_splits_a_frame_without_changing_it() {
+        let physics = PhysicsSimulation::new(Vec::new(), ImportTransform::default(), 0);
+        let mut whole = Camera::new((0.0, 0.0, 0.0).into(), 1.0);
+        let mut split = Camera::new((0.0, 0.0, 0.0).into(), 1.0);
+        let mut whole_fly = Fly::new(CameraSmoothing::OFF);
+        let mut split_fly = Fly::new(CameraSmoothing::OFF);
+
+        let start = instant::Instant::now();
+        let at = |seconds: f32| start + std::time::Duration::from_secs_f32(seconds);
+        let mut whole_keys = KeyboardWatcher::new();
+        let mut split_keys = KeyboardWatcher::new();
+        for keyboard in [&mut whole_keys, &mut split_keys] {
+            keyboard.press(VirtualKeyCode::W, start);
+            keyboard.press(VirtualKeyCode::Left, start);
+        }
+
+        // Once at the update and again just before it's sent off, which should
+        // end up the same as all at once
+        whole_fly.update(&mut whole, &whole_keys.snapshot_at(at(0.03)), &physics, 0.03);
+        split_fly.update(&mut split, &split_keys.snapshot_at(at(0.01)), &physics, 0.01);
+        split_fly.update(&mut split, &split_keys.snapshot_at(at(0.03)), &physics, 0.02);
+
+        assert!((whole.forward() - split.forward()).magnitude() < 1e-4);
+        // The split one turns partway through, so it goes a slightly
+        // different way, but just as far
+        let distance = |camera: &Camera| camera.eye.to_vec().magnitude();
+        assert!((distance(&whole) - distance(&split)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn orbit_circles_and_zooms() {
+        let physics = PhysicsSimulation::new(Vec::new(), ImportTransform::default(), 0);
+        let mut camera = Camera::new((8.0, 5.0, 8.0).into(), 1.0);
+        let target = Point3::new(0.0, 1.0, 0.0);
+        let mut orbit = Orbit::new(&mut camera, target);
+        let distance = orbit.distance;
+
+        let mut keyboard = KeyboardWatcher::new();
+        let start = instant::Instant::now();
+        keyboard.press(VirtualKeyCode::A, start);
+        keyboard.release(VirtualKeyCode::A, start + std::time::Duration::from_secs(1));
+        let input = keyboard.snapshot_at(start);
+        let eye = camera.eye;
+        orbit.update(&mut camera, &input, &physics, 1.0);
+
+        // It's gone round, but not any closer
+        assert!((camera.eye - eye).magnitude() > 1.0);
+        assert!(((camera.eye - target).magnitude() - distance).abs() < 1e-3);
+        assert!((camera.forward() - (target - camera.eye).normalize()).magnitude() < 1e-3);
+
+        keyboard.scroll(3.0);
+        orbit.update(&mut camera, &keyboard.snapshot(), &physics, 0.0);
+        let zoomed = distance * ZOOM_PER_LINE.powi(3);
+        assert!(((camera.eye - target).magnitude() - zoomed).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,146 @@
+//! Shake and roll for the camera, for things that should be felt more than
+//! seen. They're applied on top of the camera's view (see
+//! [Camera::set_view_effect](crate::camera::Camera::set_view_effect)), so the
+//! camera itself still moves and turns the same as ever.
+
+use cgmath::{Matrix4, Rad, SquareMatrix};
+
+use crate::locale::Strings;
+
+// Impacts further than this from the camera don't shake it at all
+const SHAKE_RADIUS: f32 = 20.0;
+// How much shake the hardest impact right next to the camera adds, out of 1
+const SHAKE_PER_IMPACT: f32 = 0.4;
+// How much shake wears off per second
+const SHAKE_DECAY: f32 = 1.5;
+// How far the camera turns at the most violent shake, in radians
+const MAX_SHAKE_YAW: f32 = 0.02;
+const MAX_SHAKE_PITCH: f32 = 0.02;
+const MAX_SHAKE_ROLL: f32 = 0.03;
+// Roughly how many times a second the shake changes direction
+const SHAKE_FREQUENCY: f32 = 15.0;
+
+/// Shake from nearby impacts, and a roll that choreography camera cuts can
+/// set (or that can be set from the ui)
+#[derive(Debug, Clone)]
+pub struct CameraEffects {
+    pub shake_enabled: bool,
+    /// Scales how far the camera shakes, 1 is normal
+    pub shake_strength: f32,
+    /// How far the camera's tilted, in radians (clockwise is positive)
+    pub roll: f32,
+    // From 0 to 1, the shake itself goes up with the square of this so small
+    // bumps are barely noticeable
+    trauma: f32,
+    time: f32,
+}
+
+impl Default for CameraEffects {
+    fn default() -> Self {
+        Self {
+            shake_enabled: true,
+            shake_strength: 1.0,
+            roll: 0.0,
+            trauma: 0.0,
+            time: 0.0,
+        }
+    }
+}
+
+impl CameraEffects {
+    /// Shakes the camera for something hitting something else. `strength`
+    /// goes from 0 (nothing) to 1 (the hardest hit there is).
+    pub fn impact(&mut self, strength: f32, distance: f32) {
+        if !self.shake_enabled {
+            return;
+        }
+
+        let closeness = (1.0 - distance / SHAKE_RADIUS).max(0.0);
+        self.trauma = (self.trauma + SHAKE_PER_IMPACT * strength.min(1.0) * closeness).min(1.0);
+    }
+
+    /// Lets the shake wear off. This should get real time, not simulation
+    /// time, so it still settles down while the simulation's paused.
+    pub fn update(&mut self, delta_time: f32) {
+        self.time += delta_time;
+        self.trauma = (self.trauma - SHAKE_DECAY * delta_time).max(0.0);
+
+        if !self.shake_enabled {
+            self.trauma = 0.0;
+        }
+    }
+
+    /// Stops any shaking straight away
+    pub fn settle(&mut self) {
+        self.trauma = 0.0;
+    }
+
+    /// The transform to apply after the camera's view, in view space
+    pub fn view_transform(&self) -> Matrix4<f32> {
+        let shake = self.trauma * self.trauma * self.shake_strength;
+        if shake == 0.0 && self.roll == 0.0 {
+            return Matrix4::identity();
+        }
+
+        let t = self.time * SHAKE_FREQUENCY;
+        let yaw = MAX_SHAKE_YAW * shake * wobble(t, 0.0);
+        let pitch = MAX_SHAKE_PITCH * shake * wobble(t, 10.0);
+        let roll = self.roll + MAX_SHAKE_ROLL * shake * wobble(t, 20.0);
+
+        // The view looks down -z, so turning the world the other way around
+        // it tilts the camera clockwise
+        Matrix4::from_angle_z(Rad(-roll))
+            * Matrix4::from_angle_x(Rad(pitch))
+            * Matrix4::from_angle_y(Rad(yaw))
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, strings: &Strings) {
+        ui.checkbox(&mut self.shake_enabled, strings.get("camera.shake"));
+
+        ui.add_enabled_ui(self.shake_enabled, |ui| {
+            ui.horizontal(|ui| {
+                ui.label(strings.get("camera.shake_strength"));
+                ui.add(egui::Slider::new(&mut self.shake_strength, 0.0..=3.0));
+            });
+        });
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("camera.roll"));
+            ui.drag_angle(&mut self.roll);
+            if ui.button(strings.get("camera.level")).clicked() {
+                self.roll = 0.0;
+            }
+        });
+    }
+}
+
+// Smooth noise from -1 to 1. A few sine waves at awkward ratios don't repeat
+// often enough for anyone to notice.
+fn wobble(t: f32, offset: f32) -> f32 {
+    let t = t + offset;
+    0.5 * (t * 1.0).sin() + 0.3 * (t * 2.13 + 1.7).sin() + 0.2 * (t * 3.71 + 4.1).sin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shake_wears_off() {
+        let mut effects = CameraEffects::default();
+        assert_eq!(effects.view_transform(), Matrix4::identity());
+
+        effects.impact(1.0, 0.0);
+        effects.update(0.05);
+        assert_ne!(effects.view_transform(), Matrix4::identity());
+
+        // Far away impacts don't count
+        effects.settle();
+        effects.impact(1.0, SHAKE_RADIUS * 2.0);
+        assert_eq!(effects.view_transform(), Matrix4::identity());
+
+        effects.impact(1.0, 0.0);
+        effects.update(10.0);
+        assert_eq!(effects.view_transform(), Matrix4::identity());
+    }
+}
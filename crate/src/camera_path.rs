@@ -0,0 +1,197 @@
+//! The choreography's camera cuts on their own, as a json file that can be
+//! taken to another machine and dropped into whatever show's playing there.
+//! Times are written in seconds, since the other show might have a
+//! different tempo.
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(target_arch = "wasm32")]
+use crate::files;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::storage;
+use crate::{
+    choreography::{Action, Choreography, Cue, CueTime},
+    error::{Error, Result},
+};
+
+/// The version written into new files. It goes up whenever the format changes
+/// in a way older versions couldn't read.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// What the file's called if nobody says otherwise. Natively it goes in
+/// [crate::storage::Location::Data] unless it's given a path.
+pub const DEFAULT_FILENAME: &str = "camera_path.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CameraPath {
+    pub version: u32,
+    pub keyframes: Vec<Keyframe>,
+}
+
+/// One camera cut
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// Seconds into the show
+    pub time: f32,
+    pub position: [f32; 3],
+    pub look_at: [f32; 3],
+    /// In degrees clockwise
+    #[serde(default)]
+    pub roll: f32,
+}
+
+// Just enough of a file to tell which version it is, so a newer one gets a
+// better error than whatever doesn't parse in it
+#[derive(Deserialize)]
+struct Versioned {
+    version: u32,
+}
+
+impl CameraPath {
+    /// Every camera cut in a choreography, in order
+    pub fn from_choreography(choreography: &Choreography) -> Self {
+        let mut keyframes: Vec<Keyframe> = choreography
+            .cues
+            .iter()
+            .filter_map(|cue| match cue.action {
+                Action::Camera {
+                    position,
+                    look_at,
+                    roll,
+                } => Some(Keyframe {
+                    time: choreography.seconds(cue.at),
+                    position,
+                    look_at,
+                    roll,
+                }),
+                _ => None,
+            })
+            .collect();
+
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        Self {
+            version: FORMAT_VERSION,
+            keyframes,
+        }
+    }
+
+    /// Swaps a choreography's camera cuts for these ones, leaving its other
+    /// cues alone
+    pub fn apply(&self, choreography: &mut Choreography) {
+        choreography
+            .cues
+            .retain(|cue| !matches!(cue.action, Action::Camera { .. }));
+
+        choreography
+            .cues
+            .extend(self.keyframes.iter().map(|keyframe| Cue {
+                at: CueTime::Time(keyframe.time),
+                action: Action::Camera {
+                    position: keyframe.position,
+                    look_at: keyframe.look_at,
+                    roll: keyframe.roll,
+                },
+            }));
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Reads a path written by this or an older version. `filename` is just
+    /// for the errors.
+    pub fn from_json(filename: &str, json: &str) -> Result<Self> {
+        let Versioned { version } =
+            serde_json::from_str(json).map_err(|e| Error::decode(filename, e))?;
+
+        if version > FORMAT_VERSION {
+            return Err(Error::decode(
+                filename,
+                format!("it's version {version}, and only up to {FORMAT_VERSION} can be read"),
+            ));
+        }
+
+        serde_json::from_str(json).map_err(|e| Error::decode(filename, e))
+    }
+
+    /// Has the browser download the path as a file
+    #[cfg(target_arch = "wasm32")]
+    pub fn download(&self, filename: &str) -> Result<()> {
+        let data = self.to_json().map_err(|e| Error::save(filename, e))?;
+        files::download(filename, &data)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, filename: &str) -> Result<()> {
+        let data = self.to_json().map_err(|e| Error::save(filename, e))?;
+        storage::write_file(filename.as_ref(), &data)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(filename: &str) -> Result<Self> {
+        let data = std::fs::read_to_string(filename).map_err(|e| Error::from_io(filename, e))?;
+        Self::from_json(filename, &data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_another_show() {
+        let mut choreography = Choreography {
+            bpm: 60.0,
+            ..Default::default()
+        };
+        choreography.cues.push(Cue {
+            at: CueTime::Beat(4.0),
+            action: Action::Camera {
+                position: [1.0, 2.0, 3.0],
+                look_at: [0.0; 3],
+                roll: 10.0,
+            },
+        });
+        choreography.cues.push(Cue {
+            at: CueTime::Time(1.0),
+            action: Action::Gravity {
+                gravity: [0.0, -1.0, 0.0],
+            },
+        });
+
+        let path = CameraPath::from_choreography(&choreography);
+        let json = path.to_json().unwrap();
+        let read = CameraPath::from_json("path.json", &json).unwrap();
+        assert_eq!(read, path);
+        assert_eq!(read.keyframes[0].time, 4.0);
+
+        // The other show's camera cuts go, but its other cues stay
+        let mut other = Choreography::default();
+        other.cues.push(Cue {
+            at: CueTime::Time(9.0),
+            action: Action::Camera {
+                position: [5.0; 3],
+                look_at: [0.0; 3],
+                roll: 0.0,
+            },
+        });
+        choreography
+            .cues
+            .retain(|cue| !matches!(cue.action, Action::Camera { .. }));
+        other.cues.extend(choreography.cues);
+        read.apply(&mut other);
+
+        assert_eq!(CameraPath::from_choreography(&other), path);
+        assert_eq!(other.cues.len(), 2);
+    }
+
+    #[test]
+    fn newer_versions_are_turned_down() {
+        let json = r#"{ "version": 2, "keyframes": [], "splines": true }"#;
+        assert!(CameraPath::from_json("path.json", json).is_err());
+
+        let json = r#"{ "version": 1, "keyframes": [] }"#;
+        assert!(CameraPath::from_json("path.json", json).is_ok());
+    }
+}
@@ -0,0 +1,175 @@
+use std::{cell::Cell, rc::Rc};
+
+use wasm_bindgen::{prelude::*, JsCast};
+use winit::{dpi::PhysicalSize, platform::web::WindowExtWebSys, window::Window};
+
+// The element on the page that the canvas goes in
+const CONTAINER_ID: &str = "wasm-example";
+
+/// What the event loop should do about the canvas this frame
+pub enum CanvasStatus {
+    /// Carry on as normal
+    Ready,
+    /// The canvas isn't on the page (or the page can't be seen), so there's
+    /// no point drawing anything
+    Hidden,
+    /// The canvas has come back or changed size, so the surface and anything
+    /// that depends on its size needs setting up again before drawing
+    Refresh(PhysicalSize<u32>),
+    /// The browser took the webgl context away, along with everything on the
+    /// gpu. Nothing can be drawn until it gives it back.
+    Lost,
+    /// The browser's given the context back, but not what was on the gpu, so
+    /// the app has to start over
+    Restored,
+}
+
+/// Keeps an eye on the canvas and the page it's on.
+///
+/// Single page apps can take the canvas out of the page and put it back later,
+/// the page can be frozen in the back/forward cache and brought back, and the
+/// browser can take the webgl context away when it's short on memory. None of
+/// that comes through winit, so the event listeners here catch it instead.
+/// They're removed again when this is dropped.
+pub struct CanvasWatcher {
+    canvas: web_sys::HtmlCanvasElement,
+    // Set by the listeners, and picked up next time the status is checked
+    changed: Rc<Cell<bool>>,
+    lost: Rc<Cell<bool>>,
+    restored: Rc<Cell<bool>>,
+    // Whether the canvas was on the page last time it was checked
+    attached: bool,
+    _listeners: Vec<Listener>,
+}
+
+// An event listener that takes itself back off when it's dropped
+struct Listener {
+    target: web_sys::EventTarget,
+    event: &'static str,
+    closure: Closure<dyn FnMut(web_sys::Event)>,
+}
+
+impl Listener {
+    fn new(
+        target: &web_sys::EventTarget,
+        event: &'static str,
+        callback: impl FnMut(web_sys::Event) + 'static,
+    ) -> Option<Self> {
+        let closure = Closure::<dyn FnMut(_)>::new(callback);
+        target
+            .add_event_listener_with_callback(event, closure.as_ref().unchecked_ref())
+            .ok()?;
+
+        Some(Self {
+            target: target.clone(),
+            event,
+            closure,
+        })
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        let _ = self
+            .target
+            .remove_event_listener_with_callback(self.event, self.closure.as_ref().unchecked_ref());
+    }
+}
+
+impl CanvasWatcher {
+    /// Puts the window's canvas on the page and starts watching it
+    pub fn mount(window: &Window) -> Option<Self> {
+        let canvas = window.canvas();
+        canvas.set_id("render-canvas");
+        container()?.append_child(&canvas).ok()?;
+
+        let changed = Rc::new(Cell::new(false));
+        let lost = Rc::new(Cell::new(false));
+        let restored = Rc::new(Cell::new(false));
+
+        let page = web_sys::window()?;
+        let flag = |flag: &Rc<Cell<bool>>| {
+            let flag = flag.clone();
+            move |_| flag.set(true)
+        };
+
+        let listeners = vec![
+            Listener::new(&page, "resize", flag(&changed))?,
+            // Fired when the page comes back from the back/forward cache too
+            Listener::new(&page, "pageshow", flag(&changed))?,
+            // The browser only gives the context back if it's told it's
+            // wanted, by cancelling this
+            Listener::new(&canvas, "webglcontextlost", {
+                let lost = lost.clone();
+                move |event| {
+                    event.prevent_default();
+                    lost.set(true);
+                }
+            })?,
+            Listener::new(&canvas, "webglcontextrestored", flag(&restored))?,
+        ];
+
+        Some(Self {
+            canvas,
+            changed,
+            lost,
+            restored,
+            attached: true,
+            _listeners: listeners,
+        })
+    }
+
+    pub fn status(&mut self) -> CanvasStatus {
+        if self.restored.replace(false) {
+            self.lost.set(false);
+            return CanvasStatus::Restored;
+        }
+        if self.lost.get() {
+            return CanvasStatus::Lost;
+        }
+
+        // If the page has a new container (like after a single page app has
+        // navigated away and back) the canvas goes in that
+        if !self.canvas.is_connected() {
+            if let Some(container) = container() {
+                let _ = container.append_child(&self.canvas);
+            }
+        }
+
+        let attached = self.canvas.is_connected();
+        let hidden = web_sys::window()
+            .and_then(|page| page.document())
+            .is_some_and(|document| document.hidden());
+
+        if !attached || hidden {
+            self.attached = attached;
+            return CanvasStatus::Hidden;
+        }
+
+        if !std::mem::replace(&mut self.attached, true) || self.changed.replace(false) {
+            return CanvasStatus::Refresh(page_size());
+        }
+
+        CanvasStatus::Ready
+    }
+}
+
+/// The element the canvas goes in, if it's on the page
+pub fn container() -> Option<web_sys::Element> {
+    web_sys::window()?
+        .document()?
+        .get_element_by_id(CONTAINER_ID)
+}
+
+/// The size of the page, which the canvas fills
+pub fn page_size() -> PhysicalSize<u32> {
+    let page = web_sys::window().unwrap();
+    let dimension = |value: Result<JsValue, JsValue>| {
+        value.ok().and_then(|value| value.as_f64()).unwrap_or(0.0) as u32
+    };
+
+    PhysicalSize::new(
+        dimension(page.inner_width()),
+        dimension(page.inner_height()),
+    )
+}
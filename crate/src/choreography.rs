@@ -0,0 +1,411 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{locale::Strings, physics};
+
+/// A timeline of things that happen during the show, like bursts of reis or
+/// the light changing colour. Cues can be keyed to seconds or to beats of the
+/// music, and the same timeline always plays out the same way.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Choreography {
+    /// Tempo of the music, for cues keyed to beats
+    pub bpm: f32,
+    /// Seconds into the music that the first beat lands on
+    pub offset: f32,
+    /// If set, the timeline follows the music's playback position (and waits
+    /// for the music to start). Otherwise it has its own clock.
+    pub follow_music: bool,
+    pub cues: Vec<Cue>,
+}
+
+impl Default for Choreography {
+    fn default() -> Self {
+        Self {
+            bpm: 120.0,
+            offset: 0.0,
+            follow_music: true,
+            cues: Vec::new(),
+        }
+    }
+}
+
+impl Choreography {
+    /// The time a cue happens at, in seconds
+    pub fn seconds(&self, at: CueTime) -> f32 {
+        match at {
+            CueTime::Time(seconds) => seconds,
+            CueTime::Beat(beat) => self.offset + beat * 60.0 / self.bpm.max(1.0),
+        }
+    }
+}
+
+/// Something that happens at a certain point in the show
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cue {
+    #[serde(flatten)]
+    pub at: CueTime,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+/// When a cue happens, written as `time = seconds` or `beat = n`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CueTime {
+    Time(f32),
+    Beat(f32),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Spawns a bunch of reis from an emitter at once
+    Burst {
+        emitter: String,
+        count: usize,
+    },
+    Gravity {
+        gravity: [f32; 3],
+    },
+    LightColour {
+        colour: [f32; 3],
+    },
+    /// Moves the camera straight to a new spot
+    Camera {
+        position: [f32; 3],
+        look_at: [f32; 3],
+        /// How far the camera's tilted, in degrees clockwise
+        #[serde(default)]
+        roll: f32,
+    },
+}
+
+impl Action {
+    fn name<'a>(&self, strings: &'a Strings) -> &'a str {
+        strings.get(match self {
+            Action::Burst { .. } => "choreography.burst",
+            Action::Gravity { .. } => "choreography.gravity",
+            Action::LightColour { .. } => "choreography.light_colour",
+            Action::Camera { .. } => "choreography.camera_cut",
+        })
+    }
+
+    // One of each kind of action, for picking from in the ui
+    fn defaults() -> [Action; 4] {
+        [
+            Action::Burst {
+                emitter: String::new(),
+                count: 20,
+            },
+            Action::Gravity {
+                gravity: physics::GRAVITY.into(),
+            },
+            Action::LightColour {
+                colour: [1.0, 1.0, 1.0],
+            },
+            Action::Camera {
+                position: [0.0, 5.0, 10.0],
+                look_at: [0.0, 0.0, 0.0],
+                roll: 0.0,
+            },
+        ]
+    }
+}
+
+/// Plays through a choreography, working out which cues are due each frame
+pub struct Sequencer {
+    pub choreography: Choreography,
+    // Only advanced when the choreography isn't following the music
+    clock: f64,
+    // Everything up to (and including) this time has already happened
+    last_time: Option<f32>,
+}
+
+impl Sequencer {
+    pub fn new(choreography: Choreography) -> Self {
+        Self {
+            choreography,
+            clock: 0.0,
+            last_time: None,
+        }
+    }
+
+    /// Starts the show again from the beginning. If it's following the
+    /// music, the music has to be rewound too.
+    pub fn restart(&mut self) {
+        self.clock = 0.0;
+        self.last_time = None;
+    }
+
+    /// Advances the show, returning the actions of every cue that's come up
+    /// since the last update, in order. `music_position` is how far into the
+    /// music it is, if it's playing.
+    pub fn update(&mut self, delta_time: f32, music_position: Option<f64>) -> Vec<Action> {
+        let time = if self.choreography.follow_music {
+            match music_position {
+                Some(position) => position as f32,
+                None => return Vec::new(),
+            }
+        } else {
+            self.clock += delta_time as f64;
+            self.clock as f32
+        };
+
+        // Going backwards means the music has looped or been rewound
+        let after = self.last_time.filter(|&last| last <= time);
+        self.last_time = Some(time);
+
+        let mut due: Vec<(f32, &Action)> = self
+            .choreography
+            .cues
+            .iter()
+            .map(|cue| (self.choreography.seconds(cue.at), &cue.action))
+            .filter(|&(at, _)| after.is_none_or(|after| at > after) && at <= time)
+            .collect();
+
+        due.sort_by(|a, b| a.0.total_cmp(&b.0));
+        due.into_iter().map(|(_, action)| action.clone()).collect()
+    }
+
+    /// The timeline editor. `emitters` are the names of the emitters that
+    /// bursts can come from. Returns true if the show should be restarted.
+    pub fn ui(&mut self, ui: &mut egui::Ui, emitters: &[String], strings: &Strings) -> bool {
+        let mut restart = false;
+
+        ui.horizontal(|ui| {
+            match self.last_time {
+                Some(time) => ui
+                    .label(strings.format("choreography.time", &[("time", &format!("{time:.1}"))])),
+                None => ui.label(strings.get("choreography.waiting")),
+            };
+
+            restart = ui.button(strings.get("choreography.restart")).clicked();
+        });
+
+        ui.checkbox(
+            &mut self.choreography.follow_music,
+            strings.get("choreography.follow_music"),
+        );
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("choreography.bpm"));
+            ui.add(
+                egui::DragValue::new(&mut self.choreography.bpm)
+                    .clamp_range(1.0..=400.0)
+                    .speed(0.1),
+            );
+            ui.label(strings.get("choreography.first_beat"));
+            ui.add(egui::DragValue::new(&mut self.choreography.offset).speed(0.01));
+        });
+
+        let mut remove = None;
+
+        for (i, cue) in self.choreography.cues.iter_mut().enumerate() {
+            ui.push_id(i, |ui| {
+                ui.separator();
+                cue_ui(ui, cue, emitters, strings);
+
+                if ui.button(strings.get("choreography.remove")).clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+
+        if let Some(i) = remove {
+            self.choreography.cues.remove(i);
+        }
+
+        ui.separator();
+        if ui.button(strings.get("choreography.add_cue")).clicked() {
+            let at = self.last_time.unwrap_or(0.0);
+            self.choreography.cues.push(Cue {
+                at: CueTime::Time((at * 10.0).round() / 10.0),
+                action: Action::LightColour {
+                    colour: [1.0, 1.0, 1.0],
+                },
+            });
+        }
+
+        restart
+    }
+}
+
+fn cue_ui(ui: &mut egui::Ui, cue: &mut Cue, emitters: &[String], strings: &Strings) {
+    ui.horizontal(|ui| {
+        let (mut value, mut beats) = match cue.at {
+            CueTime::Time(seconds) => (seconds, false),
+            CueTime::Beat(beat) => (beat, true),
+        };
+
+        ui.add(
+            egui::DragValue::new(&mut value)
+                .clamp_range(0.0..=f32::INFINITY)
+                .speed(0.05),
+        );
+        ui.radio_value(&mut beats, false, strings.get("choreography.seconds"));
+        ui.radio_value(&mut beats, true, strings.get("choreography.beats"));
+
+        cue.at = if beats {
+            CueTime::Beat(value)
+        } else {
+            CueTime::Time(value)
+        };
+    });
+
+    egui::ComboBox::from_id_source("action")
+        .selected_text(cue.action.name(strings))
+        .show_ui(ui, |ui| {
+            for action in Action::defaults() {
+                let selected =
+                    std::mem::discriminant(&action) == std::mem::discriminant(&cue.action);
+                let name = action.name(strings);
+                if ui.selectable_label(selected, name).clicked() && !selected {
+                    cue.action = action;
+                }
+            }
+        });
+
+    match &mut cue.action {
+        Action::Burst { emitter, count } => {
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("emitter")
+                    .selected_text(emitter.as_str())
+                    .show_ui(ui, |ui| {
+                        for name in emitters {
+                            ui.selectable_value(emitter, name.clone(), name);
+                        }
+                    });
+
+                ui.label(strings.get("choreography.count"));
+                ui.add(egui::DragValue::new(count).clamp_range(1..=physics::NUM_REIS));
+            });
+        }
+
+        Action::Gravity { gravity } => {
+            ui.horizontal(|ui| {
+                ui.label(strings.get("choreography.gravity_value"));
+                for value in gravity.iter_mut() {
+                    ui.add(egui::DragValue::new(value).speed(0.1));
+                }
+            });
+        }
+
+        Action::LightColour { colour } => {
+            ui.horizontal(|ui| {
+                ui.label(strings.get("choreography.colour"));
+                ui.color_edit_button_rgb(colour);
+            });
+        }
+
+        Action::Camera {
+            position,
+            look_at,
+            roll,
+        } => {
+            ui.horizontal(|ui| {
+                ui.label(strings.get("choreography.position"));
+                for value in position.iter_mut() {
+                    ui.add(egui::DragValue::new(value).speed(0.1));
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label(strings.get("choreography.looking_at"));
+                for value in look_at.iter_mut() {
+                    ui.add(egui::DragValue::new(value).speed(0.1));
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label(strings.get("choreography.roll"));
+                ui.add(
+                    egui::DragValue::new(roll)
+                        .speed(0.5)
+                        .clamp_range(-180.0..=180.0)
+                        .suffix("°"),
+                );
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gravity(y: f32) -> Action {
+        Action::Gravity {
+            gravity: [0.0, y, 0.0],
+        }
+    }
+
+    // Cues at 1, 2 and 3 seconds (the middle one keyed to a beat at 60bpm),
+    // listed out of order
+    fn sequencer(follow_music: bool) -> Sequencer {
+        Sequencer::new(Choreography {
+            bpm: 60.0,
+            offset: 0.0,
+            follow_music,
+            cues: vec![
+                Cue {
+                    at: CueTime::Time(3.0),
+                    action: gravity(-3.0),
+                },
+                Cue {
+                    at: CueTime::Time(1.0),
+                    action: gravity(-1.0),
+                },
+                Cue {
+                    at: CueTime::Beat(2.0),
+                    action: gravity(-2.0),
+                },
+            ],
+        })
+    }
+
+    #[test]
+    fn cues_come_out_in_time_order() {
+        let mut sequencer = sequencer(false);
+        assert_eq!(
+            sequencer.update(5.0, None),
+            vec![gravity(-1.0), gravity(-2.0), gravity(-3.0)]
+        );
+    }
+
+    #[test]
+    fn each_cue_fires_once() {
+        let mut sequencer = sequencer(false);
+        let mut fired = Vec::new();
+        for _ in 0..100 {
+            fired.extend(sequencer.update(0.05, None));
+        }
+
+        assert_eq!(fired, vec![gravity(-1.0), gravity(-2.0), gravity(-3.0)]);
+        assert!(sequencer.update(0.05, None).is_empty());
+    }
+
+    #[test]
+    fn following_the_music_waits_for_it_and_seeks_with_it() {
+        let mut sequencer = sequencer(true);
+        assert!(sequencer.update(5.0, None).is_empty());
+
+        assert_eq!(sequencer.update(0.0, Some(1.5)), vec![gravity(-1.0)]);
+        // Skipping ahead still fires what was skipped over
+        assert_eq!(
+            sequencer.update(0.0, Some(3.5)),
+            vec![gravity(-2.0), gravity(-3.0)]
+        );
+        // Going back means it's looped, so it all happens again
+        assert_eq!(sequencer.update(0.0, Some(1.5)), vec![gravity(-1.0)]);
+        assert_eq!(sequencer.update(0.0, Some(2.5)), vec![gravity(-2.0)]);
+    }
+
+    #[test]
+    fn restarting_plays_it_again() {
+        let mut sequencer = sequencer(false);
+        sequencer.update(5.0, None);
+        sequencer.restart();
+
+        assert_eq!(sequencer.update(1.5, None), vec![gravity(-1.0)]);
+    }
+}
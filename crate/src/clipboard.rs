@@ -0,0 +1,28 @@
+use cfg_if::cfg_if;
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+extern "C" {
+    // Gives back a promise, but there's nothing to do once it's done
+    #[wasm_bindgen(catch, js_namespace = ["navigator", "clipboard"], js_name = writeText)]
+    fn write_text(text: &str) -> Result<JsValue, JsValue>;
+}
+
+/// Puts some text on the clipboard. egui only does this natively, so on the
+/// web it goes through the browser's clipboard api instead.
+pub fn copy(ctx: &egui::Context, text: String) {
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            let _ = ctx;
+            // Only there on https pages (or localhost)
+            if let Err(e) = write_text(&text) {
+                log::warn!("Couldn't copy to the clipboard: {e:?}");
+            }
+        } else {
+            ctx.output_mut(|output| output.copied_text = text);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::ops::Range;
+
+use crate::{emitter::Emitter, layouts::LayoutCache, physics::Cluster, sync_audit::AuditedQueue};
+
+static CLUSTER_BIND_GROUP_LAYOUT: LayoutCache = LayoutCache::new();
+
+const UNIFORM_SIZE: u64 = std::mem::size_of::<EmitterUniform>() as u64;
+
+// Only the tint's in here. An emitter's reis can't be drawn at a different
+// scale or with a different model than the rest, since both change the shape
+// they collide with, and the shadow map and the culling draw every rei the
+// same way without looking at this. The emitters' scales are in the
+// instances, where the colliders get theirs from too.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct EmitterUniform {
+    tint: [f32; 3],
+    _padding: f32,
+}
+
+/// The bits of an emitter that decide how its cluster is drawn, which is
+/// all that needs copying out of it each frame
+#[derive(Debug, Clone, Copy)]
+pub struct EmitterLook {
+    pub tint: [f32; 3],
+    pub visible: bool,
+}
+
+impl EmitterLook {
+    pub fn of(emitter: &Emitter) -> Self {
+        Self {
+            tint: emitter.tint,
+            visible: emitter.visible,
+        }
+    }
+}
+
+impl EmitterUniform {
+    // Used for the statue, which doesn't have an emitter (or a tint of its own)
+    const PLAIN: Self = Self {
+        tint: [1.0, 1.0, 1.0],
+        _padding: 0.0,
+    };
+}
+
+/// Draws the reis one emitter at a time, so anything about how an emitter's
+/// reis look can go in a uniform instead of in every instance.
+///
+/// The bind group also has the light and the shader tweaks in it, since the
+/// model pipeline doesn't have any bind groups left over for them to go in by
+/// themselves.
+pub struct EmitterClusters {
+    buffer: wgpu::Buffer,
+    // Distance between each cluster's uniform in the buffer
+    stride: u64,
+    // How many clusters the buffer has room for
+    capacity: usize,
+    bind_group: wgpu::BindGroup,
+    // The offset of each visible cluster's uniform, which cluster it is, and
+    // its instances
+    draws: Vec<(u32, usize, Range<u32>)>,
+    visible_instances: u32,
+    // What's in the buffer, so it's only written when a cluster changes
+    uploaded: Vec<u8>,
+}
+
+impl EmitterClusters {
+    pub fn bind_group_layout(device: &wgpu::Device) -> &'static wgpu::BindGroupLayout {
+        CLUSTER_BIND_GROUP_LAYOUT.get(device, |device| {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("cluster bind group layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: wgpu::BufferSize::new(UNIFORM_SIZE),
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            })
+        })
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        light_buffer: &wgpu::Buffer,
+        tweak_buffer: &wgpu::Buffer,
+    ) -> Self {
+        let stride = UNIFORM_SIZE.max(device.limits().min_uniform_buffer_offset_alignment as u64);
+        // Enough for the statue and a few emitters, it grows if there are more
+        let capacity = 8;
+        let buffer = create_buffer(device, stride, capacity);
+        let bind_group = create_bind_group(device, light_buffer, tweak_buffer, &buffer);
+
+        Self {
+            buffer,
+            stride,
+            capacity,
+            bind_group,
+            draws: Vec::new(),
+            visible_instances: 0,
+            uploaded: Vec::new(),
+        }
+    }
+
+    /// Uploads each cluster's uniform and works out which clusters get drawn
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &AuditedQueue,
+        light_buffer: &wgpu::Buffer,
+        tweak_buffer: &wgpu::Buffer,
+        emitters: &[EmitterLook],
+        clusters: &[Cluster],
+    ) {
+        if clusters.len() > self.capacity {
+            self.capacity = clusters.len().next_power_of_two();
+            self.buffer = create_buffer(device, self.stride, self.capacity);
+            self.bind_group = create_bind_group(device, light_buffer, tweak_buffer, &self.buffer);
+            self.uploaded.clear();
+        }
+
+        let mut data = vec![0; self.stride as usize * clusters.len()];
+        self.draws.clear();
+        self.visible_instances = 0;
+
+        for (i, cluster) in clusters.iter().enumerate() {
+            let emitter = cluster.emitter.and_then(|index| emitters.get(index));
+            let tint = cluster.tint.or(emitter.map(|emitter| emitter.tint));
+            let uniform = tint.map_or(EmitterUniform::PLAIN, |tint| EmitterUniform {
+                tint,
+                _padding: 0.0,
+            });
+
+            let offset = i * self.stride as usize;
+            data[offset..offset + UNIFORM_SIZE as usize]
+                .copy_from_slice(bytemuck::bytes_of(&uniform));
+
+            let visible = emitter.is_none_or(|emitter| emitter.visible);
+            if visible && !cluster.instances.is_empty() {
+                self.draws.push((offset as u32, i, cluster.instances.clone()));
+                self.visible_instances += cluster.instances.len() as u32;
+            }
+        }
+
+        if !data.is_empty() && data != self.uploaded {
+            queue.write_buffer(&self.buffer, 0, &data);
+            self.uploaded = data;
+        }
+    }
+
+    /// How many instances are in visible clusters. These are always the first
+    /// ones in the instance buffer, so anything that draws every visible
+    /// instance at once (like the shadow map) can draw this many.
+    pub fn visible_instances(&self) -> u32 {
+        self.visible_instances
+    }
+
+    /// Sets the bind group up for drawing something that isn't from an
+    /// emitter, so it's drawn without a tint (like the statue is)
+    pub fn bind_plain<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, group: u32) {
+        render_pass.set_bind_group(group, &self.bind_group, &[0]);
+    }
+
+    /// Calls `draw` with the instances of each visible cluster, after setting
+    /// its bind group
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        group: u32,
+        mut draw: impl FnMut(&mut wgpu::RenderPass<'a>, Range<u32>),
+    ) {
+        for (offset, _, instances) in self.draws.iter() {
+            render_pass.set_bind_group(group, &self.bind_group, &[*offset]);
+            draw(render_pass, instances.clone());
+        }
+    }
+
+    /// Like [EmitterClusters::draw], but `draw` also gets which cluster it
+    /// is, for when where its instances are drawn from depends on that
+    pub fn draw_indexed<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        group: u32,
+        mut draw: impl FnMut(&mut wgpu::RenderPass<'a>, usize, Range<u32>),
+    ) {
+        for (offset, cluster, instances) in self.draws.iter() {
+            render_pass.set_bind_group(group, &self.bind_group, &[*offset]);
+            draw(render_pass, *cluster, instances.clone());
+        }
+    }
+}
+
+fn create_buffer(device: &wgpu::Device, stride: u64, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Cluster uniform buffer"),
+        size: stride * capacity as u64,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    light_buffer: &wgpu::Buffer,
+    tweak_buffer: &wgpu::Buffer,
+    buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("cluster bind group"),
+        layout: EmitterClusters::bind_group_layout(device),
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: light_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(UNIFORM_SIZE),
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: tweak_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
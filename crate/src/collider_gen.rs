@@ -0,0 +1,651 @@
+//! Colliders made from a model's triangles, rather than written by hand to
+//! fit it, and a report on how well they fit and what they cost. Which kind
+//! gets made is up to the scene, per model, so it's possible to trade how
+//! closely the reis pile up against how many of them the simulation can
+//! keep up with.
+
+use std::collections::HashMap;
+
+use instant::Instant;
+use rapier3d::na;
+use rapier3d::parry::query::{self, PointQuery};
+use rapier3d::parry::transformation::vhacd::VHACDParameters;
+use rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::locale::Strings;
+use crate::model::CollisionMesh;
+
+/// The shapes a collider's made of, each with where it is in the model
+pub type Parts = Vec<(Isometry<Real>, SharedShape)>;
+
+// How many contact checks each piece gets timed over
+const COST_SAMPLES: usize = 64;
+
+/// What kind of collider to give a model
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColliderShape {
+    /// The shapes written for the model in physics.rs
+    #[default]
+    HandMade,
+    /// One capsule around the whole model, along its longest side
+    Capsule,
+    /// One convex hull around the whole model
+    ConvexHull,
+    /// The model split up into convex pieces
+    Decomposed,
+}
+
+impl ColliderShape {
+    pub const ALL: [ColliderShape; 4] = [
+        ColliderShape::HandMade,
+        ColliderShape::Capsule,
+        ColliderShape::ConvexHull,
+        ColliderShape::Decomposed,
+    ];
+
+    pub fn name<'a>(&self, strings: &'a Strings) -> &'a str {
+        match self {
+            ColliderShape::HandMade => strings.get("colliders.hand_made"),
+            ColliderShape::Capsule => strings.get("colliders.capsule"),
+            ColliderShape::ConvexHull => strings.get("colliders.convex_hull"),
+            ColliderShape::Decomposed => strings.get("colliders.decomposed"),
+        }
+    }
+}
+
+/// How a model's collider gets made, from the scene's `colliders` table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColliderSettings {
+    pub shape: ColliderShape,
+    /// The most pieces a decomposed collider gets split into
+    pub max_pieces: u32,
+}
+
+impl Default for ColliderSettings {
+    fn default() -> Self {
+        Self {
+            shape: ColliderShape::HandMade,
+            max_pieces: 8,
+        }
+    }
+}
+
+impl ColliderSettings {
+    /// The settings for a model, by the name the scene gives it
+    pub fn for_model(colliders: &HashMap<String, ColliderSettings>, name: &str) -> Self {
+        colliders.get(name).copied().unwrap_or_default()
+    }
+
+    /// Returns true if anything was changed
+    pub fn ui(&mut self, ui: &mut egui::Ui, strings: &Strings) -> bool {
+        let before = *self;
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("colliders.shape"));
+            for shape in ColliderShape::ALL {
+                ui.radio_value(&mut self.shape, shape, shape.name(strings));
+            }
+        });
+
+        ui.add_enabled_ui(self.shape == ColliderShape::Decomposed, |ui| {
+            ui.horizontal(|ui| {
+                ui.label(strings.get("colliders.max_pieces"));
+                ui.add(egui::DragValue::new(&mut self.max_pieces).clamp_range(1..=64));
+            });
+        });
+
+        *self != before
+    }
+}
+
+/// Makes the parts of a collider for a mesh. Hand-made colliders aren't made
+/// from the mesh, so they give None, as does a mesh there's no hull around
+/// (one that's empty or flat).
+pub fn generate(mesh: &CollisionMesh, settings: &ColliderSettings) -> Option<Parts> {
+    let points = points(mesh);
+
+    match settings.shape {
+        ColliderShape::HandMade => None,
+        ColliderShape::Capsule => fit_capsule(&points),
+        ColliderShape::ConvexHull => {
+            let hull = SharedShape::convex_hull(&points)?;
+            Some(vec![(Isometry::identity(), hull)])
+        }
+        ColliderShape::Decomposed => {
+            let params = VHACDParameters {
+                max_convex_hulls: settings.max_pieces.max(1),
+                ..Default::default()
+            };
+            let decomposed =
+                SharedShape::convex_decomposition_with_params(&points, &mesh.triangles, &params);
+            let parts = decomposed.as_compound()?.shapes().to_vec();
+            let parts = merge_pieces(parts, settings.max_pieces as usize);
+            (!parts.is_empty()).then_some(parts)
+        }
+    }
+}
+
+/// A collider made in the background, and how well it fits. Hand-made
+/// colliders aren't made, just measured, so there aren't any parts.
+pub struct Generated {
+    pub parts: Option<Parts>,
+    pub report: ColliderReport,
+}
+
+/// Makes a collider for the mesh and measures it, or if it's meant to be
+/// hand-made (or can't be made) measures the hand-made one
+pub fn generate_and_measure(
+    mesh: &CollisionMesh,
+    settings: &ColliderSettings,
+    hand_made: &Parts,
+) -> Generated {
+    let parts = generate(mesh, settings);
+    let report = ColliderReport::new(mesh, parts.as_ref().unwrap_or(hand_made));
+    Generated { parts, report }
+}
+
+// The decomposition treats its limit as more of a guideline, so this takes
+// the smallest piece and wraps it up with whichever's closest, until there
+// are few enough
+fn merge_pieces(mut parts: Parts, max_pieces: usize) -> Parts {
+    let volume = |(_, shape): &(Isometry<Real>, SharedShape)| shape.mass_properties(1.0).mass();
+    let centre =
+        |(position, shape): &(Isometry<Real>, SharedShape)| shape.compute_aabb(position).center();
+    let corners = |(position, shape): &(Isometry<Real>, SharedShape)| {
+        let points = shape
+            .as_convex_polyhedron()
+            .map_or(&[][..], |hull| hull.points());
+        points
+            .iter()
+            .map(|point| position * point)
+            .collect::<Vec<_>>()
+    };
+
+    while parts.len() > max_pieces.max(1) {
+        let smallest = (0..parts.len())
+            .min_by(|&a, &b| volume(&parts[a]).total_cmp(&volume(&parts[b])))
+            .unwrap_or_default();
+        let piece = parts.swap_remove(smallest);
+
+        let distance = |i: usize| na::distance(&centre(&parts[i]), &centre(&piece));
+        let closest = (0..parts.len())
+            .min_by(|&a, &b| distance(a).total_cmp(&distance(b)))
+            .unwrap_or_default();
+
+        let mut points = corners(&parts[closest]);
+        points.extend(corners(&piece));
+        match SharedShape::convex_hull(&points) {
+            Some(merged) => parts[closest] = (Isometry::identity(), merged),
+            None => {
+                parts.push(piece);
+                break;
+            }
+        }
+    }
+
+    parts
+}
+
+fn points(mesh: &CollisionMesh) -> Vec<Point<Real>> {
+    mesh.positions
+        .iter()
+        .map(|&position| position.into())
+        .collect()
+}
+
+// The corners of the box around the points
+fn bounds(points: &[Point<Real>]) -> Option<(Point<Real>, Point<Real>)> {
+    let first = points.first()?;
+    Some(points.iter().fold((*first, *first), |(min, max), point| {
+        (min.inf(point), max.sup(point))
+    }))
+}
+
+// A capsule down the middle of the longest side of the mesh's bounding box,
+// just wide and long enough to have every vertex inside it
+fn fit_capsule(points: &[Point<Real>]) -> Option<Parts> {
+    let (min, max) = bounds(points)?;
+    let axis = (max - min).imax();
+    let centre = na::center(&min, &max);
+
+    // How far each point is along the axis from the middle, and out from it
+    let offsets = points
+        .iter()
+        .map(|point| {
+            let mut offset = point - centre;
+            let along = offset[axis].abs();
+            offset[axis] = 0.0;
+            (along, offset.norm())
+        })
+        .collect::<Vec<_>>();
+
+    let radius = offsets.iter().map(|&(_, out)| out).fold(0.0, f32::max);
+    if radius <= 0.0 {
+        return None;
+    }
+
+    // The round ends have to reach the points out near the edge too, not
+    // just the ones on the axis
+    let mut half_height = Vector::zeros();
+    half_height[axis] = offsets
+        .iter()
+        .map(|&(along, out)| along - (radius * radius - out * out).max(0.0).sqrt())
+        .fold(0.0, f32::max);
+
+    let capsule = SharedShape::capsule(centre - half_height, centre + half_height, radius);
+    Some(vec![(Isometry::identity(), capsule)])
+}
+
+/// The parts made bigger or smaller, as if the model they fit was scaled by
+/// `scale`. Only the kinds of shapes colliders get made of can be scaled,
+/// anything else is left the size it is.
+pub fn scale_parts(parts: &Parts, scale: f32) -> Parts {
+    parts
+        .iter()
+        .map(|(position, shape)| {
+            let mut position = *position;
+            position.translation.vector *= scale;
+            (
+                position,
+                scale_shape(shape, scale).unwrap_or_else(|| shape.clone()),
+            )
+        })
+        .collect()
+}
+
+fn scale_shape(shape: &SharedShape, scale: f32) -> Option<SharedShape> {
+    let shape = match shape.as_typed_shape() {
+        TypedShape::Ball(ball) => SharedShape::ball(ball.radius * scale),
+        TypedShape::Cuboid(cuboid) => {
+            let half = cuboid.half_extents * scale;
+            SharedShape::cuboid(half.x, half.y, half.z)
+        }
+        TypedShape::Capsule(capsule) => SharedShape::capsule(
+            capsule.segment.a * scale,
+            capsule.segment.b * scale,
+            capsule.radius * scale,
+        ),
+        TypedShape::RoundCylinder(cylinder) => SharedShape::round_cylinder(
+            cylinder.inner_shape.half_height * scale,
+            cylinder.inner_shape.radius * scale,
+            cylinder.border_radius * scale,
+        ),
+        TypedShape::ConvexPolyhedron(hull) => {
+            SharedShape::new(hull.clone().scaled(&Vector::repeat(scale))?)
+        }
+        _ => return None,
+    };
+
+    Some(shape)
+}
+
+/// How well a collider fits its model, and how much work it is for the
+/// simulation
+#[derive(Debug, Clone, Default)]
+pub struct ColliderReport {
+    pub pieces: Vec<PieceReport>,
+    /// How far the collider's surface is from the model's, at most and on
+    /// average. It's in model units, so compare it to `size`.
+    pub max_error: f32,
+    pub mean_error: f32,
+    /// How far the model pokes out of the collider, at most
+    pub missed: f32,
+    /// The longest side of the model's bounding box
+    pub size: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct PieceReport {
+    /// The name of the kind of shape it is, in the lang files
+    pub kind: &'static str,
+    /// How many corners the piece has, for hulls
+    pub vertices: usize,
+    /// How long a contact check against the piece takes, in microseconds
+    pub cost: f32,
+}
+
+impl ColliderReport {
+    /// Measures a collider against the mesh it's meant to fit
+    pub fn new(mesh: &CollisionMesh, parts: &Parts) -> Self {
+        let points = points(mesh);
+        let model = (!mesh.triangles.is_empty())
+            .then(|| TriMesh::new(points.clone(), mesh.triangles.clone()));
+
+        // Places on the collider's surface, leaving out any that are inside
+        // another of its pieces (where they overlap)
+        let surface = parts
+            .iter()
+            .enumerate()
+            .flat_map(|(i, part)| {
+                surface_points(part)
+                    .into_iter()
+                    .map(move |point| (i, point))
+            })
+            .filter(|(i, point)| {
+                parts.iter().enumerate().all(|(j, (position, shape))| {
+                    j == *i || shape.distance_to_point(position, point, true) > 1.0e-4
+                })
+            })
+            .map(|(_, point)| point);
+
+        let errors = model
+            .map(|model| {
+                surface
+                    .map(|point| {
+                        model
+                            .distance_to_point(&Isometry::identity(), &point, false)
+                            .abs()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let max_error = errors.iter().copied().fold(0.0, f32::max);
+        let mean_error = errors.iter().sum::<f32>() / errors.len().max(1) as f32;
+
+        let missed = points
+            .iter()
+            .map(|point| {
+                parts
+                    .iter()
+                    .map(|(position, shape)| shape.distance_to_point(position, point, true))
+                    .fold(f32::INFINITY, f32::min)
+            })
+            .fold(0.0, f32::max);
+
+        let size = bounds(&points).map_or(0.0, |(min, max)| (max - min).max());
+
+        let pieces = parts
+            .iter()
+            .map(|(position, shape)| PieceReport {
+                kind: kind(shape),
+                vertices: shape
+                    .as_convex_polyhedron()
+                    .map_or(0, |hull| hull.points().len()),
+                cost: cost(position, shape),
+            })
+            .collect();
+
+        Self {
+            pieces,
+            max_error,
+            mean_error,
+            missed,
+            size,
+        }
+    }
+
+    /// The cost of every piece together, in microseconds
+    pub fn total_cost(&self) -> f32 {
+        self.pieces.iter().map(|piece| piece.cost).sum()
+    }
+
+    pub fn ui(&self, ui: &mut egui::Ui, strings: &Strings) {
+        ui.label(strings.format("colliders.pieces", &[("count", &self.pieces.len())]));
+        ui.label(strings.format(
+            "colliders.error",
+            &[
+                ("max", &format!("{:.3}", self.max_error)),
+                ("mean", &format!("{:.3}", self.mean_error)),
+                ("size", &format!("{:.2}", self.size)),
+            ],
+        ))
+        .on_hover_text(strings.get("colliders.error_hint"));
+        ui.label(strings.format(
+            "colliders.missed",
+            &[("missed", &format!("{:.3}", self.missed))],
+        ))
+        .on_hover_text(strings.get("colliders.missed_hint"));
+        ui.label(strings.format(
+            "colliders.cost",
+            &[("micros", &format!("{:.2}", self.total_cost()))],
+        ))
+        .on_hover_text(strings.get("colliders.cost_hint"));
+
+        egui::Grid::new("collider pieces")
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label(strings.get("colliders.piece"));
+                ui.label(strings.get("colliders.vertices"));
+                ui.label(strings.get("colliders.piece_cost"));
+                ui.end_row();
+
+                for piece in self.pieces.iter() {
+                    ui.label(strings.get(piece.kind));
+                    if piece.vertices > 0 {
+                        ui.label(piece.vertices.to_string());
+                    } else {
+                        ui.label("-");
+                    }
+                    ui.label(format!("{:.2}", piece.cost));
+                    ui.end_row();
+                }
+            });
+    }
+}
+
+// Points spread over a piece's surface: the corners of its triangles, and
+// the middles of them and their sides (a hull's corners are all on the model,
+// it's the faces between them that aren't). Rounded cylinders are measured
+// as the plain cylinder that goes out to the edge of the rounding.
+fn surface_points((position, shape): &(Isometry<Real>, SharedShape)) -> Vec<Point<Real>> {
+    const SUBDIVISIONS: u32 = 12;
+
+    let (points, triangles) = match shape.as_typed_shape() {
+        TypedShape::Ball(ball) => ball.to_trimesh(SUBDIVISIONS, SUBDIVISIONS),
+        TypedShape::Cuboid(cuboid) => cuboid.to_trimesh(),
+        TypedShape::Capsule(capsule) => capsule.to_trimesh(SUBDIVISIONS, SUBDIVISIONS),
+        TypedShape::RoundCylinder(cylinder) => {
+            let border = cylinder.border_radius;
+            let inner = cylinder.inner_shape;
+            Cylinder::new(inner.half_height + border, inner.radius + border)
+                .to_trimesh(SUBDIVISIONS)
+        }
+        TypedShape::ConvexPolyhedron(hull) => hull.to_trimesh(),
+        _ => return Vec::new(),
+    };
+
+    let middles = triangles.iter().flat_map(|triangle| {
+        let [a, b, c] = triangle.map(|i| points[i as usize].coords);
+        [
+            (a + b + c) / 3.0,
+            (a + b) / 2.0,
+            (b + c) / 2.0,
+            (c + a) / 2.0,
+        ]
+        .map(Point::from)
+    });
+
+    points
+        .iter()
+        .copied()
+        .chain(middles)
+        .map(|point| position * point)
+        .collect()
+}
+
+fn kind(shape: &SharedShape) -> &'static str {
+    match shape.as_typed_shape() {
+        TypedShape::Ball(_) => "colliders.ball",
+        TypedShape::Cuboid(_) => "colliders.cuboid",
+        TypedShape::Capsule(_) => "colliders.capsule",
+        TypedShape::RoundCylinder(_) => "colliders.round_cylinder",
+        TypedShape::ConvexPolyhedron(_) => "colliders.hull",
+        _ => "colliders.other",
+    }
+}
+
+// Times contact checks between the piece and a ball (about the size of the
+// piece) coming at it from all around
+fn cost(position: &Isometry<Real>, shape: &SharedShape) -> f32 {
+    let aabb = shape.compute_aabb(position);
+    let centre = aabb.center();
+    let radius = aabb.half_extents().norm();
+    let ball = Ball::new(radius / 2.0);
+
+    // Evenly spread around a sphere that just touches the piece's box
+    let probes = (0..COST_SAMPLES)
+        .map(|i| {
+            let y = 1.0 - 2.0 * (i as f32 + 0.5) / COST_SAMPLES as f32;
+            let around = i as f32 * std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+            let ring = (1.0 - y * y).sqrt();
+            let direction = vector![ring * around.cos(), y, ring * around.sin()];
+            Isometry::translation(
+                centre.x + direction.x * radius,
+                centre.y + direction.y * radius,
+                centre.z + direction.z * radius,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let start = Instant::now();
+    for probe in probes.iter() {
+        let _ = query::contact(position, shape.as_ref(), probe, &ball, 0.0);
+    }
+
+    start.elapsed().as_secs_f32() * 1.0e6 / COST_SAMPLES as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A box twice as tall as it is wide
+    fn cube() -> CollisionMesh {
+        let mut positions = Vec::new();
+        for x in [-1.0, 1.0] {
+            for y in [-2.0, 2.0] {
+                for z in [-1.0, 1.0] {
+                    positions.push([x, y, z]);
+                }
+            }
+        }
+
+        let triangles = vec![
+            [0, 1, 3],
+            [0, 3, 2],
+            [4, 6, 7],
+            [4, 7, 5],
+            [0, 4, 5],
+            [0, 5, 1],
+            [2, 3, 7],
+            [2, 7, 6],
+            [0, 2, 6],
+            [0, 6, 4],
+            [1, 5, 7],
+            [1, 7, 3],
+        ];
+
+        CollisionMesh {
+            positions,
+            triangles,
+        }
+    }
+
+    #[test]
+    fn hand_made_colliders_are_left_alone() {
+        assert!(generate(&cube(), &ColliderSettings::default()).is_none());
+    }
+
+    #[test]
+    fn generated_colliders_hold_the_mesh() {
+        let mesh = cube();
+
+        for shape in [
+            ColliderShape::Capsule,
+            ColliderShape::ConvexHull,
+            ColliderShape::Decomposed,
+        ] {
+            let settings = ColliderSettings {
+                shape,
+                max_pieces: 4,
+            };
+            let parts = generate(&mesh, &settings).unwrap();
+            let compound = SharedShape::compound(parts.clone());
+
+            for &position in mesh.positions.iter() {
+                let point = position.into();
+                let distance = compound.distance_to_point(&Isometry::identity(), &point, true);
+                assert!(distance < 1.0e-3, "{shape:?} misses {position:?}");
+            }
+
+            // The capsule's round ends stick out past the box, but a hull is
+            // the box
+            let report = ColliderReport::new(&mesh, &parts);
+            assert_eq!(report.size, 4.0);
+            assert!(report.missed < 1.0e-3);
+            match shape {
+                ColliderShape::Capsule => assert!(report.max_error > 0.1),
+                ColliderShape::ConvexHull => assert!(report.max_error < 1.0e-3),
+                _ => {}
+            }
+            assert!(report.pieces.len() <= 4);
+        }
+    }
+
+    #[test]
+    fn pieces_get_merged_down_to_the_limit() {
+        // A row of boxes, each a bit bigger than the last
+        let parts = (0..5)
+            .map(|i| {
+                let size = 0.5 + i as f32 * 0.1;
+                let position = Isometry::translation(i as f32 * 2.0, 0.0, 0.0);
+                let corners = cube()
+                    .positions
+                    .iter()
+                    .map(|&p| Point::from(p) * size)
+                    .collect::<Vec<_>>();
+                (position, SharedShape::convex_hull(&corners).unwrap())
+            })
+            .collect::<Parts>();
+
+        let merged = merge_pieces(parts.clone(), 2);
+        assert_eq!(merged.len(), 2);
+
+        // Everything that was covered still is
+        let compound = SharedShape::compound(merged);
+        for (position, shape) in parts.iter() {
+            for corner in shape.as_convex_polyhedron().unwrap().points() {
+                let point = position * corner;
+                assert!(compound.distance_to_point(&Isometry::identity(), &point, true) < 1.0e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn capsules_go_along_the_longest_side() {
+        let parts = generate(
+            &cube(),
+            &ColliderSettings {
+                shape: ColliderShape::Capsule,
+                max_pieces: 1,
+            },
+        )
+        .unwrap();
+        let capsule = parts[0].1.as_capsule().unwrap();
+        let axis = capsule.segment.b - capsule.segment.a;
+
+        assert_eq!(axis.x, 0.0);
+        assert_eq!(axis.z, 0.0);
+        assert!((capsule.radius - 2.0f32.sqrt()).abs() < 1.0e-5);
+    }
+
+    #[test]
+    fn scaled_parts_fit_a_scaled_model() {
+        let parts = generate(
+            &cube(),
+            &ColliderSettings {
+                shape: ColliderShape::ConvexHull,
+                max_pieces: 1,
+            },
+        )
+        .unwrap();
+        let small = SharedShape::compound(scale_parts(&parts, 0.5));
+
+        let aabb = small.compute_local_aabb();
+        assert!((aabb.maxs - point![0.5, 1.0, 0.5]).norm() < 1.0e-5);
+        assert!((aabb.mins - point![-0.5, -1.0, -0.5]).norm() < 1.0e-5);
+    }
+}
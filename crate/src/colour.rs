@@ -0,0 +1,87 @@
+//! Dealing with sRGB vs linear colours.
+//!
+//! All the lighting maths happens in linear space. sRGB render targets turn
+//! that into sRGB when it's written, but some surfaces (a lot of WebGL ones
+//! in particular) don't have an sRGB format at all, and writing linear
+//! colours straight to those makes everything look washed out.
+
+use crate::shader_include;
+
+/// How the colours in a texture are encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColourSpace {
+    /// Colours meant to be looked at, like diffuse textures
+    Srgb,
+    /// Data that just happens to be stored in a texture, like normal maps
+    // Nothing loads textures like that yet
+    #[allow(dead_code)]
+    Linear,
+}
+
+impl ColourSpace {
+    /// The texture format to store rgba8 data in this colour space with,
+    /// so that sampling it gives back linear values
+    pub fn rgba8_format(self) -> wgpu::TextureFormat {
+        match self {
+            ColourSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            ColourSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+const PASSTHROUGH_OUTPUT: &str = "
+// The render target is sRGB, so the conversion happens when it's written
+fn output_colour(colour: vec4<f32>) -> vec4<f32> {
+    return colour;
+}
+";
+
+const ENCODE_SRGB_OUTPUT: &str = "
+// The render target isn't sRGB, so the shader has to do the conversion itself
+fn output_colour(colour: vec4<f32>) -> vec4<f32> {
+    let c = max(colour.rgb, vec3<f32>(0.0));
+    let low = c * 12.92;
+    let high = 1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return vec4<f32>(select(high, low, c <= vec3<f32>(0.0031308)), colour.a);
+}
+";
+
+/// Adds an `output_colour` function to a shader, which takes the final linear
+/// colour and converts it to whatever the render target expects. Fragment
+/// shaders should pass everything they return through it. The shader's
+/// includes get expanded too (see shader_include.rs).
+pub fn shader_for_target(source: &str, target: wgpu::TextureFormat) -> String {
+    let output = if target.is_srgb() {
+        PASSTHROUGH_OUTPUT
+    } else {
+        ENCODE_SRGB_OUTPUT
+    };
+
+    let source = shader_include::expand(source);
+    format!("{source}\n{output}")
+}
+
+/// Converts a linear colour (e.g. a clear colour) to whatever the render target expects
+pub fn colour_for_target(colour: wgpu::Color, target: wgpu::TextureFormat) -> wgpu::Color {
+    if target.is_srgb() {
+        return colour;
+    }
+
+    let encode = |c: f64| linear_to_srgb(c as f32) as f64;
+
+    wgpu::Color {
+        r: encode(colour.r),
+        g: encode(colour.g),
+        b: encode(colour.b),
+        a: colour.a,
+    }
+}
+
+/// Encodes one linear colour channel as sRGB
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
@@ -0,0 +1,298 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audio,
+    camera_controller::CameraSmoothing,
+    error::{Error, Result},
+    locale::{Language, Strings},
+    storage::{self, Location},
+};
+
+/// What the settings file's called, in [Location::Settings]. On the web
+/// it's the key they're kept under in local storage instead.
+pub const SETTINGS_FILE: &str = "settings.toml";
+
+/// The antialiasing sample counts there are. Only 1 and 4 work everywhere,
+/// the renderer works out which of the others the gpu has.
+pub const SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+/// Settings that stick around between runs. They're read before the window's
+/// opened, and saved again whenever they're changed from the ui.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The window's size when it opens. On the web the page decides instead.
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: bool,
+    pub present_mode: PresentMode,
+    /// How often frames get drawn, on top of vsync
+    pub frame_limit: FrameLimit,
+    /// Samples per pixel for antialiasing (1 or 4). If it isn't set, it's
+    /// whatever the quality preset picked for the device says.
+    pub sample_count: Option<u32>,
+    /// How loud everything is, from 0 (silent) to 1
+    pub master_volume: f64,
+    /// How loud the music and the sound effects are, on top of the master
+    /// volume (also from 0 to 1)
+    pub music_volume: f64,
+    pub sfx_volume: f64,
+    /// How loud the song's turned up or down to be, in LUFS
+    pub music_loudness: f64,
+    pub muted: bool,
+    /// Keep the music at normal speed when the simulation's slowed down,
+    /// instead of it slowing down (and dropping in pitch) along with it
+    pub preserve_music_pitch: bool,
+    /// Slow the emitters down (and eventually stop them) while frames are
+    /// taking too long
+    pub adaptive_spawning: bool,
+    /// Whether the introduction's been gone through (or skipped), so it
+    /// isn't shown again
+    pub seen_onboarding: bool,
+    /// Which language the ui's in
+    pub language: Language,
+    /// How much the flying camera eases in and out of moving and turning
+    pub camera_smoothing: CameraSmoothing,
+    /// Move the camera once more just before each frame's sent off to the
+    /// gpu, so it keeps up with the keys better when frames are slow
+    pub late_camera: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            width: 1280,
+            height: 720,
+            fullscreen: false,
+            present_mode: PresentMode::Vsync,
+            frame_limit: FrameLimit::MatchRefresh,
+            sample_count: None,
+            master_volume: 1.0,
+            music_volume: 1.0,
+            sfx_volume: 1.0,
+            music_loudness: audio::DEFAULT_MUSIC_LOUDNESS,
+            muted: false,
+            preserve_music_pitch: false,
+            adaptive_spawning: false,
+            seen_onboarding: false,
+            language: Language::English,
+            camera_smoothing: CameraSmoothing::default(),
+            late_camera: true,
+        }
+    }
+}
+
+/// How frames are put on the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresentMode {
+    /// Wait for the screen, so there's no tearing
+    #[default]
+    Vsync,
+    /// Go as fast as possible, tearing or not
+    NoVsync,
+    /// Go as fast as possible, but only ever show whole frames, so there's
+    /// no tearing. Not every system has it.
+    Mailbox,
+    /// Go as fast as possible and show frames the moment they're done, which
+    /// tears. Not every system has it.
+    Immediate,
+}
+
+impl PresentMode {
+    pub const ALL: [Self; 4] = [Self::Vsync, Self::NoVsync, Self::Mailbox, Self::Immediate];
+
+    pub fn to_wgpu(self) -> wgpu::PresentMode {
+        match self {
+            Self::Vsync => wgpu::PresentMode::AutoVsync,
+            Self::NoVsync => wgpu::PresentMode::AutoNoVsync,
+            Self::Mailbox => wgpu::PresentMode::Mailbox,
+            Self::Immediate => wgpu::PresentMode::Immediate,
+        }
+    }
+
+    /// Which of these a wgpu present mode is. Anything else is vsync, since
+    /// that's what the renderer falls back on.
+    pub fn from_wgpu(present_mode: wgpu::PresentMode) -> Self {
+        match present_mode {
+            wgpu::PresentMode::AutoNoVsync => Self::NoVsync,
+            wgpu::PresentMode::Mailbox => Self::Mailbox,
+            wgpu::PresentMode::Immediate => Self::Immediate,
+            _ => Self::Vsync,
+        }
+    }
+
+    pub fn name(self, strings: &Strings) -> &str {
+        strings.get(match self {
+            Self::Vsync => "graphics.vsync",
+            Self::NoVsync => "graphics.no_vsync",
+            Self::Mailbox => "graphics.mailbox",
+            Self::Immediate => "graphics.immediate",
+        })
+    }
+}
+
+/// The most frames a second that get drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameLimit {
+    /// As often as the monitor refreshes. On the web the browser decides.
+    #[default]
+    MatchRefresh,
+    #[serde(rename = "30")]
+    Fps30,
+    #[serde(rename = "60")]
+    Fps60,
+    /// As many as possible (vsync still holds it back if it's on)
+    Unlimited,
+}
+
+impl FrameLimit {
+    pub const ALL: [Self; 4] = [
+        Self::MatchRefresh,
+        Self::Fps30,
+        Self::Fps60,
+        Self::Unlimited,
+    ];
+
+    pub fn name(self, strings: &Strings) -> &str {
+        strings.get(match self {
+            Self::MatchRefresh => "graphics.match_refresh",
+            Self::Fps30 => "graphics.fps_30",
+            Self::Fps60 => "graphics.fps_60",
+            Self::Unlimited => "graphics.unlimited",
+        })
+    }
+}
+
+impl Config {
+    /// Reads the saved settings. Anything missing or broken falls back to the
+    /// defaults, since it's not worth refusing to start over.
+    pub fn load() -> Self {
+        match read() {
+            Ok(Some(config)) => config.sanitised(),
+            Ok(None) => Self::default(),
+            Err(error) => {
+                log::warn!("{}", error.report());
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        write(self)
+    }
+
+    // Settings files get edited by hand, so they can say anything
+    fn sanitised(mut self) -> Self {
+        if let Some(count) = self.sample_count.filter(|c| !SAMPLE_COUNTS.contains(c)) {
+            log::warn!("Can't use {count}x antialiasing, leaving it up to the quality preset");
+            self.sample_count = None;
+        }
+
+        self.width = self.width.max(1);
+        self.height = self.height.max(1);
+        self.master_volume = self.master_volume.clamp(0.0, 1.0);
+        self.music_volume = self.music_volume.clamp(0.0, 1.0);
+        self.sfx_volume = self.sfx_volume.clamp(0.0, 1.0);
+        self.camera_smoothing = self.camera_smoothing.sanitised();
+        self
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read() -> Result<Option<Config>> {
+    // They used to be kept in the working directory, so they're picked up
+    // from there until they've been saved somewhere better
+    let mut path = storage::path(Location::Settings, SETTINGS_FILE);
+    let mut data = storage::read_file(&path)?;
+    if data.is_none() {
+        path = SETTINGS_FILE.into();
+        data = storage::read_file(&path)?;
+    }
+
+    data.map(|data| toml::from_str(&data).map_err(|e| Error::decode(&path.display().to_string(), e)))
+        .transpose()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write(config: &Config) -> Result<()> {
+    let data = toml::to_string(config).map_err(|e| Error::save(SETTINGS_FILE, e))?;
+    storage::write(Location::Settings, SETTINGS_FILE, &data)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read() -> Result<Option<Config>> {
+    storage::read(Location::Settings, SETTINGS_FILE)?
+        .map(|data| serde_json::from_str(&data).map_err(|e| Error::decode(SETTINGS_FILE, e)))
+        .transpose()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write(config: &Config) -> Result<()> {
+    let data = serde_json::to_string(config).map_err(|e| Error::save(SETTINGS_FILE, e))?;
+    storage::write(Location::Settings, SETTINGS_FILE, &data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn present_modes_come_back_from_wgpu() {
+        for mode in PresentMode::ALL {
+            assert_eq!(PresentMode::from_wgpu(mode.to_wgpu()), mode);
+        }
+        assert_eq!(PresentMode::from_wgpu(wgpu::PresentMode::Fifo), PresentMode::Vsync);
+    }
+
+    #[test]
+    fn missing_settings_are_defaults() {
+        let config: Config = toml::from_str("fullscreen = true").unwrap();
+
+        assert!(config.fullscreen);
+        assert_eq!(config.width, Config::default().width);
+        assert_eq!(config.present_mode, PresentMode::Vsync);
+
+        let config: Config = toml::from_str("frame_limit = \"30\"").unwrap();
+        assert_eq!(config.frame_limit, FrameLimit::Fps30);
+
+        let config: Config = toml::from_str("present_mode = \"mailbox\"").unwrap();
+        assert_eq!(config.present_mode, PresentMode::Mailbox);
+
+        let config: Config = toml::from_str("language = \"es\"").unwrap();
+        assert_eq!(config.language, Language::Spanish);
+
+        let config: Config = toml::from_str("[camera_smoothing]\nturn_time = 0.5").unwrap();
+        assert_eq!(config.camera_smoothing.turn_time, 0.5);
+        assert_eq!(
+            config.camera_smoothing.damping_time,
+            CameraSmoothing::default().damping_time
+        );
+    }
+
+    #[test]
+    fn unusable_settings_are_fixed() {
+        let config = Config {
+            sample_count: Some(3),
+            master_volume: 2.0,
+            sfx_volume: -1.0,
+            camera_smoothing: CameraSmoothing {
+                damping_time: f32::NAN,
+                turn_time: 100.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+        .sanitised();
+
+        assert_eq!(config.sample_count, None);
+        assert_eq!(config.master_volume, 1.0);
+        assert_eq!(config.sfx_volume, 0.0);
+        assert_eq!(config.camera_smoothing.damping_time, 0.0);
+        assert!(config.camera_smoothing.turn_time <= 2.0);
+
+        // And they still save, with the camera's settings in their own table
+        toml::to_string(&Config::default()).unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+//! Contact darkening, a cheap stand-in for ambient occlusion. Each frame the
+//! reis near the ground get counted up into a coarse grid seen from above,
+//! which goes to the gpu as a small texture. Anything low down where the grid
+//! is crowded gets darker, so the pile (and the ground under it) doesn't look
+//! like it's floating. It's only a few thousand bytes a frame, so it's fine on
+//! WebGL2 where there's no budget for anything screen space.
+//!
+//! The textures and uniform go in the shadow bind group (see shadow.rs),
+//! since WebGL2 only allows four bind groups and they're all taken. Sprites
+//! don't get darkened, they're far enough away for it not to matter.
+
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use crate::{
+    ground::DEFORM_EXTENT, locale::Strings, physics::GROUND_LEVEL, sync_audit::AuditedQueue,
+};
+
+// The grid covers the same square around the origin as the ground's
+// heightfield, since that's where the pile is
+const EXTENT: f32 = DEFORM_EXTENT;
+const GRID_SIZE: usize = 128;
+// How many reis (close to the ground) in one cell makes it as dark as it gets
+const FULL_DENSITY: f32 = 4.0;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct ContactUniform {
+    extent: f32,
+    // 0 when it's turned off
+    strength: f32,
+    reach: f32,
+    ground_level: f32,
+}
+
+/// Settings for the darkening, tweakable from the ui
+#[derive(Debug, Clone)]
+pub struct ContactConfig {
+    pub enabled: bool,
+    /// How dark the most crowded bits get, from 0 to 1
+    pub strength: f32,
+    /// How far above the ground things get darkened, and how far above it
+    /// reis still count towards the density
+    pub reach: f32,
+}
+
+impl Default for ContactConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            strength: 0.5,
+            reach: 1.5,
+        }
+    }
+}
+
+pub struct ContactDarkening {
+    pub config: ContactConfig,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+}
+
+impl ContactDarkening {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let config = ContactConfig::default();
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("contact density"),
+            size: wgpu::Extent3d {
+                width: GRID_SIZE as u32,
+                height: GRID_SIZE as u32,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("contact density sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("contact uniform buffer"),
+            contents: bytemuck::cast_slice(&[uniform(&config)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        Self {
+            config,
+            texture,
+            view,
+            sampler,
+            uniform_buffer,
+        }
+    }
+
+    /// The entries for the shadow bind group layout, from `first_binding` on
+    pub fn layout_entries(first_binding: u32) -> [wgpu::BindGroupLayoutEntry; 3] {
+        [
+            wgpu::BindGroupLayoutEntry {
+                binding: first_binding,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: first_binding + 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: first_binding + 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ]
+    }
+
+    /// The entries for the shadow bind group, to go with [Self::layout_entries]
+    pub fn entries(&self, first_binding: u32) -> [wgpu::BindGroupEntry<'_>; 3] {
+        [
+            wgpu::BindGroupEntry {
+                binding: first_binding,
+                resource: wgpu::BindingResource::TextureView(&self.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: first_binding + 1,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: first_binding + 2,
+                resource: self.uniform_buffer.as_entire_binding(),
+            },
+        ]
+    }
+
+    /// Counts up the reis near the ground and uploads the grid
+    pub fn update(&self, queue: &AuditedQueue, positions: &[[f32; 3]]) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[uniform(&self.config)]),
+        );
+
+        if !self.config.enabled {
+            return;
+        }
+
+        let grid = density_grid(positions.iter().copied(), self.config.reach);
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &grid,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(GRID_SIZE as u32),
+                rows_per_image: None,
+            },
+            self.texture.size(),
+        );
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, strings: &Strings) {
+        ui.checkbox(&mut self.config.enabled, strings.get("contact.enabled"));
+
+        ui.add_enabled_ui(self.config.enabled, |ui| {
+            ui.horizontal(|ui| {
+                ui.label(strings.get("contact.strength"));
+                ui.add(egui::Slider::new(&mut self.config.strength, 0.0..=1.0));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label(strings.get("contact.reach"));
+                ui.add(
+                    egui::DragValue::new(&mut self.config.reach)
+                        .clamp_range(0.1..=10.0)
+                        .speed(0.05),
+                );
+            });
+        });
+    }
+}
+
+fn uniform(config: &ContactConfig) -> ContactUniform {
+    ContactUniform {
+        extent: EXTENT,
+        strength: if config.enabled { config.strength } else { 0.0 },
+        reach: config.reach,
+        ground_level: GROUND_LEVEL,
+    }
+}
+
+/// How crowded each cell of the grid is, from 0 to 255, in rows going along
+/// x with z going down them. Reis count for less the higher up they are, and
+/// not at all past `reach`. Each one's spread over the cells around it too,
+/// so the texture comes out soft instead of blocky.
+fn density_grid(positions: impl Iterator<Item = [f32; 3]>, reach: f32) -> Vec<u8> {
+    let mut counts = vec![0.0; GRID_SIZE * GRID_SIZE];
+    let cell_size = EXTENT / GRID_SIZE as f32;
+
+    for [x, y, z] in positions {
+        let weight = 1.0 - (y - GROUND_LEVEL) / reach;
+        if weight <= 0.0 {
+            continue;
+        }
+
+        let column = ((x + EXTENT / 2.0) / cell_size).floor();
+        let row = ((z + EXTENT / 2.0) / cell_size).floor();
+
+        for (dx, dz, share) in [
+            (0, 0, 0.5),
+            (-1, 0, 0.125),
+            (1, 0, 0.125),
+            (0, -1, 0.125),
+            (0, 1, 0.125),
+        ] {
+            let (column, row) = (column as isize + dx, row as isize + dz);
+            if (0..GRID_SIZE as isize).contains(&column) && (0..GRID_SIZE as isize).contains(&row) {
+                counts[row as usize * GRID_SIZE + column as usize] += weight.min(1.0) * share;
+            }
+        }
+    }
+
+    counts
+        .into_iter()
+        .map(|count| ((count / FULL_DENSITY).min(1.0) * 255.0).round() as u8)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_reis_near_the_ground_count() {
+        let cell = |x: f32, z: f32| {
+            let cell_size = EXTENT / GRID_SIZE as f32;
+            let column = ((x + EXTENT / 2.0) / cell_size) as usize;
+            let row = ((z + EXTENT / 2.0) / cell_size) as usize;
+            row * GRID_SIZE + column
+        };
+
+        let pile = std::iter::repeat_n([2.0, GROUND_LEVEL, 3.0], 20);
+        let high_up = std::iter::repeat_n([-5.0, 8.0, -5.0], 20);
+        let grid = density_grid(pile.chain(high_up), 1.5);
+
+        assert_eq!(grid[cell(2.0, 3.0)], 255);
+        // Spread a bit into the next cell over
+        assert!(grid[cell(2.0 + EXTENT / GRID_SIZE as f32, 3.0)] > 0);
+        assert_eq!(grid[cell(-5.0, -5.0)], 0);
+
+        // Off the edge of the grid doesn't count anywhere
+        let outside = density_grid(std::iter::once([EXTENT, GROUND_LEVEL, 0.0]), 1.5);
+        assert!(outside.iter().all(|&density| density == 0));
+    }
+}
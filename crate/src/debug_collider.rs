@@ -1,299 +1,309 @@
-#![allow(unused)]
-use crate::model::Instance;
+use rapier3d::prelude::{Cylinder, Point, Real, Shape, TypedShape};
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
     vertex_attr_array,
 };
 
-/// Contains a [rapier3d] collider, along with various things needed
-/// to draw the collider to the screen for debug purposes.
+use crate::{
+    camera::CameraBinding,
+    model::{InstanceBuffer, InstanceRaw, Vertex},
+    sync_audit::AuditedQueue,
+    texture,
+};
+
+// How finely the round parts of the colliders get split up
+const SUBDIVISIONS: u32 = 20;
+
+/// How the colliders get drawn, if they do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColliderView {
+    Hidden,
+    // See-through, with the outlines on top
+    Solid,
+    Wireframe,
+}
+
+/// The meshes for drawing one collider shape, along with where to draw it.
+/// Every instance of the shape gets drawn at once, so there only needs to be
+/// one of these for all the reis' heads (for example).
 pub struct DebugCollider {
-    collider: rapier3d::prelude::Collider,
-    // Stuff for rendering
-    collider_vertex_buffer: wgpu::Buffer,
-    collider_index_buffer: wgpu::Buffer,
-    collider_indices: u32,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
     outline_vertex_buffer: wgpu::Buffer,
     outline_index_buffer: wgpu::Buffer,
     outline_indices: u32,
-    instance_buffer: wgpu::Buffer,
+    instances: InstanceBuffer,
 }
 
 impl DebugCollider {
-    pub fn new_capsule(device: &wgpu::Device, collider: rapier3d::prelude::Collider) -> Self {
-        let (vertices, indices) = collider.shape().as_capsule().unwrap().to_trimesh(20, 20);
-
-        let vertices = vertices.iter().map(|p| [p.x, p.y, p.z]).collect::<Vec<_>>();
+    /// Makes the meshes for a shape. Only the kinds of shapes the simulation
+    /// actually uses are supported, anything else gives None.
+    pub fn new(device: &wgpu::Device, shape: &dyn Shape) -> Option<Self> {
+        let (vertices, indices) = match shape.as_typed_shape() {
+            TypedShape::Ball(ball) => ball.to_trimesh(SUBDIVISIONS, SUBDIVISIONS),
+            TypedShape::Cuboid(cuboid) => cuboid.to_trimesh(),
+            TypedShape::Capsule(capsule) => capsule.to_trimesh(SUBDIVISIONS, SUBDIVISIONS),
+            // There's no mesh for a rounded cylinder, but a normal one that
+            // goes out to the edge of the rounding is close enough
+            TypedShape::RoundCylinder(cylinder) => {
+                let border = cylinder.border_radius;
+                let inner = cylinder.inner_shape;
+                Cylinder::new(inner.half_height + border, inner.radius + border)
+                    .to_trimesh(SUBDIVISIONS)
+            }
+            TypedShape::ConvexPolyhedron(hull) => hull.to_trimesh(),
+            _ => return None,
+        };
+
+        let (outline_vertices, outline_indices) = match shape.as_typed_shape() {
+            TypedShape::Ball(ball) => ball.to_outline(SUBDIVISIONS),
+            TypedShape::Cuboid(cuboid) => cuboid.to_outline(),
+            TypedShape::Capsule(capsule) => capsule.to_outline(SUBDIVISIONS),
+            TypedShape::RoundCylinder(cylinder) => cylinder.to_outline(SUBDIVISIONS, SUBDIVISIONS),
+            // Generated colliders' hulls. Edges between flat bits of the same
+            // face aren't on any face's list, so they're left out.
+            TypedShape::ConvexPolyhedron(hull) => {
+                let mut edges = hull
+                    .faces()
+                    .iter()
+                    .flat_map(|face| {
+                        let first = face.first_vertex_or_edge as usize;
+                        &hull.edges_adj_to_face()
+                            [first..first + face.num_vertices_or_edges as usize]
+                    })
+                    .copied()
+                    .collect::<Vec<_>>();
+                edges.sort_unstable();
+                edges.dedup();
+
+                let edges = edges.into_iter().map(|i| {
+                    let edge = &hull.edges()[i as usize];
+                    [edge.vertices.x, edge.vertices.y]
+                });
+                (hull.points().to_vec(), edges.collect())
+            }
+            _ => return None,
+        };
 
         let indices = indices.iter().flatten().copied().collect::<Vec<u32>>();
-
-        let (outline_vertices, outline_indices) =
-            collider.shape().as_capsule().unwrap().to_outline(20);
-
-        let outline_vertices = outline_vertices
-            .iter()
-            .map(|p| [p.x, p.y, p.z])
-            .collect::<Vec<_>>();
-
-        let outline_indices = outline_indices
-            .iter()
-            .flatten()
-            .copied()
-            .collect::<Vec<u32>>();
-
-        Self::new(
-            device,
-            collider,
-            vertices,
-            indices,
-            outline_vertices,
-            outline_indices,
-        )
-    }
-
-    pub fn new_round_cylinder(
-        device: &wgpu::Device,
-        collider: rapier3d::prelude::Collider,
-    ) -> Self {
-        let (vertices, indices) = collider
-            .shape()
-            .as_round_cylinder()
-            .unwrap()
-            .inner_shape
-            .to_trimesh(20);
-
-        let vertices = vertices.iter().map(|p| [p.x, p.y, p.z]).collect::<Vec<_>>();
-
-        let indices = indices.iter().flatten().copied().collect::<Vec<u32>>();
-
-        let (outline_vertices, outline_indices) = collider
-            .shape()
-            .as_round_cylinder()
-            .unwrap()
-            .to_outline(20, 20);
-
-        let outline_vertices = outline_vertices
-            .iter()
-            .map(|p| [p.x, p.y, p.z])
-            .collect::<Vec<_>>();
-
         let outline_indices = outline_indices
             .iter()
             .flatten()
             .copied()
             .collect::<Vec<u32>>();
 
-        Self::new(
-            device,
-            collider,
-            vertices,
-            indices,
-            outline_vertices,
-            outline_indices,
-        )
+        Some(Self {
+            vertex_buffer: create_vertex_buffer(device, &vertices),
+            index_buffer: create_index_buffer(device, &indices),
+            num_indices: indices.len() as _,
+            outline_vertex_buffer: create_vertex_buffer(device, &outline_vertices),
+            outline_index_buffer: create_index_buffer(device, &outline_indices),
+            outline_indices: outline_indices.len() as _,
+            instances: InstanceBuffer::new(device, "Collider instance buffer", 1),
+        })
     }
 
-    fn new(
+    /// Moves the collider to wherever these instances say it is
+    pub fn set_instances(
+        &mut self,
         device: &wgpu::Device,
-        collider: rapier3d::prelude::Collider,
-        vertices: Vec<[f32; 3]>,
-        indices: Vec<u32>,
-        outline_vertices: Vec<[f32; 3]>,
-        outline_indices: Vec<u32>,
-    ) -> Self {
-        let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("collider vertex buffer"),
-            contents: bytemuck::cast_slice(&vertices),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Collider index buffer"),
-            contents: bytemuck::cast_slice(&indices),
-            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let outline_vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("collider vertex buffer"),
-            contents: bytemuck::cast_slice(&outline_vertices),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let outline_index_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Collider index buffer"),
-            contents: bytemuck::cast_slice(&outline_indices),
-            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let instance = Instance::from_rapier_position(collider.position());
-
-        let instance_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Collider instance buffer"),
-            contents: bytemuck::cast_slice(&[instance.to_raw()]),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-        });
-
-        Self {
-            collider,
-            collider_vertex_buffer: vertex_buffer,
-            collider_index_buffer: index_buffer,
-            collider_indices: indices.len() as _,
-            outline_vertex_buffer,
-            outline_index_buffer,
-            outline_indices: outline_indices.len() as _,
-            instance_buffer,
-        }
+        queue: &AuditedQueue,
+        instances: &[InstanceRaw],
+    ) {
+        self.instances.write(device, queue, instances);
     }
 
-    pub fn draw<'r, 's>(&'s self, render_pass: &mut wgpu::RenderPass<'r>)
-    where
-        's: 'r,
-    {
-        render_pass.set_vertex_buffer(0, self.collider_vertex_buffer.slice(..));
-        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-        render_pass.set_index_buffer(
-            self.collider_index_buffer.slice(..),
-            wgpu::IndexFormat::Uint32,
-        );
-        render_pass.draw_indexed(0..self.collider_indices, 0, 0..1);
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instances.buffer().slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len());
     }
 
-    pub fn draw_outline<'r, 's>(&'s self, render_pass: &mut wgpu::RenderPass<'r>)
-    where
-        's: 'r,
-    {
+    pub fn draw_outline<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
         render_pass.set_vertex_buffer(0, self.outline_vertex_buffer.slice(..));
-        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instances.buffer().slice(..));
         render_pass.set_index_buffer(
             self.outline_index_buffer.slice(..),
             wgpu::IndexFormat::Uint32,
         );
-        render_pass.draw_indexed(0..self.outline_indices, 0, 0..1);
+        render_pass.draw_indexed(0..self.outline_indices, 0, 0..self.instances.len());
     }
 
-    pub fn update_capsule(&self, queue: &wgpu::Queue) {
-        let (vertices, indices) = self
-            .collider
-            .shape()
-            .as_capsule()
-            .unwrap()
-            .to_trimesh(20, 20);
-
-        let vertices = vertices.iter().map(|p| [p.x, p.y, p.z]).collect::<Vec<_>>();
-
-        let indices = indices.iter().flatten().copied().collect::<Vec<u32>>();
-
-        let (outline_vertices, outline_indices) =
-            self.collider.shape().as_capsule().unwrap().to_outline(20);
-
-        let outline_vertices = outline_vertices
-            .iter()
-            .map(|p| [p.x, p.y, p.z])
-            .collect::<Vec<_>>();
-
-        let outline_indices = outline_indices
-            .iter()
-            .flatten()
-            .copied()
-            .collect::<Vec<u32>>();
-
-        queue.write_buffer(
-            &self.collider_vertex_buffer,
-            0,
-            bytemuck::cast_slice(&vertices),
-        );
-        queue.write_buffer(
-            &self.collider_index_buffer,
-            0,
-            bytemuck::cast_slice(&indices),
-        );
-        queue.write_buffer(
-            &self.outline_vertex_buffer,
-            0,
-            bytemuck::cast_slice(&outline_vertices),
-        );
-        queue.write_buffer(
-            &self.outline_index_buffer,
-            0,
-            bytemuck::cast_slice(&outline_indices),
-        );
-        queue.write_buffer(
-            &self.instance_buffer,
-            0,
-            bytemuck::cast_slice(&[
-                Instance::from_rapier_position(self.collider.position()).to_raw()
-            ]),
-        );
+    pub fn vertex_desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<[f32; 3]>() as _,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &vertex_attr_array![0 => Float32x3],
+        }
     }
+}
 
-    pub fn update_round_cylinder(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
-        let (vertices, indices) = self
-            .collider
-            .shape()
-            .as_round_cylinder()
-            .unwrap()
-            .inner_shape
-            .to_trimesh(20);
+fn create_vertex_buffer(device: &wgpu::Device, vertices: &[Point<Real>]) -> wgpu::Buffer {
+    let vertices = vertices.iter().map(|p| [p.x, p.y, p.z]).collect::<Vec<_>>();
 
-        let vertices = vertices.iter().map(|p| [p.x, p.y, p.z]).collect::<Vec<_>>();
+    device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("Collider vertex buffer"),
+        contents: bytemuck::cast_slice(&vertices),
+        usage: wgpu::BufferUsages::VERTEX,
+    })
+}
 
-        let indices = indices.iter().flatten().copied().collect::<Vec<u32>>();
+fn create_index_buffer(device: &wgpu::Device, indices: &[u32]) -> wgpu::Buffer {
+    device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("Collider index buffer"),
+        contents: bytemuck::cast_slice(indices),
+        usage: wgpu::BufferUsages::INDEX,
+    })
+}
 
-        let (outline_vertices, outline_indices) = self
-            .collider
-            .shape()
-            .as_round_cylinder()
-            .unwrap()
-            .to_outline(20, 20);
+/// Draws [DebugCollider]s into the scene, so it's possible to see whether the
+/// colliders actually line up with the models
+pub struct ColliderRenderer {
+    solid_pipeline: wgpu::RenderPipeline,
+    outline_pipeline: wgpu::RenderPipeline,
+}
 
-        let outline_vertices = outline_vertices
-            .iter()
-            .map(|p| [p.x, p.y, p.z])
-            .collect::<Vec<_>>();
+impl ColliderRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        Self {
+            solid_pipeline: create_pipeline(device, shader, format, sample_count, false),
+            outline_pipeline: create_pipeline(device, shader, format, sample_count, true),
+        }
+    }
 
-        let outline_indices = outline_indices
-            .iter()
-            .flatten()
-            .copied()
-            .collect::<Vec<u32>>();
+    /// Recreates the pipelines for a scene with a different number of samples
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        *self = Self::new(device, shader, format, sample_count);
+    }
 
-        queue.write_buffer(
-            &self.collider_vertex_buffer,
-            0,
-            bytemuck::cast_slice(&vertices),
-        );
-        queue.write_buffer(
-            &self.collider_index_buffer,
-            0,
-            bytemuck::cast_slice(&indices),
-        );
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a CameraBinding,
+        colliders: &'a [DebugCollider],
+        view: ColliderView,
+    ) {
+        if view == ColliderView::Hidden || colliders.is_empty() {
+            return;
+        }
 
-        self.outline_vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("collider vertex buffer"),
-            contents: bytemuck::cast_slice(&outline_vertices),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-        });
+        render_pass.set_bind_group(0, &camera.bind_group, &[]);
 
-        self.outline_index_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Collider index buffer"),
-            contents: bytemuck::cast_slice(&outline_indices),
-            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
-        });
+        if view == ColliderView::Solid {
+            render_pass.set_pipeline(&self.solid_pipeline);
+            for collider in colliders {
+                collider.draw(render_pass);
+            }
+        }
 
-        queue.write_buffer(
-            &self.instance_buffer,
-            0,
-            bytemuck::cast_slice(&[
-                Instance::from_rapier_position(self.collider.position()).to_raw()
-            ]),
-        );
+        render_pass.set_pipeline(&self.outline_pipeline);
+        for collider in colliders {
+            collider.draw_outline(render_pass);
+        }
     }
 
-    pub fn vertex_desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<[f32; 3]>() as _,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &vertex_attr_array![0 => Float32x3],
+    /// Draws with both pipelines even though there aren't any colliders to
+    /// show, so they've been used before the collider view's turned on. The
+    /// buffer stands in for the vertices and the instance, so it needs to be
+    /// big enough for a triangle and an [InstanceRaw].
+    pub fn warm_up<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a CameraBinding,
+        buffer: &'a wgpu::Buffer,
+    ) {
+        render_pass.set_bind_group(0, &camera.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, buffer.slice(..));
+        render_pass.set_vertex_buffer(1, buffer.slice(..));
+
+        for pipeline in [&self.solid_pipeline, &self.outline_pipeline] {
+            render_pass.set_pipeline(pipeline);
+            render_pass.draw(0..3, 0..1);
         }
     }
 }
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    outline: bool,
+) -> wgpu::RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("collider pipeline layout"),
+        bind_group_layouts: &[CameraBinding::bind_group_layout(device)],
+        push_constant_ranges: &[],
+    });
+
+    let (label, entry_point, topology) = if outline {
+        (
+            "collider outline pipeline",
+            "fs_outline",
+            wgpu::PrimitiveTopology::LineList,
+        )
+    } else {
+        (
+            "collider pipeline",
+            "fs_solid",
+            wgpu::PrimitiveTopology::TriangleList,
+        )
+    };
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[DebugCollider::vertex_desc(), InstanceRaw::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology,
+            cull_mode: None,
+            ..Default::default()
+        },
+        // This gets drawn in the overlay pass, over the scene's depth buffer.
+        // The solid parts only show where they poke out of the models, but
+        // the outlines go over the top so the whole shape can be seen.
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: if outline {
+                wgpu::CompareFunction::Always
+            } else {
+                wgpu::CompareFunction::LessEqual
+            },
+            stencil: Default::default(),
+            bias: Default::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
@@ -0,0 +1,190 @@
+//! Numbers for working out where a frame's time is going, shown over the top
+//! of the scene with F3. Unlike the stats, these are for whoever's working on
+//! it rather than whoever's watching.
+
+use std::{collections::VecDeque, time::Duration};
+
+use crate::{frustum::CullCounts, gpu_timings::GpuPass, locale::Strings, physics::BodyCounts};
+
+// How many frames the timings are averaged over
+const WINDOW: usize = 120;
+
+// The last few frames' worth of one timing, in milliseconds
+#[derive(Debug, Default)]
+struct Rolling {
+    samples: VecDeque<f32>,
+}
+
+impl Rolling {
+    fn push(&mut self, milliseconds: f32) {
+        self.samples.push_back(milliseconds);
+        if self.samples.len() > WINDOW {
+            self.samples.pop_front();
+        }
+    }
+
+    fn average(&self) -> f32 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+
+        self.samples.iter().sum::<f32>() / self.samples.len() as f32
+    }
+
+    fn max(&self) -> f32 {
+        self.samples.iter().copied().fold(0.0, f32::max)
+    }
+}
+
+/// Rolling timings of the parts of a frame, and how big things are
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    pub visible: bool,
+    frame: Rolling,
+    update: Rolling,
+    physics: Rolling,
+    submit: Rolling,
+    counts: BodyCounts,
+    cull_counts: CullCounts,
+    // If the reis are culled on the gpu, where they can't be counted
+    gpu_culling: bool,
+    // How long each pass took on the gpu, if it can be timed
+    gpu_timings: Option<[f32; GpuPass::ALL.len()]>,
+    // Each gpu buffer worth keeping an eye on, by the key of its ui string,
+    // with its size in bytes
+    buffers: Vec<(&'static str, u64)>,
+}
+
+impl Diagnostics {
+    /// Records the time since the last frame, and the time `update` took
+    pub fn record_update(&mut self, delta_time: f32, took: Duration, counts: BodyCounts) {
+        self.frame.push(delta_time * 1000.0);
+        self.update.push(milliseconds(took));
+        self.counts = counts;
+    }
+
+    /// Records how long the physics steps for a frame took, which is part of
+    /// the update's time
+    pub fn record_physics(&mut self, took: Duration) {
+        self.physics.push(milliseconds(took));
+    }
+
+    pub fn record_submit(&mut self, took: Duration) {
+        self.submit.push(milliseconds(took));
+    }
+
+    /// Records how many instances were culled on the cpu, and whether the
+    /// reis were left for the gpu to cull
+    pub fn set_cull_counts(&mut self, cull_counts: CullCounts, gpu_culling: bool) {
+        self.cull_counts = cull_counts;
+        self.gpu_culling = gpu_culling;
+    }
+
+    pub fn set_gpu_timings(&mut self, timings: Option<[f32; GpuPass::ALL.len()]>) {
+        self.gpu_timings = timings;
+    }
+
+    pub fn set_buffers(&mut self, buffers: Vec<(&'static str, u64)>) {
+        self.buffers = buffers;
+    }
+
+    /// Frames per second, going by the average frame time
+    pub fn fps(&self) -> f32 {
+        let frame = self.frame.average();
+        if frame > 0.0 {
+            1000.0 / frame
+        } else {
+            0.0
+        }
+    }
+
+    pub fn show(&self, ctx: &egui::Context, strings: &Strings) {
+        if !self.visible {
+            return;
+        }
+
+        egui::Area::new("diagnostics")
+            .anchor(egui::Align2::RIGHT_TOP, [-10.0, 10.0])
+            .interactable(false)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.style_mut().override_text_style = Some(egui::TextStyle::Monospace);
+
+                    ui.label(
+                        strings
+                            .format("diagnostics.fps", &[("fps", &format!("{:.0}", self.fps()))]),
+                    );
+
+                    for (key, timing) in [
+                        ("diagnostics.frame", &self.frame),
+                        ("diagnostics.update", &self.update),
+                        ("diagnostics.physics", &self.physics),
+                        ("diagnostics.submit", &self.submit),
+                    ] {
+                        ui.label(strings.format(
+                            key,
+                            &[
+                                ("average", &format!("{:.2}", timing.average())),
+                                ("max", &format!("{:.2}", timing.max())),
+                            ],
+                        ));
+                    }
+
+                    if let Some(timings) = &self.gpu_timings {
+                        for (pass, milliseconds) in GpuPass::ALL.iter().zip(timings) {
+                            ui.label(strings.format(
+                                pass.key(),
+                                &[("milliseconds", &format!("{milliseconds:.2}"))],
+                            ));
+                        }
+                    }
+
+                    let active = self.counts.total - self.counts.sleeping;
+                    ui.label(strings.format(
+                        "diagnostics.bodies",
+                        &[("active", &active), ("total", &self.counts.total)],
+                    ));
+                    ui.label(strings.format(
+                        "diagnostics.culling",
+                        &[
+                            ("drawn", &self.cull_counts.drawn),
+                            ("culled", &self.cull_counts.culled),
+                        ],
+                    ));
+                    if self.gpu_culling {
+                        ui.label(strings.get("diagnostics.gpu_culling"));
+                    }
+
+                    for (key, size) in &self.buffers {
+                        ui.label(
+                            strings
+                                .format(key, &[("kib", &format!("{:.1}", *size as f32 / 1024.0))]),
+                        );
+                    }
+                });
+            });
+    }
+}
+
+fn milliseconds(duration: Duration) -> f32 {
+    duration.as_secs_f32() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timings_only_cover_the_last_few_frames() {
+        let mut diagnostics = Diagnostics::default();
+        assert_eq!(diagnostics.fps(), 0.0);
+
+        diagnostics.record_update(0.1, Duration::ZERO, BodyCounts::default());
+        for _ in 0..WINDOW {
+            diagnostics.record_update(1.0 / 50.0, Duration::ZERO, BodyCounts::default());
+        }
+
+        assert!((diagnostics.fps() - 50.0).abs() < 0.01);
+        assert!((diagnostics.frame.max() - 20.0).abs() < 0.01);
+    }
+}
@@ -0,0 +1,353 @@
+//! The editor, for laying a scene out by hand. Clicking on an emitter, a prop
+//! or the light selects it, and dragging one of the arrows of the gizmo that
+//! shows up on it moves it along that axis. The layout gets saved back to the
+//! scene file along with everything else.
+//!
+//! With snapping on, whatever's dragged lines up with a grid along the arrow
+//! it's dragged by, and props' rotations go in steps. Holding ctrl does the
+//! opposite of whatever the checkbox says, for a quick nudge either way.
+//!
+//! Dragging across empty space draws a box (or a lasso) instead, and every rei
+//! inside it gets picked out as a group, which can be cleared away, frozen,
+//! thrown upwards or tinted all at once. Holding shift adds to the group
+//! rather than starting a new one.
+
+use cgmath::{MetricSpace, Point3};
+
+use crate::{
+    camera::Camera,
+    lines::LineRenderer,
+    selection::{Marquee, MarqueeShape},
+};
+
+// How long the gizmo's arrows are, as a fraction of how far away they are, so
+// they stay the same size on screen
+const GIZMO_SCALE: f32 = 0.15;
+// How close the mouse has to be to an arrow to grab it, in pixels
+const GRAB_DISTANCE: f32 = 10.0;
+// How close the mouse has to be to something to select it by clicking on it
+const PICK_DISTANCE: f32 = 40.0;
+
+const AXIS_COLOURS: [[f32; 3]; 3] = [[1.0, 0.2, 0.2], [0.2, 1.0, 0.2], [0.3, 0.4, 1.0]];
+const HIGHLIGHT_COLOUR: [f32; 3] = [1.0, 0.9, 0.2];
+
+#[derive(Debug, Default)]
+pub struct Editor {
+    pub enabled: bool,
+    pub snapping: Snapping,
+    // The arrow under the mouse, or the one being dragged
+    axis: Option<usize>,
+    dragging: bool,
+    // Where what's being dragged would be without snapping. The mouse only
+    // moves a few pixels at a time, which would never be enough to get to the
+    // next grid line if it was snapped every time.
+    unsnapped: Option<[f32; 3]>,
+    /// What shape dragging across empty space selects reis with
+    pub marquee_shape: MarqueeShape,
+    marquee: Option<Marquee>,
+}
+
+/// How things line up while they're being placed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Snapping {
+    pub enabled: bool,
+    /// How far apart the grid lines positions snap to are, in units
+    pub grid: f32,
+    /// What rotations snap to multiples of, in degrees
+    pub angle: f32,
+}
+
+impl Default for Snapping {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            grid: 0.5,
+            angle: 15.0,
+        }
+    }
+}
+
+impl Snapping {
+    /// The nearest grid line to a position along one axis
+    pub fn position(&self, value: f32) -> f32 {
+        snap(value, self.grid)
+    }
+
+    /// The nearest step to a rotation, in degrees
+    pub fn angle(&self, degrees: f32) -> f32 {
+        snap(degrees, self.angle)
+    }
+}
+
+impl Editor {
+    /// Works out which of the gizmo's arrows is under the mouse, for the
+    /// gizmo at `position` (if there is one). It stays on the same one while
+    /// it's dragged.
+    pub fn hover(
+        &mut self,
+        camera: &Camera,
+        position: Option<[f32; 3]>,
+        cursor: [f32; 2],
+        size: [f32; 2],
+    ) {
+        if self.dragging {
+            return;
+        }
+
+        let Some((position, origin)) = position.and_then(|position| {
+            let position = Point3::from(position);
+            Some((position, camera.to_pixel(position, size)?))
+        }) else {
+            self.axis = None;
+            return;
+        };
+
+        self.axis = (0..3)
+            .filter_map(|axis| {
+                let end = camera.to_pixel(axis_end(camera, position, axis), size)?;
+                Some((axis, distance_to_segment(cursor, origin, end)))
+            })
+            .filter(|&(_, distance)| distance < GRAB_DISTANCE)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(axis, _)| axis);
+    }
+
+    /// Grabs the arrow under the mouse, returning false if there isn't one
+    pub fn start_drag(&mut self) -> bool {
+        self.dragging = self.axis.is_some();
+        self.unsnapped = None;
+        self.dragging
+    }
+
+    pub fn stop_drag(&mut self) {
+        self.dragging = false;
+        self.unsnapped = None;
+    }
+
+    /// Whether one of the arrows is being dragged
+    pub fn dragging(&self) -> bool {
+        self.dragging
+    }
+
+    /// Starts dragging out a marquee from the mouse
+    pub fn start_marquee(&mut self, cursor: [f32; 2]) {
+        self.marquee = Some(Marquee::new(self.marquee_shape, cursor));
+    }
+
+    /// Stretches the marquee out to the mouse, returning false if there isn't
+    /// one being dragged
+    pub fn extend_marquee(&mut self, to: [f32; 2]) -> bool {
+        self.marquee
+            .as_mut()
+            .map(|marquee| marquee.extend(to))
+            .is_some()
+    }
+
+    /// The marquee being dragged out, if there is one
+    pub fn marquee(&self) -> Option<&Marquee> {
+        self.marquee.as_ref()
+    }
+
+    /// Lets go of the marquee, returning it so whatever's inside it can be
+    /// selected
+    pub fn finish_marquee(&mut self) -> Option<Marquee> {
+        self.marquee.take()
+    }
+
+    /// Where something at `position` ends up when the arrow being dragged
+    /// follows the mouse from `from` to `to`. Only the part of the mouse's
+    /// movement along the arrow counts, and if it's `snap`ping that's the
+    /// only axis that snaps to the grid.
+    pub fn drag(
+        &mut self,
+        camera: &Camera,
+        position: [f32; 3],
+        from: [f32; 2],
+        to: [f32; 2],
+        size: [f32; 2],
+        snap: bool,
+    ) -> Option<[f32; 3]> {
+        let axis = self.axis.filter(|_| self.dragging)?;
+
+        let start = Point3::from(position);
+        let origin = camera.to_pixel(start, size)?;
+        let end = camera.to_pixel(axis_end(camera, start, axis), size)?;
+        let arrow = [end[0] - origin[0], end[1] - origin[1]];
+
+        // An arrow pointing straight at the camera can't be dragged along
+        let length_squared = arrow[0] * arrow[0] + arrow[1] * arrow[1];
+        if length_squared < 1.0 {
+            return None;
+        }
+
+        let moved = [to[0] - from[0], to[1] - from[1]];
+        let along = (moved[0] * arrow[0] + moved[1] * arrow[1]) / length_squared;
+
+        let unsnapped = self.unsnapped.get_or_insert(position);
+        unsnapped[axis] += along * arrow_length(camera, start);
+
+        let mut position = position;
+        position[axis] = if snap {
+            self.snapping.position(unsnapped[axis])
+        } else {
+            unsnapped[axis]
+        };
+        Some(position)
+    }
+
+    /// Adds the gizmo's arrows to the lines drawn over the scene
+    pub fn draw(&self, lines: &mut LineRenderer, camera: &Camera, position: [f32; 3]) {
+        for (axis, colour) in AXIS_COLOURS.into_iter().enumerate() {
+            let colour = if self.axis == Some(axis) {
+                HIGHLIGHT_COLOUR
+            } else {
+                colour
+            };
+
+            let end = axis_end(camera, Point3::from(position), axis);
+            lines.line(position, end.into(), colour);
+        }
+    }
+}
+
+/// Whichever of the things at these positions is closest to the mouse on
+/// screen, as long as it's close enough to have been clicked on
+pub fn pick<T>(
+    camera: &Camera,
+    candidates: impl IntoIterator<Item = (T, [f32; 3])>,
+    cursor: [f32; 2],
+    size: [f32; 2],
+) -> Option<T> {
+    candidates
+        .into_iter()
+        .filter_map(|(candidate, position)| {
+            let [x, y] = camera.to_pixel(Point3::from(position), size)?;
+            let distance = ((x - cursor[0]).powi(2) + (y - cursor[1]).powi(2)).sqrt();
+            (distance < PICK_DISTANCE).then_some((candidate, distance))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(candidate, _)| candidate)
+}
+
+fn arrow_length(camera: &Camera, position: Point3<f32>) -> f32 {
+    camera.eye.distance(position) * GIZMO_SCALE
+}
+
+fn axis_end(camera: &Camera, position: Point3<f32>, axis: usize) -> Point3<f32> {
+    let mut end = position;
+    end[axis] += arrow_length(camera, position);
+    end
+}
+
+// Rounds to the nearest multiple of step, unless there isn't a step
+fn snap(value: f32, step: f32) -> f32 {
+    if step > 0.0 {
+        (value / step).round() * step
+    } else {
+        value
+    }
+}
+
+fn distance_to_segment(point: [f32; 2], start: [f32; 2], end: [f32; 2]) -> f32 {
+    let segment = [end[0] - start[0], end[1] - start[1]];
+    let offset = [point[0] - start[0], point[1] - start[1]];
+    let length_squared = segment[0] * segment[0] + segment[1] * segment[1];
+
+    let t = if length_squared > 0.0 {
+        ((offset[0] * segment[0] + offset[1] * segment[1]) / length_squared).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    let closest = [start[0] + segment[0] * t, start[1] + segment[1] * t];
+    ((point[0] - closest[0]).powi(2) + (point[1] - closest[1]).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dragging_an_arrow_moves_along_its_axis() {
+        // Looking down -z, with something straight ahead
+        let mut camera = Camera::new(Point3::new(0.0, 0.0, 0.0), 1.0);
+        camera.refresh();
+        let size = [400.0, 400.0];
+        let position = [0.0, 0.0, -10.0];
+
+        let mut editor = Editor::default();
+        let end = camera
+            .to_pixel(axis_end(&camera, Point3::from(position), 0), size)
+            .unwrap();
+
+        // Nothing's grabbed away from the arrows
+        editor.hover(&camera, Some(position), [200.0, 350.0], size);
+        assert!(!editor.start_drag());
+
+        editor.hover(&camera, Some(position), end, size);
+        assert!(editor.start_drag());
+
+        // Dragging to the right moves it along x, and moving up and down
+        // doesn't count
+        let moved = editor
+            .drag(
+                &camera,
+                position,
+                end,
+                [end[0] + 20.0, end[1] + 30.0],
+                size,
+                false,
+            )
+            .unwrap();
+        assert!(moved[0] > 0.0);
+        assert_eq!(moved[1..], position[1..]);
+
+        editor.stop_drag();
+        assert!(editor
+            .drag(&camera, position, end, [0.0, 0.0], size, false)
+            .is_none());
+
+        // Picking goes for the closest thing
+        let picked = pick(
+            &camera,
+            [("far", [3.0, 0.0, -10.0]), ("near", position)],
+            [205.0, 200.0],
+            size,
+        );
+        assert_eq!(picked, Some("near"));
+    }
+
+    #[test]
+    fn snapping_adds_up_small_drags() {
+        let mut camera = Camera::new(Point3::new(0.0, 0.0, 0.0), 1.0);
+        camera.refresh();
+        let size = [400.0, 400.0];
+        let mut position = [0.1, 0.2, -10.0];
+
+        let mut editor = Editor::default();
+        let end = camera
+            .to_pixel(axis_end(&camera, Point3::from(position), 0), size)
+            .unwrap();
+        editor.hover(&camera, Some(position), end, size);
+        assert!(editor.start_drag());
+
+        // A pixel at a time is never enough to reach the next grid line on its
+        // own, but it gets there in the end
+        let mut cursor = end;
+        for _ in 0..40 {
+            let to = [cursor[0] + 1.0, cursor[1]];
+            position = editor
+                .drag(&camera, position, cursor, to, size, true)
+                .unwrap();
+            cursor = to;
+
+            let steps = position[0] / editor.snapping.grid;
+            assert!((steps - steps.round()).abs() < 1e-4);
+        }
+        assert!(position[0] >= editor.snapping.grid);
+
+        // The other axes are left alone, even off the grid
+        assert_eq!(position[1..], [0.2, -10.0]);
+        assert_eq!(editor.snapping.angle(37.0), 30.0);
+    }
+}
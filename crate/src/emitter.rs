@@ -0,0 +1,250 @@
+use rand::Rng;
+use rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::locale::Strings;
+
+// Inherited velocities are capped at this, so typing in a new position
+// doesn't fire reis off at a thousand metres a second
+const MAX_INHERITED_SPEED: f32 = 30.0;
+
+/// A box-shaped region of space that periodically spawns Reis somewhere inside it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Emitter {
+    pub name: String,
+    /// The centre of the spawn area
+    pub position: [f32; 3],
+    /// Half the size of the spawn area along each axis
+    pub extents: [f32; 3],
+    /// Seconds between each spawn
+    pub spawn_interval: f32,
+    /// Spawned Reis get a random uniform scale between these two values
+    pub min_scale: f32,
+    pub max_scale: f32,
+    /// If set, spawn points are moved to just above whatever is below them
+    /// (the ground, or the pile) instead of being at a fixed height
+    pub snap_to_surface: bool,
+    /// How far above the surface to spawn, when snapping to the surface
+    pub surface_offset: f32,
+    /// Colour the reis it spawns get multiplied by
+    pub tint: [f32; 3],
+    /// How much of the emitter's velocity the reis it spawns start with,
+    /// when it's being moved around
+    pub inherit_velocity: f32,
+
+    /// Hidden emitters' reis are still simulated, just not drawn
+    #[serde(skip)]
+    pub visible: bool,
+    #[serde(skip)]
+    pub timer: f32,
+    // Where it was when its velocity was last worked out, if it has been
+    #[serde(skip)]
+    pub last_position: Option<[f32; 3]>,
+    #[serde(skip)]
+    pub velocity: [f32; 3],
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        // The original hard-coded spawn area
+        Self {
+            name: "Rain".to_string(),
+            position: [0.0, 10.0, -25.0],
+            extents: [20.0, 0.0, 25.0],
+            spawn_interval: 3.157 / 16.0,
+            min_scale: 1.0,
+            max_scale: 1.0,
+            snap_to_surface: false,
+            surface_offset: 2.0,
+            tint: [1.0, 1.0, 1.0],
+            inherit_velocity: 1.0,
+            visible: true,
+            timer: 0.0,
+            last_position: None,
+            velocity: [0.0; 3],
+        }
+    }
+}
+
+impl Emitter {
+    /// Advances the spawn timer, returning true if it's time to spawn something.
+    pub fn tick(&mut self, delta_time: f32) -> bool {
+        self.timer += delta_time;
+
+        if self.timer >= self.spawn_interval {
+            self.timer = 0.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Works out how fast the emitter's moving from how far it's gone since
+    /// the last time. Anything can move it (the editor, the emitters panel),
+    /// so this gets called once a frame rather than by whatever moved it.
+    pub fn track_velocity(&mut self, delta_time: f32) {
+        // While paused it isn't going anywhere, however far it's dragged
+        if delta_time <= 0.0 {
+            self.reset_velocity();
+            return;
+        }
+
+        if let Some(last) = self.last_position {
+            let velocity = vector![
+                self.position[0] - last[0],
+                self.position[1] - last[1],
+                self.position[2] - last[2]
+            ] / delta_time;
+            self.velocity = velocity.cap_magnitude(MAX_INHERITED_SPEED).into();
+        }
+
+        self.last_position = Some(self.position);
+    }
+
+    /// Forgets how fast it was going, for when it's jumped somewhere instead
+    /// of being moved there
+    pub fn reset_velocity(&mut self) {
+        self.last_position = None;
+        self.velocity = [0.0; 3];
+    }
+
+    /// The velocity a rei it's just spawned starts off with
+    pub fn spawn_velocity(&self) -> Vector<f32> {
+        Vector::from(self.velocity) * self.inherit_velocity
+    }
+
+    pub fn random_position(&self, rng: &mut impl Rng) -> Vector<f32> {
+        let [x, y, z] = self.position;
+        let [ex, ey, ez] = self.extents.map(f32::abs);
+
+        vector![
+            x + rng.gen_range(-ex..=ex),
+            y + rng.gen_range(-ey..=ey),
+            z + rng.gen_range(-ez..=ez)
+        ]
+    }
+
+    /// Puts the scales the right way round if they've been swapped, or says
+    /// what's wrong with them if they can't be used at all
+    pub fn check_scales(&mut self) -> Result<(), String> {
+        let usable = |scale: f32| scale.is_finite() && scale > 0.0;
+        if !usable(self.min_scale) || !usable(self.max_scale) {
+            return Err(format!(
+                "emitter \"{}\" has scales that aren't above 0 ({} to {})",
+                self.name, self.min_scale, self.max_scale
+            ));
+        }
+
+        if self.min_scale > self.max_scale {
+            log::warn!(
+                "Emitter \"{}\" has its min_scale above its max_scale, swapping them",
+                self.name
+            );
+            std::mem::swap(&mut self.min_scale, &mut self.max_scale);
+        }
+
+        Ok(())
+    }
+
+    pub fn random_scale(&self, rng: &mut impl Rng) -> f32 {
+        let min = self.min_scale.min(self.max_scale);
+        let max = self.min_scale.max(self.max_scale);
+        rng.gen_range(min..=max)
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, strings: &Strings) {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.visible, strings.get("emitters.visible"));
+            ui.label(strings.get("emitters.tint"));
+            ui.color_edit_button_rgb(&mut self.tint);
+        });
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("emitters.position"));
+            for value in self.position.iter_mut() {
+                ui.add(egui::DragValue::new(value).speed(0.1));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("emitters.extents"));
+            for value in self.extents.iter_mut() {
+                ui.add(
+                    egui::DragValue::new(value)
+                        .clamp_range(0.0..=f32::INFINITY)
+                        .speed(0.1),
+                );
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("emitters.spawn_interval"));
+            ui.add(
+                egui::DragValue::new(&mut self.spawn_interval)
+                    .clamp_range(0.01..=f32::INFINITY)
+                    .speed(0.01),
+            );
+        });
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("emitters.scale"));
+            ui.add(
+                egui::DragValue::new(&mut self.min_scale)
+                    .clamp_range(0.1..=self.max_scale)
+                    .speed(0.01),
+            );
+            ui.label(strings.get("emitters.scale_to"));
+            ui.add(
+                egui::DragValue::new(&mut self.max_scale)
+                    .clamp_range(self.min_scale..=10.0)
+                    .speed(0.01),
+            );
+        });
+
+        ui.horizontal(|ui| {
+            ui.checkbox(
+                &mut self.snap_to_surface,
+                strings.get("emitters.snap_to_surface"),
+            );
+            ui.add(
+                egui::DragValue::new(&mut self.surface_offset)
+                    .clamp_range(0.0..=f32::INFINITY)
+                    .speed(0.1),
+            );
+        });
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("emitters.inherit_velocity"));
+            ui.add(egui::Slider::new(&mut self.inherit_velocity, 0.0..=1.0));
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn velocity_comes_from_how_far_it_moved() {
+        let mut emitter = Emitter::default();
+        emitter.track_velocity(0.1);
+        emitter.position[0] += 0.5;
+        emitter.track_velocity(0.1);
+
+        assert!((emitter.velocity[0] - 5.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn moving_it_while_paused_doesnt_fling_anything() {
+        let mut emitter = Emitter::default();
+        emitter.track_velocity(0.1);
+
+        // Dragged a long way while paused, then unpaused
+        emitter.position[0] += 20.0;
+        emitter.track_velocity(0.0);
+        emitter.track_velocity(0.1);
+
+        assert_eq!(emitter.velocity, [0.0; 3]);
+    }
+}
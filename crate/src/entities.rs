@@ -0,0 +1,150 @@
+//! The models in the scene, and what each one gets drawn at. The app goes
+//! through these when it draws and animates things, so something new in the
+//! scene is another entity rather than another field (and another unwrap)
+//! on the app. This is what's running, the description of the scene that
+//! gets loaded and saved is in scene.rs.
+
+use std::sync::Arc;
+
+use cgmath::{Matrix4, SquareMatrix};
+use rapier3d::prelude::RigidBodyHandle;
+
+use crate::{
+    light::LightUniform,
+    model::{Instance, InstanceBuffer, InstanceRaw, Model},
+    physics::PhysicsSimulation,
+    sync_audit::AuditedQueue,
+};
+
+/// Which of the simulation's rigid bodies a model gets drawn at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bodies {
+    /// The reis, and the statue and props along with them
+    Reis,
+    /// Whatever's been thrown and hasn't landed yet
+    Projectiles,
+}
+
+pub struct Entity {
+    /// What the scene file calls it, in `models` and `animations`
+    pub name: String,
+    pub model: Arc<Model>,
+    /// Where the model sits relative to whatever it's drawn at
+    pub transform: Matrix4<f32>,
+    /// The rigid body it moves with, if it's drawn at just the one
+    pub body: Option<RigidBodyHandle>,
+    /// Whether it's drawn at the light, in the light's colour instead of lit
+    /// by it
+    pub light: bool,
+    /// The set of bodies it's drawn once for each of, from their instance
+    /// buffer, instead of being drawn by itself
+    pub bodies: Option<Bodies>,
+    // Where it's drawn when it's drawn by itself
+    instance: InstanceBuffer,
+}
+
+impl Entity {
+    pub fn new(device: &wgpu::Device, name: &str, model: Arc<Model>) -> Self {
+        Self {
+            name: name.to_string(),
+            model,
+            transform: Matrix4::identity(),
+            body: None,
+            light: false,
+            bodies: None,
+            instance: InstanceBuffer::new(device, "Entity instance buffer", 1),
+        }
+    }
+
+    pub fn with_transform(mut self, transform: Matrix4<f32>) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn at_light(mut self) -> Self {
+        self.light = true;
+        self
+    }
+
+    pub fn at_bodies(mut self, bodies: Bodies) -> Self {
+        self.bodies = Some(bodies);
+        self
+    }
+
+    /// The instance it's drawn with when it's drawn by itself. Empty if its
+    /// body's gone.
+    pub fn instance(&self) -> &InstanceBuffer {
+        &self.instance
+    }
+
+    // Where it's drawn in the world when it's drawn by itself, or None if
+    // its body isn't there anymore
+    fn placement(&self, physics: &PhysicsSimulation, light: &LightUniform) -> Option<Matrix4<f32>> {
+        let at = if self.light {
+            Matrix4::from_translation(light.position.into())
+        } else if let Some(body) = self.body {
+            let instance = Instance::from_rapier_position(&physics.body_position(body)?);
+            Matrix4::from_translation(instance.position) * Matrix4::from(instance.rotation)
+        } else {
+            Matrix4::identity()
+        };
+
+        Some(at * self.transform)
+    }
+}
+
+/// Every entity, drawn in the order they were added
+#[derive(Default)]
+pub struct Entities {
+    entities: Vec<Entity>,
+}
+
+impl Entities {
+    pub fn push(&mut self, entity: Entity) {
+        self.entities.push(entity);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Entity> {
+        self.entities.iter()
+    }
+
+    /// Moves the entities that are drawn by themselves to wherever they're
+    /// drawn at this frame
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &AuditedQueue,
+        physics: &PhysicsSimulation,
+        light: &LightUniform,
+    ) {
+        for entity in self
+            .entities
+            .iter_mut()
+            .filter(|entity| entity.bodies.is_none())
+        {
+            let instance = entity
+                .placement(physics, light)
+                .map(InstanceRaw::from_matrix);
+            entity.instance.write(device, queue, instance.as_slice());
+        }
+    }
+
+    /// The entity the scene file calls `name`
+    pub fn get(&self, name: &str) -> Option<&Entity> {
+        self.entities.iter().find(|entity| entity.name == name)
+    }
+
+    /// The model drawn at a set of bodies
+    pub fn model_for(&self, bodies: Bodies) -> Option<&Arc<Model>> {
+        self.entities
+            .iter()
+            .find(|entity| entity.bodies == Some(bodies))
+            .map(|entity| &entity.model)
+    }
+
+    /// The model drawn at the reis, which is what casts shadows and gets
+    /// baked into sprites
+    pub fn rei_model(&self) -> Option<&Arc<Model>> {
+        self.model_for(Bodies::Reis)
+    }
+}
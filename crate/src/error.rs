@@ -0,0 +1,168 @@
+use thiserror::Error;
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Errors for the whole crate. These are split up by what went wrong rather
+/// than where, so that whatever ends up showing the error (the error screen,
+/// or something embedding us) can react to e.g. a missing asset differently
+/// than a broken graphics driver.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("couldn't find asset \"{path}\"")]
+    AssetMissing { path: String },
+
+    #[error("couldn't load asset \"{path}\"")]
+    AssetLoad {
+        path: String,
+        #[source]
+        source: BoxError,
+    },
+
+    #[error("\"{path}\" isn't the file that was shipped ({detail})")]
+    AssetCorrupt { path: String, detail: String },
+
+    #[error("couldn't decode \"{path}\"")]
+    Decode {
+        path: String,
+        #[source]
+        source: BoxError,
+    },
+
+    #[error("couldn't initialise graphics: {context}")]
+    GpuInit {
+        context: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+
+    #[error("couldn't save \"{path}\"")]
+    Save {
+        path: String,
+        #[source]
+        source: BoxError,
+    },
+
+    #[error("audio error: {context}")]
+    Audio {
+        context: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn decode(path: &str, source: impl Into<BoxError>) -> Self {
+        Self::Decode {
+            path: path.to_string(),
+            source: source.into(),
+        }
+    }
+
+    pub fn save(path: &str, source: impl Into<BoxError>) -> Self {
+        Self::Save {
+            path: path.to_string(),
+            source: source.into(),
+        }
+    }
+
+    pub fn gpu_init(context: &str, source: impl Into<BoxError>) -> Self {
+        Self::GpuInit {
+            context: context.to_string(),
+            source: Some(source.into()),
+        }
+    }
+
+    #[cfg(feature = "audio")]
+    pub fn audio(context: &str, source: impl Into<BoxError>) -> Self {
+        Self::Audio {
+            context: context.to_string(),
+            source: Some(source.into()),
+        }
+    }
+
+    /// Turns an io error from reading a file into the right kind of asset error
+    pub fn from_io(path: &str, error: std::io::Error) -> Self {
+        if error.kind() == std::io::ErrorKind::NotFound {
+            Self::AssetMissing {
+                path: path.to_string(),
+            }
+        } else {
+            Self::AssetLoad {
+                path: path.to_string(),
+                source: error.into(),
+            }
+        }
+    }
+
+    /// A copy of the error, for when more than one thing is waiting on it.
+    /// Sources can't be cloned, so they're kept as just their messages.
+    pub fn duplicate(&self) -> Self {
+        let source = |source: &BoxError| -> BoxError { source.to_string().into() };
+
+        match self {
+            Self::AssetMissing { path } => Self::AssetMissing { path: path.clone() },
+            Self::AssetCorrupt { path, detail } => Self::AssetCorrupt {
+                path: path.clone(),
+                detail: detail.clone(),
+            },
+            Self::AssetLoad { path, source: s } => Self::AssetLoad {
+                path: path.clone(),
+                source: source(s),
+            },
+            Self::Decode { path, source: s } => Self::Decode {
+                path: path.clone(),
+                source: source(s),
+            },
+            Self::GpuInit { context, source: s } => Self::GpuInit {
+                context: context.clone(),
+                source: s.as_ref().map(source),
+            },
+            Self::Save { path, source: s } => Self::Save {
+                path: path.clone(),
+                source: source(s),
+            },
+            Self::Audio { context, source: s } => Self::Audio {
+                context: context.clone(),
+                source: s.as_ref().map(source),
+            },
+        }
+    }
+
+    /// A suggestion for what the user could do about the error
+    pub fn hint(&self) -> &'static str {
+        match self {
+            Self::AssetMissing { .. } => {
+                "Make sure the assets folder is next to the executable (or being served alongside the page)."
+            }
+            Self::AssetLoad { .. } => "Check your connection and try reloading.",
+            Self::AssetCorrupt { .. } => {
+                if cfg!(target_arch = "wasm32") {
+                    "The download was probably cut off, or an old copy was cached. Try downloading the assets again."
+                } else {
+                    "The assets are out of date or damaged. If you changed them on purpose, update the asset manifest (see the README)."
+                }
+            }
+            Self::Decode { .. } => "The file might be corrupted or only partially downloaded.",
+            Self::Save { .. } => "Check that the file isn't read-only and the disk isn't full.",
+            Self::GpuInit { .. } => {
+                "Your browser or graphics driver might not support WebGPU/WebGL2. Try updating it."
+            }
+            Self::Audio { .. } => "Check that an audio output device is available.",
+        }
+    }
+
+    /// The error along with everything that caused it, one per line
+    pub fn report(&self) -> String {
+        let mut report = self.to_string();
+        let mut source = std::error::Error::source(self);
+
+        while let Some(error) = source {
+            report.push_str(&format!("\ncaused by: {error}"));
+            source = error.source();
+        }
+
+        report
+    }
+}
@@ -0,0 +1,83 @@
+//! Getting files to and from whoever's using it in the browser, where
+//! there's no file system to save to or load from. Natively files are just
+//! read and written at a path instead.
+
+use wasm_bindgen::{closure::Closure, JsCast};
+use wasm_bindgen_futures::{js_sys, JsFuture};
+
+use crate::{
+    error::{Error, Result},
+    tasks::{self, Task},
+};
+
+/// Has the browser download some text as a file
+pub fn download(filename: &str, contents: &str) -> Result<()> {
+    let link = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.create_element("a").ok())
+        .and_then(|element| element.dyn_into::<web_sys::HtmlAnchorElement>().ok())
+        .ok_or_else(|| Error::save(filename, "there's no page to download it from"))?;
+
+    link.set_href(&format!(
+        "data:text/plain;charset=utf-8,{}",
+        percent_encode(contents)
+    ));
+    link.set_download(filename.rsplit('/').next().unwrap_or(filename));
+    link.click();
+    Ok(())
+}
+
+/// Asks the browser to pick a file with one of the `accept`ed extensions
+/// (like ".json"), and reads it as text. The task finishes with its name and
+/// what's in it, or never if the picker's cancelled, since browsers don't
+/// say when that happens.
+pub fn upload(accept: &str) -> Result<Task<Result<(String, String)>>> {
+    let input = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.create_element("input").ok())
+        .and_then(|element| element.dyn_into::<web_sys::HtmlInputElement>().ok())
+        .ok_or_else(|| Error::decode(accept, "there's no page to upload it to"))?;
+
+    input.set_type("file");
+    input.set_accept(accept);
+
+    let (sender, picked) = futures::channel::oneshot::channel();
+    let on_change = Closure::once_into_js({
+        let input = input.clone();
+        move || {
+            if let Some(file) = input.files().and_then(|files| files.get(0)) {
+                let _ = sender.send(file);
+            }
+        }
+    });
+    input.set_onchange(Some(on_change.unchecked_ref()));
+    input.click();
+
+    Ok(tasks::spawn(async move {
+        let file: web_sys::File = picked
+            .await
+            .map_err(|_| Error::decode("upload", "the file picker went away"))?;
+        let name = file.name();
+
+        let text = JsFuture::from(file.text())
+            .await
+            .map_err(|e| Error::decode(&name, format!("{e:?}")))?;
+        let text = text
+            .dyn_into::<js_sys::JsString>()
+            .map_err(|_| Error::decode(&name, "it isn't text"))?;
+
+        Ok((name, text.into()))
+    }))
+}
+
+// Escapes everything but the characters that are always safe in a url
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
@@ -0,0 +1,171 @@
+use egui::{FontData, FontDefinitions, FontFamily};
+
+use crate::{
+    error::{Error, Result},
+    locale::Strings,
+    resources,
+    tasks::{self, Task},
+};
+
+// Compiled in so there's always something that covers more than egui's
+// default fonts, even if the custom font can't be loaded. The text drawn into
+// the scene uses it too.
+pub const FALLBACK_FONT: &[u8] = include_bytes!("../assets/fonts/DejaVuSans.ttf");
+
+/// Builds the font definitions for egui. The custom font (if there is one)
+/// goes first so it gets used wherever it has the glyphs, then egui's own
+/// fonts, then the embedded fallback for anything neither of them cover.
+pub fn font_definitions(custom: Option<Vec<u8>>) -> FontDefinitions {
+    let mut fonts = FontDefinitions::default();
+
+    fonts
+        .font_data
+        .insert("fallback".to_string(), FontData::from_static(FALLBACK_FONT));
+
+    for family in [FontFamily::Proportional, FontFamily::Monospace] {
+        fonts
+            .families
+            .entry(family)
+            .or_default()
+            .push("fallback".to_string());
+    }
+
+    if let Some(data) = custom {
+        fonts
+            .font_data
+            .insert("custom".to_string(), FontData::from_owned(data));
+
+        fonts
+            .families
+            .entry(FontFamily::Proportional)
+            .or_default()
+            .insert(0, "custom".to_string());
+
+        // Monospace text should stay monospace where it can, so the custom
+        // font only fills in the gaps there
+        let monospace = fonts.families.entry(FontFamily::Monospace).or_default();
+        let index = monospace.len() - 1;
+        monospace.insert(index, "custom".to_string());
+    }
+
+    fonts
+}
+
+/// Loads a ttf/otf file through the asset loader, making sure it's actually a
+/// font first (egui panics if it's given something it can't parse).
+pub async fn load_font(filename: &str) -> Result<Vec<u8>> {
+    let data = resources::load_bytes(filename).await?;
+
+    ab_glyph::FontRef::try_from_slice(&data).map_err(|e| Error::decode(filename, e))?;
+
+    Ok(data)
+}
+
+/// Keeps track of which custom font is in use, and loads new ones in the
+/// background when it changes.
+pub struct FontSettings {
+    /// The path typed into the settings menu
+    pub path: String,
+    /// The path of the custom font currently in use, if any
+    current: Option<String>,
+    pending: Option<(String, Task<Result<Vec<u8>>>)>,
+    pub error: Option<String>,
+}
+
+impl FontSettings {
+    pub fn new() -> Self {
+        Self {
+            path: String::new(),
+            current: None,
+            pending: None,
+            error: None,
+        }
+    }
+
+    /// Applies the default fonts (egui's plus the embedded fallback)
+    pub fn apply_default(&mut self, ctx: &egui::Context) {
+        self.current = None;
+        self.pending = None;
+        self.error = None;
+        ctx.set_fonts(font_definitions(None));
+    }
+
+    /// Starts loading a custom font. It gets applied by `poll` once it's loaded.
+    pub fn request(&mut self, path: String) {
+        let task = {
+            let path = path.clone();
+            tasks::spawn(async move { load_font(&path).await })
+        };
+
+        self.path = path.clone();
+        self.error = None;
+        self.pending = Some((path, task));
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    pub fn current(&self) -> Option<&str> {
+        self.current.as_deref()
+    }
+
+    /// Checks on the font being loaded and applies it if it's done.
+    /// If it failed, the current fonts are left alone.
+    pub fn poll(&mut self, ctx: &egui::Context) {
+        let Some((path, task)) = self.pending.as_mut() else {
+            return;
+        };
+
+        if let Some(result) = task.poll() {
+            match result {
+                Ok(data) => {
+                    log::info!("Using font {path}");
+                    ctx.set_fonts(font_definitions(Some(data)));
+                    self.current = Some(path.clone());
+                }
+
+                Err(error) => {
+                    log::error!("{}", error.report());
+                    self.error = Some(error.to_string());
+                }
+            }
+
+            self.pending = None;
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, ctx: &egui::Context, strings: &Strings) {
+        let current = self.current().unwrap_or(strings.get("font.default"));
+        ui.label(strings.format("font.current", &[("font", &current)]));
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("font.custom"));
+            ui.text_edit_singleline(&mut self.path);
+        });
+
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(
+                    !self.is_loading(),
+                    egui::Button::new(strings.get("font.load")),
+                )
+                .clicked()
+            {
+                self.request(self.path.trim().to_string());
+            }
+
+            if ui.button(strings.get("font.use_default")).clicked() {
+                self.apply_default(ctx);
+            }
+        });
+
+        if self.is_loading() {
+            ui.label(strings.get("font.loading"));
+        }
+
+        if let Some(error) = &self.error {
+            ui.colored_label(egui::Color32::LIGHT_RED, error);
+        }
+    }
+}
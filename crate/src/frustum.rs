@@ -0,0 +1,201 @@
+//! Working out what's on screen before anything gets sent to the gpu, so
+//! instances the camera can't see don't get uploaded or drawn. With a
+//! thousand reis that's a lot of vertices webgl2 doesn't have to get through
+//! whenever the camera's looking at only some of them.
+
+use cgmath::{InnerSpace, Matrix, Matrix4, Vector3, Vector4};
+
+use crate::model::{BoundingSphere, InstanceRaw};
+
+/// The six planes around what a camera can see, facing inwards
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// The planes of a world to clip space transform with wgpu's depth
+    /// range, where z goes from 0 to w
+    pub fn from_matrix(matrix: Matrix4<f32>) -> Self {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|i| matrix.row(i));
+
+        let planes = [w + x, w - x, w + y, w - y, z, w - z].map(|plane| {
+            // Normalised so the distances to them are in world units
+            let length = plane.truncate().magnitude();
+            if length > 0.0 {
+                plane / length
+            } else {
+                plane
+            }
+        });
+
+        Self { planes }
+    }
+
+    /// The planes as (normal, distance), for sending to a shader
+    pub fn planes(&self) -> [[f32; 4]; 6] {
+        self.planes.map(Into::into)
+    }
+
+    /// Whether any of the sphere's inside the frustum. Some spheres near the
+    /// corners that are actually outside it count as inside, which is fine
+    /// since they're just drawn for nothing.
+    pub fn intersects(&self, sphere: &BoundingSphere) -> bool {
+        let centre = Vector3::from(sphere.centre).extend(1.0);
+        self.planes
+            .iter()
+            .all(|plane| plane.dot(centre) >= -sphere.radius)
+    }
+}
+
+/// How many instances were drawn and how many were left out last frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CullCounts {
+    pub drawn: usize,
+    pub culled: usize,
+}
+
+impl CullCounts {
+    pub fn add(&mut self, other: CullCounts) {
+        self.drawn += other.drawn;
+        self.culled += other.culled;
+    }
+}
+
+/// Keeps the instances of a model with bounding sphere `sphere` that are in
+/// the frustum, in the same order
+pub fn cull(
+    frustum: &Frustum,
+    sphere: &BoundingSphere,
+    instances: impl IntoIterator<Item = InstanceRaw>,
+    kept: &mut Vec<InstanceRaw>,
+) -> CullCounts {
+    cull_casting(frustum, None, sphere, instances, kept, &mut Vec::new())
+}
+
+/// Like [cull], but the instances that are off screen and in the light's
+/// frustum `shadow` go into `casters`, since their shadows could still land
+/// on screen. They count as culled, since they aren't drawn on the screen.
+pub fn cull_casting(
+    frustum: &Frustum,
+    shadow: Option<&Frustum>,
+    sphere: &BoundingSphere,
+    instances: impl IntoIterator<Item = InstanceRaw>,
+    kept: &mut Vec<InstanceRaw>,
+    casters: &mut Vec<InstanceRaw>,
+) -> CullCounts {
+    let before = kept.len();
+    let mut culled = 0;
+
+    for instance in instances {
+        let around = sphere.around(&instance);
+
+        if frustum.intersects(&around) {
+            kept.push(instance);
+        } else {
+            culled += 1;
+            if shadow.is_some_and(|shadow| shadow.intersects(&around)) {
+                casters.push(instance);
+            }
+        }
+    }
+
+    CullCounts {
+        drawn: kept.len() - before,
+        culled,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{perspective, Deg, One, Point3, Quaternion};
+
+    use super::*;
+    use crate::{camera::OPENGL_TO_WGPU_MATRIX, model::Instance};
+
+    fn sphere(centre: [f32; 3], radius: f32) -> BoundingSphere {
+        BoundingSphere { centre, radius }
+    }
+
+    #[test]
+    fn spheres_are_culled_outside_each_plane() {
+        // At the origin looking down -z, seeing from 1 to 100 units away
+        let view = Matrix4::look_at_rh(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vector3::unit_y(),
+        );
+        let projection = OPENGL_TO_WGPU_MATRIX * perspective(Deg(90.0), 1.0, 1.0, 100.0);
+        let frustum = Frustum::from_matrix(projection * view);
+
+        assert!(frustum.intersects(&sphere([0.0, 0.0, -10.0], 0.5)));
+        // Behind, in front of the near plane and past the far plane
+        assert!(!frustum.intersects(&sphere([0.0, 0.0, 10.0], 0.5)));
+        assert!(!frustum.intersects(&sphere([0.0, 0.0, -0.2], 0.5)));
+        assert!(!frustum.intersects(&sphere([0.0, 0.0, -101.0], 0.5)));
+        // Off to each side, where the sides are 45 degrees out
+        for side in [[1.0, 0.0], [-1.0, 0.0], [0.0, 1.0], [0.0, -1.0]] {
+            let outside = sphere([side[0] * 12.0, side[1] * 12.0, -10.0], 0.5);
+            assert!(!frustum.intersects(&outside));
+
+            // Poking in over the edge
+            let touching = sphere([side[0] * 10.5, side[1] * 10.5, -10.0], 1.0);
+            assert!(frustum.intersects(&touching));
+        }
+    }
+
+    // At the origin looking down -z, seeing from `near` to `far` units away
+    fn looking_down_z(near: f32, far: f32) -> Frustum {
+        let view = Matrix4::look_at_rh(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vector3::unit_y(),
+        );
+        let projection = OPENGL_TO_WGPU_MATRIX * perspective(Deg(90.0), 1.0, near, far);
+        Frustum::from_matrix(projection * view)
+    }
+
+    #[test]
+    fn off_screen_instances_in_the_light_still_cast() {
+        let at = |z| {
+            Instance {
+                position: Vector3::new(0.0, 0.0, z),
+                rotation: Quaternion::one(),
+                scale: Vector3::new(1.0, 1.0, 1.0),
+                speed: 0.0,
+                alpha: 1.0,
+            }
+            .to_raw()
+        };
+        let camera = looking_down_z(1.0, 10.0);
+        let light = looking_down_z(1.0, 100.0);
+
+        let mut kept = Vec::new();
+        let mut casters = Vec::new();
+        let counts = cull_casting(
+            &camera,
+            Some(&light),
+            &sphere([0.0; 3], 0.5),
+            [-5.0, -50.0, 5.0].map(at),
+            &mut kept,
+            &mut casters,
+        );
+
+        let z = |instances: &[InstanceRaw]| {
+            instances
+                .iter()
+                .map(|i| i.position()[2])
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            counts,
+            CullCounts {
+                drawn: 1,
+                culled: 2
+            }
+        );
+        assert_eq!(z(&kept), [-5.0]);
+        // The one behind both of them doesn't cast anything
+        assert_eq!(z(&casters), [-50.0]);
+    }
+}
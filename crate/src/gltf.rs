@@ -0,0 +1,642 @@
+//! Gets meshes out of glTF 2.0 files exported from Blender, using the gltf
+//! crate: triangle meshes with positions, normals and texture coordinates,
+//! materials with a base colour (and texture), and the node hierarchy's
+//! transforms. Animations, skins and morph targets are ignored.
+//!
+//! Both .gltf (with its buffers in other files or embedded as data uris) and
+//! binary .glb files work. The crate can only load other files from the file
+//! system, so we find them here and they're fetched through the assets like
+//! everything else. See https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html
+
+use std::borrow::Cow;
+
+use base64::Engine;
+use cgmath::{Matrix, Matrix3, Matrix4, SquareMatrix, Vector3, Vector4};
+use gltf::accessor::{DataType, Dimensions, Item};
+use gltf::json::validation::Checked;
+use gltf::{Accessor, Document, Semantic};
+use serde::Deserialize;
+
+use crate::model::Surface;
+
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct GltfError(String);
+
+impl From<gltf::Error> for GltfError {
+    fn from(e: gltf::Error) -> Self {
+        GltfError(e.to_string())
+    }
+}
+
+type Result<T> = std::result::Result<T, GltfError>;
+
+fn error<T>(message: impl Into<String>) -> Result<T> {
+    Err(GltfError(message.into()))
+}
+
+// The crate fills in the spec's defaults for these, but we need to know if
+// they were there at all (see Gltf::materials)
+#[derive(Deserialize)]
+struct Factors {
+    #[serde(default)]
+    materials: Vec<MaterialFactors>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct MaterialFactors {
+    #[serde(default)]
+    pbr_metallic_roughness: PbrFactors,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct PbrFactors {
+    metallic_factor: Option<f32>,
+    roughness_factor: Option<f32>,
+}
+
+/// Where a buffer or image's data comes from
+pub enum Source<'a> {
+    Data(Cow<'a, [u8]>),
+    /// A path relative to the gltf file
+    File(String),
+}
+
+/// A triangle mesh, with the transforms of the nodes it's in already applied
+pub struct MeshData {
+    pub name: String,
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+    /// An index into [Gltf::materials]. Primitives without a material of
+    /// their own get the default one on the end.
+    pub material: usize,
+}
+
+pub struct MaterialData {
+    pub name: String,
+    /// Multiplied with the texture, or used as the colour if there isn't one
+    pub base_colour: [f32; 4],
+    pub texture: Option<Source<'static>>,
+    pub surface: Surface,
+}
+
+/// A parsed gltf or glb file. Its buffers have to be loaded (see
+/// [Gltf::buffer_sources]) before the meshes can be read out of it.
+pub struct Gltf<'a> {
+    document: Document,
+    factors: Vec<MaterialFactors>,
+    // The binary chunk of a glb file
+    bin: Option<Cow<'a, [u8]>>,
+}
+
+impl<'a> Gltf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        let (json, bin) = if data.starts_with(b"glTF") {
+            let glb = gltf::Glb::from_slice(data)?;
+            (glb.json, glb.bin)
+        } else {
+            (Cow::Borrowed(data), None)
+        };
+
+        let root = gltf::json::Root::from_slice(&json).map_err(|e| GltfError(e.to_string()))?;
+        let factors = serde_json::from_slice::<Factors>(&json)
+            .map_err(|e| GltfError(e.to_string()))?
+            .materials;
+
+        // The crate's validation looks the position accessors up without
+        // checking they're there first
+        let position_accessors = root
+            .meshes
+            .iter()
+            .flat_map(|mesh| mesh.primitives.iter())
+            .filter_map(|primitive| {
+                primitive
+                    .attributes
+                    .get(&Checked::Valid(Semantic::Positions))
+            });
+        for accessor in position_accessors {
+            if accessor.value() >= root.accessors.len() {
+                return error(format!("accessor {} doesn't exist", accessor.value()));
+            }
+        }
+
+        // This checks every index in the file is in range, so from here on
+        // nothing can point at something missing
+        let document = Document::from_json(root)?;
+
+        Ok(Self {
+            document,
+            factors,
+            bin,
+        })
+    }
+
+    /// Where each of the buffers' data is. Any files have to be loaded and
+    /// passed to [Gltf::meshes] in the same order.
+    pub fn buffer_sources(&self) -> Result<Vec<Source<'_>>> {
+        self.document
+            .buffers()
+            .map(|buffer| match buffer.source() {
+                gltf::buffer::Source::Uri(uri) => uri_source(uri),
+                gltf::buffer::Source::Bin => match self.bin.as_deref() {
+                    Some(bin) => Ok(Source::Data(Cow::Borrowed(bin))),
+                    None => error("buffer has no uri and there's no glb binary chunk"),
+                },
+            })
+            .collect()
+    }
+
+    /// Every mesh in the default scene, with the node transforms applied.
+    /// If there's no scene, the meshes are used as they are.
+    pub fn meshes(&self, buffers: &[&[u8]]) -> Result<Vec<MeshData>> {
+        let mut meshes = Vec::new();
+
+        let scene = self
+            .document
+            .default_scene()
+            .or_else(|| self.document.scenes().next());
+
+        match scene {
+            Some(scene) => {
+                for node in scene.nodes() {
+                    self.add_node(node, Matrix4::identity(), buffers, &mut meshes, 0)?;
+                }
+            }
+
+            None => {
+                for mesh in self.document.meshes() {
+                    self.add_mesh(mesh, None, Matrix4::identity(), buffers, &mut meshes)?;
+                }
+            }
+        }
+
+        Ok(meshes)
+    }
+
+    fn add_node(
+        &self,
+        node: gltf::Node,
+        parent: Matrix4<f32>,
+        buffers: &[&[u8]],
+        meshes: &mut Vec<MeshData>,
+        depth: usize,
+    ) -> Result<()> {
+        // The hierarchy is meant to be a tree, but that's not checked anywhere
+        if depth > self.document.nodes().len() {
+            return error("node hierarchy has a cycle");
+        }
+
+        let transform = parent * Matrix4::from(node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            self.add_mesh(mesh, node.name(), transform, buffers, meshes)?;
+        }
+
+        for child in node.children() {
+            self.add_node(child, transform, buffers, meshes, depth + 1)?;
+        }
+
+        Ok(())
+    }
+
+    fn add_mesh(
+        &self,
+        mesh: gltf::Mesh,
+        node_name: Option<&str>,
+        transform: Matrix4<f32>,
+        buffers: &[&[u8]],
+        meshes: &mut Vec<MeshData>,
+    ) -> Result<()> {
+        let name = node_name
+            .or(mesh.name())
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("mesh {}", mesh.index()));
+
+        // Normals need the inverse transpose, in case of non-uniform scaling
+        let normal_matrix = Matrix3::from_cols(
+            transform.x.truncate(),
+            transform.y.truncate(),
+            transform.z.truncate(),
+        )
+        .invert()
+        .unwrap_or(Matrix3::identity())
+        .transpose();
+
+        let primitive_count = mesh.primitives().len();
+        for (i, primitive) in mesh.primitives().enumerate() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                log::warn!("Skipping primitive {i} of mesh \"{name}\", it isn't made of triangles");
+                continue;
+            }
+
+            let Some(position_accessor) = primitive.get(&Semantic::Positions) else {
+                log::warn!("Skipping primitive {i} of mesh \"{name}\", it has no positions");
+                continue;
+            };
+
+            let positions = read_vec3s(&position_accessor, buffers)?
+                .into_iter()
+                .map(|p| {
+                    (transform * Vector4::new(p[0], p[1], p[2], 1.0))
+                        .truncate()
+                        .into()
+                })
+                .collect::<Vec<[f32; 3]>>();
+
+            let indices = match primitive.indices() {
+                Some(accessor) => read_indices(&accessor, buffers)?,
+                None => (0..positions.len() as u32).collect(),
+            };
+
+            if let Some(&bad) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+                return error(format!("mesh \"{name}\" has an index out of range ({bad})"));
+            }
+
+            let normals = match primitive.get(&Semantic::Normals) {
+                Some(accessor) => read_vec3s(&accessor, buffers)?
+                    .into_iter()
+                    .map(|n| normalise(normal_matrix * Vector3::from(n)))
+                    .collect(),
+                None => smooth_normals(&positions, &indices),
+            };
+
+            let tex_coords = match primitive.get(&Semantic::TexCoords(0)) {
+                Some(accessor) => read_tex_coords(&accessor, buffers)?,
+                None => vec![[0.0; 2]; positions.len()],
+            };
+
+            if normals.len() != positions.len() || tex_coords.len() != positions.len() {
+                return error(format!(
+                    "mesh \"{name}\" has a different number of positions and normals or texture coordinates"
+                ));
+            }
+
+            let name = match primitive_count {
+                1 => name.clone(),
+                _ => format!("{name} {i}"),
+            };
+
+            meshes.push(MeshData {
+                name,
+                positions,
+                normals,
+                tex_coords,
+                indices,
+                material: primitive
+                    .material()
+                    .index()
+                    .unwrap_or(self.default_material()),
+            });
+        }
+
+        Ok(())
+    }
+
+    // Where the default material goes in the list, after the file's own
+    fn default_material(&self) -> usize {
+        self.document.materials().len()
+    }
+
+    /// Every material in the file, then a plain white one for any primitives
+    /// that don't have one.
+    pub fn materials(&self, buffers: &[&[u8]]) -> Result<Vec<MaterialData>> {
+        let mut materials = self
+            .document
+            .materials()
+            .enumerate()
+            .map(|(i, material)| {
+                let pbr = material.pbr_metallic_roughness();
+
+                let texture = match pbr.base_color_texture() {
+                    Some(info) => Some(texture_source(info.texture().source(), buffers)?),
+                    None => None,
+                };
+
+                // These default to 1 in the spec, but most of what's exported
+                // without them wasn't meant to look like rough metal
+                let factors = self
+                    .factors
+                    .get(i)
+                    .map(|factors| &factors.pbr_metallic_roughness);
+                let surface = match factors {
+                    Some(PbrFactors {
+                        metallic_factor: None,
+                        roughness_factor: None,
+                    })
+                    | None => Surface::default(),
+                    Some(_) => {
+                        Surface::from_roughness(pbr.metallic_factor(), pbr.roughness_factor())
+                    }
+                };
+
+                Ok(MaterialData {
+                    name: material
+                        .name()
+                        .map(str::to_string)
+                        .unwrap_or_else(|| format!("material {i}")),
+                    base_colour: pbr.base_color_factor(),
+                    texture,
+                    surface,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let needs_default = self
+            .document
+            .meshes()
+            .flat_map(|mesh| mesh.primitives())
+            .any(|primitive| primitive.material().index().is_none());
+        if needs_default {
+            materials.push(MaterialData {
+                name: "default material".to_string(),
+                base_colour: [1.0; 4],
+                texture: None,
+                surface: Surface::default(),
+            });
+        }
+
+        Ok(materials)
+    }
+}
+
+fn texture_source(image: gltf::Image, buffers: &[&[u8]]) -> Result<Source<'static>> {
+    match image.source() {
+        gltf::image::Source::Uri { uri, .. } => uri_source(uri).map(|source| match source {
+            Source::Data(data) => Source::Data(Cow::Owned(data.into_owned())),
+            Source::File(path) => Source::File(path),
+        }),
+        gltf::image::Source::View { view, .. } => {
+            let bytes = buffers
+                .get(view.buffer().index())
+                .and_then(|buffer| buffer.get(view.offset()..view.offset() + view.length()))
+                .ok_or_else(|| {
+                    GltfError(format!("buffer view {} is out of range", view.index()))
+                })?;
+            Ok(Source::Data(Cow::Owned(bytes.to_vec())))
+        }
+    }
+}
+
+// Checks an accessor holds what we're about to read it as. The crate's
+// reader assumes it does.
+fn check_accessor(accessor: &Accessor, dimensions: Dimensions, types: &[DataType]) -> Result<()> {
+    if accessor.dimensions() != dimensions || !types.contains(&accessor.data_type()) {
+        return error(format!(
+            "accessor {} is {:?} {:?} but should be {dimensions:?}",
+            accessor.index(),
+            accessor.data_type(),
+            accessor.dimensions(),
+        ));
+    }
+
+    Ok(())
+}
+
+// Every element of an accessor, once its type's been checked to match T
+fn read<T: Item + Copy>(accessor: &Accessor, buffers: &[&[u8]]) -> Result<Vec<T>> {
+    // The crate can't read empty accessors
+    if accessor.count() == 0 {
+        return Ok(Vec::new());
+    }
+
+    // An accessor without a buffer view (or sparse values) is all zeroes
+    if accessor.view().is_none() && accessor.sparse().is_none() {
+        return Ok(vec![T::zero(); accessor.count()]);
+    }
+
+    gltf::accessor::Iter::new(accessor.clone(), |buffer: gltf::Buffer| {
+        buffers.get(buffer.index()).copied()
+    })
+    .map(Iterator::collect)
+    .ok_or_else(|| GltfError(format!("accessor {} is out of range", accessor.index())))
+}
+
+// Positions and normals are always floats
+fn read_vec3s(accessor: &Accessor, buffers: &[&[u8]]) -> Result<Vec<[f32; 3]>> {
+    check_accessor(accessor, Dimensions::Vec3, &[DataType::F32])?;
+    read(accessor, buffers)
+}
+
+// Texture coordinates can also be normalised integers
+fn read_tex_coords(accessor: &Accessor, buffers: &[&[u8]]) -> Result<Vec<[f32; 2]>> {
+    check_accessor(
+        accessor,
+        Dimensions::Vec2,
+        &[DataType::F32, DataType::U8, DataType::U16],
+    )?;
+
+    let scale = |max: f32| if accessor.normalized() { max } else { 1.0 };
+    Ok(match accessor.data_type() {
+        DataType::U8 => read::<[u8; 2]>(accessor, buffers)?
+            .into_iter()
+            .map(|uv| uv.map(|c| c as f32 / scale(255.0)))
+            .collect(),
+        DataType::U16 => read::<[u16; 2]>(accessor, buffers)?
+            .into_iter()
+            .map(|uv| uv.map(|c| c as f32 / scale(65535.0)))
+            .collect(),
+        _ => read(accessor, buffers)?,
+    })
+}
+
+fn read_indices(accessor: &Accessor, buffers: &[&[u8]]) -> Result<Vec<u32>> {
+    check_accessor(
+        accessor,
+        Dimensions::Scalar,
+        &[DataType::U8, DataType::U16, DataType::U32],
+    )?;
+
+    Ok(match accessor.data_type() {
+        DataType::U8 => read::<u8>(accessor, buffers)?
+            .into_iter()
+            .map(u32::from)
+            .collect(),
+        DataType::U16 => read::<u16>(accessor, buffers)?
+            .into_iter()
+            .map(u32::from)
+            .collect(),
+        _ => read(accessor, buffers)?,
+    })
+}
+
+// Embedded data gets decoded, anything else is a path to another file
+fn uri_source<'a>(uri: &str) -> Result<Source<'a>> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let Some((_, encoded)) = data.split_once(";base64,") else {
+            return error("only base64 data uris are supported");
+        };
+
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|e| GltfError(format!("couldn't decode data uri: {e}")))?;
+
+        return Ok(Source::Data(Cow::Owned(bytes)));
+    }
+
+    // Blender only really escapes spaces
+    Ok(Source::File(uri.replace("%20", " ")))
+}
+
+fn normalise(vector: Vector3<f32>) -> [f32; 3] {
+    use cgmath::InnerSpace;
+
+    if vector.magnitude2() > 0.0 {
+        vector.normalize().into()
+    } else {
+        [0.0, 1.0, 0.0]
+    }
+}
+
+// For meshes without normals. Each vertex gets the average of the normals of
+// the triangles it's part of, weighted by their area.
+fn smooth_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+    let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); positions.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| Vector3::from(positions[triangle[i] as usize]));
+        let normal = (b - a).cross(c - a);
+
+        for &i in triangle {
+            normals[i as usize] += normal;
+        }
+    }
+
+    normals.into_iter().map(normalise).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One triangle, with its buffer embedded as a data uri and a node that
+    // moves it up by 1
+    const TRIANGLE: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "name": "tri", "mesh": 0, "translation": [0.0, 1.0, 0.0] }],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1 }] }],
+        "accessors": [
+            {
+                "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                "min": [0.0, 0.0, 0.0], "max": [1.0, 0.0, 1.0]
+            },
+            { "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }
+        ],
+        "bufferViews": [
+            { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 36, "byteLength": 6 }
+        ],
+        "buffers": [{ "byteLength": 42, "uri": "data:application/octet-stream;base64,DATA" }]
+    }"#;
+
+    fn triangle_buffer() -> Vec<u8> {
+        let positions: [f32; 9] = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0];
+        let indices: [u16; 3] = [0, 2, 1];
+
+        let mut bytes = bytemuck::cast_slice::<f32, u8>(&positions).to_vec();
+        bytes.extend_from_slice(bytemuck::cast_slice(&indices));
+        bytes
+    }
+
+    fn load(gltf: &Gltf) -> Vec<MeshData> {
+        let sources = gltf.buffer_sources().unwrap();
+        let buffers = sources
+            .iter()
+            .map(|source| match source {
+                Source::Data(data) => &data[..],
+                Source::File(path) => panic!("unexpected file {path}"),
+            })
+            .collect::<Vec<_>>();
+
+        gltf.meshes(&buffers).unwrap()
+    }
+
+    #[test]
+    fn reads_embedded_triangle() {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(triangle_buffer());
+        let json = TRIANGLE.replace("DATA", &encoded);
+
+        let gltf = Gltf::parse(json.as_bytes()).unwrap();
+        let meshes = load(&gltf);
+
+        assert_eq!(meshes.len(), 1);
+        let mesh = &meshes[0];
+        assert_eq!(mesh.name, "tri");
+        assert_eq!(mesh.indices, vec![0, 2, 1]);
+        assert_eq!(mesh.positions[1], [1.0, 1.0, 0.0]);
+        // Worked out from the winding, since the file doesn't have any
+        assert_eq!(mesh.normals[0], [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn reads_glb() {
+        let json = TRIANGLE.replace(
+            r#", "uri": "data:application/octet-stream;base64,DATA""#,
+            "",
+        );
+        let mut json = json.into_bytes();
+        while !json.len().is_multiple_of(4) {
+            json.push(b' ');
+        }
+        let mut bin = triangle_buffer();
+        while !bin.len().is_multiple_of(4) {
+            bin.push(0);
+        }
+
+        let mut glb = Vec::new();
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&((12 + 8 + json.len() + 8 + bin.len()) as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(&json);
+        glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"BIN\0");
+        glb.extend_from_slice(&bin);
+
+        let gltf = Gltf::parse(&glb).unwrap();
+        let meshes = load(&gltf);
+
+        assert_eq!(meshes[0].positions[2], [0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn missing_buffer_views_are_an_error() {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(triangle_buffer());
+        let json = TRIANGLE
+            .replace("DATA", &encoded)
+            .replace(r#""bufferView": 1"#, r#""bufferView": 7"#);
+
+        assert!(Gltf::parse(json.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn missing_materials_are_an_error() {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(triangle_buffer());
+        let json = TRIANGLE
+            .replace("DATA", &encoded)
+            .replace(r#""indices": 1"#, r#""indices": 1, "material": 3"#);
+
+        assert!(Gltf::parse(json.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn primitives_without_a_material_get_a_white_one() {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(triangle_buffer());
+        let json = TRIANGLE.replace("DATA", &encoded);
+
+        let gltf = Gltf::parse(json.as_bytes()).unwrap();
+        let meshes = load(&gltf);
+        let materials = gltf.materials(&[]).unwrap();
+
+        assert_eq!(materials.len(), 1);
+        let material = &materials[meshes[0].material];
+        assert_eq!(material.base_colour, [1.0; 4]);
+        assert!(material.texture.is_none());
+    }
+}
@@ -0,0 +1,351 @@
+//! Culling the reis in a compute shader instead of on the cpu, for gpus that
+//! can. Every rei gets uploaded as usual, then the shader packs the ones the
+//! camera can see into another buffer and counts them into indirect draws,
+//! so with thousands of reis nothing has to go through them one by one on
+//! the cpu. Webgl2 has neither compute shaders nor indirect draws, so there
+//! they're still culled in [crate::render_inputs::RenderInputs::extract].
+//!
+//! The shadow map and the sprites still draw from the full instance buffer,
+//! so reis just off screen keep their shadows.
+
+use wgpu::util::DrawIndexedIndirect;
+
+use crate::{
+    clusters::EmitterClusters,
+    frustum::Frustum,
+    model::{InstanceBuffer, InstanceRaw, Model},
+    physics::Cluster,
+    sync_audit::AuditedQueue,
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+const INSTANCE_SIZE: u64 = std::mem::size_of::<InstanceRaw>() as u64;
+// The shader reads the instances as plain floats, and has to be told how many
+// there are in each (INSTANCE_FLOATS in cull_shader.wgsl)
+const _: () = assert!(
+    INSTANCE_SIZE == 27 * 4,
+    "update INSTANCE_FLOATS in cull_shader.wgsl"
+);
+const DRAW_SIZE: u64 = std::mem::size_of::<DrawIndexedIndirect>() as u64;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct CullUniform {
+    planes: [[f32; 4]; 6],
+    sphere: [f32; 4],
+    num_instances: u32,
+    num_clusters: u32,
+    num_meshes: u32,
+    _padding: u32,
+}
+
+pub struct GpuCulling {
+    cull_pipeline: wgpu::ComputePipeline,
+    copy_counts_pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    // The visible instances, each cluster's starting where it does in the
+    // full instance buffer
+    visible: wgpu::Buffer,
+    visible_capacity: usize,
+    cluster_starts: wgpu::Buffer,
+    // The draws as they are before any instances have been counted into
+    // them, copied over the real ones before each cull
+    blank_draws: wgpu::Buffer,
+    draws: wgpu::Buffer,
+    // How many draws (clusters times meshes) the buffers have room for
+    draw_capacity: usize,
+    // Made again whenever any of the buffers it has in it are
+    bind_group: Option<(wgpu::Id<wgpu::Buffer>, wgpu::BindGroup)>,
+    num_instances: u32,
+    num_clusters: u32,
+    num_meshes: usize,
+}
+
+impl GpuCulling {
+    /// Whether the gpu can cull things itself. This is decided once at the
+    /// start, with the cpu culling as the fallback.
+    pub fn is_supported(adapter: &wgpu::Adapter, device: &wgpu::Device) -> bool {
+        let needed =
+            wgpu::DownlevelFlags::COMPUTE_SHADERS | wgpu::DownlevelFlags::INDIRECT_EXECUTION;
+
+        adapter.get_info().backend != wgpu::Backend::Gl
+            && adapter.get_downlevel_capabilities().flags.contains(needed)
+            && device.limits().max_storage_buffers_per_shader_stage >= 4
+    }
+
+    pub fn new(device: &wgpu::Device, shader: &wgpu::ShaderModule) -> Self {
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("cull bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, false),
+                storage(3, true),
+                storage(4, false),
+            ],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("cull pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = |label, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                module: shader,
+                entry_point,
+            })
+        };
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("cull uniform buffer"),
+            size: std::mem::size_of::<CullUniform>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // Room for the statue and a few emitters, with a few meshes each.
+        // They grow if there are more.
+        let visible_capacity = 1;
+        let draw_capacity = 32;
+        let (cluster_starts, blank_draws, draws) = create_draw_buffers(device, draw_capacity);
+
+        Self {
+            cull_pipeline: pipeline("cull pipeline", "cull"),
+            copy_counts_pipeline: pipeline("cull count copying pipeline", "copy_counts"),
+            bind_group_layout,
+            uniform_buffer,
+            visible: create_visible_buffer(device, visible_capacity),
+            visible_capacity,
+            cluster_starts,
+            blank_draws,
+            draws,
+            draw_capacity,
+            bind_group: None,
+            num_instances: 0,
+            num_clusters: 0,
+            num_meshes: 0,
+        }
+    }
+
+    /// Sets up this frame's culling of the reis in `instances`, which are
+    /// drawn with `model`
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &AuditedQueue,
+        instances: &InstanceBuffer,
+        clusters: &[Cluster],
+        frustum: &Frustum,
+        model: &Model,
+    ) {
+        let sphere = model.bounds.bounding_sphere();
+        let [x, y, z] = sphere.centre;
+
+        self.num_instances = instances.len();
+        self.num_clusters = clusters.len() as u32;
+        self.num_meshes = model.meshes.len();
+
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[CullUniform {
+                planes: frustum.planes(),
+                sphere: [x, y, z, sphere.radius],
+                num_instances: self.num_instances,
+                num_clusters: self.num_clusters,
+                num_meshes: self.num_meshes as u32,
+                _padding: 0,
+            }]),
+        );
+
+        let mut rebind = false;
+
+        if instances.capacity() > self.visible_capacity {
+            self.visible_capacity = instances.capacity();
+            self.visible = create_visible_buffer(device, self.visible_capacity);
+            rebind = true;
+        }
+
+        // The cluster starts are in buffers this size too
+        let num_draws = clusters.len() * self.num_meshes;
+        let needed = num_draws.max(clusters.len());
+        if needed > self.draw_capacity {
+            self.draw_capacity = needed.next_power_of_two();
+            (self.cluster_starts, self.blank_draws, self.draws) =
+                create_draw_buffers(device, self.draw_capacity);
+            rebind = true;
+        }
+
+        let input = instances.buffer();
+        if rebind || self.bind_group.as_ref().map(|(id, _)| *id) != Some(input.global_id()) {
+            self.bind_group = Some((input.global_id(), self.create_bind_group(device, input)));
+        }
+
+        let starts: Vec<u32> = clusters
+            .iter()
+            .map(|cluster| cluster.instances.start)
+            .collect();
+        if !starts.is_empty() {
+            queue.write_buffer(&self.cluster_starts, 0, bytemuck::cast_slice(&starts));
+        }
+
+        let mut blank = Vec::with_capacity(num_draws * DRAW_SIZE as usize);
+        for _ in clusters {
+            for mesh in model.meshes.iter() {
+                let draw = DrawIndexedIndirect {
+                    vertex_count: mesh.num_indices,
+                    ..Default::default()
+                };
+                blank.extend_from_slice(draw.as_bytes());
+            }
+        }
+        if !blank.is_empty() {
+            queue.write_buffer(&self.blank_draws, 0, &blank);
+        }
+    }
+
+    fn create_bind_group(&self, device: &wgpu::Device, input: &wgpu::Buffer) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("cull bind group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: input.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.visible.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.cluster_starts.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: self.draws.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Culls the reis, which has to happen before they're drawn
+    pub fn dispatch(&self, encoder: &mut wgpu::CommandEncoder) {
+        let Some((_, bind_group)) = &self.bind_group else {
+            return;
+        };
+        if self.num_instances == 0 {
+            return;
+        }
+
+        let num_draws = self.num_clusters as u64 * self.num_meshes as u64;
+        encoder.copy_buffer_to_buffer(&self.blank_draws, 0, &self.draws, 0, num_draws * DRAW_SIZE);
+
+        // Separate passes, so every cluster's been counted before the counts
+        // get copied
+        for (pipeline, invocations) in [
+            (&self.cull_pipeline, self.num_instances),
+            (&self.copy_counts_pipeline, self.num_clusters),
+        ] {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("cull pass"),
+            });
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(invocations.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+    }
+
+    /// Draws mesh number `mesh` of the rei model at each visible cluster's
+    /// reis that made it through the culling. Its vertex and index buffers
+    /// should already be set.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        clusters: &'a EmitterClusters,
+        group: u32,
+        mesh: usize,
+    ) {
+        clusters.draw_indexed(render_pass, group, |render_pass, cluster, instances| {
+            let start = instances.start as u64 * INSTANCE_SIZE;
+            render_pass.set_vertex_buffer(1, self.visible.slice(start..));
+
+            let draw = (cluster * self.num_meshes + mesh) as u64;
+            render_pass.draw_indexed_indirect(&self.draws, draw * DRAW_SIZE);
+        });
+    }
+}
+
+fn create_visible_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Culled rei instance buffer"),
+        size: INSTANCE_SIZE * capacity.max(1) as u64,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    })
+}
+
+// The clusters' starts, the blank draws and the draws, for this many draws
+fn create_draw_buffers(
+    device: &wgpu::Device,
+    capacity: usize,
+) -> (wgpu::Buffer, wgpu::Buffer, wgpu::Buffer) {
+    let buffer = |label, size, usage| {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage,
+            mapped_at_creation: false,
+        })
+    };
+
+    let draws_size = DRAW_SIZE * capacity as u64;
+    (
+        buffer(
+            "Cull cluster start buffer",
+            4 * capacity as u64,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        ),
+        buffer(
+            "Blank indirect draw buffer",
+            draws_size,
+            wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+        ),
+        buffer(
+            "Indirect draw buffer",
+            draws_size,
+            wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::COPY_DST,
+        ),
+    )
+}
@@ -0,0 +1,196 @@
+//! How long the gpu spends on each part of a frame, measured with timestamp
+//! queries between its passes. Only some gpus can do these (webgl can't), and
+//! they're only written while the diagnostics are showing. The results come
+//! back a frame or two late, since they have to be copied off the gpu.
+
+use std::sync::{Arc, Mutex};
+
+/// The parts of a frame that get timed, in the order they happen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuPass {
+    /// Drawing the reis into the shadow map, from the light's point of view
+    Shadows,
+    /// Everything in the scene pass
+    Scene,
+    /// The particles, debug views and text drawn over the scene
+    Overlay,
+    /// Copying the scene onto the screen
+    Screen,
+    /// The ui
+    Ui,
+}
+
+impl GpuPass {
+    pub const ALL: [GpuPass; 5] = [
+        GpuPass::Shadows,
+        GpuPass::Scene,
+        GpuPass::Overlay,
+        GpuPass::Screen,
+        GpuPass::Ui,
+    ];
+
+    /// The key of its name in the language files
+    pub fn key(self) -> &'static str {
+        match self {
+            GpuPass::Shadows => "diagnostics.gpu_shadows",
+            GpuPass::Scene => "diagnostics.gpu_scene",
+            GpuPass::Overlay => "diagnostics.gpu_overlay",
+            GpuPass::Screen => "diagnostics.gpu_screen",
+            GpuPass::Ui => "diagnostics.gpu_ui",
+        }
+    }
+}
+
+// One at the start of the frame, then one as each pass finishes
+const NUM_TIMESTAMPS: u32 = GpuPass::ALL.len() as u32 + 1;
+const BUFFER_SIZE: u64 = NUM_TIMESTAMPS as u64 * std::mem::size_of::<u64>() as u64;
+
+pub struct GpuTimer {
+    /// Whether to time the next frames. It's turned on with the diagnostics.
+    pub enabled: bool,
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // Nanoseconds per tick
+    period: f32,
+    // Whether the current frame's being timed
+    timing: bool,
+    // Whether the readback buffer's waiting to be read, in which case frames
+    // don't get timed until it has been
+    pending: bool,
+    // Whether the readback buffer mapped, once it's done trying
+    mapped: Arc<Mutex<Option<bool>>>,
+    // How long each pass took in milliseconds, as of the last frame that got
+    // back from the gpu
+    timings: Option<[f32; GpuPass::ALL.len()]>,
+}
+
+impl GpuTimer {
+    /// The features the device needs, out of what the adapter has
+    pub fn features(adapter: &wgpu::Adapter) -> wgpu::Features {
+        adapter.features() & wgpu::Features::TIMESTAMP_QUERY
+    }
+
+    /// A timer, if the device can do timestamp queries
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("gpu timing query set"),
+            ty: wgpu::QueryType::Timestamp,
+            count: NUM_TIMESTAMPS,
+        });
+
+        let buffer = |label, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: BUFFER_SIZE,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+
+        Some(Self {
+            enabled: false,
+            query_set,
+            resolve_buffer: buffer(
+                "gpu timing resolve buffer",
+                wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            ),
+            readback_buffer: buffer(
+                "gpu timing readback buffer",
+                wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            ),
+            period: queue.get_timestamp_period(),
+            timing: false,
+            pending: false,
+            mapped: Arc::new(Mutex::new(None)),
+            timings: None,
+        })
+    }
+
+    /// How long each pass took in milliseconds, the last time it was timed
+    pub fn timings(&self) -> Option<[f32; GpuPass::ALL.len()]> {
+        self.timings
+    }
+
+    /// Starts timing a frame, if it's enabled and the last one's been read
+    pub fn start(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        self.timing = self.enabled && !self.pending;
+        if self.timing {
+            encoder.write_timestamp(&self.query_set, 0);
+        }
+    }
+
+    /// Marks the end of a pass
+    pub fn finish(&self, encoder: &mut wgpu::CommandEncoder, pass: GpuPass) {
+        if self.timing {
+            encoder.write_timestamp(&self.query_set, pass as u32 + 1);
+        }
+    }
+
+    /// Copies the frame's timestamps somewhere they can be read from, which
+    /// has to be the last thing in the frame
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.timing {
+            encoder.resolve_query_set(&self.query_set, 0..NUM_TIMESTAMPS, &self.resolve_buffer, 0);
+            encoder.copy_buffer_to_buffer(
+                &self.resolve_buffer,
+                0,
+                &self.readback_buffer,
+                0,
+                BUFFER_SIZE,
+            );
+        }
+    }
+
+    /// Starts reading the timestamps back, once the frame's been submitted
+    pub fn submitted(&mut self) {
+        if !self.timing {
+            return;
+        }
+
+        self.timing = false;
+        self.pending = true;
+        let mapped = self.mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                *mapped.lock().unwrap() = Some(result.is_ok());
+            });
+    }
+
+    /// Picks up the timings of the last frame that was timed, if they're back
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        if !self.pending {
+            return;
+        }
+
+        device.poll(wgpu::Maintain::Poll);
+        let Some(mapped) = self.mapped.lock().unwrap().take() else {
+            return;
+        };
+
+        // If it didn't map there's nothing to read, but the next frame can
+        // still be timed
+        self.pending = false;
+        if !mapped {
+            log::warn!("Couldn't read the gpu timings back");
+            return;
+        }
+
+        let timestamps: Vec<u64> = {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            bytemuck::cast_slice(&data).to_vec()
+        };
+        self.readback_buffer.unmap();
+
+        let period = self.period;
+        self.timings = Some(std::array::from_fn(|i| {
+            let ticks = timestamps[i + 1].saturating_sub(timestamps[i]);
+            ticks as f32 * period / 1_000_000.0
+        }));
+    }
+}
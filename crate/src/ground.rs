@@ -0,0 +1,704 @@
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use crate::{
+    camera::CameraBinding,
+    clusters::EmitterClusters,
+    layouts::LayoutCache,
+    locale::Strings,
+    physics::{GROUND_EXTENT, GROUND_LEVEL},
+    shadow::ShadowMap,
+    sync_audit::AuditedQueue,
+    texture,
+};
+
+// The heightfield covers a square this wide (in world units) around the
+// origin, which is plenty for the pile. Past it the ground is just flat.
+pub const DEFORM_EXTENT: f32 = 64.0;
+const HEIGHTFIELD_SIZE: u32 = 512;
+// The ground's mesh is this fine where it can be dented
+const CELL_SIZE: f32 = 0.25;
+// Past the dentable part the cells get bigger and bigger, out to the edge of
+// the physics ground, so there's always something under whatever's on it
+const OUTER_CELLS: usize = 10;
+// Room for this many splats to start with, the buffer grows if it needs more
+const INITIAL_SPLATS: usize = 256;
+// Impacts further than this above the ground don't leave a mark
+const SPLAT_REACH: f32 = 1.5;
+
+static GROUND_BIND_GROUP_LAYOUT: LayoutCache = LayoutCache::new();
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct GroundVertex {
+    // Just x and z, the height comes from the heightfield
+    position: [f32; 2],
+}
+
+impl GroundVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Float32x2];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<GroundVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct Splat {
+    centre: [f32; 2],
+    radius: f32,
+    strength: f32,
+}
+
+impl Splat {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 3] =
+        wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32, 2 => Float32];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Splat>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct GroundUniform {
+    level: f32,
+    extent: f32,
+    max_depth: f32,
+    // 0 for no checkerboard
+    tile_size: f32,
+}
+
+/// Settings for the dents and the checkerboard, tweakable from the ui
+#[derive(Debug, Clone)]
+pub struct GroundConfig {
+    /// Whether impacts dent the ground
+    pub deform: bool,
+    /// How far down (in world units) the deepest dents go
+    pub max_depth: f32,
+    /// How wide each impact's dent is
+    pub splat_radius: f32,
+    /// How much each impact adds to a dent. Dents get deeper more slowly the
+    /// deeper they already are, so this mostly affects how fast they form.
+    pub splat_strength: f32,
+    /// Whether the ground has a faint checkerboard on it, which makes it
+    /// easier to tell how far away things are and how fast they're going
+    pub checker: bool,
+    /// How wide each square of the checkerboard is, in world units
+    pub tile_size: f32,
+}
+
+impl Default for GroundConfig {
+    fn default() -> Self {
+        Self {
+            deform: true,
+            max_depth: 0.3,
+            splat_radius: 0.6,
+            splat_strength: 0.08,
+            checker: true,
+            tile_size: 2.0,
+        }
+    }
+}
+
+/// The texture format the heightfield is kept in. Half floats are best, but
+/// they can't always be drawn into (webgl needs an extension for it), in
+/// which case it's 8 bits and the dents are a bit blockier.
+pub fn heightfield_format(adapter: &wgpu::Adapter) -> wgpu::TextureFormat {
+    let features = adapter.get_texture_format_features(wgpu::TextureFormat::R16Float);
+    let drawable = features
+        .allowed_usages
+        .contains(wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING)
+        && features.flags.contains(
+            wgpu::TextureFormatFeatureFlags::BLENDABLE
+                | wgpu::TextureFormatFeatureFlags::FILTERABLE,
+        );
+
+    if drawable {
+        wgpu::TextureFormat::R16Float
+    } else {
+        wgpu::TextureFormat::R8Unorm
+    }
+}
+
+/// The ground under everything, which gets dented where things land on it.
+///
+/// The dents are purely for looks, the physics ground stays flat. Every
+/// impact near the ground draws a soft blob into a heightfield texture, and
+/// the ground's vertex shader pushes the mesh down by however much has built
+/// up there, so a pile that's been going for a while leaves a crater (and
+/// things that slide around leave trails).
+pub struct Ground {
+    pub config: GroundConfig,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+    heightfield_view: wgpu::TextureView,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    // Just the uniform, since the splats are drawn into the heightfield and
+    // can't read from it at the same time
+    splat_bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    splat_pipeline: wgpu::RenderPipeline,
+    heightfield_format: wgpu::TextureFormat,
+    splats: Vec<Splat>,
+    splat_buffer: wgpu::Buffer,
+    // How many splats were uploaded last
+    uploaded: u32,
+    // Whether the heightfield needs clearing before the next splats
+    needs_clear: bool,
+}
+
+impl Ground {
+    pub fn bind_group_layout(device: &wgpu::Device) -> &'static wgpu::BindGroupLayout {
+        GROUND_BIND_GROUP_LAYOUT.get(device, |device| {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("ground bind group layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            })
+        })
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        splat_shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        heightfield_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let config = GroundConfig::default();
+        let (vertices, indices) = ground_mesh();
+
+        let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("ground vertex buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("ground index buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let heightfield = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("ground heightfield"),
+            size: wgpu::Extent3d {
+                width: HEIGHTFIELD_SIZE,
+                height: HEIGHTFIELD_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: heightfield_format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let heightfield_view = heightfield.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("ground heightfield sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("ground uniform buffer"),
+            contents: bytemuck::cast_slice(&[config.uniform()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ground bind group"),
+            layout: Self::bind_group_layout(device),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&heightfield_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let splat_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("ground splat bind group layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+
+        let splat_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ground splat bind group"),
+            layout: &splat_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            config,
+            vertex_buffer,
+            index_buffer,
+            num_indices: indices.len() as _,
+            heightfield_view,
+            uniform_buffer,
+            bind_group,
+            splat_bind_group,
+            pipeline: create_pipeline(device, shader, format, sample_count),
+            splat_pipeline: create_splat_pipeline(
+                device,
+                splat_shader,
+                &splat_bind_group_layout,
+                heightfield_format,
+            ),
+            heightfield_format,
+            splats: Vec::new(),
+            splat_buffer: create_splat_buffer(device, INITIAL_SPLATS),
+            uploaded: 0,
+            needs_clear: true,
+        }
+    }
+
+    /// Recreates the pipeline for a scene with a different number of samples
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        self.pipeline = create_pipeline(device, shader, format, sample_count);
+    }
+
+    /// Smooths the ground back out
+    pub fn reset(&mut self) {
+        self.splats.clear();
+        self.needs_clear = true;
+    }
+
+    /// Dents the ground under an impact, if it was close enough to it
+    pub fn impact(&mut self, position: [f32; 3]) {
+        if !self.config.deform {
+            return;
+        }
+
+        let [x, y, z] = position;
+        let closeness = 1.0 - (y - GROUND_LEVEL) / SPLAT_REACH;
+        let half_extent = DEFORM_EXTENT / 2.0 + self.config.splat_radius;
+        if closeness <= 0.0 || x.abs() > half_extent || z.abs() > half_extent {
+            return;
+        }
+
+        self.splats.push(Splat {
+            centre: [x, z],
+            radius: self.config.splat_radius,
+            strength: self.config.splat_strength * closeness.min(1.0),
+        });
+    }
+
+    /// Uploads the settings and this frame's splats, making the buffer bigger
+    /// first if they don't fit
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &AuditedQueue) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.config.uniform()]),
+        );
+
+        let size = (self.splats.len() * std::mem::size_of::<Splat>()) as u64;
+        if size > self.splat_buffer.size() {
+            self.splat_buffer = create_splat_buffer(device, self.splats.len().next_power_of_two());
+        }
+
+        if !self.splats.is_empty() {
+            queue.write_buffer(&self.splat_buffer, 0, bytemuck::cast_slice(&self.splats));
+        }
+
+        self.uploaded = self.splats.len() as u32;
+    }
+
+    /// Draws the uploaded splats into the heightfield. This has to happen
+    /// before the ground is drawn.
+    pub fn render_splats(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if self.uploaded == 0 && !self.needs_clear {
+            return;
+        }
+
+        let load = if self.needs_clear {
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK)
+        } else {
+            wgpu::LoadOp::Load
+        };
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Ground splat pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.heightfield_view,
+                resolve_target: None,
+                ops: wgpu::Operations { load, store: true },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        if self.uploaded > 0 {
+            render_pass.set_pipeline(&self.splat_pipeline);
+            render_pass.set_bind_group(0, &self.splat_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, self.splat_buffer.slice(..));
+            render_pass.draw(0..6, 0..self.uploaded);
+        }
+
+        drop(render_pass);
+
+        self.splats.clear();
+        self.uploaded = 0;
+        self.needs_clear = false;
+    }
+
+    /// Draws a splat (whatever was last uploaded) into a heightfield of its
+    /// own, so the splat pipeline's been used before anything lands without
+    /// denting the real one
+    pub fn warm_up_splats(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        let view = device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("ground warm-up heightfield"),
+                size: wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: self.heightfield_format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Ground splat warm-up pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: false,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&self.splat_pipeline);
+        render_pass.set_bind_group(0, &self.splat_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.splat_buffer.slice(..));
+        render_pass.draw(0..6, 0..1);
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a CameraBinding,
+        clusters: &'a EmitterClusters,
+        shadow_map: &'a ShadowMap,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &camera.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        clusters.bind_plain(render_pass, 2);
+        render_pass.set_bind_group(3, &shadow_map.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, strings: &Strings) {
+        ui.checkbox(&mut self.config.deform, strings.get("ground.deform"));
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("ground.depth"));
+            ui.add(
+                egui::DragValue::new(&mut self.config.max_depth)
+                    .clamp_range(0.0..=2.0)
+                    .speed(0.01),
+            );
+        });
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("ground.size"));
+            ui.add(
+                egui::DragValue::new(&mut self.config.splat_radius)
+                    .clamp_range(0.1..=3.0)
+                    .speed(0.01),
+            );
+        });
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("ground.strength"));
+            ui.add(
+                egui::DragValue::new(&mut self.config.splat_strength)
+                    .clamp_range(0.01..=1.0)
+                    .speed(0.005),
+            );
+        });
+
+        if ui.button(strings.get("ground.smooth")).clicked() {
+            self.reset();
+        }
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.config.checker, strings.get("ground.checker"));
+            ui.add_enabled(
+                self.config.checker,
+                egui::DragValue::new(&mut self.config.tile_size)
+                    .clamp_range(0.25..=20.0)
+                    .speed(0.05),
+            );
+        });
+    }
+}
+
+impl GroundConfig {
+    fn uniform(&self) -> GroundUniform {
+        GroundUniform {
+            level: GROUND_LEVEL,
+            extent: DEFORM_EXTENT,
+            max_depth: self.max_depth,
+            tile_size: if self.checker { self.tile_size } else { 0.0 },
+        }
+    }
+}
+
+// Where the grid lines go along one axis: evenly spaced over the part that
+// can be dented, then further and further apart out to the horizon
+fn grid_lines() -> Vec<f32> {
+    let half = DEFORM_EXTENT / 2.0;
+    let inner_cells = (DEFORM_EXTENT / CELL_SIZE) as usize;
+    let growth = (GROUND_EXTENT / half).powf(1.0 / OUTER_CELLS as f32);
+
+    let outer = (1..=OUTER_CELLS).map(|i| half * growth.powi(i as i32));
+    let inner = (0..=inner_cells).map(|i| -half + i as f32 * CELL_SIZE);
+
+    outer
+        .clone()
+        .rev()
+        .map(|x| -x)
+        .chain(inner)
+        .chain(outer)
+        .collect()
+}
+
+fn ground_mesh() -> (Vec<GroundVertex>, Vec<u32>) {
+    let lines = grid_lines();
+    let n = lines.len() as u32;
+
+    let vertices = lines
+        .iter()
+        .flat_map(|&z| {
+            lines
+                .iter()
+                .map(move |&x| GroundVertex { position: [x, z] })
+        })
+        .collect();
+
+    let mut indices = Vec::with_capacity(((n - 1) * (n - 1) * 6) as usize);
+    for row in 0..n - 1 {
+        for column in 0..n - 1 {
+            let i = row * n + column;
+            // Counter-clockwise from above
+            indices.extend_from_slice(&[i, i + n, i + 1, i + 1, i + n, i + n + 1]);
+        }
+    }
+
+    (vertices, indices)
+}
+
+fn create_splat_buffer(device: &wgpu::Device, splats: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("ground splat buffer"),
+        size: (splats * std::mem::size_of::<Splat>()) as _,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("ground pipeline layout"),
+        bind_group_layouts: &[
+            CameraBinding::bind_group_layout(device),
+            Ground::bind_group_layout(device),
+            EmitterClusters::bind_group_layout(device),
+            ShadowMap::bind_group_layout(device),
+        ],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("ground pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[GroundVertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: Default::default(),
+            bias: Default::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+fn create_splat_pipeline(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("ground splat pipeline layout"),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    // Splats add up, so the same spot being hit over and over keeps getting deeper
+    let additive = wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    };
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("ground splat pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[Splat::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState {
+                    color: additive,
+                    alpha: additive,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: None,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: Default::default(),
+        multiview: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_covers_the_ground_in_order() {
+        let lines = grid_lines();
+
+        assert!(lines.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!((lines[0] + GROUND_EXTENT).abs() < 0.01);
+        assert!((lines[lines.len() - 1] - GROUND_EXTENT).abs() < 0.01);
+        // The edges of the dentable part are on grid lines
+        assert!(lines.contains(&(-DEFORM_EXTENT / 2.0)));
+    }
+}
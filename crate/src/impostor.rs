@@ -0,0 +1,433 @@
+use cgmath::{ortho, Matrix4, Point3, Vector3};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use crate::{
+    camera::{CameraBinding, OPENGL_TO_WGPU_MATRIX},
+    clusters::EmitterClusters,
+    model::{InstanceRaw, Model, ModelVertex, Vertex},
+    sync_audit::AuditedQueue,
+    texture,
+};
+
+// How many angles around the model are pre-rendered
+pub const IMPOSTOR_ANGLES: u32 = 8;
+// Size of each angle's cell in the atlas, in pixels
+const CELL_SIZE: u32 = 256;
+pub const ATLAS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Whether reis get drawn as meshes or sprites
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    /// Meshes, until the frame rate gets too low
+    Auto,
+    Meshes,
+    Sprites,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct SpriteUniform {
+    centre: [f32; 3],
+    radius: f32,
+    angles: u32,
+    _padding: [u32; 3],
+}
+
+/// Draws a model as flat sprites instead of meshes, for when the device can't
+/// keep up with rendering the real thing. The model gets rendered from a few
+/// angles around it into an atlas, and each instance then shows whichever
+/// angle is closest to how the camera sees it.
+pub struct SpriteRenderer {
+    atlas_view: wgpu::TextureView,
+    atlas_depth_view: wgpu::TextureView,
+    uniform_buffer: wgpu::Buffer,
+    bake_uniform_layout: wgpu::BindGroupLayout,
+    bake_pipeline: wgpu::RenderPipeline,
+    sprite_pipeline_layout: wgpu::PipelineLayout,
+    sprite_pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    baked: bool,
+}
+
+impl SpriteRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        bake_shader: &wgpu::ShaderModule,
+        sprite_shader: &wgpu::ShaderModule,
+        colour_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let atlas_size = wgpu::Extent3d {
+            width: CELL_SIZE * IMPOSTOR_ANGLES,
+            height: CELL_SIZE,
+            depth_or_array_layers: 1,
+        };
+
+        let atlas = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("impostor atlas"),
+            size: atlas_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: ATLAS_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let atlas_depth = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("impostor atlas depth"),
+            size: atlas_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: texture::Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+
+        let atlas_view = atlas.create_view(&wgpu::TextureViewDescriptor::default());
+        let atlas_depth_view = atlas_depth.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("impostor sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("sprite uniform buffer"),
+            size: std::mem::size_of::<SpriteUniform>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let sprite_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("sprite bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                uniform_layout_entry(2),
+            ],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("sprite bind group"),
+            layout: &sprite_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&atlas_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let bake_uniform_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("impostor bake bind group layout"),
+                entries: &[uniform_layout_entry(0)],
+            });
+
+        let bake_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("impostor bake pipeline layout"),
+            bind_group_layouts: &[
+                &bake_uniform_layout,
+                texture::Texture::texture_bind_group_layout(device),
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let bake_pipeline = create_pipeline(
+            device,
+            "impostor bake pipeline",
+            &bake_pipeline_layout,
+            bake_shader,
+            &[ModelVertex::desc()],
+            ATLAS_FORMAT,
+            1,
+        );
+
+        let sprite_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("sprite pipeline layout"),
+                bind_group_layouts: &[
+                    CameraBinding::bind_group_layout(device),
+                    &sprite_layout,
+                    EmitterClusters::bind_group_layout(device),
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let sprite_pipeline = create_pipeline(
+            device,
+            "sprite pipeline",
+            &sprite_pipeline_layout,
+            sprite_shader,
+            &[InstanceRaw::desc()],
+            colour_format,
+            sample_count,
+        );
+
+        Self {
+            atlas_view,
+            atlas_depth_view,
+            uniform_buffer,
+            bake_uniform_layout,
+            bake_pipeline,
+            sprite_pipeline_layout,
+            sprite_pipeline,
+            bind_group,
+            baked: false,
+        }
+    }
+
+    /// Recreates the sprite pipeline for a render target with a different
+    /// number of samples. The atlas doesn't need to be baked again.
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        sprite_shader: &wgpu::ShaderModule,
+        colour_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        self.sprite_pipeline = create_pipeline(
+            device,
+            "sprite pipeline",
+            &self.sprite_pipeline_layout,
+            sprite_shader,
+            &[InstanceRaw::desc()],
+            colour_format,
+            sample_count,
+        );
+    }
+
+    pub fn is_baked(&self) -> bool {
+        self.baked
+    }
+
+    /// Renders the model into the atlas from every angle. This only needs
+    /// to happen once, before the sprites are first drawn.
+    pub fn bake(&mut self, device: &wgpu::Device, queue: &AuditedQueue, model: &Model) {
+        let sphere = model.bounds.bounding_sphere();
+        let centre = Point3::from(sphere.centre);
+        // Big enough to fit the model whichever way it's facing
+        let radius = sphere.radius.max(0.01);
+
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[SpriteUniform {
+                centre: centre.into(),
+                radius,
+                angles: IMPOSTOR_ANGLES,
+                _padding: [0; 3],
+            }]),
+        );
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Impostor bake encoder"),
+        });
+
+        // Each angle needs its own camera, and the buffers have to stay alive
+        // until the commands are submitted
+        let bind_groups = (0..IMPOSTOR_ANGLES)
+            .map(|i| {
+                let angle = i as f32 / IMPOSTOR_ANGLES as f32 * std::f32::consts::TAU;
+                let direction = Vector3::new(angle.sin(), 0.0, angle.cos());
+                let eye = centre + direction * radius * 2.0;
+
+                let view = Matrix4::look_at_rh(eye, centre, Vector3::unit_y());
+                let projection = ortho(-radius, radius, -radius, radius, 0.01, radius * 4.0);
+                let view_proj: [[f32; 4]; 4] = (OPENGL_TO_WGPU_MATRIX * projection * view).into();
+
+                let buffer = device.create_buffer_init(&BufferInitDescriptor {
+                    label: Some("impostor bake buffer"),
+                    contents: bytemuck::cast_slice(&[view_proj]),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("impostor bake bind group"),
+                    layout: &self.bake_uniform_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Impostor bake pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.atlas_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.atlas_depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: false,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_pipeline(&self.bake_pipeline);
+
+        for (i, bind_group) in bind_groups.iter().enumerate() {
+            render_pass.set_viewport(
+                (i as u32 * CELL_SIZE) as f32,
+                0.0,
+                CELL_SIZE as f32,
+                CELL_SIZE as f32,
+                0.0,
+                1.0,
+            );
+            render_pass.set_bind_group(0, bind_group, &[]);
+
+            for mesh in model.meshes.iter() {
+                let Some(bind_group) = mesh
+                    .material
+                    .and_then(|material| model.materials[material].diffuse_bind_group.as_ref())
+                else {
+                    continue;
+                };
+
+                render_pass.set_bind_group(1, bind_group, &[]);
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass
+                    .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..mesh.num_indices, 0, 0..1);
+            }
+        }
+
+        drop(render_pass);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        self.baked = true;
+    }
+
+    /// Draws every visible instance as a sprite. The atlas has to have been baked first.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a CameraBinding,
+        instance_buffer: &'a wgpu::Buffer,
+        clusters: &'a EmitterClusters,
+    ) {
+        render_pass.set_pipeline(&self.sprite_pipeline);
+        render_pass.set_bind_group(0, &camera.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, instance_buffer.slice(..));
+        clusters.draw(render_pass, 2, |render_pass, instances| {
+            render_pass.draw(0..6, instances);
+        });
+    }
+
+    /// Draws the first instance as a sprite without a tint, whether there are
+    /// any reis or not, so the pipeline's been used before the sprites are.
+    /// It doesn't need baking first, since it's not drawn anywhere that's seen.
+    pub fn warm_up<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a CameraBinding,
+        instance_buffer: &'a wgpu::Buffer,
+        clusters: &'a EmitterClusters,
+    ) {
+        render_pass.set_pipeline(&self.sprite_pipeline);
+        render_pass.set_bind_group(0, &camera.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, instance_buffer.slice(..));
+        clusters.bind_plain(render_pass, 2);
+        render_pass.draw(0..6, 0..1);
+    }
+}
+
+fn uniform_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: vertex_layouts,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            // The sprites always face the camera anyway
+            cull_mode: None,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: Default::default(),
+            bias: Default::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+    })
+}
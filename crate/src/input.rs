@@ -1,43 +1,403 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 
+use instant::Instant;
 use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
 
+use crate::locale::Strings;
+
 // A very basic input system. Why did I write it myself?
 // because it's more work to figure out someone else's implementation.
+//
+// Key events get timestamped as soon as winit hands them to us rather than
+// when the next frame happens to run, and the time each key spent held down is
+// accumulated until the next update takes a snapshot. That way a quick tap
+// between two slow frames still moves the camera, and holding a key moves it
+// the same distance no matter how the frames were spaced out.
 pub struct KeyboardWatcher {
-    pressed: HashSet<VirtualKeyCode>,
+    // Keys currently held, along with the last time their held duration was counted
+    pressed: HashMap<VirtualKeyCode, Instant>,
+    // Held time (in seconds) accumulated since the last snapshot
+    held: HashMap<VirtualKeyCode, f32>,
+    // Lines the mouse wheel's been scrolled since the last snapshot
+    scrolled: f32,
+}
+
+/// How long each key was held down since the previous snapshot, and how far
+/// the mouse wheel was scrolled.
+#[derive(Debug, Default, Clone)]
+pub struct InputSnapshot {
+    held: HashMap<VirtualKeyCode, f32>,
+    scrolled: f32,
 }
 
 impl KeyboardWatcher {
     pub fn new() -> Self {
         Self {
-            pressed: HashSet::new(),
-        }
-    }
-
-    pub fn process_input(&mut self, event: &WindowEvent) {
-        match event {
-            WindowEvent::KeyboardInput {
-                input:
-                    KeyboardInput {
-                        state,
-                        virtual_keycode: Some(keycode),
-                        ..
-                    },
-                ..
-            } => {
-                if *state == ElementState::Pressed {
-                    self.pressed.insert(*keycode);
-                } else {
-                    self.pressed.remove(keycode);
+            pressed: HashMap::new(),
+            held: HashMap::new(),
+            scrolled: 0.0,
+        }
+    }
+
+    /// Keeps track of key presses and releases. Returns the key if it's just
+    /// been pressed (and isn't a key repeat).
+    pub fn process_input(&mut self, event: &WindowEvent) -> Option<VirtualKeyCode> {
+        if let WindowEvent::KeyboardInput {
+            input:
+                KeyboardInput {
+                    state,
+                    virtual_keycode: Some(keycode),
+                    ..
+                },
+            ..
+        } = event
+        {
+            let now = Instant::now();
+
+            if *state == ElementState::Pressed {
+                if self.press(*keycode, now) {
+                    return Some(*keycode);
                 }
+            } else {
+                self.release(*keycode, now);
             }
+        }
+
+        None
+    }
+
+    /// Whether a key's being held down right now
+    pub fn is_down(&self, keycode: VirtualKeyCode) -> bool {
+        self.pressed.contains_key(&keycode)
+    }
+
+    /// Records a key being pressed at a certain time, returning false if it
+    /// was already held down
+    pub fn press(&mut self, keycode: VirtualKeyCode, at: Instant) -> bool {
+        // Key repeat sends more pressed events, which shouldn't restart the timer
+        if self.pressed.contains_key(&keycode) {
+            return false;
+        }
+
+        self.pressed.insert(keycode, at);
+        true
+    }
+
+    /// Records a key being released at a certain time
+    pub fn release(&mut self, keycode: VirtualKeyCode, at: Instant) {
+        if let Some(since) = self.pressed.remove(&keycode) {
+            *self.held.entry(keycode).or_default() +=
+                at.saturating_duration_since(since).as_secs_f32();
+        }
+    }
+
+    /// Records the mouse wheel being scrolled this many lines, up (away from
+    /// whoever's scrolling) being positive
+    pub fn scroll(&mut self, lines: f32) {
+        self.scrolled += lines;
+    }
+
+    /// Collects the held durations of every key since the last call.
+    /// Should be called once per update.
+    pub fn snapshot(&mut self) -> InputSnapshot {
+        self.snapshot_at(Instant::now())
+    }
 
-            _ => {}
+    /// Like `snapshot`, but keys that are still held are counted up to `now`
+    pub fn snapshot_at(&mut self, now: Instant) -> InputSnapshot {
+        for (keycode, since) in self.pressed.iter_mut() {
+            *self.held.entry(*keycode).or_default() +=
+                now.saturating_duration_since(*since).as_secs_f32();
+            *since = now;
+        }
+
+        InputSnapshot {
+            held: std::mem::take(&mut self.held),
+            scrolled: std::mem::take(&mut self.scrolled),
+        }
+    }
+}
+
+impl InputSnapshot {
+    /// The number of seconds this key was held for since the last snapshot
+    pub fn held(&self, keycode: VirtualKeyCode) -> f32 {
+        self.held.get(&keycode).copied().unwrap_or(0.0)
+    }
+
+    /// Held time of the positive key minus held time of the negative key
+    pub fn axis(&self, positive: VirtualKeyCode, negative: VirtualKeyCode) -> f32 {
+        self.held(positive) - self.held(negative)
+    }
+
+    /// How many lines the mouse wheel was scrolled up since the last snapshot
+    pub fn scrolled(&self) -> f32 {
+        self.scrolled
+    }
+}
+
+/// Things that can be done by pressing a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Command {
+    /// Blows the reis away from the emitter being looked at
+    Explode,
+    /// Picks up (or drops) the light
+    GrabLight,
+    /// Throws a bowling ball from the camera
+    Bowl,
+    /// Stops (or restarts) the simulation and the sound
+    Pause,
+    /// Hides all the ui, for recording
+    CleanMode,
+    /// Silences (or unsilences) all the sound
+    Mute,
+    /// Switches between flying around and walking on the ground
+    Walk,
+    /// Flies the camera around freely
+    FlyCamera,
+    /// Walks the camera around on the ground
+    WalkCamera,
+    /// Circles the camera around the statue
+    OrbitCamera,
+    /// Circles the camera around the newest rei, following it as it falls
+    FollowCamera,
+    /// Shows (or hides) the frame timings
+    Diagnostics,
+    /// Clears the reis away and starts again, leaving everything else alone
+    Reset,
+    /// Puts everything back how the scene was loaded, camera included
+    FullReset,
+    /// Opens (or closes) the editor, for moving things around the scene
+    Editor,
+    /// Switches between a window and borderless fullscreen (so does alt+enter)
+    Fullscreen,
+}
+
+impl Command {
+    pub const ALL: [Command; 16] = [
+        Command::Explode,
+        Command::GrabLight,
+        Command::Bowl,
+        Command::Pause,
+        Command::CleanMode,
+        Command::Mute,
+        Command::Walk,
+        Command::FlyCamera,
+        Command::WalkCamera,
+        Command::OrbitCamera,
+        Command::FollowCamera,
+        Command::Diagnostics,
+        Command::Reset,
+        Command::FullReset,
+        Command::Editor,
+        Command::Fullscreen,
+    ];
+
+    pub fn description(self, strings: &Strings) -> &str {
+        strings.get(match self {
+            Command::Explode => "commands.explode",
+            Command::GrabLight => "commands.grab_light",
+            Command::Bowl => "commands.throw_ball",
+            Command::Pause => "commands.pause",
+            Command::CleanMode => "commands.clean_mode",
+            Command::Mute => "commands.mute",
+            Command::Walk => "commands.walk",
+            Command::FlyCamera => "commands.fly_camera",
+            Command::WalkCamera => "commands.walk_camera",
+            Command::OrbitCamera => "commands.orbit_camera",
+            Command::FollowCamera => "commands.follow_camera",
+            Command::Diagnostics => "commands.diagnostics",
+            Command::Reset => "commands.reset",
+            Command::FullReset => "commands.full_reset",
+            Command::Editor => "commands.editor",
+            Command::Fullscreen => "commands.fullscreen",
+        })
+    }
+}
+
+/// Which keys do which commands. These can be changed from the ui.
+pub struct InputMap {
+    bindings: HashMap<Command, VirtualKeyCode>,
+    // Set while waiting for a key to bind this command to
+    rebinding: Option<Command>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        Self {
+            bindings: HashMap::from([
+                (Command::Explode, VirtualKeyCode::E),
+                (Command::GrabLight, VirtualKeyCode::G),
+                (Command::Bowl, VirtualKeyCode::F),
+                (Command::Pause, VirtualKeyCode::P),
+                (Command::CleanMode, VirtualKeyCode::F10),
+                (Command::Mute, VirtualKeyCode::M),
+                (Command::Walk, VirtualKeyCode::V),
+                (Command::FlyCamera, VirtualKeyCode::Key1),
+                (Command::WalkCamera, VirtualKeyCode::Key2),
+                (Command::OrbitCamera, VirtualKeyCode::Key3),
+                (Command::FollowCamera, VirtualKeyCode::Key4),
+                (Command::Diagnostics, VirtualKeyCode::F3),
+                (Command::Reset, VirtualKeyCode::R),
+                (Command::FullReset, VirtualKeyCode::F5),
+                (Command::Editor, VirtualKeyCode::F2),
+                (Command::Fullscreen, VirtualKeyCode::F11),
+            ]),
+            rebinding: None,
         }
     }
+}
+
+impl InputMap {
+    pub fn key(&self, command: Command) -> VirtualKeyCode {
+        self.bindings[&command]
+    }
+
+    /// If a command is waiting to be rebound, binds it to this key and
+    /// returns true. Escape cancels it instead, since it's used for quitting.
+    /// If another command already had the key, the two swap keys, so every
+    /// key only ever does one thing.
+    pub fn rebind(&mut self, key: VirtualKeyCode) -> bool {
+        let Some(command) = self.rebinding.take() else {
+            return false;
+        };
+
+        if key != VirtualKeyCode::Escape {
+            let old_key = self.key(command);
+            if let Some(other) = self.command_for(key) {
+                self.bindings.insert(other, old_key);
+            }
+
+            self.bindings.insert(command, key);
+        }
+
+        true
+    }
+
+    pub fn command_for(&self, key: VirtualKeyCode) -> Option<Command> {
+        self.bindings
+            .iter()
+            .find(|(_, &bound)| bound == key)
+            .map(|(&command, _)| command)
+    }
+
+    /// A hint like "E: explode"
+    pub fn hint(&self, command: Command, description: &str) -> String {
+        format!("{}: {description}", key_name(self.key(command)))
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, strings: &Strings) {
+        for command in Command::ALL {
+            ui.horizontal(|ui| {
+                ui.label(format!("{}: ", command.description(strings)));
+
+                let text = if self.rebinding == Some(command) {
+                    strings.get("controls.press_a_key").to_string()
+                } else {
+                    key_name(self.key(command))
+                };
+
+                if ui.button(text).clicked() {
+                    self.rebinding = Some(command);
+                }
+            });
+        }
+    }
+}
+
+pub fn key_name(key: VirtualKeyCode) -> String {
+    // The debug names are mostly fine, except for the number keys
+    let name = format!("{key:?}");
+    match name.strip_prefix("Key") {
+        Some(digit) if !digit.is_empty() => digit.to_string(),
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn secs(seconds: f32) -> Duration {
+        Duration::from_secs_f32(seconds)
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1.0e-4, "{a} != {b}");
+    }
+
+    #[test]
+    fn tap_between_snapshots_is_counted() {
+        let start = Instant::now();
+        let mut keyboard = KeyboardWatcher::new();
+
+        keyboard.press(VirtualKeyCode::W, start + secs(0.1));
+        keyboard.release(VirtualKeyCode::W, start + secs(0.3));
+
+        let snapshot = keyboard.snapshot_at(start + secs(1.0));
+        assert_close(snapshot.held(VirtualKeyCode::W), 0.2);
+    }
+
+    #[test]
+    fn held_key_is_split_across_snapshots() {
+        let start = Instant::now();
+        let mut keyboard = KeyboardWatcher::new();
+
+        keyboard.press(VirtualKeyCode::A, start);
+
+        let first = keyboard.snapshot_at(start + secs(0.25));
+        keyboard.release(VirtualKeyCode::A, start + secs(0.4));
+        let second = keyboard.snapshot_at(start + secs(0.5));
+        let third = keyboard.snapshot_at(start + secs(0.6));
+
+        assert_close(first.held(VirtualKeyCode::A), 0.25);
+        assert_close(second.held(VirtualKeyCode::A), 0.15);
+        assert_close(third.held(VirtualKeyCode::A), 0.0);
+    }
+
+    #[test]
+    fn key_repeat_doesnt_restart_the_timer() {
+        let start = Instant::now();
+        let mut keyboard = KeyboardWatcher::new();
+
+        assert!(keyboard.press(VirtualKeyCode::D, start));
+        assert!(!keyboard.press(VirtualKeyCode::D, start + secs(0.5)));
+        keyboard.release(VirtualKeyCode::D, start + secs(1.0));
+
+        assert_close(
+            keyboard
+                .snapshot_at(start + secs(1.0))
+                .held(VirtualKeyCode::D),
+            1.0,
+        );
+    }
+
+    #[test]
+    fn rebinding_to_a_taken_key_swaps_them() {
+        let mut input_map = InputMap {
+            rebinding: Some(Command::Explode),
+            ..Default::default()
+        };
+
+        assert!(input_map.rebind(VirtualKeyCode::G));
+        assert_eq!(input_map.key(Command::Explode), VirtualKeyCode::G);
+        assert_eq!(input_map.key(Command::GrabLight), VirtualKeyCode::E);
+        assert_eq!(
+            input_map.command_for(VirtualKeyCode::G),
+            Some(Command::Explode)
+        );
+    }
+
+    #[test]
+    fn axis_subtracts_the_negative_key() {
+        let start = Instant::now();
+        let mut keyboard = KeyboardWatcher::new();
+
+        keyboard.press(VirtualKeyCode::D, start);
+        keyboard.press(VirtualKeyCode::A, start + secs(0.75));
 
-    pub fn pressed(&self, keycode: VirtualKeyCode) -> bool {
-        self.pressed.contains(&keycode)
+        let snapshot = keyboard.snapshot_at(start + secs(1.0));
+        assert_close(snapshot.axis(VirtualKeyCode::D, VirtualKeyCode::A), 0.75);
     }
 }
@@ -0,0 +1,247 @@
+//! Checking that the assets are the ones that were shipped.
+//!
+//! `assets/manifest.toml` lists the size and checksum of every asset. Once
+//! it's loaded, everything read through [resources](crate::resources) gets
+//! checked against it, so a truncated or stale file (say, a CDN serving half
+//! an obj) fails with [Error::AssetCorrupt] instead of some confusing parse
+//! error further down the line. Files that aren't in the manifest aren't
+//! checked.
+//!
+//! It also has the loudness of the songs, which can't be measured when
+//! they're streamed (see [crate::loudness]).
+//!
+//! The manifest gets out of date whenever an asset changes. The
+//! `manifest_matches_assets` test catches that, and running it with
+//! `UPDATE_ASSET_MANIFEST=1` writes a new one.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, OnceLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+pub const MANIFEST_PATH: &str = "assets/manifest.toml";
+
+static MANIFEST: OnceLock<Manifest> = OnceLock::new();
+// Files that didn't match, which haven't been told to the user yet
+static CORRUPTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileHash {
+    pub size: u64,
+    pub crc32: u32,
+}
+
+impl FileHash {
+    pub fn of(data: &[u8]) -> Self {
+        Self {
+            size: data.len() as u64,
+            crc32: crc32fast::hash(data),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: BTreeMap<String, FileHash>,
+    /// The integrated loudness of each song, in LUFS
+    #[serde(default)]
+    pub loudness: BTreeMap<String, f64>,
+}
+
+impl Manifest {
+    /// Whether a file's contents are what the manifest expects. Anything the
+    /// manifest doesn't know about is fine.
+    fn check(&self, path: &str, data: &[u8]) -> Result<()> {
+        let Some(expected) = self.files.get(normalise(path).as_str()) else {
+            return Ok(());
+        };
+
+        let found = FileHash::of(data);
+        if found == *expected {
+            return Ok(());
+        }
+
+        let detail = if found.size != expected.size {
+            format!("it's {} bytes, but should be {}", found.size, expected.size)
+        } else {
+            "its checksum doesn't match".to_string()
+        };
+
+        Err(Error::AssetCorrupt {
+            path: path.to_string(),
+            detail,
+        })
+    }
+}
+
+// Paths in the manifest always use forward slashes, even on windows
+fn normalise(path: &str) -> String {
+    path.replace('\\', "/").trim_start_matches("./").to_string()
+}
+
+/// Loads the manifest, after which assets start being checked. Without one
+/// (e.g. someone's using their own assets folder) nothing's checked.
+pub async fn load() {
+    let data = match crate::resources::load_string(MANIFEST_PATH).await {
+        Ok(data) => data,
+        Err(error) => {
+            log::warn!("{}", error.report());
+            log::warn!("Assets won't be checked for corruption");
+            return;
+        }
+    };
+
+    match toml::from_str::<Manifest>(&data) {
+        Ok(manifest) => {
+            log::info!("Checking assets against {} hashes", manifest.files.len());
+            let _ = MANIFEST.set(manifest);
+        }
+        Err(e) => log::warn!("{}", Error::decode(MANIFEST_PATH, e).report()),
+    }
+}
+
+/// Checks a file that's just been read against the manifest, if it's loaded
+pub fn check(path: &str, data: &[u8]) -> Result<()> {
+    let Some(manifest) = MANIFEST.get() else {
+        return Ok(());
+    };
+
+    manifest.check(path, data).inspect_err(|_| {
+        CORRUPTED.lock().unwrap().push(path.to_string());
+    })
+}
+
+/// How loud a song is in LUFS, if the manifest's loaded and knows
+#[cfg(feature = "audio")]
+pub fn loudness(path: &str) -> Option<f64> {
+    MANIFEST
+        .get()?
+        .loudness
+        .get(normalise(path).as_str())
+        .copied()
+}
+
+/// The files that have failed their checks since this was last called
+pub fn take_corrupted() -> Vec<String> {
+    std::mem::take(&mut *CORRUPTED.lock().unwrap())
+}
+
+/// Reloads the page, skipping any cached copies of the assets this time
+#[cfg(target_arch = "wasm32")]
+pub fn redownload() {
+    crate::resources::bypass_cache_next_time();
+
+    if let Some(window) = web_sys::window() {
+        if let Err(e) = window.location().reload() {
+            log::error!("Couldn't reload the page: {e:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mismatches_are_caught() {
+        let mut manifest = Manifest::default();
+        manifest.files.insert(
+            "assets/a.obj".to_string(),
+            FileHash::of(b"v 0 0 0\nv 1 0 0\n"),
+        );
+
+        assert!(manifest
+            .check("assets/a.obj", b"v 0 0 0\nv 1 0 0\n")
+            .is_ok());
+        assert!(manifest
+            .check("./assets/a.obj", b"v 0 0 0\nv 1 0 0\n")
+            .is_ok());
+        // Cut off halfway through, and the same size but different
+        assert!(manifest.check("assets/a.obj", b"v 0 0 0\nv 1").is_err());
+        assert!(manifest
+            .check("assets/a.obj", b"v 0 0 0\nv 2 0 0\n")
+            .is_err());
+        assert!(manifest.check("assets/b.obj", b"anything").is_ok());
+    }
+
+    const MANIFEST_HEADER: &str =
+        "# Written by the manifest_matches_assets test in src/integrity.rs";
+
+    // These get changed on purpose (the scene's saved from the ui), so there's
+    // no point checking them
+    const UNCHECKED: [&str; 2] = [MANIFEST_PATH, "assets/scene.toml"];
+
+    // Hashes every file in a directory (and the ones in it)
+    fn generate(dir: &str) -> Manifest {
+        let mut manifest = Manifest::default();
+        let mut dirs = vec![std::path::PathBuf::from(dir)];
+
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(&dir).unwrap() {
+                let path = entry.unwrap().path();
+                let name = normalise(&path.to_string_lossy());
+
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if !UNCHECKED.contains(&name.as_str()) {
+                    let data = std::fs::read(&path).unwrap();
+                    manifest.files.insert(name, FileHash::of(&data));
+                }
+            }
+        }
+
+        manifest
+    }
+
+    // Measures how loud every song is. Rounded off, since the last few digits
+    // depend on exactly how it was decoded.
+    #[cfg(feature = "audio")]
+    fn measure(manifest: &mut Manifest) {
+        let songs = manifest.files.keys().filter(|path| path.ends_with(".ogg"));
+        manifest.loudness = songs
+            .filter_map(|path| {
+                let sound =
+                    kira::sound::static_sound::StaticSoundData::from_file(path, Default::default())
+                        .unwrap();
+                let loudness = crate::loudness::integrated(&sound.frames, sound.sample_rate)?;
+                Some((path.clone(), (loudness * 100.0).round() / 100.0))
+            })
+            .collect();
+    }
+
+    #[test]
+    fn manifest_matches_assets() {
+        let mut manifest = generate("assets");
+        let saved: Option<Manifest> = std::fs::read_to_string(MANIFEST_PATH)
+            .ok()
+            .map(|data| toml::from_str(&data).unwrap());
+
+        // Decoding the songs to measure them takes a while, so it's only done
+        // when the manifest's being written. If a song's changed its hash
+        // won't match anyway.
+        if let Some(saved) = &saved {
+            manifest.loudness = saved.loudness.clone();
+        }
+
+        if std::env::var_os("UPDATE_ASSET_MANIFEST").is_some() {
+            // Without kira the songs can't be decoded, so they're left
+            // however loud they were
+            #[cfg(feature = "audio")]
+            measure(&mut manifest);
+
+            let data = toml::to_string(&manifest).unwrap();
+            std::fs::write(MANIFEST_PATH, format!("{MANIFEST_HEADER}\n\n{data}")).unwrap();
+            return;
+        }
+
+        let saved = saved.unwrap();
+        assert!(
+            saved == manifest,
+            "{MANIFEST_PATH} is out of date, run the tests with UPDATE_ASSET_MANIFEST=1 to update it"
+        );
+    }
+}
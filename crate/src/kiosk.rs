@@ -0,0 +1,279 @@
+//! Kiosk mode, for leaving it running on its own at an installation. The
+//! window covers the screen and stays on top, there's no ui or cursor, and
+//! only the harmless commands work. Quitting takes holding ctrl and typing
+//! a passphrase, so whoever's walking past can't close it.
+//!
+//! It's turned on from the command line:
+//!
+//! ```text
+//! tumblin-down --kiosk --kiosk-restart 600 --kiosk-passphrase letmeout
+//! ```
+
+use winit::event::{ModifiersState, VirtualKeyCode, WindowEvent};
+
+use crate::{bench, input::Command, physics};
+
+const DEFAULT_PASSPHRASE: &str = "tumbleout";
+
+pub const USAGE: &str = "\
+Usage: tumblin-down [options]
+
+Options:
+  --kiosk                     Run fullscreen and on top, with no ui, for installations
+  --kiosk-restart <seconds>   Start the simulation over this often (turns on --kiosk)
+  --kiosk-passphrase <text>   What to type while holding ctrl to quit, letters and
+                              numbers only (turns on --kiosk, default \"tumbleout\")
+  --bench                     Run the same 30 seconds as fast as possible, write how long
+                              the frames took to bench.json and quit
+  --bench-output <path>       Where to write the benchmark report (turns on --bench)
+  --seed <number>             Start the simulation with this seed, so it rains the same
+                              way every time (benchmarks always use their own)
+  --help                      Show this and quit";
+
+/// What the command line asked for
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    pub kiosk: Option<Kiosk>,
+    /// Where to write the benchmark report, if it's running one (see bench.rs)
+    pub bench: Option<String>,
+    /// What to seed the simulation with instead of whatever the scene says
+    pub seed: Option<u64>,
+    pub help: bool,
+}
+
+impl Args {
+    /// Reads the arguments (without the program's name in front)
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+
+            match arg.as_str() {
+                "--kiosk" => {
+                    parsed.kiosk.get_or_insert_with(Kiosk::default);
+                }
+
+                "--kiosk-restart" => {
+                    let value = value()?;
+                    let seconds = value
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|seconds| *seconds > 0.0)
+                        .ok_or(format!("{arg} needs a number of seconds, not \"{value}\""))?;
+                    parsed
+                        .kiosk
+                        .get_or_insert_with(Kiosk::default)
+                        .restart_every = Some(seconds);
+                }
+
+                "--kiosk-passphrase" => {
+                    let value = value()?;
+                    let passphrase = parse_passphrase(&value).ok_or(format!(
+                        "{arg} can only have letters and numbers in it, not \"{value}\""
+                    ))?;
+                    parsed.kiosk.get_or_insert_with(Kiosk::default).passphrase = passphrase;
+                }
+
+                "--bench" => {
+                    parsed
+                        .bench
+                        .get_or_insert_with(|| bench::DEFAULT_OUTPUT.to_string());
+                }
+
+                "--bench-output" => parsed.bench = Some(value()?),
+
+                "--seed" => {
+                    let value = value()?;
+                    let seed = value
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|seed| *seed <= physics::MAX_RANDOM_SEED)
+                        .ok_or(format!(
+                            "{arg} needs a whole number up to {}, not \"{value}\"",
+                            physics::MAX_RANDOM_SEED
+                        ))?;
+                    parsed.seed = Some(seed);
+                }
+
+                "--help" | "-h" => parsed.help = true,
+
+                _ => return Err(format!("Don't know what \"{arg}\" means")),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+/// The state of kiosk mode while it's running
+#[derive(Debug, Clone, PartialEq)]
+pub struct Kiosk {
+    /// Seconds between the simulation starting over, if it does
+    pub restart_every: Option<f32>,
+    passphrase: Vec<VirtualKeyCode>,
+    // The last few keys typed with ctrl held, as many as there are in the
+    // passphrase
+    typed: Vec<VirtualKeyCode>,
+    modifiers: ModifiersState,
+    since_restart: f32,
+}
+
+impl Default for Kiosk {
+    fn default() -> Self {
+        Self {
+            restart_every: None,
+            passphrase: parse_passphrase(DEFAULT_PASSPHRASE).unwrap(),
+            typed: Vec::new(),
+            modifiers: ModifiersState::empty(),
+            since_restart: 0.0,
+        }
+    }
+}
+
+impl Kiosk {
+    /// Whether anyone walking up to it should be able to use a command
+    pub fn allows(command: Command) -> bool {
+        matches!(
+            command,
+            Command::Explode | Command::GrabLight | Command::Bowl
+        )
+    }
+
+    /// Keeps track of which modifiers are held
+    pub fn process_event(&mut self, event: &WindowEvent) {
+        if let WindowEvent::ModifiersChanged(modifiers) = event {
+            self.modifiers = *modifiers;
+        }
+    }
+
+    /// Takes a key that's just been pressed, and returns true once the whole
+    /// passphrase has been typed with ctrl held down
+    pub fn type_key(&mut self, key: VirtualKeyCode) -> bool {
+        if !self.modifiers.ctrl() {
+            self.typed.clear();
+            return false;
+        }
+
+        self.typed.push(key);
+        if self.typed.len() > self.passphrase.len() {
+            self.typed.remove(0);
+        }
+
+        self.typed == self.passphrase
+    }
+
+    /// Counts down to the next restart, returning true when it's time.
+    /// This gets simulation time, so it doesn't restart while paused.
+    pub fn restart_due(&mut self, delta_time: f32) -> bool {
+        let Some(restart_every) = self.restart_every else {
+            return false;
+        };
+
+        self.since_restart += delta_time;
+        if self.since_restart >= restart_every {
+            self.since_restart = 0.0;
+            return true;
+        }
+
+        false
+    }
+}
+
+// The keys to type for a passphrase, or None if it has anything that can't
+// be typed with a single key
+fn parse_passphrase(text: &str) -> Option<Vec<VirtualKeyCode>> {
+    use VirtualKeyCode::*;
+
+    const LETTERS: [VirtualKeyCode; 26] = [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    ];
+    const DIGITS: [VirtualKeyCode; 10] =
+        [Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9];
+
+    if text.is_empty() {
+        return None;
+    }
+
+    text.chars()
+        .map(|c| match c.to_ascii_lowercase() {
+            c @ 'a'..='z' => Some(LETTERS[c as usize - 'a' as usize]),
+            c @ '0'..='9' => Some(DIGITS[c as usize - '0' as usize]),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn kiosk_flags_are_parsed() {
+        assert_eq!(parse(&[]).unwrap(), Args::default());
+
+        let kiosk = parse(&["--kiosk-restart", "90", "--kiosk-passphrase", "Out2"])
+            .unwrap()
+            .kiosk
+            .unwrap();
+        assert_eq!(kiosk.restart_every, Some(90.0));
+        assert_eq!(
+            kiosk.passphrase,
+            [
+                VirtualKeyCode::O,
+                VirtualKeyCode::U,
+                VirtualKeyCode::T,
+                VirtualKeyCode::Key2
+            ]
+        );
+
+        assert!(parse(&["--kiosk-restart"]).is_err());
+        assert!(parse(&["--kiosk-restart", "soon"]).is_err());
+        assert!(parse(&["--kiosk-passphrase", "let me out"]).is_err());
+        assert!(parse(&["--fullscreen"]).is_err());
+
+        assert_eq!(
+            parse(&["--bench"]).unwrap().bench.as_deref(),
+            Some(bench::DEFAULT_OUTPUT)
+        );
+        assert_eq!(
+            parse(&["--bench-output", "before.json", "--bench"])
+                .unwrap()
+                .bench
+                .as_deref(),
+            Some("before.json")
+        );
+    }
+
+    #[test]
+    fn seeds_have_to_fit() {
+        assert_eq!(parse(&["--seed", "42"]).unwrap().seed, Some(42));
+        assert!(parse(&["--seed", "-1"]).is_err());
+        assert!(parse(&["--seed", &u64::MAX.to_string()]).is_err());
+    }
+
+    #[test]
+    fn quitting_takes_the_whole_passphrase_with_ctrl() {
+        let mut kiosk = Kiosk {
+            passphrase: parse_passphrase("aab").unwrap(),
+            ..Default::default()
+        };
+        let type_keys = |kiosk: &mut Kiosk, keys: &[VirtualKeyCode]| {
+            keys.iter().any(|&key| kiosk.type_key(key))
+        };
+        use VirtualKeyCode::{A, B};
+
+        // Not without ctrl
+        assert!(!type_keys(&mut kiosk, &[A, A, B]));
+
+        kiosk.process_event(&WindowEvent::ModifiersChanged(ModifiersState::CTRL));
+        assert!(!type_keys(&mut kiosk, &[A, B]));
+        // A slip partway through can still lead into it
+        assert!(type_keys(&mut kiosk, &[A, A, A, B]));
+    }
+}
@@ -0,0 +1,60 @@
+//! Names floating over the emitters, so it's easy to tell which is which in
+//! a scene with lots of them
+
+use cgmath::{MetricSpace, Point3};
+
+use crate::{
+    camera::Camera,
+    locale::Strings,
+    physics::PhysicsSimulation,
+    text::{TextAnchor, TextRenderer},
+};
+
+// Labels further away than this aren't drawn, and they fade out on the way
+const MAX_DISTANCE: f32 = 80.0;
+// How far above the top of the spawn area they float
+const HEIGHT: f32 = 1.5;
+// In points
+const SIZE: f32 = 14.0;
+
+/// Queues each visible emitter's name, and how many reis it has in the scene,
+/// over its spawn area
+pub fn queue(
+    text: &mut TextRenderer,
+    camera: &Camera,
+    physics: &PhysicsSimulation,
+    strings: &Strings,
+) {
+    let counts = physics.emitter_counts();
+
+    for (emitter, count) in physics.emitters().iter().zip(counts) {
+        if !emitter.visible {
+            continue;
+        }
+
+        let [x, y, z] = emitter.position;
+        let position = [x, y + emitter.extents[1] + HEIGHT, z];
+
+        let distance = Point3::from(position).distance(camera.eye);
+        let opacity = (1.0 - distance / MAX_DISTANCE).clamp(0.0, 1.0).sqrt();
+        if opacity <= 0.0 {
+            continue;
+        }
+
+        let reis = if count == 1 {
+            "emitters.one_rei"
+        } else {
+            "emitters.reis"
+        };
+        text.queue(
+            format!(
+                "{}\n{}",
+                emitter.name,
+                strings.format(reis, &[("count", &count)])
+            ),
+            TextAnchor::World(position),
+            SIZE,
+            [1.0, 1.0, 1.0, opacity],
+        );
+    }
+}
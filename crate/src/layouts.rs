@@ -0,0 +1,41 @@
+//! Bind group layouts that get shared by everything drawing with them.
+//!
+//! They used to be made once and kept forever, but when the gpu's lost we
+//! start again on a new device, and a layout from the old one can't be used
+//! with it. So each layout's kept per device instead.
+
+use std::sync::Mutex;
+
+/// One bind group layout per device, made the first time that device asks.
+/// The layouts are leaked so they can be handed out as `&'static`, which
+/// only costs one layout each time the gpu's lost.
+pub struct LayoutCache {
+    // More than one device at once is possible, since the old app keeps
+    // going while the new one loads
+    layouts: Mutex<Vec<(wgpu::Id<wgpu::Device>, &'static wgpu::BindGroupLayout)>>,
+}
+
+impl LayoutCache {
+    pub const fn new() -> Self {
+        Self {
+            layouts: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn get(
+        &self,
+        device: &wgpu::Device,
+        create: impl FnOnce(&wgpu::Device) -> wgpu::BindGroupLayout,
+    ) -> &'static wgpu::BindGroupLayout {
+        let id = device.global_id();
+        let mut layouts = self.layouts.lock().unwrap();
+
+        if let Some((_, layout)) = layouts.iter().find(|(device, _)| *device == id) {
+            return layout;
+        }
+
+        let layout: &'static _ = Box::leak(Box::new(create(device)));
+        layouts.push((id, layout));
+        layout
+    }
+}
@@ -1,80 +1,249 @@
-use std::{
-    sync::{Arc, Mutex},
-    task::Context,
-};
+use std::{future::Future, pin::Pin, sync::Arc};
 
 use cfg_if::cfg_if;
+use cgmath::Matrix4;
+use futures::FutureExt;
 use instant::Instant;
-use kira::sound::{
-    static_sound::{StaticSoundData, StaticSoundSettings},
-    PlaybackState,
-};
-use resources::load_bytes;
-use std::future::Future;
 use winit::{
-    dpi::PhysicalSize,
     event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::EventLoop,
-    window::WindowBuilder,
+    window::{Fullscreen, WindowBuilder, WindowLevel},
 };
 
+mod ambience;
+mod animation;
 mod app;
+mod assets;
+#[cfg(feature = "audio")]
+mod audio;
+mod bench;
 mod camera;
+mod camera_controller;
+mod camera_effects;
+mod camera_path;
+#[cfg(target_arch = "wasm32")]
+mod canvas;
+mod choreography;
+mod clipboard;
+mod clusters;
+mod collider_gen;
+mod colour;
+mod config;
+mod contact;
+#[cfg(feature = "debug-render")]
 mod debug_collider;
+mod diagnostics;
+mod editor;
+mod emitter;
+mod entities;
+mod error;
+#[cfg(target_arch = "wasm32")]
+mod files;
+mod fonts;
+mod frustum;
+mod gpu_culling;
+mod gpu_timings;
+mod gltf;
+mod ground;
+mod impostor;
 mod input;
+mod integrity;
+mod kiosk;
+mod labels;
+mod layouts;
 mod light;
+mod lines;
+mod locale;
+#[cfg(feature = "audio")]
+mod loudness;
 mod model;
+#[cfg(feature = "audio")]
+mod music;
+mod notifications;
+mod onboarding;
+mod outline;
+mod pacing;
+mod particles;
 mod physics;
+mod prompts;
+mod quality;
+mod render_inputs;
+mod render_target;
+mod renderer;
 mod resources;
+mod scene;
+mod selection;
+mod shader_include;
+#[cfg(not(target_arch = "wasm32"))]
+mod shader_watch;
+mod shadow;
+#[cfg(not(feature = "audio"))]
+mod silent_audio;
+mod skybox;
+mod stats;
+mod storage;
+mod sun;
+mod sync_audit;
+mod tasks;
+mod text;
 mod texture;
+mod throttle;
+mod touch;
+mod transition;
+mod tweaks;
+mod upload;
+mod walker;
+
+// Everything goes through `audio`, whether it's been built in or not
+#[cfg(not(feature = "audio"))]
+use silent_audio as audio;
 
 use app::*;
+use config::Config;
+use entities::{Bodies, Entity};
+pub use stats::StatsSnapshot;
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
-const WIDTH: u32 = 1280;
-const HEIGHT: u32 = 720;
+// How much smaller than it's loaded the light's model gets drawn, so it's a
+// marker rather than something in the way
+const LIGHT_MODEL_SCALE: f32 = 0.25;
+
+// Everything that gets loaded in the background before the scene can be
+// shown
+struct Loaded {
+    scene: scene::SceneDescription,
+    rei_model: assets::Handle<model::Model>,
+    light_model: assets::Handle<model::Model>,
+    ball_model: Arc<model::Model>,
+    ambience: Vec<(ambience::AmbientSound, audio::Sound)>,
+    sky: Option<skybox::SkyFaces>,
+    song: audio::Music,
+}
 
-// Async function to load resources in the background while the
-// window is running. It was a bit of an ordeal to get that working...
-async fn load_resources(app: Arc<Mutex<App>>) -> anyhow::Result<()> {
+// Loads the scene and everything in it. This runs as a background task while
+// the window's running, so it only gets handles to the bits of the app it
+// needs, and the app gets everything once it's done (see finish_loading).
+async fn load_resources(
+    device: Arc<wgpu::Device>,
+    uploads: upload::SharedUploads,
+    assets: assets::Assets,
+) -> error::Result<Loaded> {
     log::info!("Loading resources...");
-    let (device, queue) = {
-        let app = app.lock().unwrap();
-        (app.device.clone(), app.queue.clone())
-    };
 
-    let rei_model = model::Model::load(
+    // Everything after this gets checked against the manifest
+    integrity::load().await;
+
+    // The scene says how the models should be transformed, so it goes first
+    let scene = scene::SceneDescription::load(scene::SCENE_PATH).await?;
+    let import_transform = |name: &str| scene.models.get(name).copied().unwrap_or_default();
+    let rei_transform = import_transform("rei");
+    let light_transform = import_transform("light");
+
+    // The balls that get thrown don't have a model file, they're just spheres
+    let ball_model = Arc::new(model::Model::sphere(
         device.as_ref(),
-        queue.as_ref(),
-        "assets/rei/rei.obj",
-        Some(&texture::Texture::texture_bind_group_layout(
-            device.as_ref(),
-        )),
-    )
-    .await?;
-
-    let light_model =
-        model::Model::load(device.as_ref(), queue.as_ref(), "assets/ike.obj", None).await?;
-
-    let song = StaticSoundData::from_cursor(
-        std::io::Cursor::new(load_bytes("assets/komm-susser-tod.ogg").await?),
-        StaticSoundSettings::default(),
-    )?;
-
-    {
-        let mut app = app.lock().unwrap();
-        app.rei_model = Some(rei_model);
-        app.light_model = Some(light_model);
-        app.song = Some(song);
-
-        app.state = State::Playing;
+        &uploads,
+        "bowling ball",
+        [40, 40, 60, 255],
+        texture::Texture::texture_bind_group_layout(device.as_ref()),
+    )?);
+
+    // There's always the generated sky, so one that can't be loaded is just
+    // left out like the ambience
+    let sky = async {
+        match &scene.sky {
+            Some(source) => skybox::SkyFaces::load(source)
+                .await
+                .map_err(|error| log::error!("{}", error.report()))
+                .ok(),
+            None => None,
+        }
+    };
+
+    // None of these depend on each other, so they all load at once
+    let (rei_model, light_model, ambience, sky, song) = futures::join!(
+        assets.load_model(
+            "assets/rei/rei.obj",
+            Some(texture::Texture::texture_bind_group_layout(
+                device.as_ref(),
+            )),
+            &rei_transform,
+        ),
+        assets.load_model("assets/ike.obj", None, &light_transform),
+        // The scene keeps its copy so it can be saved again later
+        ambience::load_sounds(&assets, scene.ambience.clone()),
+        sky,
+        audio::Music::load("assets/komm-susser-tod.ogg"),
+    );
+
+    Ok(Loaded {
+        rei_model: rei_model?,
+        light_model: light_model?,
+        ball_model,
+        ambience,
+        sky,
+        song: song?,
+        scene,
+    })
+}
+
+// Hands everything that's been loaded over to the app and starts it going
+fn finish_loading(app: &mut App, mut loaded: Loaded, seed: Option<u64>) {
+    // Drawn in this order
+    let device = &app.renderer.device;
+    if let Some(model) = app.assets.get(loaded.light_model) {
+        let entity = Entity::new(device, "light", model)
+            .with_transform(Matrix4::from_scale(LIGHT_MODEL_SCALE))
+            .at_light();
+        app.entities.push(entity);
+    }
+    if let Some(model) = app.assets.get(loaded.rei_model) {
+        app.entities
+            .push(Entity::new(device, "rei", model).at_bodies(Bodies::Reis));
     }
+    app.entities.push(
+        Entity::new(device, "ball", loaded.ball_model).at_bodies(Bodies::Projectiles),
+    );
+    app.song = Some(loaded.song);
+
+    // Benchmarks always run the same scene, whatever's been saved
+    if app.is_benchmarking() {
+        app.load_scene(bench::scene(loaded.scene));
+    } else {
+        loaded.scene.seed = seed.or(loaded.scene.seed);
+        app.load_scene(loaded.scene);
+    }
+    app.set_ambience(loaded.ambience);
 
+    if let Some(sky) = loaded.sky {
+        let renderer = &mut app.renderer;
+        renderer
+            .skybox
+            .set_faces(&renderer.device, &renderer.queue, &sky);
+    }
+
+    app.state = State::Playing;
     log::info!("Resources loaded!");
+}
+
+// Without a working renderer there's no error screen to show, so the best
+// that can be done is logging it (and on the web, putting it on the page)
+fn show_fatal_error(error: &error::Error) {
+    log::error!("{}", error.report());
+
+    #[cfg(target_arch = "wasm32")]
+    if let Some(dst) = canvas::container() {
+        dst.set_text_content(Some(&format!("{}\n{}", error.report(), error.hint())));
+    }
+}
 
-    Ok(())
+/// The latest stats about the running simulation, updated once a second.
+/// On the web this is exported to javascript as `simulationStats()`.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = simulationStats))]
+pub fn simulation_stats() -> StatsSnapshot {
+    stats::latest()
 }
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
@@ -92,115 +261,128 @@ pub async fn run() {
         }
     }
 
+    let args = match kiosk::Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(error) => {
+            eprintln!("{error}\n\n{}", kiosk::USAGE);
+            return;
+        }
+    };
+
+    if args.help {
+        println!("{}", kiosk::USAGE);
+        return;
+    }
+
+    let mut config = Config::load();
+
+    // Benchmarks go as fast as they can, so the frame times are the time it
+    // actually takes to draw them. The app doesn't save the config while it's
+    // benchmarking, so this doesn't stick.
+    if args.bench.is_some() {
+        config.present_mode = config::PresentMode::NoVsync;
+        config.frame_limit = config::FrameLimit::Unlimited;
+    }
+
     // Set the width and height of the window
     // on web this is going to have to be the dimensions of the page
     // so we need some web-specific code
     cfg_if! {
         if #[cfg(target_arch="wasm32")] {
-            let width = web_sys::window()
-                .and_then(|win| win.inner_width().ok())
-                .and_then(|wid| wid.as_f64())
-                .unwrap() as u32;
-
-            let height = web_sys::window()
-                .and_then(|win| win.inner_height().ok())
-                .and_then(|hei| hei.as_f64())
-                .unwrap() as u32;
-
+            let size = canvas::page_size();
         } else {
-            let width = WIDTH;
-            let height = HEIGHT;
+            let size = winit::dpi::PhysicalSize::new(config.width, config.height);
         }
     }
 
-    // Instantiate the window
+    // Instantiate the window. Browsers only go fullscreen when the user's
+    // clicked on something, so on the web it has to wait for the checkbox.
+    // Kiosk mode always covers the screen, and stays above everything else.
+    let kiosk = args.kiosk.is_some();
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
-        .with_inner_size(PhysicalSize::new(width, height))
+        .with_inner_size(size)
+        .with_fullscreen(
+            ((config.fullscreen || kiosk) && cfg!(not(target_arch = "wasm32")))
+                .then_some(Fullscreen::Borderless(None)),
+        )
+        .with_window_level(if kiosk {
+            WindowLevel::AlwaysOnTop
+        } else {
+            WindowLevel::Normal
+        })
         .build(&event_loop)
         .unwrap();
+    window.set_cursor_visible(!kiosk);
 
+    // On web we need to bind the window to the canvas
     #[cfg(target_arch = "wasm32")]
-    {
-        // On web we need to bind the window to the canvas
-        use winit::platform::web::WindowExtWebSys;
-        web_sys::window()
-            .and_then(|win| win.document())
-            .and_then(|document| {
-                let dst = document.get_element_by_id("wasm-example")?;
-                let canvas = web_sys::Element::from(window.canvas());
-                canvas.set_id("render-canvas");
-                dst.append_child(&canvas).ok()?;
-                Some(())
-            })
-            .expect("Couldn't append canvas to document.");
-    }
-
-    let app = App::new(window).await.unwrap();
-
-    // On the web, we need to add an event listener to resize the window when the
-    // page is resized. This isn't in sync with the regular window events, so
-    // we need to wrap the app in a mutex.
-    // TODO: make the mutex control less data so we dont have to interrupt so much stuff
-    // every time the page is resized
-    let app = Arc::new(Mutex::new(app));
-
-    #[cfg(target_arch = "wasm32")]
-    {
-        let app = app.clone();
-        let resize_closure = Closure::<dyn FnMut(_)>::new(move |_event: web_sys::UiEvent| {
-            let width = web_sys::window()
-                .and_then(|win| win.inner_width().ok())
-                .and_then(|wid| wid.as_f64())
-                .unwrap() as u32;
-
-            let height = web_sys::window()
-                .and_then(|win| win.inner_height().ok())
-                .and_then(|hei| hei.as_f64())
-                .unwrap() as u32;
-
-            app.lock().unwrap().resize(PhysicalSize::new(width, height));
-        });
-
-        web_sys::window()
-            .unwrap()
-            .add_event_listener_with_callback("resize", resize_closure.as_ref().unchecked_ref())
-            .expect("couldn't add event listener");
-
-        resize_closure.forget();
-    }
-
-    let mut loaded = false;
-    let mut load_result = Box::pin({
-        let app = app.clone();
-        load_resources(app)
-    });
+    let mut canvas_watcher =
+        canvas::CanvasWatcher::mount(&window).expect("Couldn't append canvas to document.");
+
+    let bench = args.bench.map(bench::Bench::new);
+    let mut app = match App::new(Arc::new(window), config, args.kiosk, bench).await {
+        Ok(app) => app,
+        Err(error) => {
+            show_fatal_error(&error);
+            return;
+        }
+    };
 
-    let mut frame_time = Instant::now();
+    let mut quit_at = None;
+    let mut loading = Some(tasks::spawn(load_resources(
+        app.renderer.device.clone(),
+        app.uploads.clone(),
+        app.assets.clone(),
+    )));
+    // What's been loaded, waiting for the models' buffers to finish uploading
+    let mut loaded: Option<Loaded> = None;
+    // A new app starting up in the same window after the gpu's been lost,
+    // and the scene to put back once it's loaded. Apps aren't Send, so this
+    // gets polled from the event loop rather than spawned.
+    let mut restarting: Option<Pin<Box<dyn Future<Output = error::Result<App>>>>> = None;
+    let mut restored_scene = None;
 
     event_loop.run(move |event, _, control_flow| {
-        let mut app = app.lock().unwrap();
-
-        if loaded {
-            if let Some(handle) = app.song_handle_mut() {
-                if handle.state() != PlaybackState::Playing {
-                    log::info!("Resuming music");
-                    handle.resume(Default::default()).unwrap();
+        // Nothing else can happen without a gpu
+        if let Some(restart) = restarting.as_mut() {
+            control_flow.set_poll();
+            let Some(result) = restart.as_mut().now_or_never() else {
+                return;
+            };
+
+            restarting = None;
+            match result {
+                Ok(restarted) => {
+                    log::info!("Started again after losing the gpu");
+                    app = restarted;
+                    loading = Some(tasks::spawn(load_resources(
+                        app.renderer.device.clone(),
+                        app.uploads.clone(),
+                        app.assets.clone(),
+                    )));
+                    loaded = None;
+                }
+                Err(error) => {
+                    show_fatal_error(&error);
+                    control_flow.set_exit();
+                    return;
                 }
-            } else {
-                log::info!("Playing music");
-                app.play_music();
-                app.song_handle_mut()
-                    .unwrap()
-                    .pause(Default::default())
-                    .unwrap();
-                app.song_handle_mut()
-                    .unwrap()
-                    .resume(Default::default())
-                    .unwrap();
             }
         }
 
+        // Quitting waits for the sound to fade out first
+        if quit_at.is_some_and(|quit_at| Instant::now() >= quit_at) {
+            control_flow.set_exit();
+        }
+
+        // The kiosk passphrase quits from inside the app
+        if quit_at.is_none() && app.quit_requested() {
+            quit_at = Some(Instant::now() + app.quit());
+        }
+
+        app.update_music();
+
         app.egui_platform.handle_event(&event);
 
         match event {
@@ -217,8 +399,8 @@ pub async fn run() {
                                 ..
                             },
                         ..
-                    } => {
-                        control_flow.set_exit();
+                    } if quit_at.is_none() => {
+                        quit_at = Some(Instant::now() + app.quit());
                     }
 
                     WindowEvent::Resized(size) => {
@@ -227,63 +409,108 @@ pub async fn run() {
 
                     WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
                         app.resize(*new_inner_size);
+                        app.update_refresh_rate();
                     }
 
+                    // It might be on a different monitor now
+                    WindowEvent::Moved(_) => app.update_refresh_rate(),
+
                     _ => {}
                 }
             }
 
             Event::RedrawRequested(window_id) if window_id == app.window().id() => {
-                let delta_time = frame_time.elapsed().as_secs_f32();
-                frame_time = Instant::now();
+                // Page resizes come through here too, since winit doesn't
+                // resize the canvas on its own
+                #[cfg(target_arch = "wasm32")]
+                match canvas_watcher.status() {
+                    canvas::CanvasStatus::Ready => {}
+                    canvas::CanvasStatus::Refresh(size) => app.resize(size),
+
+                    // Nothing's drawn while the canvas is away, and the time
+                    // it was away for doesn't count
+                    canvas::CanvasStatus::Hidden => {
+                        app.pacer.reset();
+                        return;
+                    }
+
+                    // The browser gives the context back when it can, and
+                    // everything gets made again then
+                    canvas::CanvasStatus::Lost => {
+                        app.pacer.reset();
+                        return;
+                    }
+                    canvas::CanvasStatus::Restored => {
+                        log::warn!("The webgl context was lost, starting again");
+                        let (restart, scene) = app.restart();
+                        restarting = Some(Box::pin(restart));
+                        restored_scene = scene;
+                        return;
+                    }
+                }
 
+                let delta_time = app.pacer.start_frame();
                 app.update(delta_time);
 
                 match app.render() {
                     Ok(_) => {}
 
+                    // Setting the surface up again is usually enough, but if
+                    // it keeps getting lost the gpu's gone with it (which is
+                    // how losing it shows up with webgpu and natively)
                     Err(wgpu::SurfaceError::Lost) => {
-                        let size = *app.size();
-                        app.resize(size);
+                        if !app.recover_from_lost_surface() {
+                            let (restart, scene) = app.restart();
+                            restarting = Some(Box::pin(restart));
+                            restored_scene = scene;
+                        }
+                    }
+                    // The window's changed under the surface (usually it's
+                    // been resized and the event hasn't come through yet), so
+                    // it's set up again at the size the window is now and this
+                    // frame's skipped
+                    Err(wgpu::SurfaceError::Outdated) => app.resize(app.window().inner_size()),
+                    Err(wgpu::SurfaceError::OutOfMemory) => {
+                        if !app.recover_from_out_of_memory() {
+                            control_flow.set_exit();
+                        }
                     }
-                    Err(wgpu::SurfaceError::OutOfMemory) => control_flow.set_exit(),
                     Err(e) => log::error!("{e:?}"),
                 }
             }
 
-            Event::MainEventsCleared => app.window().request_redraw(),
+            Event::MainEventsCleared => match app.pacer.wait_until(Instant::now()) {
+                None => {
+                    control_flow.set_poll();
+                    app.window().request_redraw();
+                }
+                // Loading gets checked on when the loop comes round, so it
+                // doesn't wait while there's still loading to do
+                Some(_) if loading.is_some() || loaded.is_some() => control_flow.set_poll(),
+                Some(next_frame) => control_flow.set_wait_until(next_frame),
+            },
 
             _ => {}
         }
 
-        drop(app);
-
-        // Perhaps I owe a bit of explanation to whoever's reading this.
-        // This code is awful, and it's the fault of rust being special.
-        // Rust could have a very nice async ecosystem but unfortunately, winit
-        // needs to take control of the entire thread just to run its even loop.
-        // This means winit can't be easily integrated with an async runtime like tokio,
-        // and if you spawn a task to be completed while the window runs (for example,
-        // i dunno, loading resources while a loading screen is displayed), the task
-        // will never complete as winit is hogging all the resources for itself.
-        // As a result, I've had to implement my own basic future executor to load
-        // resources. This is awful and possibly a good sign that someone needs
-        // to integrate async into winit. Apparently someone tried but they gave up
-        // 4 years ago.
-        //
-        // Update: 1 day after i got this problem, a crate called "async-winit" was
-        // announced. :shrug:
-        if !loaded {
-            let waker = futures::task::noop_waker();
-            let mut cx = Context::from_waker(&waker);
-            match (&mut load_result).as_mut().poll(&mut cx) {
-                std::task::Poll::Ready(result) => {
-                    result.unwrap();
-                    loaded = true;
-                }
+        if let Some(result) = loading.as_mut().and_then(tasks::Task::poll) {
+            match result {
+                Ok(result) => loaded = Some(result),
+                Err(error) => app.fail(error),
+            }
+
+            loading = None;
+        }
 
-                std::task::Poll::Pending => {}
+        // The models' buffers get filled in over the next few frames, so
+        // they're not shown until that's finished
+        if loaded.is_some() && app.uploads.lock().unwrap().is_idle() {
+            let mut loaded = loaded.take().unwrap();
+            // Whatever had been changed about the scene before starting over
+            if let Some(scene) = restored_scene.take() {
+                loaded.scene = scene;
             }
+            finish_loading(&mut app, loaded, args.seed);
         }
     });
 }
@@ -1,4 +1,125 @@
 use cgmath::{Deg, Quaternion, Rotation3, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::{animation::LightAnimation, layouts::LayoutCache, skybox, sun::DayCycle};
+
+static LIGHT_BIND_GROUP_LAYOUT: LayoutCache = LayoutCache::new();
+
+// Where the light sits relative to the selection when following it,
+// before it's rotated around
+pub const FOLLOW_OFFSET: [f32; 3] = [4.0, 10.0, 4.0];
+// How far in front of the camera the light is carried
+pub const HOLD_DISTANCE: f32 = 4.0;
+// How quickly the light goes round when it's orbiting, in degrees per second
+pub const ORBIT_SPEED: f32 = 48.0;
+
+/// How the light moves around the scene
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LightMode {
+    /// Circles around the middle of the scene
+    Orbit,
+    /// Circles above whatever's selected (or the first emitter, if nothing
+    /// is), so the shadows are always around the interesting part
+    FollowSelection,
+    /// Carried around in front of the camera
+    Held,
+    /// Stays wherever it was left
+    Still,
+}
+
+/// The light as it's written in the scene file
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LightSettings {
+    /// Where the light starts off (or stays, if it's still)
+    pub position: [f32; 3],
+    pub colour: [f32; 3],
+    /// How far the light reaches before it starts falling off
+    pub scale: f32,
+    pub brightness: f32,
+    pub mode: LightMode,
+    pub shadow: LightShadow,
+    /// Flickering, pulsing and colour cycling
+    pub animation: LightAnimation,
+}
+
+/// Whether a light casts shadows, and how much it matters when there isn't
+/// room in the [ShadowBudget] for every light that does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LightShadow {
+    pub enabled: bool,
+    /// Lights with a higher priority get their shadows first
+    pub priority: i32,
+}
+
+impl Default for LightShadow {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            priority: 0,
+        }
+    }
+}
+
+/// How many lights can cast shadows at once. Each one needs its own shadow
+/// pass, which gets expensive fast, so past this only the most important
+/// lights get them: the highest priority first, then the closest to the
+/// camera.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShadowBudget {
+    pub max_lights: usize,
+}
+
+impl Default for ShadowBudget {
+    fn default() -> Self {
+        Self { max_lights: 1 }
+    }
+}
+
+impl ShadowBudget {
+    /// The most that can be picked, since that's more shadow passes than
+    /// anything could keep up with
+    pub const LIMIT: usize = 8;
+
+    /// Picks which lights get shadows this frame. Each light comes with its
+    /// distance from the camera, and what's given back is the indices of the
+    /// picked ones.
+    pub fn pick(&self, lights: &[(LightShadow, f32)]) -> Vec<usize> {
+        let mut candidates = (0..lights.len())
+            .filter(|&i| lights[i].0.enabled)
+            .collect::<Vec<_>>();
+
+        candidates.sort_by(|&a, &b| {
+            let (a, b) = (lights[a], lights[b]);
+            b.0.priority.cmp(&a.0.priority).then(a.1.total_cmp(&b.1))
+        });
+
+        candidates.truncate(self.max_lights);
+        candidates
+    }
+}
+
+impl Default for LightSettings {
+    fn default() -> Self {
+        Self {
+            position: [2.0, 3.0, 2.0],
+            colour: [0.96, 0.68, 1.0],
+            scale: 15.0,
+            brightness: 1.5,
+            mode: LightMode::Orbit,
+            shadow: LightShadow::default(),
+            animation: LightAnimation::default(),
+        }
+    }
+}
+
+impl LightSettings {
+    pub fn uniform(&self) -> LightUniform {
+        LightUniform::new(self.position, self.colour, self.scale, self.brightness)
+    }
+}
 
 #[repr(C)]
 #[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
@@ -7,6 +128,15 @@ pub struct LightUniform {
     pub scale: f32,
     pub colour: [f32; 3],
     pub brightness: f32,
+    // The sun goes in here too, see sun.rs. Its colour's black when there
+    // isn't one.
+    pub sun_direction: [f32; 3],
+    _padding: f32,
+    pub sun_colour: [f32; 3],
+    _padding2: f32,
+    /// What things fade into in the fog, and get lit by as ambient light
+    pub sky_colour: [f32; 3],
+    _padding3: f32,
 }
 
 impl LightUniform {
@@ -16,12 +146,70 @@ impl LightUniform {
             scale,
             colour,
             brightness,
+            sun_direction: [0.0, 1.0, 0.0],
+            _padding: 0.0,
+            sun_colour: [0.0; 3],
+            _padding2: 0.0,
+            sky_colour: skybox::HORIZON_COLOUR,
+            _padding3: 0.0,
         }
     }
 
-    pub fn update(&mut self) {
-        let position: Vector3<f32> = self.position.into();
-        self.position =
-            (Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), Deg(0.8)) * position).into();
+    /// Puts the sun where it is at this time of day
+    pub fn set_sun(&mut self, day: &DayCycle) {
+        self.sun_direction = day.direction();
+        self.sun_colour = day.colour();
+        self.sky_colour = day.sky_colour();
+    }
+
+    pub fn bind_group_layout(device: &wgpu::Device) -> &'static wgpu::BindGroupLayout {
+        LIGHT_BIND_GROUP_LAYOUT.get(device, |device| {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("light bind group layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            })
+        })
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        self.position = orbit(self.position, delta_time);
+    }
+}
+
+/// Rotates a position around the y axis as far as the light orbits in
+/// `delta_time` seconds
+pub fn orbit(position: [f32; 3], delta_time: f32) -> [f32; 3] {
+    let position: Vector3<f32> = position.into();
+    let angle = Deg(ORBIT_SPEED * delta_time);
+    (Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), angle) * position).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget_picks_the_most_important_lights() {
+        let shadow = |enabled, priority| LightShadow { enabled, priority };
+        let lights = [
+            (shadow(true, 0), 5.0),
+            (shadow(true, 0), 1.0),
+            (shadow(false, 10), 1.0),
+            (shadow(true, 1), 50.0),
+        ];
+
+        let budget = |max_lights| ShadowBudget { max_lights };
+        assert_eq!(budget(2).pick(&lights), vec![3, 1]);
+        assert_eq!(budget(8).pick(&lights), vec![3, 1, 0]);
+        assert!(budget(0).pick(&lights).is_empty());
     }
 }
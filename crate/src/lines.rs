@@ -0,0 +1,200 @@
+use crate::{camera::CameraBinding, sync_audit::AuditedQueue, texture};
+
+// Room for this many lines to start with, the buffer grows if it needs more
+const INITIAL_LINES: usize = 1024;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct LineVertex {
+    position: [f32; 3],
+    colour: [f32; 3],
+}
+
+impl LineVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// Draws plain coloured lines for debug views. Lines get added each frame,
+/// uploaded with [LineRenderer::prepare], then drawn over the top of the
+/// scene so they can be seen even when they're buried in the pile.
+pub struct LineRenderer {
+    vertices: Vec<LineVertex>,
+    vertex_buffer: wgpu::Buffer,
+    // How many vertices were uploaded last
+    uploaded: u32,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl LineRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        Self {
+            vertices: Vec::new(),
+            vertex_buffer: create_vertex_buffer(device, INITIAL_LINES * 2),
+            uploaded: 0,
+            pipeline: create_pipeline(device, shader, format, sample_count),
+        }
+    }
+
+    /// Recreates the pipeline for a scene with a different number of samples
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        self.pipeline = create_pipeline(device, shader, format, sample_count);
+    }
+
+    /// Gets rid of all the lines, ready for the next frame's
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub fn line(&mut self, start: [f32; 3], end: [f32; 3], colour: [f32; 3]) {
+        self.vertices.push(LineVertex {
+            position: start,
+            colour,
+        });
+        self.vertices.push(LineVertex {
+            position: end,
+            colour,
+        });
+    }
+
+    /// A little 3d cross centred on a point
+    pub fn cross(&mut self, centre: [f32; 3], size: f32, colour: [f32; 3]) {
+        let [x, y, z] = centre;
+        let half = size / 2.0;
+
+        self.line([x - half, y, z], [x + half, y, z], colour);
+        self.line([x, y - half, z], [x, y + half, z], colour);
+        self.line([x, y, z - half], [x, y, z + half], colour);
+    }
+
+    #[cfg(feature = "debug-render")]
+    pub fn num_lines(&self) -> usize {
+        self.vertices.len() / 2
+    }
+
+    /// Uploads this frame's lines, making the buffer bigger first if they
+    /// don't fit
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &AuditedQueue) {
+        let size = (self.vertices.len() * std::mem::size_of::<LineVertex>()) as u64;
+        if size > self.vertex_buffer.size() {
+            self.vertex_buffer =
+                create_vertex_buffer(device, self.vertices.len().next_power_of_two());
+        }
+
+        if !self.vertices.is_empty() {
+            queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
+        }
+
+        self.uploaded = self.vertices.len() as u32;
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera: &'a CameraBinding) {
+        if self.uploaded == 0 {
+            return;
+        }
+
+        self.record_draw(render_pass, camera, self.uploaded);
+    }
+
+    /// Draws a line (whatever was last uploaded) even when there aren't any,
+    /// so the pipeline's been used before the first real one
+    pub fn warm_up<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a CameraBinding,
+    ) {
+        self.record_draw(render_pass, camera, 2);
+    }
+
+    fn record_draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a CameraBinding,
+        vertices: u32,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &camera.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..vertices, 0..1);
+    }
+}
+
+fn create_vertex_buffer(device: &wgpu::Device, vertices: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("line vertex buffer"),
+        size: (vertices * std::mem::size_of::<LineVertex>()) as _,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("line pipeline layout"),
+        bind_group_layouts: &[CameraBinding::bind_group_layout(device)],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("line pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[LineVertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::LineList,
+            cull_mode: None,
+            ..Default::default()
+        },
+        // The overlay pass has the scene's depth buffer attached, but the
+        // lines ignore it and always go on top
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: Default::default(),
+            bias: Default::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
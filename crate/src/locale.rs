@@ -0,0 +1,285 @@
+//! The text in the ui, in whichever language's been picked. Each language is
+//! a toml file in `assets/lang` with the same keys as English's. Anything a
+//! translation leaves out shows up in English, which is compiled in so
+//! there's always something to fall back to.
+
+use std::{collections::HashMap, fmt::Display, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    assets::Assets,
+    error::{Error, Result},
+    tasks::{self, Task},
+};
+
+const ENGLISH: &str = include_str!("../assets/lang/en.toml");
+
+// Strings by key, with the toml's tables flattened into dotted keys
+// ("graphics.vsync")
+type Table = HashMap<String, String>;
+
+/// The languages there are translations for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Language {
+    #[default]
+    #[serde(rename = "en")]
+    English,
+    #[serde(rename = "es")]
+    Spanish,
+}
+
+impl Language {
+    pub const ALL: [Self; 2] = [Self::English, Self::Spanish];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::English => "en",
+            Self::Spanish => "es",
+        }
+    }
+
+    /// What the language calls itself, which is how it's shown in the
+    /// selector no matter which language the rest of the ui is in
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::English => "English",
+            Self::Spanish => "Español",
+        }
+    }
+
+    fn path(self) -> String {
+        format!("assets/lang/{}.toml", self.code())
+    }
+}
+
+/// A language's strings, as loaded from its file. English is compiled in, so
+/// its translation is empty.
+#[derive(Debug, Default)]
+pub struct Translation(Table);
+
+impl Translation {
+    pub fn from_bytes(path: &str, bytes: &[u8]) -> Result<Self> {
+        let data = std::str::from_utf8(bytes).map_err(|e| Error::decode(path, e))?;
+        parse(data).map(Self).map_err(|e| Error::decode(path, e))
+    }
+}
+
+/// One language's worth of ui text
+#[derive(Debug)]
+pub struct Strings {
+    language: Language,
+    translated: Arc<Translation>,
+    english: Table,
+}
+
+impl Strings {
+    fn new(language: Language, translated: Arc<Translation>) -> Self {
+        Self {
+            language,
+            translated,
+            english: parse(ENGLISH).expect("the english strings should be valid"),
+        }
+    }
+
+    /// The text for a key, in English if this language doesn't have it. If
+    /// English doesn't either, the key itself shows up so it's easy to spot.
+    pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+        self.translated
+            .0
+            .get(key)
+            .or_else(|| self.english.get(key))
+            .map_or(key, String::as_str)
+    }
+
+    /// Like [Strings::get], with each `{name}` filled in
+    pub fn format(&self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        let mut text = self.get(key).to_string();
+        for (name, value) in args {
+            text = text.replace(&format!("{{{name}}}"), &value.to_string());
+        }
+        text
+    }
+}
+
+fn parse(data: &str) -> std::result::Result<Table, toml::de::Error> {
+    fn flatten(prefix: &str, table: toml::Table, into: &mut Table) {
+        for (key, value) in table {
+            let key = if prefix.is_empty() {
+                key
+            } else {
+                format!("{prefix}.{key}")
+            };
+
+            match value {
+                toml::Value::String(text) => {
+                    into.insert(key, text);
+                }
+                toml::Value::Table(table) => flatten(&key, table, into),
+                _ => log::warn!("Ui string \"{key}\" isn't a string, leaving it out"),
+            }
+        }
+    }
+
+    let mut strings = Table::new();
+    flatten("", toml::from_str(data)?, &mut strings);
+    Ok(strings)
+}
+
+/// Loads a language's strings through the asset cache, so switching back to
+/// one that's been loaded before doesn't load it again
+async fn load(assets: Assets, language: Language) -> Result<Arc<Translation>> {
+    if language == Language::English {
+        return Ok(Arc::default());
+    }
+
+    let path = language.path();
+    let handle = assets.load_translation(&path).await?;
+    assets.get(handle).ok_or(Error::AssetMissing { path })
+}
+
+/// Keeps track of which language the ui's in, and loads new ones in the
+/// background when it's changed.
+pub struct Locale {
+    assets: Assets,
+    strings: Arc<Strings>,
+    pending: Option<(Language, Task<Result<Arc<Translation>>>)>,
+    pub error: Option<String>,
+}
+
+impl Locale {
+    /// Loads the starting language. If that doesn't work it's English instead,
+    /// since it's not worth refusing to start over.
+    pub async fn new(assets: &Assets, language: Language) -> Self {
+        let strings = match load(assets.clone(), language).await {
+            Ok(translation) => Strings::new(language, translation),
+            Err(error) => {
+                log::warn!("{}", error.report());
+                Strings::new(Language::English, Arc::default())
+            }
+        };
+
+        Self {
+            assets: assets.clone(),
+            strings: Arc::new(strings),
+            pending: None,
+            error: None,
+        }
+    }
+
+    /// The current strings. These are shared so the ui code can hold onto
+    /// them while it changes everything else.
+    pub fn strings(&self) -> Arc<Strings> {
+        self.strings.clone()
+    }
+
+    pub fn language(&self) -> Language {
+        self.strings.language
+    }
+
+    /// Starts loading a language. It gets switched to by `poll` once it's
+    /// loaded.
+    pub fn request(&mut self, language: Language) {
+        self.error = None;
+        self.pending = Some((language, tasks::spawn(load(self.assets.clone(), language))));
+    }
+
+    /// Checks on the language being loaded and switches to it if it's done.
+    /// If it failed, the current one's kept.
+    pub fn poll(&mut self) {
+        let Some((language, task)) = self.pending.as_mut() else {
+            return;
+        };
+
+        if let Some(result) = task.poll() {
+            match result {
+                Ok(translation) => {
+                    log::info!("Switching the ui to {}", language.name());
+                    self.strings = Arc::new(Strings::new(*language, translation));
+                }
+
+                Err(error) => {
+                    log::error!("{}", error.report());
+                    self.error = Some(error.to_string());
+                }
+            }
+
+            self.pending = None;
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label(self.strings.get("language"));
+
+            let current = self
+                .pending
+                .as_ref()
+                .map_or(self.language(), |(language, _)| *language);
+
+            for language in Language::ALL {
+                if ui
+                    .selectable_label(current == language, language.name())
+                    .clicked()
+                    && current != language
+                {
+                    self.request(language);
+                }
+            }
+        });
+
+        if let Some(error) = &self.error {
+            ui.colored_label(egui::Color32::LIGHT_RED, error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The bits of a string that get filled in
+    fn placeholders(text: &str) -> Vec<&str> {
+        let mut names: Vec<_> = text
+            .split('{')
+            .skip(1)
+            .filter_map(|rest| rest.split_once('}').map(|(name, _)| name))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn translations_match_english() {
+        let english = parse(ENGLISH).unwrap();
+
+        for language in Language::ALL {
+            let path = language.path();
+            let table = parse(&std::fs::read_to_string(&path).unwrap()).unwrap();
+
+            for (key, text) in &table {
+                let Some(original) = english.get(key) else {
+                    panic!("{path} has \"{key}\", which English doesn't");
+                };
+                assert_eq!(
+                    placeholders(text),
+                    placeholders(original),
+                    "\"{key}\" in {path} fills in different things to English"
+                );
+            }
+        }
+
+        // Anything missing comes out in English
+        let strings = Strings::new(
+            Language::Spanish,
+            Arc::new(Translation(Table::from([(
+                "audio.mute".to_string(),
+                "Silenciar".to_string(),
+            )]))),
+        );
+        assert_eq!(strings.get("audio.mute"), "Silenciar");
+        assert_eq!(strings.get("audio.heading"), "Audio");
+        assert_eq!(strings.get("not.a.key"), "not.a.key");
+        assert_eq!(strings.format("stats.fps", &[("fps", &60)]), "Fps: 60");
+    }
+}
@@ -0,0 +1,179 @@
+//! Measuring how loud a song is, so different songs can be played back at
+//! about the same volume. It's the integrated loudness from ITU-R BS.1770
+//! (what LUFS are), which weights the sound the way ears hear it and leaves
+//! the quiet bits out, so a long fade at the end doesn't make a song seem
+//! quieter than it is.
+//!
+//! Songs that are streamed can't be measured when they load without decoding
+//! the whole thing, so theirs are worked out ahead of time and kept in the
+//! asset manifest (see [crate::integrity]).
+
+use kira::dsp::Frame;
+
+// How long each measured block is, and how far apart they start, in seconds
+const BLOCK: f64 = 0.4;
+const STEP: f64 = 0.1;
+// Blocks quieter than this are left out completely, and so are ones this
+// much quieter than the rest
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+
+/// The integrated loudness of a sound in LUFS, or None if it's too short or
+/// too quiet to say
+pub fn integrated(frames: &[Frame], sample_rate: u32) -> Option<f64> {
+    let step = (STEP * sample_rate as f64).round() as usize;
+    let steps_per_block = (BLOCK / STEP).round() as usize;
+    if step == 0 {
+        return None;
+    }
+
+    // The weighted power of each step, added up
+    let mut filters = [
+        KWeighting::new(sample_rate as f64),
+        KWeighting::new(sample_rate as f64),
+    ];
+    let steps: Vec<f64> = frames
+        .chunks_exact(step)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .map(|frame| {
+                    let left = filters[0].process(frame.left as f64);
+                    let right = filters[1].process(frame.right as f64);
+                    left * left + right * right
+                })
+                .sum()
+        })
+        .collect();
+
+    // The mean power of each block, which overlap by three quarters
+    let block_size = (step * steps_per_block) as f64;
+    let blocks: Vec<f64> = steps
+        .windows(steps_per_block)
+        .map(|window| window.iter().sum::<f64>() / block_size)
+        .filter(|&power| loudness(power) > ABSOLUTE_GATE)
+        .collect();
+
+    let threshold = loudness(mean(&blocks)?) + RELATIVE_GATE;
+    let gated: Vec<f64> = blocks
+        .into_iter()
+        .filter(|&power| loudness(power) > threshold)
+        .collect();
+
+    Some(loudness(mean(&gated)?))
+}
+
+fn loudness(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+// The filter that makes the power closer to how loud things sound: a shelf
+// boosting everything over about 1.5khz, then a high pass cutting out the
+// very lowest bass. These are the standard's coefficients, worked out for any
+// sample rate the way libebur128 does.
+struct KWeighting {
+    shelf: Biquad,
+    high_pass: Biquad,
+}
+
+impl KWeighting {
+    fn new(sample_rate: f64) -> Self {
+        let shelf = {
+            let f0 = 1681.974450955533;
+            let gain = 3.999843853973347;
+            let q = 0.7071752369554196;
+
+            let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+            let vh = 10f64.powf(gain / 20.0);
+            let vb = vh.powf(0.4996667741545416);
+            let a0 = 1.0 + k / q + k * k;
+
+            Biquad::new(
+                [
+                    (vh + vb * k / q + k * k) / a0,
+                    2.0 * (k * k - vh) / a0,
+                    (vh - vb * k / q + k * k) / a0,
+                ],
+                [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            )
+        };
+
+        let high_pass = {
+            let f0 = 38.13547087602444;
+            let q = 0.5003270373238773;
+
+            let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+            let a0 = 1.0 + k / q + k * k;
+
+            Biquad::new(
+                [1.0, -2.0, 1.0],
+                [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            )
+        };
+
+        Self { shelf, high_pass }
+    }
+
+    fn process(&mut self, sample: f64) -> f64 {
+        self.high_pass.process(self.shelf.process(sample))
+    }
+}
+
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    // The last two inputs and outputs
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self {
+            b,
+            a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sine_measures_its_level() {
+        // A 1khz sine at -23 dbfs in both ears is -23 lufs, by definition
+        // (near enough, the weighting isn't completely flat there)
+        let sample_rate = 48000;
+        let amplitude = 10f32.powf(-23.0 / 20.0);
+        let frames: Vec<Frame> = (0..sample_rate * 5)
+            .map(|i| {
+                let t = i as f32 / sample_rate as f32;
+                Frame::from_mono((t * 1000.0 * std::f32::consts::TAU).sin() * amplitude)
+            })
+            .collect();
+
+        let lufs = integrated(&frames, sample_rate).unwrap();
+        assert!((lufs + 23.0).abs() < 0.1, "{lufs}");
+
+        // Silence isn't any loudness at all
+        let silence = vec![Frame::ZERO; sample_rate as usize];
+        assert_eq!(integrated(&silence, sample_rate), None);
+    }
+}
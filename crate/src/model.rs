@@ -1,8 +1,18 @@
 // TODO: Switch over entirely to nalgebra to work well with rapier3d
-use std::io::{BufReader, Cursor};
 
-use crate::{resources, texture};
-use cgmath::{vec3, Matrix4, Quaternion, Vector3};
+use crate::{
+    animation::{MaterialUniform, UvAnimation},
+    assets::Assets,
+    colour::ColourSpace,
+    error::{Error, Result},
+    gltf,
+    sync_audit::AuditedQueue,
+    texture,
+    upload::SharedUploads,
+};
+use std::sync::{Arc, Mutex};
+use cgmath::{vec3, Deg, InnerSpace, Matrix, Matrix3, Matrix4, Quaternion, Rotation3, SquareMatrix, Vector3};
+use serde::{Deserialize, Serialize};
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
     vertex_attr_array, VertexBufferLayout,
@@ -26,13 +36,80 @@ pub struct ModelVertex {
 #[repr(C)]
 pub struct InstanceRaw {
     model: [[f32; 4]; 4],
-    rotation: [[f32; 3]; 3],
+    // The inverse transpose of the model matrix's upper 3x3, for transforming normals
+    normal: [[f32; 3]; 3],
+    // How fast the instance is moving, for the speed debug view
+    speed: f32,
+    // How faded out it is, from 0 (invisible) to 1
+    alpha: f32,
 }
 
 #[derive(Debug)]
 pub struct Instance {
     pub position: Vector3<f32>,
     pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+    pub speed: f32,
+    pub alpha: f32,
+}
+
+/// Moves a model's vertices around as it's loaded, e.g. to put its origin
+/// somewhere more sensible than where the file has it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImportTransform {
+    /// Added to every vertex after it's been rotated and scaled
+    pub offset: [f32; 3],
+    /// Degrees around the x, y and z axes, applied in that order
+    pub rotation: [f32; 3],
+    /// Negative to mirror the model
+    pub scale: f32,
+    pub winding: Winding,
+}
+
+impl Default for ImportTransform {
+    fn default() -> Self {
+        Self {
+            offset: [0.0; 3],
+            rotation: [0.0; 3],
+            scale: 1.0,
+            winding: Winding::Auto,
+        }
+    }
+}
+
+/// What to do about which way round a model's triangles go. Only the fronts
+/// of triangles get drawn, so a model wound the wrong way round (which some
+/// exporters do, especially for mirrored models) looks inside out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Winding {
+    /// Flip any meshes that look inside out
+    #[default]
+    Auto,
+    /// Leave them how the file has them
+    Keep,
+    /// Flip every mesh
+    Flip,
+}
+
+impl ImportTransform {
+    pub fn rotation(&self) -> Quaternion<f32> {
+        let [x, y, z] = self.rotation;
+        Quaternion::from_angle_z(Deg(z)) * Quaternion::from_angle_y(Deg(y)) * Quaternion::from_angle_x(Deg(x))
+    }
+
+    pub fn transform_point(&self, point: [f32; 3]) -> [f32; 3] {
+        (self.rotation() * Vector3::from(point) * self.scale + Vector3::from(self.offset)).into()
+    }
+
+    // Scaling is uniform, so normals only need rotating (and turning around
+    // if it's mirrored)
+    fn transform_normal(&self, normal: [f32; 3]) -> [f32; 3] {
+        (self.rotation() * Vector3::from(normal) * self.scale.signum())
+            .normalize()
+            .into()
+    }
 }
 
 /// A 3d object that may be made up of multiple meshes,
@@ -40,50 +117,408 @@ pub struct Instance {
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    /// The box containing every vertex of every mesh, in model space
+    pub bounds: Bounds,
+    /// Every mesh's triangles, kept on the cpu so colliders can be made from them
+    pub collision_mesh: Arc<CollisionMesh>,
+}
+
+/// The triangles of a model, all in one list and in model space (so after
+/// the import transform)
+#[derive(Debug, Clone, Default)]
+pub struct CollisionMesh {
+    pub positions: Vec<[f32; 3]>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+impl CollisionMesh {
+    fn add(&mut self, vertices: &[ModelVertex], indices: &[u32]) {
+        let start = self.positions.len() as u32;
+        self.positions
+            .extend(vertices.iter().map(|vertex| vertex.position));
+        self.triangles.extend(
+            indices
+                .chunks_exact(3)
+                .map(|triangle| [0, 1, 2].map(|i| start + triangle[i])),
+        );
+    }
+}
+
+/// An axis-aligned bounding box
+#[derive(Debug, Clone, Copy)]
+pub struct Bounds {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Bounds {
+    // Doesn't contain anything, but grows to fit whatever's added to it
+    const EMPTY: Bounds = Bounds {
+        min: [f32::INFINITY; 3],
+        max: [f32::NEG_INFINITY; 3],
+    };
+
+    fn add_point(&mut self, point: [f32; 3]) {
+        self.min = [0, 1, 2].map(|i| self.min[i].min(point[i]));
+        self.max = [0, 1, 2].map(|i| self.max[i].max(point[i]));
+    }
+
+    // A model with no vertices still needs sensible bounds
+    fn or_empty(self) -> Self {
+        if self.min[0] > self.max[0] {
+            Bounds {
+                min: [0.0; 3],
+                max: [0.0; 3],
+            }
+        } else {
+            self
+        }
+    }
+
+    pub fn centre(&self) -> [f32; 3] {
+        [0, 1, 2].map(|i| (self.min[i] + self.max[i]) / 2.0)
+    }
+
+    pub fn size(&self) -> [f32; 3] {
+        [0, 1, 2].map(|i| self.max[i] - self.min[i])
+    }
+
+    /// The smallest sphere around the middle of the box that the whole box
+    /// fits in, whichever way it's turned
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere {
+            centre: self.centre(),
+            radius: Vector3::from(self.size()).magnitude() / 2.0,
+        }
+    }
+}
+
+/// A sphere around a model, for quickly telling whether it could be on screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub centre: [f32; 3],
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// The sphere around an instance of the model
+    pub fn around(&self, instance: &InstanceRaw) -> BoundingSphere {
+        let model = Matrix4::from(instance.model);
+        let centre = model * Vector3::from(self.centre).extend(1.0);
+        BoundingSphere {
+            centre: centre.truncate().into(),
+            radius: self.radius * instance.scale(),
+        }
+    }
 }
 
 /// A single 3d object. This struct contains a handle to a vertex and index
 /// buffer on the GPU, as well as the index of its material (stored in the
 /// parent Model struct).
 pub struct Mesh {
+    #[allow(dead_code)]
     pub name: String,
-    pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
+    // These are filled in gradually by the upload scheduler, see upload.rs
+    pub vertex_buffer: Arc<wgpu::Buffer>,
+    pub index_buffer: Arc<wgpu::Buffer>,
     pub num_indices: u32,
     pub material: Option<usize>,
 }
 
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: Option<texture::Texture>,
+    // Textures can be shared between models, see assets.rs
+    #[allow(dead_code)]
+    pub diffuse_texture: Option<Arc<texture::Texture>>,
     pub diffuse_bind_group: Option<wgpu::BindGroup>,
+    pub uniform_buffer: wgpu::Buffer,
+    pub surface: Surface,
+    // Models are shared too, so this has to be changeable from behind an Arc
+    pub animation: Mutex<Option<UvAnimation>>,
+}
+
+/// How shiny a material is. These come from the Ks and Ns of an obj's
+/// material file, or the roughness of a gltf material.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Surface {
+    /// How bright the highlights are, on top of the specular shader tweak
+    pub specular: f32,
+    /// How tight the highlights are (the specular exponent). If it's not set
+    /// the shader tweak's specular power is used instead.
+    pub shininess: Option<f32>,
+}
+
+impl Default for Surface {
+    fn default() -> Self {
+        Self {
+            specular: 1.0,
+            shininess: None,
+        }
+    }
+}
+
+impl Surface {
+    // The most an mtl file's allowed to set Ns to
+    const MAX_SHININESS: f32 = 1000.0;
+
+    /// Reads an obj material's specular colour and exponent. Anything
+    /// missing is left at the default.
+    pub fn from_mtl(specular: Option<[f32; 3]>, shininess: Option<f32>) -> Self {
+        let default = Self::default();
+        Self {
+            // Highlights are drawn in the light's colour, so only the
+            // brightness of Ks matters
+            specular: specular.map_or(default.specular, |ks| (ks[0] + ks[1] + ks[2]) / 3.0),
+            shininess: shininess
+                .filter(|ns| *ns > 0.0)
+                .map(|ns| ns.min(Self::MAX_SHININESS)),
+        }
+    }
+
+    /// Turns a gltf material's metallic/roughness pair into a highlight.
+    /// This isn't physically based, just close enough that rough things look
+    /// dull and smooth things look shiny.
+    pub fn from_roughness(metallic: f32, roughness: f32) -> Self {
+        let roughness = roughness.clamp(0.05, 1.0);
+        Self {
+            specular: (1.0 - roughness) * (1.0 + metallic.clamp(0.0, 1.0)),
+            // The usual way of matching a Blinn-Phong exponent to a roughness
+            shininess: Some((2.0 / roughness.powi(4) - 2.0).clamp(1.0, Self::MAX_SHININESS)),
+        }
+    }
+
+    /// Puts this surface into a material uniform
+    pub fn uniform(&self, uniform: MaterialUniform) -> MaterialUniform {
+        MaterialUniform {
+            specular: self.specular,
+            shininess: self.shininess.unwrap_or(0.0),
+            ..uniform
+        }
+    }
+}
+
+impl Mesh {
+    fn new(
+        device: &wgpu::Device,
+        uploads: &SharedUploads,
+        filename: &str,
+        name: String,
+        vertices: &[ModelVertex],
+        indices: &[u32],
+        material: Option<usize>,
+    ) -> Self {
+        let mut uploads = uploads.lock().unwrap();
+
+        let vertex_buffer = uploads.create_buffer(
+            device,
+            &format!("{}/{} vertex buffer", filename, name),
+            bytemuck::cast_slice(vertices),
+            wgpu::BufferUsages::VERTEX,
+        );
+
+        let index_buffer = uploads.create_buffer(
+            device,
+            &format!("{}/{} index buffer", filename, name),
+            bytemuck::cast_slice(indices),
+            wgpu::BufferUsages::INDEX,
+        );
+
+        Mesh {
+            name,
+            vertex_buffer,
+            index_buffer,
+            num_indices: indices.len() as _,
+            material,
+        }
+    }
+}
+
+impl Material {
+    fn new(
+        device: &wgpu::Device,
+        filename: &str,
+        name: String,
+        texture: Option<Arc<texture::Texture>>,
+        texture_layout: Option<&wgpu::BindGroupLayout>,
+        surface: Surface,
+    ) -> Self {
+        // TODO: This rubs me the wrong way. We're passed in the texture bind group layout
+        // but then we just go ahead and use this layout instead. Is there some way to
+        // make it so the object loading function doesn't say anything about the layout
+        // of the texture bind group?
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(&format!("{}/{} material buffer", filename, name)),
+            contents: bytemuck::cast_slice(&[surface.uniform(MaterialUniform::default())]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group = texture
+            .as_ref()
+            .and_then(|tex| Some((tex, texture_layout?)))
+            .map(|(texture, layout)| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some(&format!("{}/{} texture bind group", filename, name)),
+                    layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&texture.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: uniform_buffer.as_entire_binding(),
+                        },
+                    ],
+                })
+            });
+
+        Material {
+            name,
+            diffuse_texture: texture,
+            diffuse_bind_group: bind_group,
+            uniform_buffer,
+            surface,
+            animation: Mutex::new(None),
+        }
+    }
+}
+
+// The volume enclosed by the triangles, which is negative if they're facing
+// inwards. Also gives the total size of every triangle's part of the volume,
+// to tell how sure the sign is: for a flat or open mesh the parts mostly
+// cancel out, and the sign doesn't mean much.
+fn signed_volume(vertices: &[ModelVertex], indices: &[u32]) -> (f32, f32) {
+    let position = |i: u32| Vector3::from(vertices[i as usize].position);
+
+    // Measuring from the middle keeps the numbers small
+    let centre = vertices
+        .iter()
+        .fold(Vector3::new(0.0, 0.0, 0.0), |sum, v| sum + Vector3::from(v.position))
+        / vertices.len().max(1) as f32;
+
+    indices
+        .chunks_exact(3)
+        .map(|triangle| {
+            let [a, b, c] = [0, 1, 2].map(|i| position(triangle[i]) - centre);
+            a.dot(b.cross(c)) / 6.0
+        })
+        .fold((0.0, 0.0), |(volume, total), part| (volume + part, total + part.abs()))
+}
+
+// Puts the triangles the right way round, if they need it
+fn fix_winding(label: &str, vertices: &[ModelVertex], indices: &mut [u32], winding: Winding) {
+    let flip = match winding {
+        Winding::Keep => false,
+        Winding::Flip => true,
+        Winding::Auto => {
+            let (volume, total) = signed_volume(vertices, indices);
+            let inside_out = volume < -0.1 * total;
+            if inside_out {
+                log::info!("{label} looks inside out, flipping its triangles");
+            }
+            inside_out
+        }
+    };
+
+    if flip {
+        for triangle in indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+}
+
+// A single pixel of one colour. Materials without a texture (or whose texture
+// wouldn't load) get a white one, so they still have something to bind.
+fn plain_texture(
+    device: &wgpu::Device,
+    uploads: &SharedUploads,
+    colour: [u8; 4],
+    label: &str,
+) -> Result<Arc<texture::Texture>> {
+    let image = image::RgbaImage::from_pixel(1, 1, image::Rgba(colour));
+    texture::Texture::from_image(
+        device,
+        uploads,
+        &image.into(),
+        Some(label),
+        ColourSpace::Srgb,
+    )
+    .map(Arc::new)
+}
+
+fn decode_image(bytes: &[u8], label: &str) -> Result<image::RgbaImage> {
+    image::load_from_memory(bytes)
+        .map(|image| image.to_rgba8())
+        .map_err(|e| Error::decode(label, e))
+}
+
+// Gltf materials have a colour the texture gets multiplied by. It's baked
+// into the texture here so the shader doesn't need to know about it.
+fn tint_image(image: &mut image::RgbaImage, colour: [f32; 4]) {
+    if colour == [1.0; 4] {
+        return;
+    }
+
+    // The factor's linear, but the texture is sRGB. This is close enough.
+    let colour = colour.map(|c| c.clamp(0.0, 1.0).powf(1.0 / 2.2));
+    for pixel in image.pixels_mut() {
+        for (channel, c) in pixel.0.iter_mut().zip(colour) {
+            *channel = (*channel as f32 * c).round() as u8;
+        }
+    }
+}
+
+// Turns a path relative to a model file into one relative to the assets
+fn relative_to(filename: &str, path: &str) -> String {
+    let parent = std::path::Path::new(filename)
+        .parent()
+        .unwrap_or(std::path::Path::new(""));
+
+    // After doing some testing, it seems like relative_path isn't very sophisticated
+    // so TODO: Refactor this to just use normal paths and save a dependency?
+    let new_path = relative_path::RelativePath::new(path).to_path(parent);
+    new_path.as_path().to_str().unwrap().to_string()
 }
 
 impl Model {
+    /// Loads an obj, or a gltf/glb if the file has one of those extensions.
+    /// Usually this goes through [Assets::load_model] so it's only loaded once.
     pub async fn load(
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
+        assets: &Assets,
         filename: &str,
         texture_layout: Option<&wgpu::BindGroupLayout>,
-    ) -> anyhow::Result<Self> {
-        // Get the path of the parent so we can load materials
-        let parent = std::path::Path::new(filename)
-            .parent()
-            .unwrap_or(std::path::Path::new(""));
-
-        // After doing some testing, it seems like relative_path isn't very sophisticated
-        // so TODO: Refactor this to just use normal paths and save a dependency?
-        let format_path = |path: &str| {
-            let new_path = relative_path::RelativePath::new(path).to_path(parent);
-            new_path.as_path().to_str().unwrap().to_string()
-        };
-
-        let data = resources::load_string(filename).await?;
-        // A cursor allows us to implement Read on a String so we can use it
-        // in a buffered reader, which is required for tobj to load from memory.
-        let cursor = Cursor::new(data);
-
-        let mut reader = BufReader::new(cursor);
+        transform: &ImportTransform,
+    ) -> Result<Self> {
+        let extension = std::path::Path::new(filename)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+
+        match extension.as_deref() {
+            Some("gltf" | "glb") => {
+                Self::load_gltf(assets, filename, texture_layout, transform).await
+            }
+            _ => Self::load_obj(assets, filename, texture_layout, transform).await,
+        }
+    }
+
+    async fn load_obj(
+        assets: &Assets,
+        filename: &str,
+        texture_layout: Option<&wgpu::BindGroupLayout>,
+        transform: &ImportTransform,
+    ) -> Result<Self> {
+        let (device, uploads) = (assets.device(), assets.uploads());
+        let format_path = |path: &str| relative_to(filename, path);
+
+        // A byte slice is already a buffered reader, so tobj can parse the
+        // file straight out of the mapped memory
+        let data = assets.fetch(filename).await?;
+        let mut reader = &data[..];
 
         let (meshes, materials) = tobj::load_obj_buf_async(
             &mut reader,
@@ -95,13 +530,18 @@ impl Model {
             },
             |p| async move {
                 let filename = format_path(&p);
-                let mat_string = resources::load_string(&filename).await.unwrap();
-                let mat_cursor = Cursor::new(mat_string);
-                let mut mat_reader = BufReader::new(mat_cursor);
-                tobj::load_mtl_buf(&mut mat_reader)
+                let Ok(mat_data) = assets.fetch(&filename).await else {
+                    log::error!("Couldn't load material file {filename}");
+                    return Err(tobj::LoadError::OpenFileFailed);
+                };
+                tobj::load_mtl_buf(&mut &mat_data[..])
             },
         )
-        .await?;
+        .await
+        .map_err(|e| Error::decode(filename, e))?;
+
+        let mut bounds = Bounds::EMPTY;
+        let mut collision_mesh = CollisionMesh::default();
 
         let meshes = meshes
             .into_iter()
@@ -110,96 +550,357 @@ impl Model {
 
                 let vertices = (0..mesh.positions.len() / 3)
                     .map(|i| ModelVertex {
-                        position: [
+                        position: transform.transform_point([
                             mesh.positions[3 * i],
                             mesh.positions[3 * i + 1],
                             mesh.positions[3 * i + 2],
-                        ],
+                        ]),
                         tex_coords: [mesh.texcoords[2 * i], 1.0 - mesh.texcoords[2 * i + 1]],
-                        normal: [
+                        normal: transform.transform_normal([
                             mesh.normals[3 * i],
                             mesh.normals[3 * i + 1],
                             mesh.normals[3 * i + 2],
-                        ],
+                        ]),
                     })
                     .collect::<Vec<_>>();
 
-                let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
-                    label: Some(&format!("{}/{} vertex buffer", filename, model.name)),
-                    contents: bytemuck::cast_slice(&vertices),
-                    usage: wgpu::BufferUsages::VERTEX,
-                });
+                for vertex in vertices.iter() {
+                    bounds.add_point(vertex.position);
+                }
 
-                let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
-                    label: Some(&format!("{}/{} index buffer", filename, model.name)),
-                    contents: bytemuck::cast_slice(&mesh.indices),
-                    usage: wgpu::BufferUsages::INDEX,
-                });
+                let label = format!("{}/{}", filename, model.name);
+                let mut indices = mesh.indices;
+                fix_winding(&label, &vertices, &mut indices, transform.winding);
+                collision_mesh.add(&vertices, &indices);
 
-                Mesh {
-                    name: model.name,
-                    vertex_buffer,
-                    index_buffer,
-                    num_indices: mesh.indices.len() as _,
-                    material: mesh.material_id,
-                }
+                Mesh::new(
+                    device,
+                    uploads,
+                    filename,
+                    model.name,
+                    &vertices,
+                    &indices,
+                    mesh.material_id,
+                )
             })
             .collect::<Vec<_>>();
 
-        let mut new_materials = Vec::new();
+        let materials = materials.map_err(|e| Error::decode(filename, e))?;
 
-        for mat in materials?.into_iter() {
-            let diffuse_filename = format_path(mat.diffuse_texture.as_ref().unwrap());
-            let texture = texture::Texture::load_texture(&device, &queue, &diffuse_filename)
+        // The textures all load at once, rather than one after another
+        let textures = futures::future::join_all(materials.iter().map(|mat| async {
+            // A missing texture isn't fatal, the material's just drawn plain white
+            let diffuse_filename = format_path(mat.diffuse_texture.as_ref()?);
+            assets
+                .load_texture(&diffuse_filename, ColourSpace::Srgb)
                 .await
-                .ok();
-
-            // TODO: This rubs me the wrong way. We're passed in the texture bind group layout
-            // but then we just go ahead and use this layout instead. Is there some way to
-            // make it so the object loading function doesn't say anything about the layout
-            // of the texture bind group?
-            let bind_group = texture
-                .as_ref()
-                .and_then(|tex| Some((tex, texture_layout?)))
-                .map(|(texture, layout)| {
-                    device.create_bind_group(&wgpu::BindGroupDescriptor {
-                        label: Some(&format!("{}/{} texture bind group", filename, mat.name)),
-                        layout,
-                        entries: &[
-                            wgpu::BindGroupEntry {
-                                binding: 0,
-                                resource: wgpu::BindingResource::TextureView(&texture.view),
-                            },
-                            wgpu::BindGroupEntry {
-                                binding: 1,
-                                resource: wgpu::BindingResource::Sampler(&texture.sampler),
-                            },
-                        ],
+                .map_err(|e| log::warn!("{}", e.report()))
+                .ok()
+                .and_then(|handle| assets.get(handle))
+        }))
+        .await;
+
+        let new_materials = materials
+            .into_iter()
+            .zip(textures)
+            .map(|(mat, texture)| {
+                let texture = match texture {
+                    Some(texture) => texture,
+                    None => {
+                        let label = format!("{}/{}", filename, mat.name);
+                        plain_texture(device, uploads, [255; 4], &label)?
+                    }
+                };
+                let surface = Surface::from_mtl(mat.specular, mat.shininess);
+                Ok(Material::new(
+                    device,
+                    filename,
+                    mat.name,
+                    Some(texture),
+                    texture_layout,
+                    surface,
+                ))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Model {
+            meshes,
+            materials: new_materials,
+            bounds: bounds.or_empty(),
+            collision_mesh: Arc::new(collision_mesh),
+        })
+    }
+
+    /// Loads a gltf or glb file, e.g. one exported from Blender. See gltf.rs
+    /// for what's supported.
+    pub async fn load_gltf(
+        assets: &Assets,
+        filename: &str,
+        texture_layout: Option<&wgpu::BindGroupLayout>,
+        transform: &ImportTransform,
+    ) -> Result<Self> {
+        let (device, uploads) = (assets.device(), assets.uploads());
+        let data = assets.fetch(filename).await?;
+        let document = gltf::Gltf::parse(&data).map_err(|e| Error::decode(filename, e))?;
+
+        // Buffers in other files have to be loaded before anything can be read
+        let sources = document
+            .buffer_sources()
+            .map_err(|e| Error::decode(filename, e))?;
+
+        let files = futures::future::try_join_all(sources.iter().filter_map(|source| {
+            let gltf::Source::File(path) = source else {
+                return None;
+            };
+
+            let path = relative_to(filename, path);
+            Some(async move { assets.fetch(&path).await })
+        }))
+        .await?;
+
+        let mut files_iter = files.iter();
+        let buffers = sources
+            .iter()
+            .map(|source| match source {
+                gltf::Source::Data(data) => &data[..],
+                gltf::Source::File(_) => &files_iter.next().unwrap()[..],
+            })
+            .collect::<Vec<_>>();
+
+        let mut bounds = Bounds::EMPTY;
+        let mut collision_mesh = CollisionMesh::default();
+
+        let meshes = document
+            .meshes(&buffers)
+            .map_err(|e| Error::decode(filename, e))?
+            .into_iter()
+            .map(|mesh| {
+                let vertices = (0..mesh.positions.len())
+                    .map(|i| ModelVertex {
+                        position: transform.transform_point(mesh.positions[i]),
+                        tex_coords: mesh.tex_coords[i],
+                        normal: transform.transform_normal(mesh.normals[i]),
                     })
-                });
+                    .collect::<Vec<_>>();
+
+                for vertex in vertices.iter() {
+                    bounds.add_point(vertex.position);
+                }
+
+                let label = format!("{}/{}", filename, mesh.name);
+                let mut indices = mesh.indices;
+                fix_winding(&label, &vertices, &mut indices, transform.winding);
+                collision_mesh.add(&vertices, &indices);
+
+                Mesh::new(
+                    device,
+                    uploads,
+                    filename,
+                    mesh.name,
+                    &vertices,
+                    &indices,
+                    Some(mesh.material),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let materials = document
+            .materials(&buffers)
+            .map_err(|e| Error::decode(filename, e))?;
+
+        // Texture files get fetched and decoded all at once
+        let images = futures::future::join_all(materials.iter().map(|mat| async {
+            let label = format!("{}/{}", filename, mat.name);
 
-            new_materials.push(Material {
-                name: mat.name,
-                diffuse_texture: texture,
-                diffuse_bind_group: bind_group,
+            match &mat.texture {
+                Some(gltf::Source::File(path)) => {
+                    let path = relative_to(filename, path);
+                    match assets.fetch(&path).await {
+                        Ok(bytes) => decode_image(&bytes, &path),
+                        Err(e) => Err(e),
+                    }
+                }
+                Some(gltf::Source::Data(bytes)) => decode_image(bytes, &label),
+                None => Ok(image::RgbaImage::from_pixel(1, 1, image::Rgba([255; 4]))),
+            }
+        }))
+        .await;
+
+        let mut new_materials = Vec::new();
+
+        for (mat, image) in materials.into_iter().zip(images) {
+            let label = format!("{}/{}", filename, mat.name);
+
+            // Like with objs, a broken texture just means it's drawn plain
+            let mut image = image.unwrap_or_else(|e| {
+                log::warn!("{}", e.report());
+                image::RgbaImage::from_pixel(1, 1, image::Rgba([255; 4]))
             });
+            tint_image(&mut image, mat.base_colour);
+            let texture = texture::Texture::from_image(
+                device,
+                uploads,
+                &image.into(),
+                Some(&label),
+                ColourSpace::Srgb,
+            )?;
+
+            new_materials.push(Material::new(
+                device,
+                filename,
+                mat.name,
+                Some(Arc::new(texture)),
+                texture_layout,
+                mat.surface,
+            ));
         }
 
         Ok(Model {
             meshes,
             materials: new_materials,
+            bounds: bounds.or_empty(),
+            collision_mesh: Arc::new(collision_mesh),
         })
     }
+
+    /// Sets the animation of the material with the given name.
+    /// Returns false if there's no such material.
+    pub fn set_animation(&self, material: &str, animation: UvAnimation) -> bool {
+        match self.materials.iter().find(|mat| mat.name == material) {
+            Some(mat) => {
+                *mat.animation.lock().unwrap() = Some(animation);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// A plain sphere with a radius of 1, for things that don't need a model
+    /// file. It's all one colour.
+    pub fn sphere(
+        device: &wgpu::Device,
+        uploads: &SharedUploads,
+        name: &str,
+        colour: [u8; 4],
+        texture_layout: &wgpu::BindGroupLayout,
+    ) -> Result<Self> {
+        // Rings go from the top to the bottom, segments go around
+        const RINGS: u32 = 16;
+        const SEGMENTS: u32 = 32;
+
+        let mut vertices = Vec::new();
+        for ring in 0..=RINGS {
+            let v = ring as f32 / RINGS as f32;
+            let (sin_theta, cos_theta) = (v * std::f32::consts::PI).sin_cos();
+
+            for segment in 0..=SEGMENTS {
+                let u = segment as f32 / SEGMENTS as f32;
+                let (sin_phi, cos_phi) = (u * std::f32::consts::TAU).sin_cos();
+                let normal = [sin_theta * cos_phi, cos_theta, sin_theta * sin_phi];
+
+                vertices.push(ModelVertex {
+                    position: normal,
+                    tex_coords: [u, v],
+                    normal,
+                });
+            }
+        }
+
+        let mut indices = Vec::new();
+        for ring in 0..RINGS {
+            for segment in 0..SEGMENTS {
+                let top = ring * (SEGMENTS + 1) + segment;
+                let bottom = top + SEGMENTS + 1;
+                indices.extend([top, top + 1, bottom, bottom, top + 1, bottom + 1]);
+            }
+        }
+
+        let mesh = Mesh::new(
+            device,
+            uploads,
+            name,
+            name.to_string(),
+            &vertices,
+            &indices,
+            Some(0),
+        );
+        let mut collision_mesh = CollisionMesh::default();
+        collision_mesh.add(&vertices, &indices);
+
+        let texture = plain_texture(device, uploads, colour, name)?;
+        let material = Material::new(
+            device,
+            name,
+            name.to_string(),
+            Some(texture),
+            Some(texture_layout),
+            Surface::default(),
+        );
+
+        Ok(Model {
+            meshes: vec![mesh],
+            materials: vec![material],
+            bounds: Bounds {
+                min: [-1.0; 3],
+                max: [1.0; 3],
+            },
+            collision_mesh: Arc::new(collision_mesh),
+        })
+    }
+
+    /// Writes the current state of every animated material to the gpu.
+    /// `time` is the number of seconds since the scene started.
+    pub fn update_animations(&self, queue: &AuditedQueue, time: f32) {
+        for material in self.materials.iter() {
+            if let Some(animation) = material.animation.lock().unwrap().as_ref() {
+                queue.write_buffer(
+                    &material.uniform_buffer,
+                    0,
+                    bytemuck::cast_slice(&[material.surface.uniform(animation.uniform_at(time))]),
+                );
+            }
+        }
+    }
 }
 
 impl Instance {
     pub fn to_raw(&self) -> InstanceRaw {
+        let scale = Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z);
+
+        // The inverse transpose of (rotation * scale) is rotation * scale^-1,
+        // which keeps normals correct even if the scale isn't uniform
+        let inverse_scale = Matrix3::from_diagonal(vec3(
+            1.0 / self.scale.x,
+            1.0 / self.scale.y,
+            1.0 / self.scale.z,
+        ));
+
         InstanceRaw {
-            model: (Matrix4::from_translation(self.position) * Matrix4::from(self.rotation)).into(),
-            rotation: cgmath::Matrix3::from(self.rotation).into(),
+            model: (Matrix4::from_translation(self.position)
+                * Matrix4::from(self.rotation)
+                * scale)
+                .into(),
+            normal: (Matrix3::from(self.rotation) * inverse_scale).into(),
+            speed: self.speed,
+            alpha: self.alpha,
         }
     }
 
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = vec3(scale, scale, scale);
+        self
+    }
+
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    pub fn with_alpha(mut self, alpha: f32) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
     pub fn from_rapier_position(
         position: &na::Isometry<f32, na::Unit<na::Quaternion<f32>>, 3>,
     ) -> Self {
@@ -215,7 +916,13 @@ impl Instance {
             position.translation.z,
         );
 
-        Self { rotation, position }
+        Self {
+            rotation,
+            position,
+            scale: vec3(1.0, 1.0, 1.0),
+            speed: 0.0,
+            alpha: 1.0,
+        }
     }
 }
 
@@ -234,6 +941,39 @@ impl Vertex for ModelVertex {
     }
 }
 
+impl InstanceRaw {
+    /// An instance that's drawn with the model matrix `model`, standing
+    /// still and not faded out at all
+    pub fn from_matrix(model: Matrix4<f32>) -> Self {
+        let upper = Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+        let normal = upper
+            .invert()
+            .map_or(Matrix3::identity(), |inverse| inverse.transpose());
+
+        InstanceRaw {
+            model: model.into(),
+            normal: normal.into(),
+            speed: 0.0,
+            alpha: 1.0,
+        }
+    }
+
+    /// Where the instance is, out of its model matrix
+    pub fn position(&self) -> [f32; 3] {
+        let [x, y, z, _] = self.model[3];
+        [x, y, z]
+    }
+
+    /// How much bigger than its model the instance is, going by whichever
+    /// axis it's stretched along the most
+    pub fn scale(&self) -> f32 {
+        self.model[..3]
+            .iter()
+            .map(|axis| Vector3::new(axis[0], axis[1], axis[2]).magnitude())
+            .fold(0.0, f32::max)
+    }
+}
+
 impl Vertex for InstanceRaw {
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         use std::mem;
@@ -285,7 +1025,152 @@ impl Vertex for InstanceRaw {
                     shader_location: 11,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 26]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Float32,
+                },
             ],
         }
     }
 }
+
+/// A vertex buffer of instances, which grows whenever there are more
+/// instances than fit in it
+pub struct InstanceBuffer {
+    label: &'static str,
+    buffer: wgpu::Buffer,
+    // Anything it's used for besides being drawn from and written to
+    usage: wgpu::BufferUsages,
+    capacity: usize,
+    len: u32,
+}
+
+impl InstanceBuffer {
+    pub fn new(device: &wgpu::Device, label: &'static str, capacity: usize) -> Self {
+        Self::with_usage(device, label, capacity, wgpu::BufferUsages::empty())
+    }
+
+    /// An instance buffer that can be used for other things too, like being
+    /// read in a compute shader
+    pub fn with_usage(
+        device: &wgpu::Device,
+        label: &'static str,
+        capacity: usize,
+        usage: wgpu::BufferUsages,
+    ) -> Self {
+        Self {
+            label,
+            buffer: create_instance_buffer(device, label, capacity, usage),
+            usage,
+            capacity,
+            len: 0,
+        }
+    }
+
+    /// Replaces the instances with these ones
+    pub fn write(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &AuditedQueue,
+        instances: &[InstanceRaw],
+    ) {
+        if instances.len() > self.capacity {
+            self.capacity = instances.len().next_power_of_two();
+            self.buffer = create_instance_buffer(device, self.label, self.capacity, self.usage);
+        }
+
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(instances));
+        self.len = instances.len() as u32;
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// How many instances there's room for before it has to grow
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// How many instances were last written
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+fn create_instance_buffer(
+    device: &wgpu::Device,
+    label: &str,
+    capacity: usize,
+    usage: wgpu::BufferUsages,
+) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        // Buffers can't be empty
+        size: (std::mem::size_of::<InstanceRaw>() * capacity.max(1)) as _,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST | usage,
+        mapped_at_creation: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A tetrahedron with its triangles facing outwards
+    fn tetrahedron() -> (Vec<ModelVertex>, Vec<u32>) {
+        let vertices = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]
+            .map(|position| ModelVertex {
+                position,
+                tex_coords: [0.0; 2],
+                normal: [0.0; 3],
+            })
+            .to_vec();
+        let indices = vec![0, 2, 1, 0, 1, 3, 0, 3, 2, 1, 2, 3];
+
+        (vertices, indices)
+    }
+
+    #[test]
+    fn outward_triangles_have_positive_volume() {
+        let (vertices, indices) = tetrahedron();
+        let (volume, _) = signed_volume(&vertices, &indices);
+        assert!((volume - 1.0 / 6.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn inside_out_meshes_get_flipped() {
+        let (vertices, indices) = tetrahedron();
+        let mut flipped = indices.clone();
+        fix_winding("test", &vertices, &mut flipped, Winding::Flip);
+        assert!(signed_volume(&vertices, &flipped).0 < 0.0);
+
+        fix_winding("test", &vertices, &mut flipped, Winding::Auto);
+        assert_eq!(flipped, indices);
+
+        fix_winding("test", &vertices, &mut flipped, Winding::Auto);
+        assert_eq!(flipped, indices);
+    }
+
+    #[test]
+    fn mtl_surfaces_fall_back_to_the_tweaks() {
+        let surface = Surface::from_mtl(Some([0.2, 0.3, 0.4]), Some(105.0));
+        assert!((surface.specular - 0.3).abs() < 1e-6);
+        assert_eq!(surface.shininess, Some(105.0));
+
+        // Missing (or nonsense) values mean the shader tweaks decide
+        assert_eq!(Surface::from_mtl(None, Some(0.0)), Surface::default());
+        let uniform = Surface::default().uniform(MaterialUniform::default());
+        assert_eq!(uniform.shininess, 0.0);
+    }
+}
@@ -0,0 +1,151 @@
+use kira::{
+    sound::{
+        static_sound::{StaticSoundData, StaticSoundHandle},
+        PlaybackState,
+    },
+    tween::Tween,
+    CommandError, Volume,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use kira::sound::{
+    streaming::{StreamingSoundData, StreamingSoundHandle, StreamingSoundSettings},
+    FromFileError,
+};
+
+use crate::{
+    error::{Error, Result},
+    integrity, loudness, resources,
+};
+
+// Songs bigger than this get streamed from disk as they play, instead of
+// being decoded into memory all at once
+#[cfg(not(target_arch = "wasm32"))]
+const STREAMING_THRESHOLD: u64 = 1024 * 1024;
+
+/// A song, and how loud it is
+pub struct Music {
+    pub data: MusicData,
+    /// Its integrated loudness in LUFS, if that's known
+    pub loudness: Option<f64>,
+}
+
+/// A song's sound, either decoded up front or streamed as it plays
+pub enum MusicData {
+    Static(StaticSoundData),
+    // Kira can't stream sounds on the web, so there the whole thing always
+    // gets decoded up front
+    #[cfg(not(target_arch = "wasm32"))]
+    Streaming(StreamingSoundData<FromFileError>),
+}
+
+/// A song that's been started
+pub enum MusicHandle {
+    Static(StaticSoundHandle),
+    #[cfg(not(target_arch = "wasm32"))]
+    Streaming(StreamingSoundHandle<FromFileError>),
+}
+
+impl Music {
+    /// Loads a song, picking whether to stream it based on how big the file
+    /// is. Its loudness comes from the asset manifest, or if it's not in
+    /// there it's measured, unless it's being streamed.
+    pub async fn load(path: &str) -> Result<Self> {
+        let known = integrity::loudness(path);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let size = tokio::fs::metadata(path)
+                .await
+                .map_err(|e| Error::from_io(path, e))?
+                .len();
+
+            if size > STREAMING_THRESHOLD {
+                log::info!("Streaming {path} ({} KiB)", size / 1024);
+
+                let data = StreamingSoundData::from_file(path, StreamingSoundSettings::default())
+                    .map_err(|e| Error::decode(path, e))?;
+                if known.is_none() {
+                    log::warn!("Don't know how loud {path} is, so it won't be normalised");
+                }
+
+                return Ok(Self {
+                    data: MusicData::Streaming(data),
+                    loudness: known,
+                });
+            }
+        }
+
+        let data = StaticSoundData::from_cursor(
+            std::io::Cursor::new(resources::map_bytes(path).await?),
+            Default::default(),
+        )
+        .map_err(|e| Error::decode(path, e))?;
+
+        let loudness = known.or_else(|| loudness::integrated(&data.frames, data.sample_rate));
+        Ok(Self {
+            data: MusicData::Static(data),
+            loudness,
+        })
+    }
+}
+
+impl MusicHandle {
+    pub fn state(&self) -> PlaybackState {
+        match self {
+            Self::Static(handle) => handle.state(),
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Streaming(handle) => handle.state(),
+        }
+    }
+
+    /// How far into the song it is, in seconds
+    pub fn position(&self) -> f64 {
+        match self {
+            Self::Static(handle) => handle.position(),
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Streaming(handle) => handle.position(),
+        }
+    }
+
+    pub fn resume(&mut self, tween: Tween) -> std::result::Result<(), CommandError> {
+        match self {
+            Self::Static(handle) => handle.resume(tween),
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Streaming(handle) => handle.resume(tween),
+        }
+    }
+
+    /// How fast the song plays (and how high it sounds), where 1 is normal
+    pub fn set_playback_rate(
+        &mut self,
+        rate: f64,
+        tween: Tween,
+    ) -> std::result::Result<(), CommandError> {
+        match self {
+            Self::Static(handle) => handle.set_playback_rate(rate, tween),
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Streaming(handle) => handle.set_playback_rate(rate, tween),
+        }
+    }
+
+    pub fn set_volume(
+        &mut self,
+        volume: Volume,
+        tween: Tween,
+    ) -> std::result::Result<(), CommandError> {
+        match self {
+            Self::Static(handle) => handle.set_volume(volume, tween),
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Streaming(handle) => handle.set_volume(volume, tween),
+        }
+    }
+
+    pub fn seek_to(&mut self, position: f64) -> std::result::Result<(), CommandError> {
+        match self {
+            Self::Static(handle) => handle.seek_to(position),
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Streaming(handle) => handle.seek_to(position),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+// How long a notification stays up, in seconds
+const NOTIFICATION_DURATION: f32 = 8.0;
+
+struct Notification {
+    text: String,
+    remaining: f32,
+    action: Option<Action>,
+}
+
+/// A button on a notification, which gets rid of the notification when it's
+/// clicked
+pub struct Action {
+    pub label: String,
+    pub run: fn(),
+}
+
+/// Little messages that pop up in the corner of the screen for a few seconds
+pub struct Notifications {
+    notifications: Vec<Notification>,
+}
+
+impl Notifications {
+    pub fn new() -> Self {
+        Self {
+            notifications: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, text: impl Into<String>) {
+        self.notifications.push(Notification {
+            text: text.into(),
+            remaining: NOTIFICATION_DURATION,
+            action: None,
+        });
+    }
+
+    // Only the web has any actions yet
+    #[allow(dead_code)]
+    pub fn push_with_action(&mut self, text: impl Into<String>, action: Action) {
+        self.notifications.push(Notification {
+            text: text.into(),
+            remaining: NOTIFICATION_DURATION,
+            action: Some(action),
+        });
+    }
+
+    /// Shows the notifications, counting down how long they have left.
+    /// This should be called once a frame.
+    pub fn show(&mut self, ctx: &egui::Context) {
+        let delta_time = ctx.input(|input| input.unstable_dt);
+
+        for notification in self.notifications.iter_mut() {
+            notification.remaining -= delta_time;
+        }
+
+        self.notifications
+            .retain(|notification| notification.remaining > 0.0);
+
+        if self.notifications.is_empty() {
+            return;
+        }
+
+        egui::Area::new("notifications")
+            .anchor(egui::Align2::RIGHT_BOTTOM, [-10.0, -10.0])
+            .show(ctx, |ui| {
+                for notification in self.notifications.iter_mut() {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.set_max_width(300.0);
+                        ui.horizontal(|ui| {
+                            ui.label(&notification.text);
+
+                            if ui.small_button("x").clicked() {
+                                notification.remaining = 0.0;
+                            }
+                        });
+
+                        if let Some(action) = &notification.action {
+                            if ui.button(&action.label).clicked() {
+                                (action.run)();
+                                notification.remaining = 0.0;
+                            }
+                        }
+                    });
+                }
+            });
+    }
+}
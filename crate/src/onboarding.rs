@@ -0,0 +1,143 @@
+//! A few cards shown over everything the first time it's opened, going
+//! through the camera controls, the light's colour and the reset button, for
+//! anyone who's stumbled onto the page without knowing what it is. Once
+//! they've been clicked through (or skipped) that's remembered in the
+//! settings, so they don't come up again.
+
+use crate::locale::Strings;
+
+// How far the cards sit from whatever they're pointing at
+const GAP: f32 = 12.0;
+const CARD_WIDTH: f32 = 260.0;
+
+/// One card
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Camera,
+    LightColour,
+    Reset,
+}
+
+impl Step {
+    pub const ALL: [Step; 3] = [Step::Camera, Step::LightColour, Step::Reset];
+
+    fn title(self, strings: &Strings) -> &str {
+        strings.get(match self {
+            Step::Camera => "onboarding.camera_title",
+            Step::LightColour => "onboarding.light_title",
+            Step::Reset => "onboarding.reset_title",
+        })
+    }
+
+    fn text(self, strings: &Strings) -> &str {
+        strings.get(match self {
+            Step::Camera => "onboarding.camera",
+            Step::LightColour => "onboarding.light",
+            Step::Reset => "onboarding.reset",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Onboarding {
+    // Which card's showing, if any are
+    step: Option<usize>,
+    // Where the widget each card's about was drawn this frame
+    targets: [Option<egui::Rect>; Step::ALL.len()],
+}
+
+impl Onboarding {
+    /// Starts at the first card, unless they've all been `seen` before
+    pub fn new(seen: bool) -> Self {
+        Self {
+            step: (!seen).then_some(0),
+            targets: [None; Step::ALL.len()],
+        }
+    }
+
+    /// Goes through the cards again from the start
+    pub fn restart(&mut self) {
+        self.step = Some(0);
+    }
+
+    /// Records where a widget a card points at was drawn. This should be
+    /// called every frame the widget's drawn, before [Onboarding::show].
+    pub fn target(&mut self, step: Step, rect: egui::Rect) {
+        self.targets[step as usize] = Some(rect);
+    }
+
+    /// Shows the current card, highlighting whatever it's about if that's on
+    /// screen. Returns true when the last one's been clicked past or they've
+    /// been skipped, which is when they should be remembered as seen.
+    pub fn show(&mut self, ctx: &egui::Context, strings: &Strings) -> bool {
+        let targets = std::mem::take(&mut self.targets);
+        let Some(index) = self.step else {
+            return false;
+        };
+
+        let step = Step::ALL[index];
+        let target = targets[index];
+        let last = index + 1 == Step::ALL.len();
+
+        if let Some(rect) = target {
+            let painter = ctx.layer_painter(egui::LayerId::new(
+                egui::Order::Foreground,
+                egui::Id::new("onboarding highlight"),
+            ));
+            let colour = ctx.style().visuals.selection.bg_fill;
+            painter.rect_stroke(rect.expand(4.0), 4.0, egui::Stroke::new(2.0, colour));
+        }
+
+        // Next to whatever it's about, or in the middle of the screen if
+        // that's nowhere to be seen (or it's the camera, which is everywhere)
+        let area = egui::Area::new("onboarding")
+            .order(egui::Order::Foreground)
+            .interactable(true);
+        let area = match target {
+            Some(rect) => area.fixed_pos(rect.right_top() + egui::vec2(GAP, 0.0)),
+            None => area.anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0]),
+        };
+
+        let mut next = false;
+        let mut skip = false;
+
+        let response = area.show(ctx, |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.set_max_width(CARD_WIDTH);
+                ui.heading(step.title(strings));
+                ui.label(step.text(strings));
+                ui.add_space(8.0);
+
+                ui.horizontal(|ui| {
+                    ui.label(strings.format(
+                        "onboarding.progress",
+                        &[("step", &(index + 1)), ("steps", &Step::ALL.len())],
+                    ));
+
+                    let next_label = if last {
+                        strings.get("onboarding.done")
+                    } else {
+                        strings.get("onboarding.next")
+                    };
+                    next = ui.button(next_label).clicked();
+                    if !last {
+                        skip = ui.button(strings.get("onboarding.skip")).clicked();
+                    }
+                });
+            });
+        });
+
+        // Clicking anywhere on the card moves it on too, not just the button
+        next |= response.response.interact(egui::Sense::click()).clicked();
+
+        if skip || (next && last) {
+            self.step = None;
+            true
+        } else {
+            if next {
+                self.step = Some(index + 1);
+            }
+            false
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! An outline around the body that's been picked, so it can be told apart
+//! from the rest of the pile. The body gets drawn twice in the overlay pass:
+//! once into only the stencil buffer, marking where it is, then again pushed
+//! out along its normals in the outline's colour, which only shows where it
+//! wasn't marked. That leaves a rim around its silhouette, which shows
+//! through anything in front of it.
+
+use crate::{
+    camera::CameraBinding,
+    model::{InstanceRaw, Model, ModelVertex, Vertex},
+    sync_audit::AuditedQueue,
+    texture,
+};
+
+// An orange nothing else in the scene is
+const COLOUR: [f32; 4] = [1.0, 0.45, 0.05, 1.0];
+// How far the outline sticks out, in pixels on the screen
+const WIDTH: f32 = 3.0;
+// What the stencil's set to wherever the body is
+const MARK: u32 = 1;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct OutlineUniform {
+    colour: [f32; 4],
+    viewport: [f32; 2],
+    width: f32,
+    _padding: f32,
+}
+
+pub struct OutlineRenderer {
+    mask_pipeline: wgpu::RenderPipeline,
+    outline_pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    // The one body's instance
+    instance_buffer: wgpu::Buffer,
+    // Whether there's anything to outline this frame
+    visible: bool,
+}
+
+impl OutlineRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("outline bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("outline uniform buffer"),
+            size: std::mem::size_of::<OutlineUniform>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("outline bind group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("outline instance buffer"),
+            size: std::mem::size_of::<InstanceRaw>() as _,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let (mask_pipeline, outline_pipeline) =
+            create_pipelines(device, shader, &bind_group_layout, format, sample_count);
+
+        Self {
+            mask_pipeline,
+            outline_pipeline,
+            bind_group_layout,
+            uniform_buffer,
+            bind_group,
+            instance_buffer,
+            visible: false,
+        }
+    }
+
+    /// Recreates the pipelines for a scene with a different number of samples
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        (self.mask_pipeline, self.outline_pipeline) = create_pipelines(
+            device,
+            shader,
+            &self.bind_group_layout,
+            format,
+            sample_count,
+        );
+    }
+
+    /// Uploads where the body to outline is this frame, if there is one, for
+    /// a scene `viewport` pixels big that's `scale` times the size of the
+    /// screen
+    pub fn prepare(
+        &mut self,
+        queue: &AuditedQueue,
+        instance: Option<InstanceRaw>,
+        viewport: [u32; 2],
+        scale: f32,
+    ) {
+        self.visible = instance.is_some();
+        let Some(instance) = instance else {
+            return;
+        };
+
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&[instance]));
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[OutlineUniform {
+                colour: COLOUR,
+                viewport: viewport.map(|size| size as f32),
+                width: WIDTH * scale,
+                _padding: 0.0,
+            }]),
+        );
+    }
+
+    /// Outlines the body, which is drawn with `model`. The render pass needs
+    /// the scene's depth buffer attached, for its stencil.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a CameraBinding,
+        model: &'a Model,
+    ) {
+        if !self.visible {
+            return;
+        }
+
+        self.record_draw(render_pass, camera, model);
+    }
+
+    /// Outlines the model at the last body that was outlined, whether
+    /// anything's outlined now or not, so both pipelines get used once
+    pub fn warm_up<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a CameraBinding,
+        model: &'a Model,
+    ) {
+        self.record_draw(render_pass, camera, model);
+    }
+
+    fn record_draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a CameraBinding,
+        model: &'a Model,
+    ) {
+        render_pass.set_bind_group(0, &camera.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.set_stencil_reference(MARK);
+
+        // Every mesh has to be marked before any of them are outlined, or the
+        // outlines would go over the other meshes
+        for pipeline in [&self.mask_pipeline, &self.outline_pipeline] {
+            render_pass.set_pipeline(pipeline);
+
+            for mesh in model.meshes.iter() {
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass
+                    .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..mesh.num_indices, 0, 0..1);
+            }
+        }
+    }
+}
+
+// The pipeline that marks the stencil, and the one that draws the outline
+fn create_pipelines(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("outline pipeline layout"),
+        bind_group_layouts: &[CameraBinding::bind_group_layout(device), bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    let pipeline = |label, entry_point, write_mask, stencil| {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point,
+                buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask,
+                })],
+            }),
+            // Both sides, so nothing's missing from the silhouette wherever
+            // the model's got holes or inside out bits
+            primitive: wgpu::PrimitiveState {
+                cull_mode: None,
+                ..Default::default()
+            },
+            // The depth's ignored so it can be seen through the pile
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil,
+                bias: Default::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        })
+    };
+
+    let stencil = |compare, pass_op, write_mask| {
+        let face = wgpu::StencilFaceState {
+            compare,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op,
+        };
+
+        wgpu::StencilState {
+            front: face,
+            back: face,
+            read_mask: 0xff,
+            write_mask,
+        }
+    };
+
+    let mask = pipeline(
+        "outline mask pipeline",
+        "vs_mask",
+        wgpu::ColorWrites::empty(),
+        stencil(
+            wgpu::CompareFunction::Always,
+            wgpu::StencilOperation::Replace,
+            0xff,
+        ),
+    );
+
+    let outline = pipeline(
+        "outline pipeline",
+        "vs_outline",
+        wgpu::ColorWrites::ALL,
+        stencil(
+            wgpu::CompareFunction::NotEqual,
+            wgpu::StencilOperation::Keep,
+            0,
+        ),
+    );
+
+    (mask, outline)
+}
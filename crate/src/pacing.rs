@@ -0,0 +1,181 @@
+//! Deciding when frames get drawn, and how much time each one says passed.
+//!
+//! Vsync alone keeps frames in step with the screen, but the time measured
+//! between them still wobbles by a millisecond or so either way. On a fast
+//! monitor that's a big chunk of a frame, and it shows up as the reis
+//! stuttering even though every frame makes it to the screen on time. So when
+//! the monitor's refresh rate is known, frame times that are close to a whole
+//! number of refreshes get rounded to it.
+
+use std::time::Duration;
+
+use instant::Instant;
+use winit::window::Window;
+
+use crate::config::FrameLimit;
+
+// How far off a whole number of refreshes a frame time can be and still get
+// rounded to it, as a fraction of a refresh
+const SNAP_TOLERANCE: f32 = 0.1;
+
+pub struct FramePacer {
+    pub limit: FrameLimit,
+    /// With vsync on, matching the refresh rate is already taken care of
+    pub vsync: bool,
+    // In hertz, if the monitor said
+    refresh_rate: Option<f32>,
+    last_frame: Instant,
+    next_frame: Instant,
+    // The time rounding has taken away from (or added to) the frames so far,
+    // which gets given back once it adds up to a refresh
+    drift: f32,
+}
+
+impl FramePacer {
+    pub fn new(limit: FrameLimit, vsync: bool) -> Self {
+        let now = Instant::now();
+        Self {
+            limit,
+            vsync,
+            refresh_rate: None,
+            last_frame: now,
+            next_frame: now,
+            drift: 0.0,
+        }
+    }
+
+    pub fn refresh_rate(&self) -> Option<f32> {
+        self.refresh_rate
+    }
+
+    /// Takes the refresh rate of the monitor the window's on. This changes
+    /// when the window gets moved to a different one.
+    pub fn set_refresh_rate(&mut self, millihertz: Option<u32>) {
+        let refresh_rate = millihertz
+            .filter(|&mhz| mhz > 0)
+            .map(|mhz| mhz as f32 / 1000.0);
+
+        if refresh_rate != self.refresh_rate {
+            match refresh_rate {
+                Some(hz) => log::info!("The monitor refreshes at {hz:.2}hz"),
+                None => log::info!("Couldn't tell how fast the monitor refreshes"),
+            }
+            self.refresh_rate = refresh_rate;
+            self.drift = 0.0;
+        }
+    }
+
+    // The least time between frames, in seconds
+    fn interval(&self) -> Option<f32> {
+        match self.limit {
+            // Waiting on top of vsync only risks missing the refresh
+            FrameLimit::MatchRefresh if self.vsync => None,
+            FrameLimit::MatchRefresh => self.refresh_rate.map(|hz| 1.0 / hz),
+            FrameLimit::Fps30 => Some(1.0 / 30.0),
+            FrameLimit::Fps60 => Some(1.0 / 60.0),
+            FrameLimit::Unlimited => None,
+        }
+    }
+
+    /// How long a frame's meant to take, in seconds. Without a limit that's
+    /// a refresh, or a sixtieth of a second if the monitor didn't say.
+    pub fn frame_budget(&self) -> f32 {
+        self.interval()
+            .or(self.refresh_rate.map(|hz| 1.0 / hz))
+            .unwrap_or(1.0 / 60.0)
+    }
+
+    /// When the next frame should be drawn, or None if it can be drawn now
+    pub fn wait_until(&self, now: Instant) -> Option<Instant> {
+        self.interval()?;
+        (self.next_frame > now).then_some(self.next_frame)
+    }
+
+    /// Starts a frame, returning how many seconds it should say have passed
+    /// since the last one
+    pub fn start_frame(&mut self) -> f32 {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_frame).as_secs_f32();
+        self.last_frame = now;
+
+        if let Some(interval) = self.interval() {
+            // Keeping to the schedule rather than counting from now means a
+            // frame that's woken up a bit late doesn't push the rest back.
+            // If it's fallen a whole frame behind though, there's no catching up.
+            let interval = Duration::from_secs_f32(interval);
+            self.next_frame += interval;
+            if self.next_frame + interval < now {
+                self.next_frame = now + interval;
+            }
+        }
+
+        match self.refresh_rate {
+            Some(hz) => self.snap(elapsed, 1.0 / hz),
+            None => elapsed,
+        }
+    }
+
+    /// Forgets about the time since the last frame, for when the canvas has
+    /// been hidden and nothing was drawn
+    #[cfg(target_arch = "wasm32")]
+    pub fn reset(&mut self) {
+        self.last_frame = Instant::now();
+        self.next_frame = self.last_frame;
+        self.drift = 0.0;
+    }
+
+    fn snap(&mut self, elapsed: f32, refresh: f32) -> f32 {
+        let refreshes = (elapsed / refresh).round();
+        if refreshes < 1.0 || (elapsed - refreshes * refresh).abs() > SNAP_TOLERANCE * refresh {
+            return elapsed;
+        }
+
+        let snapped = refreshes * refresh;
+        self.drift += elapsed - snapped;
+
+        // Every so often the real time comes out a refresh ahead or behind, and
+        // then it has to be made up for or the simulation would slowly drift
+        if self.drift.abs() >= refresh {
+            let made_up = self.drift.signum() * refresh;
+            self.drift -= made_up;
+            snapped + made_up
+        } else {
+            snapped
+        }
+    }
+}
+
+/// How fast the monitor the window's on refreshes, in millihertz. Browsers
+/// don't say, so on the web this is always None.
+pub fn monitor_refresh_rate(window: &Window) -> Option<u32> {
+    window.current_monitor()?.refresh_rate_millihertz()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wobbly_frames_are_snapped_to_the_refresh() {
+        let mut pacer = FramePacer::new(FrameLimit::MatchRefresh, true);
+        let refresh = 1.0 / 144.0;
+
+        // A millisecond either way is rounded off
+        assert_eq!(pacer.snap(refresh + 0.0005, refresh), refresh);
+        assert_eq!(pacer.snap(refresh - 0.0005, refresh), refresh);
+        // Missing a refresh counts as two
+        assert_eq!(pacer.snap(2.0 * refresh + 0.0003, refresh), 2.0 * refresh);
+        // Anything else is left alone
+        assert_eq!(pacer.snap(1.5 * refresh, refresh), 1.5 * refresh);
+
+        // No time goes missing in the long run
+        let mut pacer = FramePacer::new(FrameLimit::MatchRefresh, true);
+        let (mut real, mut snapped) = (0.0, 0.0);
+        for _ in 0..1000 {
+            let elapsed = refresh * 1.01;
+            real += elapsed;
+            snapped += pacer.snap(elapsed, refresh);
+        }
+        assert!((real - snapped).abs() < refresh);
+    }
+}
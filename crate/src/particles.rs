@@ -0,0 +1,610 @@
+use cgmath::{vec3, Vector3};
+use rand::Rng;
+
+use crate::{
+    camera::{Camera, CameraBinding},
+    locale::Strings,
+    sync_audit::AuditedQueue,
+    texture,
+};
+
+// There's one buffer big enough for this many, and anything past it just doesn't spawn
+const MAX_PARTICLES: usize = 2048;
+const PARTICLES_PER_PUFF: usize = 4;
+const PARTICLE_LIFETIME: f32 = 0.8;
+// Dust is light, so it doesn't fall very fast
+const PARTICLE_GRAVITY: f32 = -1.5;
+const PARTICLE_DRAG: f32 = 2.0;
+const RESOLVED_DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct ParticleRaw {
+    position: [f32; 3],
+    size: f32,
+    alpha: f32,
+}
+
+impl ParticleRaw {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 3] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32, 2 => Float32];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ParticleRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct ParticleUniform {
+    right: [f32; 4],
+    up: [f32; 4],
+    znear: f32,
+    zfar: f32,
+    softness: f32,
+    // 0 or 1, bools can't go in uniforms
+    soft: u32,
+}
+
+struct Particle {
+    position: Vector3<f32>,
+    velocity: Vector3<f32>,
+    age: f32,
+    max_size: f32,
+}
+
+/// Settings for the particles, tweakable from the ui
+#[derive(Debug, Clone)]
+pub struct ParticleConfig {
+    /// Whether impacts kick up dust
+    pub enabled: bool,
+    /// Whether particles fade out near whatever's behind them
+    pub soft: bool,
+    /// How close (in world units) a particle has to get to the scene behind
+    /// it before it starts fading out
+    pub softness: f32,
+}
+
+impl Default for ParticleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            soft: true,
+            softness: 0.5,
+        }
+    }
+}
+
+// Copies the multisampled depth buffer into a texture the particle shader can read
+struct DepthResolve {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    // Which binding the depth buffer goes in, since the shader has a
+    // different one for multisampled depth buffers
+    binding: u32,
+    bind_group: wgpu::BindGroup,
+    view: wgpu::TextureView,
+}
+
+/// Little puffs of dust that get kicked up when things hit each other.
+///
+/// Soft particles need to read the scene's depth, which means copying it out
+/// of the depth buffer first. That isn't possible everywhere (multisampled
+/// textures can't be read from shaders on webgl), so there they're always
+/// hard-edged.
+pub struct ParticleSystem {
+    pub config: ParticleConfig,
+    particles: Vec<Particle>,
+    instance_buffer: wgpu::Buffer,
+    uniform_buffer: wgpu::Buffer,
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    depth_resolve: Option<DepthResolve>,
+}
+
+impl ParticleSystem {
+    pub fn new(
+        device: &wgpu::Device,
+        particle_shader: &wgpu::ShaderModule,
+        resolve_shader: Option<&wgpu::ShaderModule>,
+        config: &wgpu::SurfaceConfiguration,
+        depth_texture: &texture::Texture,
+        sample_count: u32,
+    ) -> Self {
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("particle instance buffer"),
+            size: (MAX_PARTICLES * std::mem::size_of::<ParticleRaw>()) as _,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("particle uniform buffer"),
+            size: std::mem::size_of::<ParticleUniform>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("particle bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("particle pipeline layout"),
+            bind_group_layouts: &[CameraBinding::bind_group_layout(device), &layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("particle pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: particle_shader,
+                entry_point: "vs_main",
+                buffers: &[ParticleRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: particle_shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                cull_mode: None,
+                ..Default::default()
+            },
+            // Particles are tested against the scene's depth but don't write
+            // their own, so they don't cut holes in each other
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        let depth_resolve = resolve_shader.map(|shader| {
+            DepthResolve::new(device, shader, config, depth_texture, sample_count)
+        });
+
+        // Without a resolved depth texture the shader never reads it, but
+        // something still has to be bound
+        let bind_group = match depth_resolve.as_ref() {
+            Some(resolve) => create_bind_group(device, &layout, &uniform_buffer, &resolve.view),
+            None => {
+                let placeholder =
+                    create_depth_target(device, 1, 1, wgpu::TextureUsages::TEXTURE_BINDING);
+                create_bind_group(device, &layout, &uniform_buffer, &placeholder)
+            }
+        };
+
+        let config = ParticleConfig {
+            soft: depth_resolve.is_some(),
+            ..Default::default()
+        };
+
+        Self {
+            config,
+            particles: Vec::new(),
+            instance_buffer,
+            uniform_buffer,
+            pipeline,
+            layout,
+            bind_group,
+            depth_resolve,
+        }
+    }
+
+    /// Recreates the pipelines for a scene with a different number of samples.
+    /// The depth texture has to have been recreated with the new count already.
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        particle_shader: &wgpu::ShaderModule,
+        resolve_shader: Option<&wgpu::ShaderModule>,
+        config: &wgpu::SurfaceConfiguration,
+        depth_texture: &texture::Texture,
+        sample_count: u32,
+    ) {
+        let particles = std::mem::take(&mut self.particles);
+        let particle_config = self.config.clone();
+
+        *self = Self::new(
+            device,
+            particle_shader,
+            resolve_shader,
+            config,
+            depth_texture,
+            sample_count,
+        );
+
+        self.particles = particles;
+        self.config = particle_config;
+    }
+
+    /// Whether soft particles can be used at all
+    pub fn supports_soft(&self) -> bool {
+        self.depth_resolve.is_some()
+    }
+
+    pub fn num_particles(&self) -> usize {
+        self.particles.len()
+    }
+
+    /// Recreates everything that depends on the size of the depth buffer.
+    /// This has to be called whenever the depth texture is recreated.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        depth_texture: &texture::Texture,
+    ) {
+        let Some(resolve) = self.depth_resolve.as_mut() else {
+            return;
+        };
+
+        resolve.resize(device, config, depth_texture);
+        self.bind_group =
+            create_bind_group(device, &self.layout, &self.uniform_buffer, &resolve.view);
+    }
+
+    /// Kicks up a puff of dust at the given point
+    pub fn spawn_puff(&mut self, position: [f32; 3], rng: &mut impl Rng) {
+        if !self.config.enabled {
+            return;
+        }
+
+        let count = PARTICLES_PER_PUFF.min(MAX_PARTICLES - self.particles.len());
+
+        for _ in 0..count {
+            // Mostly outwards, a little bit up
+            let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+            let speed = rng.gen_range(0.5..1.5);
+            let velocity = vec3(
+                angle.cos() * speed,
+                rng.gen_range(0.2..1.0),
+                angle.sin() * speed,
+            );
+
+            self.particles.push(Particle {
+                position: Vector3::from(position),
+                velocity,
+                age: 0.0,
+                max_size: rng.gen_range(0.3..0.6),
+            });
+        }
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        for particle in self.particles.iter_mut() {
+            particle.age += delta_time;
+            particle.velocity.y += PARTICLE_GRAVITY * delta_time;
+            particle.velocity *= (1.0 - PARTICLE_DRAG * delta_time).max(0.0);
+            particle.position += particle.velocity * delta_time;
+        }
+
+        self.particles
+            .retain(|particle| particle.age < PARTICLE_LIFETIME);
+    }
+
+    /// Uploads the particles and the camera's orientation for drawing.
+    /// The camera's matrices must already be refreshed for this frame.
+    pub fn prepare(&self, queue: &AuditedQueue, camera: &Camera) {
+        let view = camera.view();
+        let right = vec3(view.x.x, view.y.x, view.z.x);
+        let up = vec3(view.x.y, view.y.y, view.z.y);
+
+        let uniform = ParticleUniform {
+            right: right.extend(0.0).into(),
+            up: up.extend(0.0).into(),
+            znear: camera.znear,
+            zfar: camera.zfar,
+            softness: self.config.softness.max(0.01),
+            soft: (self.config.soft && self.supports_soft()) as u32,
+        };
+
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        let instances = self
+            .particles
+            .iter()
+            .map(|particle| {
+                let t = particle.age / PARTICLE_LIFETIME;
+
+                ParticleRaw {
+                    position: particle.position.into(),
+                    // Puffs spread out as they fade away
+                    size: particle.max_size * (0.4 + 0.6 * t.sqrt()),
+                    alpha: 0.6 * (1.0 - t) * (1.0 - t),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if !instances.is_empty() {
+            queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instances));
+        }
+    }
+
+    /// Copies the scene's depth out for the soft particles to use. This has to
+    /// happen after the scene is drawn and before the particles are.
+    pub fn resolve_depth(&self, encoder: &mut wgpu::CommandEncoder) {
+        let Some(resolve) = self.depth_resolve.as_ref() else {
+            return;
+        };
+
+        if self.particles.is_empty() || !self.config.soft {
+            return;
+        }
+
+        resolve.run(encoder);
+    }
+
+    /// Runs the depth resolve whether the particles need it or not, so its
+    /// pipeline's been used once before they do
+    pub fn warm_up_depth_resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        if let Some(resolve) = self.depth_resolve.as_ref() {
+            resolve.run(encoder);
+        }
+    }
+
+    /// Draws one particle (whatever was last in the instance buffer), even if
+    /// there's no dust, for the same reason
+    pub fn warm_up<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a CameraBinding,
+    ) {
+        self.record_draw(render_pass, camera, 1);
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera: &'a CameraBinding) {
+        if self.particles.is_empty() {
+            return;
+        }
+
+        self.record_draw(render_pass, camera, self.particles.len() as _);
+    }
+
+    fn record_draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a CameraBinding,
+        particles: u32,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &camera.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        render_pass.draw(0..6, 0..particles);
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, strings: &Strings) {
+        ui.checkbox(&mut self.config.enabled, strings.get("particles.enabled"));
+
+        ui.add_enabled_ui(self.supports_soft(), |ui| {
+            ui.checkbox(&mut self.config.soft, strings.get("particles.soft"))
+                .on_disabled_hover_text(strings.get("particles.soft_unsupported"));
+
+            ui.horizontal(|ui| {
+                ui.label(strings.get("particles.softness"));
+                ui.add(
+                    egui::DragValue::new(&mut self.config.softness)
+                        .clamp_range(0.01..=5.0)
+                        .speed(0.01),
+                );
+            });
+        });
+
+        ui.label(strings.format("particles.count", &[("count", &self.num_particles())]));
+    }
+}
+
+impl DepthResolve {
+    fn run(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth resolve pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    fn new(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        config: &wgpu::SurfaceConfiguration,
+        depth_texture: &texture::Texture,
+        sample_count: u32,
+    ) -> Self {
+        let multisampled = sample_count > 1;
+        let (binding, entry_point) = if multisampled {
+            (0, "fs_main")
+        } else {
+            (1, "fs_single")
+        };
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("depth resolve bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Depth,
+                },
+                count: None,
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("depth resolve pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("depth resolve pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point,
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: RESOLVED_DEPTH_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: Default::default(),
+            depth_stencil: None,
+            multisample: Default::default(),
+            multiview: None,
+        });
+
+        let (bind_group, view) =
+            Self::create_targets(device, &layout, binding, config, depth_texture);
+
+        Self {
+            pipeline,
+            layout,
+            binding,
+            bind_group,
+            view,
+        }
+    }
+
+    fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        depth_texture: &texture::Texture,
+    ) {
+        (self.bind_group, self.view) =
+            Self::create_targets(device, &self.layout, self.binding, config, depth_texture);
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        binding: u32,
+        config: &wgpu::SurfaceConfiguration,
+        depth_texture: &texture::Texture,
+    ) -> (wgpu::BindGroup, wgpu::TextureView) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("depth resolve bind group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::TextureView(&depth_texture.depth_only_view()),
+            }],
+        });
+
+        let view = create_depth_target(
+            device,
+            config.width,
+            config.height,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        );
+
+        (bind_group, view)
+    }
+}
+
+fn create_depth_target(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    usage: wgpu::TextureUsages,
+) -> wgpu::TextureView {
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some("resolved depth texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: RESOLVED_DEPTH_FORMAT,
+            usage,
+            view_formats: &[],
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &wgpu::Buffer,
+    depth_view: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("particle bind group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(depth_view),
+            },
+        ],
+    })
+}
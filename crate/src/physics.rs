@@ -1,16 +1,49 @@
-use rand::{Rng, thread_rng};
-use std::f32::consts::PI;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::{collections::HashSet, f32::consts::PI, ops::Range};
 
+use rapier3d::control::{CharacterAutostep, CharacterLength, KinematicCharacterController};
 use rapier3d::prelude::*;
 
-use crate::model::{Instance, InstanceRaw};
+use crate::collider_gen::{self, Parts};
+use crate::emitter::Emitter;
+use crate::entities::Bodies;
+use crate::scene::Prop;
+use crate::model::{ImportTransform, Instance, InstanceRaw};
 
-const GRAVITY: Vector<f32> = vector![0.0, -9.81, 0.0];
-const REI_SPAWN_TIME: f32 = 3.157 / 16.0;
+pub const GRAVITY: Vector<f32> = vector![0.0, -9.81, 0.0];
+// Height that downward rays start at when looking for the surface below a point
+const SURFACE_RAY_START: f32 = 500.0;
 pub const NUM_REIS: usize = 1000;
+// How long it takes cleared reis to shrink away, in seconds
+const CLEAR_DURATION: f32 = 1.0;
+// How far cleared reis sink while they shrink
+const CLEAR_SINK: f32 = 1.5;
+// How long reis take to fade out once they reach the max age
+const AGE_FADE_DURATION: f32 = 2.0;
+// Reis going slower than this count as part of the pile
+const PILE_REST_SPEED: f32 = 0.5;
+/// How much time each simulation step covers, in seconds. Steps are always
+/// the same length so the simulation doesn't depend on the frame rate.
+pub const TIMESTEP: f32 = 1.0 / 60.0;
+// If a frame takes long enough to need more steps than this, the simulation
+// just slows down instead. Otherwise slow steps would mean more steps the next
+// frame, which would be even slower...
+const MAX_STEPS_PER_FRAME: u32 = 5;
+/// Once there are this many balls out, throwing another removes the oldest
+pub const MAX_PROJECTILES: usize = 16;
+/// The height of the top of the ground
+pub const GROUND_LEVEL: f32 = 0.1;
+/// How far the ground goes out from the origin along x and z
+pub const GROUND_EXTENT: f32 = 1000.0;
+// How long balls take to fade out at the end of their lifetime
+const PROJECTILE_FADE_DURATION: f32 = 0.5;
+/// Things only count as hitting the ground if they push on it harder than
+/// this, in newtons. A rei sitting still pushes with its weight, which is
+/// well under it unless there's a pile on top.
+pub const GROUND_HIT_FORCE: f32 = 1500.0;
 
 // https://www.youtube.com/watch?v=x4tw4CIuBks
-#[derive(Default)]
 pub struct PhysicsSimulation {
     collider_set: ColliderSet,
     rigidbody_set: RigidBodySet,
@@ -22,14 +55,188 @@ pub struct PhysicsSimulation {
     impulse_joint_set: ImpulseJointSet,
     multibody_joint_set: MultibodyJointSet,
     ccd_solver: CCDSolver,
-    reis: Vec<RigidBodyHandle>,
-    timer: f32,
+    query_pipeline: QueryPipeline,
+    emitters: Vec<Emitter>,
+    // The rei model's import transform, which its collider has to match
+    rei_transform: ImportTransform,
+    // What the reis' colliders are made of, for a rei with a scale of 1
+    rei_parts: Parts,
+    gravity: Vector<f32>,
+    ground: ColliderHandle,
+    // The rei that sits still in the middle of everything
+    statue: RigidBodyHandle,
+    reis: Vec<Rei>,
+    // Reis that stay put, which get drawn along with the statue
+    props: Vec<Rei>,
+    projectiles: Vec<Projectile>,
+    // Once there are this many reis, each new one replaces the oldest
+    max_reis: usize,
+    // If set, reis fade out and are removed once they're this many seconds old
+    max_age: Option<f32>,
+    // What the emitters' spawn timers are sped up or slowed down by
+    spawn_rate: f32,
     rei_index: usize,
+    // Total reis spawned, including ones that have since been removed
+    spawned: usize,
+    // Seconds of simulation so far
+    time: f64,
+    // Time that's passed but hasn't been simulated yet, less than a step
+    accumulator: f32,
+    collision_recv: Option<rapier3d::crossbeam::channel::Receiver<CollisionEvent>>,
+    contact_force_recv: Option<rapier3d::crossbeam::channel::Receiver<ContactForceEvent>>,
+    event_collector: Option<ChannelEventCollector>,
+    impacts: Vec<Impact>,
+    ground_hits: Vec<GroundHit>,
+    // The colliders that were pushing on the ground harder than
+    // GROUND_HIT_FORCE last step. They don't count as hitting it again until
+    // they've let up, otherwise a squashed rei would be hitting it every step.
+    pressing_ground: Vec<ColliderHandle>,
+    // Everything random about the simulation comes from this, so a run can be
+    // replayed by starting with the same seed
+    seed: u64,
+    rng: ChaCha8Rng,
 }
 
-fn random_rotation() -> Vector<f32> {
-    let mut rng = thread_rng();
+/// Something hit something else during a simulation step
+#[derive(Debug, Clone, Copy)]
+pub struct Impact {
+    /// The simulation time of the step the impact happened in
+    pub time: f64,
+    /// Roughly where the two things touched
+    pub position: [f32; 3],
+}
+
+/// Something landed on the ground hard enough to be heard
+#[derive(Debug, Clone, Copy)]
+pub struct GroundHit {
+    /// The simulation time of the step it happened in
+    pub time: f64,
+    pub position: [f32; 3],
+    /// How hard it pushed on the ground, in newtons. Always more than
+    /// [GROUND_HIT_FORCE].
+    pub force: f32,
+}
+
+/// A point where two colliders are touching, from the last simulation step
+#[cfg(feature = "debug-render")]
+#[derive(Debug, Clone, Copy)]
+pub struct Contact {
+    pub position: [f32; 3],
+    /// Points out of the first collider, towards the second
+    pub normal: [f32; 3],
+    /// How hard the solver pushed the two apart along the normal
+    pub impulse: f32,
+}
+
+/// A run of instances that all came from the same emitter
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    /// The index of the emitter, or None for the statue
+    pub emitter: Option<usize>,
+    /// The tint its reis were given in the editor, which goes over the
+    /// emitter's
+    pub tint: Option<[f32; 3]>,
+    pub instances: Range<u32>,
+}
+
+/// How many bodies are in the simulation, for the stats
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BodyCounts {
+    pub total: usize,
+    pub sleeping: usize,
+    /// How many reis have been spawned since the simulation started
+    pub spawned: usize,
+}
+
+/// A dynamic rei spawned by one of the emitters, or by clicking
+#[derive(Clone, Copy)]
+struct Rei {
+    handle: RigidBodyHandle,
+    // Which emitter spawned it, None for the statue and ones spawned by clicking
+    emitter: Option<usize>,
+    scale: f32,
+    // Seconds since it was spawned
+    age: f32,
+    // Seconds since it started shrinking away, if it's been cleared
+    despawn: Option<f32>,
+    // Where it was before the latest step, so it can be drawn part of the
+    // way between the two
+    previous: Isometry<Real>,
+    // Set by hand in the editor, over the top of its emitter's
+    tint: Option<[f32; 3]>,
+}
+
+/// Where a ray hit something, from [PhysicsSimulation::pick]
+#[derive(Debug, Clone, Copy)]
+pub struct RayHit {
+    pub point: Point<f32>,
+    /// Pointing out of the surface that was hit
+    pub normal: Vector<f32>,
+    /// The body it was part of. The ground doesn't have one.
+    pub body: Option<RigidBodyHandle>,
+}
+
+/// How the bowling balls get thrown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProjectileSettings {
+    /// The reis have a density of 1, so this is about how many reis' worth
+    /// of volume it weighs as much as
+    pub mass: f32,
+    /// How fast it leaves the camera, in units per second
+    pub speed: f32,
+    pub radius: f32,
+    /// Seconds until it's removed
+    pub lifetime: f32,
+}
+
+impl Default for ProjectileSettings {
+    fn default() -> Self {
+        Self {
+            mass: 200.0,
+            speed: 30.0,
+            radius: 0.6,
+            lifetime: 8.0,
+        }
+    }
+}
+
+// A heavy ball thrown from the camera, which plows through the pile and then
+// disappears after a while
+struct Projectile {
+    handle: RigidBodyHandle,
+    radius: f32,
+    age: f32,
+    lifetime: f32,
+    previous: Isometry<Real>,
+}
 
+impl Rei {
+    // How much of its size is left, and how far it's sunk, while it's being cleared
+    fn despawn_transform(&self) -> (f32, f32) {
+        let Some(time) = self.despawn else {
+            return (1.0, 0.0);
+        };
+
+        let t = (time / CLEAR_DURATION).clamp(0.0, 1.0);
+        let shrink = 1.0 - t * t * (3.0 - 2.0 * t);
+        (shrink.max(0.01), CLEAR_SINK * t * t)
+    }
+
+    // How visible it still is, fading out once it gets past the max age
+    fn alpha(&self, max_age: Option<f32>) -> f32 {
+        max_age.map_or(1.0, |max_age| {
+            (1.0 - (self.age - max_age) / AGE_FADE_DURATION).clamp(0.0, 1.0)
+        })
+    }
+
+    // Whether it's finished shrinking or fading away and can be removed
+    fn is_gone(&self, max_age: Option<f32>) -> bool {
+        self.despawn.is_some_and(|time| time >= CLEAR_DURATION)
+            || max_age.is_some_and(|max_age| self.age >= max_age + AGE_FADE_DURATION)
+    }
+}
+
+fn random_rotation(rng: &mut impl Rng) -> Vector<f32> {
     vector![
         rng.gen_range(0.0..6.18),
         rng.gen_range(0.0..6.18),
@@ -37,51 +244,595 @@ fn random_rotation() -> Vector<f32> {
     ]
 }
 
+/// The biggest seed [random_seed] gives. Seeds get shown in the ui and written
+/// into scene files and json, and anything bigger than this can't be kept
+/// exactly as a double (or in toml, past 63 bits).
+pub const MAX_RANDOM_SEED: u64 = (1 << 53) - 1;
+
+/// A seed for a new run that nobody asked to be any one in particular
+pub fn random_seed() -> u64 {
+    rand::thread_rng().gen_range(0..=MAX_RANDOM_SEED)
+}
+
 impl PhysicsSimulation {
-    pub fn new() -> Self {
+    /// Rain from the default emitter, with reis the model's size. The same
+    /// seed always makes the same rain, however many times it's run.
+    pub fn with_seed(seed: u64) -> Self {
+        Self::new(vec![Emitter::default()], ImportTransform::default(), seed)
+    }
+
+    pub fn new(emitters: Vec<Emitter>, rei_transform: ImportTransform, seed: u64) -> Self {
         let mut collider_set = ColliderSet::new();
         let mut rigidbody_set = RigidBodySet::new();
 
-        let ground = ColliderBuilder::cuboid(1000.0, 0.1, 1000.0).build();
-        collider_set.insert(ground);
+        // It's centred on the origin, so its half height is where the top is
+        // Only the ground asks for contact force events, so those are always
+        // something hitting it
+        let ground = ColliderBuilder::cuboid(GROUND_EXTENT, GROUND_LEVEL, GROUND_EXTENT)
+            .active_events(ActiveEvents::CONTACT_FORCE_EVENTS)
+            .contact_force_event_threshold(GROUND_HIT_FORCE)
+            .build();
+        let ground = collider_set.insert(ground);
 
-        let rei = rigidbody_set.insert(
+        let rei_parts = hand_made_rei_parts(&rei_transform);
+        let statue = rigidbody_set.insert(
             RigidBodyBuilder::fixed()
                 .translation(vector![0.0, 0.0, 0.0])
                 .build(),
         );
-        collider_set.insert_with_parent(rei_collider(), rei, &mut rigidbody_set);
+        collider_set.insert_with_parent(rei_collider(1.0, &rei_parts), statue, &mut rigidbody_set);
+
+        // The query pipeline gets updated every step, but it needs to know
+        // about the ground and the statue before the first one
+        let mut query_pipeline = QueryPipeline::new();
+        query_pipeline.update(&rigidbody_set, &collider_set);
+
+        let (collision_send, collision_recv) = rapier3d::crossbeam::channel::unbounded();
+        let (contact_force_send, contact_force_recv) = rapier3d::crossbeam::channel::unbounded();
 
         Self {
+            collision_recv: Some(collision_recv),
+            contact_force_recv: Some(contact_force_recv),
+            event_collector: Some(ChannelEventCollector::new(
+                collision_send,
+                contact_force_send,
+            )),
+            query_pipeline,
             collider_set,
             rigidbody_set,
+            emitters,
+            rei_transform,
+            rei_parts,
+            gravity: GRAVITY,
+            ground,
+            statue,
             reis: Vec::with_capacity(NUM_REIS),
-            ..Default::default()
+            props: Vec::new(),
+            projectiles: Vec::new(),
+            max_reis: NUM_REIS,
+            max_age: None,
+            spawn_rate: 1.0,
+            rei_index: 0,
+            spawned: 0,
+            time: 0.0,
+            accumulator: 0.0,
+            impacts: Vec::new(),
+            ground_hits: Vec::new(),
+            pressing_ground: Vec::new(),
+            integration_parameters: IntegrationParameters::default(),
+            physics_pipeline: PhysicsPipeline::new(),
+            island_manager: IslandManager::new(),
+            broad_phase: BroadPhase::new(),
+            narrow_phase: NarrowPhase::new(),
+            impulse_joint_set: ImpulseJointSet::new(),
+            multibody_joint_set: MultibodyJointSet::new(),
+            ccd_solver: CCDSolver::new(),
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    /// The seed the simulation was started with
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn statue_position(&self) -> [f32; 3] {
+        (*self.rigidbody_set[self.statue].translation()).into()
+    }
+
+    pub fn prop_position(&self, index: usize) -> Option<[f32; 3]> {
+        let prop = self.props.get(index)?;
+        Some((*self.rigidbody_set[prop.handle].translation()).into())
+    }
+
+    /// Where the most recently spawned rei is, unless it's being cleared away
+    pub fn newest_rei_position(&self) -> Option<[f32; 3]> {
+        // Once the ring buffer's full the newest is just before the oldest
+        let len = self.reis.len();
+        let newest = self.reis.get((self.rei_index + len).checked_sub(1)? % len)?;
+        if newest.despawn.is_some() {
+            return None;
+        }
+
+        Some((*self.rigidbody_set[newest.handle].translation()).into())
+    }
+
+    pub fn statue_body(&self) -> RigidBodyHandle {
+        self.statue
+    }
+
+    /// Where a rigid body is, or None if it isn't there anymore
+    pub fn body_position(&self, handle: RigidBodyHandle) -> Option<Isometry<Real>> {
+        self.rigidbody_set.get(handle).map(|body| *body.position())
+    }
+
+    pub fn prop_body(&self, index: usize) -> Option<RigidBodyHandle> {
+        self.props.get(index).map(|prop| prop.handle)
+    }
+
+    pub fn rei_transform(&self) -> &ImportTransform {
+        &self.rei_transform
+    }
+
+    pub fn rei_parts(&self) -> &Parts {
+        &self.rei_parts
+    }
+
+    /// Swaps what the reis' colliders are made of (for a rei with a scale of
+    /// 1), including the statue's and the props'
+    pub fn set_rei_parts(&mut self, parts: Parts) {
+        self.rei_parts = parts;
+
+        let statue = self.statue();
+        let reis = std::iter::once(&statue)
+            .chain(&self.props)
+            .chain(&self.reis);
+        for rei in reis {
+            let colliders = self.rigidbody_set[rei.handle].colliders().to_vec();
+            for collider in colliders {
+                self.collider_set.remove(
+                    collider,
+                    &mut self.island_manager,
+                    &mut self.rigidbody_set,
+                    true,
+                );
+            }
+
+            self.collider_set.insert_with_parent(
+                rei_collider(rei.scale, &self.rei_parts),
+                rei.handle,
+                &mut self.rigidbody_set,
+            );
+        }
+
+        self.query_pipeline
+            .update(&self.rigidbody_set, &self.collider_set);
+    }
+
+    pub fn emitters(&self) -> &[Emitter] {
+        &self.emitters
+    }
+
+    pub fn emitters_mut(&mut self) -> &mut Vec<Emitter> {
+        &mut self.emitters
+    }
+
+    /// Takes an emitter out, along with its reis' idea of which emitter they
+    /// came from. They're left where they are, and drawn like the statue.
+    pub fn remove_emitter(&mut self, index: usize) {
+        self.emitters.remove(index);
+
+        for rei in self.reis.iter_mut() {
+            rei.emitter = match rei.emitter {
+                Some(i) if i == index => None,
+                Some(i) if i > index => Some(i - 1),
+                emitter => emitter,
+            };
+        }
+    }
+
+    /// Replaces the props with these ones. They don't move, so anything
+    /// touching the old ones gets woken up to fall off them.
+    pub fn set_props(&mut self, props: &[Prop]) {
+        for prop in std::mem::take(&mut self.props) {
+            self.remove_body(prop.handle);
+        }
+
+        for prop in props {
+            let rotation = Rotation::new(vector![0.0, prop.rotation.to_radians(), 0.0]);
+            let position = Isometry::from_parts(Vector::from(prop.position).into(), rotation);
+            let handle = self
+                .rigidbody_set
+                .insert(RigidBodyBuilder::fixed().position(position).build());
+            self.collider_set.insert_with_parent(
+                rei_collider(prop.scale, &self.rei_parts),
+                handle,
+                &mut self.rigidbody_set,
+            );
+
+            self.props.push(Rei {
+                handle,
+                emitter: None,
+                scale: prop.scale,
+                age: 0.0,
+                despawn: None,
+                previous: position,
+                tint: None,
+            });
+        }
+
+        // Otherwise rays and surface checks wouldn't see them until the next step
+        self.query_pipeline
+            .update(&self.rigidbody_set, &self.collider_set);
+    }
+
+    /// Spawns a bunch of reis from one of the emitters all at once
+    pub fn burst(&mut self, emitter_index: usize, count: usize) {
+        for _ in 0..count {
+            self.spawn_rei(emitter_index);
+        }
+    }
+
+    /// Pushes the reis within `radius` of `centre` away from it (and a bit
+    /// upwards), harder the closer they are
+    pub fn explode(&mut self, centre: [f32; 3], radius: f32, strength: f32) {
+        let centre = Vector::from(centre);
+
+        for rei in self.reis.iter() {
+            let body = &mut self.rigidbody_set[rei.handle];
+            let offset = body.translation() - centre;
+            let distance = offset.norm();
+
+            if distance < radius {
+                let outwards = offset.try_normalize(1.0e-4).unwrap_or_default();
+                let direction = (outwards + vector![0.0, 1.0, 0.0]).normalize();
+                let falloff = 1.0 - distance / radius;
+                body.apply_impulse(direction * strength * falloff * body.mass(), true);
+            }
+        }
+    }
+
+    /// Throws a bowling ball from `origin` towards `direction`
+    pub fn launch_projectile(
+        &mut self,
+        origin: [f32; 3],
+        direction: [f32; 3],
+        settings: &ProjectileSettings,
+    ) {
+        if self.projectiles.len() >= MAX_PROJECTILES {
+            let oldest = self.projectiles.remove(0);
+            self.remove_body(oldest.handle);
+        }
+
+        let direction = Vector::from(direction).normalize();
+        // Far enough forward that it doesn't start off inside the camera
+        let position = Vector::from(origin) + direction * (settings.radius + 0.5);
+
+        let handle = self.rigidbody_set.insert(
+            RigidBodyBuilder::dynamic()
+                .translation(position)
+                .linvel(direction * settings.speed)
+                // It's fast enough to go straight through thin things otherwise
+                .ccd_enabled(true)
+                .build(),
+        );
+        self.collider_set.insert_with_parent(
+            ColliderBuilder::ball(settings.radius)
+                .mass(settings.mass)
+                .restitution(0.3)
+                .build(),
+            handle,
+            &mut self.rigidbody_set,
+        );
+
+        self.projectiles.push(Projectile {
+            handle,
+            radius: settings.radius,
+            age: 0.0,
+            lifetime: settings.lifetime,
+            previous: *self.rigidbody_set[handle].position(),
+        });
+    }
+
+    fn update_projectiles(&mut self, delta_time: f32) {
+        for projectile in self.projectiles.iter_mut() {
+            projectile.age += delta_time;
+        }
+
+        let (expired, projectiles) = std::mem::take(&mut self.projectiles)
+            .into_iter()
+            .partition(|projectile| projectile.age >= projectile.lifetime);
+        self.projectiles = projectiles;
+
+        for projectile in expired.into_iter() {
+            self.remove_body(projectile.handle);
+        }
+    }
+
+    /// An instance for each bowling ball, for drawing with a sphere of radius 1
+    pub fn projectile_instances(&self) -> Vec<InstanceRaw> {
+        self.projectiles
+            .iter()
+            .map(|projectile| self.projectile_instance(projectile))
+            .collect()
+    }
+
+    fn projectile_instance(&self, projectile: &Projectile) -> InstanceRaw {
+        let body = &self.rigidbody_set[projectile.handle];
+        let position = self.drawn_position(projectile.handle, &projectile.previous);
+        let remaining = projectile.lifetime - projectile.age;
+
+        Instance::from_rapier_position(&position)
+            .with_scale(projectile.radius)
+            .with_speed(body.linvel().norm())
+            .with_alpha((remaining / PROJECTILE_FADE_DURATION).clamp(0.0, 1.0))
+            .to_raw()
+    }
+
+    /// How a body's drawn this frame, and which model it's drawn with, or
+    /// None if it's not there anymore (or it's the ground)
+    pub fn body_instance(&self, handle: RigidBodyHandle) -> Option<(Bodies, InstanceRaw)> {
+        if handle == self.statue {
+            return Some((Bodies::Reis, self.instance(&self.statue())));
+        }
+
+        let mut reis = self.props.iter().chain(self.reis.iter());
+        if let Some(rei) = reis.find(|rei| rei.handle == handle) {
+            return Some((Bodies::Reis, self.instance(rei)));
+        }
+
+        let projectile = self.projectiles.iter().find(|projectile| projectile.handle == handle)?;
+        Some((Bodies::Projectiles, self.projectile_instance(projectile)))
+    }
+
+    pub fn set_gravity(&mut self, gravity: [f32; 3]) {
+        self.gravity = gravity.into();
+
+        // Sleeping bodies wouldn't notice otherwise
+        for (_, body) in self.rigidbody_set.iter_mut() {
+            body.wake_up(true);
         }
     }
 
-    fn spawn_rei(&mut self) {
-        let mut rng = thread_rng();
+    fn spawn_rei(&mut self, emitter_index: usize) {
+        let emitter = &self.emitters[emitter_index];
+        let scale = emitter.random_scale(&mut self.rng);
+        let mut position = emitter.random_position(&mut self.rng);
+
+        if emitter.snap_to_surface {
+            if let Some(height) = self.surface_height(position.x, position.z) {
+                position.y = height + emitter.surface_offset;
+            }
+        }
+
+        // A moving emitter throws its reis along with it
+        let velocity = emitter.spawn_velocity();
+        let rotation = random_rotation(&mut self.rng);
+        self.insert_rei(
+            rei_collider(scale, &self.rei_parts),
+            Isometry::new(position, rotation),
+            velocity,
+            scale,
+            Some(emitter_index),
+        );
+    }
+
+    /// Drops a rei onto a surface, at `point` with `normal` pointing out of it.
+    /// It's pushed out along the normal far enough to not start off inside it.
+    pub fn spawn_rei_at(&mut self, point: Point<f32>, normal: Vector<f32>) {
+        let collider = rei_collider(1.0, &self.rei_parts);
+        let rotation = Rotation::new(random_rotation(&mut self.rng));
+        let bounds = collider.shape().compute_local_bounding_sphere();
+
+        let centre = point + normal * bounds.radius;
+        let position = centre.coords - rotation * bounds.center.coords;
+        self.insert_rei(
+            collider,
+            Isometry::from_parts(position.into(), rotation),
+            Vector::zeros(),
+            1.0,
+            None,
+        );
+    }
 
-        let rei = self.rigidbody_set.insert(
+    fn insert_rei(
+        &mut self,
+        collider: Collider,
+        position: Isometry<Real>,
+        velocity: Vector<Real>,
+        scale: f32,
+        emitter: Option<usize>,
+    ) {
+        let handle = self.rigidbody_set.insert(
             RigidBodyBuilder::dynamic()
-            .translation(vector![rng.gen_range(-20.0..20.0), 10.0, rng.gen_range(-50.0..0.0)])
-            .rotation(random_rotation())
-            .build()
+                .position(position)
+                .linvel(velocity)
+                .build(),
         );
-        self.collider_set.insert_with_parent(rei_collider(), rei, &mut self.rigidbody_set);
+        self.collider_set
+            .insert_with_parent(collider, handle, &mut self.rigidbody_set);
+
+        let rei = Rei {
+            handle,
+            emitter,
+            scale,
+            age: 0.0,
+            despawn: None,
+            previous: *self.rigidbody_set[handle].position(),
+            tint: None,
+        };
+        self.spawned += 1;
 
-        if self.reis.len() < NUM_REIS {
+        if self.reis.len() < self.max_reis {
             self.reis.push(rei);
         } else {
             self.remove_rei(self.rei_index);
             self.reis[self.rei_index] = rei;
-            self.rei_index = (self.rei_index + 1) % NUM_REIS;
+            self.rei_index = (self.rei_index + 1) % self.max_reis;
+        }
+    }
+
+    /// Starts every rei shrinking and sinking away. They stop moving straight
+    /// away, and are removed once they've disappeared.
+    pub fn clear_pile(&mut self) {
+        for rei in self.reis.iter_mut().filter(|rei| rei.despawn.is_none()) {
+            rei.despawn = Some(0.0);
+            self.rigidbody_set[rei.handle].set_enabled(false);
+        }
+    }
+
+    /// Whether some reis are still shrinking away
+    pub fn is_clearing(&self) -> bool {
+        self.reis.iter().any(|rei| rei.despawn.is_some())
+    }
+
+    /// Where each rei that can be seen is drawn, for picking them out on
+    /// screen. Ones that are being cleared away, and ones from hidden
+    /// emitters, are left out.
+    pub fn visible_reis(&self) -> impl Iterator<Item = (RigidBodyHandle, [f32; 3])> + '_ {
+        self.reis
+            .iter()
+            .filter(|rei| rei.despawn.is_none())
+            .filter(|rei| {
+                rei.emitter
+                    .and_then(|index| self.emitters.get(index))
+                    .is_none_or(|emitter| emitter.visible)
+            })
+            .map(|rei| {
+                let position = self.drawn_position(rei.handle, &rei.previous);
+                (rei.handle, position.translation.vector.into())
+            })
+    }
+
+    /// Whether this is one of the reis, and it hasn't been removed or started
+    /// being cleared away
+    pub fn contains_rei(&self, handle: RigidBodyHandle) -> bool {
+        self.reis
+            .iter()
+            .any(|rei| rei.handle == handle && rei.despawn.is_none())
+    }
+
+    /// Clears some of the reis away, the same way [PhysicsSimulation::clear_pile]
+    /// clears all of them
+    pub fn clear_reis(&mut self, handles: &HashSet<RigidBodyHandle>) {
+        for rei in self.reis.iter_mut() {
+            if handles.contains(&rei.handle) && rei.despawn.is_none() {
+                rei.despawn = Some(0.0);
+                self.rigidbody_set[rei.handle].set_enabled(false);
+            }
+        }
+    }
+
+    /// Stops some of the reis where they are, or lets them go again. Frozen
+    /// reis still get in the way of everything else, like the statue does.
+    pub fn freeze_reis(&mut self, handles: &HashSet<RigidBodyHandle>, frozen: bool) {
+        let body_type = if frozen {
+            RigidBodyType::Fixed
+        } else {
+            RigidBodyType::Dynamic
+        };
+
+        for rei in self.reis.iter().filter(|rei| handles.contains(&rei.handle)) {
+            self.rigidbody_set[rei.handle].set_body_type(body_type, true);
+        }
+    }
+
+    /// Throws some of the reis upwards, all at the same speed however big they
+    /// are. Frozen ones and ones being cleared away stay put.
+    pub fn lift_reis(&mut self, handles: &HashSet<RigidBodyHandle>, speed: f32) {
+        for rei in self.reis.iter().filter(|rei| handles.contains(&rei.handle)) {
+            let body = &mut self.rigidbody_set[rei.handle];
+            if body.is_dynamic() && body.is_enabled() {
+                body.apply_impulse(vector![0.0, speed * body.mass(), 0.0], true);
+            }
+        }
+    }
+
+    /// Tints some of the reis instead of with their emitter's tint, or puts
+    /// it back with None
+    pub fn tint_reis(&mut self, handles: &HashSet<RigidBodyHandle>, tint: Option<[f32; 3]>) {
+        for rei in self
+            .reis
+            .iter_mut()
+            .filter(|rei| handles.contains(&rei.handle))
+        {
+            rei.tint = tint;
+        }
+    }
+
+    /// Sets how old reis can get before they fade out and are removed, or
+    /// None to keep them until they're replaced
+    pub fn set_max_age(&mut self, max_age: Option<f32>) {
+        self.max_age = max_age.map(|age| age.max(0.0));
+    }
+
+    pub fn max_age(&self) -> Option<f32> {
+        self.max_age
+    }
+
+    /// Slows down every emitter by the same amount, 0 stopping them. Bursts
+    /// and reis spawned by hand aren't affected.
+    pub fn set_spawn_rate(&mut self, spawn_rate: f32) {
+        self.spawn_rate = spawn_rate.max(0.0);
+    }
+
+    // Ages the reis and moves the cleared ones along, removing any that have
+    // finished disappearing
+    fn update_despawns(&mut self, delta_time: f32) {
+        let max_age = self.max_age;
+        let mut finished = false;
+
+        for rei in self.reis.iter_mut() {
+            rei.age += delta_time;
+            if let Some(time) = rei.despawn.as_mut() {
+                *time += delta_time;
+            }
+            finished |= rei.is_gone(max_age);
+        }
+
+        if !finished {
+            return;
+        }
+
+        // Oldest first, so the ring buffer carries on in the right place
+        self.reis.rotate_left(self.rei_index);
+        self.rei_index = 0;
+
+        for i in 0..self.reis.len() {
+            if self.reis[i].is_gone(max_age) {
+                self.remove_rei(i);
+            }
+        }
+
+        self.reis.retain(|rei| !rei.is_gone(max_age));
+    }
+
+    /// Changes how many reis there can be at once (up to NUM_REIS). If there
+    /// are already more than that, the oldest ones are removed.
+    pub fn set_max_reis(&mut self, max_reis: usize) {
+        let max_reis = max_reis.clamp(1, NUM_REIS);
+
+        // Put the oldest first so they're the ones that get removed, and so
+        // new ones get added in the right place afterwards
+        self.reis.rotate_left(self.rei_index);
+        self.rei_index = 0;
+
+        let excess = self.reis.len().saturating_sub(max_reis);
+        for i in 0..excess {
+            self.remove_rei(i);
         }
+        self.reis.drain(..excess);
+
+        self.max_reis = max_reis;
     }
 
     fn remove_rei(&mut self, rei_index: usize) {
-        self.rigidbody_set.remove(self.reis[rei_index], 
+        self.remove_body(self.reis[rei_index].handle);
+    }
+
+    fn remove_body(&mut self, handle: RigidBodyHandle) {
+        self.rigidbody_set.remove(handle, 
             &mut self.island_manager, 
             &mut self.collider_set, 
             &mut self.impulse_joint_set, 
@@ -90,18 +841,70 @@ impl PhysicsSimulation {
         );
     }
 
+    /// Moves the simulation on by however many steps fit in `delta_time`.
+    /// Whatever's left over gets carried over to the next update.
     pub fn update(&mut self, delta_time: f32) {
-        self.timer += delta_time;
-        
-        if self.timer >= REI_SPAWN_TIME {
-            self.timer = 0.0;
-            self.spawn_rei();
+        for emitter in self.emitters.iter_mut() {
+            emitter.track_velocity(delta_time);
+        }
+
+        self.accumulator += delta_time;
+
+        let mut steps = 0;
+        while self.accumulator >= TIMESTEP {
+            if steps == MAX_STEPS_PER_FRAME {
+                self.accumulator = 0.0;
+                break;
+            }
+
+            self.accumulator -= TIMESTEP;
+            self.step(TIMESTEP);
+            steps += 1;
+        }
+    }
+
+    /// How far through the next step the simulation is, from 0 to 1. Instances
+    /// are drawn this far between their last two positions.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator / TIMESTEP
+    }
+
+    // Where a body gets drawn, between where it was before the latest step
+    // and where it is now. Without this, anything moving would stutter
+    // whenever the frame rate isn't a multiple of the step rate.
+    fn drawn_position(&self, handle: RigidBodyHandle, previous: &Isometry<Real>) -> Isometry<Real> {
+        previous.lerp_slerp(self.rigidbody_set[handle].position(), self.alpha())
+    }
+
+    fn step(&mut self, delta_time: f32) {
+        self.update_despawns(delta_time);
+        self.update_projectiles(delta_time);
+
+        for rei in self.reis.iter_mut() {
+            rei.previous = *self.rigidbody_set[rei.handle].position();
+        }
+
+        for projectile in self.projectiles.iter_mut() {
+            projectile.previous = *self.rigidbody_set[projectile.handle].position();
+        }
+
+        for i in 0..self.emitters.len() {
+            if self.emitters[i].tick(delta_time * self.spawn_rate) {
+                self.spawn_rei(i);
+            }
         }
 
         self.integration_parameters.dt = delta_time;
+        self.time += delta_time as f64;
+
+        let no_events = ();
+        let events: &dyn EventHandler = match &self.event_collector {
+            Some(collector) => collector,
+            None => &no_events,
+        };
 
         self.physics_pipeline.step(
-            &GRAVITY,
+            &self.gravity,
             &self.integration_parameters,
             &mut self.island_manager,
             &mut self.broad_phase,
@@ -111,36 +914,693 @@ impl PhysicsSimulation {
             &mut self.impulse_joint_set,
             &mut self.multibody_joint_set,
             &mut self.ccd_solver,
-            None,
-            &(),
+            Some(&mut self.query_pipeline),
             &(),
+            events,
         );
+
+        if let Some(recv) = &self.collision_recv {
+            while let Ok(event) = recv.try_recv() {
+                if event.started() {
+                    let position = self.contact_point(event.collider1(), event.collider2());
+                    self.impacts.push(Impact {
+                        time: self.time,
+                        position,
+                    });
+                }
+            }
+        }
+
+        if let Some(recv) = &self.contact_force_recv {
+            let mut pressing = Vec::new();
+
+            while let Ok(event) = recv.try_recv() {
+                let other = if event.collider1 == self.ground {
+                    event.collider2
+                } else {
+                    event.collider1
+                };
+
+                if !self.pressing_ground.contains(&other) {
+                    let position = self.contact_point(event.collider1, event.collider2);
+                    self.ground_hits.push(GroundHit {
+                        time: self.time,
+                        position,
+                        force: event.total_force_magnitude,
+                    });
+                }
+
+                pressing.push(other);
+            }
+
+            self.pressing_ground = pressing;
+        }
     }
 
-    pub fn instances(&self) -> Vec<InstanceRaw> {
-        self.rigidbody_set
-            .iter()
-            .map(|(_, rb)| Instance::from_rapier_position(rb.position()).to_raw())
+    // A point where two colliders are touching. If the narrow phase doesn't have
+    // one (which shouldn't really happen right after they start touching) it's
+    // just the point halfway between them.
+    fn contact_point(&self, collider1: ColliderHandle, collider2: ColliderHandle) -> [f32; 3] {
+        let contact = self
+            .narrow_phase
+            .contact_pair(collider1, collider2)
+            .and_then(|pair| {
+                pair.manifolds
+                    .iter()
+                    .flat_map(|manifold| manifold.data.solver_contacts.iter())
+                    .next()
+            });
+
+        if let Some(contact) = contact {
+            return contact.point.coords.into();
+        }
+
+        let translation = |handle| {
+            self.collider_set
+                .get(handle)
+                .map(|collider: &Collider| *collider.translation())
+                .unwrap_or_default()
+        };
+
+        ((translation(collider1) + translation(collider2)) / 2.0).into()
+    }
+
+    /// Every contact point the narrow phase is tracking that the solver pushed
+    /// on with at least `min_impulse`
+    #[cfg(feature = "debug-render")]
+    pub fn contacts(&self, min_impulse: f32) -> Vec<Contact> {
+        let mut contacts = Vec::new();
+
+        for pair in self.narrow_phase.contact_pairs() {
+            let Some(collider) = self.collider_set.get(pair.collider1) else {
+                continue;
+            };
+
+            // Manifolds without any solver contacts are only close, not touching
+            for manifold in pair
+                .manifolds
+                .iter()
+                .filter(|manifold| !manifold.data.solver_contacts.is_empty())
+            {
+                for point in manifold.points.iter() {
+                    if point.data.impulse < min_impulse {
+                        continue;
+                    }
+
+                    // The points are relative to the part of a compound
+                    // collider (like a rei's) they're on, not the whole thing
+                    let part = manifold.subshape_pos1.unwrap_or_else(Isometry::identity);
+                    contacts.push(Contact {
+                        position: (collider.position() * part * point.local_p1).coords.into(),
+                        normal: manifold.data.normal.into(),
+                        impulse: point.data.impulse,
+                    });
+                }
+            }
+        }
+
+        contacts
+    }
+
+    /// Seconds of simulation so far
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Takes all the impacts that have happened since this was last called
+    pub fn drain_impacts(&mut self) -> impl Iterator<Item = Impact> + '_ {
+        self.impacts.drain(..)
+    }
+
+    /// Takes all the times something's hit the ground since this was last called
+    pub fn drain_ground_hits(&mut self) -> impl Iterator<Item = GroundHit> + '_ {
+        self.ground_hits.drain(..)
+    }
+
+    /// Casts a ray into the world, returning the first point it hits
+    pub fn cast_ray(
+        &self,
+        origin: Point<f32>,
+        direction: Vector<f32>,
+        max_distance: f32,
+    ) -> Option<Point<f32>> {
+        let ray = Ray::new(origin, direction.normalize());
+
+        self.query_pipeline
+            .cast_ray(
+                &self.rigidbody_set,
+                &self.collider_set,
+                &ray,
+                max_distance,
+                true,
+                QueryFilter::default(),
+            )
+            .map(|(_, distance)| ray.point_at(distance))
+    }
+
+    /// The first thing along a ray, for picking things out with the mouse
+    pub fn pick(
+        &self,
+        origin: Point<f32>,
+        direction: Vector<f32>,
+        max_distance: f32,
+    ) -> Option<RayHit> {
+        let ray = Ray::new(origin, direction.normalize());
+
+        let (collider, hit) = self.query_pipeline.cast_ray_and_get_normal(
+            &self.rigidbody_set,
+            &self.collider_set,
+            &ray,
+            max_distance,
+            true,
+            QueryFilter::default(),
+        )?;
+
+        Some(RayHit {
+            point: ray.point_at(hit.toi),
+            normal: hit.normal,
+            body: self.collider_set[collider].parent(),
+        })
+    }
+
+    /// Gives a body a shove at `point`, as if it was hit there. It's scaled by
+    /// the body's mass so heavy things get knocked about just as much.
+    pub fn push(&mut self, body: RigidBodyHandle, point: Point<f32>, velocity: Vector<f32>) {
+        let Some(body) = self.rigidbody_set.get_mut(body) else {
+            return;
+        };
+
+        // Reis being cleared away are frozen where they are
+        if body.is_dynamic() && body.is_enabled() {
+            let impulse = velocity * body.mass();
+            body.apply_impulse_at_point(impulse, point, true);
+        }
+    }
+
+    /// Moves a character (like the camera, when it's walking) through the
+    /// world, sliding along walls and stepping up onto small things. It isn't
+    /// in the simulation itself, so it doesn't push anything out of the way.
+    /// Returns how far it actually moved, and whether it's standing on
+    /// something.
+    pub fn move_character(
+        &self,
+        shape: &dyn Shape,
+        position: Point<f32>,
+        translation: Vector<f32>,
+        delta_time: f32,
+    ) -> (Vector<f32>, bool) {
+        let controller = KinematicCharacterController {
+            // Enough to climb onto a fallen rei, but not a pile of them
+            autostep: Some(CharacterAutostep {
+                max_height: CharacterLength::Absolute(0.6),
+                min_width: CharacterLength::Absolute(0.2),
+                include_dynamic_bodies: true,
+            }),
+            snap_to_ground: Some(CharacterLength::Absolute(0.3)),
+            ..Default::default()
+        };
+
+        let movement = controller.move_shape(
+            delta_time,
+            &self.rigidbody_set,
+            &self.collider_set,
+            &self.query_pipeline,
+            shape,
+            &Isometry::translation(position.x, position.y, position.z),
+            translation,
+            QueryFilter::default().exclude_sensors(),
+            |_| {},
+        );
+
+        (movement.translation, movement.grounded)
+    }
+
+    /// The height of the highest surface directly above or below the given x and z
+    pub fn surface_height(&self, x: f32, z: f32) -> Option<f32> {
+        self.cast_ray(
+            point![x, SURFACE_RAY_START, z],
+            vector![0.0, -1.0, 0.0],
+            2.0 * SURFACE_RAY_START,
+        )
+        .map(|point| point.y)
+    }
+
+    fn instance(&self, rei: &Rei) -> InstanceRaw {
+        let body = &self.rigidbody_set[rei.handle];
+        let (shrink, sink) = rei.despawn_transform();
+        let position = self.drawn_position(rei.handle, &rei.previous);
+
+        let mut instance = Instance::from_rapier_position(&position)
+            .with_scale(rei.scale * shrink)
+            .with_speed(body.linvel().norm())
+            .with_alpha(rei.alpha(self.max_age));
+        instance.position.y -= sink;
+
+        instance.to_raw()
+    }
+
+    /// Every instance, bucketed by the emitter that spawned them. The statue
+    /// and the props (and any reis that were spawned by clicking, or whose
+    /// emitter's gone) come first, then the reis
+    /// of visible emitters, then the hidden ones, so all the visible instances
+    /// are at the start. Reis tinted in the editor get split off into
+    /// clusters of their own, straight after the rest of their emitter's.
+    pub fn clustered_instances(&self) -> (Vec<InstanceRaw>, Vec<Cluster>) {
+        let statue = self.statue();
+
+        let mut plain: Vec<_> = std::iter::once(&statue).chain(self.props.iter()).collect();
+        let mut buckets = vec![Vec::new(); self.emitters.len()];
+        for rei in self.reis.iter() {
+            match rei.emitter {
+                Some(index) => buckets.get_mut(index).into_iter().for_each(|bucket| bucket.push(rei)),
+                None => plain.push(rei),
+            }
+        }
+
+        // Sorting is stable, so the emitters stay in order otherwise
+        let mut order: Vec<usize> = (0..self.emitters.len()).collect();
+        order.sort_by_key(|&index| !self.emitters[index].visible);
+
+        let mut instances = Vec::with_capacity(self.num_instances());
+        let mut clusters = Vec::with_capacity(self.emitters.len() + 1);
+
+        let buckets = std::iter::once((None, plain)).chain(
+            order
+                .into_iter()
+                .map(|index| (Some(index), std::mem::take(&mut buckets[index]))),
+        );
+
+        for (emitter, reis) in buckets {
+            // The untinted ones always get a cluster, even if it's empty, so
+            // there's one for every emitter
+            let mut tints = vec![None];
+            for rei in reis.iter() {
+                if !tints.contains(&rei.tint) {
+                    tints.push(rei.tint);
+                }
+            }
+
+            for tint in tints {
+                let start = instances.len() as u32;
+                instances.extend(
+                    reis.iter()
+                        .filter(|rei| rei.tint == tint)
+                        .map(|rei| self.instance(rei)),
+                );
+                clusters.push(Cluster {
+                    emitter,
+                    tint,
+                    instances: start..instances.len() as u32,
+                });
+            }
+        }
+
+        (instances, clusters)
+    }
+
+    // The statue, dressed up as a rei so it can be drawn like the others
+    fn statue(&self) -> Rei {
+        Rei {
+            handle: self.statue,
+            emitter: None,
+            scale: 1.0,
+            age: 0.0,
+            despawn: None,
+            previous: *self.rigidbody_set[self.statue].position(),
+            tint: None,
+        }
+    }
+
+    /// The shapes of the colliders, for the collider debug view. The ground
+    /// comes first, then each part of the reis' collider at the size of a
+    /// rei with a scale of 1.
+    #[cfg(feature = "debug-render")]
+    pub fn collider_shapes(&self) -> Vec<SharedShape> {
+        std::iter::once(self.collider_set[self.ground].shared_shape().clone())
+            .chain(self.rei_parts.iter().map(|(_, shape)| shape.clone()))
             .collect()
     }
 
+    /// Where each of the [PhysicsSimulation::collider_shapes] are, as
+    /// instances of them. The reis' colliders are put where their models are
+    /// drawn, between the last two steps, so they can be compared.
+    #[cfg(feature = "debug-render")]
+    pub fn collider_instances(&self) -> Vec<Vec<InstanceRaw>> {
+        let ground = self.collider_set[self.ground].position();
+        let mut instances = vec![vec![Instance::from_rapier_position(ground).to_raw()]];
+
+        let statue = self.statue();
+        for rei in std::iter::once(&statue)
+            .chain(&self.props)
+            .chain(&self.reis)
+        {
+            let body = &self.rigidbody_set[rei.handle];
+            let position = self.drawn_position(rei.handle, &rei.previous);
+
+            for &handle in body.colliders() {
+                let collider = &self.collider_set[handle];
+                let Some(compound) = collider.shape().as_compound() else {
+                    continue;
+                };
+
+                let parent = position * collider.position_wrt_parent().copied().unwrap_or_default();
+                for (i, (part, _)) in compound.shapes().iter().enumerate() {
+                    if instances.len() < i + 2 {
+                        instances.resize(i + 2, Vec::new());
+                    }
+
+                    let instance = Instance::from_rapier_position(&(parent * part))
+                        .with_scale(rei.scale)
+                        .to_raw();
+                    instances[i + 1].push(instance);
+                }
+            }
+        }
+
+        instances
+    }
+
+    /// How many reis there are, not counting the statue and the props
+    pub fn num_reis(&self) -> usize {
+        self.reis.len()
+    }
+
     pub fn num_instances(&self) -> usize {
-        self.reis.len() + 1
+        self.reis.len() + self.props.len() + 1
+    }
+
+    /// The average speed of the reis, in units per second
+    pub fn average_speed(&self) -> f32 {
+        if self.reis.is_empty() {
+            return 0.0;
+        }
+
+        let total: f32 = self
+            .reis
+            .iter()
+            .map(|rei| self.rigidbody_set[rei.handle].linvel().norm())
+            .sum();
+
+        total / self.reis.len() as f32
+    }
+
+    /// How high the pile goes, going by the highest rei that's come to rest
+    pub fn pile_height(&self) -> f32 {
+        self.reis
+            .iter()
+            .filter(|rei| rei.despawn.is_none())
+            .map(|rei| &self.rigidbody_set[rei.handle])
+            .filter(|body| body.is_sleeping() || body.linvel().norm() < PILE_REST_SPEED)
+            .map(|body| body.translation().y)
+            .fold(0.0, f32::max)
+    }
+
+    /// How many reis each emitter has in the scene right now, not counting
+    /// ones that are on their way out
+    pub fn emitter_counts(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.emitters.len()];
+        for rei in self.reis.iter().filter(|rei| rei.despawn.is_none()) {
+            if let Some(count) = rei.emitter.and_then(|i| counts.get_mut(i)) {
+                *count += 1;
+            }
+        }
+        counts
+    }
+
+    pub fn body_counts(&self) -> BodyCounts {
+        // The statue and the props never move, so they count as sleeping
+        let sleeping = 1
+            + self.props.len()
+            + self
+                .reis
+                .iter()
+                .filter(|rei| self.rigidbody_set[rei.handle].is_sleeping())
+                .count();
+
+        BodyCounts {
+            total: self.num_instances(),
+            sleeping,
+            spawned: self.spawned,
+        }
     }
 }
 
-fn rei_collider() -> rapier3d::prelude::Collider {
-    let head_shape = SharedShape::round_cylinder(0.4, 0.95, 0.5);
-    let body_shape = SharedShape::capsule_y(0.7, 0.65);
+/// The shapes that make up the rei's collider when it's not generated from
+/// the model, for a rei with a scale of 1. They're placed to fit the model as
+/// it is in the file, then moved the same way the model's import transform
+/// moves it. A mirrored model still has the shapes the same size, they're
+/// just placed on the other side.
+pub fn hand_made_rei_parts(transform: &ImportTransform) -> Parts {
+    let scale = transform.scale;
+    let size = scale.abs();
+    let head_shape = SharedShape::round_cylinder(0.4 * size, 0.95 * size, 0.5 * size);
+    let body_shape = SharedShape::capsule_y(0.7 * size, 0.65 * size);
+
+    let rotation = transform.rotation();
+    let rotation = Rotation::new_normalize(rapier3d::na::Quaternion::new(
+        rotation.s,
+        rotation.v.x,
+        rotation.v.y,
+        rotation.v.z,
+    ));
+    let [x, y, z] = transform.offset;
+    let import = Isometry::from_parts(Translation::new(x, y, z), rotation);
+
+    let head_trans = import
+        * Isometry::from_parts(
+            Translation::new(0.0, 1.1 * scale, 0.0),
+            Rotation::new(vector![1.0, 0.0, 0.0] * PI / 2.0),
+        );
+    let body_trans = import * Isometry::translation(0.0, 3.35 * scale, -0.1 * scale);
 
-    let head_trans = Isometry::from_parts(
-        Translation::new(0.0, 1.1, 0.0),
-        Rotation::new(vector![1.0, 0.0, 0.0] * PI / 2.0),
-    );
-    let body_trans = Isometry::translation(0.0, 3.35, -0.1);
+    vec![(head_trans, head_shape), (body_trans, body_shape)]
+}
 
-    ColliderBuilder::compound(vec![(head_trans, head_shape), (body_trans, body_shape)])
+// The parts are scaled uniformly to match the rei's scale, including where
+// they are (the import offset is in the model's units, so it grows with the
+// rei like everything else)
+fn rei_collider(rei_scale: f32, parts: &Parts) -> rapier3d::prelude::Collider {
+    ColliderBuilder::compound(collider_gen::scale_parts(parts, rei_scale))
         .density(1.0)
         .restitution(0.8)
+        .active_events(ActiveEvents::COLLISION_EVENTS)
         .build()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reis_are_drawn_between_steps() {
+        let mut physics = PhysicsSimulation::new(Vec::new(), ImportTransform::default(), 0);
+        physics.spawn_rei_at(point![0.0, 20.0, 0.0], Vector::y());
+
+        // Two and a half steps' worth, so it's halfway to the next one
+        physics.update(TIMESTEP * 2.5);
+        assert!((physics.alpha() - 0.5).abs() < 1e-3);
+
+        let rei = &physics.reis[0];
+        let before = rei.previous.translation.y;
+        let now = physics.rigidbody_set[rei.handle].translation().y;
+        let drawn = physics.drawn_position(rei.handle, &rei.previous).translation.y;
+
+        // It's falling, so it's gone down since the last step
+        assert!(now < before);
+        assert!((drawn - (before + now) / 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn moving_emitters_throw_their_reis() {
+        let emitter = Emitter {
+            extents: [0.0; 3],
+            spawn_interval: TIMESTEP,
+            ..Default::default()
+        };
+        let mut physics = PhysicsSimulation::new(vec![emitter], ImportTransform::default(), 0);
+
+        // Standing still, they just drop
+        physics.update(TIMESTEP);
+        let first = &mut physics.rigidbody_set[physics.reis[0].handle];
+        assert_eq!(first.linvel().x, 0.0);
+
+        // Out of the way, so the next one isn't knocked about by landing on it
+        first.set_translation(vector![50.0, 50.0, 50.0], true);
+
+        // Then it moves along x, and the next one goes with it
+        physics.emitters_mut()[0].position[0] += 0.1;
+        physics.update(TIMESTEP);
+        let second = &physics.rigidbody_set[physics.reis[1].handle];
+        assert!(second.linvel().x > 1.0);
+    }
+
+    // Where every rei is after a second of rain from the default emitter
+    fn rain(seed: u64) -> Vec<[f32; 3]> {
+        let mut physics = PhysicsSimulation::with_seed(seed);
+        physics.burst(0, 5);
+        for _ in 0..60 {
+            physics.update(TIMESTEP);
+        }
+
+        physics
+            .reis
+            .iter()
+            .map(|rei| (*physics.rigidbody_set[rei.handle].translation()).into())
+            .collect()
+    }
+
+    #[test]
+    fn the_same_seed_makes_the_same_rain() {
+        let first = rain(1234);
+        assert!(!first.is_empty());
+        assert_eq!(first, rain(1234));
+        assert_ne!(first, rain(1235));
+
+        assert!(random_seed() <= MAX_RANDOM_SEED);
+    }
+
+    #[test]
+    fn group_operations_only_touch_the_group() {
+        let mut physics = PhysicsSimulation::new(Vec::new(), ImportTransform::default(), 0);
+        for x in [-10.0, 0.0, 10.0] {
+            physics.spawn_rei_at(point![x, 20.0, 0.0], Vector::y());
+        }
+        let handles: Vec<_> = physics.reis.iter().map(|rei| rei.handle).collect();
+        let group = |indices: &[usize]| indices.iter().map(|&i| handles[i]).collect::<HashSet<_>>();
+
+        physics.tint_reis(&group(&[1, 2]), Some([1.0, 0.0, 0.0]));
+        physics.freeze_reis(&group(&[0]), true);
+        physics.lift_reis(&group(&[0, 1]), 20.0);
+        physics.update(TIMESTEP);
+
+        // The tinted ones get a cluster of their own
+        let (_, clusters) = physics.clustered_instances();
+        let tinted: Vec<_> = clusters
+            .iter()
+            .filter(|cluster| cluster.tint.is_some())
+            .collect();
+        assert_eq!(tinted.len(), 1);
+        assert_eq!(tinted[0].instances.len(), 2);
+        assert_eq!(
+            clusters
+                .iter()
+                .map(|cluster| cluster.instances.len())
+                .sum::<usize>(),
+            physics.num_instances()
+        );
+
+        // The frozen one didn't go anywhere, lifted or not
+        let velocity = |i: usize| physics.rigidbody_set[handles[i]].linvel().y;
+        assert_eq!(velocity(0), 0.0);
+        assert!(velocity(1) > 10.0);
+        assert!(velocity(2) < 0.0);
+
+        physics.clear_reis(&group(&[2]));
+        assert!(physics.contains_rei(handles[1]));
+        assert!(!physics.contains_rei(handles[2]));
+        assert_eq!(physics.visible_reis().count(), 2);
+    }
+
+    #[test]
+    fn swapped_parts_replace_every_reis_collider() {
+        let mut physics = PhysicsSimulation::new(Vec::new(), ImportTransform::default(), 0);
+        physics.set_props(&[Prop {
+            scale: 2.0,
+            ..Default::default()
+        }]);
+        physics.set_rei_parts(vec![(Isometry::identity(), SharedShape::ball(1.0))]);
+
+        let radius = |handle: RigidBodyHandle| {
+            let colliders = physics.rigidbody_set[handle].colliders();
+            assert_eq!(colliders.len(), 1);
+            let compound = physics.collider_set[colliders[0]]
+                .shape()
+                .as_compound()
+                .unwrap();
+            compound.shapes()[0].1.as_ball().unwrap().radius
+        };
+
+        assert_eq!(radius(physics.statue), 1.0);
+        assert_eq!(radius(physics.props[0].handle), 2.0);
+    }
+
+    #[test]
+    fn collider_parts_grow_with_the_rei() {
+        let transform = ImportTransform {
+            offset: [0.0, 2.0, 0.0],
+            ..Default::default()
+        };
+        let parts = |scale: f32| {
+            let collider = rei_collider(scale, &hand_made_rei_parts(&transform));
+            let compound = collider.shape().as_compound().unwrap();
+            compound
+                .shapes()
+                .iter()
+                .map(|(position, _)| position.translation.vector)
+                .collect::<Vec<_>>()
+        };
+
+        // Everything's twice as far from the origin, offset included
+        for (small, big) in parts(1.0).into_iter().zip(parts(2.0)) {
+            assert!((small * 2.0 - big).norm() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn landing_hard_hits_the_ground_once() {
+        let mut physics = PhysicsSimulation::new(Vec::new(), ImportTransform::default(), 0);
+        physics.spawn_rei_at(point![20.0, 30.0, 20.0], Vector::y());
+
+        let mut hits = Vec::new();
+        while hits.is_empty() && physics.time < 10.0 {
+            physics.update(TIMESTEP);
+            hits.extend(physics.drain_ground_hits());
+        }
+
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0].force > GROUND_HIT_FORCE);
+
+        // Stop it tumbling off, so it settles where it landed. Lying there
+        // with its weight on the ground doesn't count as hitting it.
+        let rei = &mut physics.rigidbody_set[physics.reis[0].handle];
+        rei.set_linvel(Vector::zeros(), true);
+        rei.set_angvel(Vector::zeros(), true);
+        rei.set_linear_damping(10.0);
+        rei.set_angular_damping(10.0);
+
+        for _ in 0..120 {
+            physics.update(TIMESTEP);
+        }
+        assert_eq!(physics.drain_ground_hits().count(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "debug-render")]
+    fn contacts_are_where_things_touch() {
+        // A pile of reis, so both sides of a contact are made of several parts
+        let mut physics = PhysicsSimulation::new(Vec::new(), ImportTransform::default(), 0);
+        for height in [1.0, 3.0, 5.0] {
+            physics.spawn_rei_at(point![5.0, height, 5.0], Vector::y());
+        }
+        let mut checked = 0;
+        for _ in 0..240 {
+            physics.update(TIMESTEP);
+            for contact in physics.contacts(1e-3) {
+                // A contact is where two things touch, so it should be on both
+                let point = Point::from(contact.position);
+                let touching = physics
+                    .collider_set
+                    .iter()
+                    .filter(|(_, collider)| {
+                        collider.shape().distance_to_point(collider.position(), &point, false)
+                            < 0.05
+                    })
+                    .count();
+                assert!(touching >= 2, "{contact:?} is only on {touching} colliders");
+                checked += 1;
+            }
+        }
+
+        assert!(checked > 0);
+    }
+}
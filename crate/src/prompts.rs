@@ -0,0 +1,107 @@
+use cgmath::{vec4, InnerSpace, Point3};
+
+use crate::camera::Camera;
+
+// How far away things can be interacted with
+const RANGE: f32 = 25.0;
+// How far off the middle of the screen something can be, in degrees, and
+// still count as being looked at
+const MAX_ANGLE: f32 = 12.0;
+
+/// Something in the scene that can be interacted with by looking at it and
+/// pressing a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interactable {
+    Emitter(usize),
+    Light,
+}
+
+/// The thing the camera is looking at, and how strongly. The prompt fades
+/// out as it gets further away or further from the middle of the screen.
+#[derive(Debug, Clone, Copy)]
+pub struct LookedAt {
+    pub target: Interactable,
+    pub position: [f32; 3],
+    /// Between 0 and 1
+    pub opacity: f32,
+}
+
+/// Finds whichever of the candidates the camera is looking at most directly
+pub fn looked_at(
+    camera: &Camera,
+    candidates: impl IntoIterator<Item = (Interactable, [f32; 3])>,
+) -> Option<LookedAt> {
+    let forward = camera.forward();
+
+    candidates
+        .into_iter()
+        .filter_map(|(target, position)| {
+            let offset = Point3::from(position) - camera.eye;
+            let distance = offset.magnitude();
+            if !(f32::EPSILON..=RANGE).contains(&distance) {
+                return None;
+            }
+
+            let angle = offset.angle(forward).0.to_degrees();
+            if angle > MAX_ANGLE {
+                return None;
+            }
+
+            let opacity = (1.0 - angle / MAX_ANGLE) * (1.0 - (distance / RANGE).powi(2));
+
+            Some(LookedAt {
+                target,
+                position,
+                opacity,
+            })
+        })
+        .max_by(|a, b| a.opacity.total_cmp(&b.opacity))
+}
+
+/// Where a point in the world is on the screen, in egui's coordinates. None
+/// if it's behind the camera.
+pub fn to_screen(ctx: &egui::Context, camera: &Camera, position: [f32; 3]) -> Option<egui::Pos2> {
+    let [x, y, z] = position;
+    let clip = camera.view_proj() * vec4(x, y, z, 1.0);
+    if clip.w <= 0.0 {
+        return None;
+    }
+
+    let screen = ctx.screen_rect();
+    Some(egui::pos2(
+        screen.left() + (clip.x / clip.w * 0.5 + 0.5) * screen.width(),
+        screen.top() + (0.5 - clip.y / clip.w * 0.5) * screen.height(),
+    ))
+}
+
+/// Draws a prompt floating over a point in the world
+pub fn show(ctx: &egui::Context, camera: &Camera, looked_at: &LookedAt, text: &str) {
+    let Some(pos) = to_screen(ctx, camera, looked_at.position) else {
+        return;
+    };
+
+    // Fading in quickly looks better than fading in linearly
+    let opacity = looked_at.opacity.sqrt();
+
+    let painter = ctx.layer_painter(egui::LayerId::new(
+        egui::Order::Background,
+        egui::Id::new("prompts"),
+    ));
+
+    let galley = painter.layout_no_wrap(
+        text.to_string(),
+        egui::FontId::proportional(18.0),
+        egui::Color32::WHITE.gamma_multiply(opacity),
+    );
+
+    // Just above the thing, so it doesn't cover it up
+    let rect = egui::Rect::from_center_size(pos - egui::vec2(0.0, 30.0), galley.size())
+        .expand2(egui::vec2(8.0, 4.0));
+
+    painter.rect_filled(
+        rect,
+        4.0,
+        egui::Color32::BLACK.gamma_multiply(0.6 * opacity),
+    );
+    painter.galley(rect.min + egui::vec2(8.0, 4.0), galley);
+}
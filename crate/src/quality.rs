@@ -0,0 +1,125 @@
+use cfg_if::cfg_if;
+
+use crate::{locale::Strings, physics::NUM_REIS};
+
+// Bits of gpu names that mean it's probably a phone or tablet
+const MOBILE_GPUS: &[&str] = &["mali", "adreno", "powervr", "apple gpu", "videocore"];
+// And ones that mean there isn't really a gpu at all
+const SOFTWARE_GPUS: &[&str] = &["swiftshader", "llvmpipe", "software", "basic render"];
+
+/// A bundle of settings that trade looks for speed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QualityPreset {
+    Low,
+    Medium,
+    High,
+}
+
+/// Everything the quality presets control
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualitySettings {
+    /// The most reis there can be at once. Past this the oldest ones get removed.
+    pub max_reis: usize,
+    /// How many samples per pixel to use for antialiasing (1 turns it off)
+    pub sample_count: u32,
+    /// The size the scene is rendered at, relative to the screen
+    pub render_scale: f32,
+}
+
+impl QualityPreset {
+    pub const ALL: [QualityPreset; 3] = [Self::Low, Self::Medium, Self::High];
+
+    pub fn settings(self) -> QualitySettings {
+        match self {
+            Self::Low => QualitySettings {
+                max_reis: NUM_REIS / 4,
+                sample_count: 1,
+                render_scale: 0.5,
+            },
+
+            Self::Medium => QualitySettings {
+                max_reis: NUM_REIS / 2,
+                sample_count: 4,
+                render_scale: 0.75,
+            },
+
+            Self::High => QualitySettings {
+                max_reis: NUM_REIS,
+                sample_count: 4,
+                render_scale: 1.0,
+            },
+        }
+    }
+
+    pub fn name(self, strings: &Strings) -> &str {
+        strings.get(match self {
+            Self::Low => "quality.low",
+            Self::Medium => "quality.medium",
+            Self::High => "quality.high",
+        })
+    }
+}
+
+/// What can be found out about the device, for guessing how fast it is
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    /// The gpu's name, as reported by the graphics api
+    pub renderer: String,
+    /// How many physical pixels there are per css pixel (always 1 off the web)
+    pub pixel_ratio: f64,
+    pub cores: Option<usize>,
+}
+
+impl DeviceInfo {
+    pub fn detect(adapter_info: &wgpu::AdapterInfo) -> Self {
+        cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                let window = web_sys::window();
+                let pixel_ratio = window.as_ref().map(|w| w.device_pixel_ratio()).unwrap_or(1.0);
+                let cores = window
+                    .map(|w| w.navigator().hardware_concurrency() as usize)
+                    .filter(|&cores| cores > 0);
+            } else {
+                let pixel_ratio = 1.0;
+                let cores = std::thread::available_parallelism().ok().map(|n| n.get());
+            }
+        }
+
+        Self {
+            renderer: adapter_info.name.clone(),
+            pixel_ratio,
+            cores,
+        }
+    }
+
+    /// Guesses which preset the device can handle. This is very rough, it's
+    /// really just trying to stop phones from grinding to a halt.
+    pub fn suggested_preset(&self) -> QualityPreset {
+        let renderer = self.renderer.to_lowercase();
+        let mut slowness = 0;
+
+        if SOFTWARE_GPUS.iter().any(|name| renderer.contains(name)) {
+            slowness += 3;
+        } else if MOBILE_GPUS.iter().any(|name| renderer.contains(name)) {
+            slowness += 2;
+        }
+
+        // Lots of pixels per css pixel usually means a phone, and means
+        // there's a lot more to draw
+        if self.pixel_ratio >= 2.5 {
+            slowness += 1;
+        }
+
+        match self.cores {
+            Some(cores) if cores <= 2 => slowness += 2,
+            Some(cores) if cores <= 4 => slowness += 1,
+            _ => {}
+        }
+
+        match slowness {
+            0 => QualityPreset::High,
+            1..=2 => QualityPreset::Medium,
+            _ => QualityPreset::Low,
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use crate::{
+    camera::{Camera, CameraBinding, CameraUniform},
+    clusters::{EmitterClusters, EmitterLook},
+    entities::Bodies,
+    frustum::{self, CullCounts, Frustum},
+    light::LightUniform,
+    model::{BoundingSphere, InstanceBuffer, InstanceRaw},
+    physics::{self, Cluster, PhysicsSimulation},
+    sync_audit::AuditedQueue,
+    tweaks::TweakUniform,
+};
+
+/// Everything the scene's buffers need each frame, copied out of the
+/// simulation and the camera. Once it's been made it doesn't borrow anything,
+/// so uploading it doesn't need the rest of the app (and making it could
+/// happen somewhere else entirely).
+pub struct RenderInputs {
+    /// None if the camera hasn't moved since the last upload
+    pub camera: Option<CameraUniform>,
+    pub light: LightUniform,
+    pub tweaks: TweakUniform,
+    /// The reis, in the order [PhysicsSimulation::clustered_instances] gives.
+    /// When they're culled, the ones that are only in the light's frustum go
+    /// after the visible clusters, so they still cast shadows.
+    pub instances: Vec<InstanceRaw>,
+    /// How many of the instances the shadow map draws, or None if it's every
+    /// one in a visible cluster
+    pub shadow_casters: Option<u32>,
+    /// Where every rei is, culled or not, for the contact darkening
+    pub positions: Vec<[f32; 3]>,
+    pub clusters: Vec<Cluster>,
+    // For the clusters' tints, and which ones are hidden
+    pub emitters: Vec<EmitterLook>,
+    pub projectiles: Vec<InstanceRaw>,
+    /// How many of the reis and projectiles are in the instances, and how
+    /// many were left out for being off screen
+    pub cull_counts: CullCounts,
+}
+
+/// What decides which instances are on screen
+pub struct Culling {
+    pub frustum: Frustum,
+    /// Around the model drawn at the reis, or None if the gpu culls them
+    /// itself (see gpu_culling.rs)
+    pub reis: Option<BoundingSphere>,
+    /// What the light can see, or None if nothing's being shadowed
+    pub shadow: Option<Frustum>,
+    /// Around the model drawn at the projectiles
+    pub projectiles: BoundingSphere,
+}
+
+impl RenderInputs {
+    /// Copies everything out for this frame. With `culling`, only the
+    /// instances that could be on screen are kept (with the clusters shrunk
+    /// to fit), otherwise they all are.
+    pub fn extract(
+        physics: &PhysicsSimulation,
+        camera: Option<&Camera>,
+        culling: Option<&Culling>,
+        light: LightUniform,
+        tweaks: TweakUniform,
+    ) -> Self {
+        let (mut instances, mut clusters) = physics.clustered_instances();
+        let emitters: Vec<_> = physics.emitters().iter().map(EmitterLook::of).collect();
+        let positions = instances.iter().map(InstanceRaw::position).collect();
+        let mut projectiles = physics.projectile_instances();
+        let mut shadow_casters = None;
+        let mut cull_counts = CullCounts {
+            drawn: instances.len() + projectiles.len(),
+            culled: 0,
+        };
+
+        if let Some(culling) = culling {
+            cull_counts = CullCounts::default();
+
+            if let Some(sphere) = &culling.reis {
+                let mut kept = Vec::with_capacity(instances.len());
+                let mut casters = Vec::new();
+                let mut shadow_end = None;
+                for cluster in clusters.iter_mut() {
+                    // The hidden clusters are always last, and the off screen
+                    // casters go just before them so the shadow map can draw
+                    // everything up to there in one go
+                    let visible = cluster
+                        .emitter
+                        .and_then(|index| emitters.get(index))
+                        .is_none_or(|emitter| emitter.visible);
+                    if !visible && shadow_end.is_none() {
+                        kept.append(&mut casters);
+                        shadow_end = Some(kept.len() as u32);
+                    }
+
+                    let start = kept.len() as u32;
+                    let range = cluster.instances.start as usize..cluster.instances.end as usize;
+                    cull_counts.add(frustum::cull_casting(
+                        &culling.frustum,
+                        culling.shadow.as_ref().filter(|_| visible),
+                        sphere,
+                        instances[range].iter().copied(),
+                        &mut kept,
+                        &mut casters,
+                    ));
+                    cluster.instances = start..kept.len() as u32;
+                }
+                kept.append(&mut casters);
+                shadow_casters = Some(shadow_end.unwrap_or(kept.len() as u32));
+                instances = kept;
+            } else {
+                cull_counts.drawn += instances.len();
+            }
+
+            let mut kept = Vec::with_capacity(projectiles.len());
+            cull_counts.add(frustum::cull(
+                &culling.frustum,
+                &culling.projectiles,
+                projectiles,
+                &mut kept,
+            ));
+            projectiles = kept;
+        }
+
+        Self {
+            camera: camera.map(Camera::to_uniform),
+            light,
+            tweaks,
+            instances,
+            shadow_casters,
+            positions,
+            clusters,
+            emitters,
+            projectiles,
+            cull_counts,
+        }
+    }
+}
+
+/// The buffers the scene gets drawn with that change every frame, filled in
+/// from [RenderInputs]
+pub struct SceneBuffers {
+    pub camera: CameraBinding,
+    light_buffer: wgpu::Buffer,
+    pub light_bind_group: wgpu::BindGroup,
+    tweak_buffer: wgpu::Buffer,
+    // What's in the tweak buffer, which hardly ever changes
+    tweaks: TweakUniform,
+    // Splits the reis up by emitter when they're drawn
+    pub clusters: EmitterClusters,
+    pub rei_instances: InstanceBuffer,
+    /// How many of the reis the shadow map draws
+    pub shadow_casters: u32,
+    pub projectile_instances: InstanceBuffer,
+}
+
+impl SceneBuffers {
+    /// Where a set of bodies is drawn from
+    pub fn instances(&self, bodies: Bodies) -> &InstanceBuffer {
+        match bodies {
+            Bodies::Reis => &self.rei_instances,
+            Bodies::Projectiles => &self.projectile_instances,
+        }
+    }
+
+    /// The instance buffers' sizes in bytes, which grow with the scene, each
+    /// with the key of the ui string it's shown with
+    pub fn instance_buffer_sizes(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("diagnostics.rei_buffer", self.rei_instances.buffer().size()),
+            ("diagnostics.projectile_buffer", self.projectile_instances.buffer().size()),
+        ]
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &AuditedQueue,
+        camera: &Camera,
+        light: LightUniform,
+        tweaks: TweakUniform,
+        max_reis: usize,
+        gpu_culling: bool,
+    ) -> Self {
+        let camera = CameraBinding::new(device, queue, camera);
+
+        let light_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Light buffer"),
+            contents: bytemuck::cast_slice(&[light]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("light bind group"),
+            layout: LightUniform::bind_group_layout(device),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: light_buffer.as_entire_binding(),
+            }],
+        });
+
+        let tweak_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Shader tweak buffer"),
+            contents: bytemuck::cast_slice(&[tweaks]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let clusters = EmitterClusters::new(device, &light_buffer, &tweak_buffer);
+
+        // These grow if they need to, but starting big enough for everything
+        // the quality settings allow saves doing that while it's running
+        // The gpu culling reads the reis in a compute shader
+        let rei_usage = if gpu_culling {
+            wgpu::BufferUsages::STORAGE
+        } else {
+            wgpu::BufferUsages::empty()
+        };
+        let rei_instances = InstanceBuffer::with_usage(
+            device,
+            "Rei instance buffer",
+            max_reis + 1,
+            rei_usage,
+        );
+        let projectile_instances = InstanceBuffer::new(
+            device,
+            "Projectile instance buffer",
+            physics::MAX_PROJECTILES,
+        );
+
+        Self {
+            camera,
+            light_buffer,
+            light_bind_group,
+            tweak_buffer,
+            tweaks,
+            clusters,
+            rei_instances,
+            shadow_casters: 0,
+            projectile_instances,
+        }
+    }
+
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &AuditedQueue, inputs: &RenderInputs) {
+        if let Some(camera) = &inputs.camera {
+            self.camera.write(queue, camera);
+        }
+
+        queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&[inputs.light]));
+        if inputs.tweaks != self.tweaks {
+            queue.write_buffer(
+                &self.tweak_buffer,
+                0,
+                bytemuck::cast_slice(&[inputs.tweaks]),
+            );
+            self.tweaks = inputs.tweaks;
+        }
+
+        self.clusters.update(
+            device,
+            queue,
+            &self.light_buffer,
+            &self.tweak_buffer,
+            &inputs.emitters,
+            &inputs.clusters,
+        );
+        self.rei_instances.write(device, queue, &inputs.instances);
+        self.shadow_casters = inputs
+            .shadow_casters
+            .unwrap_or(self.clusters.visible_instances());
+        self.projectile_instances
+            .write(device, queue, &inputs.projectiles);
+    }
+}
@@ -0,0 +1,287 @@
+use crate::texture;
+
+/// Draws the scene target over the whole screen
+pub struct Blitter {
+    pipeline: wgpu::RenderPipeline,
+    // Same as the normal one, but mixed with what's already there by the
+    // render pass's blend constant
+    fade_pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+}
+
+impl Blitter {
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("blit bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("blit pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let create_pipeline = |label, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: Default::default(),
+                depth_stencil: None,
+                multisample: Default::default(),
+                multiview: None,
+            })
+        };
+
+        let pipeline = create_pipeline("blit pipeline", None);
+
+        let fade = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Constant,
+            dst_factor: wgpu::BlendFactor::OneMinusConstant,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let fade_pipeline = create_pipeline(
+            "fade blit pipeline",
+            Some(wgpu::BlendState {
+                color: fade,
+                alpha: fade,
+            }),
+        );
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("blit sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            pipeline,
+            fade_pipeline,
+            layout,
+            sampler,
+        }
+    }
+
+    /// A bind group for drawing a texture with the blitter
+    pub fn bind_group(&self, device: &wgpu::Device, view: &wgpu::TextureView) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("blit bind group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        })
+    }
+
+    /// Draws a texture over the whole of the render pass's target, on top of
+    /// what's already there with the given opacity
+    pub fn blit_faded<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        bind_group: &'a wgpu::BindGroup,
+        opacity: f32,
+    ) {
+        let opacity = opacity.clamp(0.0, 1.0) as f64;
+
+        render_pass.set_pipeline(&self.fade_pipeline);
+        render_pass.set_blend_constant(wgpu::Color {
+            r: opacity,
+            g: opacity,
+            b: opacity,
+            a: opacity,
+        });
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+/// What the scene gets rendered into. On slow devices it can be smaller than
+/// the screen, and it gets stretched over the screen afterwards either way.
+pub struct SceneTarget {
+    /// The surface's config, but with the size of the scene target
+    pub config: wgpu::SurfaceConfiguration,
+    // Only there when multisampling, otherwise the scene is drawn straight into the colour texture
+    msaa_view: Option<wgpu::TextureView>,
+    colour_texture: wgpu::Texture,
+    colour_view: wgpu::TextureView,
+    pub depth_texture: texture::Texture,
+    blit_bind_group: wgpu::BindGroup,
+}
+
+impl SceneTarget {
+    /// Creates a scene target that's `render_scale` times the size of the surface.
+    /// If the depth texture is readable, it can be bound in shaders (see particles.rs).
+    pub fn new(
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+        render_scale: f32,
+        sample_count: u32,
+        readable_depth: bool,
+        blitter: &Blitter,
+    ) -> Self {
+        let scale = |size: u32| ((size as f32 * render_scale).round() as u32).max(1);
+
+        let config = wgpu::SurfaceConfiguration {
+            width: scale(surface_config.width),
+            height: scale(surface_config.height),
+            ..surface_config.clone()
+        };
+
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+
+        let colour_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("scene texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            // Copied from to take snapshots for transitions
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let colour_view = colour_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let msaa_view = (sample_count > 1).then(|| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some("msaa texture"),
+                    size,
+                    mip_level_count: 1,
+                    sample_count,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: config.format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        });
+
+        let depth_texture = texture::Texture::create_depth_texture(
+            device,
+            &config,
+            "depth texture",
+            sample_count,
+            readable_depth,
+        );
+
+        let blit_bind_group = blitter.bind_group(device, &colour_view);
+
+        Self {
+            config,
+            msaa_view,
+            colour_texture,
+            colour_view,
+            depth_texture,
+            blit_bind_group,
+        }
+    }
+
+    /// The texture the finished scene ends up in
+    pub fn colour_texture(&self) -> &wgpu::Texture {
+        &self.colour_texture
+    }
+
+    /// The colour attachment for a render pass that draws into the scene
+    pub fn colour_attachment(
+        &self,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) -> wgpu::RenderPassColorAttachment<'_> {
+        let ops = wgpu::Operations { load, store: true };
+
+        match &self.msaa_view {
+            Some(msaa_view) => wgpu::RenderPassColorAttachment {
+                view: msaa_view,
+                resolve_target: Some(&self.colour_view),
+                ops,
+            },
+
+            None => wgpu::RenderPassColorAttachment {
+                view: &self.colour_view,
+                resolve_target: None,
+                ops,
+            },
+        }
+    }
+
+    /// The depth attachment for a render pass that draws into the scene
+    pub fn depth_attachment(
+        &self,
+        load: wgpu::LoadOp<f32>,
+    ) -> wgpu::RenderPassDepthStencilAttachment<'_> {
+        // The stencil's cleared whenever the depth is
+        let stencil_load = match load {
+            wgpu::LoadOp::Clear(_) => wgpu::LoadOp::Clear(0),
+            wgpu::LoadOp::Load => wgpu::LoadOp::Load,
+        };
+
+        wgpu::RenderPassDepthStencilAttachment {
+            view: &self.depth_texture.view,
+            depth_ops: Some(wgpu::Operations { load, store: true }),
+            stencil_ops: Some(wgpu::Operations {
+                load: stencil_load,
+                store: true,
+            }),
+        }
+    }
+
+    /// Draws the scene over the whole of the render pass's target
+    pub fn blit<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, blitter: &'a Blitter) {
+        render_pass.set_pipeline(&blitter.pipeline);
+        render_pass.set_bind_group(0, &self.blit_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
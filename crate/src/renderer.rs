@@ -0,0 +1,1354 @@
+use std::{sync::Arc, time::Duration};
+
+use egui_wgpu::renderer::ScreenDescriptor;
+use instant::Instant;
+use winit::{dpi::PhysicalSize, window::Window};
+
+use crate::{
+    camera::CameraBinding,
+    clusters::EmitterClusters,
+    colour,
+    config::{self, Config},
+    contact::ContactDarkening,
+    error::{self, Error},
+    gpu_culling::GpuCulling,
+    gpu_timings::{GpuPass, GpuTimer},
+    ground::{self, Ground},
+    impostor::{self, SpriteRenderer},
+    light::LightUniform,
+    lines::LineRenderer,
+    model::{InstanceRaw, Model, ModelVertex, Vertex},
+    outline::OutlineRenderer,
+    particles::ParticleSystem,
+    quality::{DeviceInfo, QualityPreset},
+    render_inputs::SceneBuffers,
+    render_target::{Blitter, SceneTarget},
+    resources,
+    shader_include,
+    shadow::ShadowMap,
+    skybox::Skybox,
+    sync_audit::AuditedQueue,
+    text::TextRenderer,
+    texture,
+    transition::Transition,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::shader_watch::{ShaderWatcher, SHADER_DIR};
+#[cfg(feature = "debug-render")]
+use crate::debug_collider::ColliderRenderer;
+
+/// Owns everything on the gpu side that isn't specific to what's being drawn:
+/// the surface, the scene target it all gets drawn into, and the pipelines
+/// and renderers for drawing the scene.
+///
+/// Each frame goes [Renderer::begin_frame], then any number of
+/// [Renderer::scene_pass]es and [Renderer::overlay_pass]es, then
+/// [Renderer::end_frame] to put it on the screen.
+pub struct Renderer {
+    surface: wgpu::Surface,
+    config: wgpu::SurfaceConfiguration,
+    // The present modes the surface says it has
+    present_modes: Vec<wgpu::PresentMode>,
+    pub device: Arc<wgpu::Device>,
+    pub queue: Arc<AuditedQueue>,
+    // Whether the depth buffer can be read in shaders
+    readable_depth: bool,
+    suggested_preset: QualityPreset,
+    sample_count: u32,
+    // The sample counts the gpu can do, from config::SAMPLE_COUNTS
+    sample_counts: Vec<u32>,
+    render_scale: f32,
+
+    sources: PipelineSources,
+    pipelines: ScenePipelines,
+    scene_target: SceneTarget,
+    blitter: Blitter,
+    // Fading from the loading screen or the last scene, if it's just changed
+    transition: Option<Transition>,
+    egui_renderer: egui_wgpu::Renderer,
+    #[cfg(not(target_arch = "wasm32"))]
+    shader_watcher: Option<ShaderWatcher>,
+
+    pub shadow_map: ShadowMap,
+    pub contact: ContactDarkening,
+    // None if the gpu can't cull the reis itself, so the cpu does
+    pub culling: Option<GpuCulling>,
+    // None if the gpu can't do timestamp queries
+    pub timer: Option<GpuTimer>,
+    pub skybox: Skybox,
+    pub ground: Ground,
+    pub sprites: SpriteRenderer,
+    pub particles: ParticleSystem,
+    pub lines: LineRenderer,
+    pub text: TextRenderer,
+    pub outline: OutlineRenderer,
+    #[cfg(feature = "debug-render")]
+    pub colliders: ColliderRenderer,
+}
+
+/// The pipelines that draw models into the scene
+pub struct ScenePipelines {
+    pub model: wgpu::RenderPipeline,
+    // Same as the model pipeline, but colours each rei by how fast it's going
+    pub speed: wgpu::RenderPipeline,
+    pub light: wgpu::RenderPipeline,
+}
+
+/// A frame on its way to the screen, from [Renderer::begin_frame] until it's
+/// handed back to [Renderer::end_frame]
+pub struct Frame {
+    output: wgpu::SurfaceTexture,
+    view: wgpu::TextureView,
+    encoder: wgpu::CommandEncoder,
+    // The ui's paint jobs, if there's any ui this frame
+    ui: Option<(Vec<egui::ClippedPrimitive>, ScreenDescriptor)>,
+}
+
+impl Frame {
+    /// The frame's command encoder, for anything that isn't a draw into the
+    /// scene (like rendering the shadow map)
+    pub fn encoder(&mut self) -> &mut wgpu::CommandEncoder {
+        &mut self.encoder
+    }
+}
+
+// Everything needed to remake the pipelines that draw into the scene, which
+// has to happen whenever the number of msaa samples changes or a shader's
+// reloaded
+struct PipelineSources {
+    layout: wgpu::PipelineLayout,
+    light_layout: wgpu::PipelineLayout,
+    model_shader: wgpu::ShaderModule,
+    light_shader: wgpu::ShaderModule,
+    sprite_shader: wgpu::ShaderModule,
+    particle_shader: wgpu::ShaderModule,
+    line_shader: wgpu::ShaderModule,
+    text_shader: wgpu::ShaderModule,
+    outline_shader: wgpu::ShaderModule,
+    #[cfg(feature = "debug-render")]
+    collider_shader: wgpu::ShaderModule,
+    ground_shader: wgpu::ShaderModule,
+    skybox_shader: wgpu::ShaderModule,
+    depth_resolve_shader: Option<wgpu::ShaderModule>,
+}
+
+impl PipelineSources {
+    // The shader loaded from a file, and whether it goes through
+    // colour::shader_for_target. The shaders that aren't here only get
+    // loaded once, so they can't be reloaded.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn shader_mut(&mut self, filename: &str) -> Option<(&mut wgpu::ShaderModule, bool)> {
+        Some(match filename {
+            "model_shader.wgsl" => (&mut self.model_shader, true),
+            "light_shader.wgsl" => (&mut self.light_shader, true),
+            "sprite_shader.wgsl" => (&mut self.sprite_shader, true),
+            "particle_shader.wgsl" => (&mut self.particle_shader, true),
+            "line_shader.wgsl" => (&mut self.line_shader, true),
+            "text_shader.wgsl" => (&mut self.text_shader, true),
+            "outline_shader.wgsl" => (&mut self.outline_shader, true),
+            #[cfg(feature = "debug-render")]
+            "collider_debug_shader.wgsl" => (&mut self.collider_shader, true),
+            "ground_shader.wgsl" => (&mut self.ground_shader, true),
+            "skybox_shader.wgsl" => (&mut self.skybox_shader, true),
+            "depth_resolve_shader.wgsl" => (self.depth_resolve_shader.as_mut()?, false),
+            _ => return None,
+        })
+    }
+}
+
+impl ScenePipelines {
+    fn new(
+        device: &wgpu::Device,
+        sources: &PipelineSources,
+        format: wgpu::TextureFormat,
+        samples: u32,
+    ) -> Self {
+        let model = create_render_pipeline(
+            device,
+            "render pipeline",
+            &sources.layout,
+            format,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[ModelVertex::desc(), InstanceRaw::desc()],
+            &sources.model_shader,
+            "fs_main",
+            samples,
+        );
+
+        let speed = create_render_pipeline(
+            device,
+            "speed debug pipeline",
+            &sources.layout,
+            format,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[ModelVertex::desc(), InstanceRaw::desc()],
+            &sources.model_shader,
+            "fs_speed",
+            samples,
+        );
+
+        let light = create_render_pipeline(
+            device,
+            "light pipeline",
+            &sources.light_layout,
+            format,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[ModelVertex::desc(), InstanceRaw::desc()],
+            &sources.light_shader,
+            "fs_main",
+            samples,
+        );
+
+        Self {
+            model,
+            speed,
+            light,
+        }
+    }
+}
+
+// Which of the sample counts in the settings both the screen's format and the
+// depth buffer's can be multisampled with
+fn supported_sample_counts(
+    adapter: &wgpu::Adapter,
+    features: wgpu::Features,
+    format: wgpu::TextureFormat,
+) -> Vec<u32> {
+    // Without the feature only the counts every gpu has can be used, even if
+    // this one has more
+    if !features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+        return vec![1, 4];
+    }
+
+    let colour = adapter.get_texture_format_features(format).flags;
+    let depth = adapter
+        .get_texture_format_features(texture::Texture::DEPTH_FORMAT)
+        .flags;
+
+    config::SAMPLE_COUNTS
+        .into_iter()
+        .filter(|&count| {
+            colour.sample_count_supported(count) && depth.sample_count_supported(count)
+        })
+        .collect()
+}
+
+// The sample count to use, or the highest one the gpu has below it if it
+// can't do that one
+fn pick_sample_count(wanted: u32, available: &[u32]) -> u32 {
+    if available.contains(&wanted) {
+        return wanted;
+    }
+
+    let picked = available
+        .iter()
+        .copied()
+        .filter(|&count| count < wanted)
+        .max()
+        .unwrap_or(1);
+    log::warn!("The gpu can't do {wanted}x antialiasing, using {picked}x instead");
+    picked
+}
+
+// The present mode to use, or vsync if the surface doesn't have it (which
+// every surface has to)
+fn pick_present_mode(
+    wanted: wgpu::PresentMode,
+    available: &[wgpu::PresentMode],
+) -> wgpu::PresentMode {
+    match wanted {
+        wgpu::PresentMode::AutoVsync | wgpu::PresentMode::AutoNoVsync => wanted,
+        _ if available.contains(&wanted) => wanted,
+        _ => {
+            log::warn!("The surface can't use {wanted:?}, going with vsync");
+            wgpu::PresentMode::AutoVsync
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    colour_format: wgpu::TextureFormat,
+    depth_format: Option<wgpu::TextureFormat>,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    shader: &wgpu::ShaderModule,
+    fragment_entry: &str,
+    samples: u32,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: vertex_layouts,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: fragment_entry,
+            targets: &[Some(wgpu::ColorTargetState {
+                format: colour_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+            polygon_mode: wgpu::PolygonMode::Fill,
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        },
+        depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: Default::default(),
+            bias: Default::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: samples,
+            ..Default::default()
+        },
+        multiview: None,
+    })
+}
+
+impl Renderer {
+    /// Sets up the gpu for drawing into the window. The window has to outlive
+    /// the renderer.
+    pub async fn new(window: &Window, settings: &Config) -> error::Result<Self> {
+        // A lot of this instantiation boilerplate (as well as a lot of the
+        // code, to be fair) was taken from the wgpu tutorial at
+        // https://sotrh.github.io/learn-wgpu/
+        let size = window.inner_size();
+
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            dx12_shader_compiler: Default::default(),
+        });
+
+        // SAFETY: surface should live as long as the window as the renderer
+        // and the window are both owned by the app. I'm pretty sure. That's what they said
+        // on the tutorial. But aren't self referential structs generally
+        // unsafe?
+        let surface = unsafe { instance.create_surface(window) }
+            .map_err(|e| Error::gpu_init("couldn't create surface", e))?;
+
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: Default::default(),
+                compatible_surface: Some(&surface),
+                force_fallback_adapter: false,
+            })
+            .await
+            .ok_or(Error::GpuInit {
+                context: "no suitable graphics adapter found".to_string(),
+                source: None,
+            })?;
+
+        log::info!("Backend: {:?}", adapter.get_info().backend);
+
+        // Webgl can't read multisampled textures in shaders, which soft particles need
+        let readable_depth = adapter.get_info().backend != wgpu::Backend::Gl;
+
+        // Phones can't handle everything turned up, so on the web the
+        // settings are picked based on what the device looks like
+        let device_info = DeviceInfo::detect(&adapter.get_info());
+        log::info!("Device: {device_info:?}");
+
+        let suggested_preset = if cfg!(target_arch = "wasm32") {
+            device_info.suggested_preset()
+        } else {
+            QualityPreset::High
+        };
+        let mut quality = suggested_preset.settings();
+        if let Some(sample_count) = settings.sample_count {
+            quality.sample_count = sample_count;
+        }
+
+        // Needed for any sample counts past 1 and 4, if the gpu has them
+        let features =
+            adapter.features() & wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES;
+        // For timing the frames in the diagnostics, if the gpu has them
+        let features = features | GpuTimer::features(&adapter);
+
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    features,
+                    limits: if cfg!(target_arch = "wasm32") {
+                        wgpu::Limits::downlevel_webgl2_defaults()
+                            .using_resolution(wgpu::Limits::default())
+                    } else {
+                        wgpu::Limits::default()
+                    },
+                },
+                None, /*trace_path*/
+            )
+            .await
+            .map_err(|e| Error::gpu_init("couldn't get a device from the adapter", e))?;
+
+        let surface_capabilities = surface.get_capabilities(&adapter);
+
+        let format = surface_capabilities
+            .formats
+            .iter()
+            .copied()
+            .find(|f| f.is_srgb())
+            .unwrap_or(surface_capabilities.formats[0]);
+
+        // The shaders convert to sRGB themselves in this case (see colour.rs)
+        if !format.is_srgb() {
+            log::info!("No sRGB surface format available, using {format:?}");
+        }
+
+        let sample_counts = supported_sample_counts(&adapter, features, format);
+        log::info!("Antialiasing sample counts available: {sample_counts:?}");
+        quality.sample_count = pick_sample_count(quality.sample_count, &sample_counts);
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: size.width,
+            height: size.height,
+            present_mode: pick_present_mode(
+                settings.present_mode.to_wgpu(),
+                &surface_capabilities.present_modes,
+            ),
+            alpha_mode: surface_capabilities.alpha_modes[0],
+            view_formats: vec![],
+        };
+
+        surface.configure(&device, &config);
+
+        let camera_bind_group_layout = CameraBinding::bind_group_layout(&device);
+        let light_bind_group_layout = LightUniform::bind_group_layout(&device);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("pipeline layout descriptor"),
+            bind_group_layouts: &[
+                camera_bind_group_layout,
+                texture::Texture::texture_bind_group_layout(&device),
+                EmitterClusters::bind_group_layout(&device),
+                ShadowMap::bind_group_layout(&device),
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let shadow_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("shadow shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                #[cfg(debug_assertions)]
+                resources::load_string("shaders/shadow_shader.wgsl")
+                    .await?
+                    .into(),
+                #[cfg(not(debug_assertions))]
+                include_str!("../shaders/shadow_shader.wgsl").into(),
+            ),
+        });
+
+        let contact = ContactDarkening::new(&device);
+        let shadow_map = ShadowMap::new(&device, &shadow_shader, &contact);
+
+        let culling = if GpuCulling::is_supported(&adapter, &device) {
+            let cull_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("cull shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    #[cfg(debug_assertions)]
+                    resources::load_string("shaders/cull_shader.wgsl")
+                        .await?
+                        .into(),
+                    #[cfg(not(debug_assertions))]
+                    include_str!("../shaders/cull_shader.wgsl").into(),
+                ),
+            });
+            Some(GpuCulling::new(&device, &cull_shader))
+        } else {
+            None
+        };
+
+        let timer = GpuTimer::new(&device, &queue);
+        log::info!(
+            "Culling the reis on the {}",
+            if culling.is_some() { "gpu" } else { "cpu" }
+        );
+
+        let impostor_bake_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("impostor bake shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                colour::shader_for_target(
+                    #[cfg(debug_assertions)]
+                    &resources::load_string("shaders/impostor_bake_shader.wgsl").await?,
+                    #[cfg(not(debug_assertions))]
+                    include_str!("../shaders/impostor_bake_shader.wgsl"),
+                    impostor::ATLAS_FORMAT,
+                )
+                .into(),
+            ),
+        });
+
+        let sprite_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("sprite shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                colour::shader_for_target(
+                    #[cfg(debug_assertions)]
+                    &resources::load_string("shaders/sprite_shader.wgsl").await?,
+                    #[cfg(not(debug_assertions))]
+                    include_str!("../shaders/sprite_shader.wgsl"),
+                    config.format,
+                )
+                .into(),
+            ),
+        });
+
+        let sprites = SpriteRenderer::new(
+            &device,
+            &impostor_bake_shader,
+            &sprite_shader,
+            config.format,
+            quality.sample_count,
+        );
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("model shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                colour::shader_for_target(
+                    #[cfg(debug_assertions)]
+                    &resources::load_string("shaders/model_shader.wgsl").await?,
+                    #[cfg(not(debug_assertions))]
+                    include_str!("../shaders/model_shader.wgsl"),
+                    config.format,
+                )
+                .into(),
+            ),
+        });
+
+        let blit_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("blit shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                #[cfg(debug_assertions)]
+                resources::load_string("shaders/blit_shader.wgsl")
+                    .await?
+                    .into(),
+                #[cfg(not(debug_assertions))]
+                include_str!("../shaders/blit_shader.wgsl").into(),
+            ),
+        });
+
+        let blitter = Blitter::new(&device, &blit_shader, config.format);
+
+        let scene_target = SceneTarget::new(
+            &device,
+            &config,
+            quality.render_scale,
+            quality.sample_count,
+            readable_depth,
+            &blitter,
+        );
+
+        let light_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Light shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                colour::shader_for_target(
+                    #[cfg(debug_assertions)]
+                    &resources::load_string("shaders/light_shader.wgsl").await?,
+                    #[cfg(not(debug_assertions))]
+                    include_str!("../shaders/light_shader.wgsl"),
+                    config.format,
+                )
+                .into(),
+            ),
+        });
+
+        let light_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Light pipeline layout"),
+                bind_group_layouts: &[camera_bind_group_layout, light_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let particle_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("particle shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                colour::shader_for_target(
+                    #[cfg(debug_assertions)]
+                    &resources::load_string("shaders/particle_shader.wgsl").await?,
+                    #[cfg(not(debug_assertions))]
+                    include_str!("../shaders/particle_shader.wgsl"),
+                    config.format,
+                )
+                .into(),
+            ),
+        });
+
+        let line_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("line shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                colour::shader_for_target(
+                    #[cfg(debug_assertions)]
+                    &resources::load_string("shaders/line_shader.wgsl").await?,
+                    #[cfg(not(debug_assertions))]
+                    include_str!("../shaders/line_shader.wgsl"),
+                    config.format,
+                )
+                .into(),
+            ),
+        });
+
+        let lines = LineRenderer::new(&device, &line_shader, config.format, quality.sample_count);
+
+        let text_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("text shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                colour::shader_for_target(
+                    #[cfg(debug_assertions)]
+                    &resources::load_string("shaders/text_shader.wgsl").await?,
+                    #[cfg(not(debug_assertions))]
+                    include_str!("../shaders/text_shader.wgsl"),
+                    config.format,
+                )
+                .into(),
+            ),
+        });
+
+        let text = TextRenderer::new(&device, &text_shader, config.format, quality.sample_count);
+
+        let outline_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("outline shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                colour::shader_for_target(
+                    #[cfg(debug_assertions)]
+                    &resources::load_string("shaders/outline_shader.wgsl").await?,
+                    #[cfg(not(debug_assertions))]
+                    include_str!("../shaders/outline_shader.wgsl"),
+                    config.format,
+                )
+                .into(),
+            ),
+        });
+
+        let outline =
+            OutlineRenderer::new(&device, &outline_shader, config.format, quality.sample_count);
+
+        #[cfg(feature = "debug-render")]
+        let collider_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("collider shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                colour::shader_for_target(
+                    #[cfg(debug_assertions)]
+                    &resources::load_string("shaders/collider_debug_shader.wgsl").await?,
+                    #[cfg(not(debug_assertions))]
+                    include_str!("../shaders/collider_debug_shader.wgsl"),
+                    config.format,
+                )
+                .into(),
+            ),
+        });
+
+        #[cfg(feature = "debug-render")]
+        let colliders =
+            ColliderRenderer::new(&device, &collider_shader, config.format, quality.sample_count);
+
+        let ground_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("ground shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                colour::shader_for_target(
+                    #[cfg(debug_assertions)]
+                    &resources::load_string("shaders/ground_shader.wgsl").await?,
+                    #[cfg(not(debug_assertions))]
+                    include_str!("../shaders/ground_shader.wgsl"),
+                    config.format,
+                )
+                .into(),
+            ),
+        });
+
+        let ground_splat_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("ground splat shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                shader_include::expand(
+                    #[cfg(debug_assertions)]
+                    &resources::load_string("shaders/ground_splat_shader.wgsl").await?,
+                    #[cfg(not(debug_assertions))]
+                    include_str!("../shaders/ground_splat_shader.wgsl"),
+                )
+                .into(),
+            ),
+        });
+
+        let heightfield_format = ground::heightfield_format(&adapter);
+        log::info!("Ground heightfield format: {heightfield_format:?}");
+
+        let ground = Ground::new(
+            &device,
+            &ground_shader,
+            &ground_splat_shader,
+            config.format,
+            heightfield_format,
+            quality.sample_count,
+        );
+
+        let skybox_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("skybox shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                colour::shader_for_target(
+                    #[cfg(debug_assertions)]
+                    &resources::load_string("shaders/skybox_shader.wgsl").await?,
+                    #[cfg(not(debug_assertions))]
+                    include_str!("../shaders/skybox_shader.wgsl"),
+                    config.format,
+                )
+                .into(),
+            ),
+        });
+
+        let queue = AuditedQueue::new(queue);
+        let skybox = Skybox::new(
+            &device,
+            &queue,
+            &skybox_shader,
+            config.format,
+            quality.sample_count,
+        );
+
+        let depth_resolve_shader = if readable_depth {
+            Some(
+                device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some("depth resolve shader"),
+                    source: wgpu::ShaderSource::Wgsl(
+                        #[cfg(debug_assertions)]
+                        resources::load_string("shaders/depth_resolve_shader.wgsl")
+                            .await?
+                            .into(),
+                        #[cfg(not(debug_assertions))]
+                        include_str!("../shaders/depth_resolve_shader.wgsl").into(),
+                    ),
+                }),
+            )
+        } else {
+            None
+        };
+
+        let particles = ParticleSystem::new(
+            &device,
+            &particle_shader,
+            depth_resolve_shader.as_ref(),
+            &scene_target.config,
+            &scene_target.depth_texture,
+            quality.sample_count,
+        );
+
+        let sources = PipelineSources {
+            layout: pipeline_layout,
+            light_layout: light_pipeline_layout,
+            model_shader: shader,
+            light_shader,
+            sprite_shader,
+            particle_shader,
+            line_shader,
+            text_shader,
+            outline_shader,
+            #[cfg(feature = "debug-render")]
+            collider_shader,
+            ground_shader,
+            skybox_shader,
+            depth_resolve_shader,
+        };
+
+        let pipelines = ScenePipelines::new(&device, &sources, config.format, quality.sample_count);
+
+        // Egui gets drawn straight onto the screen, after the scene
+        let egui_renderer = egui_wgpu::Renderer::new(&device, config.format, None, 1);
+
+        Ok(Self {
+            surface,
+            config,
+            present_modes: surface_capabilities.present_modes,
+            device: Arc::new(device),
+            queue: Arc::new(queue),
+            readable_depth,
+            suggested_preset,
+            sample_count: quality.sample_count,
+            sample_counts,
+            render_scale: quality.render_scale,
+            sources,
+            pipelines,
+            scene_target,
+            blitter,
+            transition: None,
+            egui_renderer,
+            #[cfg(not(target_arch = "wasm32"))]
+            shader_watcher: ShaderWatcher::new(),
+            shadow_map,
+            contact,
+            culling,
+            timer,
+            skybox,
+            ground,
+            sprites,
+            particles,
+            lines,
+            text,
+            outline,
+            #[cfg(feature = "debug-render")]
+            colliders,
+        })
+    }
+
+    /// The quality preset the renderer started out with, picked based on the device
+    pub fn suggested_preset(&self) -> QualityPreset {
+        self.suggested_preset
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// The antialiasing sample counts this gpu can do, lowest first
+    pub fn sample_counts(&self) -> &[u32] {
+        &self.sample_counts
+    }
+
+    pub fn pipelines(&self) -> &ScenePipelines {
+        &self.pipelines
+    }
+
+    /// The size of the screen, in pixels
+    pub fn size(&self) -> PhysicalSize<u32> {
+        PhysicalSize::new(self.config.width, self.config.height)
+    }
+
+    /// Lays out and uploads the text queued this frame, for a window with
+    /// `pixels_per_point` pixels to a point
+    pub fn prepare_text(&mut self, pixels_per_point: f32) {
+        let screen_size = [self.scene_target.config.width, self.scene_target.config.height];
+        self.text.prepare(
+            &self.device,
+            &self.queue,
+            screen_size,
+            pixels_per_point * self.render_scale,
+        );
+    }
+
+    /// Uploads where the picked body is this frame, if anything's picked
+    pub fn prepare_outline(&mut self, instance: Option<InstanceRaw>) {
+        let viewport = [self.scene_target.config.width, self.scene_target.config.height];
+        self.outline
+            .prepare(&self.queue, instance, viewport, self.render_scale);
+    }
+
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        if size.width > 0 && size.height > 0 {
+            self.config.width = size.width;
+            self.config.height = size.height;
+            self.surface.configure(&self.device, &self.config);
+            self.recreate_scene_target();
+            self.particles.resize(
+                &self.device,
+                &self.scene_target.config,
+                &self.scene_target.depth_texture,
+            );
+        }
+    }
+
+    /// Whether the surface can be shown with this present mode. The auto
+    /// ones always work, since they fall back on whatever there is.
+    pub fn supports_present_mode(&self, present_mode: wgpu::PresentMode) -> bool {
+        matches!(
+            present_mode,
+            wgpu::PresentMode::AutoVsync | wgpu::PresentMode::AutoNoVsync
+        ) || self.present_modes.contains(&present_mode)
+    }
+
+    /// The present mode the surface is being shown with
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.config.present_mode
+    }
+
+    /// Switches to a different present mode, or to vsync if the surface
+    /// doesn't have it. Returns the one that's being used.
+    pub fn set_present_mode(&mut self, present_mode: wgpu::PresentMode) -> wgpu::PresentMode {
+        self.config.present_mode = pick_present_mode(present_mode, &self.present_modes);
+        self.surface.configure(&self.device, &self.config);
+        self.config.present_mode
+    }
+
+    /// Changes the number of msaa samples and how big the scene is drawn
+    /// compared to the screen, rebuilding whatever they affect
+    pub fn set_quality(&mut self, sample_count: u32, render_scale: f32) {
+        let sample_count = pick_sample_count(sample_count, &self.sample_counts);
+        let old_sample_count = std::mem::replace(&mut self.sample_count, sample_count);
+        let old_render_scale = std::mem::replace(&mut self.render_scale, render_scale);
+
+        if sample_count != old_sample_count || render_scale != old_render_scale {
+            self.recreate_scene_target();
+        }
+
+        if sample_count != old_sample_count {
+            self.recreate_pipelines();
+        } else if render_scale != old_render_scale {
+            self.particles.resize(
+                &self.device,
+                &self.scene_target.config,
+                &self.scene_target.depth_texture,
+            );
+        }
+    }
+
+    /// Swaps in any shaders that have been saved since the last frame. One
+    /// that doesn't compile, or doesn't fit its pipelines, gets logged and the
+    /// old one's kept.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn reload_changed_shaders(&mut self) {
+        let Some(watcher) = &self.shader_watcher else {
+            return;
+        };
+
+        for filename in watcher.changed() {
+            self.reload_shader(&filename);
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_shader(&mut self, filename: &str) {
+        let Some((_, for_target)) = self.sources.shader_mut(filename) else {
+            log::warn!("{filename} changed, but it's only loaded at startup so it needs a restart");
+            return;
+        };
+
+        // Straight from the disk rather than through resources, since this
+        // only happens natively and there's no need to wait on anything
+        let path = format!("{SHADER_DIR}/{filename}");
+        let source = match std::fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(error) => {
+                log::error!("Couldn't read {path} to reload it: {error}");
+                return;
+            }
+        };
+
+        let source = if for_target {
+            colour::shader_for_target(&source, self.config.format)
+        } else {
+            shader_include::expand(&source)
+        };
+
+        // Validation errors normally panic, so they're caught instead
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = self
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(filename),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            });
+        if let Some(error) = futures::executor::block_on(self.device.pop_error_scope()) {
+            log::error!("{path} didn't compile, keeping the old one:\n{error}");
+            return;
+        }
+
+        let (slot, _) = self.sources.shader_mut(filename).unwrap();
+        let old = std::mem::replace(slot, shader);
+
+        // It can compile and still not work with the pipelines (say, a
+        // missing entry point), so that needs checking too
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        self.recreate_pipelines();
+        if let Some(error) = futures::executor::block_on(self.device.pop_error_scope()) {
+            log::error!("{path} doesn't fit its pipelines, keeping the old one:\n{error}");
+            let (slot, _) = self.sources.shader_mut(filename).unwrap();
+            *slot = old;
+            self.recreate_pipelines();
+            return;
+        }
+
+        log::info!("Reloaded {path}");
+    }
+
+    // Remakes everything that uses a shader from the pipeline sources
+    fn recreate_pipelines(&mut self) {
+        self.pipelines = ScenePipelines::new(
+            &self.device,
+            &self.sources,
+            self.config.format,
+            self.sample_count,
+        );
+
+        self.sprites.set_sample_count(
+            &self.device,
+            &self.sources.sprite_shader,
+            self.config.format,
+            self.sample_count,
+        );
+        self.lines.set_sample_count(
+            &self.device,
+            &self.sources.line_shader,
+            self.config.format,
+            self.sample_count,
+        );
+        self.text.set_sample_count(
+            &self.device,
+            &self.sources.text_shader,
+            self.config.format,
+            self.sample_count,
+        );
+        self.outline.set_sample_count(
+            &self.device,
+            &self.sources.outline_shader,
+            self.config.format,
+            self.sample_count,
+        );
+        #[cfg(feature = "debug-render")]
+        self.colliders.set_sample_count(
+            &self.device,
+            &self.sources.collider_shader,
+            self.config.format,
+            self.sample_count,
+        );
+        self.ground.set_sample_count(
+            &self.device,
+            &self.sources.ground_shader,
+            self.config.format,
+            self.sample_count,
+        );
+        self.skybox.set_sample_count(
+            &self.device,
+            &self.sources.skybox_shader,
+            self.config.format,
+            self.sample_count,
+        );
+        self.particles.set_sample_count(
+            &self.device,
+            &self.sources.particle_shader,
+            self.sources.depth_resolve_shader.as_ref(),
+            &self.scene_target.config,
+            &self.scene_target.depth_texture,
+            self.sample_count,
+        );
+    }
+
+    fn recreate_scene_target(&mut self) {
+        self.scene_target = SceneTarget::new(
+            &self.device,
+            &self.config,
+            self.render_scale,
+            self.sample_count,
+            self.readable_depth,
+            &self.blitter,
+        );
+    }
+
+    /// Draws once with every pipeline that draws the scene, into a tiny target
+    /// that's never shown. Some drivers only finish compiling a pipeline the
+    /// first time something's drawn with it, which would be a hitch partway
+    /// through the scene the first time there's dust, or a label, or the
+    /// reis turn into sprites. It takes a while, so it's done while the
+    /// loading screen (or the last scene) is still showing.
+    ///
+    /// The reis' sprites get baked too if they might be used, since that's
+    /// slow as well.
+    pub fn warm_up(&mut self, buffers: &SceneBuffers, rei_model: &Model, bake_sprites: bool) {
+        let started = Instant::now();
+
+        if bake_sprites && !self.sprites.is_baked() {
+            log::info!("Baking rei sprites");
+            self.sprites.bake(&self.device, &self.queue, rei_model);
+        }
+
+        let target = SceneTarget::new(
+            &self.device,
+            &wgpu::SurfaceConfiguration {
+                width: 1,
+                height: 1,
+                ..self.config.clone()
+            },
+            1.0,
+            self.sample_count,
+            self.readable_depth,
+            &self.blitter,
+        );
+
+        // Stands in for the instances (and the collider vertices), so nothing
+        // in the scene's touched. It's all zeroes, so whatever's drawn with it
+        // doesn't cover anything.
+        let blank = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("warm-up buffer"),
+            size: std::mem::size_of::<InstanceRaw>() as _,
+            usage: wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Warm-up encoder"),
+            });
+
+        self.shadow_map.warm_up(&mut encoder, rei_model, &blank);
+        self.ground.warm_up_splats(&self.device, &mut encoder);
+        self.particles.warm_up_depth_resolve(&mut encoder);
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Warm-up pass"),
+            color_attachments: &[Some(target.colour_attachment(wgpu::LoadOp::Clear(
+                wgpu::Color::BLACK,
+            )))],
+            depth_stencil_attachment: Some(target.depth_attachment(wgpu::LoadOp::Clear(1.0))),
+        });
+
+        self.skybox.warm_up(&mut render_pass);
+        self.ground.draw(
+            &mut render_pass,
+            &buffers.camera,
+            &buffers.clusters,
+            &self.shadow_map,
+        );
+
+        // The model pipelines all get the rei's first mesh
+        if let Some(mesh) = rei_model.meshes.first() {
+            render_pass.set_bind_group(0, &buffers.camera.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, blank.slice(..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+
+            render_pass.set_pipeline(&self.pipelines.light);
+            render_pass.set_bind_group(1, &buffers.light_bind_group, &[]);
+            render_pass.draw_indexed(0..mesh.num_indices, 0, 0..1);
+
+            let material = mesh
+                .material
+                .and_then(|material| rei_model.materials[material].diffuse_bind_group.as_ref());
+            if let Some(material) = material {
+                render_pass.set_bind_group(1, material, &[]);
+                buffers.clusters.bind_plain(&mut render_pass, 2);
+                render_pass.set_bind_group(3, &self.shadow_map.bind_group, &[]);
+
+                for pipeline in [&self.pipelines.model, &self.pipelines.speed] {
+                    render_pass.set_pipeline(pipeline);
+                    render_pass.draw_indexed(0..mesh.num_indices, 0, 0..1);
+                }
+            }
+        }
+
+        self.sprites
+            .warm_up(&mut render_pass, &buffers.camera, &blank, &buffers.clusters);
+        self.particles.warm_up(&mut render_pass, &buffers.camera);
+        self.outline
+            .warm_up(&mut render_pass, &buffers.camera, rei_model);
+        self.lines.warm_up(&mut render_pass, &buffers.camera);
+        self.text.warm_up(&mut render_pass, &buffers.camera);
+        #[cfg(feature = "debug-render")]
+        self.colliders
+            .warm_up(&mut render_pass, &buffers.camera, &blank);
+
+        drop(render_pass);
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        log::info!("Pipelines warmed up in {:?}", started.elapsed());
+    }
+
+    /// Starts fading whatever was last drawn into the scene over the next frames
+    pub fn start_transition(&mut self) {
+        self.transition = Some(Transition::new(
+            &self.device,
+            &self.queue,
+            &self.scene_target,
+            &self.blitter,
+        ));
+    }
+
+    /// Moves any transition along
+    pub fn update(&mut self, delta_time: f32) {
+        if let Some(transition) = self.transition.as_mut() {
+            if !transition.update(delta_time) {
+                self.transition = None;
+            }
+        }
+    }
+
+    /// Starts timing the passes in a frame on the gpu, if it can and the
+    /// timer's enabled. Each one's timed from the end of the one before, so
+    /// the frame should be started just before the first one.
+    pub fn start_timing(&mut self, frame: &mut Frame) {
+        if let Some(timer) = &mut self.timer {
+            timer.start(&mut frame.encoder);
+        }
+    }
+
+    /// Marks the end of a pass in a frame that's being timed
+    pub fn finish_timing(&self, encoder: &mut wgpu::CommandEncoder, pass: GpuPass) {
+        if let Some(timer) = &self.timer {
+            timer.finish(encoder, pass);
+        }
+    }
+
+    pub fn begin_frame(&self) -> Result<Frame, wgpu::SurfaceError> {
+        let output = self.surface.get_current_texture()?;
+        let view = output.texture.create_view(&Default::default());
+
+        let encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
+
+        Ok(Frame {
+            output,
+            view,
+            encoder,
+            ui: None,
+        })
+    }
+
+    /// Uploads everything egui needs to the gpu, so the ui gets drawn over
+    /// the frame when it ends
+    pub fn prepare_ui(
+        &mut self,
+        frame: &mut Frame,
+        ctx: &egui::Context,
+        output: egui::FullOutput,
+        pixels_per_point: f32,
+    ) {
+        let screen_descriptor = ScreenDescriptor {
+            size_in_pixels: [self.config.width, self.config.height],
+            pixels_per_point,
+        };
+
+        let paint_jobs = ctx.tessellate(output.shapes);
+        let textures_delta = output.textures_delta;
+
+        for texture in textures_delta.free.iter() {
+            self.egui_renderer.free_texture(texture);
+        }
+
+        for (id, image_delta) in textures_delta.set {
+            self.egui_renderer
+                .update_texture(&self.device, &self.queue, id, &image_delta);
+        }
+
+        self.egui_renderer.update_buffers(
+            &self.device,
+            &self.queue,
+            &mut frame.encoder,
+            &paint_jobs,
+            &screen_descriptor,
+        );
+
+        frame.ui = Some((paint_jobs, screen_descriptor));
+    }
+
+    /// A pass that clears the scene and draws into it
+    pub fn scene_pass<'a>(
+        &'a self,
+        frame: &'a mut Frame,
+        clear: wgpu::Color,
+    ) -> wgpu::RenderPass<'a> {
+        self.queue.begin_recording();
+        frame
+            .encoder
+            .begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render pass"),
+                color_attachments: &[Some(self.scene_target.colour_attachment(
+                    wgpu::LoadOp::Clear(colour::colour_for_target(clear, self.config.format)),
+                ))],
+                depth_stencil_attachment: Some(
+                    self.scene_target.depth_attachment(wgpu::LoadOp::Clear(1.0)),
+                ),
+            })
+    }
+
+    /// A pass that draws over the top of what's already in the scene
+    pub fn overlay_pass<'a>(&'a self, frame: &'a mut Frame) -> wgpu::RenderPass<'a> {
+        self.queue.begin_recording();
+        frame
+            .encoder
+            .begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Overlay pass"),
+                color_attachments: &[Some(
+                    self.scene_target.colour_attachment(wgpu::LoadOp::Load),
+                )],
+                depth_stencil_attachment: Some(
+                    self.scene_target.depth_attachment(wgpu::LoadOp::Load),
+                ),
+            })
+    }
+
+    /// Puts the scene on the screen, with any transition and then the ui on
+    /// top at full resolution, and presents it. Returns how long submitting
+    /// it took.
+    pub fn end_frame(&mut self, frame: Frame) -> Duration {
+        let Frame {
+            output,
+            view,
+            mut encoder,
+            ui,
+        } = frame;
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Screen pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        self.scene_target.blit(&mut render_pass, &self.blitter);
+
+        if let Some(transition) = self.transition.as_ref() {
+            transition.draw(&mut render_pass, &self.blitter);
+        }
+
+        drop(render_pass);
+        self.finish_timing(&mut encoder, GpuPass::Screen);
+
+        // The ui gets its own pass so it can be timed by itself
+        if let Some((paint_jobs, screen_descriptor)) = ui.as_ref() {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Ui pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            self.egui_renderer
+                .render(&mut render_pass, paint_jobs, screen_descriptor);
+        }
+        self.finish_timing(&mut encoder, GpuPass::Ui);
+
+        if let Some(timer) = &self.timer {
+            timer.resolve(&mut encoder);
+        }
+
+        let started = Instant::now();
+        self.queue.submit(std::iter::once(encoder.finish()));
+        let submit_time = started.elapsed();
+
+        if let Some(timer) = &mut self.timer {
+            timer.submitted();
+        }
+
+        output.present();
+        submit_time
+    }
+}
@@ -1,53 +1,163 @@
 /// Functions for loading resources (platform independent)
 use cfg_if::cfg_if;
 
+#[cfg(target_arch = "wasm32")]
+use crate::storage::{self, Location};
+use crate::{
+    error::{Error, Result},
+    integrity,
+};
+
 #[cfg(target_arch = "wasm32")]
 const CRATE_LOCATION: &str = "";
 
+// Kept with the caches to get around the browser's (and any CDN's) cache
+// after the page is reloaded
 #[cfg(target_arch = "wasm32")]
-fn format_url(file_name: &str) -> reqwest::Url {
-    use anyhow::anyhow;
+const BYPASS_CACHE_FLAG: &str = "bypass-cache";
+
+/// Makes every request after the page is next loaded skip any cached copies,
+/// for when the cached ones turn out to be broken
+#[cfg(target_arch = "wasm32")]
+pub fn bypass_cache_next_time() {
+    let _ = storage::write(Location::Cache, BYPASS_CACHE_FLAG, "1");
+}
 
+// Something to put on the end of urls that's different every time the cache
+// is being bypassed, so nothing can have a copy of them yet
+#[cfg(target_arch = "wasm32")]
+fn cache_buster() -> Option<&'static str> {
+    static BUSTER: std::sync::OnceLock<Option<String>> = std::sync::OnceLock::new();
+
+    BUSTER
+        .get_or_init(|| {
+            storage::read(Location::Cache, BYPASS_CACHE_FLAG).ok().flatten()?;
+            storage::remove(Location::Cache, BYPASS_CACHE_FLAG);
+            log::info!("Bypassing the cache");
+            Some(rand::random::<u32>().to_string())
+        })
+        .as_deref()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn format_url(file_name: &str) -> reqwest::Url {
     let window = web_sys::window().unwrap();
     let location = window.location();
     let origin = location.origin().unwrap();
-    reqwest::Url::parse(&format!("{}/{}", origin, CRATE_LOCATION))
+    let mut url = reqwest::Url::parse(&format!("{}/{}", origin, CRATE_LOCATION))
         .unwrap()
         .join(file_name)
-        .unwrap()
+        .unwrap();
+
+    if let Some(buster) = cache_buster() {
+        url.query_pairs_mut().append_pair("fresh", buster);
+    }
+
+    url
 }
 
-pub async fn load_bytes(filename: &str) -> anyhow::Result<Vec<u8>> {
+#[cfg(target_arch = "wasm32")]
+async fn request(filename: &str) -> Result<reqwest::Response> {
+    let url = format_url(filename);
+    log::info!("requesting {url}");
+
+    let load_error = |source: reqwest::Error| Error::AssetLoad {
+        path: filename.to_string(),
+        source: source.into(),
+    };
+
+    let response = reqwest::get(url).await.map_err(load_error)?;
+
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(Error::AssetMissing {
+            path: filename.to_string(),
+        });
+    }
+
+    response.error_for_status().map_err(load_error)
+}
+
+pub async fn load_bytes(filename: &str) -> Result<Vec<u8>> {
     cfg_if! {
         if #[cfg(target_arch="wasm32")] {
-            let url = format_url(filename);
-            log::info!("requesting {url}");
-            let data = reqwest::get(url)
+            let data = request(filename)
                 .await?
                 .bytes()
-                .await?
+                .await
+                .map_err(|e| Error::AssetLoad { path: filename.to_string(), source: e.into() })?
                 .to_vec();
         } else {
-            let data = tokio::fs::read(filename).await?;
+            let data = tokio::fs::read(filename)
+                .await
+                .map_err(|e| Error::from_io(filename, e))?;
         }
     }
 
+    integrity::check(filename, &data)?;
     Ok(data)
 }
 
-pub async fn load_string(filename: &str) -> anyhow::Result<String> {
+/// The contents of an asset file, from [map_bytes]. On native the file is
+/// memory mapped rather than read in, so big models and sounds can be decoded
+/// straight from disk without a copy of the whole file sitting in memory too.
+pub struct Bytes {
+    #[cfg(target_arch = "wasm32")]
+    data: Vec<u8>,
+    #[cfg(not(target_arch = "wasm32"))]
+    data: memmap2::Mmap,
+}
+
+impl std::ops::Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl AsRef<[u8]> for Bytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// Like [load_bytes], but for when the data only needs to be read, not owned.
+/// On the web this is the same as loading it.
+pub async fn map_bytes(filename: &str) -> Result<Bytes> {
+    cfg_if! {
+        if #[cfg(target_arch="wasm32")] {
+            let data = load_bytes(filename).await?;
+        } else {
+            let file = std::fs::File::open(filename).map_err(|e| Error::from_io(filename, e))?;
+
+            // Safety: the map is only ever read from. If something else changes
+            // the file while it's mapped we could read garbage, but the assets
+            // aren't meant to be edited while the game is running.
+            let data = unsafe { memmap2::Mmap::map(&file) }
+                .map_err(|e| Error::from_io(filename, e))?;
+            // On the web load_bytes has already done this
+            integrity::check(filename, &data)?;
+        }
+    }
+
+    Ok(Bytes { data })
+}
+
+pub async fn load_string(filename: &str) -> Result<String> {
     cfg_if! {
         if #[cfg(target_arch="wasm32")] {
-            let url = format_url(filename);
-            log::info!("requesting {url}");
-            let data = reqwest::get(url)
+            let data = request(filename)
                 .await?
                 .text()
-                .await?;
+                .await
+                .map_err(|e| Error::AssetLoad { path: filename.to_string(), source: e.into() })?;
         } else {
-            let data = tokio::fs::read_to_string(filename).await?;
+            let data = tokio::fs::read_to_string(filename)
+                .await
+                .map_err(|e| Error::from_io(filename, e))?;
         }
     }
 
+    integrity::check(filename, data.as_bytes())?;
     Ok(data)
 }
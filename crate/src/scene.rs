@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(target_arch = "wasm32")]
+use crate::files;
+use crate::{
+    ambience::AmbientSound,
+    animation::UvAnimation,
+    choreography::Choreography,
+    collider_gen::ColliderSettings,
+    emitter::Emitter,
+    error::{Error, Result},
+    light::LightSettings,
+    model::ImportTransform,
+    resources,
+    skybox::SkySource,
+    sun::DayCycle,
+    tweaks::ShaderTweaks,
+};
+
+pub const SCENE_PATH: &str = "assets/scene.toml";
+
+/// The contents of a scene file. This describes everything about the scene
+/// that isn't hard-coded, and is loaded along with the rest of the resources.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SceneDescription {
+    pub emitters: Vec<Emitter>,
+    /// Reis that stay where they're put, like the statue, for things to pile
+    /// up on
+    pub props: Vec<Prop>,
+    /// If set, reis fade out and disappear once they're this many seconds old
+    pub max_age: Option<f32>,
+    /// Transforms applied to the models as they're loaded, by name ("rei" or
+    /// "light"). The rei's collider gets moved along with it.
+    pub models: HashMap<String, ImportTransform>,
+    /// How each model's collider gets made, by name. Only the rei has one,
+    /// which is hand-made unless this says otherwise.
+    pub colliders: HashMap<String, ColliderSettings>,
+    pub animations: Vec<MaterialAnimation>,
+    pub light: LightSettings,
+    /// Looping sounds placed around the scene
+    pub ambience: Vec<AmbientSound>,
+    /// A ttf/otf file to use for the ui instead of the default font
+    pub font: Option<String>,
+    /// Images for the sky. Without any, it's a plain generated one.
+    pub sky: Option<SkySource>,
+    /// The sun, and how quickly the day goes by
+    pub sun: DayCycle,
+    /// A timeline of things that happen during the show
+    pub choreography: Choreography,
+    /// Lighting and fog numbers for the model shader
+    pub tweaks: ShaderTweaks,
+    /// If set, the simulation always starts with this seed, so it plays out
+    /// the same way every time
+    pub seed: Option<u64>,
+}
+
+/// A rei that doesn't move, placed in the scene from the editor
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Prop {
+    pub position: [f32; 3],
+    /// How far it's turned around the vertical axis, in degrees
+    pub rotation: f32,
+    pub scale: f32,
+}
+
+impl Default for Prop {
+    fn default() -> Self {
+        Self {
+            position: [0.0; 3],
+            rotation: 0.0,
+            scale: 1.0,
+        }
+    }
+}
+
+/// Animates the texture of one of a model's materials
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaterialAnimation {
+    /// Which model the material belongs to ("rei" or "light")
+    pub model: String,
+    pub material: String,
+    #[serde(flatten)]
+    pub animation: UvAnimation,
+}
+
+impl SceneDescription {
+    pub async fn load(filename: &str) -> Result<Self> {
+        let data = resources::load_string(filename).await?;
+        Self::from_toml(filename, &data)
+    }
+
+    /// Reads a scene, turning it down if anything in it can't be used
+    pub fn from_toml(filename: &str, data: &str) -> Result<Self> {
+        let mut scene: Self = toml::from_str(data).map_err(|e| Error::decode(filename, e))?;
+
+        for emitter in &mut scene.emitters {
+            emitter
+                .check_scales()
+                .map_err(|e| Error::decode(filename, e))?;
+        }
+
+        Ok(scene)
+    }
+
+    /// The scene written out the same way it's read in
+    pub fn to_toml(&self) -> std::result::Result<String, toml::ser::Error> {
+        let mut value = toml::Value::try_from(self)?;
+        tidy_floats(&mut value);
+        toml::to_string(&value)
+    }
+
+    /// Writes the scene back out. There's nowhere to write it to on the web.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, filename: &str) -> Result<()> {
+        let data = self.to_toml().map_err(|e| Error::save(filename, e))?;
+        std::fs::write(filename, data).map_err(|e| Error::save(filename, e))
+    }
+
+    /// Has the browser download the scene as a file, named like the one it
+    /// was loaded from
+    #[cfg(target_arch = "wasm32")]
+    pub fn download(&self, filename: &str) -> Result<()> {
+        let data = self.to_toml().map_err(|e| Error::save(filename, e))?;
+        files::download(filename, &data)
+    }
+}
+
+// Everything's stored as f32s, which come out as things like 0.9599999785
+// once they've been widened for toml. This puts them back to how they'd have
+// been written.
+fn tidy_floats(value: &mut toml::Value) {
+    match value {
+        toml::Value::Float(float) => *float = (*float as f32).to_string().parse().unwrap_or(*float),
+        toml::Value::Array(array) => array.iter_mut().for_each(tidy_floats),
+        toml::Value::Table(table) => table.iter_mut().for_each(|(_, value)| tidy_floats(value)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_scenes_load_back_the_same() {
+        let original: SceneDescription =
+            toml::from_str(include_str!("../assets/scene.toml")).unwrap();
+        let saved = original.to_toml().unwrap();
+        let loaded: SceneDescription = toml::from_str(&saved).unwrap();
+
+        // Saving it again gives exactly the same file, so nothing was lost
+        // or changed on the way through
+        assert_eq!(loaded.to_toml().unwrap(), saved);
+        assert_eq!(loaded.emitters.len(), original.emitters.len());
+        assert_eq!(loaded.props, original.props);
+        assert_eq!(loaded.seed, original.seed);
+    }
+
+    #[test]
+    fn emitter_scales_are_checked() {
+        let swapped = "[[emitters]]\nmin_scale = 2.0\nmax_scale = 0.5";
+        let scene = SceneDescription::from_toml("scene.toml", swapped).unwrap();
+        assert_eq!(scene.emitters[0].min_scale, 0.5);
+        assert_eq!(scene.emitters[0].max_scale, 2.0);
+
+        let zero = "[[emitters]]\nmin_scale = 0.0\nmax_scale = 1.0";
+        assert!(SceneDescription::from_toml("scene.toml", zero).is_err());
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashSet;
+
+use cgmath::Point3;
+use rapier3d::prelude::RigidBodyHandle;
+
+use crate::{camera::Camera, physics::PhysicsSimulation};
+
+// How far the mouse has to move before the lasso gets another corner, in
+// pixels, so it doesn't end up with thousands of them
+const LASSO_SPACING: f32 = 4.0;
+
+/// Something in the scene that the user has selected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    /// The rei that sits still in the middle of everything
+    Statue,
+    Emitter(usize),
+    Prop(usize),
+    /// Only selectable in the editor
+    Light,
+}
+
+impl Selection {
+    /// The selected thing's rigid body, if it has one. Emitters and the
+    /// light don't.
+    pub fn body(&self, physics: &PhysicsSimulation) -> Option<RigidBodyHandle> {
+        match *self {
+            Selection::Statue => Some(physics.statue_body()),
+            Selection::Prop(index) => physics.prop_body(index),
+            Selection::Emitter(_) | Selection::Light => None,
+        }
+    }
+
+    /// Where the selected thing is in the world, or None if it's gone
+    /// (e.g. an emitter that doesn't exist after loading a new scene). The
+    /// light isn't part of the simulation, so it doesn't have one here.
+    pub fn position(&self, physics: &PhysicsSimulation) -> Option<[f32; 3]> {
+        match *self {
+            Selection::Statue => Some(physics.statue_position()),
+            Selection::Prop(index) => physics.prop_position(index),
+            Selection::Light => None,
+
+            Selection::Emitter(index) => physics.emitters().get(index).map(|emitter| {
+                // The interesting part of an emitter is where its reis land,
+                // not the empty space they spawn in
+                let [x, _, z] = emitter.position;
+                let y = physics.surface_height(x, z).unwrap_or(0.0);
+                [x, y, z]
+            }),
+        }
+    }
+}
+
+/// A bunch of reis picked out at once in the editor, by dragging a
+/// [Marquee] around them
+#[derive(Debug, Default)]
+pub struct Group {
+    bodies: HashSet<RigidBodyHandle>,
+}
+
+impl Group {
+    pub fn bodies(&self) -> &HashSet<RigidBodyHandle> {
+        &self.bodies
+    }
+
+    pub fn len(&self) -> usize {
+        self.bodies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bodies.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.bodies.clear();
+    }
+
+    /// Selects every rei that's drawn inside the marquee, on top of what was
+    /// already selected if it's `adding`, or instead of it otherwise
+    pub fn select(
+        &mut self,
+        marquee: &Marquee,
+        physics: &PhysicsSimulation,
+        camera: &Camera,
+        size: [f32; 2],
+        adding: bool,
+    ) {
+        if !adding {
+            self.bodies.clear();
+        }
+
+        self.bodies
+            .extend(physics.visible_reis().filter_map(|(handle, position)| {
+                let pixel = camera.to_pixel(Point3::from(position), size)?;
+                marquee.contains(pixel).then_some(handle)
+            }));
+    }
+
+    /// Forgets any reis that have been removed (or started clearing away)
+    /// since they were selected
+    pub fn prune(&mut self, physics: &PhysicsSimulation) {
+        self.bodies.retain(|&handle| physics.contains_rei(handle));
+    }
+}
+
+/// What shape gets dragged out to select a [Group]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MarqueeShape {
+    /// A box from where the drag started to where the mouse is
+    #[default]
+    Rectangle,
+    /// Whatever shape the mouse draws, closed off back to where it started
+    Lasso,
+}
+
+/// The outline being dragged out on screen to select a [Group], in pixels
+#[derive(Debug, Clone)]
+pub struct Marquee {
+    shape: MarqueeShape,
+    // The two opposite corners of a rectangle, or every corner of a lasso
+    points: Vec<[f32; 2]>,
+}
+
+impl Marquee {
+    pub fn new(shape: MarqueeShape, start: [f32; 2]) -> Self {
+        let points = match shape {
+            MarqueeShape::Rectangle => vec![start, start],
+            MarqueeShape::Lasso => vec![start],
+        };
+        Self { shape, points }
+    }
+
+    /// Follows the mouse to `to`
+    pub fn extend(&mut self, to: [f32; 2]) {
+        match self.shape {
+            MarqueeShape::Rectangle => self.points[1] = to,
+            MarqueeShape::Lasso => {
+                let last = self.points[self.points.len() - 1];
+                if (to[0] - last[0]).hypot(to[1] - last[1]) >= LASSO_SPACING {
+                    self.points.push(to);
+                }
+            }
+        }
+    }
+
+    /// The corners around the outside, in order
+    pub fn outline(&self) -> Vec<[f32; 2]> {
+        match self.shape {
+            MarqueeShape::Rectangle => {
+                let [[x1, y1], [x2, y2]] = [self.points[0], self.points[1]];
+                vec![[x1, y1], [x2, y1], [x2, y2], [x1, y2]]
+            }
+            MarqueeShape::Lasso => self.points.clone(),
+        }
+    }
+
+    /// Whether a pixel is inside it. Lassos that cross over themselves count
+    /// the bits that are inside an odd number of times.
+    pub fn contains(&self, [x, y]: [f32; 2]) -> bool {
+        let outline = self.outline();
+        let mut inside = false;
+
+        // Counts how many edges a line going right from the point crosses
+        for (i, &[x1, y1]) in outline.iter().enumerate() {
+            let [x2, y2] = outline[(i + 1) % outline.len()];
+            if (y1 > y) != (y2 > y) && x < x1 + (y - y1) / (y2 - y1) * (x2 - x1) {
+                inside = !inside;
+            }
+        }
+
+        inside
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rectangles_can_be_dragged_either_way() {
+        let mut marquee = Marquee::new(MarqueeShape::Rectangle, [100.0, 100.0]);
+        marquee.extend([20.0, 50.0]);
+
+        assert!(marquee.contains([60.0, 70.0]));
+        assert!(!marquee.contains([110.0, 70.0]));
+        assert!(!marquee.contains([60.0, 40.0]));
+    }
+
+    #[test]
+    fn lassos_follow_the_mouse() {
+        // A U shape, so the middle of it is outside
+        let mut marquee = Marquee::new(MarqueeShape::Lasso, [0.0, 0.0]);
+        for point in [
+            [0.0, 100.0],
+            [100.0, 100.0],
+            [100.0, 0.0],
+            [70.0, 0.0],
+            [70.0, 70.0],
+            [30.0, 70.0],
+            [30.0, 0.0],
+        ] {
+            marquee.extend(point);
+        }
+
+        assert!(marquee.contains([10.0, 50.0]));
+        assert!(marquee.contains([50.0, 85.0]));
+        assert!(!marquee.contains([50.0, 30.0]));
+
+        // Tiny movements don't add corners
+        let corners = marquee.outline().len();
+        marquee.extend([30.0, 1.0]);
+        assert_eq!(marquee.outline().len(), corners);
+    }
+}
@@ -0,0 +1,95 @@
+//! Bits of wgsl that more than one shader needs. A shader pulls one in with a
+//! line like `#include "lighting.wgsl"`, which gets swapped for the file
+//! from `shaders/include/` before it's compiled.
+//!
+//! The includes are built in, even in debug builds where the shaders
+//! themselves are loaded at runtime, so changing one needs a restart.
+
+// What's in each include, by name
+fn contents(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "camera.wgsl" => include_str!("../shaders/include/camera.wgsl"),
+        "contact.wgsl" => include_str!("../shaders/include/contact.wgsl"),
+        "dither.wgsl" => include_str!("../shaders/include/dither.wgsl"),
+        "emitter.wgsl" => include_str!("../shaders/include/emitter.wgsl"),
+        "ground.wgsl" => include_str!("../shaders/include/ground.wgsl"),
+        "light.wgsl" => include_str!("../shaders/include/light.wgsl"),
+        "lighting.wgsl" => include_str!("../shaders/include/lighting.wgsl"),
+        "shadow.wgsl" => include_str!("../shaders/include/shadow.wgsl"),
+        "tweaks.wgsl" => include_str!("../shaders/include/tweaks.wgsl"),
+        _ => return None,
+    })
+}
+
+/// Replaces a shader's includes with what's in them. Each one only goes in
+/// the first time it comes up, so includes can include each other without
+/// anything being declared twice. An include that doesn't exist is left as
+/// it is, so the shader fails to compile and the error points at it.
+pub fn expand(source: &str) -> String {
+    let mut expanded = String::with_capacity(source.len());
+    expand_into(source, &mut Vec::new(), &mut expanded);
+    expanded
+}
+
+fn expand_into(source: &str, included: &mut Vec<String>, expanded: &mut String) {
+    for line in source.lines() {
+        let found = include_name(line).and_then(|name| Some((name, contents(name)?)));
+
+        match found {
+            Some((name, _)) if included.iter().any(|done| done == name) => {}
+
+            Some((name, contents)) => {
+                included.push(name.to_string());
+                expand_into(contents, included, expanded);
+            }
+
+            None => {
+                expanded.push_str(line);
+                expanded.push('\n');
+            }
+        }
+    }
+}
+
+fn include_name(line: &str) -> Option<&str> {
+    line.trim()
+        .strip_prefix("#include")?
+        .trim()
+        .strip_prefix('"')?
+        .strip_suffix('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_include_in_the_shaders_exists() {
+        for entry in std::fs::read_dir("shaders").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|extension| extension != "wgsl") {
+                continue;
+            }
+
+            let source = std::fs::read_to_string(&path).unwrap();
+            assert!(
+                !expand(&source).contains("#include"),
+                "{} includes something that doesn't exist",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn includes_only_go_in_once() {
+        let expanded = expand("#include \"lighting.wgsl\"\n#include \"light.wgsl\"\n");
+        assert_eq!(expanded.matches("struct Light {").count(), 1);
+        assert_eq!(expanded.matches("struct Shadow {").count(), 1);
+    }
+
+    #[test]
+    fn unknown_includes_are_left_in() {
+        let expanded = expand("#include \"nope.wgsl\"\n");
+        assert_eq!(expanded, "#include \"nope.wgsl\"\n");
+    }
+}
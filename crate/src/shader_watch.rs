@@ -0,0 +1,83 @@
+//! Watching the shaders folder for changes, so the renderer can pick up an
+//! edited shader without restarting. Native only, since on the web the
+//! shaders come from the server.
+
+use std::{
+    collections::BTreeSet,
+    path::Path,
+    sync::mpsc::{self, Receiver},
+};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+pub const SHADER_DIR: &str = "shaders";
+
+pub struct ShaderWatcher {
+    // Never used, but the watching stops when it's dropped
+    _watcher: RecommendedWatcher,
+    changes: Receiver<String>,
+}
+
+impl ShaderWatcher {
+    /// Starts watching the shaders folder. If that can't be done (say, it's
+    /// been run from somewhere else) there's just no hot reloading.
+    pub fn new() -> Option<Self> {
+        let (sender, changes) = mpsc::channel();
+
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let event = match event {
+                Ok(event) => event,
+                Err(error) => {
+                    log::warn!("Error watching the shaders: {error}");
+                    return;
+                }
+            };
+
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                return;
+            }
+
+            for path in event.paths {
+                if let Some(filename) = shader_filename(&path) {
+                    // The renderer's gone if this fails, so there's nothing to tell
+                    let _ = sender.send(filename);
+                }
+            }
+        });
+
+        let result = watcher.and_then(|mut watcher| {
+            watcher.watch(Path::new(SHADER_DIR), RecursiveMode::NonRecursive)?;
+            Ok(watcher)
+        });
+
+        match result {
+            Ok(watcher) => {
+                log::info!("Watching {SHADER_DIR}/ for changes");
+                Some(Self {
+                    _watcher: watcher,
+                    changes,
+                })
+            }
+
+            Err(error) => {
+                log::warn!("Couldn't watch {SHADER_DIR}/, shaders won't hot reload: {error}");
+                None
+            }
+        }
+    }
+
+    /// The shaders that have changed since this was last called, by filename
+    /// (`model_shader.wgsl`). Saving a file usually sends a few events, so
+    /// each one only comes up once.
+    pub fn changed(&self) -> BTreeSet<String> {
+        self.changes.try_iter().collect()
+    }
+}
+
+fn shader_filename(path: &Path) -> Option<String> {
+    if path.extension()? != "wgsl" {
+        return None;
+    }
+
+    Some(path.file_name()?.to_str()?.to_string())
+}
@@ -0,0 +1,423 @@
+use cgmath::{perspective, Deg, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use crate::{
+    camera::OPENGL_TO_WGPU_MATRIX,
+    contact::ContactDarkening,
+    frustum::Frustum,
+    layouts::LayoutCache,
+    locale::Strings,
+    model::{InstanceRaw, Model, ModelVertex, Vertex},
+    sync_audit::AuditedQueue,
+};
+
+static SHADOW_BIND_GROUP_LAYOUT: LayoutCache = LayoutCache::new();
+
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+pub const SHADOW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+const SHADOW_NEAR: f32 = 0.5;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct ShadowUniform {
+    view_proj: [[f32; 4]; 4],
+    // 0 or 1, bools can't go in uniforms
+    enabled: u32,
+    bias: f32,
+    _padding: [f32; 2],
+}
+
+/// Settings for how the shadow map is rendered, tweakable from the ui
+#[derive(Debug, Clone)]
+pub struct ShadowConfig {
+    pub enabled: bool,
+    /// Field of view of the light's camera, in degrees. Anything outside
+    /// of it is never shadowed.
+    pub fov: f32,
+    /// How far from the light shadows are rendered
+    pub range: f32,
+    /// Depth offset used when comparing against the shadow map, to stop
+    /// surfaces from shadowing themselves
+    pub bias: f32,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            fov: 100.0,
+            range: 100.0,
+            bias: 0.002,
+        }
+    }
+}
+
+/// A shadow map rendered from the point of view of the light, looking
+/// towards a target. Only things in front of the light (from the target's
+/// side) get shadows, but that's good enough as long as the target is
+/// something interesting.
+pub struct ShadowMap {
+    pub config: ShadowConfig,
+    // Whether the light was given a shadow this frame (see ShadowBudget)
+    casting: bool,
+    // What the light's camera can see, as of the last update
+    frustum: Frustum,
+    view: wgpu::TextureView,
+    uniform_buffer: wgpu::Buffer,
+    pipeline: wgpu::RenderPipeline,
+    // Used while rendering the shadow map, so it can't contain the map itself
+    pass_bind_group: wgpu::BindGroup,
+    /// Used while rendering everything else, to sample the shadow map. The
+    /// contact darkening's in it too.
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl ShadowMap {
+    pub fn bind_group_layout(device: &wgpu::Device) -> &'static wgpu::BindGroupLayout {
+        SHADOW_BIND_GROUP_LAYOUT.get(device, |device| {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("shadow bind group layout"),
+                entries: &[
+                    uniform_layout_entry(),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Depth,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                        count: None,
+                    },
+                ]
+                .into_iter()
+                .chain(ContactDarkening::layout_entries(3))
+                .collect::<Vec<_>>(),
+            })
+        })
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        contact: &ContactDarkening,
+    ) -> Self {
+        let config = ShadowConfig::default();
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shadow map"),
+            size: wgpu::Extent3d {
+                width: SHADOW_MAP_SIZE,
+                height: SHADOW_MAP_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: SHADOW_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadow sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("shadow uniform buffer"),
+            contents: bytemuck::cast_slice(&[ShadowUniform {
+                view_proj: Matrix4::identity().into(),
+                enabled: 0,
+                bias: config.bias,
+                _padding: [0.0; 2],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shadow bind group"),
+            layout: Self::bind_group_layout(device),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ]
+            .into_iter()
+            .chain(contact.entries(3))
+            .collect::<Vec<_>>(),
+        });
+
+        let pass_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("shadow pass bind group layout"),
+                entries: &[uniform_layout_entry()],
+            });
+
+        let pass_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shadow pass bind group"),
+            layout: &pass_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("shadow pipeline layout"),
+            bind_group_layouts: &[&pass_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("shadow pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            // Only the depth is needed
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: SHADOW_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: Default::default(),
+                // Slope-scaled bias takes care of most of the acne on
+                // surfaces at a steep angle to the light
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: Default::default(),
+            multiview: None,
+        });
+
+        Self {
+            config,
+            casting: true,
+            frustum: Frustum::from_matrix(Matrix4::identity()),
+            view,
+            uniform_buffer,
+            pipeline,
+            pass_bind_group,
+            bind_group,
+        }
+    }
+
+    /// Points the light's camera from `light` towards `target`. If the light
+    /// isn't `casting` this frame, nothing's shadowed.
+    pub fn update(
+        &mut self,
+        queue: &AuditedQueue,
+        light: [f32; 3],
+        target: [f32; 3],
+        casting: bool,
+    ) {
+        self.casting = casting;
+
+        let view_proj = light_view_proj(&self.config, light, target);
+        self.frustum = Frustum::from_matrix(view_proj);
+
+        let uniform = ShadowUniform {
+            view_proj: view_proj.into(),
+            enabled: self.enabled() as u32,
+            bias: self.config.bias,
+            _padding: [0.0; 2],
+        };
+
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Renders the shadow casters into the shadow map
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &Model,
+        instance_buffer: &wgpu::Buffer,
+        num_instances: u32,
+    ) {
+        // The map still gets cleared when shadows are off, so nothing is
+        // left over from before they were turned off
+        let num_instances = if self.enabled() { num_instances } else { 0 };
+        self.record(encoder, model, instance_buffer, num_instances);
+    }
+
+    /// Draws the model into the shadow map at the first instance, even if
+    /// shadows are off, so the pipeline's been used before they're turned on.
+    /// The next frame's [ShadowMap::render] clears it again.
+    pub fn warm_up(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &Model,
+        instance_buffer: &wgpu::Buffer,
+    ) {
+        self.record(encoder, model, instance_buffer, 1);
+    }
+
+    fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &Model,
+        instance_buffer: &wgpu::Buffer,
+        num_instances: u32,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        if num_instances == 0 {
+            return;
+        }
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.pass_bind_group, &[]);
+        render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+
+        for mesh in model.meshes.iter() {
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..mesh.num_indices, 0, 0..num_instances);
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.config.enabled && self.casting
+    }
+
+    /// What the light's camera can see, or None if nothing's being shadowed.
+    /// Anything in it casts a shadow, on screen or not.
+    pub fn frustum(&self) -> Option<Frustum> {
+        self.enabled().then_some(self.frustum)
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, strings: &Strings) {
+        ui.checkbox(&mut self.config.enabled, strings.get("shadows.enabled"));
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("shadows.fov"));
+            ui.add(
+                egui::DragValue::new(&mut self.config.fov)
+                    .clamp_range(10.0..=170.0)
+                    .speed(0.5),
+            );
+        });
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("shadows.range"));
+            ui.add(
+                egui::DragValue::new(&mut self.config.range)
+                    .clamp_range(5.0..=500.0)
+                    .speed(0.5),
+            );
+        });
+
+        ui.horizontal(|ui| {
+            ui.label(strings.get("shadows.bias"));
+            ui.add(
+                egui::DragValue::new(&mut self.config.bias)
+                    .clamp_range(0.0..=0.05)
+                    .speed(0.0001),
+            );
+        });
+    }
+}
+
+fn uniform_layout_entry() -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+// What the light sees, looking from `light` at `target`
+fn light_view_proj(config: &ShadowConfig, light: [f32; 3], target: [f32; 3]) -> Matrix4<f32> {
+    let eye = Point3::from(light);
+    let mut target = Point3::from(target);
+
+    // Right on top of the target there's no way to look at it, so it looks
+    // straight down instead
+    if (target - eye).magnitude2() < 1e-6 {
+        target = eye - Vector3::unit_y();
+    }
+
+    let direction = (target - eye).normalize();
+    // look_at breaks if the up vector is parallel to the view direction
+    let up = if direction.y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    };
+
+    let view = Matrix4::look_at_rh(eye, target, up);
+    let projection = perspective(
+        Deg(config.fov),
+        1.0,
+        SHADOW_NEAR,
+        config.range.max(SHADOW_NEAR + 1.0),
+    );
+
+    OPENGL_TO_WGPU_MATRIX * projection * view
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_light_on_its_target_still_looks_somewhere() {
+        let config = ShadowConfig::default();
+        let view_proj = light_view_proj(&config, [1.0, 2.0, 3.0], [1.0, 2.0, 3.0]);
+
+        let columns: [[f32; 4]; 4] = view_proj.into();
+        assert!(columns.iter().flatten().all(|x| x.is_finite()));
+    }
+}
@@ -0,0 +1,131 @@
+//! What the app plays sounds through when it's built without the `audio`
+//! feature. It has the same api as audio.rs, so nothing else needs to know,
+//! but nothing's ever played and nothing gets loaded.
+
+use std::{convert::Infallible, time::Duration};
+
+use cgmath::{Quaternion, Vector3};
+
+use crate::{ambience::AmbientSound, error::Result, locale::Strings};
+
+/// How loud the song would be made to be, in LUFS
+pub const DEFAULT_MUSIC_LOUDNESS: f64 = -16.0;
+
+/// A sound, which here is nothing at all
+#[derive(Debug, Clone)]
+pub struct Sound;
+
+/// A song, which there never is
+pub struct Music;
+
+impl Music {
+    pub async fn load(_path: &str) -> Result<Self> {
+        Ok(Self)
+    }
+}
+
+pub struct MusicHandle;
+
+impl MusicHandle {
+    pub fn seek_to(&mut self, _position: f64) -> std::result::Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+pub struct SoundEvent;
+
+impl SoundEvent {
+    pub fn centred(_time: f64, _sound: Sound, _volume: f64) -> Self {
+        Self
+    }
+
+    pub fn at(
+        _time: f64,
+        _sound: Sound,
+        _volume: f64,
+        _position: Vector3<f32>,
+        _listener_position: Vector3<f32>,
+        _listener_orientation: Quaternion<f32>,
+    ) -> Self {
+        Self
+    }
+}
+
+#[derive(Default)]
+pub struct AudioSystem {
+    // So the app doesn't keep trying to start the music
+    music_started: bool,
+}
+
+impl AudioSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn play_music(&mut self, _song: Music) -> Result<()> {
+        self.music_started = true;
+        Ok(())
+    }
+
+    pub fn set_music_loudness(&mut self, _loudness: f64) {}
+
+    pub fn song_handle_mut(&mut self) -> Option<&mut MusicHandle> {
+        None
+    }
+
+    pub fn music_started(&self) -> bool {
+        self.music_started
+    }
+
+    pub fn keep_music_playing(&mut self) {}
+
+    pub fn set_paused(&mut self, _paused: bool) {}
+
+    pub fn set_ducked(&mut self, _ducked: bool) {}
+
+    pub fn set_master_volume(&mut self, _volume: f64) {}
+
+    pub fn set_music_volume(&mut self, _volume: f64) {}
+
+    pub fn set_sfx_volume(&mut self, _volume: f64) {}
+
+    pub fn set_muted(&mut self, _muted: bool) {}
+
+    pub fn set_time_scale(&mut self, _scale: f64) {}
+
+    pub fn set_preserve_music_pitch(&mut self, _preserve: bool) {}
+
+    pub fn fade_out(&mut self) -> Duration {
+        Duration::ZERO
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, strings: &Strings) {
+        ui.label(strings.get("audio.not_built"));
+    }
+
+    pub fn song_position(&self) -> Option<f64> {
+        None
+    }
+
+    pub fn set_ambience(&mut self, _sounds: Vec<(AmbientSound, Sound)>) {}
+
+    pub fn update_spatial(
+        &mut self,
+        _listener_position: Vector3<f32>,
+        _listener_orientation: Quaternion<f32>,
+        _position_of: impl Fn(&AmbientSound) -> [f32; 3],
+    ) {
+    }
+
+    pub fn queue(&mut self, _event: SoundEvent) {}
+
+    pub fn update(&mut self, _sim_time: f64) {}
+}
+
+pub fn click_sound() -> Sound {
+    Sound
+}
+
+pub fn thud_sound() -> Sound {
+    Sound
+}
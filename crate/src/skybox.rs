@@ -0,0 +1,486 @@
+use std::f32::consts::PI;
+
+use cgmath::{Matrix4, SquareMatrix, Vector4};
+use serde::{Deserialize, Serialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use crate::{
+    camera::Camera,
+    colour,
+    error::{Error, Result},
+    layouts::LayoutCache,
+    resources,
+    sync_audit::AuditedQueue,
+    texture,
+};
+
+// How big each face of the cubemap is when it's made from one image (or
+// generated), in pixels. Equirect images are a quarter of their width.
+const MIN_FACE_SIZE: u32 = 16;
+const MAX_FACE_SIZE: u32 = 1024;
+const GENERATED_FACE_SIZE: u32 = 64;
+// The generated sky, in linear colour. The horizon's the same as the clear
+// colour, so it lines up with the fog.
+pub const HORIZON_COLOUR: [f32; 3] = [0.5, 0.82, 0.98];
+const ZENITH_COLOUR: [f32; 3] = [0.12, 0.35, 0.8];
+
+static SKYBOX_BIND_GROUP_LAYOUT: LayoutCache = LayoutCache::new();
+
+/// Where the sky comes from, in the scene file. Either six images, or one
+/// equirectangular (latitude/longitude) one like most hdr skies come as.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SkySource {
+    /// In the order +x, -x, +y, -y, +z, -z
+    Faces([String; 6]),
+    Equirect(String),
+}
+
+/// The six faces of a cubemap in rgba8 (sRGB), ready to be uploaded
+#[derive(Debug, Clone)]
+pub struct SkyFaces {
+    pub size: u32,
+    pub faces: [Vec<u8>; 6],
+}
+
+impl SkyFaces {
+    /// Loads the images for a sky. An equirect image gets turned into a
+    /// cubemap here, and hdr images are squashed down into what a normal
+    /// image can hold.
+    pub async fn load(source: &SkySource) -> Result<Self> {
+        match source {
+            SkySource::Faces(paths) => {
+                let images =
+                    futures::future::try_join_all(paths.iter().map(|path| load_image(path)))
+                        .await?;
+
+                let size = images[0].width();
+                for (image, path) in images.iter().zip(paths) {
+                    if image.width() != size || image.height() != size {
+                        return Err(Error::decode(
+                            path,
+                            format!("sky faces have to be square and all {size}x{size}"),
+                        ));
+                    }
+                }
+
+                let mut images = images.into_iter().map(image::RgbaImage::into_raw);
+                Ok(Self {
+                    size,
+                    faces: std::array::from_fn(|_| images.next().unwrap()),
+                })
+            }
+
+            SkySource::Equirect(path) => {
+                let image = load_image(path).await?;
+                let size = (image.width() / 4).clamp(MIN_FACE_SIZE, MAX_FACE_SIZE);
+                Ok(Self::from_fn(size, |direction| {
+                    let [u, v] = equirect_coords(direction);
+                    let x = ((u * image.width() as f32) as u32).min(image.width() - 1);
+                    let y = ((v * image.height() as f32) as u32).min(image.height() - 1);
+                    image.get_pixel(x, y).0
+                }))
+            }
+        }
+    }
+
+    /// A plain sky for when the scene doesn't have one: the clear colour at
+    /// the horizon, getting bluer further up
+    pub fn generated() -> Self {
+        Self::from_fn(GENERATED_FACE_SIZE, |[x, y, z]| {
+            let up = (y / (x * x + y * y + z * z).sqrt()).max(0.0).powf(0.6);
+            let mut pixel = [255; 4];
+            for i in 0..3 {
+                let linear = HORIZON_COLOUR[i] + (ZENITH_COLOUR[i] - HORIZON_COLOUR[i]) * up;
+                pixel[i] = to_byte(colour::linear_to_srgb(linear));
+            }
+            pixel
+        })
+    }
+
+    // Fills in every face, given the colour in each direction
+    fn from_fn(size: u32, colour: impl Fn([f32; 3]) -> [u8; 4]) -> Self {
+        let faces = std::array::from_fn(|face| {
+            let mut data = Vec::with_capacity((size * size * 4) as usize);
+            for y in 0..size {
+                for x in 0..size {
+                    data.extend_from_slice(&colour(face_direction(face, size, x, y)));
+                }
+            }
+            data
+        });
+
+        Self { size, faces }
+    }
+}
+
+async fn load_image(path: &str) -> Result<image::RgbaImage> {
+    let data = resources::load_bytes(path).await?;
+    let image = image::load_from_memory(&data).map_err(|e| Error::decode(path, e))?;
+
+    Ok(match image {
+        // Hdr colours go past 1, so they get tonemapped before they're
+        // squashed into bytes
+        image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_) => {
+            let mut hdr = image.into_rgba32f();
+            for pixel in hdr.pixels_mut() {
+                for c in &mut pixel.0[..3] {
+                    *c = colour::linear_to_srgb(*c / (1.0 + *c));
+                }
+            }
+            image::DynamicImage::ImageRgba32F(hdr).into_rgba8()
+        }
+        image => image.into_rgba8(),
+    })
+}
+
+fn to_byte(c: f32) -> u8 {
+    (c.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+// The direction through the middle of a texel of a cubemap face. Sampling
+// the cubemap in this direction gives back that texel.
+fn face_direction(face: usize, size: u32, x: u32, y: u32) -> [f32; 3] {
+    let u = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+    let v = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+
+    match face {
+        0 => [1.0, -v, -u],
+        1 => [-1.0, -v, u],
+        2 => [u, 1.0, v],
+        3 => [u, -1.0, -v],
+        4 => [u, -v, 1.0],
+        _ => [-u, -v, -1.0],
+    }
+}
+
+// Where a direction lands on an equirect image, from 0 to 1 across and down.
+// The middle of the image is straight down -z.
+fn equirect_coords([x, y, z]: [f32; 3]) -> [f32; 2] {
+    let length = (x * x + y * y + z * z).sqrt();
+    let longitude = x.atan2(-z);
+    let latitude = (y / length).clamp(-1.0, 1.0).asin();
+
+    [0.5 + longitude / (2.0 * PI), 0.5 - latitude / PI]
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct SkyboxUniform {
+    // Clip space to a direction in world space, with the camera's position
+    // left out so the sky never gets any closer
+    inverse_view_proj: [[f32; 4]; 4],
+    // What the sky's colour gets multiplied by, for the time of day
+    tint: [f32; 3],
+    _padding: f32,
+}
+
+/// The sky behind everything, drawn first thing in the scene pass. It's one
+/// triangle over the whole screen, and each pixel looks up the cubemap in
+/// whichever direction the camera sees through it.
+pub struct Skybox {
+    pub enabled: bool,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Skybox {
+    pub fn bind_group_layout(device: &wgpu::Device) -> &'static wgpu::BindGroupLayout {
+        SKYBOX_BIND_GROUP_LAYOUT.get(device, |device| {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("skybox bind group layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::Cube,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            })
+        })
+    }
+
+    /// Starts out with the generated sky, until [Skybox::set_faces] is given
+    /// the scene's
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &AuditedQueue,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("skybox uniform buffer"),
+            contents: bytemuck::cast_slice(&[SkyboxUniform {
+                inverse_view_proj: Matrix4::identity().into(),
+                tint: [1.0; 3],
+                _padding: 0.0,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("skybox sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let bind_group = create_bind_group(
+            device,
+            queue,
+            &uniform_buffer,
+            &sampler,
+            &SkyFaces::generated(),
+        );
+
+        Self {
+            enabled: true,
+            uniform_buffer,
+            sampler,
+            bind_group,
+            pipeline: create_pipeline(device, shader, format, sample_count),
+        }
+    }
+
+    /// Recreates the pipeline for a scene with a different number of samples
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        self.pipeline = create_pipeline(device, shader, format, sample_count);
+    }
+
+    /// Swaps the sky for a different one
+    pub fn set_faces(&mut self, device: &wgpu::Device, queue: &AuditedQueue, faces: &SkyFaces) {
+        self.bind_group =
+            create_bind_group(device, queue, &self.uniform_buffer, &self.sampler, faces);
+    }
+
+    /// Points the sky the same way as the camera. Only its rotation matters,
+    /// the sky's infinitely far away. The tint darkens (or reddens) it for
+    /// the time of day.
+    pub fn prepare(&self, queue: &AuditedQueue, camera: &Camera, tint: [f32; 3]) {
+        let mut view = camera.view();
+        view.w = Vector4::new(0.0, 0.0, 0.0, 1.0);
+
+        let inverse_view_proj = (camera.projection() * view)
+            .invert()
+            .unwrap_or_else(Matrix4::identity);
+
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[SkyboxUniform {
+                inverse_view_proj: inverse_view_proj.into(),
+                tint,
+                _padding: 0.0,
+            }]),
+        );
+    }
+
+    /// Draws the sky. This should be the first thing in the scene pass, since
+    /// it doesn't write any depth for the models to be tested against.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.enabled {
+            self.record_draw(render_pass);
+        }
+    }
+
+    /// Draws the sky even if it's turned off, so the pipeline's been used
+    /// before it's turned on
+    pub fn warm_up<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        self.record_draw(render_pass);
+    }
+
+    fn record_draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    queue: &AuditedQueue,
+    uniform_buffer: &wgpu::Buffer,
+    sampler: &wgpu::Sampler,
+    faces: &SkyFaces,
+) -> wgpu::BindGroup {
+    let size = wgpu::Extent3d {
+        width: faces.size,
+        height: faces.size,
+        depth_or_array_layers: 6,
+    };
+
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("skybox cubemap"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8UnormSrgb,
+        usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+
+    // It's only done once per scene, so it doesn't go through the upload
+    // scheduler like the model textures do
+    for (layer, data) in faces.faces.iter().enumerate() {
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer as u32,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * faces.size),
+                rows_per_image: Some(faces.size),
+            },
+            wgpu::Extent3d {
+                depth_or_array_layers: 1,
+                ..size
+            },
+        );
+    }
+
+    let view = texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::Cube),
+        ..Default::default()
+    });
+
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("skybox bind group"),
+        layout: Skybox::bind_group_layout(device),
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ],
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("skybox pipeline layout"),
+        bind_group_layouts: &[Skybox::bind_group_layout(device)],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("skybox pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: None,
+            ..Default::default()
+        },
+        // Everything else gets drawn over it, so it doesn't need any depth
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: Default::default(),
+            bias: Default::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn faces_line_up_with_the_equirect() {
+        // The middle of each face, and where it should be on the image
+        let expected = [
+            [0.75, 0.5],
+            [0.25, 0.5],
+            [0.5, 0.0],
+            [0.5, 1.0],
+            [0.0, 0.5],
+            [0.5, 0.5],
+        ];
+
+        for (face, [u, v]) in expected.into_iter().enumerate() {
+            let [found_u, found_v] = equirect_coords(face_direction(face, 1, 0, 0));
+
+            assert!((found_v - v).abs() < 1e-4, "face {face}: {found_v} vs {v}");
+            // Straight up and down the longitude doesn't matter, and straight
+            // behind is both edges at once
+            if v == 0.5 && u != 0.0 {
+                assert!((found_u - u).abs() < 1e-4, "face {face}: {found_u} vs {u}");
+            }
+        }
+    }
+}
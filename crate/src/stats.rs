@@ -0,0 +1,194 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use egui::plot::{Legend, Line, Plot, PlotPoints};
+use instant::Instant;
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    locale::Strings,
+    physics::{BodyCounts, PhysicsSimulation},
+};
+
+// How often the recorder produces a new sample, in seconds
+const SAMPLE_PERIOD: f32 = 1.0;
+// How many seconds of samples are kept around for the plots
+const HISTORY_LENGTH: f32 = 60.0;
+const PLOT_HEIGHT: f32 = 100.0;
+
+// The latest snapshot, for whatever's embedding the app to poll. There's only
+// ever one app running so this doesn't need to belong to it.
+static LATEST: Mutex<StatsSnapshot> = Mutex::new(StatsSnapshot {
+    bodies: 0,
+    sleeping: 0,
+    average_speed: 0.0,
+    pile_height: 0.0,
+    fps: 0.0,
+});
+
+/// The simulation's stats at one point in time. A new one is taken every
+/// second, and the latest can be had from [crate::simulation_stats].
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StatsSnapshot {
+    /// Reis in the simulation, including the statue
+    pub bodies: usize,
+    pub sleeping: usize,
+    /// In units per second
+    pub average_speed: f32,
+    /// Height of the highest rei that's come to rest
+    pub pile_height: f32,
+    pub fps: f32,
+}
+
+/// The most recent snapshot
+pub fn latest() -> StatsSnapshot {
+    *LATEST.lock().unwrap()
+}
+
+/// One sample's worth of stats
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    // Seconds since the recorder was created
+    time: f64,
+    fps: f32,
+    bodies: usize,
+    sleeping: usize,
+    // Reis spawned per second
+    spawn_rate: f32,
+}
+
+/// Keeps track of simple statistics about the running simulation, averaged
+/// over one second windows. Anything that wants to show numbers to the user
+/// (the egui window, the window title...) should read them from here.
+pub struct StatsRecorder {
+    start: Instant,
+    frames_counted: u32,
+    frame_counter: Instant,
+    fps: f32,
+    counts: BodyCounts,
+    // How many reis had been spawned at the last sample
+    last_spawned: usize,
+    history: VecDeque<Sample>,
+}
+
+impl StatsRecorder {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            frames_counted: 0,
+            frame_counter: Instant::now(),
+            fps: 0.0,
+            counts: BodyCounts::default(),
+            last_spawned: 0,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Records that a frame happened. Returns true if a new sample was
+    /// produced, so callers can throttle anything expensive to once a second.
+    pub fn record_frame(&mut self, counts: BodyCounts) -> bool {
+        self.frames_counted += 1;
+        self.counts = counts;
+
+        let elapsed = self.frame_counter.elapsed().as_secs_f32();
+
+        if elapsed >= SAMPLE_PERIOD {
+            self.fps = self.frames_counted as f32 / elapsed;
+            self.frame_counter = Instant::now();
+            self.frames_counted = 0;
+
+            // The spawn count goes back to 0 when the simulation is reset
+            let spawned = counts.spawned.saturating_sub(self.last_spawned);
+            self.last_spawned = counts.spawned;
+
+            self.history.push_back(Sample {
+                time: self.start.elapsed().as_secs_f64(),
+                fps: self.fps,
+                bodies: counts.total,
+                sleeping: counts.sleeping,
+                spawn_rate: spawned as f32 / elapsed,
+            });
+
+            while self.history.len() as f32 > HISTORY_LENGTH / SAMPLE_PERIOD {
+                self.history.pop_front();
+            }
+
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Takes a snapshot of the stats and the simulation, which then becomes
+    /// the latest one
+    pub fn publish(&self, physics: &PhysicsSimulation) {
+        *LATEST.lock().unwrap() = StatsSnapshot {
+            bodies: self.counts.total,
+            sleeping: self.counts.sleeping,
+            average_speed: physics.average_speed(),
+            pile_height: physics.pile_height(),
+            fps: self.fps,
+        };
+    }
+
+    pub fn fps(&self) -> f32 {
+        self.fps
+    }
+
+    pub fn bodies(&self) -> usize {
+        self.counts.total
+    }
+
+    /// A short summary, e.g. "tumblin-down — 1000 bodies @ 58 FPS"
+    pub fn title(&self) -> String {
+        format!(
+            "tumblin-down — {} bodies @ {:.0} FPS",
+            self.counts.total, self.fps
+        )
+    }
+
+    // Turns one of the sample's fields into a line on a plot
+    fn line(&self, name: &str, value: impl Fn(&Sample) -> f64) -> Line {
+        let points = self
+            .history
+            .iter()
+            .map(|sample| [sample.time, value(sample)])
+            .collect::<PlotPoints>();
+
+        Line::new(points).name(name)
+    }
+
+    /// Plots of the last minute of stats
+    pub fn plot_ui(&self, ui: &mut egui::Ui, strings: &Strings) {
+        let plot = |id: &str| {
+            Plot::new(id)
+                .height(PLOT_HEIGHT)
+                .include_y(0.0)
+                .allow_drag(false)
+                .allow_zoom(false)
+                .allow_scroll(false)
+                .legend(Legend::default())
+        };
+
+        plot("fps plot").show(ui, |plot_ui| {
+            plot_ui.line(self.line(strings.get("stats.fps_line"), |sample| sample.fps as f64));
+        });
+
+        plot("bodies plot").show(ui, |plot_ui| {
+            plot_ui.line(self.line(strings.get("stats.active"), |sample| {
+                (sample.bodies - sample.sleeping) as f64
+            }));
+            plot_ui.line(self.line(strings.get("stats.sleeping"), |sample| {
+                sample.sleeping as f64
+            }));
+        });
+
+        plot("spawn rate plot").show(ui, |plot_ui| {
+            plot_ui.line(self.line(strings.get("stats.spawns"), |sample| {
+                sample.spawn_rate as f64
+            }));
+        });
+    }
+}
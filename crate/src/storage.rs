@@ -0,0 +1,235 @@
+//! Where things that stick around between runs are kept, so nothing else has
+//! to know what platform it's on to save something.
+//!
+//! Natively each [Location] is a directory in the usual place for the OS
+//! (`~/.config`, `~/.local/share` and `~/.cache` on linux, following the XDG
+//! variables if they're set, `Library` on macos and `AppData` on windows),
+//! which is made the first time something's written to it. If there's no home
+//! directory to find them in, it's the working directory like it always used
+//! to be.
+//!
+//! On the web there are no files, so everything's kept in the browser's
+//! storage under `tumblin-down-<name>` (leaving out any extension). Settings
+//! and data go in local storage, and caches in session storage so they're
+//! gone once the tab's closed. Anything big, or that someone wants to take
+//! somewhere else, gets downloaded and uploaded instead (see [crate::files]).
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+
+/// What every directory (or storage key) is named after
+pub const APP_NAME: &str = "tumblin-down";
+
+/// The kinds of things that get kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    /// The settings file
+    Settings,
+    /// Things made with it, like exported camera paths
+    Data,
+    /// Things that can be thrown away and made again. Only the web has
+    /// anything cached so far.
+    #[allow(dead_code)]
+    Cache,
+}
+
+/// The directory things in this location are kept in
+#[cfg(not(target_arch = "wasm32"))]
+pub fn dir(location: Location) -> PathBuf {
+    platform_dir(location, std::env::consts::OS, |name| {
+        std::env::var_os(name).map(PathBuf::from)
+    })
+    .map(|dir| dir.join(APP_NAME))
+    .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Where a file with this name in this location is
+#[cfg(not(target_arch = "wasm32"))]
+pub fn path(location: Location, name: &str) -> PathBuf {
+    dir(location).join(name)
+}
+
+/// Writes a file in a location, replacing it if it's already there
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write(location: Location, name: &str, data: &str) -> Result<()> {
+    write_file(&path(location, name), data)
+}
+
+/// Reads a file from anywhere, or None if it isn't there
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read_file(path: &Path) -> Result<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::from_io(&path.display().to_string(), e)),
+    }
+}
+
+/// Writes a file anywhere, making the directories it's in if they aren't
+/// there yet
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write_file(path: &Path, data: &str) -> Result<()> {
+    let name = path.display().to_string();
+
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent).map_err(|e| Error::save(&name, e))?;
+    }
+
+    std::fs::write(path, data).map_err(|e| Error::save(&name, e))
+}
+
+// Where the OS wants each location to go, before our own directory's added
+// on. The environment's passed in so the tests don't depend on who's running
+// them.
+#[cfg(not(target_arch = "wasm32"))]
+fn platform_dir(
+    location: Location,
+    os: &str,
+    env: impl Fn(&str) -> Option<PathBuf>,
+) -> Option<PathBuf> {
+    // Relative paths in the XDG variables are meant to be ignored
+    let absolute = |name: &str| env(name).filter(|path| path.is_absolute());
+    let home = || absolute("HOME");
+
+    match os {
+        "windows" => match location {
+            Location::Settings | Location::Data => absolute("APPDATA"),
+            Location::Cache => absolute("LOCALAPPDATA"),
+        },
+        "macos" | "ios" => {
+            let library = home()?.join("Library");
+            Some(match location {
+                Location::Settings | Location::Data => library.join("Application Support"),
+                Location::Cache => library.join("Caches"),
+            })
+        }
+        _ => {
+            let (variable, fallback) = match location {
+                Location::Settings => ("XDG_CONFIG_HOME", ".config"),
+                Location::Data => ("XDG_DATA_HOME", ".local/share"),
+                Location::Cache => ("XDG_CACHE_HOME", ".cache"),
+            };
+            absolute(variable).or_else(|| Some(home()?.join(fallback)))
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn web_storage(location: Location) -> Option<web_sys::Storage> {
+    let window = web_sys::window()?;
+    match location {
+        Location::Settings | Location::Data => window.local_storage(),
+        Location::Cache => window.session_storage(),
+    }
+    .ok()
+    .flatten()
+}
+
+// Storage keys don't have extensions, which also keeps the settings where
+// they were before there was more than one thing kept
+#[cfg(target_arch = "wasm32")]
+fn key(name: &str) -> String {
+    let stem = name.split('.').next().unwrap_or(name);
+    format!("{APP_NAME}-{stem}")
+}
+
+/// Reads something from a location, or None if it hasn't been written yet.
+/// Storage can be turned off, in which case nothing ever has been.
+#[cfg(target_arch = "wasm32")]
+pub fn read(location: Location, name: &str) -> Result<Option<String>> {
+    Ok(web_storage(location).and_then(|storage| storage.get_item(&key(name)).ok().flatten()))
+}
+
+/// Writes something in a location, replacing it if it's already there
+#[cfg(target_arch = "wasm32")]
+pub fn write(location: Location, name: &str, data: &str) -> Result<()> {
+    let key = key(name);
+    let storage = web_storage(location)
+        .ok_or_else(|| Error::save(&key, "the browser's storage isn't available"))?;
+
+    storage
+        .set_item(&key, data)
+        .map_err(|e| Error::save(&key, format!("{e:?}")))
+}
+
+/// Deletes something from a location, if it's there
+#[cfg(target_arch = "wasm32")]
+pub fn remove(location: Location, name: &str) {
+    if let Some(storage) = web_storage(location) {
+        let _ = storage.remove_item(&key(name));
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    fn env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<PathBuf> + 'a {
+        move |name| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| PathBuf::from(value))
+        }
+    }
+
+    #[test]
+    fn follows_each_platforms_conventions() {
+        let home = [("HOME", "/home/rei")];
+        assert_eq!(
+            platform_dir(Location::Settings, "linux", env(&home)),
+            Some(PathBuf::from("/home/rei/.config"))
+        );
+        assert_eq!(
+            platform_dir(Location::Data, "linux", env(&home)),
+            Some(PathBuf::from("/home/rei/.local/share"))
+        );
+        assert_eq!(
+            platform_dir(Location::Cache, "macos", env(&home)),
+            Some(PathBuf::from("/home/rei/Library/Caches"))
+        );
+
+        // Windows paths aren't absolute anywhere else, so this only checks
+        // that it doesn't go looking in HOME
+        assert_eq!(
+            platform_dir(Location::Settings, "windows", env(&home)),
+            None
+        );
+    }
+
+    #[test]
+    fn xdg_variables_win_if_theyre_usable() {
+        let vars = [
+            ("HOME", "/home/rei"),
+            ("XDG_CONFIG_HOME", "/etc/rei"),
+            ("XDG_CACHE_HOME", "relative/cache"),
+        ];
+
+        assert_eq!(
+            platform_dir(Location::Settings, "linux", env(&vars)),
+            Some(PathBuf::from("/etc/rei"))
+        );
+        assert_eq!(
+            platform_dir(Location::Cache, "linux", env(&vars)),
+            Some(PathBuf::from("/home/rei/.cache"))
+        );
+        assert_eq!(platform_dir(Location::Data, "linux", env(&[])), None);
+    }
+
+    #[test]
+    fn writing_makes_the_directories() {
+        let dir =
+            std::env::temp_dir().join(format!("{APP_NAME}-storage-test-{}", std::process::id()));
+        let file = dir.join("nested").join("thing.json");
+
+        assert_eq!(read_file(&file).unwrap(), None);
+        write_file(&file, "{}").unwrap();
+        assert_eq!(read_file(&file).unwrap().as_deref(), Some("{}"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+//! The sun, which goes round once a day and lights everything from one
+//! direction. It doesn't cast shadows, only the point light does.
+
+use std::f32::consts::TAU;
+
+use egui::DragValue;
+use serde::{Deserialize, Serialize};
+
+use crate::{locale::Strings, skybox};
+
+// How far the sun's path leans away from straight overhead, in radians
+const TILT: f32 = 0.5;
+const NOON_COLOUR: [f32; 3] = [1.0, 0.95, 0.85];
+const SUNSET_COLOUR: [f32; 3] = [1.0, 0.5, 0.25];
+const BRIGHTNESS: f32 = 0.8;
+
+// What the sky gets multiplied by around sunset, and at night
+const DUSK_TINT: [f32; 3] = [0.95, 0.6, 0.5];
+const NIGHT_TINT: [f32; 3] = [0.05, 0.07, 0.15];
+
+/// The time of day, and how quickly it goes by. Turned off, it's always the
+/// middle of the day and there's no sun, which is how the scene looked before
+/// there was one.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DayCycle {
+    pub enabled: bool,
+    /// How many days go by per minute. 0 stops the sun where it is.
+    pub speed: f32,
+    /// From 0 to 1, where 0 is midnight and 0.5 is midday
+    pub time_of_day: f32,
+}
+
+impl Default for DayCycle {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            speed: 1.0,
+            time_of_day: 0.4,
+        }
+    }
+}
+
+impl DayCycle {
+    /// Moves the day along. This gets simulation time, so pausing stops the
+    /// sun too.
+    pub fn update(&mut self, delta_time: f32) {
+        if self.enabled {
+            self.time_of_day = (self.time_of_day + self.speed * delta_time / 60.0).rem_euclid(1.0);
+        }
+    }
+
+    /// Which way the sun is, from anywhere in the scene. It comes up in +x
+    /// and goes down in -x.
+    pub fn direction(&self) -> [f32; 3] {
+        let angle = (self.time_of_day - 0.25) * TAU;
+        let (sin, cos) = angle.sin_cos();
+        [cos, sin * TILT.cos(), sin * TILT.sin()]
+    }
+
+    /// The sunlight's colour (and brightness). It goes orange near the horizon
+    /// and black once it's gone down.
+    pub fn colour(&self) -> [f32; 3] {
+        if !self.enabled {
+            return [0.0; 3];
+        }
+
+        let height = self.direction()[1];
+        let strength = smoothstep(-0.05, 0.1, height) * BRIGHTNESS;
+        let noon = smoothstep(0.0, 0.4, height);
+        mix(SUNSET_COLOUR, NOON_COLOUR, noon).map(|c| c * strength)
+    }
+
+    /// What the sky's colour gets multiplied by
+    pub fn sky_tint(&self) -> [f32; 3] {
+        if !self.enabled {
+            return [1.0; 3];
+        }
+
+        let height = self.direction()[1];
+        let day = mix(DUSK_TINT, [1.0; 3], smoothstep(0.05, 0.35, height));
+        mix(NIGHT_TINT, day, smoothstep(-0.2, 0.2, height))
+    }
+
+    /// The colour of the sky at the horizon, which is also what the
+    /// background's cleared to and what things fade into in the fog
+    pub fn sky_colour(&self) -> [f32; 3] {
+        let tint = self.sky_tint();
+        std::array::from_fn(|i| skybox::HORIZON_COLOUR[i] * tint[i])
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, strings: &Strings) {
+        ui.checkbox(&mut self.enabled, strings.get("sun.enabled"));
+
+        ui.add_enabled_ui(self.enabled, |ui| {
+            ui.horizontal(|ui| {
+                ui.label(strings.get("sun.speed"));
+                ui.add(
+                    DragValue::new(&mut self.speed)
+                        .clamp_range(0.0..=60.0)
+                        .speed(0.05),
+                );
+            });
+
+            ui.horizontal(|ui| {
+                ui.label(strings.get("sun.time_of_day"));
+                ui.add(
+                    egui::Slider::new(&mut self.time_of_day, 0.0..=1.0).custom_formatter(
+                        |time, _| {
+                            let minutes = (time * 24.0 * 60.0) as u32 % (24 * 60);
+                            format!("{:02}:{:02}", minutes / 60, minutes % 60)
+                        },
+                    ),
+                );
+            });
+        });
+    }
+}
+
+fn smoothstep(low: f32, high: f32, x: f32) -> f32 {
+    let t = ((x - low) / (high - low)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn mix(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    std::array::from_fn(|i| a[i] + (b[i] - a[i]) * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_sun_goes_down_at_night() {
+        let at = |time_of_day| DayCycle {
+            enabled: true,
+            time_of_day,
+            ..Default::default()
+        };
+
+        assert!(at(0.5).direction()[1] > 0.8);
+        assert_eq!(at(0.0).colour(), [0.0; 3]);
+        assert!(at(0.0).sky_tint()[0] < 0.1);
+        assert_eq!(at(0.5).sky_tint(), [1.0; 3]);
+
+        // Turned off, it looks the same as it did before there was a sun
+        let off = DayCycle::default();
+        assert_eq!(off.colour(), [0.0; 3]);
+        assert_eq!(off.sky_colour(), skybox::HORIZON_COLOUR);
+    }
+}
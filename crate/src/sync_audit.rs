@@ -0,0 +1,210 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    panic::Location,
+    sync::Mutex,
+};
+
+type Caller = &'static Location<'static>;
+
+/// The queue, but buffer writes can be checked for mistakes first.
+///
+/// `write_buffer` doesn't write anything straight away. The data gets copied
+/// in just before the next submit, so every pass in that submit sees whatever
+/// was written last, no matter when it was recorded. That makes two kinds of
+/// mistake easy:
+///  - writing the same buffer twice before a submit, expecting each write to
+///    be seen by whatever was drawn in between
+///  - writing a buffer after this frame's passes have started being recorded,
+///    expecting those passes to see the old contents
+///
+/// With auditing turned on (in the debug ui), both of these get a warning in
+/// the log saying where the writes came from. Everything else the queue does
+/// goes straight through.
+pub struct AuditedQueue {
+    queue: wgpu::Queue,
+    // None when auditing is off
+    audit: Mutex<Option<Audit<wgpu::Id<wgpu::Buffer>>>>,
+}
+
+impl std::ops::Deref for AuditedQueue {
+    type Target = wgpu::Queue;
+
+    fn deref(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+}
+
+impl AuditedQueue {
+    pub fn new(queue: wgpu::Queue) -> Self {
+        Self {
+            queue,
+            audit: Mutex::new(None),
+        }
+    }
+
+    pub fn auditing(&self) -> bool {
+        self.audit.lock().unwrap().is_some()
+    }
+
+    pub fn set_auditing(&self, auditing: bool) {
+        let mut audit = self.audit.lock().unwrap();
+        if auditing != audit.is_some() {
+            *audit = auditing.then(Audit::default);
+        }
+    }
+
+    /// How many different problems have been found since auditing was turned on
+    pub fn hazards(&self) -> usize {
+        self.audit
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(0, |audit| audit.reported.len())
+    }
+
+    #[track_caller]
+    pub fn write_buffer(&self, buffer: &wgpu::Buffer, offset: wgpu::BufferAddress, data: &[u8]) {
+        if let Some(audit) = self.audit.lock().unwrap().as_mut() {
+            if let Some(hazard) = audit.write(buffer.global_id(), Location::caller()) {
+                log::warn!("{hazard}");
+            }
+        }
+
+        self.queue.write_buffer(buffer, offset, data);
+    }
+
+    /// Lets the audit know that passes that'll use this frame's writes are
+    /// being recorded now
+    pub fn begin_recording(&self) {
+        if let Some(audit) = self.audit.lock().unwrap().as_mut() {
+            audit.recording = true;
+        }
+    }
+
+    pub fn submit<I: IntoIterator<Item = wgpu::CommandBuffer>>(
+        &self,
+        command_buffers: I,
+    ) -> wgpu::SubmissionIndex {
+        if let Some(audit) = self.audit.lock().unwrap().as_mut() {
+            audit.submit();
+        }
+
+        self.queue.submit(command_buffers)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Hazard {
+    /// Written again before the first write was submitted
+    Rewritten { first: Caller, second: Caller },
+    /// Written after passes that'll see it started being recorded
+    WrittenWhileRecording { caller: Caller },
+}
+
+impl std::fmt::Display for Hazard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rewritten { first, second } => write!(
+                f,
+                "Buffer written at {first} is written again at {second} before being \
+                 submitted, so only the second write will ever be seen"
+            ),
+            Self::WrittenWhileRecording { caller } => write!(
+                f,
+                "Buffer written at {caller} after this frame's passes started recording, \
+                 so they'll see the new contents rather than the old ones"
+            ),
+        }
+    }
+}
+
+// Keeps track of the writes since the last submit. Generic over what a buffer
+// is so it can be tested without a gpu.
+struct Audit<K> {
+    // Where each buffer was first written since the last submit
+    writes: HashMap<K, Caller>,
+    recording: bool,
+    // Each problem is only reported once, otherwise it'd be every frame
+    reported: HashSet<Hazard>,
+}
+
+impl<K> Default for Audit<K> {
+    fn default() -> Self {
+        Self {
+            writes: HashMap::new(),
+            recording: false,
+            reported: HashSet::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq> Audit<K> {
+    // Records a write, and gives back what's wrong with it if it hasn't been
+    // reported already
+    fn write(&mut self, buffer: K, caller: Caller) -> Option<Hazard> {
+        let hazard = if self.recording {
+            Some(Hazard::WrittenWhileRecording { caller })
+        } else {
+            self.writes.get(&buffer).map(|&first| Hazard::Rewritten {
+                first,
+                second: caller,
+            })
+        };
+
+        self.writes.entry(buffer).or_insert(caller);
+        hazard.filter(|hazard| self.reported.insert(*hazard))
+    }
+
+    fn submit(&mut self) {
+        self.writes.clear();
+        self.recording = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_write_per_submit_is_fine() {
+        let mut audit = Audit::default();
+
+        for _ in 0..3 {
+            assert_eq!(audit.write(0, Location::caller()), None);
+            assert_eq!(audit.write(1, Location::caller()), None);
+            audit.submit();
+        }
+    }
+
+    #[test]
+    fn hazards_are_reported_once() {
+        let mut audit = Audit::default();
+        let (first, second) = (Location::caller(), Location::caller());
+
+        for i in 0..3 {
+            audit.write(0, first);
+            let hazard = audit.write(0, second);
+            assert_eq!(hazard.is_some(), i == 0);
+            audit.submit();
+        }
+
+        assert_eq!(audit.reported.len(), 1);
+    }
+
+    #[test]
+    fn writes_while_recording_are_reported() {
+        let mut audit = Audit {
+            recording: true,
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            audit.write(0, Location::caller()),
+            Some(Hazard::WrittenWhileRecording { .. })
+        ));
+
+        audit.submit();
+        assert_eq!(audit.write(0, Location::caller()), None);
+    }
+}
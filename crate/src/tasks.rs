@@ -0,0 +1,81 @@
+//! Running futures in the background while the event loop has the main
+//! thread. Winit won't hand the thread over to an async runtime, so loading
+//! gets spawned onto one instead (tokio's worker threads natively, the
+//! browser's microtask queue on the web), and the result comes back over a
+//! channel that gets checked once a frame.
+
+use std::future::Future;
+
+use cfg_if::cfg_if;
+use futures::channel::oneshot;
+
+cfg_if! {
+    if #[cfg(target_arch = "wasm32")] {
+        /// Nothing needs to be `Send` on the web, since there's only the one
+        /// thread (and requests aren't `Send` anyway)
+        pub trait MaybeSend {}
+        impl<T> MaybeSend for T {}
+    } else {
+        /// Tasks run on tokio's worker threads, so they have to be `Send`
+        pub trait MaybeSend: Send {}
+        impl<T: Send> MaybeSend for T {}
+    }
+}
+
+/// A future that's been started in the background. Its result gets picked up
+/// with [Task::poll] once it's finished.
+pub struct Task<T> {
+    result: oneshot::Receiver<T>,
+}
+
+impl<T> Task<T> {
+    /// The result, if it's finished. It's only handed out the once.
+    pub fn poll(&mut self) -> Option<T> {
+        self.result
+            .try_recv()
+            .expect("a background task panicked before it finished")
+    }
+}
+
+/// Starts running a future in the background. Natively this has to be called
+/// from inside the tokio runtime, which the whole app is.
+pub fn spawn<T, F>(future: F) -> Task<T>
+where
+    T: MaybeSend + 'static,
+    F: Future<Output = T> + MaybeSend + 'static,
+{
+    let (sender, result) = oneshot::channel();
+    let future = async move {
+        // Nobody's waiting for it any more if this fails, which is fine
+        let _ = sender.send(future.await);
+    };
+
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            wasm_bindgen_futures::spawn_local(future);
+        } else {
+            tokio::spawn(future);
+        }
+    }
+
+    Task { result }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn tasks_run_alongside_each_other() {
+        let (sender, receiver) = oneshot::channel();
+
+        // The first can only finish once the second has run
+        let mut first = spawn(async move { receiver.await.unwrap() + 1 });
+        let mut second = spawn(async move { sender.send(1).unwrap() });
+
+        while first.poll().is_none() {
+            tokio::task::yield_now().await;
+        }
+        assert!(second.poll().is_some());
+    }
+}
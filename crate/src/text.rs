@@ -0,0 +1,568 @@
+//! Text drawn straight into the scene rather than through egui: the hud, and
+//! labels that hang in the world over things. Glyphs are rasterised with
+//! ab_glyph at the exact size they're shown at, packed into an atlas as
+//! they're needed, and drawn as one quad each in the overlay pass, so they
+//! get the scene's antialiasing and depth (labels go behind whatever's in
+//! front of them).
+
+use std::collections::HashMap;
+
+use ab_glyph::{Font, FontRef, GlyphId, PxScale, PxScaleFont, ScaleFont};
+
+use crate::{camera::CameraBinding, fonts, sync_audit::AuditedQueue, texture};
+
+const ATLAS_SIZE: u32 = 1024;
+// Empty pixels left around each glyph, so the filtering doesn't bleed the
+// next one in
+const PADDING: u32 = 1;
+// Room for this many glyphs to start with, the buffer grows if it needs more
+const INITIAL_GLYPHS: usize = 1024;
+// How far down and right the shadow behind the text goes, in points
+const SHADOW_OFFSET: f32 = 1.0;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct GlyphInstance {
+    anchor: [f32; 3],
+    // 1 if the anchor's a point in the world, 0 if the offset's already a
+    // place on the screen
+    in_world: f32,
+    // In pixels, from the anchor to the glyph's top left corner
+    offset: [f32; 2],
+    size: [f32; 2],
+    uv_min: [f32; 2],
+    uv_max: [f32; 2],
+    colour: [f32; 4],
+}
+
+impl GlyphInstance {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 7] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32,
+        2 => Float32x2,
+        3 => Float32x2,
+        4 => Float32x2,
+        5 => Float32x2,
+        6 => Float32x4,
+    ];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<GlyphInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct TextUniform {
+    screen_size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+/// Where a bit of text goes
+#[derive(Debug, Clone, Copy)]
+pub enum TextAnchor {
+    /// The top left corner, in points from the top left of the screen
+    Screen([f32; 2]),
+    /// The middle of the bottom edge, at a point in the world. The text
+    /// stays the same size however far away it is.
+    World([f32; 3]),
+}
+
+// A bit of text waiting to be laid out in prepare
+struct Section {
+    text: String,
+    anchor: TextAnchor,
+    // In points
+    size: f32,
+    colour: [f32; 4],
+}
+
+// Where a glyph is in the atlas, and how it sits relative to where it's drawn
+#[derive(Debug, Clone, Copy)]
+struct AtlasGlyph {
+    uv_min: [f32; 2],
+    uv_max: [f32; 2],
+    // From the pen position on the baseline to the top left corner
+    offset: [f32; 2],
+    size: [f32; 2],
+}
+
+struct AtlasFull;
+
+// Glyphs rasterised so far, packed in rows from the top down
+struct GlyphAtlas {
+    texture: wgpu::Texture,
+    // None for glyphs with nothing to draw, like spaces
+    glyphs: HashMap<(GlyphId, u32), Option<AtlasGlyph>>,
+    cursor: [u32; 2],
+    row_height: u32,
+}
+
+impl GlyphAtlas {
+    fn clear(&mut self) {
+        self.glyphs.clear();
+        self.cursor = [0, 0];
+        self.row_height = 0;
+    }
+
+    // The glyph at a size in whole pixels, rasterising it first if it isn't
+    // in the atlas yet
+    fn get(
+        &mut self,
+        queue: &AuditedQueue,
+        font: &FontRef<'static>,
+        id: GlyphId,
+        pixels: u32,
+    ) -> Result<Option<AtlasGlyph>, AtlasFull> {
+        if let Some(glyph) = self.glyphs.get(&(id, pixels)) {
+            return Ok(*glyph);
+        }
+
+        let glyph = id.with_scale(PxScale::from(pixels as f32));
+        let Some(outline) = font.outline_glyph(glyph) else {
+            self.glyphs.insert((id, pixels), None);
+            return Ok(None);
+        };
+
+        let bounds = outline.px_bounds();
+        let width = bounds.width() as u32;
+        let height = bounds.height() as u32;
+
+        if self.cursor[0] + width + PADDING > ATLAS_SIZE {
+            self.cursor = [0, self.cursor[1] + self.row_height + PADDING];
+            self.row_height = 0;
+        }
+        if self.cursor[1] + height + PADDING > ATLAS_SIZE {
+            return Err(AtlasFull);
+        }
+
+        let mut pixels_data = vec![0; (width * height) as usize];
+        outline.draw(|x, y, coverage| {
+            pixels_data[(y * width + x) as usize] = (coverage * 255.0).round() as u8;
+        });
+
+        let [x, y] = self.cursor;
+        if width > 0 && height > 0 {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &self.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x, y, z: 0 },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &pixels_data,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(width),
+                    rows_per_image: None,
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        self.cursor[0] += width + PADDING;
+        self.row_height = self.row_height.max(height);
+
+        let atlas = ATLAS_SIZE as f32;
+        let entry = AtlasGlyph {
+            uv_min: [x as f32 / atlas, y as f32 / atlas],
+            uv_max: [(x + width) as f32 / atlas, (y + height) as f32 / atlas],
+            offset: [bounds.min.x, bounds.min.y],
+            size: [width as f32, height as f32],
+        };
+        self.glyphs.insert((id, pixels), Some(entry));
+        Ok(Some(entry))
+    }
+}
+
+/// Queues up text each frame, then lays it out and uploads it with
+/// [TextRenderer::prepare] so it can be drawn in the overlay pass
+pub struct TextRenderer {
+    font: FontRef<'static>,
+    atlas: GlyphAtlas,
+    sections: Vec<Section>,
+    instances: Vec<GlyphInstance>,
+    instance_buffer: wgpu::Buffer,
+    // How many glyphs were uploaded last
+    uploaded: u32,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl TextRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let font = FontRef::try_from_slice(fonts::FALLBACK_FONT)
+            .expect("the embedded font should always parse");
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("glyph atlas"),
+            size: wgpu::Extent3d {
+                width: ATLAS_SIZE,
+                height: ATLAS_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("glyph atlas sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("text uniform buffer"),
+            size: std::mem::size_of::<TextUniform>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("text bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("text bind group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline = create_pipeline(device, shader, &bind_group_layout, format, sample_count);
+
+        Self {
+            font,
+            atlas: GlyphAtlas {
+                texture,
+                glyphs: HashMap::new(),
+                cursor: [0, 0],
+                row_height: 0,
+            },
+            sections: Vec::new(),
+            instances: Vec::new(),
+            instance_buffer: create_instance_buffer(device, INITIAL_GLYPHS),
+            uploaded: 0,
+            uniform_buffer,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    /// Recreates the pipeline for a scene with a different number of samples
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        self.pipeline = create_pipeline(
+            device,
+            shader,
+            &self.bind_group_layout,
+            format,
+            sample_count,
+        );
+    }
+
+    /// Gets rid of all the text, ready for the next frame's
+    pub fn clear(&mut self) {
+        self.sections.clear();
+    }
+
+    /// Queues some text to be drawn this frame, with a shadow behind it so it
+    /// can be read against anything. It can have more than one line.
+    pub fn queue(&mut self, text: String, anchor: TextAnchor, size: f32, colour: [f32; 4]) {
+        self.sections.push(Section {
+            text,
+            anchor,
+            size,
+            colour,
+        });
+    }
+
+    /// Lays out this frame's text for a scene `screen_size` pixels big with
+    /// `pixels_per_point` pixels to a point, and uploads it
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &AuditedQueue,
+        screen_size: [u32; 2],
+        pixels_per_point: f32,
+    ) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[TextUniform {
+                screen_size: [screen_size[0] as f32, screen_size[1] as f32],
+                _padding: [0.0; 2],
+            }]),
+        );
+
+        // If the atlas fills up it starts over with just this frame's glyphs.
+        // If even they don't fit, whatever didn't make it is left out.
+        if self.layout(queue, pixels_per_point).is_err() {
+            log::warn!("The glyph atlas is full, starting it over");
+            self.atlas.clear();
+            if self.layout(queue, pixels_per_point).is_err() {
+                log::warn!("Too much text to fit in the glyph atlas, some of it's been left out");
+            }
+        }
+
+        let size = (self.instances.len() * std::mem::size_of::<GlyphInstance>()) as u64;
+        if size > self.instance_buffer.size() {
+            self.instance_buffer =
+                create_instance_buffer(device, self.instances.len().next_power_of_two());
+        }
+
+        if !self.instances.is_empty() {
+            queue.write_buffer(
+                &self.instance_buffer,
+                0,
+                bytemuck::cast_slice(&self.instances),
+            );
+        }
+
+        self.uploaded = self.instances.len() as u32;
+    }
+
+    // Turns the sections into glyphs, rasterising any new ones
+    fn layout(&mut self, queue: &AuditedQueue, pixels_per_point: f32) -> Result<(), AtlasFull> {
+        self.instances.clear();
+
+        for section in &self.sections {
+            let pixels = (section.size * pixels_per_point).round().max(1.0) as u32;
+            let font = self.font.as_scaled(PxScale::from(pixels as f32));
+            let line_height = (font.height() + font.line_gap()).round();
+            let lines: Vec<&str> = section.text.lines().collect();
+
+            let (anchor, in_world, top_left) = match section.anchor {
+                TextAnchor::Screen([x, y]) => (
+                    [0.0; 3],
+                    0.0,
+                    [
+                        (x * pixels_per_point).round(),
+                        (y * pixels_per_point).round(),
+                    ],
+                ),
+                TextAnchor::World(position) => {
+                    let widest = lines
+                        .iter()
+                        .map(|line| line_width(&font, line))
+                        .fold(0.0, f32::max);
+                    let height = line_height * lines.len() as f32;
+                    (position, 1.0, [-(widest / 2.0).round(), -height])
+                }
+            };
+
+            let shadow = (SHADOW_OFFSET * pixels_per_point).round().max(1.0);
+            let shadow_colour = [0.0, 0.0, 0.0, section.colour[3] * 0.75];
+
+            for (i, line) in lines.iter().enumerate() {
+                let baseline = top_left[1] + font.ascent().round() + line_height * i as f32;
+                let mut pen = top_left[0];
+                let mut last = None;
+
+                for c in line.chars() {
+                    let id = font.glyph_id(c);
+                    if let Some(last) = last {
+                        pen += font.kern(last, id);
+                    }
+                    last = Some(id);
+
+                    if let Some(glyph) = self.atlas.get(queue, &self.font, id, pixels)? {
+                        let offset = [pen.round() + glyph.offset[0], baseline + glyph.offset[1]];
+
+                        for (nudge, colour) in [(shadow, shadow_colour), (0.0, section.colour)] {
+                            self.instances.push(GlyphInstance {
+                                anchor,
+                                in_world,
+                                offset: [offset[0] + nudge, offset[1] + nudge],
+                                size: glyph.size,
+                                uv_min: glyph.uv_min,
+                                uv_max: glyph.uv_max,
+                                colour,
+                            });
+                        }
+                    }
+
+                    pen += font.h_advance(id);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera: &'a CameraBinding) {
+        if self.uploaded == 0 {
+            return;
+        }
+
+        self.record_draw(render_pass, camera, self.uploaded);
+    }
+
+    /// Draws one glyph (whichever was last uploaded) even when there's no
+    /// text, to get the pipeline used before any labels show up
+    pub fn warm_up<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a CameraBinding,
+    ) {
+        self.record_draw(render_pass, camera, 1);
+    }
+
+    fn record_draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a CameraBinding,
+        glyphs: u32,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &camera.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        render_pass.draw(0..4, 0..glyphs);
+    }
+}
+
+// How wide a line of text is, in pixels
+fn line_width(font: &PxScaleFont<&FontRef<'static>>, line: &str) -> f32 {
+    let mut width = 0.0;
+    let mut last = None;
+    for c in line.chars() {
+        let id = font.glyph_id(c);
+        if let Some(last) = last {
+            width += font.kern(last, id);
+        }
+        width += font.h_advance(id);
+        last = Some(id);
+    }
+    width
+}
+
+fn create_instance_buffer(device: &wgpu::Device, glyphs: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("glyph instance buffer"),
+        size: (glyphs * std::mem::size_of::<GlyphInstance>()) as _,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("text pipeline layout"),
+        bind_group_layouts: &[CameraBinding::bind_group_layout(device), bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("text pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[GlyphInstance::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleStrip,
+            cull_mode: None,
+            ..Default::default()
+        },
+        // Labels in the world get hidden behind things, but don't hide
+        // anything themselves. Screen text is at the very front, so it's
+        // never hidden.
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: Default::default(),
+            bias: Default::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
@@ -1,22 +1,29 @@
-use std::sync::OnceLock;
+use std::sync::Arc;
 
 use image::GenericImageView;
 
-use crate::resources::load_bytes;
+use crate::{
+    colour::ColourSpace,
+    error::{Error, Result},
+    layouts::LayoutCache,
+    upload::SharedUploads,
+};
 
-static TEXTURE_BIND_GROUP_LAYOUT: OnceLock<wgpu::BindGroupLayout> = OnceLock::new();
+static TEXTURE_BIND_GROUP_LAYOUT: LayoutCache = LayoutCache::new();
 
 pub struct Texture {
-    pub texture: wgpu::Texture,
+    #[allow(dead_code)]
+    pub texture: Arc<wgpu::Texture>,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
 }
 
 impl Texture {
-    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    // With a stencil for outlining things (see outline.rs)
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8;
 
     pub fn texture_bind_group_layout(device: &wgpu::Device) -> &wgpu::BindGroupLayout {
-        TEXTURE_BIND_GROUP_LAYOUT.get_or_init(|| {
+        TEXTURE_BIND_GROUP_LAYOUT.get(device, |device| {
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("Texture bind group layout descriptor"),
                 entries: &[
@@ -36,15 +43,30 @@ impl Texture {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    // The material uniform (see animation::MaterialUniform)
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
             })
         })
     }
 
+    // If it's readable, it can be bound as a (multisampled) texture as well as
+    // used as a depth buffer. Not every backend can do that.
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
         label: &str,
+        sample_count: u32,
+        readable: bool,
     ) -> Self {
         let size = wgpu::Extent3d {
             width: config.width,
@@ -55,13 +77,17 @@ impl Texture {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: crate::app::SAMPLE_COUNT,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            usage: if readable {
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+            } else {
+                wgpu::TextureUsages::RENDER_ATTACHMENT
+            },
             view_formats: &[],
         };
-        let texture = device.create_texture(&desc);
+        let texture = Arc::new(device.create_texture(&desc));
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
@@ -84,31 +110,37 @@ impl Texture {
         }
     }
 
-    pub async fn load_texture(
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        filename: &str,
-    ) -> anyhow::Result<Self> {
-        let bytes = load_bytes(filename).await?;
-        Self::from_bytes(device, queue, &bytes, Some(filename))
+    /// A view of just a depth texture's depth, without the stencil, which is
+    /// what has to be bound to read it in a shader
+    pub fn depth_only_view(&self) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor {
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        })
     }
 
     pub fn from_bytes(
         device: &wgpu::Device,
-        queue: &wgpu::Queue,
+        uploads: &SharedUploads,
         bytes: &[u8],
         label: Option<&str>,
-    ) -> anyhow::Result<Self> {
-        let image = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &image, label)
+        colour_space: ColourSpace,
+    ) -> Result<Self> {
+        let image = image::load_from_memory(bytes)
+            .map_err(|e| Error::decode(label.unwrap_or("texture"), e))?;
+        Self::from_image(device, uploads, &image, label, colour_space)
     }
 
+    // The texture's contents get uploaded over the next few frames by the upload
+    // scheduler, so it'll be blank (or partially filled in) for a little while.
+    // The colour space decides the format, so that sampling always gives linear values.
     pub fn from_image(
         device: &wgpu::Device,
-        queue: &wgpu::Queue,
+        uploads: &SharedUploads,
         image: &image::DynamicImage,
         label: Option<&str>,
-    ) -> anyhow::Result<Self> {
+        colour_space: ColourSpace,
+    ) -> Result<Self> {
         let rgba = image.to_rgba8();
         let dimensions = image.dimensions();
 
@@ -118,39 +150,31 @@ impl Texture {
             depth_or_array_layers: 1,
         };
 
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
+        let texture = Arc::new(device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format: colour_space.rgba8_format(),
             usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
             view_formats: &[],
-        });
+        }));
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(dimensions.0 * 4),
-                rows_per_image: Some(dimensions.1),
-            },
-            size,
+        uploads.lock().unwrap().upload_texture(
+            texture.clone(),
+            rgba.into_raw(),
+            dimensions.0,
+            dimensions.1,
         );
 
         let view = texture.create_view(&Default::default());
 
+        // Repeat so that animated materials can scroll their textures
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Nearest,
             mipmap_filter: wgpu::FilterMode::Nearest,
@@ -0,0 +1,210 @@
+//! Slowing the emitters down when frames start taking too long. Left running
+//! for a long time (on the web especially) the pile grows until every frame
+//! is a struggle, so with this on the emitters drop to a trickle once frames
+//! have been over budget for a while, and stop altogether if that doesn't
+//! help. They pick back up once frames have been comfortably under budget
+//! for a bit longer. The gap between the two, in both frame time and how
+//! long it has to last, keeps it from flicking back and forth.
+//!
+//! What's measured is the work that goes into a frame (updating and sending
+//! it off), not the time between frames. With vsync or a frame limit, frames
+//! never come quicker than the budget however little there is to do, so
+//! that could never get under it to pick back up.
+
+use crate::locale::Strings;
+
+// How much of each new frame time goes into the smoothed one. Low enough that
+// a single slow frame (a shader compiling, say) doesn't count for much.
+const SMOOTHING: f32 = 0.05;
+// Frames this much over the budget count as too slow
+const OVER_BUDGET: f32 = 1.25;
+// And this much under it count as fast enough to speed back up
+const UNDER_BUDGET: f32 = 0.9;
+// Seconds frames have to be too slow for before spawning slows down a step
+const SLOW_DOWN_AFTER: f32 = 2.0;
+// Seconds they have to be fast enough for before it speeds back up a step
+const SPEED_UP_AFTER: f32 = 5.0;
+
+/// How much spawning's been held back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleLevel {
+    Full,
+    Slowed,
+    Stopped,
+}
+
+impl ThrottleLevel {
+    /// What the emitters' spawn rates get multiplied by
+    pub fn rate(self) -> f32 {
+        match self {
+            ThrottleLevel::Full => 1.0,
+            ThrottleLevel::Slowed => 0.25,
+            ThrottleLevel::Stopped => 0.0,
+        }
+    }
+
+    fn slower(self) -> Self {
+        match self {
+            ThrottleLevel::Full => ThrottleLevel::Slowed,
+            _ => ThrottleLevel::Stopped,
+        }
+    }
+
+    fn faster(self) -> Self {
+        match self {
+            ThrottleLevel::Stopped => ThrottleLevel::Slowed,
+            _ => ThrottleLevel::Full,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SpawnThrottle {
+    pub enabled: bool,
+    level: ThrottleLevel,
+    // Smoothed time each frame's work takes, in seconds
+    work_time: Option<f32>,
+    // How long frames have been too slow, or fast enough, for
+    over_for: f32,
+    under_for: f32,
+}
+
+impl SpawnThrottle {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            level: ThrottleLevel::Full,
+            work_time: None,
+            over_for: 0.0,
+            under_for: 0.0,
+        }
+    }
+
+    /// Takes the time since the last frame, how much of it went on working
+    /// (rather than waiting for the screen) and how long frames are meant to
+    /// take, all in seconds, and returns how fast the emitters should go
+    pub fn update(&mut self, delta_time: f32, work_time: f32, budget: f32) -> f32 {
+        if !self.enabled {
+            self.reset();
+            return self.level.rate();
+        }
+
+        let smoothed = match self.work_time {
+            Some(smoothed) => smoothed + (work_time - smoothed) * SMOOTHING,
+            None => work_time,
+        };
+        self.work_time = Some(smoothed);
+
+        if smoothed > budget * OVER_BUDGET {
+            self.over_for += delta_time;
+            self.under_for = 0.0;
+        } else if smoothed < budget * UNDER_BUDGET {
+            self.under_for += delta_time;
+            self.over_for = 0.0;
+        } else {
+            self.over_for = 0.0;
+            self.under_for = 0.0;
+        }
+
+        if self.over_for >= SLOW_DOWN_AFTER {
+            self.level = self.level.slower();
+            self.over_for = 0.0;
+        } else if self.under_for >= SPEED_UP_AFTER {
+            self.level = self.level.faster();
+            self.under_for = 0.0;
+        }
+
+        self.level.rate()
+    }
+
+    /// Back to spawning at full speed, forgetting the frame times so far
+    pub fn reset(&mut self) {
+        self.level = ThrottleLevel::Full;
+        self.work_time = None;
+        self.over_for = 0.0;
+        self.under_for = 0.0;
+    }
+
+    /// A little note in the corner while spawning's being held back
+    pub fn show(&self, ctx: &egui::Context, strings: &Strings) {
+        let key = match self.level {
+            ThrottleLevel::Full => return,
+            ThrottleLevel::Slowed => "throttle.slowed",
+            ThrottleLevel::Stopped => "throttle.stopped",
+        };
+
+        egui::Area::new("spawn throttle")
+            .anchor(egui::Align2::LEFT_BOTTOM, [10.0, -10.0])
+            .interactable(false)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.label(strings.get(key));
+                });
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUDGET: f32 = 1.0 / 60.0;
+
+    // Runs frames that take the given time to work through for however many
+    // seconds. They're never quicker than the budget, like with vsync on.
+    fn run(throttle: &mut SpawnThrottle, work_time: f32, seconds: f32) -> f32 {
+        let frame_time = work_time.max(BUDGET);
+        let mut rate = 1.0;
+        for _ in 0..(seconds / frame_time).round() as usize {
+            rate = throttle.update(frame_time, work_time, BUDGET);
+        }
+        rate
+    }
+
+    #[test]
+    fn slows_down_then_recovers() {
+        let mut throttle = SpawnThrottle::new(true);
+
+        // A moment over budget isn't enough
+        assert_eq!(run(&mut throttle, 1.0 / 30.0, 1.0), 1.0);
+        assert_eq!(
+            run(&mut throttle, 1.0 / 30.0, 2.0),
+            ThrottleLevel::Slowed.rate()
+        );
+        assert_eq!(
+            run(&mut throttle, 1.0 / 30.0, 3.0),
+            ThrottleLevel::Stopped.rate()
+        );
+
+        // Just under budget is in the gap, so it stays stopped
+        assert_eq!(
+            run(&mut throttle, BUDGET * 0.95, 20.0),
+            ThrottleLevel::Stopped.rate()
+        );
+
+        assert_eq!(
+            run(&mut throttle, 1.0 / 120.0, 6.0),
+            ThrottleLevel::Slowed.rate()
+        );
+        assert_eq!(run(&mut throttle, 1.0 / 120.0, 6.0), 1.0);
+
+        // Turning it off lets everything go again straight away
+        run(&mut throttle, 1.0 / 20.0, 10.0);
+        throttle.enabled = false;
+        assert_eq!(throttle.update(1.0 / 20.0, 1.0 / 20.0, BUDGET), 1.0);
+    }
+
+    #[test]
+    fn recovers_when_frames_wait_for_the_screen() {
+        let mut throttle = SpawnThrottle::new(true);
+        run(&mut throttle, 1.0 / 20.0, 10.0);
+        assert_eq!(throttle.update(BUDGET, BUDGET, BUDGET), ThrottleLevel::Stopped.rate());
+
+        // Every frame takes exactly the budget, but most of it's waiting
+        let mut rate = 0.0;
+        for _ in 0..(12.0 / BUDGET) as usize {
+            rate = throttle.update(BUDGET, BUDGET * 0.3, BUDGET);
+        }
+        assert_eq!(rate, 1.0);
+    }
+}
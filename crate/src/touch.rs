@@ -0,0 +1,214 @@
+//! The layout for phones and tablets. The normal windows are far too small to
+//! hit with a finger, so instead there's a row of big buttons along the bottom
+//! for the things worth doing, and a sheet with the few settings that matter.
+//! The buttons get out of the way after a few seconds without a touch, and
+//! come back with the next one.
+//!
+//! It's picked as soon as the screen's touched (or straight away on the web,
+//! if there's a touchscreen), and `?layout=touch` or `?layout=desktop` on the
+//! end of the url forces one or the other.
+
+use crate::locale::Strings;
+
+// Seconds without a touch before the buttons hide
+const HIDE_AFTER: f32 = 4.0;
+// In points, which is plenty for a thumb
+const BUTTON_SIZE: [f32; 2] = [96.0, 64.0];
+const BUTTON_TEXT_SIZE: f32 = 20.0;
+// How much bigger everything is in the settings sheet than in the windows
+const SHEET_SCALE: f32 = 1.5;
+
+/// What the big buttons do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TouchAction {
+    Spawn,
+    Explode,
+    Reset,
+    Pause,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TouchLayout {
+    pub active: bool,
+    // Set when the url picked the layout, so touches don't change it
+    forced: bool,
+    settings_open: bool,
+    // Seconds since the screen was last touched
+    idle: f32,
+}
+
+impl TouchLayout {
+    pub fn new() -> Self {
+        let forced = forced_layout();
+        if let Some(touch) = forced {
+            log::info!(
+                "The url asked for the {} layout",
+                if touch { "touch" } else { "desktop" }
+            );
+        }
+
+        Self {
+            active: forced.unwrap_or_else(has_touchscreen),
+            forced: forced.is_some(),
+            settings_open: false,
+            idle: 0.0,
+        }
+    }
+
+    /// Switches to the touch layout (unless the url said not to) and brings
+    /// the buttons back if they'd hidden
+    pub fn touched(&mut self) {
+        self.idle = 0.0;
+        if !self.forced {
+            self.active = true;
+        }
+    }
+
+    pub fn settings_open(&self) -> bool {
+        self.settings_open
+    }
+
+    /// Draws the buttons, returning the one that was pressed (if any)
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        strings: &Strings,
+        paused: bool,
+    ) -> Option<TouchAction> {
+        self.idle += ctx.input(|input| input.unstable_dt);
+        if self.idle >= HIDE_AFTER && !self.settings_open {
+            return None;
+        }
+
+        let mut action = None;
+
+        egui::Area::new("touch buttons")
+            .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -16.0])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let pause = if paused {
+                        "touch.resume"
+                    } else {
+                        "touch.pause"
+                    };
+
+                    for (key, pressed) in [
+                        ("touch.spawn", Some(TouchAction::Spawn)),
+                        ("touch.explode", Some(TouchAction::Explode)),
+                        ("touch.reset", Some(TouchAction::Reset)),
+                        (pause, Some(TouchAction::Pause)),
+                        ("touch.settings", None),
+                    ] {
+                        let text = egui::RichText::new(strings.get(key)).size(BUTTON_TEXT_SIZE);
+                        if ui.add_sized(BUTTON_SIZE, egui::Button::new(text)).clicked() {
+                            self.idle = 0.0;
+                            match pressed {
+                                Some(pressed) => action = Some(pressed),
+                                None => self.settings_open = !self.settings_open,
+                            }
+                        }
+                    }
+                });
+            });
+
+        action
+    }
+
+    /// The settings sheet, which slides up from the bottom when it's open.
+    /// `add_contents` fills in the settings themselves.
+    pub fn settings_sheet(
+        &mut self,
+        ctx: &egui::Context,
+        strings: &Strings,
+        add_contents: impl FnOnce(&mut egui::Ui),
+    ) {
+        egui::TopBottomPanel::bottom("touch settings").show_animated(
+            ctx,
+            self.settings_open,
+            |ui| {
+                let style = ui.style_mut();
+                for font in style.text_styles.values_mut() {
+                    font.size *= SHEET_SCALE;
+                }
+                style.spacing.interact_size *= SHEET_SCALE;
+                style.spacing.item_spacing *= SHEET_SCALE;
+                style.spacing.button_padding *= SHEET_SCALE;
+
+                ui.horizontal(|ui| {
+                    ui.heading(strings.get("touch.settings"));
+                    if ui.button(strings.get("touch.close")).clicked() {
+                        self.settings_open = false;
+                    }
+                });
+
+                add_contents(ui);
+
+                // For a tablet with a keyboard, or a touchscreen laptop
+                if ui.button(strings.get("touch.desktop_layout")).clicked() {
+                    self.active = false;
+                    self.forced = true;
+                    self.settings_open = false;
+                }
+
+                // Leaves room for the buttons underneath
+                ui.add_space(BUTTON_SIZE[1] + 24.0);
+            },
+        );
+    }
+}
+
+// Whether the url says which layout to use
+#[cfg(target_arch = "wasm32")]
+fn forced_layout() -> Option<bool> {
+    let search = web_sys::window()?.location().search().ok()?;
+    parse_layout(&search)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn forced_layout() -> Option<bool> {
+    None
+}
+
+// Whether the device has a touchscreen. Natively this is only found out once
+// it's touched.
+#[cfg(target_arch = "wasm32")]
+fn has_touchscreen() -> bool {
+    web_sys::window().is_some_and(|window| window.navigator().max_touch_points() > 0)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn has_touchscreen() -> bool {
+    false
+}
+
+/// Reads `layout=touch` or `layout=desktop` out of a url's query string
+/// (`?layout=touch&other=thing`), as whether it's the touch layout
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+fn parse_layout(search: &str) -> Option<bool> {
+    let layout = search
+        .trim_start_matches('?')
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("layout="))?;
+
+    match layout {
+        "touch" => Some(true),
+        "desktop" => Some(false),
+        _ => {
+            log::warn!("Don't know the \"{layout}\" layout, going with whatever fits");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_comes_from_the_query_string() {
+        assert_eq!(parse_layout(""), None);
+        assert_eq!(parse_layout("?layout=touch"), Some(true));
+        assert_eq!(parse_layout("?fresh=1&layout=desktop"), Some(false));
+        assert_eq!(parse_layout("?layout=tablet"), None);
+    }
+}
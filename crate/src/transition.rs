@@ -0,0 +1,81 @@
+use crate::{
+    render_target::{Blitter, SceneTarget},
+    sync_audit::AuditedQueue,
+};
+
+// How long the old picture takes to fade away, in seconds
+const DURATION: f32 = 0.5;
+// The most the fade moves along in one frame
+const MAX_STEP: f32 = 1.0 / 30.0;
+
+/// Cross-fades from a still of whatever was on screen (the loading screen, or
+/// the last scene) to the live scene, so switching isn't a sudden cut.
+pub struct Transition {
+    // Kept alive for the bind group, which reads from it
+    _snapshot: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+    elapsed: f32,
+}
+
+impl Transition {
+    /// Takes a snapshot of what's in the scene target right now, which then
+    /// gets faded out over the top of everything drawn afterwards
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &AuditedQueue,
+        scene_target: &SceneTarget,
+        blitter: &Blitter,
+    ) -> Self {
+        let source = scene_target.colour_texture();
+
+        let snapshot = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("transition snapshot"),
+            size: source.size(),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: source.format(),
+            usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Transition encoder"),
+        });
+        encoder.copy_texture_to_texture(
+            source.as_image_copy(),
+            snapshot.as_image_copy(),
+            source.size(),
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let view = snapshot.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = blitter.bind_group(device, &view);
+
+        Self {
+            _snapshot: snapshot,
+            bind_group,
+            elapsed: 0.0,
+        }
+    }
+
+    /// Moves the fade along. Returns false once it's finished.
+    pub fn update(&mut self, delta_time: f32) -> bool {
+        // One long frame (like the one after loading, while everything's
+        // being compiled) shouldn't skip most of the fade
+        self.elapsed += delta_time.min(MAX_STEP);
+        self.elapsed < DURATION
+    }
+
+    // Smoothed at both ends so the fade doesn't start or stop suddenly
+    fn opacity(&self) -> f32 {
+        let t = (self.elapsed / DURATION).clamp(0.0, 1.0);
+        1.0 - t * t * (3.0 - 2.0 * t)
+    }
+
+    /// Draws the old picture over the screen. This should come after the scene
+    /// and before the ui.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, blitter: &'a Blitter) {
+        blitter.blit_faded(render_pass, &self.bind_group, self.opacity());
+    }
+}
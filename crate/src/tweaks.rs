@@ -0,0 +1,92 @@
+use egui::DragValue;
+use serde::{Deserialize, Serialize};
+
+use crate::locale::Strings;
+
+/// Numbers the model shader uses for its lighting, which are nicer to fiddle
+/// with while everything's running than by editing the wgsl and reloading.
+/// They're saved in the scene file.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShaderTweaks {
+    /// How much of the light's colour everything gets, even facing away from it
+    pub ambient_strength: f32,
+    /// How much of the sky's colour everything gets
+    pub sky_ambient_strength: f32,
+    /// How bright the highlights are. Each material's specular gets
+    /// multiplied by this.
+    pub specular_strength: f32,
+    /// How tight the highlights are, for materials that don't say (e.g. an
+    /// mtl without Ns). Higher is shinier.
+    pub specular_power: f32,
+    /// How quickly things fade into the sky with distance. 0 is no fog.
+    pub fog_density: f32,
+}
+
+impl Default for ShaderTweaks {
+    fn default() -> Self {
+        Self {
+            ambient_strength: 0.1,
+            sky_ambient_strength: 0.5,
+            specular_strength: 0.4,
+            specular_power: 10.0,
+            fog_density: 0.0,
+        }
+    }
+}
+
+impl ShaderTweaks {
+    pub fn uniform(&self) -> TweakUniform {
+        TweakUniform {
+            ambient_strength: self.ambient_strength,
+            sky_ambient_strength: self.sky_ambient_strength,
+            specular_strength: self.specular_strength,
+            specular_power: self.specular_power,
+            fog_density: self.fog_density,
+            _padding: [0.0; 3],
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, strings: &Strings) {
+        // Label, value, the most it can go up to and how fast it drags
+        let rows = [
+            ("tweaks.ambient", &mut self.ambient_strength, 1.0, 0.01),
+            (
+                "tweaks.sky_ambient",
+                &mut self.sky_ambient_strength,
+                1.0,
+                0.01,
+            ),
+            ("tweaks.specular", &mut self.specular_strength, 4.0, 0.01),
+            (
+                "tweaks.specular_power",
+                &mut self.specular_power,
+                256.0,
+                0.5,
+            ),
+            ("tweaks.fog_density", &mut self.fog_density, 1.0, 0.001),
+        ];
+
+        for (label, value, max, speed) in rows {
+            ui.horizontal(|ui| {
+                ui.label(strings.get(label));
+                ui.add(DragValue::new(value).clamp_range(0.0..=max).speed(speed));
+            });
+        }
+
+        if ui.button(strings.get("tweaks.reset")).clicked() {
+            *self = Self::default();
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct TweakUniform {
+    pub ambient_strength: f32,
+    pub sky_ambient_strength: f32,
+    pub specular_strength: f32,
+    pub specular_power: f32,
+    pub fog_density: f32,
+    _padding: [f32; 3],
+}
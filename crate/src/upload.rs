@@ -0,0 +1,242 @@
+use std::{
+    collections::VecDeque,
+    num::NonZeroU64,
+    sync::{Arc, Mutex},
+};
+
+// 4 MiB a frame is enough to get a model onto the gpu in a handful of frames
+// without the copies themselves taking a noticeable amount of time
+pub const DEFAULT_BUDGET: u64 = 4 * 1024 * 1024;
+const BELT_CHUNK_SIZE: u64 = 1024 * 1024;
+
+pub type SharedUploads = Arc<Mutex<UploadScheduler>>;
+
+/// Spreads uploads of large buffers and textures over several frames.
+///
+/// Creating a buffer with `create_buffer_init` copies the whole thing in one go,
+/// which stalls whatever frame happens to be running when an asset finishes
+/// loading. Instead, the gpu resource is created empty and its contents are
+/// queued up here, and then every frame copies at most `budget` bytes.
+pub struct UploadScheduler {
+    pending: VecDeque<PendingUpload>,
+    belt: wgpu::util::StagingBelt,
+    /// Max number of bytes uploaded per frame
+    pub budget: u64,
+}
+
+enum PendingUpload {
+    Buffer {
+        target: Arc<wgpu::Buffer>,
+        data: Vec<u8>,
+        // How much of the data has been uploaded so far
+        uploaded: usize,
+    },
+    Texture {
+        target: Arc<wgpu::Texture>,
+        data: Vec<u8>,
+        width: u32,
+        height: u32,
+        // Rows of the texture uploaded so far
+        uploaded_rows: u32,
+    },
+}
+
+impl UploadScheduler {
+    pub fn new(budget: u64) -> Self {
+        Self {
+            pending: VecDeque::new(),
+            belt: wgpu::util::StagingBelt::new(BELT_CHUNK_SIZE),
+            budget,
+        }
+    }
+
+    pub fn shared(budget: u64) -> SharedUploads {
+        Arc::new(Mutex::new(Self::new(budget)))
+    }
+
+    /// Creates an empty buffer big enough for `contents`, and queues the contents
+    /// to be uploaded to it over the next few frames.
+    pub fn create_buffer(
+        &mut self,
+        device: &wgpu::Device,
+        label: &str,
+        contents: &[u8],
+        usage: wgpu::BufferUsages,
+    ) -> Arc<wgpu::Buffer> {
+        let align = wgpu::COPY_BUFFER_ALIGNMENT;
+        let size = (contents.len() as u64).div_ceil(align) * align;
+
+        let buffer = Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage: usage | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+
+        self.upload_buffer(buffer.clone(), contents.to_vec());
+        buffer
+    }
+
+    /// Queues `data` to be copied to the start of `target`, which must have been
+    /// created with `COPY_DST` usage.
+    pub fn upload_buffer(&mut self, target: Arc<wgpu::Buffer>, data: Vec<u8>) {
+        if !data.is_empty() {
+            self.pending.push_back(PendingUpload::Buffer {
+                target,
+                data,
+                uploaded: 0,
+            });
+        }
+    }
+
+    /// Queues rgba8 image data to be copied to mip level 0 of `target`, which must
+    /// have been created with `COPY_DST` usage.
+    pub fn upload_texture(
+        &mut self,
+        target: Arc<wgpu::Texture>,
+        data: Vec<u8>,
+        width: u32,
+        height: u32,
+    ) {
+        self.pending.push_back(PendingUpload::Texture {
+            target,
+            data,
+            width,
+            height,
+            uploaded_rows: 0,
+        });
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Bytes still waiting to be uploaded
+    pub fn pending_bytes(&self) -> usize {
+        self.pending
+            .iter()
+            .map(|upload| match upload {
+                PendingUpload::Buffer { data, uploaded, .. } => data.len() - uploaded,
+                PendingUpload::Texture {
+                    width,
+                    height,
+                    uploaded_rows,
+                    ..
+                } => (*width * (height - uploaded_rows) * 4) as usize,
+            })
+            .sum()
+    }
+
+    /// Does this frame's share of the uploads. Buffer copies get recorded into
+    /// the encoder, so it has to be submitted (followed by a call to `recall`)
+    /// before the belt can reuse its staging memory.
+    pub fn flush(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        // Buffer copies have to be a multiple of 4 bytes
+        let mut remaining = self.budget.max(wgpu::COPY_BUFFER_ALIGNMENT) as usize;
+
+        while remaining > 0 {
+            let Some(upload) = self.pending.front_mut() else {
+                break;
+            };
+
+            let finished = match upload {
+                PendingUpload::Buffer {
+                    target,
+                    data,
+                    uploaded,
+                } => {
+                    let align = wgpu::COPY_BUFFER_ALIGNMENT as usize;
+                    let left = data.len() - *uploaded;
+                    let size = if left <= remaining {
+                        left
+                    } else {
+                        remaining / align * align
+                    };
+
+                    if size == 0 {
+                        break;
+                    }
+
+                    // The buffer's size is padded to the alignment, so the final
+                    // chunk can be padded too
+                    let padded = size.div_ceil(align) * align;
+
+                    let mut view = self.belt.write_buffer(
+                        encoder,
+                        target,
+                        *uploaded as _,
+                        NonZeroU64::new(padded as _).unwrap(),
+                        device,
+                    );
+                    view[..size].copy_from_slice(&data[*uploaded..*uploaded + size]);
+                    view[size..].fill(0);
+                    drop(view);
+
+                    *uploaded += size;
+                    remaining -= size.min(remaining);
+                    *uploaded == data.len()
+                }
+
+                PendingUpload::Texture {
+                    target,
+                    data,
+                    width,
+                    height,
+                    uploaded_rows,
+                } => {
+                    let bytes_per_row = *width * 4;
+                    // Always do at least one row, even if it's over budget
+                    let rows =
+                        ((remaining as u32 / bytes_per_row).max(1)).min(*height - *uploaded_rows);
+                    let start = (*uploaded_rows * bytes_per_row) as usize;
+                    let end = start + (rows * bytes_per_row) as usize;
+
+                    queue.write_texture(
+                        wgpu::ImageCopyTexture {
+                            texture: target,
+                            mip_level: 0,
+                            origin: wgpu::Origin3d {
+                                x: 0,
+                                y: *uploaded_rows,
+                                z: 0,
+                            },
+                            aspect: wgpu::TextureAspect::All,
+                        },
+                        &data[start..end],
+                        wgpu::ImageDataLayout {
+                            offset: 0,
+                            bytes_per_row: Some(bytes_per_row),
+                            rows_per_image: Some(rows),
+                        },
+                        wgpu::Extent3d {
+                            width: *width,
+                            height: rows,
+                            depth_or_array_layers: 1,
+                        },
+                    );
+
+                    *uploaded_rows += rows;
+                    remaining -= (end - start).min(remaining);
+                    *uploaded_rows == *height
+                }
+            };
+
+            if finished {
+                self.pending.pop_front();
+            }
+        }
+
+        self.belt.finish();
+    }
+
+    /// Lets the staging belt reuse memory from previous flushes.
+    /// Call this after submitting the encoder passed to `flush`.
+    pub fn recall(&mut self) {
+        self.belt.recall();
+    }
+}
@@ -0,0 +1,100 @@
+//! Walking around on the ground instead of flying, for seeing the pile the
+//! way someone standing next to it would. The camera becomes the eyes of a
+//! capsule-shaped person who falls, jumps, and bumps into things.
+
+use cgmath::vec3;
+use rapier3d::{na, prelude::Capsule};
+use winit::event::VirtualKeyCode;
+
+use crate::{
+    camera::Camera,
+    input::InputSnapshot,
+    physics::{self, PhysicsSimulation},
+};
+
+// The walker's capsule. It's a bit under 2 units tall, which is about the
+// height of a rei.
+const RADIUS: f32 = 0.3;
+const HALF_HEIGHT: f32 = 0.6;
+// How far the eyes are above the middle of the capsule
+const EYE_OFFSET: f32 = 0.75;
+const JUMP_SPEED: f32 = 5.0;
+// Falling any faster than this doesn't look any different, and a long fall
+// could otherwise go right through something thin
+const MAX_FALL_SPEED: f32 = 30.0;
+
+/// Whoever's walking starts off standing still, and falls from wherever the
+/// camera was
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Walker {
+    // Units per second, up is positive
+    vertical_speed: f32,
+    grounded: bool,
+}
+
+impl Walker {
+    /// Moves the camera with wasd like flying does, but it's pulled down and
+    /// can't go through anything. Space jumps. This gets real time rather than
+    /// simulation time, so walking still works while it's paused.
+    pub fn update(
+        &mut self,
+        camera: &mut Camera,
+        input: &InputSnapshot,
+        physics: &PhysicsSimulation,
+        delta_time: f32,
+    ) {
+        camera.turn(input);
+
+        if self.grounded && input.held(VirtualKeyCode::Space) > 0.0 {
+            self.vertical_speed = JUMP_SPEED;
+        }
+
+        self.vertical_speed =
+            (self.vertical_speed + physics::GRAVITY.y * delta_time).max(-MAX_FALL_SPEED);
+
+        let walk = camera.ground_movement(input);
+        let desired = na::Vector3::new(walk.x, self.vertical_speed * delta_time, walk.z);
+
+        let centre = camera.eye - vec3(0.0, EYE_OFFSET, 0.0);
+        let (moved, grounded) = physics.move_character(
+            &Capsule::new_y(HALF_HEIGHT, RADIUS),
+            na::Point3::new(centre.x, centre.y, centre.z),
+            desired,
+            delta_time,
+        );
+
+        camera.eye += vec3(moved.x, moved.y, moved.z);
+        self.grounded = grounded;
+
+        // Landing (or hitting a ceiling) stops it going any further that way
+        let bumped_head = self.vertical_speed > 0.0 && moved.y < desired.y - 1e-4;
+        if grounded && self.vertical_speed < 0.0 || bumped_head {
+            self.vertical_speed = 0.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ImportTransform;
+
+    #[test]
+    fn walker_lands_on_the_ground() {
+        let physics = PhysicsSimulation::new(Vec::new(), ImportTransform::default(), 0);
+        let mut camera = Camera::new((8.0, 5.0, 8.0).into(), 1.0);
+        let mut walker = Walker::default();
+
+        for _ in 0..180 {
+            walker.update(&mut camera, &InputSnapshot::default(), &physics, 1.0 / 60.0);
+        }
+
+        let standing = physics::GROUND_LEVEL + RADIUS + HALF_HEIGHT + EYE_OFFSET;
+        assert!(walker.grounded);
+        assert!(
+            (camera.eye.y - standing).abs() < 0.2,
+            "{} != {standing}",
+            camera.eye.y
+        );
+    }
+}